@@ -0,0 +1,206 @@
+//
+// Copyright (c) 2024 Nathan Fiedler
+//
+use crate::reader::ContentReader;
+use crate::{Entry, Error, ItemKind, PackReader, Sink, KIND_DIRECTORY};
+use std::io::{Cursor, Read};
+
+///
+/// Metadata of an entry in an archive, as returned by `ReadArchive::stat()`.
+///
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EntryMetadata {
+    /// Type of the entry.
+    pub kind: ItemKind,
+    /// Size in bytes of the content of a file, or the length of the value of
+    /// a symbolic link; zero for directories.
+    pub size: u64,
+    /// Modification time in seconds since the Unix epoch, if recorded.
+    pub mtime: Option<i64>,
+    /// Unix permission bits, if recorded.
+    pub mode: Option<u32>,
+}
+
+///
+/// Read-only access to the contents of an archive, as implemented by
+/// `PackReader`. Applications can accept a `&dyn ReadArchive` in place of a
+/// reader such that their tests may substitute a mock, or so that entries can
+/// be served from some other kind of archive.
+///
+/// Paths are relative and slash-separated, as returned from `entries()`, with
+/// or without the trailing slash for directories.
+///
+pub trait ReadArchive {
+    ///
+    /// Return all of the entries in the archive, with the name of each being
+    /// its full path, and a trailing slash for directories.
+    ///
+    fn entries(&self) -> Result<Vec<Entry>, Error>;
+
+    ///
+    /// Return the metadata of the entry at the given path.
+    ///
+    /// Returns `Error::EntryNotFound` if there is no such entry.
+    ///
+    fn stat(&self, path: &str) -> Result<EntryMetadata, Error>;
+
+    ///
+    /// Return a reader for the content of the file at the given path, or the
+    /// value of the symbolic link at that path.
+    ///
+    /// Returns `Error::EntryNotFound` if there is no such file or link.
+    ///
+    fn open_entry(&self, path: &str) -> Result<Box<dyn Read + '_>, Error>;
+
+    ///
+    /// Write all of the directories, files, and symbolic links in the archive
+    /// to the given sink, returning the number of files written.
+    ///
+    fn extract(&self, sink: &mut dyn Sink) -> Result<u64, Error>;
+}
+
+impl ReadArchive for PackReader {
+    fn entries(&self) -> Result<Vec<Entry>, Error> {
+        let mut entries: Vec<Entry> = vec![];
+        for result in PackReader::entries(self)? {
+            entries.push(result?);
+        }
+        Ok(entries)
+    }
+
+    fn stat(&self, path: &str) -> Result<EntryMetadata, Error> {
+        let (item_id, kind) = self
+            .lookup_path(path)?
+            .ok_or_else(|| Error::EntryNotFound(path.to_owned()))?;
+        let size: u64 = if kind == KIND_DIRECTORY {
+            0
+        } else if let Some(target) = self.link_target(item_id)? {
+            target.len() as u64
+        } else {
+            self.conn.query_row(
+                "SELECT IFNULL(SUM(size), 0) FROM itemcontent WHERE item = ?1",
+                [&item_id],
+                |row| row.get(0),
+            )?
+        };
+        let mut metadata = EntryMetadata {
+            kind: ItemKind::try_from(kind)?,
+            size,
+            mtime: None,
+            mode: None,
+        };
+        if crate::has_table(&self.conn, "attrs")? {
+            let mode = if crate::has_column(&self.conn, "attrs", "mode")? {
+                "mode"
+            } else {
+                "NULL"
+            };
+            let query = format!("SELECT mtime, {} FROM attrs WHERE item = ?1", mode);
+            let mut stmt = self.conn.prepare(&query)?;
+            let mut rows = stmt.query([&item_id])?;
+            if let Some(row) = rows.next()? {
+                metadata.mtime = row.get(0)?;
+                metadata.mode = row.get(1)?;
+            }
+        }
+        Ok(metadata)
+    }
+
+    fn open_entry(&self, path: &str) -> Result<Box<dyn Read + '_>, Error> {
+        let item_id = match self.lookup_path(path)? {
+            Some((item_id, kind)) if kind != KIND_DIRECTORY => item_id,
+            _ => return Err(Error::EntryNotFound(path.to_owned())),
+        };
+        if let Some(target) = self.link_target(item_id)? {
+            return Ok(Box::new(Cursor::new(target)));
+        }
+        if self.is_metadata_only()? {
+            return Err(Error::MetadataOnly);
+        }
+        Ok(Box::new(ContentReader::new(self, item_id)?))
+    }
+
+    fn extract(&self, sink: &mut dyn Sink) -> Result<u64, Error> {
+        self.extract_to(sink)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{MemorySink, MemorySource, PackBuilder, SinkEntry};
+
+    // Return the total size of the files in the archive, as an application
+    // written against the trait might.
+    fn total_size(archive: &dyn ReadArchive) -> Result<u64, Error> {
+        let mut total: u64 = 0;
+        for entry in archive.entries()? {
+            if entry.kind == ItemKind::File {
+                total += archive.stat(&entry.name)?.size;
+            }
+        }
+        Ok(total)
+    }
+
+    #[test]
+    fn test_read_archive() -> Result<(), Error> {
+        let mut source = MemorySource::new();
+        source.add_file("site/index.html", "<html></html>");
+        source.add_symlink("site/home.html", "index.html");
+        let workspace = tempfile::tempdir()?;
+        let packfile = workspace.path().join("pack.db3");
+        let mut builder = PackBuilder::new()?;
+        builder.add_dir_all_from(&source, "site")?;
+        let item_id = builder.add_stream("extra.txt", 0, "extra".as_bytes())?;
+        builder.record_attrs(item_id, Some(1_709_210_096), Some(0o600))?;
+        builder.finish(&packfile)?;
+
+        let reader = PackReader::new(&packfile)?;
+        let archive: &dyn ReadArchive = &reader;
+        let mut names: Vec<String> = archive.entries()?.into_iter().map(|e| e.name).collect();
+        names.sort();
+        assert_eq!(
+            names,
+            vec!["extra.txt", "site/", "site/home.html", "site/index.html"]
+        );
+        assert_eq!(total_size(archive)?, 18);
+        assert_eq!(
+            archive.stat("extra.txt")?,
+            EntryMetadata {
+                kind: ItemKind::File,
+                size: 5,
+                mtime: Some(1_709_210_096),
+                mode: Some(0o600),
+            }
+        );
+        assert_eq!(archive.stat("site/")?.kind, ItemKind::Directory);
+        assert_eq!(archive.stat("site/home.html")?.size, 10);
+        assert!(matches!(
+            archive.stat("missing.txt"),
+            Err(Error::EntryNotFound(_))
+        ));
+
+        let mut content = String::new();
+        archive
+            .open_entry("site/index.html")?
+            .read_to_string(&mut content)?;
+        assert_eq!(content, "<html></html>");
+        let mut target: Vec<u8> = vec![];
+        archive
+            .open_entry("site/home.html")?
+            .read_to_end(&mut target)?;
+        assert_eq!(target, b"index.html");
+        assert!(matches!(
+            archive.open_entry("site"),
+            Err(Error::EntryNotFound(_))
+        ));
+
+        let mut sink = MemorySink::new();
+        assert_eq!(archive.extract(&mut sink)?, 2);
+        assert_eq!(
+            sink.entries().get("extra.txt"),
+            Some(&SinkEntry::File(b"extra".to_vec()))
+        );
+        Ok(())
+    }
+}
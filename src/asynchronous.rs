@@ -0,0 +1,316 @@
+//
+// Copyright (c) 2024 Nathan Fiedler
+//
+use crate::{
+    CancelToken, CreateReport, Entry, Error, ExtractOptions, ExtractReport, ManifestEntry,
+    PackBuilder, PackReader, SpaceReport, UpdateSummary,
+};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+///
+/// Asynchronous wrapper around `PackBuilder` that performs all of its work on
+/// the blocking thread pool of the tokio runtime.
+///
+/// **Note:** Must be used from within a tokio runtime.
+///
+#[derive(Clone)]
+pub struct AsyncPackBuilder {
+    inner: Arc<Mutex<PackBuilder>>,
+    // obtained up front, as the builder is locked while it is busy
+    cancel: CancelToken,
+}
+
+impl AsyncPackBuilder {
+    ///
+    /// Construct a new `AsyncPackBuilder` that will operate entirely in memory.
+    ///
+    pub async fn new() -> Result<Self, Error> {
+        let builder = tokio::task::spawn_blocking(PackBuilder::new).await??;
+        Ok(Self {
+            cancel: builder.cancel_token(),
+            inner: Arc::new(Mutex::new(builder)),
+        })
+    }
+
+    ///
+    /// Construct an `AsyncPackBuilder` that will modify the existing pack file
+    /// at the given location, as with `PackBuilder::open()`.
+    ///
+    pub async fn open<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let path = path.as_ref().to_path_buf();
+        let builder = tokio::task::spawn_blocking(move || PackBuilder::open(path)).await??;
+        Ok(Self {
+            cancel: builder.cancel_token(),
+            inner: Arc::new(Mutex::new(builder)),
+        })
+    }
+
+    ///
+    /// Return a token that can be used to cancel the operation in progress, as
+    /// with `PackBuilder::cancel_token()`.
+    ///
+    pub fn cancel_token(&self) -> CancelToken {
+        self.cancel.clone()
+    }
+
+    ///
+    /// Visit all of the files and directories within the specified path, adding
+    /// them to the database.
+    ///
+    pub async fn add_dir_all<P: AsRef<Path>>(&self, basepath: P) -> Result<u64, Error> {
+        let basepath = basepath.as_ref().to_path_buf();
+        self.run(move |builder| builder.add_dir_all(basepath)).await
+    }
+
+    ///
+    /// Adds a single file to the archive, returning the item identifier.
+    ///
+    pub async fn add_file<P: AsRef<Path>>(&self, path: P, parent: i64) -> Result<i64, Error> {
+        let path = path.as_ref().to_path_buf();
+        self.run(move |builder| builder.add_file(path, parent))
+            .await
+    }
+
+    ///
+    /// Adds a symbolic link to the archive, returning the item identifier.
+    ///
+    pub async fn add_symlink<P: AsRef<Path>>(&self, path: P, parent: i64) -> Result<i64, Error> {
+        let path = path.as_ref().to_path_buf();
+        self.run(move |builder| builder.add_symlink(path, parent))
+            .await
+    }
+
+    ///
+    /// Compare the named inputs against the archive, as with
+    /// `PackBuilder::update_all()`.
+    ///
+    pub async fn update_all(&self, inputs: Vec<PathBuf>) -> Result<UpdateSummary, Error> {
+        self.run(move |builder| builder.update_all(inputs.iter().collect()))
+            .await
+    }
+
+    ///
+    /// Call `finish()` when all file content has been added to the builder.
+    ///
+    pub async fn finish<P: AsRef<Path>>(&self, path: P) -> Result<CreateReport, Error> {
+        let path = path.as_ref().to_path_buf();
+        self.run(move |builder| builder.finish(path)).await
+    }
+
+    ///
+    /// Let other connections read the archive while it is being changed, as
+    /// with `PackBuilder::set_concurrent_reads()`.
+    ///
+    pub async fn set_concurrent_reads(&self, enabled: bool) -> Result<(), Error> {
+        self.run(move |builder| builder.set_concurrent_reads(enabled))
+            .await
+    }
+
+    ///
+    /// Commit the changes made so far and carry on, as with
+    /// `PackBuilder::publish()`.
+    ///
+    pub async fn publish(&self) -> Result<(), Error> {
+        self.run(|builder| builder.publish()).await
+    }
+
+    ///
+    /// Call `commit()` when all changes have been made to an archive that was
+    /// opened using `open()`.
+    ///
+    pub async fn commit(&self) -> Result<(), Error> {
+        self.run(|builder| builder.commit()).await
+    }
+
+    // Run the function against the builder on the blocking thread pool.
+    async fn run<T, F>(&self, func: F) -> Result<T, Error>
+    where
+        F: FnOnce(&mut PackBuilder) -> Result<T, Error> + Send + 'static,
+        T: Send + 'static,
+    {
+        let inner = self.inner.clone();
+        tokio::task::spawn_blocking(move || {
+            let mut builder = inner.lock().map_err(|_| Error::Database)?;
+            func(&mut builder)
+        })
+        .await?
+    }
+}
+
+///
+/// Asynchronous wrapper around `PackReader` that performs all of its work on
+/// the blocking thread pool of the tokio runtime.
+///
+/// **Note:** Must be used from within a tokio runtime.
+///
+#[derive(Clone)]
+pub struct AsyncPackReader {
+    inner: Arc<Mutex<PackReader>>,
+}
+
+impl AsyncPackReader {
+    ///
+    /// Construct a new `AsyncPackReader` that will read from the pack file at
+    /// the given location.
+    ///
+    pub async fn new<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let path = path.as_ref().to_path_buf();
+        let reader = tokio::task::spawn_blocking(move || PackReader::new(path)).await??;
+        Ok(Self {
+            inner: Arc::new(Mutex::new(reader)),
+        })
+    }
+
+    ///
+    /// Construct a new `AsyncPackReader` that opens the pack file in read-only
+    /// mode, as with `PackReader::open_read_only()`.
+    ///
+    pub async fn open_read_only<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let path = path.as_ref().to_path_buf();
+        let reader =
+            tokio::task::spawn_blocking(move || PackReader::open_read_only(path)).await??;
+        Ok(Self {
+            inner: Arc::new(Mutex::new(reader)),
+        })
+    }
+
+    ///
+    /// Return all items in the archive with the `name` as the full path.
+    ///
+    pub async fn entries(&self) -> Result<Vec<Entry>, Error> {
+        self.run(|reader| {
+            let entries = reader.entries()?;
+            Ok(entries.into_iter().collect::<Result<Vec<_>, _>>()?)
+        })
+        .await
+    }
+
+    ///
+    /// Extract all of the files, returning a summary of what was written.
+    ///
+    pub async fn extract_all(&self) -> Result<ExtractReport, Error> {
+        self.run(|reader| reader.extract_all()).await
+    }
+
+    ///
+    /// Extract all of the entries according to the given options, returning
+    /// a summary of what was written.
+    ///
+    pub async fn extract_all_with(&self, options: ExtractOptions) -> Result<ExtractReport, Error> {
+        self.run(move |reader| reader.extract_all_with(&options))
+            .await
+    }
+
+    ///
+    /// Extract the directory at the given path within the archive, along with
+    /// everything beneath it, returning a summary of what was written.
+    ///
+    pub async fn extract_subtree_with(
+        &self,
+        path: &str,
+        options: ExtractOptions,
+    ) -> Result<ExtractReport, Error> {
+        let path = path.to_owned();
+        self.run(move |reader| reader.extract_subtree_with(&path, &options))
+            .await
+    }
+
+    ///
+    /// Return the number of items and the number of content bundles.
+    ///
+    pub async fn counts(&self) -> Result<(u64, u64), Error> {
+        self.run(|reader| reader.counts()).await
+    }
+
+    ///
+    /// Return the number of entries in the archive, including directories.
+    ///
+    pub async fn len(&self) -> Result<u64, Error> {
+        self.run(|reader| reader.len()).await
+    }
+
+    ///
+    /// Return `true` if the archive has no entries at all.
+    ///
+    pub async fn is_empty(&self) -> Result<bool, Error> {
+        self.run(|reader| reader.is_empty()).await
+    }
+
+    ///
+    /// Return `true` if the archive has an entry with the given path.
+    ///
+    pub async fn contains(&self, path: &str) -> Result<bool, Error> {
+        let path = path.to_owned();
+        self.run(move |reader| reader.contains(&path)).await
+    }
+
+    ///
+    /// Gather the compressed and uncompressed sizes of the content bundles,
+    /// along with the largest files in the archive.
+    ///
+    pub async fn space_report(&self) -> Result<SpaceReport, Error> {
+        self.run(|reader| reader.space_report()).await
+    }
+
+    ///
+    /// Read up to `len` bytes of the named file, starting at `offset`.
+    ///
+    pub async fn read_range(&self, path: &str, offset: u64, len: u64) -> Result<Vec<u8>, Error> {
+        let path = path.to_owned();
+        self.run(move |reader| reader.read_range(&path, offset, len))
+            .await
+    }
+
+    ///
+    /// Produce a manifest of every entry in the archive.
+    ///
+    pub async fn manifest(&self) -> Result<Vec<ManifestEntry>, Error> {
+        self.run(|reader| reader.manifest()).await
+    }
+
+    // Run the function against the reader on the blocking thread pool.
+    async fn run<T, F>(&self, func: F) -> Result<T, Error>
+    where
+        F: FnOnce(&PackReader) -> Result<T, Error> + Send + 'static,
+        T: Send + 'static,
+    {
+        let inner = self.inner.clone();
+        tokio::task::spawn_blocking(move || {
+            let reader = inner.lock().map_err(|_| Error::Database)?;
+            func(&reader)
+        })
+        .await?
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_async_create_and_read() -> Result<(), Error> {
+        let runtime = tokio::runtime::Builder::new_current_thread().build()?;
+        runtime.block_on(async {
+            let workspace = tempfile::tempdir()?;
+            let basepath = workspace.path().join("input");
+            fs::create_dir(&basepath)?;
+            fs::write(basepath.join("hello.txt"), "hello world")?;
+            let packfile = workspace.path().join("pack.db3");
+
+            let builder = AsyncPackBuilder::new().await?;
+            let count = builder.add_dir_all(&basepath).await?;
+            assert_eq!(count, 1);
+            builder.finish(&packfile).await?;
+
+            let reader = AsyncPackReader::new(&packfile).await?;
+            let entries = reader.entries().await?;
+            let names: Vec<String> = entries.into_iter().map(|e| e.name).collect();
+            assert_eq!(names, vec!["input/", "input/hello.txt"]);
+            assert_eq!(reader.len().await?, 2);
+            assert!(reader.contains("input/hello.txt").await?);
+            Ok(())
+        })
+    }
+}
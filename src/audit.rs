@@ -0,0 +1,181 @@
+//
+// Copyright (c) 2024 Nathan Fiedler
+//
+use crate::{Error, PackBuilder, PackReader};
+use rusqlite::Connection;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+///
+/// A change made to an archive after it was written, as recorded in the
+/// `audit` table when the change was committed.
+///
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AuditRecord {
+    /// When the change was committed, in ISO 8601 form (UTC).
+    pub time: String,
+    /// Name of the operation, such as `update` or `set-metadata`.
+    pub operation: String,
+    /// Paths within the archive of the entries that were affected, if any.
+    pub paths: Vec<String>,
+    /// Name and version of the program that made the change.
+    pub tool: String,
+}
+
+impl PackReader {
+    ///
+    /// Return the changes made to the archive after it was written, oldest
+    /// first. Archives whose changes were made by earlier versions, or that
+    /// have never been changed, have no history.
+    ///
+    pub fn history(&self) -> Result<Vec<AuditRecord>, Error> {
+        if !crate::has_table(&self.conn, "audit")? {
+            return Ok(vec![]);
+        }
+        let mut stmt = self
+            .conn
+            .prepare("SELECT time, operation, paths, tool FROM audit ORDER BY id")?;
+        let rows = stmt
+            .query_map([], |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, Option<String>>(2)?,
+                    row.get::<_, Option<String>>(3)?,
+                ))
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut records: Vec<AuditRecord> = vec![];
+        for (time, operation, paths, tool) in rows {
+            let paths: Vec<String> = match paths {
+                Some(paths) => serde_json::from_str(&paths)
+                    .map_err(|err| Error::InvalidAudit(err.to_string()))?,
+                None => vec![],
+            };
+            records.push(AuditRecord {
+                time,
+                operation,
+                paths,
+                tool: tool.unwrap_or_default(),
+            });
+        }
+        Ok(records)
+    }
+}
+
+impl PackBuilder {
+    // Note that the operation affected the entry at the given path within the
+    // archive, to be recorded in the audit table by commit().
+    pub(crate) fn note_change(&self, operation: &'static str, path: String) {
+        let mut audit = self.audit.borrow_mut();
+        match audit.last_mut() {
+            Some((last, paths)) if *last == operation => paths.push(path),
+            _ => audit.push((operation, vec![path])),
+        }
+    }
+
+    // Record the changes noted so far in the audit table.
+    pub(crate) fn record_changes(&self) -> Result<(), Error> {
+        for (operation, paths) in self.audit.take() {
+            record_audit(&self.conn, operation, &paths)?;
+        }
+        Ok(())
+    }
+}
+
+//
+// Add a row to the audit table for the operation, with the paths within the
+// archive of the entries it affected.
+//
+pub(crate) fn record_audit(
+    conn: &Connection,
+    operation: &str,
+    paths: &[String],
+) -> Result<(), Error> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0);
+    let paths = serde_json::to_string(paths).map_err(|err| Error::IOError(err.into()))?;
+    conn.execute(
+        "INSERT INTO audit (time, operation, paths, tool) VALUES (?1, ?2, ?3, ?4)",
+        (
+            crate::format_timestamp(now),
+            operation,
+            paths,
+            concat!("pack-rs ", env!("CARGO_PKG_VERSION")),
+        ),
+    )?;
+    tracing::info!(operation, "recorded change in audit table");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::EntryMetadataPatch;
+    use std::fs;
+
+    #[test]
+    fn test_history() -> Result<(), Error> {
+        let workspace = tempfile::tempdir()?;
+        let basepath = workspace.path().join("input");
+        fs::create_dir_all(basepath.join("docs"))?;
+        fs::write(basepath.join("docs/notes.txt"), "notes")?;
+        fs::write(basepath.join("hello.txt"), "hello")?;
+        fs::write(basepath.join("gone.txt"), "gone")?;
+        let packfile = workspace.path().join("pack.db3");
+        let mut builder = PackBuilder::new()?;
+        builder.add_dir_all(&basepath)?;
+        builder.finish(&packfile)?;
+        assert!(PackReader::new(&packfile)?.history()?.is_empty());
+
+        fs::write(basepath.join("hello.txt"), "hello world")?;
+        fs::remove_file(basepath.join("gone.txt"))?;
+        fs::write(basepath.join("docs/new.txt"), "new")?;
+        let mut builder = PackBuilder::open(&packfile)?;
+        builder.update_all(vec![&basepath])?;
+        let patch = EntryMetadataPatch {
+            mode: Some(0o600),
+            ..Default::default()
+        };
+        builder.set_metadata("input/hello.txt", &patch)?;
+        builder.set_metadata("input/docs/", &patch)?;
+        builder.commit()?;
+        // an update that changes nothing is not recorded
+        let mut builder = PackBuilder::open(&packfile)?;
+        builder.update_all(vec![&basepath])?;
+        builder.commit()?;
+
+        let reader = PackReader::new(&packfile)?;
+        let history = reader.history()?;
+        assert_eq!(history.len(), 2);
+        assert_eq!(history[0].operation, "update");
+        let mut paths = history[0].paths.clone();
+        paths.sort();
+        assert_eq!(
+            paths,
+            vec!["input/docs/new.txt", "input/gone.txt", "input/hello.txt"]
+        );
+        assert_eq!(history[1].operation, "set-metadata");
+        assert_eq!(history[1].paths, vec!["input/hello.txt", "input/docs/"]);
+        assert!(history[1].tool.starts_with("pack-rs "));
+        assert!(history[1].time.ends_with('Z'));
+
+        // the log cannot be rewritten
+        let conn = Connection::open(&packfile)?;
+        assert!(conn.execute("DELETE FROM audit", ()).is_err());
+        assert!(conn
+            .execute("UPDATE audit SET operation = 'create'", ())
+            .is_err());
+
+        // a malformed list of paths is reported as such
+        conn.execute(
+            "INSERT INTO audit (time, operation, paths, tool) VALUES (?1, ?2, ?3, ?4)",
+            ("2024-03-01T00:00:00Z", "update", "[\"input/", "pack-rs"),
+        )?;
+        drop(conn);
+        let reader = PackReader::new(&packfile)?;
+        assert!(matches!(reader.history(), Err(Error::InvalidAudit(_))));
+        Ok(())
+    }
+}
@@ -0,0 +1,266 @@
+//
+// Copyright (c) 2024 Nathan Fiedler
+//
+use crate::{Error, ExtractOptions, PackBuilder, PackReader, PerformanceProfile, ZstdCodec};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+///
+/// Settings to be compared by `benchmark()`.
+///
+#[derive(Clone, Debug)]
+pub struct BenchOptions {
+    /// Maximum number of bytes of file content to be packed with each
+    /// combination of settings.
+    pub sample_size: u64,
+    /// Target sizes of the uncompressed content bundles.
+    pub bundle_sizes: Vec<u64>,
+    /// Zstandard compression levels, where zero selects the default level.
+    pub levels: Vec<i32>,
+    /// Numbers of threads used for compression.
+    pub threads: Vec<u32>,
+    /// Performance profiles with which each archive is then extracted.
+    pub profiles: Vec<PerformanceProfile>,
+}
+
+impl Default for BenchOptions {
+    fn default() -> Self {
+        Self {
+            sample_size: 64 * 1048576,
+            bundle_sizes: vec![4 * 1048576, 16 * 1048576, 64 * 1048576],
+            levels: vec![1, 3, 9, 19],
+            threads: vec![1, 4],
+            profiles: vec![PerformanceProfile::Balanced],
+        }
+    }
+}
+
+///
+/// Outcome of packing the sample with one combination of settings.
+///
+#[derive(Clone, Debug)]
+pub struct BenchResult {
+    /// Target size of the uncompressed content bundles.
+    pub bundle_size: u64,
+    /// Zstandard compression level.
+    pub level: i32,
+    /// Number of threads used for compression.
+    pub threads: u32,
+    /// Performance profile used to extract the archive.
+    pub profile: PerformanceProfile,
+    /// Time taken to create the archive.
+    pub elapsed: Duration,
+    /// Time taken to extract the archive.
+    pub extract_elapsed: Duration,
+    /// Total size of the files in the sample.
+    pub input_bytes: u64,
+    /// Size of the resulting archive.
+    pub archive_bytes: u64,
+}
+
+impl BenchResult {
+    ///
+    /// Return the size of the archive relative to the size of the input, as a
+    /// percentage, or zero if there was no input.
+    ///
+    pub fn ratio(&self) -> f64 {
+        if self.input_bytes == 0 {
+            0.0
+        } else {
+            self.archive_bytes as f64 * 100.0 / self.input_bytes as f64
+        }
+    }
+
+    ///
+    /// Return the average number of input bytes processed per second.
+    ///
+    pub fn throughput(&self) -> f64 {
+        rate(self.input_bytes, self.elapsed)
+    }
+
+    ///
+    /// Return the average number of bytes extracted per second.
+    ///
+    pub fn extract_throughput(&self) -> f64 {
+        rate(self.input_bytes, self.extract_elapsed)
+    }
+}
+
+// Return the number of bytes processed per second, or zero if no time passed.
+fn rate(bytes: u64, elapsed: Duration) -> f64 {
+    let seconds = elapsed.as_secs_f64();
+    if seconds > 0.0 {
+        bytes as f64 / seconds
+    } else {
+        0.0
+    }
+}
+
+///
+/// Pack a sample of the files within the given directory using every
+/// combination of the bundle sizes, compression levels, and thread counts in
+/// the options, and extract each archive with every performance profile,
+/// returning the time taken and resulting size of each. The sample consists
+/// of the files found first when walking the directory, up to the sample
+/// size, and the archives are written to the temporary directory and then
+/// removed, as are the extracted files.
+///
+/// Note that the archive will usually be in the page cache of the operating
+/// system when it is extracted, such that the extraction times reflect the
+/// cost of reading the database and decompressing more than that of the
+/// storage device.
+///
+pub fn benchmark<P: AsRef<Path>>(
+    basepath: P,
+    options: &BenchOptions,
+) -> Result<Vec<BenchResult>, Error> {
+    let sample = collect_sample(basepath.as_ref(), options.sample_size)?;
+    let archive = std::env::temp_dir().join(format!("pack-rs-bench-{}.db3", std::process::id()));
+    let output = std::env::temp_dir().join(format!("pack-rs-bench-{}", std::process::id()));
+    let mut results: Vec<BenchResult> = vec![];
+    for bundle_size in options.bundle_sizes.iter() {
+        for level in options.levels.iter() {
+            for threads in options.threads.iter() {
+                let result = pack_sample(&sample, &archive, *bundle_size, *level, *threads)
+                    .and_then(|packed| {
+                        let mut extracted = vec![];
+                        for profile in options.profiles.iter() {
+                            let result = extract_sample(&archive, &output, *profile);
+                            let _ = fs::remove_dir_all(&output);
+                            extracted.push((*profile, result?));
+                        }
+                        Ok((packed, extracted))
+                    });
+                let _ = fs::remove_file(&archive);
+                let ((elapsed, input_bytes, archive_bytes), extracted) = result?;
+                for (profile, extract_elapsed) in extracted {
+                    tracing::info!(
+                        bundle_size,
+                        level,
+                        threads,
+                        ?profile,
+                        elapsed_ms = elapsed.as_millis() as u64,
+                        extract_ms = extract_elapsed.as_millis() as u64,
+                        archive_bytes,
+                        "benchmark complete"
+                    );
+                    results.push(BenchResult {
+                        bundle_size: *bundle_size,
+                        level: *level,
+                        threads: *threads,
+                        profile,
+                        elapsed,
+                        extract_elapsed,
+                        input_bytes,
+                        archive_bytes,
+                    });
+                }
+            }
+        }
+    }
+    Ok(results)
+}
+
+// Return the paths of the regular files found first in the directory, until
+// their total size reaches the sample size.
+fn collect_sample(basepath: &Path, sample_size: u64) -> Result<Vec<PathBuf>, Error> {
+    let mut sample: Vec<PathBuf> = vec![];
+    let mut total: u64 = 0;
+    let mut pending: Vec<PathBuf> = vec![basepath.to_path_buf()];
+    while let Some(dir) = pending.pop() {
+        let mut entries = fs::read_dir(&dir)?
+            .map(|e| e.map(|e| e.path()))
+            .collect::<Result<Vec<_>, _>>()?;
+        // sort for a consistent sample from one run to the next
+        entries.sort();
+        for path in entries {
+            let metadata = fs::symlink_metadata(&path)?;
+            if metadata.is_dir() {
+                pending.push(path);
+            } else if metadata.is_file() {
+                if total >= sample_size {
+                    return Ok(sample);
+                }
+                total += metadata.len();
+                sample.push(path);
+            }
+        }
+    }
+    Ok(sample)
+}
+
+// Pack the files into an archive with the given settings, returning the time
+// taken, the size of the input, and the size of the archive.
+fn pack_sample(
+    sample: &[PathBuf],
+    archive: &Path,
+    bundle_size: u64,
+    level: i32,
+    threads: u32,
+) -> Result<(Duration, u64, u64), Error> {
+    let mut builder = PackBuilder::new()?;
+    builder.set_bundle_size(bundle_size);
+    builder.set_codec(Box::new(ZstdCodec::new(level)));
+    builder.set_cpu_limit(threads);
+    for path in sample.iter() {
+        builder.add_file(path, 0)?;
+    }
+    let report = builder.finish(archive)?;
+    Ok((report.elapsed, report.input_bytes, report.archive_bytes))
+}
+
+// Extract the archive into the given directory using the performance
+// profile, returning the time taken.
+fn extract_sample(
+    archive: &Path,
+    output: &Path,
+    profile: PerformanceProfile,
+) -> Result<Duration, Error> {
+    let start = std::time::Instant::now();
+    fs::create_dir_all(output)?;
+    let mut reader = PackReader::new(archive)?;
+    reader.set_performance_profile(profile)?;
+    let options = ExtractOptions {
+        destination: output.to_path_buf(),
+        ..Default::default()
+    };
+    reader.extract_all_with(&options)?;
+    Ok(start.elapsed())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_benchmark() -> Result<(), Error> {
+        let workspace = tempfile::tempdir()?;
+        let basepath = workspace.path().join("input");
+        fs::create_dir_all(basepath.join("sub"))?;
+        fs::write(basepath.join("one.txt"), "one ".repeat(1000))?;
+        fs::write(basepath.join("sub/two.txt"), "two ".repeat(1000))?;
+        fs::write(basepath.join("three.txt"), "three ".repeat(1000))?;
+        let options = BenchOptions {
+            sample_size: 5000,
+            bundle_sizes: vec![1024, 65536],
+            levels: vec![1, 19],
+            threads: vec![1],
+            profiles: vec![PerformanceProfile::Safe, PerformanceProfile::Fast],
+        };
+        let results = benchmark(&basepath, &options)?;
+        assert_eq!(results.len(), 8);
+        for result in results.iter() {
+            // one.txt and three.txt reach the sample size before sub/two.txt
+            assert_eq!(result.input_bytes, 10000);
+            assert!(result.archive_bytes > 0);
+        }
+        assert_eq!(results[0].bundle_size, 1024);
+        assert_eq!(results[0].profile, PerformanceProfile::Safe);
+        assert_eq!(results[1].profile, PerformanceProfile::Fast);
+        assert_eq!(results[1].elapsed, results[0].elapsed);
+        assert_eq!(results[2].level, 19);
+        assert_eq!(results[4].bundle_size, 65536);
+        Ok(())
+    }
+}
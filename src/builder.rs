@@ -0,0 +1,5096 @@
+//
+// Copyright (c) 2024 Nathan Fiedler
+//
+use crate::crypto::{ContentKey, SealedCodec, ENCRYPTION_CONTENT, KEY_ENTRY};
+use crate::digest::Hasher;
+use crate::owner::{NameCache, Owner};
+use crate::{
+    create_tables, Codec, DigestAlgorithm, Error, PerformanceProfile, StoredCodec, Warning,
+    WarningCallback, ZstdCodec, BUNDLE_SIZE, KIND_DIRECTORY, KIND_FILE, KIND_SYMLINK,
+};
+use rusqlite::{Connection, DatabaseName};
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+//
+// Represents the content of a file (item) and its position within a content
+// bundle when building an archive. It is possible that a portion of the file is
+// being added and thus the itempos might be non-zero; similarly the size may be
+// less than the actual file length.
+//
+struct IncomingContent {
+    // path of the file being packed
+    path: PathBuf,
+    // the rowid in the item table
+    item: i64,
+    // offset within the file from which to start, usually zero
+    itempos: u64,
+    // offset within the content bundle where the data will go
+    contentpos: u64,
+    // size of the item content
+    size: u64,
+    // content read from a stream, in which case the path is not used
+    data: Option<Vec<u8>>,
+}
+
+///
+/// Token for cancelling a long-running operation on a `PackBuilder` from
+/// another thread, obtained from `PackBuilder::cancel_token()`.
+///
+#[derive(Clone, Debug, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    ///
+    /// Request that the builder stop as soon as possible. The operation in
+    /// progress, and any that follow, will fail with `Error::Cancelled`.
+    ///
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    ///
+    /// Return true if cancellation has been requested.
+    ///
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+///
+/// How to handle files and directories that cannot be read while adding
+/// content to the archive, such as those without read permission.
+///
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ErrorPolicy {
+    /// Fail with the error that occurred.
+    #[default]
+    Fail,
+    /// Leave the entry out of the archive, logging a warning, and continue.
+    Warn,
+    /// Leave the entry out of the archive and continue.
+    Skip,
+}
+
+///
+/// Order in which the files found within a directory tree are added to the
+/// content bundles by `add_dir_all()` and `add_input()`. Compression improves
+/// when similar files are placed in the same bundle.
+///
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SortOrder {
+    /// Add the files in the order in which the directories are read.
+    #[default]
+    None,
+    /// Add the files in order of their paths.
+    Name,
+    /// Group the files by extension, and then by directory.
+    Extension,
+    /// Add the files from the smallest to the largest.
+    Size,
+}
+
+///
+/// Creates or updates an archive.
+///
+/// Dropping the builder without calling `finish()` (or `commit()` for those
+/// created using `open()`) discards all of the changes.
+///
+pub struct PackBuilder {
+    // database connection
+    pub(crate) conn: Connection,
+    // byte offset within a bundle to which new content is added
+    current_pos: u64,
+    // item content that will reside in the bundle under construction
+    contents: Vec<IncomingContent>,
+    // position and content of the bundle for content that is not compressed,
+    // which are swapped with the above while adding such content
+    stored_pos: u64,
+    stored_contents: Vec<IncomingContent>,
+    // true while the bundle for uncompressed content is the current bundle
+    storing: bool,
+    // if true, files that are unlikely to compress are not compressed
+    store_incompressible: bool,
+    // workspace for compressing the content bundles
+    buffer: Option<Vec<u8>>,
+    // if true, bundles are compressed directly into the database when the
+    // codec can bound the compressed size
+    streaming_writes: bool,
+    // algorithm for compressing the content bundles
+    pub(crate) codec: Box<dyn Codec>,
+    // if set, the level of the above is adjusted for each bundle
+    adaptive: Option<AdaptiveLevel>,
+    // files of at least this size are given content bundles of their own
+    large_file_threshold: Option<u64>,
+    // algorithm for compressing the bundles of large files, if not the above
+    large_file_codec: Option<Box<dyn Codec>>,
+    // true while the content of a large file is being added
+    isolating: bool,
+    // checksums of files whose content has not yet been fully bundled, along
+    // with the length of each file
+    hashers: HashMap<i64, (Hasher, u64)>,
+    // algorithm for the checksums of the files and content bundles
+    pub(crate) digest: DigestAlgorithm,
+    // if true, files with identical content share the same item content
+    pub(crate) content_addressed: bool,
+    // if true, only the size and checksum of each file are recorded
+    pub(crate) metadata_only: bool,
+    // item whose content was first stored for each file digest
+    digests: HashMap<Vec<u8>, i64>,
+    // if true, files added to an archive opened for update share identical
+    // content that the archive held when it was opened
+    reuse_prior: bool,
+    // sizes of the files whose content the archive held when it was opened,
+    // loaded when first needed
+    prior_sizes: Option<HashSet<u64>>,
+    // items that will share the content of another item whose content has
+    // not yet been fully bundled, as (item, canonical item)
+    pub(crate) aliases: Vec<(i64, i64)>,
+    // patterns for excluding entries when visiting directories
+    pub(crate) excludes: Vec<glob::Pattern>,
+    // if true, directory entries are added in order sorted by name
+    pub(crate) sort_entries: bool,
+    // order in which the files of a directory tree are added
+    sort_order: SortOrder,
+    // if set, modification times later than this are clamped to this value
+    mtime_clamp: Option<i64>,
+    // names of the owners of the entries, by identifier
+    names: RefCell<NameCache>,
+    // true if the content bundles of the archive are to be encrypted
+    pub(crate) encrypted: bool,
+    // key with which the content bundles are encrypted, once known
+    pub(crate) content_key: Option<ContentKey>,
+    // if set, the rate at which file content is read is limited
+    throttle: Option<Throttle>,
+    // maximum number of threads used for compression, zero if not limited
+    threads: u32,
+    // target size of the uncompressed content bundles
+    bundle_size: u64,
+    // if true, named pipes and device nodes are added to the archive
+    pub(crate) special_files: bool,
+    // if set, directories deeper than this below an input are not descended
+    max_depth: Option<usize>,
+    // if true, directories on other file systems than the input are not
+    // descended
+    one_file_system: bool,
+    // if true, the resource fork and Finder metadata are recorded (macOS)
+    mac_metadata: bool,
+    // canonical path of the archive being written, which is left out of the
+    // directories being walked
+    output: Option<PathBuf>,
+    // paths of entries that were not added because of their type
+    pub(crate) skipped: Vec<PathBuf>,
+    // how to handle entries that cannot be read
+    error_policy: ErrorPolicy,
+    // entries that were not added because they could not be read
+    unreadable: Vec<(PathBuf, Error)>,
+    // non-fatal events that occurred while adding entries
+    warnings: RefCell<Vec<Warning>>,
+    // invoked with each warning as it occurs
+    on_warning: Option<Box<WarningCallback>>,
+    // changes to be recorded in the audit table by commit(), as (operation,
+    // paths of the entries affected)
+    pub(crate) audit: RefCell<Vec<(&'static str, Vec<String>)>>,
+    // journal mode to restore once the changes are committed, if the archive
+    // is using the write-ahead log for the sake of concurrent readers
+    restore_journal_mode: Option<String>,
+    // if true, the archive is verified after being written by finish()
+    verify_on_finish: bool,
+    // when the builder was constructed, for reporting the elapsed time
+    started: Instant,
+    // signals that the current operation should stop
+    cancel: CancelToken,
+    // page size of the written archive, if other than the default
+    pub(crate) page_size: Option<u32>,
+    // settings of the database, applied again should it be moved to disk
+    pub(crate) profile: PerformanceProfile,
+    // pragmas set by the caller, in the order given
+    pub(crate) pragmas: Vec<(String, String)>,
+    // size beyond which the in-memory database is moved to disk
+    memory_limit: Option<u64>,
+    // true once the database has been moved to a temporary file
+    spilled: bool,
+    // entries added since the memory limit was last checked
+    unchecked_entries: u64,
+    // true once the database has been moved to the output path, such that
+    // finish() commits the archive in place rather than copying it there
+    in_place: bool,
+    // time spent in each phase of building the archive
+    timings: Cell<Timings>,
+}
+
+impl PackBuilder {
+    ///
+    /// Construct a new `PackBuilder` that will operate entirely in memory.
+    ///
+    pub fn new() -> Result<Self, Error> {
+        let conn = Connection::open_in_memory()?;
+        // the page size must be set before any tables are created
+        PerformanceProfile::default().apply_for_writing(&conn)?;
+        create_tables(&conn)?;
+        Ok(Self::with_connection(conn))
+    }
+
+    ///
+    /// Construct a `PackBuilder` that will modify the existing pack file at the
+    /// given location. All changes are made within a single transaction.
+    ///
+    /// **Note:** Remember to call `commit()` when done making changes.
+    ///
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let conn = Connection::open(path.as_ref())?;
+        PerformanceProfile::default().apply_for_writing(&conn)?;
+        conn.execute_batch("BEGIN TRANSACTION")?;
+        // changing an archive written by a newer version could corrupt it
+        crate::check_features(&conn)?;
+        // older archives will lack some of the tables
+        create_tables(&conn)?;
+        let mut builder = Self::with_connection(conn);
+        builder.reuse_prior = true;
+        builder.set_output_path(path.as_ref());
+        if let Some(digest) = builder.read_archive_meta("digest")? {
+            builder.digest = digest.parse()?;
+        }
+        match builder.read_archive_meta("layout")?.as_deref() {
+            Some(crate::LAYOUT_CONTENT_ADDRESSED) => builder.set_content_addressed(true)?,
+            Some(crate::LAYOUT_METADATA_ONLY) => builder.set_metadata_only(true),
+            _ => (),
+        }
+        builder.encrypted = builder.read_archive_meta("encryption")?.is_some();
+        let mode: String = builder
+            .conn
+            .pragma_query_value(None, "journal_mode", |row| row.get(0))?;
+        if mode.eq_ignore_ascii_case("wal") {
+            // left that way by a builder while readers had the archive open
+            let prior = PerformanceProfile::default().journal_mode();
+            builder.restore_journal_mode = Some(prior.to_owned());
+        }
+        Ok(builder)
+    }
+
+    // Construct a `PackBuilder` using the given (prepared) database connection.
+    fn with_connection(mut conn: Connection) -> Self {
+        conn.profile(Some(crate::trace_statement));
+        Self {
+            conn,
+            current_pos: 0,
+            contents: vec![],
+            stored_pos: 0,
+            stored_contents: vec![],
+            storing: false,
+            store_incompressible: true,
+            buffer: None,
+            streaming_writes: false,
+            codec: Box::new(ZstdCodec::default()),
+            adaptive: None,
+            large_file_threshold: None,
+            large_file_codec: None,
+            isolating: false,
+            hashers: HashMap::new(),
+            digest: DigestAlgorithm::Blake3,
+            content_addressed: false,
+            metadata_only: false,
+            digests: HashMap::new(),
+            reuse_prior: false,
+            prior_sizes: None,
+            aliases: vec![],
+            excludes: vec![],
+            sort_entries: false,
+            sort_order: SortOrder::None,
+            mtime_clamp: None,
+            names: RefCell::new(NameCache::default()),
+            encrypted: false,
+            content_key: None,
+            throttle: None,
+            threads: 0,
+            bundle_size: BUNDLE_SIZE,
+            special_files: false,
+            max_depth: None,
+            one_file_system: false,
+            mac_metadata: false,
+            output: None,
+            skipped: vec![],
+            error_policy: ErrorPolicy::Fail,
+            unreadable: vec![],
+            warnings: RefCell::new(vec![]),
+            on_warning: None,
+            audit: RefCell::new(vec![]),
+            restore_journal_mode: None,
+            verify_on_finish: false,
+            started: Instant::now(),
+            cancel: CancelToken::default(),
+            page_size: None,
+            profile: PerformanceProfile::default(),
+            pragmas: vec![],
+            memory_limit: None,
+            spilled: false,
+            unchecked_entries: 0,
+            in_place: false,
+            timings: Cell::new(Timings::default()),
+        }
+    }
+
+    ///
+    /// Enable the reproducible mode, in which the entries of each directory
+    /// are added in sorted order and all modification times are clamped to
+    /// `epoch` (seconds since the Unix epoch, typically the value of
+    /// `SOURCE_DATE_EPOCH`), such that packing identical trees will produce
+    /// identical pack files. Use an `epoch` of zero to discard the times.
+    ///
+    pub fn set_reproducible(&mut self, epoch: i64) {
+        self.sort_entries = true;
+        self.mtime_clamp = Some(epoch);
+    }
+
+    ///
+    /// Set the order in which the files within a directory tree are added by
+    /// `add_dir_all()` and `add_input()`. Unless the order is
+    /// `SortOrder::None` (the default), the files of the entire tree are
+    /// gathered first, and then added in the given order, such that similar
+    /// files end up in the same content bundles.
+    ///
+    pub fn set_sort_order(&mut self, order: SortOrder) {
+        self.sort_order = order;
+    }
+
+    ///
+    /// Limit the rate at which file content is read to the given number of
+    /// bytes per second, averaged over the life of the builder.
+    ///
+    pub fn set_io_limit(&mut self, bytes_per_sec: u64) {
+        self.throttle = Some(Throttle::new(bytes_per_sec));
+    }
+
+    ///
+    /// Compress the content bundles using at most the given number of threads,
+    /// if the codec supports multiple threads. By default the compression is
+    /// performed on the calling thread.
+    ///
+    pub fn set_cpu_limit(&mut self, threads: u32) {
+        self.threads = threads;
+        self.codec.set_threads(threads);
+        if let Some(codec) = self.large_file_codec.as_mut() {
+            codec.set_threads(threads);
+        }
+    }
+
+    ///
+    /// Set the target size of the uncompressed content bundles, which is 16
+    /// MiB by default. Larger bundles tend to compress better, at the cost of
+    /// reading more data when extracting a single file. This should be set
+    /// before any content is added.
+    ///
+    /// The size is limited to 512 MiB, well within the largest value that
+    /// SQLite will store (about 1 GB by default).
+    ///
+    pub fn set_bundle_size(&mut self, bytes: u64) {
+        self.bundle_size = bytes.clamp(1, MAX_BUNDLE_SIZE);
+    }
+
+    ///
+    /// If enabled, each content bundle is compressed directly into space
+    /// reserved in a temporary table, rather than into a buffer that is then
+    /// copied to the database, such that the compressed bundle is never held
+    /// in memory. The space reserved is the largest size the codec could
+    /// produce, of which only the part that was written is then copied to
+    /// the archive, a piece at a time.
+    ///
+    /// Codecs that cannot bound the compressed size (see
+    /// `Codec::max_compressed_len()`) are always written using a buffer.
+    ///
+    pub fn set_streaming_writes(&mut self, enabled: bool) {
+        self.streaming_writes = enabled;
+    }
+
+    ///
+    /// Use the given codec for compressing the content bundles added from now
+    /// on, instead of the default `ZstdCodec`. The archive can only be read by
+    /// a `PackReader` that has a matching codec registered.
+    ///
+    pub fn set_codec(&mut self, mut codec: Box<dyn Codec>) {
+        if self.threads > 0 {
+            codec.set_threads(self.threads);
+        }
+        self.codec = codec;
+    }
+
+    ///
+    /// Enable the adaptive mode, in which the compression level of each
+    /// content bundle is chosen according to how long the previous bundles
+    /// took to compress compared to reading their content and writing them
+    /// out. When compressing takes longer the level is lowered, and when it
+    /// takes less time the level is raised, within the range of 1 to 19 and
+    /// starting from 3. This favors speed when the input and output are fast,
+    /// and ratio when they are slow, similar to the `--adapt` option of the
+    /// `zstd` command.
+    ///
+    /// The level is given to the usual codec via `Codec::set_level()`, and
+    /// thus has no effect on codecs without levels. Bundles of large files
+    /// that use their own codec (see `set_large_file_policy()`) keep the
+    /// level of that codec.
+    ///
+    pub fn set_adaptive_compression(&mut self, enabled: bool) {
+        self.adaptive = enabled.then(|| AdaptiveLevel::new(self.timings.get()));
+    }
+
+    ///
+    /// Give each file of at least `threshold` bytes a sequence of content
+    /// bundles of its own, rather than sharing bundles with other files. This
+    /// makes reading part of a very large file cheaper, as no other content
+    /// is decompressed along with it, and allows different files to be
+    /// decompressed in parallel. If a codec is given, such as a `ZstdCodec`
+    /// with a higher level, it is used for these bundles instead of the usual
+    /// codec. Files that appear to be incompressible are still stored without
+    /// compression (see `set_store_incompressible()`).
+    ///
+    pub fn set_large_file_policy(&mut self, threshold: u64, codec: Option<Box<dyn Codec>>) {
+        self.large_file_threshold = Some(threshold.max(1));
+        self.large_file_codec = codec.map(|mut codec| {
+            if self.threads > 0 {
+                codec.set_threads(self.threads);
+            }
+            codec
+        });
+    }
+
+    ///
+    /// Move the archive being built from memory to a temporary database on
+    /// disk once it grows beyond the given number of bytes, such that packing
+    /// a very large tree does not exhaust the available memory. The size is
+    /// checked each time a content bundle is written, and every so many
+    /// entries, such that metadata-only archives and trees of many small files
+    /// are covered as well. Archives that stay below the limit are built
+    /// entirely in memory, as usual. The temporary
+    /// database is created in the directory named by `SQLITE_TMPDIR` (or the
+    /// usual temporary directory) and removed when the builder is dropped.
+    ///
+    /// This has no effect on builders created using `open()`, which already
+    /// operate on the archive file.
+    ///
+    pub fn set_memory_limit(&mut self, bytes: u64) {
+        self.memory_limit = Some(bytes);
+    }
+
+    // Check the memory limit once for every so many entries that are added,
+    // as the rows that describe them take up space even without content.
+    pub(crate) fn count_entry(&mut self) -> Result<(), Error> {
+        self.unchecked_entries += 1;
+        if self.unchecked_entries < MEMORY_CHECK_ENTRIES {
+            return Ok(());
+        }
+        self.check_memory_limit()
+    }
+
+    // Move the in-memory database to a private temporary database on disk if
+    // it has grown beyond the memory limit.
+    pub(crate) fn check_memory_limit(&mut self) -> Result<(), Error> {
+        self.unchecked_entries = 0;
+        let Some(limit) = self.memory_limit else {
+            return Ok(());
+        };
+        if self.spilled || !self.conn.path().is_none_or(|p| p.is_empty()) {
+            return Ok(());
+        }
+        let page_count: u64 = self
+            .conn
+            .pragma_query_value(None, "page_count", |row| row.get(0))?;
+        let page_size: u64 = self
+            .conn
+            .pragma_query_value(None, "page_size", |row| row.get(0))?;
+        let size = page_count * page_size;
+        if size <= limit {
+            return Ok(());
+        }
+        // an empty file name yields a temporary database that SQLite removes
+        // once the connection is closed
+        let mut scratch = Connection::open("")?;
+        rusqlite::backup::Backup::new(&self.conn, &mut scratch)?.run_to_completion(
+            1024,
+            Duration::ZERO,
+            None,
+        )?;
+        // the database is copied to the archive by finish(), and discarded in
+        // the event of a crash, so there is no need for durability
+        scratch.pragma_update(None, "journal_mode", "OFF")?;
+        scratch.pragma_update(None, "synchronous", "OFF")?;
+        self.reapply_settings(&scratch)?;
+        scratch.profile(Some(crate::trace_statement));
+        self.conn = scratch;
+        self.spilled = true;
+        tracing::info!(size, limit, "moved build database to disk");
+        Ok(())
+    }
+
+    ///
+    /// If enabled, named pipes and device nodes found by `add_dir_all()` and
+    /// `update_all()` are recorded in the archive, otherwise they are skipped.
+    /// Sockets are always skipped.
+    ///
+    pub fn set_special_files(&mut self, enabled: bool) {
+        self.special_files = enabled;
+    }
+
+    ///
+    /// Limit how many levels of directories below each input are descended
+    /// by `add_dir_all()` and `add_input()`, with `None` (the default) for no
+    /// limit. A limit of `1` adds the entries of the input directory, but
+    /// not the contents of its subdirectories. Directories at the limit are
+    /// recorded without their contents, with `Warning::DepthLimitReached`.
+    ///
+    pub fn set_max_depth(&mut self, depth: Option<usize>) {
+        self.max_depth = depth;
+    }
+
+    ///
+    /// If enabled, directories found by `add_dir_all()` and `add_input()`
+    /// that reside on a different file system than the input, such as mount
+    /// points, are recorded without their contents, with
+    /// `Warning::FileSystemBoundary`. Has no effect on Windows.
+    ///
+    pub fn set_one_file_system(&mut self, enabled: bool) {
+        self.one_file_system = enabled;
+    }
+
+    ///
+    /// Name the file to which `finish()` will write the archive, such that
+    /// it is left out of the inputs, with `Warning::SkippedOutput`, rather
+    /// than being packed into itself, as would happen when creating an
+    /// archive within one of the directories being added. The temporary and
+    /// journal files that are written alongside the archive are left out as
+    /// well. Builders created with `open()` already know their archive.
+    ///
+    pub fn set_output_path<P: AsRef<Path>>(&mut self, path: P) {
+        self.output = crate::canonical_file_path(path.as_ref()).ok();
+    }
+
+    // Return true if the file is the archive being written, or one of the
+    // files written alongside it.
+    fn is_output(&self, path: &Path) -> bool {
+        let Some(output) = self.output.as_ref() else {
+            return false;
+        };
+        let (Some(name), Some(output_name)) = (path.file_name(), output.file_name()) else {
+            return false;
+        };
+        // only the names are compared at first, to avoid resolving the path
+        // of every file that is added
+        let matched = OUTPUT_SUFFIXES.iter().any(|suffix| {
+            let mut candidate = output_name.to_owned();
+            candidate.push(suffix);
+            candidate == name
+        });
+        matched
+            && crate::canonical_file_path(path)
+                .is_ok_and(|canonical| canonical.parent() == output.parent())
+    }
+
+    ///
+    /// If enabled, the resource fork and Finder metadata (type, creator,
+    /// flags, and label) of each file and directory are recorded in the
+    /// archive. These are only available on macOS, where they are kept in
+    /// extended attributes; elsewhere this setting has no effect.
+    ///
+    pub fn set_mac_metadata(&mut self, enabled: bool) {
+        self.mac_metadata = enabled;
+    }
+
+    ///
+    /// Return the paths of the entries that were skipped by `add_dir_all()`
+    /// or `update_all()` because they are not files, directories, or symbolic
+    /// links (or are special files that were not enabled).
+    ///
+    pub fn skipped(&self) -> &[PathBuf] {
+        &self.skipped
+    }
+
+    // Leave the special file out of the archive, noting that it was skipped.
+    pub(crate) fn skip_special(&mut self, path: PathBuf) {
+        self.warn(Warning::SkippedSpecialFile(path.clone()));
+        self.skipped.push(path);
+    }
+
+    ///
+    /// Set a function to be invoked with each non-fatal event as it occurs,
+    /// such as a special file being skipped or a symbolic link being broken.
+    /// The warnings are also collected and available from `warnings()`.
+    ///
+    pub fn set_on_warning<F>(&mut self, callback: F)
+    where
+        F: Fn(&Warning) + Send + 'static,
+    {
+        self.on_warning = Some(Box::new(callback));
+    }
+
+    ///
+    /// Return the non-fatal events that occurred while adding entries, in the
+    /// order in which they occurred.
+    ///
+    pub fn warnings(&self) -> Vec<Warning> {
+        self.warnings.borrow().clone()
+    }
+
+    // Record the warning and pass it along to the callback, if any.
+    pub(crate) fn warn(&self, warning: Warning) {
+        tracing::warn!(%warning, "non-fatal event");
+        if let Some(callback) = self.on_warning.as_ref() {
+            callback(&warning);
+        }
+        self.warnings.borrow_mut().push(warning);
+    }
+
+    // Return the last part of the path as a String, warning if the name had
+    // to be converted lossily.
+    pub(crate) fn entry_name(&self, path: &Path) -> String {
+        if path.file_name().is_some_and(|name| name.to_str().is_none()) {
+            self.warn(Warning::LossyFileName(path.to_path_buf()));
+        }
+        get_file_name(path)
+    }
+
+    ///
+    /// Set how files and directories that cannot be read are handled by
+    /// `add_dir_all()`, `add_input()`, `add_path()`, and `update_all()`. By
+    /// default the operation fails with the error.
+    ///
+    pub fn set_error_policy(&mut self, policy: ErrorPolicy) {
+        self.error_policy = policy;
+    }
+
+    ///
+    /// Return the paths of the entries that were left out because they could
+    /// not be read, along with the error that occurred for each.
+    ///
+    pub fn unreadable(&self) -> &[(PathBuf, Error)] {
+        &self.unreadable
+    }
+
+    // Ensure that the file can be opened for reading, returning `false` if it
+    // cannot and the error policy allows for it to be left out, or if it is
+    // the archive being written.
+    fn check_readable(&mut self, path: &Path) -> Result<bool, Error> {
+        if self.is_output(path) {
+            self.warn(Warning::SkippedOutput(path.to_path_buf()));
+            return Ok(false);
+        }
+        match fs::File::open(path) {
+            Ok(_) => Ok(true),
+            Err(err) => self.handle_unreadable(path, err.into()),
+        }
+    }
+
+    // Apply the error policy to the entry that could not be read, returning
+    // `false` if it is to be left out, otherwise the error.
+    pub(crate) fn handle_unreadable(&mut self, path: &Path, err: Error) -> Result<bool, Error> {
+        match self.error_policy {
+            ErrorPolicy::Fail => return Err(err),
+            ErrorPolicy::Warn => {
+                tracing::warn!(path = %path.display(), error = %err, "skipping unreadable entry")
+            }
+            ErrorPolicy::Skip => (),
+        }
+        self.unreadable.push((path.to_path_buf(), err));
+        Ok(false)
+    }
+
+    ///
+    /// Return a token that can be used to cancel the operations of this
+    /// builder from another thread.
+    ///
+    pub fn cancel_token(&self) -> CancelToken {
+        self.cancel.clone()
+    }
+
+    ///
+    /// Abandon the archive, discarding all of the changes. For builders created
+    /// using `open()` the transaction is rolled back, leaving the archive as it
+    /// was, and likewise for those building in place with concurrent reads,
+    /// leaving the archive as of the most recent `publish()`; otherwise
+    /// nothing is written. This is the same as dropping the
+    /// builder, but reports any error from rolling back.
+    ///
+    pub fn cancel(self) -> Result<(), Error> {
+        if !self.conn.is_autocommit() {
+            self.conn.execute_batch("ROLLBACK")?;
+        }
+        Ok(())
+    }
+
+    // Return an error if cancellation has been requested.
+    pub(crate) fn check_cancelled(&self) -> Result<(), Error> {
+        if self.cancel.is_cancelled() {
+            return Err(Error::Cancelled);
+        }
+        Ok(())
+    }
+
+    // Add the time elapsed since `started` to the selected phase.
+    pub(crate) fn add_timing(&self, started: Instant, phase: fn(&mut Timings) -> &mut Duration) {
+        add_timing(&self.timings, started, phase);
+    }
+
+    ///
+    /// Set how long to wait for another connection to release its lock on the
+    /// database before failing with a "database is locked" error. This mainly
+    /// applies to archives modified using `open()`, and defaults to 5 seconds.
+    ///
+    pub fn set_busy_timeout(&mut self, timeout: Duration) -> Result<(), Error> {
+        self.conn.busy_timeout(timeout)?;
+        Ok(())
+    }
+
+    ///
+    /// If enabled, the archive uses the SQLite write-ahead log, such that a
+    /// `PackReader` in another connection or process can list and extract
+    /// entries while the archive is being built or changed, without either
+    /// waiting on the other. Readers see the archive as it was at the most
+    /// recent call to `publish()`, which makes the changes so far visible
+    /// without ending the work of the builder; until then, they see the
+    /// archive as it was before it was opened, or an empty archive. Each
+    /// query of a reader, and each extraction as a whole, sees a single such
+    /// snapshot.
+    ///
+    /// For a builder created with `new()`, the archive is from now on built
+    /// directly in the file named by `set_output_path()`, which is replaced
+    /// at once rather than by `finish()`, and `finish()` must be given that
+    /// same path. Should the builder fail or be dropped before finishing, the
+    /// file holds the archive as of the most recent `publish()`. Returns an
+    /// error if no output path was set.
+    ///
+    /// Changes made before this is called are published. Once the builder
+    /// calls `commit()` or `finish()`, the archive returns to its usual
+    /// journal mode, unless a reader still has it open, in which case the
+    /// next builder to open the archive does so.
+    ///
+    pub fn set_concurrent_reads(&mut self, enabled: bool) -> Result<(), Error> {
+        if self.conn.is_autocommit() {
+            // a builder created with new(), which is not yet on disk
+            return if enabled {
+                self.build_in_place()
+            } else {
+                Ok(())
+            };
+        }
+        if enabled == self.restore_journal_mode.is_some() {
+            return Ok(());
+        }
+        self.publish_changes()?;
+        self.conn.execute_batch("COMMIT")?;
+        let result = if enabled {
+            let mode: String = self
+                .conn
+                .pragma_query_value(None, "journal_mode", |row| row.get(0))?;
+            self.restore_journal_mode = Some(mode);
+            self.conn.pragma_update(None, "journal_mode", "WAL")
+        } else {
+            self.restore_journal_mode().map(|_| ())
+        };
+        self.conn.execute_batch("BEGIN TRANSACTION")?;
+        result?;
+        Ok(())
+    }
+
+    // Move the database being built to the output path, replacing whatever
+    // is there, and carry on building it there in write-ahead log mode
+    // within a transaction, as with an archive opened using open().
+    fn build_in_place(&mut self) -> Result<(), Error> {
+        let Some(path) = self.output.clone() else {
+            return Err(Error::IOError(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "concurrent reads require an output path",
+            )));
+        };
+        self.publish_changes()?;
+        for suffix in ["", "-wal", "-shm"] {
+            let mut name = path.as_os_str().to_owned();
+            name.push(suffix);
+            if fs::symlink_metadata(&name).is_ok() {
+                fs::remove_file(&name)?;
+            }
+        }
+        let mut conn = Connection::open(&path)?;
+        rusqlite::backup::Backup::new(&self.conn, &mut conn)?.run_to_completion(
+            1024,
+            Duration::ZERO,
+            None,
+        )?;
+        self.profile.apply_for_writing(&conn)?;
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        self.reapply_settings(&conn)?;
+        conn.execute_batch("BEGIN TRANSACTION")?;
+        conn.profile(Some(crate::trace_statement));
+        self.conn = conn;
+        // as with an archive copied into place by finish(), which leaves no
+        // journal file behind
+        self.restore_journal_mode = Some("DELETE".into());
+        self.in_place = true;
+        tracing::info!(path = %path.display(), "building archive in place");
+        Ok(())
+    }
+
+    // Return the archive to the journal mode it had before the write-ahead
+    // log was enabled, returning the mode that is now in effect. The mode
+    // cannot be changed while other connections have the archive open.
+    fn restore_journal_mode(&mut self) -> rusqlite::Result<String> {
+        let Some(prior) = self.restore_journal_mode.take() else {
+            return Ok(String::new());
+        };
+        let mode: String =
+            self.conn
+                .pragma_update_and_check(None, "journal_mode", &prior, |row| row.get(0))?;
+        if !mode.eq_ignore_ascii_case(&prior) {
+            tracing::warn!(mode, "archive left in write-ahead log mode");
+        }
+        Ok(mode)
+    }
+
+    ///
+    /// If enabled, `finish()` will check the integrity of the written archive,
+    /// including the checksum of every content bundle, before moving it into
+    /// place.
+    ///
+    pub fn set_verify_on_finish(&mut self, enabled: bool) {
+        self.verify_on_finish = enabled;
+    }
+
+    ///
+    /// If enabled (the default), files that are unlikely to benefit from
+    /// compression, such as images, videos, and other archives, are placed in
+    /// content bundles that are stored without compression. Such files are
+    /// detected by their extension or the randomness of their first block.
+    ///
+    pub fn set_store_incompressible(&mut self, enabled: bool) {
+        self.store_incompressible = enabled;
+    }
+
+    ///
+    /// Enable the content-addressed layout, in which files with identical
+    /// content (by BLAKE3 digest) share a single copy of that content, both
+    /// within this archive and with archives merged into it. The digest of
+    /// every file is recorded in the `chunk` table, and the layout is noted
+    /// in the `archive_meta` table, such that `open()` will continue to use
+    /// this layout when updating the archive.
+    ///
+    /// Each file is read one additional time in order to compute its digest
+    /// before deciding whether to store its content.
+    ///
+    pub fn set_content_addressed(&mut self, enabled: bool) -> Result<(), Error> {
+        self.content_addressed = enabled;
+        self.digests.clear();
+        if enabled {
+            let mut stmt = self
+                .conn
+                .prepare("SELECT digest, item FROM chunk ORDER BY item")?;
+            let rows = stmt
+                .query_map([], |row| Ok((row.get::<_, Vec<u8>>(0)?, row.get(1)?)))?
+                .collect::<Result<Vec<_>, _>>()?;
+            for (digest, item_id) in rows {
+                self.digests.entry(digest).or_insert(item_id);
+            }
+        }
+        Ok(())
+    }
+
+    ///
+    /// For an archive opened using `open()`, files that are added or changed
+    /// will share any identical content that the archive held when it was
+    /// opened, rather than storing another copy, such that files that are
+    /// moved or restored do not bloat an archive that is updated repeatedly.
+    /// Only files whose size matches that of a file in the archive are read
+    /// an additional time to compute their checksum. This is enabled by
+    /// default, and has no effect on new archives.
+    ///
+    /// Content-addressed archives always share identical content.
+    ///
+    pub fn set_reuse_prior_content(&mut self, enabled: bool) {
+        self.reuse_prior = enabled;
+    }
+
+    // Gather the content of the files in the archive in a temporary table,
+    // if not done already, such that it remains available after the files
+    // are changed or removed.
+    fn load_prior_content(&mut self) -> Result<(), Error> {
+        if self.prior_sizes.is_none() {
+            self.conn.execute(
+                "CREATE TEMPORARY TABLE prior_content (
+                    file INTEGER,
+                    checksum BLOB,
+                    mime TEXT,
+                    itempos INTEGER,
+                    content INTEGER,
+                    contentpos INTEGER,
+                    size INTEGER
+                )",
+                (),
+            )?;
+            // the content of removed files remains until commit() deletes
+            // the bundles that are no longer referenced
+            self.conn.execute(
+                "INSERT INTO prior_content SELECT itemcontent.item, attrs.checksum,
+                    attrs.mime, itempos, content, contentpos, size FROM itemcontent
+                    INNER JOIN attrs ON attrs.item = itemcontent.item
+                    INNER JOIN item ON item.id = itemcontent.item
+                    WHERE item.kind = ?1 AND attrs.checksum IS NOT NULL AND content != ?2",
+                (KIND_FILE, crate::NO_CONTENT),
+            )?;
+            self.conn.execute(
+                "CREATE INDEX temp.prior_checksum ON prior_content (checksum)",
+                (),
+            )?;
+            let mut stmt = self
+                .conn
+                .prepare("SELECT SUM(size) FROM prior_content GROUP BY file")?;
+            let sizes = stmt
+                .query_map([], |row| row.get::<_, u64>(0))?
+                .collect::<Result<HashSet<_>, _>>()?;
+            self.prior_sizes = Some(sizes);
+        }
+        Ok(())
+    }
+
+    // Return true if the archive held a file of the given size when opened.
+    fn has_prior_size(&mut self, size: u64) -> Result<bool, Error> {
+        self.load_prior_content()?;
+        Ok(self.prior_sizes.as_ref().is_some_and(|s| s.contains(&size)))
+    }
+
+    // If the archive held content with the given digest and size when it was
+    // opened, give that content to the item and return `true`.
+    fn reuse_prior_content(&self, item_id: i64, digest: &[u8], size: u64) -> Result<bool, Error> {
+        let mut stmt = self.conn.prepare(
+            "SELECT file, mime FROM prior_content WHERE checksum = ?1
+                GROUP BY file HAVING SUM(size) = ?2 LIMIT 1",
+        )?;
+        let mut rows = stmt.query((digest, &size))?;
+        let Some(row) = rows.next()? else {
+            return Ok(false);
+        };
+        let file: i64 = row.get(0)?;
+        let mime: Option<String> = row.get(1)?;
+        self.conn.execute(
+            "INSERT INTO itemcontent (item, itempos, content, contentpos, size)
+                SELECT ?1, itempos, content, contentpos, size FROM prior_content
+                WHERE file = ?2 ORDER BY itempos",
+            (&item_id, &file),
+        )?;
+        self.conn.execute(
+            "INSERT INTO attrs (item, checksum, mime) VALUES (?1, ?2, ?3)
+                ON CONFLICT(item) DO UPDATE SET checksum = ?2, mime = ?3",
+            (&item_id, digest, &mime),
+        )?;
+        Ok(true)
+    }
+
+    ///
+    /// Enable the metadata-only mode, in which the path, size, permissions,
+    /// modification time, and checksum of each file are recorded, but not its
+    /// content, producing a compact snapshot of a tree that can later be
+    /// compared against that tree to detect any changes. The mode is noted in
+    /// the `archive_meta` table, such that `open()` will continue to use it
+    /// when updating the archive. Such archives cannot be extracted.
+    ///
+    pub fn set_metadata_only(&mut self, enabled: bool) {
+        self.metadata_only = enabled;
+    }
+
+    ///
+    /// Select the algorithm for computing the checksums of the files and
+    /// content bundles, which is BLAKE3 by default. The algorithm is noted
+    /// in the `archive_meta` table, such that `open()` will continue to use
+    /// it when updating the archive.
+    ///
+    /// Returns `Error::DigestMismatch` if the archive already records
+    /// checksums computed with another algorithm.
+    ///
+    pub fn set_digest_algorithm(&mut self, algorithm: DigestAlgorithm) -> Result<(), Error> {
+        if algorithm != self.digest && self.has_checksums()? {
+            return Err(Error::DigestMismatch(
+                self.digest.to_string(),
+                algorithm.to_string(),
+            ));
+        }
+        self.digest = algorithm;
+        Ok(())
+    }
+
+    ///
+    /// Encrypt the content of the files with a key that is protected by the
+    /// given password, leaving the metadata of the entries (names, sizes,
+    /// times, modes, owners, and file checksums) readable by anyone, such
+    /// that the archive can be listed and searched without the password. The
+    /// MIME types of the files are not recorded, as they would reveal
+    /// something of the content. The mode is noted in the `archive_meta`
+    /// table, such that readers require the password to read any content.
+    ///
+    /// For an archive opened with `open()` that is already encrypted, this
+    /// provides the password needed to add content, and returns
+    /// `Error::WrongPassword` if it does not match. Returns
+    /// `Error::EncryptionMismatch` if the archive already has content that is
+    /// not encrypted.
+    ///
+    pub fn encrypt_content(&mut self, password: &str) -> Result<(), Error> {
+        if self.encrypted {
+            let wrapped = self
+                .read_archive_meta(KEY_ENTRY)?
+                .ok_or(Error::PasswordRequired)?;
+            let encryption = self.read_archive_meta("encryption")?.unwrap_or_default();
+            self.content_key = Some(ContentKey::unwrap(&wrapped, password, &encryption)?);
+            return Ok(());
+        }
+        let has_content: bool =
+            self.conn
+                .query_row("SELECT EXISTS (SELECT 1 FROM content)", [], |row| {
+                    row.get(0)
+                })?;
+        if has_content || !self.contents.is_empty() {
+            return Err(Error::EncryptionMismatch(
+                "archive already has unencrypted content".into(),
+            ));
+        }
+        let key = ContentKey::generate()?;
+        let wrapped = key.wrap(password)?;
+        for (name, value) in [("encryption", key.encryption()), (KEY_ENTRY, &wrapped)] {
+            self.conn.execute(
+                "INSERT OR REPLACE INTO archive_meta (name, value) VALUES (?1, ?2)",
+                [name, value],
+            )?;
+        }
+        self.encrypted = true;
+        self.content_key = Some(key);
+        Ok(())
+    }
+
+    ///
+    /// Return true if the content of the archive is encrypted, in which case
+    /// `encrypt_content()` must be given the password before adding content.
+    ///
+    pub fn is_encrypted(&self) -> bool {
+        self.encrypted
+    }
+
+    ///
+    /// Change the password of an encrypted archive opened with `open()`. The
+    /// key with which the content is encrypted is unwrapped using the current
+    /// password and wrapped again with the new one, leaving the content
+    /// bundles untouched, such that the change takes the same time regardless
+    /// of the size of the archive. The change is saved by `commit()`.
+    ///
+    /// Only the key-encryption key derived from the password is rotated; the
+    /// data key that encrypts the content stays the same. Anyone who learned
+    /// the data key, or who kept a copy of the archive and knows the old
+    /// password, can still read the content. The old wrapped key is
+    /// overwritten with zeros rather than left behind in a free page.
+    ///
+    /// Returns `Error::EncryptionMismatch` if the archive is not encrypted,
+    /// and `Error::WrongPassword` if the current password does not match.
+    ///
+    pub fn change_password(&mut self, current: &str, new: &str) -> Result<(), Error> {
+        if !self.encrypted {
+            return Err(Error::EncryptionMismatch(
+                "archive content is not encrypted".into(),
+            ));
+        }
+        let wrapped = self
+            .read_archive_meta(KEY_ENTRY)?
+            .ok_or(Error::PasswordRequired)?;
+        let encryption = self.read_archive_meta("encryption")?.unwrap_or_default();
+        let key = ContentKey::unwrap(&wrapped, current, &encryption)?;
+        // zero the space freed by the update so the old wrapped key, which
+        // the old password still opens, does not linger in the file
+        let secure_delete: i64 = self
+            .conn
+            .pragma_query_value(None, "secure_delete", |row| row.get(0))?;
+        self.conn.pragma_update(None, "secure_delete", 1)?;
+        let updated = self.conn.execute(
+            "UPDATE archive_meta SET value = ?2 WHERE name = ?1",
+            [KEY_ENTRY, &key.wrap(new)?],
+        );
+        self.conn
+            .pragma_update(None, "secure_delete", secure_delete)?;
+        updated?;
+        self.content_key = Some(key);
+        self.audit.borrow_mut().push(("rekey", vec![]));
+        Ok(())
+    }
+
+    // Fail before queueing any content that could not be encrypted later.
+    fn require_content_key(&self) -> Result<(), Error> {
+        if self.encrypted && self.content_key.is_none() {
+            return Err(Error::PasswordRequired);
+        }
+        Ok(())
+    }
+
+    // Take on the encryption of an archive whose content bundles are to be
+    // copied as-is, given its wrapped key and the value of its `encryption`
+    // entry, if any. The bundles can only be mixed if both use the same key,
+    // or if either set is empty.
+    pub(crate) fn adopt_encryption(
+        &mut self,
+        wrapped: Option<String>,
+        encryption: Option<String>,
+        has_content: bool,
+    ) -> Result<(), Error> {
+        let own = self.read_archive_meta(KEY_ENTRY)?;
+        if own == wrapped || !has_content {
+            return Ok(());
+        }
+        let is_empty: bool = self.contents.is_empty()
+            && self
+                .conn
+                .query_row("SELECT NOT EXISTS (SELECT 1 FROM content)", [], |row| {
+                    row.get(0)
+                })?;
+        match (own, wrapped) {
+            (None, Some(wrapped)) if is_empty => {
+                let encryption = encryption.as_deref().unwrap_or(ENCRYPTION_CONTENT);
+                for (name, value) in [("encryption", encryption), (KEY_ENTRY, &wrapped)] {
+                    self.conn.execute(
+                        "INSERT OR REPLACE INTO archive_meta (name, value) VALUES (?1, ?2)",
+                        [name, value],
+                    )?;
+                }
+                self.encrypted = true;
+                Ok(())
+            }
+            _ => Err(Error::EncryptionMismatch(
+                "archives are not encrypted with the same key".into(),
+            )),
+        }
+    }
+
+    // Return true if any checksums have been computed, or are being computed,
+    // using the current digest algorithm.
+    fn has_checksums(&self) -> Result<bool, Error> {
+        if !self.hashers.is_empty() || !self.digests.is_empty() {
+            return Ok(true);
+        }
+        let found: bool = self.conn.query_row(
+            "SELECT EXISTS (SELECT 1 FROM content)
+                OR EXISTS (SELECT 1 FROM attrs WHERE checksum IS NOT NULL)",
+            [],
+            |row| row.get(0),
+        )?;
+        Ok(found)
+    }
+
+    // Return the value of the named entry in the archive_meta table, if any.
+    fn read_archive_meta(&self, name: &str) -> Result<Option<String>, Error> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT value FROM archive_meta WHERE name = ?1")?;
+        let mut rows = stmt.query([name])?;
+        match rows.next()? {
+            Some(row) => Ok(row.get(0)?),
+            None => Ok(None),
+        }
+    }
+
+    ///
+    /// Add a pattern for excluding entries when visiting a directory with
+    /// `add_dir_all()`. A pattern that contains a slash (/) is matched against
+    /// the path of the entry as it would appear in the archive, otherwise it
+    /// is matched against the name of the entry.
+    ///
+    /// Patterns use the usual shell wildcards: `?`, `*`, `**`, and `[...]`.
+    ///
+    pub fn add_exclude(&mut self, pattern: &str) -> Result<(), Error> {
+        let compiled =
+            glob::Pattern::new(pattern).map_err(|_| Error::InvalidPattern(pattern.to_owned()))?;
+        self.excludes.push(compiled);
+        Ok(())
+    }
+
+    // Return true if the path matches any of the exclusion patterns.
+    pub(crate) fn is_excluded(&self, relpath: &Path) -> bool {
+        let options = glob::MatchOptions {
+            require_literal_separator: true,
+            ..Default::default()
+        };
+        let name = relpath.file_name().map(Path::new).unwrap_or(relpath);
+        self.excludes.iter().any(|pattern| {
+            if pattern.as_str().contains('/') {
+                pattern.matches_path_with(relpath, options)
+            } else {
+                pattern.matches_path_with(name, options)
+            }
+        })
+    }
+
+    ///
+    /// Visit all of the files and directories within the specified path, adding
+    /// them to the database.
+    ///
+    /// Returns `Error::PathCollision` if the archive already has an entry at
+    /// the root with the same name as the directory.
+    ///
+    /// **Note:** Remember to call `finish()` when done adding content.
+    ///
+    pub fn add_dir_all<P: AsRef<Path>>(&mut self, basepath: P) -> Result<u64, Error> {
+        self.add_input(basepath, 0)
+    }
+
+    ///
+    /// Add the file or directory (and everything within it) at the given path
+    /// to the directory in the archive with the rowid
+    /// `parent`, with `0` being the root of the archive, and returning the
+    /// number of files added.
+    ///
+    /// Returns `Error::PathCollision` if that directory already has an entry
+    /// with the same name, as would happen when adding several inputs with
+    /// the same name (such as `a/src` and `b/src`) to the same directory.
+    ///
+    /// **Note:** Remember to call `finish()` when done adding content.
+    ///
+    pub fn add_input<P: AsRef<Path>>(&mut self, path: P, parent: i64) -> Result<u64, Error> {
+        let path = path.as_ref();
+        let _span = tracing::info_span!("add_input", path = %path.display()).entered();
+        let name = get_file_name(path);
+        let archived = self.item_path(parent, &name)?;
+        if self.find_child(&name, parent)?.is_some() {
+            return Err(Error::PathCollision(archived));
+        }
+        self.note_change("add", archived);
+        // symbolic links given as inputs are followed
+        let metadata = fs::metadata(path)?;
+        if metadata.is_dir() {
+            return self.add_dir_tree(path, parent, None);
+        } else if metadata.is_file() {
+            if !self.check_readable(path)? {
+                return Ok(0);
+            }
+            self.add_file(path, parent)?;
+            return Ok(1);
+        } else if self.special_files && get_special_kind(&metadata).is_some() {
+            self.add_special(path, parent)?;
+        } else {
+            self.skip_special(path.to_path_buf());
+        }
+        Ok(0)
+    }
+
+    ///
+    /// Add the directory at the given path (and everything within it) to the
+    /// archive under the slash-separated `archived_name`, rather than the
+    /// name of the directory on disk, returning the number of files added.
+    /// Any leading directories of `archived_name` are added to the root of
+    /// the archive if they are not already present, so adding `app/src` as
+    /// `src` and `vendor/lib` as `third-party/lib` needs no copying of trees.
+    ///
+    /// Exclusion patterns are matched against the archived names.
+    ///
+    /// Returns `Error::PathCollision` if the archive already has an entry
+    /// with the archived name.
+    ///
+    /// **Note:** Remember to call `finish()` when done adding content.
+    ///
+    pub fn add_dir_as<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+        archived_name: &str,
+    ) -> Result<u64, Error> {
+        let path = path.as_ref();
+        let _span =
+            tracing::info_span!("add_dir_as", path = %path.display(), archived_name).entered();
+        if !fs::metadata(path)?.is_dir() {
+            return Err(Error::IOError(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("not a directory: {}", path.display()),
+            )));
+        }
+        let (parent, name) = self.add_archived_parents(archived_name)?;
+        self.add_dir_tree(path, parent, Some(&name))
+    }
+
+    ///
+    /// Add the file at the given path to the archive under the
+    /// slash-separated `archived_name`, such as `docs/guide/index.html`,
+    /// rather than the name of the file on disk, returning the item
+    /// identifier. Any leading directories of `archived_name` are added to
+    /// the root of the archive if they are not already present, such that the
+    /// layout of the archive need not follow that of the files on disk.
+    ///
+    /// Returns `Error::PathCollision` if the archive already has an entry
+    /// with the archived name.
+    ///
+    /// **Note:** Remember to call `finish()` when done adding content.
+    ///
+    pub fn add_file_as<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+        archived_name: &str,
+    ) -> Result<i64, Error> {
+        let path = path.as_ref();
+        let _span =
+            tracing::info_span!("add_file_as", path = %path.display(), archived_name).entered();
+        if !fs::metadata(path)?.is_file() {
+            return Err(Error::IOError(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("not a file: {}", path.display()),
+            )));
+        }
+        let (parent, name) = self.add_archived_parents(archived_name)?;
+        self.add_named_file(path, parent, &name)
+    }
+
+    // Add the leading directories of the slash-separated path within the
+    // archive, returning the rowid of the last one and the final name, which
+    // must not already be in use.
+    fn add_archived_parents(&mut self, archived_name: &str) -> Result<(i64, String), Error> {
+        let relpath = crate::sanitize_path(archived_name)?;
+        let Some(name) = relpath.file_name() else {
+            return Err(Error::PathEscapesRoot(PathBuf::from(archived_name)));
+        };
+        let name = name.to_string_lossy().into_owned();
+        let parent = match relpath.parent() {
+            Some(dirpath) => self.add_prefix(&dirpath.to_string_lossy())?,
+            None => 0,
+        };
+        let archived = self.item_path(parent, &name)?;
+        if self.find_child(&name, parent)?.is_some() {
+            return Err(Error::PathCollision(archived));
+        }
+        self.note_change("add", archived);
+        Ok((parent, name))
+    }
+
+    ///
+    /// Add the directories of the given slash-separated path to the root of
+    /// the archive, if they are not already present, returning the rowid of
+    /// the last directory (or `0` if the path is empty). Use this with
+    /// `add_input()` to place inputs within a common directory.
+    ///
+    pub fn add_prefix(&mut self, prefix: &str) -> Result<i64, Error> {
+        let relpath = crate::sanitize_path(prefix)?;
+        let mut parent: i64 = 0;
+        for component in relpath.components() {
+            let name = component.as_os_str().to_string_lossy();
+            parent = self.find_or_add_directory(&name, parent, &relpath)?;
+        }
+        Ok(parent)
+    }
+
+    // Return the path within the archive of the named entry in the directory
+    // with the given rowid.
+    fn item_path(&self, parent: i64, name: &str) -> Result<String, Error> {
+        let mut names: Vec<String> = vec![name.to_owned()];
+        let mut item_id = parent;
+        while item_id != 0 {
+            let (next, name): (i64, String) = self.conn.query_row(
+                "SELECT parent, name FROM item WHERE id = ?1",
+                [&item_id],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )?;
+            names.push(name);
+            item_id = next;
+        }
+        names.reverse();
+        Ok(names.join("/"))
+    }
+
+    // Add the directory and everything within it to the directory in the
+    // archive with the rowid `parent`, giving it the name `root_name`, if
+    // any, in place of its own.
+    fn add_dir_tree(
+        &mut self,
+        basepath: &Path,
+        parent: i64,
+        mut root_name: Option<&str>,
+    ) -> Result<u64, Error> {
+        let mut file_count: u64 = 0;
+        // directories yet to be visited, as (parent rowid, path, depth)
+        let mut subdirs: Vec<(i64, PathBuf, usize)> = Vec::new();
+        // files to be added once the tree has been walked, as (path, parent
+        // rowid, size), when they are to be added in some order
+        let mut pending: Vec<(PathBuf, i64, u64)> = Vec::new();
+        subdirs.push((parent, basepath.to_path_buf(), 0));
+        // directories already visited, by device and inode, such that bind
+        // mounts that lead back into the tree do not result in an endless
+        // walk
+        let mut visited: HashSet<(u64, u64)> = HashSet::new();
+        let root_device = fs::metadata(basepath)
+            .ok()
+            .and_then(|md| get_file_identity(&md))
+            .map(|(device, _)| device);
+        // paths within the archive are relative to the parent of the base path
+        let archive_root = basepath.parent().unwrap_or(Path::new(""));
+        let renamed_root = root_name.map(PathBuf::from);
+        while let Some((mut parent_id, currdir, depth)) = subdirs.pop() {
+            // the base path is always the first directory to be visited
+            parent_id = match root_name.take() {
+                Some(name) => self.add_directory_as(&currdir, parent_id, name)?,
+                None => self.add_directory(&currdir, parent_id)?,
+            };
+            if let Some(warning) = self.check_descent(&currdir, root_device, &mut visited) {
+                // the directory itself remains, without its contents
+                self.warn(warning);
+                continue;
+            }
+            let started = Instant::now();
+            let listing = read_dir_entries(&currdir);
+            self.add_timing(started, |t| &mut t.walking);
+            let mut entries = match listing {
+                Ok(entries) => entries,
+                Err(err) => {
+                    // the directory itself remains, without its contents
+                    self.handle_unreadable(&currdir, err.into())?;
+                    continue;
+                }
+            };
+            if self.max_depth.is_some_and(|max| depth >= max) && !entries.is_empty() {
+                self.warn(Warning::DepthLimitReached(currdir));
+                continue;
+            }
+            if self.sort_entries {
+                entries.sort_by_key(|e| e.file_name());
+            }
+            for entry in entries {
+                self.check_cancelled()?;
+                self.count_entry()?;
+                let path = entry.path();
+                if !self.excludes.is_empty() {
+                    let excluded = match renamed_root.as_ref() {
+                        Some(root) => {
+                            let subpath = path.strip_prefix(basepath).unwrap_or(&path);
+                            self.is_excluded(&root.join(subpath))
+                        }
+                        None => self.is_excluded(path.strip_prefix(archive_root).unwrap_or(&path)),
+                    };
+                    if excluded {
+                        continue;
+                    }
+                }
+                // DirEntry.metadata() does not follow symlinks and that is good
+                let started = Instant::now();
+                let metadata = entry.metadata();
+                self.add_timing(started, |t| &mut t.walking);
+                let metadata = match metadata {
+                    Ok(metadata) => metadata,
+                    Err(err) => {
+                        self.handle_unreadable(&path, err.into())?;
+                        continue;
+                    }
+                };
+                if metadata.is_dir() {
+                    subdirs.push((parent_id, path, depth + 1));
+                } else if metadata.is_file() {
+                    if self.check_readable(&path)? {
+                        if self.sort_order == SortOrder::None {
+                            self.add_file(&path, parent_id)?;
+                        } else {
+                            pending.push((path, parent_id, metadata.len()));
+                        }
+                        file_count += 1;
+                    }
+                } else if metadata.is_symlink() {
+                    self.add_symlink(&path, parent_id)?;
+                } else if self.special_files && get_special_kind(&metadata).is_some() {
+                    self.add_special(&path, parent_id)?;
+                } else {
+                    self.skip_special(path);
+                }
+            }
+        }
+        match self.sort_order {
+            SortOrder::None => (),
+            SortOrder::Name => pending.sort_by(|a, b| a.0.cmp(&b.0)),
+            SortOrder::Extension => pending.sort_by_cached_key(|(path, _, _)| {
+                let extension = path
+                    .extension()
+                    .map(|e| e.to_string_lossy().to_lowercase())
+                    .unwrap_or_default();
+                (extension, path.clone())
+            }),
+            SortOrder::Size => pending.sort_by(|a, b| (a.2, &a.0).cmp(&(b.2, &b.0))),
+        }
+        for (path, parent_id, _) in pending {
+            self.check_cancelled()?;
+            self.add_file(&path, parent_id)?;
+        }
+        Ok(file_count)
+    }
+
+    // Return the warning explaining why the contents of the directory are to
+    // be left out, if they are, recording the directory as visited.
+    fn check_descent(
+        &self,
+        path: &Path,
+        root_device: Option<u64>,
+        visited: &mut HashSet<(u64, u64)>,
+    ) -> Option<Warning> {
+        let md = fs::metadata(path).ok()?;
+        let (device, inode) = get_file_identity(&md)?;
+        if self.one_file_system && root_device.is_some_and(|root| root != device) {
+            return Some(Warning::FileSystemBoundary(path.to_path_buf()));
+        }
+        if !visited.insert((device, inode)) {
+            return Some(Warning::DirectoryLoop(path.to_path_buf()));
+        }
+        None
+    }
+
+    ///
+    /// Call `finish()` when all file content has been added to the builder.
+    ///
+    /// The resulting database will be written to the given `path`.
+    ///
+    /// Returns a summary of the archive contents and the time taken since the
+    /// builder was constructed.
+    ///
+    pub fn finish<P: AsRef<Path>>(&mut self, path: P) -> Result<CreateReport, Error> {
+        self.check_cancelled()?;
+        if self.in_place {
+            return self.finish_in_place(path.as_ref());
+        }
+        self.flush_bundles()?;
+        self.record_chunks()?;
+        self.record_origin()?;
+        // write to a temporary file and then rename it into place, such that
+        // a crash will never leave a partially written archive at the path
+        let mut tmp_name = path.as_ref().as_os_str().to_owned();
+        tmp_name.push(".tmp");
+        let tmp_path = PathBuf::from(tmp_name);
+        // backing up onto an existing database would carry over its change
+        // counter, so start with a new file to get predictable results
+        if fs::symlink_metadata(&tmp_path).is_ok() {
+            fs::remove_file(&tmp_path)?;
+        }
+        let started = Instant::now();
+        let result = self.write_archive(&tmp_path, path.as_ref());
+        self.add_timing(started, |t| &mut t.finishing);
+        if result.is_err() {
+            let _ = fs::remove_file(&tmp_path);
+        }
+        result?;
+        let report = self.create_report(path.as_ref())?;
+        tracing::info!(
+            files = report.files,
+            input_bytes = report.input_bytes,
+            archive_bytes = report.archive_bytes,
+            elapsed_ms = report.elapsed.as_millis() as u64,
+            "wrote archive"
+        );
+        Ok(report)
+    }
+
+    // Commit the archive that is being built at the output path, which must
+    // be the given path.
+    fn finish_in_place(&mut self, path: &Path) -> Result<CreateReport, Error> {
+        if crate::canonical_file_path(path).ok() != self.output {
+            return Err(Error::IOError(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("archive is being built at another path: {}", path.display()),
+            )));
+        }
+        self.flush_bundles()?;
+        self.record_chunks()?;
+        self.record_origin()?;
+        self.record_changes()?;
+        let started = Instant::now();
+        self.conn.execute_batch("COMMIT")?;
+        let mode = match self.restore_journal_mode() {
+            Ok(mode) => mode,
+            Err(err) => {
+                tracing::warn!(%err, "could not leave write-ahead log mode");
+                String::new()
+            }
+        };
+        if let Some(page_size) = self.page_size.filter(|_| !mode.eq_ignore_ascii_case("wal")) {
+            self.conn.pragma_update(None, "page_size", page_size)?;
+            self.conn.execute_batch("VACUUM")?;
+        }
+        if self.verify_on_finish {
+            crate::PackReader::new(path)?.verify_integrity()?;
+        }
+        self.add_timing(started, |t| &mut t.finishing);
+        let report = self.create_report(path)?;
+        tracing::info!(
+            files = report.files,
+            input_bytes = report.input_bytes,
+            archive_bytes = report.archive_bytes,
+            elapsed_ms = report.elapsed.as_millis() as u64,
+            "wrote archive in place"
+        );
+        Ok(report)
+    }
+
+    // Gather the statistics for the archive written to the given path.
+    fn create_report(&self, path: &Path) -> Result<CreateReport, Error> {
+        let mut report = CreateReport {
+            archive_bytes: fs::metadata(path)?.len(),
+            elapsed: self.started.elapsed(),
+            timings: self.timings.get(),
+            ..Default::default()
+        };
+        let mut stmt = self
+            .conn
+            .prepare("SELECT kind, COUNT(*) FROM item GROUP BY kind")?;
+        let counts = stmt
+            .query_map([], |row| Ok((row.get::<_, i8>(0)?, row.get::<_, u64>(1)?)))?
+            .collect::<Result<Vec<_>, _>>()?;
+        for (kind, count) in counts {
+            match kind {
+                KIND_FILE => report.files = count,
+                KIND_DIRECTORY => report.directories = count,
+                KIND_SYMLINK => report.symlinks = count,
+                _ => report.special_files += count,
+            }
+        }
+        report.input_bytes = self.conn.query_row(
+            "SELECT (SELECT IFNULL(SUM(size), 0) FROM itemcontent)
+                + (SELECT IFNULL(SUM(LENGTH(target)), 0) FROM item)",
+            [],
+            |row| row.get(0),
+        )?;
+        Ok(report)
+    }
+
+    // Write the database to the temporary path, flush it to disk, optionally
+    // verify it, and then move it to the final path.
+    fn write_archive(&self, tmp_path: &Path, path: &Path) -> Result<(), Error> {
+        self.conn.backup(DatabaseName::Main, tmp_path, None)?;
+        if let Some(page_size) = self.page_size {
+            // an in-memory database cannot change its page size, the copy can
+            let conn = Connection::open(tmp_path)?;
+            conn.pragma_update(None, "page_size", page_size)?;
+            conn.execute_batch("VACUUM")?;
+        }
+        fs::File::open(tmp_path)?.sync_all()?;
+        if self.verify_on_finish {
+            crate::PackReader::new(tmp_path)?.verify_integrity()?;
+        }
+        fs::rename(tmp_path, path)?;
+        // make the rename durable as well
+        #[cfg(target_family = "unix")]
+        {
+            let parent = match path.parent() {
+                Some(dir) if !dir.as_os_str().is_empty() => dir,
+                _ => Path::new("."),
+            };
+            fs::File::open(parent)?.sync_all()?;
+        }
+        Ok(())
+    }
+
+    ///
+    /// Commit the changes made so far to an archive that was opened using
+    /// `open()`, such that other connections can see them, and carry on
+    /// making changes in a new transaction. The files waiting to be written
+    /// to a content bundle are written first, even if the bundle is not full,
+    /// such that readers never see a file without its content. This is most
+    /// useful along with `set_concurrent_reads()`.
+    ///
+    pub fn publish(&mut self) -> Result<(), Error> {
+        self.check_cancelled()?;
+        self.publish_changes()?;
+        self.conn.execute_batch("COMMIT; BEGIN TRANSACTION")?;
+        tracing::info!("published changes");
+        Ok(())
+    }
+
+    // Write the changes that are still held by the builder to the database,
+    // such that they may be committed.
+    fn publish_changes(&mut self) -> Result<(), Error> {
+        self.flush_bundles()?;
+        self.record_chunks()?;
+        self.record_changes()
+    }
+
+    ///
+    /// Call `commit()` when all changes have been made to an archive that was
+    /// opened using `open()`.
+    ///
+    pub fn commit(&mut self) -> Result<(), Error> {
+        self.check_cancelled()?;
+        self.flush_bundles()?;
+        self.record_chunks()?;
+        self.record_origin()?;
+        self.record_changes()?;
+        // remove any content bundles that are no longer referenced
+        self.conn.execute(
+            "DELETE FROM content WHERE id NOT IN (SELECT content FROM itemcontent)",
+            (),
+        )?;
+        self.conn.execute_batch("COMMIT")?;
+        if self.restore_journal_mode.is_some() {
+            // the changes are committed regardless
+            if let Err(err) = self.restore_journal_mode() {
+                tracing::warn!(%err, "could not leave write-ahead log mode");
+            }
+        }
+        tracing::info!(
+            elapsed_ms = self.started.elapsed().as_millis() as u64,
+            "committed changes"
+        );
+        Ok(())
+    }
+
+    ///
+    /// Process any item content that has not yet been written to a bundle.
+    ///
+    fn flush_bundles(&mut self) -> Result<(), Error> {
+        if !self.contents.is_empty() {
+            self.process_contents()?;
+        }
+        if !self.stored_contents.is_empty() {
+            self.swap_bundles();
+            let result = self.process_contents();
+            self.swap_bundles();
+            result?;
+        }
+        Ok(())
+    }
+
+    ///
+    /// Give the items that share content their item content, then for the
+    /// content-addressed layout, record the digest of every file in the
+    /// `chunk` table along with the layout of the archive. The metadata-only
+    /// mode is likewise noted as the layout.
+    ///
+    fn record_chunks(&mut self) -> Result<(), Error> {
+        for (item_id, canonical) in std::mem::take(&mut self.aliases) {
+            self.copy_item_content(item_id, canonical)?;
+        }
+        if self.metadata_only {
+            self.conn.execute(
+                "INSERT OR REPLACE INTO archive_meta (name, value) VALUES ('layout', ?1)",
+                [crate::LAYOUT_METADATA_ONLY],
+            )?;
+            return Ok(());
+        }
+        if !self.content_addressed {
+            return Ok(());
+        }
+        // rebuilt from scratch as items may have been removed or changed
+        self.conn.execute("DELETE FROM chunk", ())?;
+        self.conn.execute(
+            "INSERT OR IGNORE INTO chunk (digest, item)
+                SELECT attrs.checksum, attrs.item FROM attrs
+                INNER JOIN item ON item.id = attrs.item
+                WHERE item.kind = ?1 AND attrs.checksum IS NOT NULL
+                ORDER BY attrs.item",
+            [KIND_FILE],
+        )?;
+        self.conn.execute(
+            "INSERT OR REPLACE INTO archive_meta (name, value) VALUES ('layout', ?1)",
+            [crate::LAYOUT_CONTENT_ADDRESSED],
+        )?;
+        Ok(())
+    }
+
+    ///
+    /// Record when the archive was created (as a UTC date and time) and by
+    /// which program, unless an earlier build of the archive already did so.
+    /// In the reproducible mode, the creation time is the clamped time.
+    ///
+    fn record_origin(&mut self) -> Result<(), Error> {
+        let epoch = self.mtime_clamp.unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs() as i64)
+                .unwrap_or(0)
+        });
+        let created = crate::format_timestamp(epoch);
+        let creator = concat!("pack-rs ", env!("CARGO_PKG_VERSION"));
+        for (name, value) in [("created", created.as_str()), ("creator", creator)] {
+            self.conn.execute(
+                "INSERT OR IGNORE INTO archive_meta (name, value) VALUES (?1, ?2)",
+                [name, value],
+            )?;
+        }
+        self.conn.execute(
+            "INSERT OR REPLACE INTO archive_meta (name, value) VALUES ('digest', ?1)",
+            [self.digest.identifier()],
+        )?;
+        Ok(())
+    }
+
+    ///
+    /// Exchange the current bundle with the bundle for uncompressed content.
+    ///
+    fn swap_bundles(&mut self) {
+        std::mem::swap(&mut self.current_pos, &mut self.stored_pos);
+        std::mem::swap(&mut self.contents, &mut self.stored_contents);
+        self.storing = !self.storing;
+    }
+
+    ///
+    /// Process the current bundle of item content, clearing the collection and
+    /// resetting the current content position.
+    ///
+    fn process_contents(&mut self) -> Result<(), Error> {
+        self.insert_content()?;
+        self.contents = vec![];
+        self.current_pos = 0;
+        Ok(())
+    }
+
+    ///
+    /// Add a row to the `item` table that corresponds to this directory.
+    ///
+    pub fn add_directory<P: AsRef<Path>>(&self, path: P, parent: i64) -> Result<i64, Error> {
+        let name = self.entry_name(path.as_ref());
+        self.add_directory_as(path.as_ref(), parent, &name)
+    }
+
+    // Add a row to the `item` table for the directory at the given path,
+    // with the given name.
+    fn add_directory_as(&self, path: &Path, parent: i64, name: &str) -> Result<i64, Error> {
+        let started = Instant::now();
+        self.conn.execute(
+            "INSERT INTO item (parent, kind, name) VALUES (?1, ?2, ?3)",
+            (&parent, KIND_DIRECTORY, name),
+        )?;
+        let item_id = self.conn.last_insert_rowid();
+        self.add_timing(started, |t| &mut t.inserting);
+        let md = fs::metadata(path)?;
+        self.set_attrs(item_id, &md)?;
+        self.record_mac_metadata(item_id, path)?;
+        Ok(item_id)
+    }
+
+    ///
+    /// Adds a single file to the archive, returning the item identifier.
+    ///
+    /// Depending on the size of the file and the content bundle so far, this
+    /// may result in writing one or more rows to the content and itemcontent
+    /// tables.
+    ///
+    /// **Note:** Remember to call `finish()` when done adding content.
+    ///
+    pub fn add_file<P: AsRef<Path>>(&mut self, path: P, parent: i64) -> Result<i64, Error> {
+        let name = self.entry_name(path.as_ref());
+        self.add_named_file(path.as_ref(), parent, &name)
+    }
+
+    // Add the file to the directory with the rowid `parent` under the given
+    // name, returning the item identifier.
+    fn add_named_file(&mut self, path: &Path, parent: i64, name: &str) -> Result<i64, Error> {
+        let started = Instant::now();
+        self.conn.execute(
+            "INSERT INTO item (parent, kind, name) VALUES (?1, ?2, ?3)",
+            (&parent, KIND_FILE, name),
+        )?;
+        let item_id = self.conn.last_insert_rowid();
+        self.add_timing(started, |t| &mut t.inserting);
+        self.add_file_content(path, item_id)?;
+        Ok(item_id)
+    }
+
+    ///
+    /// Adds the content of the file to the archive for the existing item, which
+    /// is assumed to have no content at present.
+    ///
+    fn add_file_content<P: AsRef<Path>>(&mut self, path: P, item_id: i64) -> Result<(), Error> {
+        let md = fs::metadata(path.as_ref());
+        let file_len = match md.as_ref() {
+            Ok(attr) => attr.len(),
+            Err(_) => 0,
+        };
+        match md.as_ref() {
+            Ok(attr) => self.set_attrs(item_id, attr)?,
+            Err(_) => self.warn(Warning::MetadataUnavailable(path.as_ref().to_path_buf())),
+        }
+        self.record_mac_metadata(item_id, path.as_ref())?;
+        tracing::debug!(path = %path.as_ref().display(), size = file_len, "adding file");
+        if self.metadata_only {
+            let started = Instant::now();
+            let digest = checksum_file(path.as_ref(), self.digest)?;
+            self.add_timing(started, |t| &mut t.reading);
+            return self.record_without_content(item_id, &digest, file_len);
+        }
+        if self.content_addressed {
+            let started = Instant::now();
+            let digest = checksum_file(path.as_ref(), self.digest)?;
+            self.add_timing(started, |t| &mut t.reading);
+            self.conn.execute(
+                "INSERT INTO attrs (item, checksum) VALUES (?1, ?2)
+                    ON CONFLICT(item) DO UPDATE SET checksum = ?2",
+                (&item_id, &digest),
+            )?;
+            if self.share_content(item_id, &digest)? {
+                tracing::debug!(item_id, "sharing identical content");
+                return Ok(());
+            }
+        }
+        if self.reuse_prior
+            && !self.content_addressed
+            && file_len > 0
+            && self.has_prior_size(file_len)?
+        {
+            let started = Instant::now();
+            let digest = checksum_file(path.as_ref(), self.digest)?;
+            self.add_timing(started, |t| &mut t.reading);
+            if self.reuse_prior_content(item_id, &digest, file_len)? {
+                tracing::debug!(item_id, "reusing content already in the archive");
+                return Ok(());
+            }
+        }
+        self.hashers
+            .insert(item_id, (Hasher::new(self.digest), file_len));
+        let store =
+            self.store_incompressible && file_len > 0 && is_incompressible(path.as_ref(), file_len);
+        let large = self.large_file_threshold.is_some_and(|t| file_len >= t);
+        if store {
+            self.swap_bundles();
+        }
+        let result = if large {
+            self.add_large_file_chunks(path.as_ref(), item_id, file_len)
+        } else {
+            self.add_file_chunks(path.as_ref(), item_id, file_len)
+        };
+        if store {
+            self.swap_bundles();
+        }
+        result
+    }
+
+    ///
+    /// Adds the content of a large file to bundles that hold nothing else,
+    /// setting aside the bundle under construction until the file is done.
+    ///
+    fn add_large_file_chunks(
+        &mut self,
+        path: &Path,
+        item_id: i64,
+        file_len: u64,
+    ) -> Result<(), Error> {
+        tracing::debug!(path = %path.display(), "isolating large file");
+        let current_pos = std::mem::take(&mut self.current_pos);
+        let contents = std::mem::take(&mut self.contents);
+        self.isolating = true;
+        let mut result = self.add_file_chunks(path, item_id, file_len);
+        if result.is_ok() && !self.contents.is_empty() {
+            result = self.process_contents();
+        }
+        self.isolating = false;
+        self.current_pos = current_pos;
+        self.contents = contents;
+        result
+    }
+
+    ///
+    /// For the content-addressed layout, if content with the given digest is
+    /// already in the archive, arrange for the item to share that content and
+    /// return `true`. Otherwise the item is noted as having that content and
+    /// `false` is returned, in which case the caller must add the content.
+    ///
+    pub(crate) fn share_content(&mut self, item_id: i64, digest: &[u8]) -> Result<bool, Error> {
+        if !self.content_addressed {
+            return Ok(false);
+        }
+        if let Some(canonical) = self.digests.get(digest).copied() {
+            // the item may have since been removed or its content changed
+            let mut stmt = self.conn.prepare(
+                "SELECT 1 FROM attrs INNER JOIN item ON item.id = attrs.item
+                    WHERE attrs.item = ?1 AND attrs.checksum = ?2 AND item.kind = ?3",
+            )?;
+            if canonical != item_id && stmt.exists((&canonical, digest, KIND_FILE))? {
+                // content not yet written to a bundle is shared once it is
+                let pending = self.hashers.contains_key(&canonical)
+                    || self.contents.iter().any(|c| c.item == canonical)
+                    || self.stored_contents.iter().any(|c| c.item == canonical);
+                if pending {
+                    self.aliases.push((item_id, canonical));
+                } else {
+                    self.copy_item_content(item_id, canonical)?;
+                }
+                return Ok(true);
+            }
+        }
+        self.digests.insert(digest.to_vec(), item_id);
+        Ok(false)
+    }
+
+    // Give the item the same item content as the canonical item.
+    fn copy_item_content(&self, item_id: i64, canonical: i64) -> Result<(), Error> {
+        self.conn.execute(
+            "INSERT INTO itemcontent (item, itempos, content, contentpos, size)
+                SELECT ?1, itempos, content, contentpos, size FROM itemcontent
+                WHERE item = ?2 ORDER BY itempos",
+            (&item_id, &canonical),
+        )?;
+        self.conn.execute(
+            "UPDATE attrs SET mime = (SELECT mime FROM attrs WHERE item = ?2) WHERE item = ?1",
+            (&item_id, &canonical),
+        )?;
+        Ok(())
+    }
+
+    ///
+    /// Adds the content of the file to the current bundle, starting new
+    /// bundles as needed.
+    ///
+    fn add_file_chunks(&mut self, path: &Path, item_id: i64, file_len: u64) -> Result<(), Error> {
+        // empty files will result in an itemcontent row whose size is zero,
+        // allowing for the extraction process to know to create an empty file
+        // (otherwise it is difficult to tell from the available data)
+        self.require_content_key()?;
+        let mut itempos: u64 = 0;
+        let mut size: u64 = file_len;
+        loop {
+            self.check_cancelled()?;
+            if self.current_pos + size > self.bundle_size {
+                let remainder = self.bundle_size.saturating_sub(self.current_pos);
+                // add a portion of the file to fill the bundle
+                let content = IncomingContent {
+                    path: path.to_path_buf(),
+                    item: item_id,
+                    itempos,
+                    contentpos: self.current_pos,
+                    size: remainder,
+                    data: None,
+                };
+                self.contents.push(content);
+                // insert the content and itemcontent rows and start a new
+                // bundle, then continue with the current file
+                self.process_contents()?;
+                size -= remainder;
+                itempos += remainder;
+            } else {
+                // the remainder of the file fits within this content bundle
+                let content = IncomingContent {
+                    path: path.to_path_buf(),
+                    item: item_id,
+                    itempos,
+                    contentpos: self.current_pos,
+                    size,
+                    data: None,
+                };
+                self.contents.push(content);
+                self.current_pos += size;
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    ///
+    /// Adds the file, symbolic link, or directory at the given path to the
+    /// archive at the same relative location, adding any parent directories
+    /// that are not already in the archive. Directories are not traversed, and
+    /// roots and parent directory components are dropped from the location
+    /// (as with `sanitize_path()`).
+    ///
+    /// Returns the number of files added, either zero or one.
+    ///
+    /// **Note:** Remember to call `finish()` when done adding content.
+    ///
+    pub fn add_path<P: AsRef<Path>>(&mut self, path: P) -> Result<u64, Error> {
+        let path = path.as_ref();
+        let relpath = crate::sanitize_path(path)?;
+        let Some(name) = relpath.file_name() else {
+            return Ok(0);
+        };
+        // the parent directories as they appear on disk, from the top down
+        let mut dirs: Vec<&Path> = path
+            .ancestors()
+            .skip(1)
+            .filter(|p| matches!(p.components().next_back(), Some(Component::Normal(_))))
+            .collect();
+        dirs.reverse();
+        let mut parent: i64 = 0;
+        for dir in dirs {
+            parent = match self.find_child(&get_file_name(dir), parent)? {
+                Some((item_id, KIND_DIRECTORY)) => item_id,
+                Some(_) => {
+                    return Err(Error::PathCollision(relpath.to_string_lossy().into_owned()))
+                }
+                None => self.add_directory(dir, parent)?,
+            };
+        }
+        let existing = self.find_child(&name.to_string_lossy(), parent)?;
+        let metadata = fs::symlink_metadata(path)?;
+        match existing {
+            Some((_, KIND_DIRECTORY)) if metadata.is_dir() => (),
+            Some(_) => return Err(Error::PathCollision(relpath.to_string_lossy().into_owned())),
+            None => {
+                if metadata.is_dir() {
+                    self.add_directory(path, parent)?;
+                } else if metadata.is_file() {
+                    if !self.check_readable(path)? {
+                        return Ok(0);
+                    }
+                    self.add_file(path, parent)?;
+                    return Ok(1);
+                } else if metadata.is_symlink() {
+                    self.add_symlink(path, parent)?;
+                } else if self.special_files && get_special_kind(&metadata).is_some() {
+                    self.add_special(path, parent)?;
+                } else {
+                    self.skip_special(path.to_path_buf());
+                }
+            }
+        }
+        Ok(0)
+    }
+
+    // Return the rowid and kind of the named child of the given parent.
+    pub(crate) fn find_child(&self, name: &str, parent: i64) -> Result<Option<(i64, i8)>, Error> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT id, kind FROM item WHERE parent = ?1 AND name = ?2")?;
+        let mut rows = stmt.query((&parent, name))?;
+        match rows.next()? {
+            Some(row) => Ok(Some((row.get(0)?, row.get(1)?))),
+            None => Ok(None),
+        }
+    }
+
+    // Return the rowid of the named directory, adding it if necessary.
+    pub(crate) fn find_or_add_directory(
+        &self,
+        name: &str,
+        parent: i64,
+        path: &Path,
+    ) -> Result<i64, Error> {
+        match self.find_child(name, parent)? {
+            Some((item_id, KIND_DIRECTORY)) => Ok(item_id),
+            Some(_) => Err(Error::PathCollision(path.to_string_lossy().into_owned())),
+            None => {
+                self.conn.execute(
+                    "INSERT INTO item (parent, kind, name) VALUES (?1, ?2, ?3)",
+                    (&parent, KIND_DIRECTORY, name),
+                )?;
+                Ok(self.conn.last_insert_rowid())
+            }
+        }
+    }
+
+    ///
+    /// Adds a named pipe or device node to the archive, returning the item
+    /// identifier. For device nodes the major and minor numbers are recorded.
+    ///
+    pub fn add_special<P: AsRef<Path>>(&mut self, path: P, parent: i64) -> Result<i64, Error> {
+        let metadata = fs::symlink_metadata(path.as_ref())?;
+        let kind = get_special_kind(&metadata).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("not a special file: {}", path.as_ref().display()),
+            )
+        })?;
+        let name = self.entry_name(path.as_ref());
+        let started = Instant::now();
+        self.conn.execute(
+            "INSERT INTO item (parent, kind, name) VALUES (?1, ?2, ?3)",
+            (&parent, kind, &name),
+        )?;
+        let item_id = self.conn.last_insert_rowid();
+        self.add_timing(started, |t| &mut t.inserting);
+        self.set_special_attrs(item_id, &metadata)?;
+        Ok(item_id)
+    }
+
+    // Record the attributes of the special file, including the device
+    // numbers, if any.
+    fn set_special_attrs(&self, item_id: i64, metadata: &fs::Metadata) -> Result<(), Error> {
+        self.set_attrs(item_id, metadata)?;
+        if let Some((major, minor)) = get_device_numbers(metadata) {
+            self.conn.execute(
+                "UPDATE attrs SET devmajor = ?2, devminor = ?3 WHERE item = ?1",
+                (&item_id, &major, &minor),
+            )?;
+        }
+        Ok(())
+    }
+
+    ///
+    /// Adds a file with the given name to the archive, reading its content
+    /// from the reader, returning the item identifier.
+    ///
+    /// At most one content bundle worth of data is held in memory at a time.
+    ///
+    /// **Note:** Remember to call `finish()` when done adding content.
+    ///
+    pub fn add_stream<R: Read>(
+        &mut self,
+        name: &str,
+        parent: i64,
+        reader: R,
+    ) -> Result<i64, Error> {
+        self.conn.execute(
+            "INSERT INTO item (parent, kind, name) VALUES (?1, ?2, ?3)",
+            (&parent, KIND_FILE, name),
+        )?;
+        let item_id = self.conn.last_insert_rowid();
+        self.add_stream_content(reader, item_id)?;
+        Ok(item_id)
+    }
+
+    ///
+    /// Adds the content read from the reader to the archive for the existing
+    /// item, filling as many content bundles as necessary.
+    ///
+    pub(crate) fn add_stream_content<R: Read>(
+        &mut self,
+        mut reader: R,
+        item_id: i64,
+    ) -> Result<(), Error> {
+        let mut hasher = Hasher::new(self.digest);
+        if self.metadata_only {
+            let size = io::copy(&mut reader, &mut hasher)?;
+            return self.record_without_content(item_id, &hasher.finalize(), size);
+        }
+        self.require_content_key()?;
+        let mut itempos: u64 = 0;
+        loop {
+            self.check_cancelled()?;
+            // read only as much as will fit in the current bundle
+            let remainder = self.bundle_size.saturating_sub(self.current_pos);
+            let started = Instant::now();
+            let mut data: Vec<u8> = vec![];
+            (&mut reader).take(remainder).read_to_end(&mut data)?;
+            hasher.update(&data);
+            self.add_timing(started, |t| &mut t.reading);
+            let size = data.len() as u64;
+            if size == 0 && itempos > 0 {
+                // the stream ended exactly at the end of the previous bundle
+                break;
+            }
+            let content = IncomingContent {
+                path: PathBuf::new(),
+                item: item_id,
+                itempos,
+                contentpos: self.current_pos,
+                size,
+                data: Some(data),
+            };
+            self.contents.push(content);
+            if size < remainder {
+                // reached the end of the stream
+                self.current_pos += size;
+                break;
+            }
+            self.process_contents()?;
+            itempos += size;
+        }
+        self.conn.execute(
+            "INSERT INTO attrs (item, checksum) VALUES (?1, ?2)
+                ON CONFLICT(item) DO UPDATE SET checksum = ?2",
+            (&item_id, hasher.finalize()),
+        )?;
+        Ok(())
+    }
+
+    // Record the checksum and size of a file whose content is not stored, as
+    // in the metadata-only mode.
+    fn record_without_content(&self, item_id: i64, digest: &[u8], size: u64) -> Result<(), Error> {
+        self.conn.execute(
+            "INSERT INTO attrs (item, checksum) VALUES (?1, ?2)
+                ON CONFLICT(item) DO UPDATE SET checksum = ?2",
+            (&item_id, digest),
+        )?;
+        self.conn.execute(
+            "INSERT INTO itemcontent (item, itempos, content, contentpos, size)
+                VALUES (?1, 0, ?2, 0, ?3)",
+            (&item_id, crate::NO_CONTENT, &size),
+        )?;
+        Ok(())
+    }
+
+    ///
+    /// Adds a symbolic link to the archive, returning the item identifier.
+    ///
+    /// **Note:** Remember to call `finish()` when done adding content.
+    ///
+    pub fn add_symlink<P: AsRef<Path>>(&mut self, path: P, parent: i64) -> Result<i64, Error> {
+        let name = self.entry_name(path.as_ref());
+        let started = Instant::now();
+        self.conn.execute(
+            "INSERT INTO item (parent, kind, name) VALUES (?1, ?2, ?3)",
+            (&parent, KIND_SYMLINK, &name),
+        )?;
+        let item_id = self.conn.last_insert_rowid();
+        self.add_timing(started, |t| &mut t.inserting);
+        self.add_symlink_content(path, item_id)?;
+        Ok(item_id)
+    }
+
+    ///
+    /// Records the value of the symbolic link in the existing item, replacing
+    /// any value that was recorded previously.
+    ///
+    fn add_symlink_content<P: AsRef<Path>>(&mut self, path: P, item_id: i64) -> Result<(), Error> {
+        match fs::symlink_metadata(path.as_ref()) {
+            Ok(attr) => self.set_attrs(item_id, &attr)?,
+            Err(_) => self.warn(Warning::MetadataUnavailable(path.as_ref().to_path_buf())),
+        }
+        // following the link reveals whether its target exists
+        if fs::metadata(path.as_ref()).is_err() {
+            self.warn(Warning::BrokenSymlink(path.as_ref().to_path_buf()));
+        }
+        let value = read_link(path.as_ref())?;
+        self.conn.execute(
+            "UPDATE item SET target = ?2 WHERE id = ?1",
+            (&item_id, &value),
+        )?;
+        Ok(())
+    }
+
+    ///
+    /// Record the modification time and permissions of the item in the `attrs`
+    /// table.
+    ///
+    fn set_attrs(&self, item_id: i64, metadata: &fs::Metadata) -> Result<(), Error> {
+        self.record_attrs(item_id, get_mtime(metadata), get_mode(metadata))?;
+        // owners differ from one system to another
+        if self.mtime_clamp.is_none() {
+            let owner = self.names.borrow_mut().owner_of(metadata);
+            self.record_owner(item_id, &owner)?;
+        }
+        Ok(())
+    }
+
+    ///
+    /// Record the owner and group of the item in the `attrs` table, both by
+    /// numeric identifier and by name.
+    ///
+    pub(crate) fn record_owner(&self, item_id: i64, owner: &Owner) -> Result<(), Error> {
+        let started = Instant::now();
+        self.conn.execute(
+            "UPDATE attrs SET uid = ?2, gid = ?3, uname = ?4, gname = ?5 WHERE item = ?1",
+            (&item_id, &owner.uid, &owner.gid, &owner.uname, &owner.gname),
+        )?;
+        self.add_timing(started, |t| &mut t.inserting);
+        Ok(())
+    }
+
+    ///
+    /// Record the given modification time and permissions of the item in the
+    /// `attrs` table.
+    ///
+    pub(crate) fn record_attrs(
+        &self,
+        item_id: i64,
+        mtime: Option<i64>,
+        mode: Option<u32>,
+    ) -> Result<(), Error> {
+        let mtime = match (mtime, self.mtime_clamp) {
+            (Some(mtime), Some(clamp)) => Some(mtime.min(clamp)),
+            (mtime, _) => mtime,
+        };
+        let started = Instant::now();
+        self.conn.execute(
+            "INSERT INTO attrs (item, mtime, mode) VALUES (?1, ?2, ?3)
+                ON CONFLICT(item) DO UPDATE SET mtime = ?2, mode = ?3",
+            (&item_id, &mtime, &mode),
+        )?;
+        self.add_timing(started, |t| &mut t.inserting);
+        Ok(())
+    }
+
+    // Record the resource fork and Finder metadata of the entry, if enabled,
+    // replacing whatever was recorded before.
+    fn record_mac_metadata(&self, item_id: i64, path: &Path) -> Result<(), Error> {
+        if !self.mac_metadata {
+            return Ok(());
+        }
+        self.conn
+            .execute("DELETE FROM xattr WHERE item = ?1", [&item_id])?;
+        for (name, value) in crate::macos::read_mac_metadata(path)? {
+            self.conn.execute(
+                "INSERT INTO xattr (item, name, value) VALUES (?1, ?2, ?3)",
+                (&item_id, &name, &value),
+            )?;
+        }
+        Ok(())
+    }
+
+    ///
+    /// Remove the content of the item without removing the item itself.
+    ///
+    /// The content bundles are left alone as they may contain data for other
+    /// items; those that are no longer referenced will be removed by
+    /// `commit()`.
+    ///
+    fn remove_content(&self, item_id: i64) -> Result<(), Error> {
+        self.conn
+            .execute("DELETE FROM itemcontent WHERE item = ?1", [&item_id])?;
+        self.conn.execute(
+            "UPDATE attrs SET checksum = NULL, mime = NULL WHERE item = ?1",
+            [&item_id],
+        )?;
+        Ok(())
+    }
+
+    ///
+    /// Remove the item from the archive, along with all of its descendants.
+    ///
+    pub(crate) fn remove_item(&self, item_id: i64) -> Result<(), Error> {
+        let descendants = "WITH RECURSIVE DIT AS (
+            SELECT id FROM item WHERE id = ?1
+            UNION ALL
+            SELECT item.id FROM item INNER JOIN DIT ON item.parent = DIT.id
+        )
+        SELECT id FROM DIT";
+        self.conn.execute(
+            &format!("DELETE FROM itemcontent WHERE item IN ({})", descendants),
+            [&item_id],
+        )?;
+        self.conn.execute(
+            &format!("DELETE FROM attrs WHERE item IN ({})", descendants),
+            [&item_id],
+        )?;
+        self.conn.execute(
+            &format!("DELETE FROM xattr WHERE item IN ({})", descendants),
+            [&item_id],
+        )?;
+        self.conn.execute(
+            &format!("DELETE FROM item WHERE id IN ({})", descendants),
+            [&item_id],
+        )?;
+        Ok(())
+    }
+
+    //
+    // Creates a content bundle based on the data collected so far, then
+    // compresses it, writing the blob to a new row in the `content` table. Then
+    // creates the necessary rows in the `itemcontent` table to map the file
+    // data to the content bundle.
+    //
+    fn insert_content(&mut self) -> Result<(), Error> {
+        let started = Instant::now();
+        let rawsize: u64 = self.contents.iter().map(|c| c.size).sum();
+        let entries = self.contents.len() as u64;
+        // only the usual codec has its level adjusted
+        let adapting = !(self.storing || self.isolating && self.large_file_codec.is_some());
+        if let Some(adaptive) = self.adaptive.as_ref().filter(|_| adapting) {
+            self.codec.set_level(adaptive.level);
+        }
+        let codec: &dyn Codec = if self.storing {
+            &StoredCodec
+        } else if self.isolating {
+            self.large_file_codec
+                .as_deref()
+                .unwrap_or(self.codec.as_ref())
+        } else {
+            self.codec.as_ref()
+        };
+        // the rowid is chosen ahead of time, as encrypted bundles are bound
+        // to the row in which they are stored
+        let content_id = next_content_id(&self.conn)?;
+        let sealed;
+        let codec: &dyn Codec = if self.encrypted {
+            let key = self.content_key.as_ref().ok_or(Error::PasswordRequired)?;
+            sealed = SealedCodec::new(codec, key, content_id);
+            &sealed
+        } else {
+            codec
+        };
+
+        // iterate through the file contents, compressing the bundle as it is
+        // read, such that the uncompressed bundle is never held in memory
+        let mut mimes: Vec<(i64, &str)> = vec![];
+        let mut fill = |output: &mut dyn Write| -> Result<Duration, Error> {
+            let reading = Instant::now();
+            let mut output = TimedWriter::new(output);
+            for item in self.contents.iter() {
+                let sniff = item.itempos == 0 && !self.encrypted;
+                let mime = if let Some(data) = item.data.as_ref() {
+                    copy_sniffing(&mut data.as_slice(), &mut output, sniff)?
+                } else {
+                    let mut input = fs::File::open(&item.path)?;
+                    input.seek(SeekFrom::Start(item.itempos))?;
+                    let mut chunk =
+                        ThrottledReader::new(input.take(item.size), self.throttle.as_mut());
+                    if let Some((hasher, file_len)) = self.hashers.get_mut(&item.item) {
+                        let mut tee = HashingWriter::new(&mut output, hasher);
+                        let mime = copy_sniffing(&mut chunk, &mut tee, sniff)?;
+                        if item.itempos + item.size >= *file_len {
+                            // this is the final part of the file, save the checksum
+                            let checksum = hasher.finalize();
+                            self.conn.execute(
+                                "UPDATE attrs SET checksum = ?2 WHERE item = ?1",
+                                (&item.item, checksum),
+                            )?;
+                            self.hashers.remove(&item.item);
+                        }
+                        mime
+                    } else {
+                        copy_sniffing(&mut chunk, &mut output, sniff)?
+                    }
+                };
+                if let Some(mime) = mime {
+                    mimes.push((item.item, mime));
+                }
+            }
+            // the time spent in the compressor counts as compression
+            let reading = reading.elapsed().saturating_sub(output.elapsed);
+            add_duration(&self.timings, reading, |t| &mut t.reading);
+            Ok(reading)
+        };
+        let bound = if self.streaming_writes {
+            codec.max_compressed_len(rawsize)
+        } else {
+            None
+        };
+        match bound {
+            Some(bound) => write_content_streaming(
+                &self.conn,
+                content_id,
+                codec,
+                &mut fill,
+                bound,
+                self.digest,
+                &self.timings,
+            )?,
+            None => {
+                // Allocate a buffer for the compressed data, reusing it each
+                // time. For small data sets this makes no observable
+                // difference, but for any large data set (e.g. Linux kernel),
+                // it makes a huge difference.
+                let mut content: Vec<u8> = if let Some(mut buf) = self.buffer.take() {
+                    buf.clear();
+                    buf
+                } else {
+                    Vec::with_capacity(self.bundle_size as usize)
+                };
+                let result = write_content_buffered(
+                    &self.conn,
+                    content_id,
+                    codec,
+                    &mut fill,
+                    &mut content,
+                    self.digest,
+                    &self.timings,
+                );
+                self.buffer = Some(content);
+                result?
+            }
+        };
+        for (item_id, mime) in mimes {
+            self.conn.execute(
+                "INSERT INTO attrs (item, mime) VALUES (?1, ?2)
+                    ON CONFLICT(item) DO UPDATE SET mime = ?2",
+                (&item_id, mime),
+            )?;
+        }
+        if let Some(adaptive) = self.adaptive.as_mut() {
+            if adapting {
+                adaptive.update(self.timings.get());
+            } else {
+                adaptive.last = self.timings.get();
+            }
+        }
+        let inserting = Instant::now();
+        self.conn.execute(
+            "UPDATE content SET rawsize = ?2, codec = ?3, entries = ?4 WHERE id = ?1",
+            (&content_id, &rawsize, codec.identifier(), &entries),
+        )?;
+        tracing::debug!(
+            content_id,
+            rawsize,
+            entries,
+            codec = codec.identifier(),
+            elapsed_ms = started.elapsed().as_millis() as u64,
+            "wrote content bundle"
+        );
+
+        // iterate through the item contents and insert new itemcontent rows
+        for item in self.contents.iter() {
+            // create the mapping for this bit of content
+            self.conn.execute(
+                "INSERT INTO itemcontent (
+                    item, itempos, content, contentpos, size
+                ) VALUES (?1, ?2, ?3, ?4, ?5)",
+                (
+                    &item.item,
+                    &item.itempos,
+                    &content_id,
+                    &item.contentpos,
+                    &item.size,
+                ),
+            )?;
+        }
+        self.add_timing(inserting, |t| &mut t.inserting);
+
+        self.check_memory_limit()
+    }
+}
+
+//
+// State of the adaptive compression mode, in which the level of each bundle
+// depends on the time spent compressing the previous bundle compared to the
+// time spent reading its content and writing it to the database.
+//
+struct AdaptiveLevel {
+    // level with which the next bundle will be compressed
+    level: i32,
+    // timings as of the end of the previous bundle
+    last: Timings,
+}
+
+impl AdaptiveLevel {
+    fn new(timings: Timings) -> Self {
+        Self {
+            level: ADAPTIVE_START_LEVEL,
+            last: timings,
+        }
+    }
+
+    // Choose the level of the next bundle from the time spent on the bundle
+    // that was just written, given the current timings.
+    fn update(&mut self, timings: Timings) {
+        let compressing = timings.compressing.saturating_sub(self.last.compressing);
+        let io = (timings.reading + timings.writing)
+            .saturating_sub(self.last.reading + self.last.writing);
+        self.last = timings;
+        let level = next_adaptive_level(self.level, compressing, io);
+        if level != self.level {
+            tracing::debug!(level, ?compressing, ?io, "adjusting compression level");
+            self.level = level;
+        }
+    }
+}
+
+// Return the compression level that brings the time spent compressing
+// closer to the time spent reading and writing, leaving some slack to avoid
+// changing the level with every bundle.
+fn next_adaptive_level(level: i32, compressing: Duration, io: Duration) -> i32 {
+    if compressing > io.mul_f64(ADAPTIVE_SLACK) {
+        (level - 1).max(ADAPTIVE_MIN_LEVEL)
+    } else if compressing.mul_f64(ADAPTIVE_SLACK) < io {
+        (level + 1).min(ADAPTIVE_MAX_LEVEL)
+    } else {
+        level
+    }
+}
+
+//
+// Writer that passes the data through to another writer, keeping track of the
+// time spent writing.
+//
+struct TimedWriter<W: Write> {
+    inner: W,
+    elapsed: Duration,
+}
+
+impl<W: Write> TimedWriter<W> {
+    fn new(inner: W) -> Self {
+        Self {
+            inner,
+            elapsed: Duration::ZERO,
+        }
+    }
+}
+
+impl<W: Write> Write for TimedWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let started = Instant::now();
+        let result = self.inner.write(buf);
+        self.elapsed += started.elapsed();
+        result
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+//
+// Writer that passes the data through to another writer, updating the hasher
+// with everything that was written.
+//
+struct HashingWriter<'a, W: Write> {
+    inner: &'a mut W,
+    hasher: &'a mut Hasher,
+}
+
+impl<'a, W: Write> HashingWriter<'a, W> {
+    fn new(inner: &'a mut W, hasher: &'a mut Hasher) -> Self {
+        Self { inner, hasher }
+    }
+}
+
+impl<W: Write> Write for HashingWriter<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.hasher.update(&buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+//
+// Tracks the amount of data read so far in order to limit the average rate.
+//
+struct Throttle {
+    // maximum number of bytes to read per second
+    rate: u64,
+    // time at which reading began
+    start: Instant,
+    // number of bytes read since the start
+    total: u64,
+}
+
+impl Throttle {
+    fn new(rate: u64) -> Self {
+        Self {
+            rate: rate.max(1),
+            start: Instant::now(),
+            total: 0,
+        }
+    }
+
+    // Record that more data was read, sleeping as long as necessary to stay
+    // within the rate limit.
+    fn consume(&mut self, count: u64) {
+        self.total += count;
+        let delay = self.delay(self.start.elapsed());
+        if !delay.is_zero() {
+            std::thread::sleep(delay);
+        }
+    }
+
+    // Return the time remaining until the data read so far would be within
+    // the rate limit, given the time elapsed since the start.
+    fn delay(&self, elapsed: Duration) -> Duration {
+        let expected = Duration::from_secs_f64(self.total as f64 / self.rate as f64);
+        expected.saturating_sub(elapsed)
+    }
+}
+
+//
+// Reader that limits the rate at which data is read from another reader, if
+// a throttle is given.
+//
+struct ThrottledReader<'a, R: Read> {
+    inner: R,
+    throttle: Option<&'a mut Throttle>,
+}
+
+impl<'a, R: Read> ThrottledReader<'a, R> {
+    fn new(inner: R, throttle: Option<&'a mut Throttle>) -> Self {
+        Self { inner, throttle }
+    }
+}
+
+impl<R: Read> Read for ThrottledReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let count = self.inner.read(buf)?;
+        if let Some(throttle) = self.throttle.as_mut() {
+            throttle.consume(count as u64);
+        }
+        Ok(count)
+    }
+}
+
+impl Drop for PackBuilder {
+    fn drop(&mut self) {
+        // discard any uncommitted changes to an archive opened for update
+        if !self.conn.is_autocommit() {
+            let _ = self.conn.execute_batch("ROLLBACK");
+        }
+    }
+}
+
+///
+/// Summary of the archive written by `PackBuilder::finish()`.
+///
+#[derive(Clone, Debug, Default)]
+pub struct CreateReport {
+    /// Number of regular files in the archive.
+    pub files: u64,
+    /// Number of directories in the archive.
+    pub directories: u64,
+    /// Number of symbolic links in the archive.
+    pub symlinks: u64,
+    /// Number of named pipes and device nodes in the archive.
+    pub special_files: u64,
+    /// Total size of the file content and symbolic link values.
+    pub input_bytes: u64,
+    /// Size of the archive file.
+    pub archive_bytes: u64,
+    /// Time from the construction of the builder until the archive was written.
+    pub elapsed: Duration,
+    /// Time spent in each phase of building the archive.
+    pub timings: Timings,
+}
+
+///
+/// Time spent in each phase of building an archive, as reported by
+/// `PackBuilder::finish()`. The phases do not add up to the elapsed time, as
+/// the remaining work, such as examining each file, is not attributed to any
+/// particular phase.
+///
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Timings {
+    /// Reading directories and the metadata of their entries.
+    pub walking: Duration,
+    /// Reading the content of files, including computing their checksums.
+    pub reading: Duration,
+    /// Compressing the content bundles. With streaming writes this includes
+    /// writing the compressed data into the database.
+    pub compressing: Duration,
+    /// Inserting the compressed content bundles into the database.
+    pub writing: Duration,
+    /// Inserting the rows that describe the entries and their content.
+    pub inserting: Duration,
+    /// Copying the database to the archive file and verifying it.
+    pub finishing: Duration,
+}
+
+impl CreateReport {
+    ///
+    /// Return the size of the archive relative to the size of the input, as a
+    /// percentage, or zero if there was no input.
+    ///
+    pub fn ratio(&self) -> f64 {
+        if self.input_bytes == 0 {
+            0.0
+        } else {
+            self.archive_bytes as f64 * 100.0 / self.input_bytes as f64
+        }
+    }
+
+    ///
+    /// Return the average number of input bytes processed per second.
+    ///
+    pub fn throughput(&self) -> f64 {
+        let seconds = self.elapsed.as_secs_f64();
+        if seconds > 0.0 {
+            self.input_bytes as f64 / seconds
+        } else {
+            0.0
+        }
+    }
+}
+
+///
+/// Counts of the changes made to an archive by `PackBuilder::update_all()`.
+///
+#[derive(Debug, Default)]
+pub struct UpdateSummary {
+    /// Number of files and symlinks added to the archive.
+    pub added: u64,
+    /// Number of files and symlinks whose content was replaced.
+    pub changed: u64,
+    /// Number of entries (and their descendants) removed from the archive.
+    pub removed: u64,
+}
+
+///
+/// Changes to the recorded metadata of an entry, for use with
+/// `PackBuilder::set_metadata()`. Fields that are `None` are left unchanged.
+///
+/// Setting only the number of the owner or group clears its recorded name,
+/// and setting only the name clears the number, as the two would otherwise
+/// disagree about who owns the entry.
+///
+#[derive(Clone, Debug, Default)]
+pub struct EntryMetadataPatch {
+    /// Unix permission bits, of which only the lower 12 bits are kept.
+    pub mode: Option<u32>,
+    /// Modification time in seconds since the Unix epoch.
+    pub mtime: Option<i64>,
+    /// Numeric identifier of the owning user.
+    pub uid: Option<u32>,
+    /// Numeric identifier of the owning group.
+    pub gid: Option<u32>,
+    /// Name of the owning user.
+    pub uname: Option<String>,
+    /// Name of the owning group.
+    pub gname: Option<String>,
+}
+
+// Attributes of an item as recorded in the archive.
+struct StoredAttrs {
+    // total size of the item content
+    size: u64,
+    // modification time, if known
+    mtime: Option<i64>,
+    // checksum of the file content, if known
+    checksum: Option<Vec<u8>>,
+}
+
+impl PackBuilder {
+    ///
+    /// Compare the named inputs against the archive, adding new entries,
+    /// replacing the content of changed files, and removing the entries that
+    /// no longer exist on disk.
+    ///
+    /// **Note:** Remember to call `commit()` when done making changes.
+    ///
+    pub fn update_all(&mut self, inputs: Vec<&PathBuf>) -> Result<UpdateSummary, Error> {
+        let _span = tracing::info_span!("update_all").entered();
+        let mut summary = UpdateSummary::default();
+        if self.reuse_prior && !self.content_addressed && !self.metadata_only {
+            // before any files are removed or their content replaced
+            self.load_prior_content()?;
+        }
+        let paths: Vec<PathBuf> = inputs.into_iter().cloned().collect();
+        // entries at the top level of the archive that were not named in the
+        // inputs are left alone
+        let mut subdirs = self.update_children(0, paths, false, &mut summary)?;
+        while let Some((parent_id, currdir)) = subdirs.pop() {
+            let started = Instant::now();
+            let listing = read_dir_entries(&currdir);
+            self.add_timing(started, |t| &mut t.walking);
+            let paths: Vec<PathBuf> = match listing {
+                Ok(entries) => entries.iter().map(|e| e.path()).collect(),
+                Err(err) => {
+                    // leave the entries of the directory as they are
+                    self.handle_unreadable(&currdir, err.into())?;
+                    continue;
+                }
+            };
+            let more = self.update_children(parent_id, paths, true, &mut summary)?;
+            subdirs.extend(more);
+        }
+        tracing::info!(
+            added = summary.added,
+            changed = summary.changed,
+            removed = summary.removed,
+            "updated entries"
+        );
+        Ok(summary)
+    }
+
+    ///
+    /// Change the recorded permissions, modification time, and/or owner of the
+    /// entry at the given path within the archive, without touching its
+    /// content.
+    ///
+    /// Returns `Error::EntryNotFound` if there is no such entry.
+    ///
+    /// **Note:** Remember to call `commit()` when done making changes.
+    ///
+    pub fn set_metadata(&self, path: &str, patch: &EntryMetadataPatch) -> Result<(), Error> {
+        let mut found: Option<(i64, i8)> = None;
+        for name in path.split('/').filter(|n| !n.is_empty()) {
+            let parent = match found {
+                None => 0,
+                Some((item_id, KIND_DIRECTORY)) => item_id,
+                Some(_) => return Err(Error::EntryNotFound(path.to_owned())),
+            };
+            found = self.find_child(name, parent)?;
+            if found.is_none() {
+                return Err(Error::EntryNotFound(path.to_owned()));
+            }
+        }
+        let Some((item_id, _)) = found else {
+            return Err(Error::EntryNotFound(path.to_owned()));
+        };
+        let mode = patch.mode.map(|mode| mode & 0o7777);
+        self.conn.execute(
+            "INSERT INTO attrs (item, mtime, mode, uid, gid, uname, gname)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
+                ON CONFLICT(item) DO UPDATE SET
+                    mtime = IFNULL(?2, mtime), mode = IFNULL(?3, mode),
+                    uid = IIF(?4 IS NULL AND ?6 IS NULL, uid, ?4),
+                    gid = IIF(?5 IS NULL AND ?7 IS NULL, gid, ?5),
+                    uname = IIF(?4 IS NULL AND ?6 IS NULL, uname, ?6),
+                    gname = IIF(?5 IS NULL AND ?7 IS NULL, gname, ?7)",
+            (
+                &item_id,
+                &patch.mtime,
+                &mode,
+                &patch.uid,
+                &patch.gid,
+                &patch.uname,
+                &patch.gname,
+            ),
+        )?;
+        self.note_change("set-metadata", path.to_owned());
+        Ok(())
+    }
+
+    //
+    // Update the children of the given parent according to the paths found on
+    // disk, returning the directories that need to be visited next. If `prune`
+    // is true, any children that are not in `paths` will be removed.
+    //
+    fn update_children(
+        &mut self,
+        parent: i64,
+        paths: Vec<PathBuf>,
+        prune: bool,
+        summary: &mut UpdateSummary,
+    ) -> Result<Vec<(i64, PathBuf)>, Error> {
+        let mut existing = self.get_children(parent)?;
+        let mut subdirs: Vec<(i64, PathBuf)> = Vec::new();
+        for path in paths {
+            self.check_cancelled()?;
+            let name = get_file_name(&path);
+            // the inputs given by the user are followed, just as with create,
+            // while the entries within directories are not
+            let metadata = if prune {
+                match fs::symlink_metadata(&path) {
+                    Ok(metadata) => metadata,
+                    Err(err) => {
+                        existing.remove(&name);
+                        self.handle_unreadable(&path, err.into())?;
+                        continue;
+                    }
+                }
+            } else {
+                fs::metadata(&path)?
+            };
+            let kind = if metadata.is_dir() {
+                KIND_DIRECTORY
+            } else if metadata.is_file() {
+                KIND_FILE
+            } else if metadata.is_symlink() {
+                KIND_SYMLINK
+            } else if let Some(kind) = get_special_kind(&metadata) {
+                if !self.special_files {
+                    // leave any such entry in the archive alone
+                    if matches!(existing.get(&name), Some((_, k)) if *k == kind) {
+                        existing.remove(&name);
+                    }
+                    self.skip_special(path);
+                    continue;
+                }
+                kind
+            } else {
+                self.skip_special(path);
+                continue;
+            };
+            // an entry that changed its kind is replaced entirely
+            let found = match existing.remove(&name) {
+                Some((item_id, item_kind)) if item_kind != kind => {
+                    self.remove_item(item_id)?;
+                    self.note_change("update", self.item_path(parent, &name)?);
+                    summary.removed += 1;
+                    None
+                }
+                other => other.map(|(item_id, _)| item_id),
+            };
+            match (kind, found) {
+                (KIND_DIRECTORY, Some(item_id)) => {
+                    self.set_attrs(item_id, &metadata)?;
+                    subdirs.push((item_id, path));
+                }
+                (KIND_DIRECTORY, None) => {
+                    let item_id = self.add_directory(&path, parent)?;
+                    self.note_change("update", self.item_path(parent, &name)?);
+                    subdirs.push((item_id, path));
+                }
+                (KIND_FILE, Some(item_id)) => {
+                    // an unreadable file keeps whatever content it had before
+                    if self.file_changed(item_id, &path, &metadata)?
+                        && self.check_readable(&path)?
+                    {
+                        self.remove_content(item_id)?;
+                        self.add_file_content(&path, item_id)?;
+                        self.note_change("update", self.item_path(parent, &name)?);
+                        summary.changed += 1;
+                    }
+                }
+                (KIND_FILE, None) => {
+                    if self.check_readable(&path)? {
+                        self.add_file(&path, parent)?;
+                        self.note_change("update", self.item_path(parent, &name)?);
+                        summary.added += 1;
+                    }
+                }
+                (KIND_SYMLINK, Some(item_id)) => {
+                    if self.symlink_changed(item_id, &metadata)? {
+                        self.remove_content(item_id)?;
+                        self.add_symlink_content(&path, item_id)?;
+                        self.note_change("update", self.item_path(parent, &name)?);
+                        summary.changed += 1;
+                    }
+                }
+                (KIND_SYMLINK, None) => {
+                    self.add_symlink(&path, parent)?;
+                    self.note_change("update", self.item_path(parent, &name)?);
+                    summary.added += 1;
+                }
+                (_, Some(item_id)) => {
+                    self.set_special_attrs(item_id, &metadata)?;
+                }
+                (_, None) => {
+                    self.add_special(&path, parent)?;
+                    self.note_change("update", self.item_path(parent, &name)?);
+                    summary.added += 1;
+                }
+            }
+        }
+        if prune {
+            for (name, (item_id, _)) in existing {
+                self.note_change("update", self.item_path(parent, &name)?);
+                self.remove_item(item_id)?;
+                summary.removed += 1;
+            }
+        }
+        Ok(subdirs)
+    }
+
+    //
+    // Return the identifiers and kinds of the children of the given item,
+    // keyed by their names.
+    //
+    fn get_children(&self, parent: i64) -> Result<HashMap<String, (i64, i8)>, Error> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT id, kind, name FROM item WHERE parent = ?1")?;
+        let mut rows = stmt.query([&parent])?;
+        let mut children: HashMap<String, (i64, i8)> = HashMap::new();
+        while let Some(row) = rows.next()? {
+            children.insert(row.get(2)?, (row.get(0)?, row.get(1)?));
+        }
+        Ok(children)
+    }
+
+    //
+    // Return the total size of the content and the recorded modification time
+    // and checksum of the item, if any.
+    //
+    fn get_stored_attrs(&self, item_id: i64) -> Result<StoredAttrs, Error> {
+        let size: u64 = self.conn.query_row(
+            "SELECT IFNULL((SELECT LENGTH(target) FROM item WHERE id = ?1),
+                (SELECT IFNULL(SUM(size), 0) FROM itemcontent WHERE item = ?1))",
+            [&item_id],
+            |row| row.get(0),
+        )?;
+        let mut stmt = self
+            .conn
+            .prepare("SELECT mtime, checksum FROM attrs WHERE item = ?1")?;
+        let mut rows = stmt.query([&item_id])?;
+        let mut attrs = StoredAttrs {
+            size,
+            mtime: None,
+            checksum: None,
+        };
+        if let Some(row) = rows.next()? {
+            attrs.mtime = row.get(0)?;
+            attrs.checksum = row.get(1)?;
+        }
+        Ok(attrs)
+    }
+
+    //
+    // Return true if the file differs from what is stored in the archive,
+    // comparing the size, then the modification time, and finally the checksum.
+    //
+    fn file_changed(
+        &self,
+        item_id: i64,
+        path: &Path,
+        metadata: &fs::Metadata,
+    ) -> Result<bool, Error> {
+        let stored = self.get_stored_attrs(item_id)?;
+        if stored.size != metadata.len() {
+            return Ok(true);
+        }
+        if stored.mtime.is_some() && stored.mtime == get_mtime(metadata) {
+            return Ok(false);
+        }
+        if let Some(expected) = stored.checksum {
+            if checksum_file(path, self.digest)? == expected {
+                // content is the same, only the modification time changed
+                self.set_attrs(item_id, metadata)?;
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    //
+    // Return true if the symbolic link differs from what is stored in the
+    // archive, comparing the length and modification time.
+    //
+    fn symlink_changed(&self, item_id: i64, metadata: &fs::Metadata) -> Result<bool, Error> {
+        let stored = self.get_stored_attrs(item_id)?;
+        Ok(stored.size != metadata.len()
+            || stored.mtime.is_none()
+            || stored.mtime != get_mtime(metadata))
+    }
+}
+
+// Return the rowid that the next row of the content table will be given.
+pub(crate) fn next_content_id(conn: &Connection) -> Result<i64, Error> {
+    let content_id: i64 =
+        conn.query_row("SELECT IFNULL(MAX(id), 0) + 1 FROM content", [], |row| {
+            row.get(0)
+        })?;
+    Ok(content_id)
+}
+
+// Function that writes the uncompressed content of a bundle to the given
+// writer, returning the time spent reading the content.
+type FillBundle<'a> = dyn FnMut(&mut dyn Write) -> Result<Duration, Error> + 'a;
+
+// Compress the bundle into a buffer and then write that to a new row in
+// the content table with the given rowid.
+fn write_content_buffered(
+    conn: &Connection,
+    content_id: i64,
+    codec: &dyn Codec,
+    fill: &mut FillBundle,
+    content: &mut Vec<u8>,
+    digest: DigestAlgorithm,
+    timings: &Cell<Timings>,
+) -> Result<(), Error> {
+    let started = Instant::now();
+    let mut encoder = codec.compressor(Box::new(&mut *content))?;
+    let reading = fill(&mut encoder)?;
+    encoder.finish()?;
+    let compressed_len = content.len();
+    let checksum = digest.hash(content);
+    let compressing = started.elapsed().saturating_sub(reading);
+    add_duration(timings, compressing, |t| &mut t.compressing);
+
+    // create space for the blob by inserting a zeroblob and then
+    // overwriting it with the compressed content bundle
+    //
+    // NOTE: This insert takes the majority of the overall running time when
+    // writing directly to disk.
+    //
+    let started = Instant::now();
+    conn.execute(
+        "INSERT INTO content (id, value, checksum) VALUES (?1, ZEROBLOB(?2), ?3)",
+        (&content_id, compressed_len, checksum),
+    )?;
+    let mut blob = conn.blob_open(DatabaseName::Main, "content", "value", content_id, false)?;
+    let bytes_written = blob.write(content)?;
+    if bytes_written != content.len() {
+        return Err(Error::IncompleteBlobWrite);
+    }
+    add_timing(timings, started, |t| &mut t.writing);
+    Ok(())
+}
+
+// Compress the bundle into space reserved in a temporary table, the largest
+// that could be needed, and then copy the part that was written to a new row
+// in the content table with the given rowid. SQLite cannot shorten a blob
+// without reading all of it into memory, hence the copy.
+fn write_content_streaming(
+    conn: &Connection,
+    content_id: i64,
+    codec: &dyn Codec,
+    fill: &mut FillBundle,
+    bound: u64,
+    digest: DigestAlgorithm,
+    timings: &Cell<Timings>,
+) -> Result<(), Error> {
+    let started = Instant::now();
+    conn.execute(
+        "CREATE TEMPORARY TABLE IF NOT EXISTS staging (id INTEGER PRIMARY KEY, value BLOB)",
+        (),
+    )?;
+    conn.execute(
+        "INSERT OR REPLACE INTO staging (id, value) VALUES (1, ZEROBLOB(?1))",
+        [&bound],
+    )?;
+    let mut blob = conn.blob_open(DatabaseName::Temp, "staging", "value", 1, false)?;
+    add_timing(timings, started, |t| &mut t.writing);
+    // the compressed data is written to the blob as it is produced, so the
+    // time taken to write it counts as compression
+    let started = Instant::now();
+    let mut hasher = Hasher::new(digest);
+    let mut tee = HashingWriter::new(&mut blob, &mut hasher);
+    let mut compress = || -> Result<Duration, Error> {
+        let mut encoder = codec.compressor(Box::new(&mut tee))?;
+        let reading = fill(&mut encoder)?;
+        encoder.finish()?;
+        Ok(reading)
+    };
+    // the blob refuses to be written beyond its end, which shows up as a
+    // write of zero bytes if the codec exceeded its bound
+    let reading = compress().map_err(|err| match err {
+        Error::IOError(err) if err.kind() == io::ErrorKind::WriteZero => Error::IncompleteBlobWrite,
+        err => err,
+    })?;
+    let compressed_len = blob.stream_position()?;
+    let compressing = started.elapsed().saturating_sub(reading);
+    add_duration(timings, compressing, |t| &mut t.compressing);
+    let started = Instant::now();
+    conn.execute(
+        "INSERT INTO content (id, value, checksum) VALUES (?1, ZEROBLOB(?2), ?3)",
+        (&content_id, &compressed_len, hasher.finalize()),
+    )?;
+    let mut output = conn.blob_open(DatabaseName::Main, "content", "value", content_id, false)?;
+    blob.rewind()?;
+    let copied = io::copy(&mut (&mut blob).take(compressed_len), &mut output)?;
+    if copied != compressed_len {
+        return Err(Error::IncompleteBlobWrite);
+    }
+    output.close()?;
+    blob.close()?;
+    // release the reserved space for the next bundle
+    conn.execute("DELETE FROM staging", ())?;
+    add_timing(timings, started, |t| &mut t.writing);
+    Ok(())
+}
+
+// Add the time elapsed since `started` to the selected phase.
+fn add_timing(timings: &Cell<Timings>, started: Instant, phase: fn(&mut Timings) -> &mut Duration) {
+    add_duration(timings, started.elapsed(), phase);
+}
+
+// Add the given amount of time to the selected phase.
+fn add_duration(
+    timings: &Cell<Timings>,
+    duration: Duration,
+    phase: fn(&mut Timings) -> &mut Duration,
+) {
+    let mut updated = timings.get();
+    *phase(&mut updated) += duration;
+    timings.set(updated);
+}
+
+// Copy everything from the input to the output, returning the type of the
+// content as sniffed from its first bytes if `sniff` is true.
+fn copy_sniffing<W: Write>(
+    input: &mut dyn Read,
+    output: &mut W,
+    sniff: bool,
+) -> io::Result<Option<&'static str>> {
+    let mut mime = None;
+    if sniff {
+        let mut head: Vec<u8> = Vec::with_capacity(crate::mime::SNIFF_SIZE);
+        input
+            .take(crate::mime::SNIFF_SIZE as u64)
+            .read_to_end(&mut head)?;
+        mime = crate::mime::sniff_mime(&head);
+        output.write_all(&head)?;
+    }
+    io::copy(input, output)?;
+    Ok(mime)
+}
+
+///
+/// Return the last part of the path, converting to a String.
+///
+fn get_file_name<P: AsRef<Path>>(path: P) -> String {
+    // ignore any paths that end in '..'
+    if let Some(p) = path.as_ref().file_name() {
+        // ignore any paths that failed UTF-8 translation
+        if let Some(pp) = p.to_str() {
+            return pp.to_owned();
+        }
+    }
+    // normal conversion failed, return whatever garbage is there
+    path.as_ref().to_string_lossy().into_owned()
+}
+
+///
+/// Return the modification time as seconds since the Unix epoch, if available.
+///
+pub(crate) fn get_mtime(metadata: &fs::Metadata) -> Option<i64> {
+    let modified = metadata.modified().ok()?;
+    let duration = modified.duration_since(UNIX_EPOCH).ok()?;
+    Some(duration.as_secs() as i64)
+}
+
+///
+/// Return the Unix permission bits, if available.
+///
+pub(crate) fn get_mode(metadata: &fs::Metadata) -> Option<u32> {
+    #[cfg(target_family = "unix")]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        Some(metadata.permissions().mode() & 0o7777)
+    }
+    #[cfg(not(target_family = "unix"))]
+    {
+        let _ = metadata;
+        None
+    }
+}
+
+///
+/// Return the item kind for a named pipe or device node, if the metadata
+/// describes such a special file.
+///
+fn get_special_kind(metadata: &fs::Metadata) -> Option<i8> {
+    #[cfg(target_family = "unix")]
+    {
+        use crate::{KIND_BLOCK_DEVICE, KIND_CHAR_DEVICE, KIND_FIFO};
+        use std::os::unix::fs::FileTypeExt;
+        let file_type = metadata.file_type();
+        if file_type.is_fifo() {
+            Some(KIND_FIFO)
+        } else if file_type.is_char_device() {
+            Some(KIND_CHAR_DEVICE)
+        } else if file_type.is_block_device() {
+            Some(KIND_BLOCK_DEVICE)
+        } else {
+            None
+        }
+    }
+    #[cfg(not(target_family = "unix"))]
+    {
+        let _ = metadata;
+        None
+    }
+}
+
+///
+/// Return the device and inode numbers that identify the file.
+///
+fn get_file_identity(metadata: &fs::Metadata) -> Option<(u64, u64)> {
+    #[cfg(target_family = "unix")]
+    {
+        use std::os::unix::fs::MetadataExt;
+        Some((metadata.dev(), metadata.ino()))
+    }
+    #[cfg(not(target_family = "unix"))]
+    {
+        let _ = metadata;
+        None
+    }
+}
+
+///
+/// Return the major and minor numbers of a device node.
+///
+fn get_device_numbers(metadata: &fs::Metadata) -> Option<(u32, u32)> {
+    #[cfg(target_family = "unix")]
+    {
+        use std::os::unix::fs::{FileTypeExt, MetadataExt};
+        let file_type = metadata.file_type();
+        if file_type.is_char_device() || file_type.is_block_device() {
+            let rdev = metadata.rdev() as libc::dev_t;
+            return Some((libc::major(rdev) as u32, libc::minor(rdev) as u32));
+        }
+        None
+    }
+    #[cfg(not(target_family = "unix"))]
+    {
+        let _ = metadata;
+        None
+    }
+}
+
+// range of levels used by the adaptive compression mode, and the first level
+const ADAPTIVE_MIN_LEVEL: i32 = 1;
+const ADAPTIVE_MAX_LEVEL: i32 = 19;
+const ADAPTIVE_START_LEVEL: i32 = 3;
+
+// factor by which compressing must be slower or faster than reading and
+// writing for the adaptive mode to change the level
+const ADAPTIVE_SLACK: f64 = 1.25;
+
+// Extensions of file formats that are already compressed.
+const COMPRESSED_EXTENSIONS: &[&str] = &[
+    "7z", "aac", "avi", "br", "bz2", "docx", "epub", "flac", "gif", "gz", "heic", "jar", "jpeg",
+    "jpg", "lz4", "lzma", "m4a", "m4v", "mkv", "mov", "mp3", "mp4", "odt", "ogg", "opus", "png",
+    "pptx", "rar", "tgz", "txz", "webm", "webp", "whl", "woff", "woff2", "xlsx", "xz", "zip",
+    "zst",
+];
+
+// Number of bytes sampled from the start of a file to estimate its entropy.
+const ENTROPY_SAMPLE_SIZE: u64 = 65536;
+
+// Content with more bits of entropy per byte than this is unlikely to compress.
+const ENTROPY_THRESHOLD: f64 = 7.5;
+
+// Suffixes of the files written alongside the archive: the archive itself, the
+// temporary file written by finish(), and the SQLite journal files.
+const OUTPUT_SUFFIXES: &[&str] = &["", ".tmp", "-journal", "-wal", "-shm"];
+
+// number of entries added between checks of the memory limit
+const MEMORY_CHECK_ENTRIES: u64 = 1000;
+
+// Largest size of the uncompressed content bundles.
+const MAX_BUNDLE_SIZE: u64 = 512 * 1048576;
+
+///
+/// Return true if the file is likely to be incompressible, based on the file
+/// extension or an estimate of the entropy of the first block.
+///
+fn is_incompressible(path: &Path, file_len: u64) -> bool {
+    if let Some(ext) = path.extension().and_then(|e| e.to_str()) {
+        let ext = ext.to_ascii_lowercase();
+        if COMPRESSED_EXTENSIONS.contains(&ext.as_str()) {
+            return true;
+        }
+    }
+    // small samples give a poor estimate, and small files hardly matter
+    if file_len < ENTROPY_SAMPLE_SIZE {
+        return false;
+    }
+    let mut sample: Vec<u8> = Vec::with_capacity(ENTROPY_SAMPLE_SIZE as usize);
+    match fs::File::open(path) {
+        Ok(file) => {
+            if file
+                .take(ENTROPY_SAMPLE_SIZE)
+                .read_to_end(&mut sample)
+                .is_err()
+            {
+                return false;
+            }
+        }
+        Err(_) => return false,
+    }
+    entropy(&sample) > ENTROPY_THRESHOLD
+}
+
+///
+/// Compute the Shannon entropy of the data in bits per byte.
+///
+fn entropy(data: &[u8]) -> f64 {
+    if data.is_empty() {
+        return 0.0;
+    }
+    let mut counts = [0u64; 256];
+    for byte in data {
+        counts[*byte as usize] += 1;
+    }
+    let total = data.len() as f64;
+    counts
+        .iter()
+        .filter(|c| **c > 0)
+        .map(|c| {
+            let p = *c as f64 / total;
+            -p * p.log2()
+        })
+        .sum()
+}
+
+///
+/// Read all of the entries of the named directory.
+///
+fn read_dir_entries(path: &Path) -> io::Result<Vec<fs::DirEntry>> {
+    fs::read_dir(path)?.collect()
+}
+
+///
+/// Compute the checksum of the entire contents of the named file using the
+/// given algorithm.
+///
+pub(crate) fn checksum_file(path: &Path, digest: DigestAlgorithm) -> Result<Vec<u8>, Error> {
+    let mut hasher = Hasher::new(digest);
+    let mut input = fs::File::open(path)?;
+    io::copy(&mut input, &mut hasher)?;
+    Ok(hasher.finalize())
+}
+
+///
+/// Read the symbolic link value and convert to raw bytes.
+///
+fn read_link(path: &Path) -> Result<Vec<u8>, Error> {
+    // convert whatever value returned by the OS into raw bytes without string conversion
+    use os_str_bytes::OsStringBytes;
+    let value = fs::read_link(path)?;
+    Ok(value.into_os_string().into_raw_vec())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_add_dir_all_excludes() -> Result<(), Error> {
+        let workspace = tempfile::tempdir()?;
+        let basepath = workspace.path().join("input");
+        fs::create_dir_all(basepath.join("target/debug"))?;
+        fs::create_dir_all(basepath.join("src"))?;
+        fs::write(basepath.join("src/lib.rs"), "// lib")?;
+        fs::write(basepath.join("src/lib.o"), "object")?;
+        fs::write(basepath.join("target/debug/app"), "binary")?;
+        fs::write(basepath.join("README"), "read me")?;
+        let mut builder = PackBuilder::new()?;
+        builder.add_exclude("*.o")?;
+        builder.add_exclude("input/target")?;
+        let count = builder.add_dir_all(&basepath)?;
+        assert_eq!(count, 2);
+        let mut stmt = builder
+            .conn
+            .prepare("SELECT name FROM item ORDER BY name")?;
+        let names: Vec<String> = stmt
+            .query_map([], |row| row.get(0))?
+            .collect::<Result<Vec<_>, _>>()?;
+        assert_eq!(names, vec!["README", "input", "lib.rs", "src"]);
+        drop(stmt);
+
+        let result = builder.add_exclude("[unclosed");
+        assert!(matches!(result, Err(Error::InvalidPattern(_))));
+        Ok(())
+    }
+
+    #[test]
+    fn test_reproducible() -> Result<(), Error> {
+        let workspace = tempfile::tempdir()?;
+        let basepath = workspace.path().join("input");
+        fs::create_dir_all(basepath.join("sub"))?;
+        for name in ["b.txt", "a.txt", "c.txt", "sub/d.txt"] {
+            fs::write(basepath.join(name), name)?;
+        }
+        let mut outputs: Vec<Vec<u8>> = Vec::new();
+        for idx in 0..2 {
+            let packfile = workspace.path().join(format!("pack{}.db3", idx));
+            let mut builder = PackBuilder::new()?;
+            builder.set_reproducible(0);
+            builder.add_dir_all(&basepath)?;
+            let mtime: i64 = builder
+                .conn
+                .query_row("SELECT MAX(mtime) FROM attrs", [], |row| row.get(0))?;
+            assert_eq!(mtime, 0);
+            builder.finish(&packfile)?;
+            outputs.push(fs::read(&packfile)?);
+        }
+        assert!(outputs[0] == outputs[1]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_sort_order() -> Result<(), Error> {
+        let workspace = tempfile::tempdir()?;
+        let basepath = workspace.path().join("input");
+        fs::create_dir_all(basepath.join("b"))?;
+        fs::create_dir_all(basepath.join("a"))?;
+        for (name, size) in [
+            ("b/two.rs", 3),
+            ("b/one.txt", 1),
+            ("a/three.rs", 4),
+            ("a/four.TXT", 2),
+            ("five", 5),
+        ] {
+            fs::write(basepath.join(name), "x".repeat(size))?;
+        }
+        let expected = [
+            (
+                SortOrder::Name,
+                ["four.TXT", "three.rs", "one.txt", "two.rs", "five"],
+            ),
+            (
+                SortOrder::Extension,
+                ["five", "three.rs", "two.rs", "four.TXT", "one.txt"],
+            ),
+            (
+                SortOrder::Size,
+                ["one.txt", "four.TXT", "two.rs", "three.rs", "five"],
+            ),
+        ];
+        for (order, names) in expected {
+            let mut builder = PackBuilder::new()?;
+            builder.set_sort_order(order);
+            assert_eq!(builder.add_dir_all(&basepath)?, 5);
+            builder.flush_bundles()?;
+            let mut stmt = builder.conn.prepare(
+                "SELECT name FROM item INNER JOIN itemcontent ON itemcontent.item = item.id
+                    ORDER BY content, contentpos",
+            )?;
+            let bundled = stmt
+                .query_map([], |row| row.get::<_, String>(0))?
+                .collect::<Result<Vec<_>, _>>()?;
+            assert_eq!(bundled, names, "{:?}", order);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_throttle_delay() {
+        let mut throttle = Throttle::new(1000);
+        throttle.total = 500;
+        assert_eq!(throttle.delay(Duration::ZERO), Duration::from_millis(500));
+        assert_eq!(
+            throttle.delay(Duration::from_millis(200)),
+            Duration::from_millis(300)
+        );
+        assert_eq!(throttle.delay(Duration::from_secs(1)), Duration::ZERO);
+    }
+
+    #[test]
+    fn test_cpu_limit() -> Result<(), Error> {
+        let workspace = tempfile::tempdir()?;
+        let basepath = workspace.path().join("input");
+        fs::create_dir_all(&basepath)?;
+        fs::write(basepath.join("data.txt"), "lorem ipsum ".repeat(10_000))?;
+        let packfile = workspace.path().join("pack.db3");
+        let mut builder = PackBuilder::new()?;
+        builder.set_cpu_limit(2);
+        builder.set_io_limit(1024 * 1024 * 1024);
+        builder.add_dir_all(&basepath)?;
+        builder.finish(&packfile)?;
+        let reader = crate::PackReader::new(&packfile)?;
+        let destination = workspace.path().join("output");
+        fs::create_dir(&destination)?;
+        let options = crate::ExtractOptions {
+            destination: destination.clone(),
+            ..Default::default()
+        };
+        assert_eq!(reader.extract_all_with(&options)?.files, 1);
+        let text = fs::read_to_string(destination.join("input/data.txt"))?;
+        assert_eq!(text, "lorem ipsum ".repeat(10_000));
+        Ok(())
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn test_special_files() -> Result<(), Error> {
+        use std::os::unix::fs::FileTypeExt;
+        let workspace = tempfile::tempdir()?;
+        let basepath = workspace.path().join("input");
+        fs::create_dir_all(&basepath)?;
+        fs::write(basepath.join("file.txt"), "content")?;
+        let fifo = std::ffi::CString::new(basepath.join("pipe").to_str().unwrap()).unwrap();
+        assert_eq!(unsafe { libc::mkfifo(fifo.as_ptr(), 0o640) }, 0);
+
+        // by default the named pipe is skipped
+        let mut builder = PackBuilder::new()?;
+        builder.add_dir_all(&basepath)?;
+        assert_eq!(builder.skipped(), &[basepath.join("pipe")]);
+
+        // otherwise it is recorded in the archive and can be extracted
+        let packfile = workspace.path().join("pack.db3");
+        let mut builder = PackBuilder::new()?;
+        builder.set_special_files(true);
+        builder.add_dir_all(&basepath)?;
+        assert!(builder.skipped().is_empty());
+        builder.finish(&packfile)?;
+        let reader = crate::PackReader::new(&packfile)?;
+        let manifest = reader.manifest()?;
+        let entry = manifest.iter().find(|e| e.path == "input/pipe").unwrap();
+        assert_eq!(entry.kind, "fifo");
+
+        let destination = workspace.path().join("output");
+        fs::create_dir(&destination)?;
+        let mut options = crate::ExtractOptions {
+            destination: destination.clone(),
+            ..Default::default()
+        };
+        assert_eq!(reader.extract_all_with(&options)?.files, 1);
+        assert!(!destination.join("input/pipe").exists());
+        assert_eq!(
+            reader.warnings(),
+            vec![Warning::SkippedSpecialFile(PathBuf::from("input/pipe"))]
+        );
+        fs::remove_dir_all(destination.join("input"))?;
+        options.special_files = true;
+        assert_eq!(reader.extract_all_with(&options)?.files, 1);
+        let metadata = fs::symlink_metadata(destination.join("input/pipe"))?;
+        assert!(metadata.file_type().is_fifo());
+        assert!(crate::verify_manifest(&manifest, &destination)?.is_empty());
+        Ok(())
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn test_warnings() -> Result<(), Error> {
+        use std::sync::Mutex;
+        let workspace = tempfile::tempdir()?;
+        let basepath = workspace.path().join("input");
+        fs::create_dir_all(&basepath)?;
+        fs::write(basepath.join("file.txt"), "content")?;
+        std::os::unix::fs::symlink("missing.txt", basepath.join("broken"))?;
+        let fifo = std::ffi::CString::new(basepath.join("pipe").to_str().unwrap()).unwrap();
+        assert_eq!(unsafe { libc::mkfifo(fifo.as_ptr(), 0o640) }, 0);
+        // macOS does not allow names that are not valid UTF-8
+        #[cfg(target_os = "linux")]
+        let lossy = {
+            use std::os::unix::ffi::OsStrExt;
+            let lossy = basepath.join(std::ffi::OsStr::from_bytes(b"caf\xe9.txt"));
+            fs::write(&lossy, "latin-1")?;
+            lossy
+        };
+
+        let seen: Arc<Mutex<Vec<Warning>>> = Arc::new(Mutex::new(vec![]));
+        let received = seen.clone();
+        let mut builder = PackBuilder::new()?;
+        builder.set_reproducible(0);
+        builder.set_on_warning(move |warning| received.lock().unwrap().push(warning.clone()));
+        builder.add_dir_all(&basepath)?;
+        let warnings = builder.warnings();
+        assert_eq!(*seen.lock().unwrap(), warnings);
+        assert!(warnings.contains(&Warning::BrokenSymlink(basepath.join("broken"))));
+        assert!(warnings.contains(&Warning::SkippedSpecialFile(basepath.join("pipe"))));
+        #[cfg(target_os = "linux")]
+        assert!(warnings.contains(&Warning::LossyFileName(lossy)));
+        #[cfg(target_os = "linux")]
+        assert_eq!(warnings.len(), 3);
+        #[cfg(not(target_os = "linux"))]
+        assert_eq!(warnings.len(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_walk_limits() -> Result<(), Error> {
+        let workspace = tempfile::tempdir()?;
+        let basepath = workspace.path().join("input");
+        fs::create_dir_all(basepath.join("a/b/c"))?;
+        fs::create_dir_all(basepath.join("empty/dir"))?;
+        fs::write(basepath.join("top.txt"), "top")?;
+        fs::write(basepath.join("a/one.txt"), "one")?;
+        fs::write(basepath.join("a/b/two.txt"), "two")?;
+
+        let mut builder = PackBuilder::new()?;
+        builder.set_max_depth(Some(2));
+        builder.set_one_file_system(true);
+        assert_eq!(builder.add_dir_all(&basepath)?, 2);
+        // empty directories at the limit are not worth a warning
+        assert_eq!(
+            builder.warnings(),
+            vec![Warning::DepthLimitReached(basepath.join("a/b"))]
+        );
+        let packfile = workspace.path().join("pack.db3");
+        builder.finish(&packfile)?;
+        let reader = crate::PackReader::new(&packfile)?;
+        let mut paths: Vec<String> = reader
+            .export_entries()?
+            .into_iter()
+            .map(|e| e.path)
+            .collect();
+        paths.sort();
+        assert_eq!(
+            paths,
+            vec![
+                "input/",
+                "input/a/",
+                "input/a/b/",
+                "input/a/one.txt",
+                "input/empty/",
+                "input/empty/dir/",
+                "input/top.txt"
+            ]
+        );
+
+        // a directory that was already visited is not descended again
+        let builder = PackBuilder::new()?;
+        let mut visited: HashSet<(u64, u64)> = HashSet::new();
+        assert_eq!(builder.check_descent(&basepath, None, &mut visited), None);
+        #[cfg(target_family = "unix")]
+        assert_eq!(
+            builder.check_descent(&basepath, None, &mut visited),
+            Some(Warning::DirectoryLoop(basepath.clone()))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_finish_replaces_atomically() -> Result<(), Error> {
+        let workspace = tempfile::tempdir()?;
+        let basepath = workspace.path().join("input");
+        fs::create_dir_all(&basepath)?;
+        fs::write(basepath.join("hello.txt"), "hello world")?;
+        let packfile = workspace.path().join("pack.db3");
+        fs::write(&packfile, "not an archive")?;
+        // a leftover temporary file from an earlier crash is replaced
+        fs::write(workspace.path().join("pack.db3.tmp"), "partial")?;
+        let mut builder = PackBuilder::new()?;
+        builder.set_verify_on_finish(true);
+        builder.add_dir_all(&basepath)?;
+        builder.finish(&packfile)?;
+        assert!(crate::is_pack_file(&packfile)?);
+        assert!(!workspace.path().join("pack.db3.tmp").exists());
+        let reader = crate::PackReader::new(&packfile)?;
+        assert_eq!(reader.read_range("input/hello.txt", 0, 5)?, b"hello");
+        Ok(())
+    }
+
+    #[test]
+    fn test_store_incompressible() -> Result<(), Error> {
+        assert_eq!(entropy(&[7u8; 1000]), 0.0);
+        let pattern: Vec<u8> = (0..=255).cycle().take(65536).collect();
+        assert!((entropy(&pattern) - 8.0).abs() < 0.001);
+
+        let workspace = tempfile::tempdir()?;
+        let basepath = workspace.path().join("input");
+        fs::create_dir_all(&basepath)?;
+        fs::write(basepath.join("text.txt"), "lorem ipsum ".repeat(10_000))?;
+        fs::write(basepath.join("photo.JPG"), "not really a photo")?;
+        fs::write(basepath.join("noise.bin"), &pattern)?;
+        assert!(is_incompressible(&basepath.join("photo.JPG"), 18));
+        assert!(is_incompressible(&basepath.join("noise.bin"), 65536));
+        assert!(!is_incompressible(&basepath.join("text.txt"), 120_000));
+
+        let packfile = workspace.path().join("pack.db3");
+        let mut builder = PackBuilder::new()?;
+        builder.add_dir_all(&basepath)?;
+        builder.finish(&packfile)?;
+        let conn = Connection::open(&packfile)?;
+        let mut stmt = conn.prepare("SELECT codec, rawsize FROM content ORDER BY codec")?;
+        let rows = stmt
+            .query_map([], |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, u64>(1)?))
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+        assert_eq!(
+            rows,
+            vec![
+                ("none".to_owned(), 65536 + 18),
+                ("zstd".to_owned(), 120_000)
+            ]
+        );
+
+        let reader = crate::PackReader::new(&packfile)?;
+        let entries: Vec<u64> = reader
+            .space_report()?
+            .bundles
+            .iter()
+            .map(|b| b.entries)
+            .collect();
+        assert_eq!(entries, vec![1, 2]);
+        assert_eq!(reader.read_range("input/photo.JPG", 4, 6)?, b"really");
+        assert_eq!(reader.read_range("input/noise.bin", 256, 4)?, &[0, 1, 2, 3]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_large_file_policy() -> Result<(), Error> {
+        let workspace = tempfile::tempdir()?;
+        let basepath = workspace.path().join("input");
+        fs::create_dir_all(&basepath)?;
+        fs::write(basepath.join("a.txt"), "small ".repeat(100))?;
+        fs::write(basepath.join("big.txt"), "lorem ipsum ".repeat(25_000))?;
+        fs::write(basepath.join("c.txt"), "tiny")?;
+
+        let packfile = workspace.path().join("pack.db3");
+        let mut builder = PackBuilder::new()?;
+        builder.set_bundle_size(100_000);
+        builder.set_large_file_policy(200_000, Some(Box::new(StoredCodec)));
+        builder.set_reproducible(0);
+        builder.add_dir_all(&basepath)?;
+        builder.finish(&packfile)?;
+        let conn = Connection::open(&packfile)?;
+        let mut stmt = conn.prepare("SELECT codec, rawsize, entries FROM content ORDER BY id")?;
+        let rows = stmt
+            .query_map([], |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, u64>(1)?,
+                    row.get::<_, u64>(2)?,
+                ))
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+        // the large file fills bundles of its own, leaving the small files
+        // to share a bundle that uses the usual codec
+        assert_eq!(
+            rows,
+            vec![
+                ("none".to_owned(), 100_000, 1),
+                ("none".to_owned(), 100_000, 1),
+                ("none".to_owned(), 100_000, 1),
+                ("zstd".to_owned(), 604, 2),
+            ]
+        );
+
+        let reader = crate::PackReader::new(&packfile)?;
+        assert_eq!(reader.read_range("input/big.txt", 199_996, 8)?, b"m ipsum ");
+        assert_eq!(reader.read_range("input/c.txt", 0, 4)?, b"tiny");
+        Ok(())
+    }
+
+    // Codec that records the levels it is given.
+    struct LevelCodec {
+        inner: ZstdCodec,
+        levels: Arc<std::sync::Mutex<Vec<i32>>>,
+    }
+
+    impl Codec for LevelCodec {
+        fn identifier(&self) -> &str {
+            self.inner.identifier()
+        }
+
+        fn compress_stream(&self, input: &mut dyn Read, output: &mut dyn Write) -> io::Result<()> {
+            self.inner.compress_stream(input, output)
+        }
+
+        fn compressor<'a>(
+            &'a self,
+            output: Box<dyn Write + 'a>,
+        ) -> io::Result<Box<dyn crate::WriteFinish + 'a>> {
+            self.inner.compressor(output)
+        }
+
+        fn decompress_stream<'a>(
+            &self,
+            input: Box<dyn Read + 'a>,
+        ) -> io::Result<Box<dyn Read + 'a>> {
+            self.inner.decompress_stream(input)
+        }
+
+        fn set_level(&mut self, level: i32) {
+            self.inner.set_level(level);
+            self.levels.lock().unwrap().push(level);
+        }
+    }
+
+    #[test]
+    fn test_adaptive_compression() -> Result<(), Error> {
+        let secs = Duration::from_secs;
+        assert_eq!(next_adaptive_level(3, secs(4), secs(2)), 2);
+        assert_eq!(next_adaptive_level(3, secs(2), secs(4)), 4);
+        assert_eq!(next_adaptive_level(3, secs(2), secs(2)), 3);
+        assert_eq!(next_adaptive_level(1, secs(4), secs(2)), 1);
+        assert_eq!(next_adaptive_level(19, secs(2), secs(4)), 19);
+
+        let workspace = tempfile::tempdir()?;
+        let basepath = workspace.path().join("input");
+        fs::create_dir_all(&basepath)?;
+        for index in 0..8 {
+            let content = format!("file number {} ", index).repeat(2_000);
+            fs::write(basepath.join(format!("{}.txt", index)), content)?;
+        }
+        let levels = Arc::new(std::sync::Mutex::new(vec![]));
+        let packfile = workspace.path().join("pack.db3");
+        let mut builder = PackBuilder::new()?;
+        builder.set_bundle_size(16_384);
+        builder.set_codec(Box::new(LevelCodec {
+            inner: ZstdCodec::default(),
+            levels: levels.clone(),
+        }));
+        builder.set_adaptive_compression(true);
+        builder.add_dir_all(&basepath)?;
+        builder.finish(&packfile)?;
+
+        // every bundle is given a level, starting with the default
+        let levels = levels.lock().unwrap().clone();
+        let conn = Connection::open(&packfile)?;
+        let bundles: usize =
+            conn.query_row("SELECT COUNT(*) FROM content", [], |row| row.get(0))?;
+        assert_eq!(levels.len(), bundles);
+        assert_eq!(levels[0], ADAPTIVE_START_LEVEL);
+        assert!(levels
+            .iter()
+            .all(|l| (ADAPTIVE_MIN_LEVEL..=ADAPTIVE_MAX_LEVEL).contains(l)));
+        assert!(levels.windows(2).all(|w| (w[0] - w[1]).abs() <= 1));
+
+        let reader = crate::PackReader::new(&packfile)?;
+        assert_eq!(reader.read_range("input/7.txt", 0, 13)?, b"file number 7");
+        Ok(())
+    }
+
+    #[test]
+    fn test_memory_limit() -> Result<(), Error> {
+        let workspace = tempfile::tempdir()?;
+        let basepath = workspace.path().join("input");
+        fs::create_dir_all(&basepath)?;
+        for index in 0..8 {
+            let content = format!("file number {} ", index).repeat(2_000);
+            fs::write(basepath.join(format!("{}.txt", index)), content)?;
+        }
+
+        // the database stays in memory while it is below the limit
+        let packfile = workspace.path().join("small.db3");
+        let mut builder = PackBuilder::new()?;
+        builder.set_bundle_size(16_384);
+        builder.set_memory_limit(1_073_741_824);
+        builder.add_dir_all(&basepath)?;
+        assert!(!builder.spilled);
+        builder.finish(&packfile)?;
+
+        // and is moved to disk once it exceeds the limit, without losing any
+        // of the entries already added
+        let packfile = workspace.path().join("spilled.db3");
+        let mut builder = PackBuilder::new()?;
+        builder.set_bundle_size(16_384);
+        builder.set_memory_limit(65_536);
+        builder.set_performance_profile(PerformanceProfile::Fast)?;
+        builder.set_pragma("cache_size", "-1234")?;
+        builder.set_pragma("temp_store", "MEMORY")?;
+        builder.add_dir_all(&basepath)?;
+        assert!(builder.spilled);
+        // along with the settings made by the caller
+        let pragma = |name: &str| -> Result<i64, Error> {
+            Ok(builder
+                .conn
+                .pragma_query_value(None, name, |row| row.get(0))?)
+        };
+        assert_eq!(pragma("cache_size")?, -1234);
+        assert_eq!(pragma("temp_store")?, 2);
+        let report = builder.finish(&packfile)?;
+        assert_eq!(report.files, 8);
+        let reader = crate::PackReader::new(&packfile)?;
+        reader.verify_integrity()?;
+        for index in 0..8 {
+            let mut content: Vec<u8> = vec![];
+            reader.read_path(&format!("input/{}.txt", index), &mut content)?;
+            assert_eq!(
+                content,
+                format!("file number {} ", index).repeat(2_000).into_bytes()
+            );
+        }
+
+        // metadata-only archives write no bundles, yet are moved all the same
+        let manypath = workspace.path().join("many");
+        fs::create_dir_all(&manypath)?;
+        for index in 0..2_500 {
+            fs::write(manypath.join(format!("{:04}.txt", index)), "x")?;
+        }
+        let packfile = workspace.path().join("metadata.db3");
+        let mut builder = PackBuilder::new()?;
+        builder.set_metadata_only(true);
+        builder.set_memory_limit(65_536);
+        builder.add_dir_all(&manypath)?;
+        assert!(builder.spilled);
+        assert_eq!(builder.finish(&packfile)?.files, 2_500);
+        Ok(())
+    }
+
+    #[test]
+    fn test_timings() -> Result<(), Error> {
+        let workspace = tempfile::tempdir()?;
+        let basepath = workspace.path().join("input");
+        fs::create_dir_all(basepath.join("sub"))?;
+        fs::write(basepath.join("a.txt"), "lorem ipsum ".repeat(1_000))?;
+        fs::write(basepath.join("sub/b.txt"), "dolor sit amet ".repeat(1_000))?;
+        let packfile = workspace.path().join("pack.db3");
+        let mut builder = PackBuilder::new()?;
+        builder.add_dir_all(&basepath)?;
+        let report = builder.finish(&packfile)?;
+        let timings = report.timings;
+        for phase in [
+            timings.walking,
+            timings.reading,
+            timings.compressing,
+            timings.writing,
+            timings.inserting,
+            timings.finishing,
+        ] {
+            assert!(phase > Duration::ZERO);
+            assert!(phase <= report.elapsed);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_add_path() -> Result<(), Error> {
+        let workspace = tempfile::tempdir()?;
+        let basepath = workspace.path().join("input");
+        fs::create_dir_all(basepath.join("sub/deeper"))?;
+        fs::write(basepath.join("sub/deeper/one.txt"), "one")?;
+        fs::write(basepath.join("sub/two.txt"), "two")?;
+        fs::write(basepath.join("ignored.txt"), "ignored")?;
+        let packfile = workspace.path().join("pack.db3");
+        let mut builder = PackBuilder::new()?;
+        assert_eq!(builder.add_path(basepath.join("sub/deeper/one.txt"))?, 1);
+        assert_eq!(builder.add_path(basepath.join("sub"))?, 0);
+        assert_eq!(builder.add_path(basepath.join("sub/./two.txt"))?, 1);
+        let result = builder.add_path(basepath.join("sub/two.txt"));
+        assert!(matches!(result, Err(Error::PathCollision(_))));
+        builder.finish(&packfile)?;
+
+        let prefix = crate::sanitize_path(&basepath)?;
+        let prefix = prefix.to_string_lossy().replace('\\', "/");
+        let reader = crate::PackReader::new(&packfile)?;
+        let mut paths: Vec<String> = vec![];
+        for result in reader.entries()? {
+            let entry = result?;
+            if let Some(path) = entry.name.strip_prefix(&prefix) {
+                paths.push(path.to_owned());
+            }
+        }
+        paths.sort();
+        assert_eq!(
+            paths,
+            vec![
+                "/",
+                "/sub/",
+                "/sub/deeper/",
+                "/sub/deeper/one.txt",
+                "/sub/two.txt"
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_create_report() -> Result<(), Error> {
+        let workspace = tempfile::tempdir()?;
+        let basepath = workspace.path().join("input");
+        fs::create_dir_all(basepath.join("sub"))?;
+        fs::write(basepath.join("sub/one.txt"), "one ".repeat(1000))?;
+        fs::write(basepath.join("two.txt"), "two")?;
+        #[cfg(target_family = "unix")]
+        std::os::unix::fs::symlink("two.txt", basepath.join("link"))?;
+        let packfile = workspace.path().join("pack.db3");
+        let mut builder = PackBuilder::new()?;
+        builder.add_dir_all(&basepath)?;
+        let report = builder.finish(&packfile)?;
+        assert_eq!(report.files, 2);
+        assert_eq!(report.directories, 2);
+        #[cfg(target_family = "unix")]
+        {
+            assert_eq!(report.symlinks, 1);
+            assert_eq!(report.input_bytes, 4010);
+        }
+        assert_eq!(report.special_files, 0);
+        assert_eq!(report.archive_bytes, fs::metadata(&packfile)?.len());
+        assert!(report.ratio() > 0.0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_cancel() -> Result<(), Error> {
+        let workspace = tempfile::tempdir()?;
+        let basepath = workspace.path().join("input");
+        fs::create_dir_all(&basepath)?;
+        fs::write(basepath.join("one.txt"), "one")?;
+        let packfile = workspace.path().join("pack.db3");
+
+        // a cancelled builder refuses to do anything more
+        let mut builder = PackBuilder::new()?;
+        let token = builder.cancel_token();
+        std::thread::spawn(move || token.cancel()).join().unwrap();
+        let result = builder.add_dir_all(&basepath);
+        assert!(matches!(result, Err(Error::Cancelled)));
+        assert!(matches!(builder.finish(&packfile), Err(Error::Cancelled)));
+        assert!(!packfile.exists());
+
+        // changes to an existing archive are rolled back
+        let mut builder = PackBuilder::new()?;
+        builder.add_dir_all(&basepath)?;
+        builder.finish(&packfile)?;
+        let mut builder = PackBuilder::open(&packfile)?;
+        builder.add_stream("two.txt", 0, &mut "two".as_bytes())?;
+        builder.cancel()?;
+        let mut builder = PackBuilder::open(&packfile)?;
+        builder.add_stream("three.txt", 0, &mut "three".as_bytes())?;
+        drop(builder);
+        let reader = crate::PackReader::new(&packfile)?;
+        assert_eq!(reader.entries()?.len(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_content_addressed() -> Result<(), Error> {
+        let workspace = tempfile::tempdir()?;
+        let basepath = workspace.path().join("input");
+        fs::create_dir_all(basepath.join("sub"))?;
+        let same = "same content ".repeat(100);
+        fs::write(basepath.join("one.txt"), &same)?;
+        fs::write(basepath.join("sub/two.txt"), &same)?;
+        fs::write(basepath.join("three.txt"), "different")?;
+        let packfile = workspace.path().join("pack.db3");
+        let mut builder = PackBuilder::new()?;
+        builder.set_content_addressed(true)?;
+        builder.add_dir_all(&basepath)?;
+        builder.finish(&packfile)?;
+
+        // identical files share the same content
+        let stored_bytes = |path: &Path| -> Result<u64, Error> {
+            let conn = rusqlite::Connection::open(path)?;
+            Ok(conn.query_row("SELECT SUM(rawsize) FROM content", [], |row| row.get(0))?)
+        };
+        assert_eq!(stored_bytes(&packfile)?, same.len() as u64 + 9);
+        let reader = crate::PackReader::new(&packfile)?;
+        assert!(reader.is_content_addressed()?);
+        let mut output: Vec<u8> = vec![];
+        reader.read_path("input/sub/two.txt", &mut output)?;
+        assert_eq!(output, same.as_bytes());
+        let digest = blake3::hash(same.as_bytes());
+        let item_id = reader.find_by_digest(digest.as_bytes())?.unwrap();
+        let mut output: Vec<u8> = vec![];
+        reader.read_item(item_id, &mut output)?;
+        assert_eq!(output, same.as_bytes());
+        assert!(reader.find_by_digest(&[0; 32])?.is_none());
+
+        // the layout is retained when updating the archive
+        let mut builder = PackBuilder::open(&packfile)?;
+        builder.add_stream("four.txt", 0, &mut "four".as_bytes())?;
+        builder.add_file(basepath.join("one.txt"), 0)?;
+        builder.commit()?;
+        assert_eq!(stored_bytes(&packfile)?, same.len() as u64 + 13);
+        let reader = crate::PackReader::new(&packfile)?;
+        let mut output: Vec<u8> = vec![];
+        reader.read_path("one.txt", &mut output)?;
+        assert_eq!(output, same.as_bytes());
+
+        // content that is already present is not copied when merging
+        let otherpath = workspace.path().join("other");
+        fs::create_dir_all(&otherpath)?;
+        fs::write(otherpath.join("copy.txt"), &same)?;
+        let otherfile = workspace.path().join("other.db3");
+        let mut builder = PackBuilder::new()?;
+        builder.add_dir_all(&otherpath)?;
+        builder.finish(&otherfile)?;
+        let mergedfile = workspace.path().join("merged.db3");
+        let mut builder = PackBuilder::new()?;
+        builder.set_content_addressed(true)?;
+        builder.merge_archive(&packfile, crate::MergePolicy::Error)?;
+        builder.merge_archive(&otherfile, crate::MergePolicy::Error)?;
+        builder.finish(&mergedfile)?;
+        assert_eq!(stored_bytes(&mergedfile)?, same.len() as u64 + 13);
+        let reader = crate::PackReader::new(&mergedfile)?;
+        let mut output: Vec<u8> = vec![];
+        reader.read_path("other/copy.txt", &mut output)?;
+        assert_eq!(output, same.as_bytes());
+        Ok(())
+    }
+
+    #[test]
+    fn test_streaming_writes() -> Result<(), Error> {
+        let workspace = tempfile::tempdir()?;
+        let basepath = workspace.path().join("input");
+        fs::create_dir_all(&basepath)?;
+        fs::write(basepath.join("text.txt"), "lorem ipsum ".repeat(1000))?;
+        let noise: Vec<u8> = (0..100_000u32)
+            .map(|v| (v.wrapping_mul(2654435761) >> 13) as u8)
+            .collect();
+        fs::write(basepath.join("noise.bin"), &noise)?;
+        let packfile = workspace.path().join("pack.db3");
+        let mut builder = PackBuilder::new()?;
+        builder.set_streaming_writes(true);
+        builder.add_dir_all(&basepath)?;
+        builder.finish(&packfile)?;
+        // the compressed bundles never passed through the buffer
+        assert!(builder.buffer.is_none());
+        // nor is the reserved space left behind
+        let staged: u64 =
+            builder
+                .conn
+                .query_row("SELECT COUNT(*) FROM temp.staging", [], |row| row.get(0))?;
+        assert_eq!(staged, 0);
+        // bundles are limited to what SQLite can store
+        builder.set_bundle_size(u64::MAX);
+        assert_eq!(builder.bundle_size, MAX_BUNDLE_SIZE);
+
+        let reader = crate::PackReader::new(&packfile)?;
+        reader.verify_integrity()?;
+        let space = reader.space_report()?;
+        assert_eq!(space.bundles.len(), 2);
+        assert!(space.bundles.iter().any(|b| b.compressed < b.uncompressed));
+        let mut output: Vec<u8> = vec![];
+        reader.read_path("input/noise.bin", &mut output)?;
+        assert_eq!(output, noise);
+        let mut output: Vec<u8> = vec![];
+        reader.read_path("input/text.txt", &mut output)?;
+        assert_eq!(output, "lorem ipsum ".repeat(1000).as_bytes());
+        Ok(())
+    }
+
+    #[test]
+    fn test_add_stream_bundle_boundary() -> Result<(), Error> {
+        let workspace = tempfile::tempdir()?;
+        let packfile = workspace.path().join("pack.db3");
+        let mut builder = PackBuilder::new()?;
+        builder.set_bundle_size(16);
+        builder.add_stream("one.txt", 0, &[b'1'; 16][..])?;
+        builder.add_stream("two.txt", 0, &[b'2'; 32][..])?;
+        builder.finish(&packfile)?;
+
+        let reader = crate::PackReader::new(&packfile)?;
+        reader.verify_integrity()?;
+        let (empty, bundles): (i64, i64) = reader.conn.query_row(
+            "SELECT (SELECT COUNT(*) FROM itemcontent WHERE size = 0),
+                (SELECT COUNT(*) FROM content)",
+            [],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )?;
+        assert_eq!(empty, 0);
+        assert_eq!(bundles, 3);
+        let mut output: Vec<u8> = vec![];
+        reader.read_path("one.txt", &mut output)?;
+        assert_eq!(output, [b'1'; 16]);
+        let mut output: Vec<u8> = vec![];
+        reader.read_path("two.txt", &mut output)?;
+        assert_eq!(output, [b'2'; 32]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_add_input() -> Result<(), Error> {
+        let workspace = tempfile::tempdir()?;
+        for name in ["a", "b"] {
+            let dir = workspace.path().join(name).join("src");
+            fs::create_dir_all(&dir)?;
+            fs::write(dir.join(format!("{}.rs", name)), name)?;
+        }
+        fs::write(workspace.path().join("a/README"), "read me")?;
+
+        // inputs with the same name collide
+        let mut builder = PackBuilder::new()?;
+        assert_eq!(builder.add_input(workspace.path().join("a/src"), 0)?, 1);
+        let result = builder.add_dir_all(workspace.path().join("b/src"));
+        assert!(matches!(result, Err(Error::PathCollision(p)) if p == "src"));
+
+        // unless they are placed in different directories
+        let mut builder = PackBuilder::new()?;
+        let parent = builder.add_prefix("a")?;
+        builder.add_input(workspace.path().join("a/src"), parent)?;
+        builder.add_input(workspace.path().join("a/README"), parent)?;
+        let parent = builder.add_prefix("./b/")?;
+        builder.add_input(workspace.path().join("b/src"), parent)?;
+        let parent = builder.add_prefix("a/")?;
+        let result = builder.add_input(workspace.path().join("a/README"), parent);
+        assert!(matches!(result, Err(Error::PathCollision(p)) if p == "a/README"));
+        let packfile = workspace.path().join("pack.db3");
+        builder.finish(&packfile)?;
+        let reader = crate::PackReader::new(&packfile)?;
+        assert_eq!(reader.read_range("a/src/a.rs", 0, 1)?, b"a");
+        assert_eq!(reader.read_range("b/src/b.rs", 0, 1)?, b"b");
+        assert_eq!(reader.read_range("a/README", 0, 4)?, b"read");
+        Ok(())
+    }
+
+    #[test]
+    fn test_add_dir_as() -> Result<(), Error> {
+        let workspace = tempfile::tempdir()?;
+        let app = workspace.path().join("app");
+        fs::create_dir_all(app.join("src/gen"))?;
+        fs::create_dir_all(app.join("assets"))?;
+        fs::write(app.join("src/main.rs"), "main")?;
+        fs::write(app.join("src/gen/out.rs"), "generated")?;
+        fs::write(app.join("assets/logo.svg"), "logo")?;
+
+        let mut builder = PackBuilder::new()?;
+        builder.add_exclude("code/gen")?;
+        assert_eq!(builder.add_dir_as(app.join("src"), "code")?, 1);
+        assert_eq!(
+            builder.add_dir_as(app.join("assets"), "./static/images/")?,
+            1
+        );
+        let result = builder.add_dir_as(app.join("assets"), "static/images");
+        assert!(matches!(result, Err(Error::PathCollision(p)) if p == "static/images"));
+        let result = builder.add_dir_as(app.join("assets"), "..");
+        assert!(matches!(result, Err(Error::PathEscapesRoot(_))));
+        let result = builder.add_dir_as(app.join("src/main.rs"), "main");
+        assert!(matches!(result, Err(Error::IOError(_))));
+        let packfile = workspace.path().join("pack.db3");
+        builder.finish(&packfile)?;
+
+        let reader = crate::PackReader::new(&packfile)?;
+        let mut paths: Vec<String> = reader
+            .export_entries()?
+            .into_iter()
+            .map(|e| e.path)
+            .collect();
+        paths.sort();
+        assert_eq!(
+            paths,
+            vec![
+                "code/",
+                "code/main.rs",
+                "static/",
+                "static/images/",
+                "static/images/logo.svg"
+            ]
+        );
+        assert_eq!(reader.read_range("code/main.rs", 0, 4)?, b"main");
+        Ok(())
+    }
+
+    #[test]
+    fn test_add_file_as() -> Result<(), Error> {
+        let workspace = tempfile::tempdir()?;
+        fs::write(workspace.path().join("guide.html"), "guide")?;
+        fs::write(workspace.path().join("logo.png"), "logo")?;
+
+        let mut builder = PackBuilder::new()?;
+        let parent = builder.add_prefix("docs")?;
+        let item_id =
+            builder.add_file_as(workspace.path().join("guide.html"), "docs/en/index.html")?;
+        builder.add_file_as(
+            workspace.path().join("logo.png"),
+            "/docs/en/images/logo.png",
+        )?;
+        builder.add_file_as(workspace.path().join("logo.png"), "favicon.png")?;
+        assert_eq!(
+            builder.find_child("docs", 0)?,
+            Some((parent, KIND_DIRECTORY))
+        );
+        let result = builder.add_file_as(workspace.path().join("logo.png"), "docs/en/index.html");
+        assert!(matches!(result, Err(Error::PathCollision(p)) if p == "docs/en/index.html"));
+        // a file cannot be placed within another file
+        let result = builder.add_file_as(workspace.path().join("logo.png"), "favicon.png/logo.png");
+        assert!(matches!(result, Err(Error::PathCollision(p)) if p == "favicon.png"));
+        let result = builder.add_file_as(workspace.path(), "docs/all");
+        assert!(matches!(result, Err(Error::IOError(_))));
+        let packfile = workspace.path().join("pack.db3");
+        builder.finish(&packfile)?;
+
+        let reader = crate::PackReader::new(&packfile)?;
+        let mut paths: Vec<String> = reader
+            .export_entries()?
+            .into_iter()
+            .map(|e| e.path)
+            .collect();
+        paths.sort();
+        assert_eq!(
+            paths,
+            vec![
+                "docs/",
+                "docs/en/",
+                "docs/en/images/",
+                "docs/en/images/logo.png",
+                "docs/en/index.html",
+                "favicon.png"
+            ]
+        );
+        let mut content: Vec<u8> = vec![];
+        reader.read_content(item_id, &mut content)?;
+        assert_eq!(content, b"guide");
+        Ok(())
+    }
+
+    #[test]
+    fn test_set_metadata() -> Result<(), Error> {
+        let workspace = tempfile::tempdir()?;
+        let basepath = workspace.path().join("input");
+        fs::create_dir_all(basepath.join("sub"))?;
+        fs::write(basepath.join("sub/one.txt"), "one")?;
+        let packfile = workspace.path().join("pack.db3");
+        let mut builder = PackBuilder::new()?;
+        builder.add_dir_all(&basepath)?;
+        builder.finish(&packfile)?;
+
+        let mut builder = PackBuilder::open(&packfile)?;
+        let patch = EntryMetadataPatch {
+            mode: Some(0o100640),
+            mtime: Some(1_000_000),
+            uid: Some(1000),
+            gname: Some("staff".into()),
+            ..Default::default()
+        };
+        builder.set_metadata("input/sub/one.txt", &patch)?;
+        let patch = EntryMetadataPatch {
+            mtime: Some(2_000_000),
+            ..Default::default()
+        };
+        builder.set_metadata("input/sub/", &patch)?;
+        let result = builder.set_metadata("input/sub/one.txt/two", &patch);
+        assert!(matches!(result, Err(Error::EntryNotFound(_))));
+        let result = builder.set_metadata("input/none", &patch);
+        assert!(matches!(result, Err(Error::EntryNotFound(_))));
+        builder.commit()?;
+
+        let reader = crate::PackReader::new(&packfile)?;
+        let manifest = reader.manifest()?;
+        let file = manifest
+            .iter()
+            .find(|e| e.path == "input/sub/one.txt")
+            .unwrap();
+        assert_eq!(file.mode, Some(0o640));
+        assert_eq!(file.mtime, Some(1_000_000));
+        let dir = manifest.iter().find(|e| e.path == "input/sub").unwrap();
+        assert_eq!(dir.mtime, Some(2_000_000));
+        assert!(dir.mode.is_some());
+        assert_eq!(reader.read_range("input/sub/one.txt", 0, 3)?, b"one");
+        reader.verify_integrity()?;
+        let owner = |reader: &crate::PackReader, path: &str| -> Result<_, Error> {
+            let item_id = reader.lookup_path(path)?.unwrap().0;
+            let owner: (Option<u32>, Option<u32>, Option<String>, Option<String>) =
+                reader.conn.query_row(
+                    "SELECT uid, gid, uname, gname FROM attrs WHERE item = ?1",
+                    [item_id],
+                    |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)),
+                )?;
+            Ok(owner)
+        };
+        // the number and name of each are replaced together
+        assert_eq!(
+            owner(&reader, "input/sub/one.txt")?,
+            (Some(1000), None, None, Some("staff".into()))
+        );
+        // while those not given are left alone
+        let recorded = owner(&reader, "input/sub")?;
+        let mut builder = PackBuilder::open(&packfile)?;
+        let patch = EntryMetadataPatch {
+            uname: Some("alice".into()),
+            ..Default::default()
+        };
+        builder.set_metadata("input/sub/one.txt", &patch)?;
+        builder.commit()?;
+        let reader = crate::PackReader::new(&packfile)?;
+        assert_eq!(
+            owner(&reader, "input/sub/one.txt")?,
+            (None, None, Some("alice".into()), Some("staff".into()))
+        );
+        assert_eq!(owner(&reader, "input/sub")?, recorded);
+        Ok(())
+    }
+
+    #[test]
+    fn test_update_all() -> Result<(), Error> {
+        let workspace = tempfile::tempdir()?;
+        let basepath = workspace.path().join("input");
+        fs::create_dir_all(basepath.join("sub"))?;
+        fs::create_dir_all(basepath.join("old"))?;
+        fs::write(basepath.join("one.txt"), "one")?;
+        fs::write(basepath.join("two.txt"), "two")?;
+        fs::write(basepath.join("sub/three.txt"), "three")?;
+        fs::write(basepath.join("old/four.txt"), "four")?;
+        fs::write(basepath.join("old/five.txt"), "five")?;
+        let packfile = workspace.path().join("pack.db3");
+        let mut builder = PackBuilder::new()?;
+        builder.add_dir_all(&basepath)?;
+        builder.finish(&packfile)?;
+
+        // change one file, remove another file and a directory, and add a
+        // file at the top and one in a subdirectory
+        fs::write(basepath.join("one.txt"), "one changed")?;
+        fs::remove_file(basepath.join("two.txt"))?;
+        fs::remove_dir_all(basepath.join("old"))?;
+        fs::write(basepath.join("six.txt"), "six")?;
+        fs::write(basepath.join("sub/seven.txt"), "seven")?;
+        let mut builder = PackBuilder::open(&packfile)?;
+        let summary = builder.update_all(vec![&basepath])?;
+        builder.commit()?;
+        assert_eq!(summary.added, 2);
+        assert_eq!(summary.changed, 1);
+        assert_eq!(summary.removed, 2);
+
+        let reader = crate::PackReader::new(&packfile)?;
+        let mut paths: Vec<String> = vec![];
+        for result in reader.entries()? {
+            paths.push(result?.name);
+        }
+        paths.sort();
+        assert_eq!(
+            paths,
+            vec![
+                "input/",
+                "input/one.txt",
+                "input/six.txt",
+                "input/sub/",
+                "input/sub/seven.txt",
+                "input/sub/three.txt",
+            ]
+        );
+        let mut content: Vec<u8> = vec![];
+        reader.read_path("input/one.txt", &mut content)?;
+        assert_eq!(content, b"one changed");
+        content.clear();
+        reader.read_path("input/sub/seven.txt", &mut content)?;
+        assert_eq!(content, b"seven");
+        reader.verify_integrity()?;
+
+        // nothing more to do when the tree has not changed
+        let mut builder = PackBuilder::open(&packfile)?;
+        let summary = builder.update_all(vec![&basepath])?;
+        builder.commit()?;
+        assert_eq!(summary.added, 0);
+        assert_eq!(summary.changed, 0);
+        assert_eq!(summary.removed, 0);
+        Ok(())
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn test_symlink_targets() -> Result<(), Error> {
+        let workspace = tempfile::tempdir()?;
+        let basepath = workspace.path().join("input");
+        fs::create_dir_all(&basepath)?;
+        fs::write(basepath.join("one.txt"), "one")?;
+        std::os::unix::fs::symlink("one.txt", basepath.join("link"))?;
+        let packfile = workspace.path().join("pack.db3");
+        let mut builder = PackBuilder::new()?;
+        builder.add_dir_all(&basepath)?;
+        builder.finish(&packfile)?;
+
+        // the link value is stored with the item rather than in a bundle
+        let reader = crate::PackReader::new(&packfile)?;
+        let (item_id, target): (i64, Vec<u8>) = reader.conn.query_row(
+            "SELECT id, target FROM item WHERE name = 'link'",
+            [],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )?;
+        assert_eq!(target, b"one.txt");
+        let parts: u64 = reader.conn.query_row(
+            "SELECT COUNT(*) FROM itemcontent WHERE item = ?1",
+            [item_id],
+            |row| row.get(0),
+        )?;
+        assert_eq!(parts, 0);
+        drop(reader);
+
+        // updating the archive replaces the link value
+        fs::remove_file(basepath.join("link"))?;
+        std::os::unix::fs::symlink("../elsewhere/two.txt", basepath.join("link"))?;
+        let mut builder = PackBuilder::open(&packfile)?;
+        let summary = builder.update_all(vec![&basepath])?;
+        builder.commit()?;
+        assert_eq!(summary.changed, 1);
+        let reader = crate::PackReader::new(&packfile)?;
+        let mut target: Vec<u8> = vec![];
+        reader.read_path("input/link", &mut target)?;
+        assert_eq!(target, b"../elsewhere/two.txt");
+        let destination = workspace.path().join("output");
+        fs::create_dir(&destination)?;
+        let options = crate::ExtractOptions {
+            destination: destination.clone(),
+            ..Default::default()
+        };
+        reader.extract_all_with(&options)?;
+        let target = fs::read_link(destination.join("input/link"))?;
+        assert_eq!(target, PathBuf::from("../elsewhere/two.txt"));
+        Ok(())
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn test_error_policy() -> Result<(), Error> {
+        use std::os::unix::fs::PermissionsExt;
+        let workspace = tempfile::tempdir()?;
+        let basepath = workspace.path().join("input");
+        fs::create_dir_all(basepath.join("private"))?;
+        fs::write(basepath.join("one.txt"), "one")?;
+        fs::write(basepath.join("private/two.txt"), "two")?;
+        let locked = basepath.join("locked.txt");
+        fs::write(&locked, "locked")?;
+        fs::set_permissions(&locked, fs::Permissions::from_mode(0o000))?;
+        fs::set_permissions(basepath.join("private"), fs::Permissions::from_mode(0o000))?;
+        if fs::File::open(&locked).is_ok() {
+            // running with privileges that ignore the permissions
+            fs::set_permissions(basepath.join("private"), fs::Permissions::from_mode(0o755))?;
+            return Ok(());
+        }
+
+        // by default the first unreadable entry is an error
+        let mut builder = PackBuilder::new()?;
+        let result = builder.add_dir_all(&basepath);
+        assert!(matches!(result, Err(Error::IOError(_))));
+
+        let packfile = workspace.path().join("pack.db3");
+        let mut builder = PackBuilder::new()?;
+        builder.set_error_policy(ErrorPolicy::Skip);
+        assert_eq!(builder.add_dir_all(&basepath)?, 1);
+        let mut unreadable: Vec<PathBuf> = builder
+            .unreadable()
+            .iter()
+            .map(|(p, _)| p.clone())
+            .collect();
+        unreadable.sort();
+        assert_eq!(unreadable, vec![locked.clone(), basepath.join("private")]);
+        builder.finish(&packfile)?;
+        fs::set_permissions(basepath.join("private"), fs::Permissions::from_mode(0o755))?;
+
+        // the unreadable directory itself is kept, without its contents
+        let reader = crate::PackReader::new(&packfile)?;
+        assert!(reader.contains("input/one.txt")?);
+        assert!(reader.contains("input/private")?);
+        assert!(!reader.contains("input/private/two.txt")?);
+        assert!(!reader.contains("input/locked.txt")?);
+        drop(reader);
+
+        // a file that cannot be read during an update keeps its old content
+        let mut builder = PackBuilder::open(&packfile)?;
+        builder.set_error_policy(ErrorPolicy::Warn);
+        fs::write(basepath.join("one.txt"), "changed")?;
+        fs::set_permissions(basepath.join("one.txt"), fs::Permissions::from_mode(0o000))?;
+        let summary = builder.update_all(vec![&basepath])?;
+        builder.commit()?;
+        assert_eq!(summary.changed, 0);
+        assert_eq!(summary.added, 1);
+        assert_eq!(builder.unreadable().len(), 2);
+        let reader = crate::PackReader::new(&packfile)?;
+        assert_eq!(reader.read_range("input/one.txt", 0, 10)?, b"one");
+        assert!(reader.contains("input/private/two.txt")?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_reuse_prior_content() -> Result<(), Error> {
+        let workspace = tempfile::tempdir()?;
+        let basepath = workspace.path().join("input");
+        fs::create_dir_all(basepath.join("old"))?;
+        let report = "quarterly figures ".repeat(1_000);
+        fs::write(basepath.join("old/report.txt"), &report)?;
+        fs::write(basepath.join("notes.txt"), "remember the milk")?;
+        let packfile = workspace.path().join("pack.db3");
+        let mut builder = PackBuilder::new()?;
+        builder.add_dir_all(&basepath)?;
+        builder.finish(&packfile)?;
+        let count_bundles = || -> Result<u64, Error> {
+            let conn = Connection::open(&packfile)?;
+            Ok(conn.query_row("SELECT COUNT(*) FROM content", [], |row| row.get(0))?)
+        };
+        assert_eq!(count_bundles()?, 1);
+
+        // moving a file to another directory reuses the content of the file
+        // that was removed, as does a new copy of an existing file
+        fs::create_dir(basepath.join("archive"))?;
+        fs::rename(
+            basepath.join("old/report.txt"),
+            basepath.join("archive/report.txt"),
+        )?;
+        fs::remove_dir(basepath.join("old"))?;
+        fs::write(basepath.join("copy.txt"), "remember the milk")?;
+        let mut builder = PackBuilder::open(&packfile)?;
+        let summary = builder.update_all(vec![&basepath])?;
+        builder.commit()?;
+        assert_eq!(summary.added, 2);
+        assert_eq!(summary.removed, 1);
+        assert_eq!(count_bundles()?, 1);
+        let reader = crate::PackReader::new(&packfile)?;
+        let mut content: Vec<u8> = vec![];
+        reader.read_path("input/archive/report.txt", &mut content)?;
+        assert_eq!(content, report.as_bytes());
+        assert_eq!(
+            reader.read_range("input/copy.txt", 0, 100)?,
+            b"remember the milk"
+        );
+        assert!(!reader.contains("input/old")?);
+        drop(reader);
+
+        // otherwise the content is stored once again
+        fs::write(basepath.join("another.txt"), "remember the milk")?;
+        let mut builder = PackBuilder::open(&packfile)?;
+        builder.set_reuse_prior_content(false);
+        builder.update_all(vec![&basepath])?;
+        builder.commit()?;
+        assert_eq!(count_bundles()?, 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_digest_algorithm() -> Result<(), Error> {
+        let workspace = tempfile::tempdir()?;
+        let basepath = workspace.path().join("input");
+        fs::create_dir_all(&basepath)?;
+        fs::write(basepath.join("hello.txt"), "hello world")?;
+        let packfile = workspace.path().join("pack.db3");
+        let mut builder = PackBuilder::new()?;
+        builder.set_digest_algorithm(DigestAlgorithm::Sha256)?;
+        builder.add_dir_all(&basepath)?;
+        // too late to change once checksums have been computed
+        assert!(matches!(
+            builder.set_digest_algorithm(DigestAlgorithm::Xxh3),
+            Err(Error::DigestMismatch(_, _))
+        ));
+        builder.finish(&packfile)?;
+
+        let reader = crate::PackReader::new(&packfile)?;
+        assert_eq!(reader.digest_algorithm()?, DigestAlgorithm::Sha256);
+        assert_eq!(reader.info()?.digest, "sha256");
+        reader.verify_integrity()?;
+        let digest = DigestAlgorithm::Sha256.hash(b"hello world");
+        assert!(reader.find_by_digest(&digest)?.is_some());
+        let manifest = reader.manifest()?;
+        let hello = manifest
+            .iter()
+            .find(|e| e.path == "input/hello.txt")
+            .unwrap();
+        assert_eq!(hello.digest.as_deref(), Some("sha256"));
+        assert!(crate::verify_manifest(&manifest, workspace.path())?.is_empty());
+        drop(reader);
+
+        // updating the archive continues to use the same algorithm
+        fs::write(basepath.join("hello.txt"), "hello there, world")?;
+        let mut builder = PackBuilder::open(&packfile)?;
+        assert!(builder
+            .set_digest_algorithm(DigestAlgorithm::Blake3)
+            .is_err());
+        builder.update_all(vec![&basepath])?;
+        builder.commit()?;
+        let reader = crate::PackReader::new(&packfile)?;
+        let digest = DigestAlgorithm::Sha256.hash(b"hello there, world");
+        assert!(reader.find_by_digest(&digest)?.is_some());
+        drop(reader);
+
+        // a new archive takes the algorithm of the first archive merged into
+        // it, while archives using another algorithm cannot be merged
+        let mut builder = PackBuilder::new()?;
+        builder.merge_archive(&packfile, crate::MergePolicy::Error)?;
+        assert_eq!(builder.digest, DigestAlgorithm::Sha256);
+        let other = workspace.path().join("other.db3");
+        let mut other_builder = PackBuilder::new()?;
+        other_builder.add_stream("other.txt", 0, "other".as_bytes())?;
+        other_builder.finish(&other)?;
+        assert!(matches!(
+            builder.merge_archive(&other, crate::MergePolicy::Error),
+            Err(Error::DigestMismatch(_, _))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_metadata_only() -> Result<(), Error> {
+        let workspace = tempfile::tempdir()?;
+        let basepath = workspace.path().join("input");
+        fs::create_dir_all(basepath.join("etc"))?;
+        fs::write(basepath.join("etc/app.conf"), "setting = 1")?;
+        fs::write(basepath.join("data.bin"), "lorem ipsum ".repeat(1000))?;
+        let packfile = workspace.path().join("snapshot.db3");
+        let mut builder = PackBuilder::new()?;
+        builder.set_metadata_only(true);
+        assert_eq!(builder.add_dir_all(&basepath)?, 2);
+        builder.finish(&packfile)?;
+
+        let reader = crate::PackReader::new(&packfile)?;
+        assert!(reader.is_metadata_only()?);
+        assert_eq!(reader.counts()?, (4, 0));
+        let manifest = reader.manifest()?;
+        let data = manifest
+            .iter()
+            .find(|e| e.path == "input/data.bin")
+            .unwrap();
+        assert_eq!(data.size, 12000);
+        assert!(crate::verify_manifest(&manifest, workspace.path())?.is_empty());
+        fs::write(basepath.join("etc/app.conf"), "setting = 22")?;
+        assert_eq!(
+            crate::verify_manifest(&manifest, workspace.path())?,
+            vec![crate::Discrepancy::SizeDiffers("input/etc/app.conf".into())]
+        );
+        assert!(matches!(
+            reader.read_range("input/data.bin", 0, 5),
+            Err(Error::MetadataOnly)
+        ));
+        let destination = workspace.path().join("output");
+        let options = crate::ExtractOptions {
+            destination: destination.clone(),
+            ..Default::default()
+        };
+        assert!(matches!(
+            reader.extract_all_with(&options),
+            Err(Error::MetadataOnly)
+        ));
+        assert!(!destination.exists());
+        drop(reader);
+
+        // updating the snapshot continues to leave out the content
+        let mut builder = PackBuilder::open(&packfile)?;
+        let summary = builder.update_all(vec![&basepath])?;
+        builder.commit()?;
+        assert_eq!(summary.changed, 1);
+        let reader = crate::PackReader::new(&packfile)?;
+        assert!(reader.is_metadata_only()?);
+        assert_eq!(reader.counts()?, (4, 0));
+        assert!(crate::verify_manifest(&reader.manifest()?, workspace.path())?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_concurrent_reads_in_place() -> Result<(), Error> {
+        let workspace = tempfile::tempdir()?;
+        fs::write(workspace.path().join("one.txt"), "one")?;
+        fs::write(workspace.path().join("two.txt"), "two")?;
+        let packfile = workspace.path().join("pack.db3");
+        fs::write(&packfile, "left from an earlier run")?;
+        let mut builder = PackBuilder::new()?;
+        assert!(builder.set_concurrent_reads(true).is_err());
+        builder.set_output_path(&packfile);
+        builder.set_concurrent_reads(true)?;
+
+        // the archive can be read as soon as it is being built
+        let reader = crate::PackReader::new(&packfile)?;
+        assert_eq!(reader.entries()?.len(), 0);
+        builder.add_file(workspace.path().join("one.txt"), 0)?;
+        builder.publish()?;
+        builder.add_file(workspace.path().join("two.txt"), 0)?;
+        assert_eq!(reader.entries()?.len(), 1);
+        assert_eq!(reader.read_range("one.txt", 0, 10)?, b"one");
+
+        // finishing commits the archive where it is
+        let other = workspace.path().join("other.db3");
+        assert!(builder.finish(&other).is_err());
+        assert_eq!(builder.finish(&packfile)?.files, 2);
+        assert!(!other.exists());
+        assert_eq!(reader.entries()?.len(), 2);
+        drop(reader);
+        let reader = crate::PackReader::new(&packfile)?;
+        assert_eq!(reader.read_range("two.txt", 0, 10)?, b"two");
+        Ok(())
+    }
+
+    #[test]
+    fn test_concurrent_reads() -> Result<(), Error> {
+        let workspace = tempfile::tempdir()?;
+        fs::write(workspace.path().join("one.txt"), "one")?;
+        fs::write(workspace.path().join("two.txt"), "two")?;
+        fs::write(workspace.path().join("three.txt"), "three")?;
+        let packfile = workspace.path().join("pack.db3");
+        let mut builder = PackBuilder::new()?;
+        builder.add_file(workspace.path().join("one.txt"), 0)?;
+        builder.finish(&packfile)?;
+        let journal_mode = |reader: &crate::PackReader| -> Result<String, Error> {
+            let mode = reader
+                .conn
+                .pragma_query_value(None, "journal_mode", |row| row.get(0))?;
+            Ok(mode)
+        };
+
+        let mut builder = PackBuilder::open(&packfile)?;
+        builder.set_concurrent_reads(true)?;
+        builder.add_file(workspace.path().join("two.txt"), 0)?;
+        let reader = crate::PackReader::new(&packfile)?;
+        assert_eq!(journal_mode(&reader)?, "wal");
+        assert_eq!(reader.entries()?.len(), 1);
+
+        // readers see the published changes, but nothing more
+        builder.publish()?;
+        builder.add_file(workspace.path().join("three.txt"), 0)?;
+        assert_eq!(reader.entries()?.len(), 2);
+        assert_eq!(reader.read_range("two.txt", 0, 10)?, b"two");
+        let destination = workspace.path().join("output");
+        fs::create_dir(&destination)?;
+        let options = crate::ExtractOptions {
+            destination: destination.clone(),
+            ..Default::default()
+        };
+        assert_eq!(reader.extract_all_with(&options)?.files, 2);
+        assert!(!destination.join("three.txt").exists());
+
+        // the archive stays in WAL mode while a reader has it open
+        builder.commit()?;
+        assert_eq!(reader.entries()?.len(), 3);
+        assert_eq!(journal_mode(&reader)?, "wal");
+        drop(reader);
+        drop(builder);
+        let mut builder = PackBuilder::open(&packfile)?;
+        builder.set_concurrent_reads(true)?;
+        builder.commit()?;
+        let reader = crate::PackReader::new(&packfile)?;
+        assert_ne!(journal_mode(&reader)?, "wal");
+        assert_eq!(reader.entries()?.len(), 3);
+        Ok(())
+    }
+
+    #[test]
+    fn test_skip_output() -> Result<(), Error> {
+        let workspace = tempfile::tempdir()?;
+        let basepath = workspace.path().join("input");
+        fs::create_dir_all(basepath.join("sub/deeper"))?;
+        fs::create_dir_all(basepath.join("other"))?;
+        fs::write(basepath.join("a.txt"), "a")?;
+        // left behind by an earlier run, along with its temporary file
+        fs::write(basepath.join("sub/deeper/pack.db3"), "stale archive")?;
+        fs::write(basepath.join("sub/deeper/pack.db3.tmp"), "partial")?;
+        // a file of the same name elsewhere is not the archive
+        fs::write(basepath.join("other/pack.db3"), "unrelated")?;
+        let names = |packfile: &Path| -> Result<Vec<String>, Error> {
+            let reader = crate::PackReader::new(packfile)?;
+            let mut names: Vec<String> = vec![];
+            for result in reader.entries()? {
+                names.push(result?.name);
+            }
+            names.sort();
+            Ok(names)
+        };
+
+        // output nested within the input, named by way of a parent component
+        let packfile = basepath.join("other/../sub/deeper/pack.db3");
+        let mut builder = PackBuilder::new()?;
+        builder.set_output_path(&packfile);
+        builder.add_dir_all(&basepath)?;
+        let warnings = builder.warnings().to_vec();
+        assert_eq!(warnings.len(), 2);
+        assert!(warnings.contains(&Warning::SkippedOutput(
+            basepath.join("sub/deeper/pack.db3")
+        )));
+        assert!(warnings.contains(&Warning::SkippedOutput(
+            basepath.join("sub/deeper/pack.db3.tmp")
+        )));
+        builder.finish(&packfile)?;
+        let expected = vec![
+            "input/",
+            "input/a.txt",
+            "input/other/",
+            "input/other/pack.db3",
+            "input/sub/",
+            "input/sub/deeper/",
+        ];
+        assert_eq!(names(&packfile)?, expected);
+
+        // the archive being updated is left out as well
+        let mut builder = PackBuilder::open(&packfile)?;
+        let summary = builder.update_all(vec![&basepath])?;
+        assert_eq!(summary.added, 0);
+        assert!(builder.warnings().contains(&Warning::SkippedOutput(
+            basepath.join("sub/deeper/pack.db3")
+        )));
+        builder.commit()?;
+        assert_eq!(names(&packfile)?, expected);
+
+        // the output given as an input of its own
+        let packfile = basepath.join("a.db3");
+        let mut builder = PackBuilder::new()?;
+        builder.set_output_path(&packfile);
+        fs::write(&packfile, "stale archive")?;
+        assert_eq!(builder.add_input(&packfile, 0)?, 0);
+        Ok(())
+    }
+}
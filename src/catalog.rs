@@ -0,0 +1,238 @@
+//
+// Copyright (c) 2024 Nathan Fiedler
+//
+use crate::{Error, PackReader};
+use rusqlite::Connection;
+use std::fs;
+use std::path::Path;
+
+///
+/// A file found in one of the archives of a catalog.
+///
+#[derive(Clone, Debug, PartialEq)]
+pub struct CatalogEntry {
+    /// Absolute path of the archive that contains the file.
+    pub archive: String,
+    /// Path of the file within the archive.
+    pub path: String,
+    /// Length of the file in bytes.
+    pub size: u64,
+    /// Hex-encoded BLAKE3 digest of the file content.
+    pub checksum: Option<String>,
+}
+
+///
+/// Index of the paths and checksums of the files in any number of archives,
+/// stored in a separate database, making it possible to find which archive
+/// contains a particular file without opening every one of them.
+///
+pub struct Catalog {
+    conn: Connection,
+}
+
+impl Catalog {
+    ///
+    /// Open the catalog at the given location, creating it if necessary.
+    ///
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let conn = Connection::open(path.as_ref())?;
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS archive (
+                id INTEGER PRIMARY KEY,
+                path TEXT NOT NULL UNIQUE
+            )",
+            (),
+        )?;
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS file (
+                archive INTEGER,
+                path TEXT NOT NULL,
+                name TEXT NOT NULL,
+                size INTEGER,
+                checksum TEXT
+            )",
+            (),
+        )?;
+        conn.execute("CREATE INDEX IF NOT EXISTS file_name ON file (name)", ())?;
+        conn.execute(
+            "CREATE INDEX IF NOT EXISTS file_checksum ON file (checksum)",
+            (),
+        )?;
+        Ok(Self { conn })
+    }
+
+    ///
+    /// Add the files in the given archive to the catalog, replacing whatever
+    /// was recorded if the archive was added before. Returns the number of
+    /// files that were recorded.
+    ///
+    pub fn add_archive<P: AsRef<Path>>(&mut self, archive: P) -> Result<u64, Error> {
+        let location = fs::canonicalize(archive.as_ref())?;
+        let location = location.to_string_lossy();
+        let reader = PackReader::new(archive.as_ref())?;
+        let manifest = reader.manifest()?;
+        let tx = self.conn.transaction()?;
+        tx.execute(
+            "DELETE FROM file WHERE archive IN (SELECT id FROM archive WHERE path = ?1)",
+            [&location],
+        )?;
+        tx.execute(
+            "INSERT OR IGNORE INTO archive (path) VALUES (?1)",
+            [&location],
+        )?;
+        let archive_id: i64 = tx.query_row(
+            "SELECT id FROM archive WHERE path = ?1",
+            [&location],
+            |row| row.get(0),
+        )?;
+        let mut count: u64 = 0;
+        {
+            let mut stmt = tx.prepare(
+                "INSERT INTO file (archive, path, name, size, checksum)
+                    VALUES (?1, ?2, ?3, ?4, ?5)",
+            )?;
+            for entry in manifest.iter().filter(|e| e.kind != "dir") {
+                let name = entry.path.rsplit('/').next().unwrap_or_default();
+                stmt.execute((archive_id, &entry.path, name, entry.size, &entry.checksum))?;
+                count += 1;
+            }
+        }
+        tx.commit()?;
+        Ok(count)
+    }
+
+    ///
+    /// Remove the given archive from the catalog, returning `false` if it was
+    /// not in the catalog.
+    ///
+    pub fn remove_archive<P: AsRef<Path>>(&mut self, archive: P) -> Result<bool, Error> {
+        let location =
+            fs::canonicalize(archive.as_ref()).unwrap_or_else(|_| archive.as_ref().to_path_buf());
+        let location = location.to_string_lossy();
+        let tx = self.conn.transaction()?;
+        tx.execute(
+            "DELETE FROM file WHERE archive IN (SELECT id FROM archive WHERE path = ?1)",
+            [&location],
+        )?;
+        let count = tx.execute("DELETE FROM archive WHERE path = ?1", [&location])?;
+        tx.commit()?;
+        Ok(count > 0)
+    }
+
+    ///
+    /// Return the paths of all of the archives in the catalog.
+    ///
+    pub fn archives(&self) -> Result<Vec<String>, Error> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT path FROM archive ORDER BY path")?;
+        let paths = stmt
+            .query_map([], |row| row.get::<_, String>(0))?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(paths)
+    }
+
+    ///
+    /// Find the files that match the given pattern, which uses the usual shell
+    /// wildcards: `?`, `*`, and `[...]`. A pattern that contains a slash is
+    /// matched against the entire path of the file within the archive, while
+    /// any other pattern is matched against the file name alone.
+    ///
+    pub fn search(&self, pattern: &str) -> Result<Vec<CatalogEntry>, Error> {
+        let column = if pattern.contains('/') {
+            "file.path"
+        } else {
+            "file.name"
+        };
+        self.query(&format!("{} GLOB ?1", column), pattern)
+    }
+
+    ///
+    /// Find the files whose content has the given hex-encoded BLAKE3 digest.
+    ///
+    pub fn search_checksum(&self, checksum: &str) -> Result<Vec<CatalogEntry>, Error> {
+        self.query("file.checksum = ?1", &checksum.to_lowercase())
+    }
+
+    // Return the files that satisfy the condition, ordered by archive and path.
+    fn query(&self, condition: &str, value: &str) -> Result<Vec<CatalogEntry>, Error> {
+        let query = format!(
+            "SELECT archive.path, file.path, file.size, file.checksum
+                FROM file INNER JOIN archive ON archive.id = file.archive
+                WHERE {} ORDER BY archive.path, file.path",
+            condition
+        );
+        let mut stmt = self.conn.prepare(&query)?;
+        let entries = stmt
+            .query_map([value], |row| {
+                Ok(CatalogEntry {
+                    archive: row.get(0)?,
+                    path: row.get(1)?,
+                    size: row.get(2)?,
+                    checksum: row.get(3)?,
+                })
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(entries)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::PackBuilder;
+
+    #[test]
+    fn test_catalog() -> Result<(), Error> {
+        let workspace = tempfile::tempdir()?;
+        let first = workspace.path().join("first");
+        fs::create_dir_all(first.join("docs"))?;
+        fs::write(first.join("docs/notes.txt"), "notes")?;
+        fs::write(first.join("hello.txt"), "hello")?;
+        let second = workspace.path().join("second");
+        fs::create_dir_all(&second)?;
+        fs::write(second.join("notes.txt"), "other notes")?;
+        let first_pack = workspace.path().join("first.db3");
+        let mut builder = PackBuilder::new()?;
+        builder.add_dir_all(&first)?;
+        builder.finish(&first_pack)?;
+        let second_pack = workspace.path().join("second.db3");
+        let mut builder = PackBuilder::new()?;
+        builder.add_dir_all(&second)?;
+        builder.finish(&second_pack)?;
+
+        let mut catalog = Catalog::open(workspace.path().join("catalog.db3"))?;
+        assert_eq!(catalog.add_archive(&first_pack)?, 2);
+        assert_eq!(catalog.add_archive(&second_pack)?, 1);
+        // adding an archive again replaces the earlier entries
+        assert_eq!(catalog.add_archive(&first_pack)?, 2);
+        assert_eq!(catalog.archives()?.len(), 2);
+
+        let found = catalog.search("notes.*")?;
+        let found: Vec<(&str, &str)> = found
+            .iter()
+            .map(|e| (e.archive.rsplit('/').next().unwrap(), e.path.as_str()))
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                ("first.db3", "first/docs/notes.txt"),
+                ("second.db3", "second/notes.txt")
+            ]
+        );
+        let found = catalog.search("first/*/notes.txt")?;
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].size, 5);
+        assert!(catalog.search("missing.txt")?.is_empty());
+
+        let digest = blake3::hash(b"hello").to_hex().to_string();
+        let found = catalog.search_checksum(&digest.to_uppercase())?;
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].path, "first/hello.txt");
+
+        assert!(catalog.remove_archive(&first_pack)?);
+        assert!(!catalog.remove_archive(&first_pack)?);
+        assert_eq!(catalog.search("*")?.len(), 1);
+        Ok(())
+    }
+}
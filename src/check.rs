@@ -0,0 +1,354 @@
+//
+// Copyright (c) 2024 Nathan Fiedler
+//
+use crate::{has_column, Error, PackReader, KIND_DIRECTORY, KIND_FILE, KIND_SYMLINK, NO_CONTENT};
+use std::fmt;
+
+///
+/// A structural problem found in an archive by `PackReader::check_archive()`,
+/// identified by the rowids of the rows involved.
+///
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Violation {
+    /// The content bundle failed checksum verification, or could not be
+    /// decompressed if it has no checksum.
+    CorruptBundle(i64),
+    /// The `itemcontent` row refers to a content bundle that does not exist.
+    MissingBundle { row: i64, content: i64 },
+    /// The `itemcontent` row refers to a range of bytes that extends beyond
+    /// the uncompressed length of its content bundle.
+    RangeOutsideBundle { row: i64, content: i64 },
+    /// The `itemcontent` row refers to an item that does not exist, or to one
+    /// that cannot have content, such as a directory.
+    OrphanContent { row: i64, item: i64 },
+    /// The content bundle is not referred to by any `itemcontent` row.
+    UnreferencedBundle(i64),
+    /// The file has no `itemcontent` rows, not even an empty one.
+    MissingContent(i64),
+    /// The parts of the file do not follow one another without gaps or
+    /// overlaps, first going astray at the `itemcontent` row given, such that
+    /// the size of the file is not the sum of its parts.
+    PartsNotContiguous { item: i64, row: i64 },
+    /// The parent of the item does not exist.
+    MissingParent { item: i64, parent: i64 },
+    /// The parent of the item is not a directory.
+    ParentNotDirectory { item: i64, parent: i64 },
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Violation::CorruptBundle(content) => {
+                write!(f, "content {}: bundle is corrupt", content)
+            }
+            Violation::MissingBundle { row, content } => {
+                write!(f, "itemcontent {}: content {} does not exist", row, content)
+            }
+            Violation::RangeOutsideBundle { row, content } => {
+                write!(
+                    f,
+                    "itemcontent {}: range extends beyond content {}",
+                    row, content
+                )
+            }
+            Violation::OrphanContent { row, item } => {
+                write!(f, "itemcontent {}: item {} cannot have content", row, item)
+            }
+            Violation::UnreferencedBundle(content) => {
+                write!(f, "content {}: bundle is not referenced", content)
+            }
+            Violation::MissingContent(item) => {
+                write!(f, "item {}: file has no itemcontent rows", item)
+            }
+            Violation::PartsNotContiguous { item, row } => {
+                write!(f, "item {}: itemcontent {} is not contiguous", item, row)
+            }
+            Violation::MissingParent { item, parent } => {
+                write!(f, "item {}: parent {} does not exist", item, parent)
+            }
+            Violation::ParentNotDirectory { item, parent } => {
+                write!(f, "item {}: parent {} is not a directory", item, parent)
+            }
+        }
+    }
+}
+
+impl PackReader {
+    ///
+    /// Check the archive for damage, returning every problem that was found.
+    /// Beyond the checksum of each content bundle, this cross-checks the
+    /// tables: every part of a file must fall within its content bundle, the
+    /// parts of each file must add up to the whole, each item must reside in
+    /// a directory that exists, and every `itemcontent` and `content` row
+    /// must belong to something.
+    ///
+    /// An empty result means the archive is intact.
+    ///
+    pub fn check_archive(&self) -> Result<Vec<Violation>, Error> {
+        let mut found: Vec<Violation> = vec![];
+        self.check_bundles(&mut found)?;
+        self.check_geometry(&mut found)?;
+        self.check_parents(&mut found)?;
+        Ok(found)
+    }
+
+    // Verify the checksum of each content bundle, and that each is in use.
+    fn check_bundles(&self, found: &mut Vec<Violation>) -> Result<(), Error> {
+        let mut stmt = self.conn.prepare(
+            "SELECT id, id NOT IN (SELECT content FROM itemcontent) FROM content ORDER BY id",
+        )?;
+        let bundles = stmt
+            .query_map([], |row| {
+                Ok((row.get::<_, i64>(0)?, row.get::<_, bool>(1)?))
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+        for (content_id, unreferenced) in bundles {
+            if !self.check_bundle(content_id)? {
+                found.push(Violation::CorruptBundle(content_id));
+            }
+            if unreferenced {
+                found.push(Violation::UnreferencedBundle(content_id));
+            }
+        }
+        Ok(())
+    }
+
+    // Check that the itemcontent rows refer to files and to byte ranges that
+    // exist, and that together they cover each file.
+    fn check_geometry(&self, found: &mut Vec<Violation>) -> Result<(), Error> {
+        // metadata-only archives refer to a bundle that does not exist
+        let no_content = if self.is_metadata_only()? {
+            NO_CONTENT
+        } else {
+            -1
+        };
+        let mut stmt = self.conn.prepare(
+            "SELECT ic.id, ic.item FROM itemcontent AS ic
+                LEFT JOIN item ON item.id = ic.item
+                WHERE item.id IS NULL OR item.kind NOT IN (?1, ?2) ORDER BY ic.id",
+        )?;
+        for result in stmt.query_map((KIND_FILE, KIND_SYMLINK), |row| {
+            Ok(Violation::OrphanContent {
+                row: row.get(0)?,
+                item: row.get(1)?,
+            })
+        })? {
+            found.push(result?);
+        }
+        let mut stmt = self.conn.prepare(
+            "SELECT ic.id, ic.content FROM itemcontent AS ic
+                LEFT JOIN content ON content.id = ic.content
+                WHERE content.id IS NULL AND ic.content != ?1 ORDER BY ic.id",
+        )?;
+        for result in stmt.query_map([no_content], |row| {
+            Ok(Violation::MissingBundle {
+                row: row.get(0)?,
+                content: row.get(1)?,
+            })
+        })? {
+            found.push(result?);
+        }
+        // archives from earlier versions do not record the bundle length
+        if has_column(&self.conn, "content", "rawsize")? {
+            let mut stmt = self.conn.prepare(
+                "SELECT ic.id, ic.content FROM itemcontent AS ic
+                    INNER JOIN content ON content.id = ic.content
+                    WHERE content.rawsize IS NOT NULL AND (ic.contentpos < 0 OR ic.size < 0
+                        OR ic.contentpos + ic.size > content.rawsize)
+                    ORDER BY ic.id",
+            )?;
+            for result in stmt.query_map([], |row| {
+                Ok(Violation::RangeOutsideBundle {
+                    row: row.get(0)?,
+                    content: row.get(1)?,
+                })
+            })? {
+                found.push(result?);
+            }
+        }
+        let mut stmt = self.conn.prepare(
+            "SELECT id FROM item WHERE kind = ?1
+                AND id NOT IN (SELECT item FROM itemcontent) ORDER BY id",
+        )?;
+        for result in stmt.query_map([KIND_FILE], |row| {
+            Ok(Violation::MissingContent(row.get(0)?))
+        })? {
+            found.push(result?);
+        }
+        // each part of a file must begin where the previous part ended
+        let mut stmt = self.conn.prepare(
+            "SELECT ic.id, ic.item, ic.itempos, ic.size FROM itemcontent AS ic
+                INNER JOIN item ON item.id = ic.item
+                WHERE item.kind = ?1 ORDER BY ic.item, ic.itempos, ic.id",
+        )?;
+        let mut rows = stmt.query([KIND_FILE])?;
+        // item being examined, the expected position of its next part, and
+        // whether a problem was already reported for it
+        let mut current: Option<(i64, i64, bool)> = None;
+        while let Some(row) = rows.next()? {
+            let (row_id, item_id, itempos, size): (i64, i64, i64, i64) =
+                (row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?);
+            let (expected, reported) = match current {
+                Some((item, expected, reported)) if item == item_id => (expected, reported),
+                _ => (0, false),
+            };
+            let astray = itempos != expected || size < 0;
+            if astray && !reported {
+                found.push(Violation::PartsNotContiguous {
+                    item: item_id,
+                    row: row_id,
+                });
+            }
+            current = Some((item_id, itempos + size, reported || astray));
+        }
+        Ok(())
+    }
+
+    // Check that the parent of each item exists and is a directory.
+    fn check_parents(&self, found: &mut Vec<Violation>) -> Result<(), Error> {
+        let mut stmt = self.conn.prepare(
+            "SELECT child.id, child.parent, parent.id IS NULL FROM item AS child
+                LEFT JOIN item AS parent ON parent.id = child.parent
+                WHERE child.parent != 0 AND (parent.id IS NULL OR parent.kind != ?1)
+                ORDER BY child.id",
+        )?;
+        for result in stmt.query_map([KIND_DIRECTORY], |row| {
+            let (item, parent) = (row.get(0)?, row.get(1)?);
+            if row.get::<_, bool>(2)? {
+                Ok(Violation::MissingParent { item, parent })
+            } else {
+                Ok(Violation::ParentNotDirectory { item, parent })
+            }
+        })? {
+            found.push(result?);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::PackBuilder;
+    use rusqlite::Connection;
+    use std::fs;
+
+    #[test]
+    fn test_check_archive() -> Result<(), Error> {
+        let workspace = tempfile::tempdir()?;
+        let basepath = workspace.path().join("input");
+        fs::create_dir_all(basepath.join("sub"))?;
+        fs::write(basepath.join("a.txt"), "the quick brown fox")?;
+        fs::write(
+            basepath.join("sub/b.txt"),
+            "jumps over the lazy dog ".repeat(1_000),
+        )?;
+        fs::write(basepath.join("empty.txt"), "")?;
+        let packfile = workspace.path().join("pack.db3");
+        let mut builder = PackBuilder::new()?;
+        builder.set_bundle_size(4_096);
+        builder.add_dir_all(&basepath)?;
+        builder.finish(&packfile)?;
+        assert!(PackReader::new(&packfile)?.check_archive()?.is_empty());
+
+        // damage the archive in every way that is checked
+        let conn = Connection::open(&packfile)?;
+        let id_of = |name: &str| -> Result<i64, Error> {
+            Ok(
+                conn.query_row("SELECT id FROM item WHERE name = ?1", [name], |row| {
+                    row.get(0)
+                })?,
+            )
+        };
+        let (a_txt, b_txt, sub) = (id_of("a.txt")?, id_of("b.txt")?, id_of("sub")?);
+        let empty_txt = id_of("empty.txt")?;
+        let (first_part, bundle): (i64, i64) = conn.query_row(
+            "SELECT id, content FROM itemcontent WHERE item = ?1",
+            [a_txt],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )?;
+        conn.execute(
+            "UPDATE itemcontent SET contentpos = contentpos + 100000 WHERE id = ?1",
+            [first_part],
+        )?;
+        let second_part: i64 = conn.query_row(
+            "SELECT id FROM itemcontent WHERE item = ?1 ORDER BY itempos LIMIT 1 OFFSET 1",
+            [b_txt],
+            |row| row.get(0),
+        )?;
+        conn.execute(
+            "UPDATE itemcontent SET itempos = itempos + 1 WHERE id = ?1",
+            [second_part],
+        )?;
+        conn.execute("DELETE FROM itemcontent WHERE item = ?1", [empty_txt])?;
+        conn.execute(
+            "INSERT INTO itemcontent (item, itempos, content, contentpos, size)
+                VALUES (?1, 0, 999, 0, 0)",
+            [sub],
+        )?;
+        let orphan = conn.last_insert_rowid();
+        conn.execute("INSERT INTO content (value, rawsize) VALUES (X'00', 1)", [])?;
+        let extra = conn.last_insert_rowid();
+        conn.execute("UPDATE item SET parent = ?2 WHERE id = ?1", [b_txt, a_txt])?;
+        conn.execute("UPDATE item SET parent = 999 WHERE id = ?1", [sub])?;
+        conn.execute("UPDATE content SET value = X'0000' WHERE id = ?1", [bundle])?;
+        drop(conn);
+
+        let found = PackReader::new(&packfile)?.check_archive()?;
+        let expected = vec![
+            Violation::CorruptBundle(bundle),
+            Violation::CorruptBundle(extra),
+            Violation::UnreferencedBundle(extra),
+            Violation::OrphanContent {
+                row: orphan,
+                item: sub,
+            },
+            Violation::MissingBundle {
+                row: orphan,
+                content: 999,
+            },
+            Violation::RangeOutsideBundle {
+                row: first_part,
+                content: bundle,
+            },
+            Violation::MissingContent(empty_txt),
+            Violation::PartsNotContiguous {
+                item: b_txt,
+                row: second_part,
+            },
+            Violation::MissingParent {
+                item: sub,
+                parent: 999,
+            },
+            Violation::ParentNotDirectory {
+                item: b_txt,
+                parent: a_txt,
+            },
+        ];
+        assert_eq!(found, expected);
+        assert_eq!(
+            found[0].to_string(),
+            format!("content {}: bundle is corrupt", bundle)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_check_metadata_only() -> Result<(), Error> {
+        let workspace = tempfile::tempdir()?;
+        let basepath = workspace.path().join("input");
+        fs::create_dir_all(&basepath)?;
+        fs::write(basepath.join("a.txt"), "the quick brown fox")?;
+        let packfile = workspace.path().join("pack.db3");
+        let mut builder = PackBuilder::new()?;
+        builder.set_metadata_only(true);
+        builder.add_dir_all(&basepath)?;
+        builder.finish(&packfile)?;
+        assert!(PackReader::new(&packfile)?.check_archive()?.is_empty());
+        // as is the fixture from an earlier version
+        assert!(PackReader::new("test/fixtures/pack.db3")?
+            .check_archive()?
+            .is_empty());
+        Ok(())
+    }
+}
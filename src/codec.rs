@@ -0,0 +1,406 @@
+//
+// Copyright (c) 2024 Nathan Fiedler
+//
+use std::io::{self, Read, Write};
+
+///
+/// Compression algorithm used for content bundles.
+///
+/// The identifier of the codec is recorded with each content bundle, such
+/// that the reader can select the matching codec when decompressing. A reader
+/// must have a codec registered for every identifier found in the archive.
+///
+pub trait Codec: Send + Sync {
+    ///
+    /// Return the identifier recorded with each bundle written by this codec.
+    ///
+    fn identifier(&self) -> &str;
+
+    ///
+    /// Compress all of the data from `input`, writing the result to `output`.
+    ///
+    fn compress_stream(&self, input: &mut dyn Read, output: &mut dyn Write) -> io::Result<()>;
+
+    ///
+    /// Return a writer that compresses everything written to it, writing the
+    /// result to `output`, such that the content of a bundle can be compressed
+    /// as it is read. The compressed data is complete only once `finish()` has
+    /// been called on the writer.
+    ///
+    /// The default implementation collects the data in memory and passes it
+    /// to `compress_stream()` when finished.
+    ///
+    fn compressor<'a>(
+        &'a self,
+        output: Box<dyn Write + 'a>,
+    ) -> io::Result<Box<dyn WriteFinish + 'a>> {
+        Ok(Box::new(BufferedCompressor {
+            codec: self,
+            output,
+            buffer: vec![],
+        }))
+    }
+
+    ///
+    /// Return a reader that yields the decompressed form of `input`.
+    ///
+    fn decompress_stream<'a>(&self, input: Box<dyn Read + 'a>) -> io::Result<Box<dyn Read + 'a>>;
+
+    ///
+    /// Use at most the given number of threads for compression, if the codec
+    /// supports doing so. The default implementation does nothing.
+    ///
+    fn set_threads(&mut self, threads: u32) {
+        let _ = threads;
+    }
+
+    ///
+    /// Compress at the given level from now on, if the codec has levels, as
+    /// when the builder adjusts the level of each bundle in the adaptive mode.
+    /// The default implementation does nothing.
+    ///
+    fn set_level(&mut self, level: i32) {
+        let _ = level;
+    }
+
+    ///
+    /// Return the largest possible size of the output when compressing `len`
+    /// bytes, if known, allowing the output to be written directly to the
+    /// database. The default implementation returns `None`.
+    ///
+    fn max_compressed_len(&self, len: u64) -> Option<u64> {
+        let _ = len;
+        None
+    }
+}
+
+///
+/// Writer that must be told when all of the data has been written, such as
+/// the compressor returned by `Codec::compressor()`.
+///
+pub trait WriteFinish: Write {
+    ///
+    /// Write whatever remains after the last of the data, and flush the
+    /// underlying writer.
+    ///
+    fn finish(self: Box<Self>) -> io::Result<()>;
+}
+
+//
+// Compressor for codecs that only compress all of their input at once.
+//
+struct BufferedCompressor<'a, C: Codec + ?Sized> {
+    codec: &'a C,
+    output: Box<dyn Write + 'a>,
+    buffer: Vec<u8>,
+}
+
+impl<C: Codec + ?Sized> Write for BufferedCompressor<'_, C> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buffer.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<C: Codec + ?Sized> WriteFinish for BufferedCompressor<'_, C> {
+    fn finish(mut self: Box<Self>) -> io::Result<()> {
+        self.codec
+            .compress_stream(&mut self.buffer.as_slice(), &mut self.output)?;
+        self.output.flush()
+    }
+}
+
+///
+/// The default codec, which uses Zstandard (without a dictionary).
+///
+#[derive(Clone, Debug, Default)]
+pub struct ZstdCodec {
+    // compression level, zero for the zstd default
+    level: i32,
+    // number of worker threads, zero for the calling thread only
+    workers: u32,
+    // base-2 logarithm of the long-distance matching window, zero if disabled
+    long_window: u32,
+    // if true, each compressed bundle includes a checksum of its content
+    checksum: bool,
+}
+
+// largest window that the decoder will accept, as a base-2 logarithm
+const WINDOW_LOG_MAX: u32 = if cfg!(target_pointer_width = "32") {
+    30
+} else {
+    31
+};
+
+impl ZstdCodec {
+    /// Identifier for bundles compressed with Zstandard.
+    pub const IDENTIFIER: &'static str = "zstd";
+
+    ///
+    /// Construct a codec that compresses at the given level, where zero
+    /// selects the default level.
+    ///
+    pub fn new(level: i32) -> Self {
+        Self {
+            level,
+            ..Default::default()
+        }
+    }
+
+    ///
+    /// Enable long-distance matching with a window of `2^window_log` bytes
+    /// (between 10 and 31, with 27 being typical), finding matches that are
+    /// far apart within a content bundle, which helps the most with large
+    /// bundles of similar files. A `window_log` of zero disables it.
+    ///
+    pub fn set_long_distance_matching(&mut self, window_log: u32) {
+        self.long_window = window_log;
+    }
+
+    ///
+    /// If enabled, a checksum of the uncompressed content is included in each
+    /// bundle and verified by Zstandard when decompressing.
+    ///
+    pub fn set_checksum(&mut self, enabled: bool) {
+        self.checksum = enabled;
+    }
+}
+
+impl Codec for ZstdCodec {
+    fn identifier(&self) -> &str {
+        Self::IDENTIFIER
+    }
+
+    fn compress_stream(&self, input: &mut dyn Read, output: &mut dyn Write) -> io::Result<()> {
+        let mut encoder = self.compressor(Box::new(output))?;
+        io::copy(input, &mut encoder)?;
+        encoder.finish()
+    }
+
+    fn compressor<'a>(
+        &'a self,
+        output: Box<dyn Write + 'a>,
+    ) -> io::Result<Box<dyn WriteFinish + 'a>> {
+        let mut encoder = zstd::stream::write::Encoder::new(output, self.level)?;
+        if self.workers > 0 {
+            // there are no threads for Zstandard to use in WebAssembly
+            #[cfg(not(target_family = "wasm"))]
+            encoder.multithread(self.workers)?;
+        }
+        if self.long_window > 0 {
+            encoder.long_distance_matching(true)?;
+            encoder.window_log(self.long_window)?;
+        }
+        if self.checksum {
+            encoder.include_checksum(true)?;
+        }
+        Ok(Box::new(encoder))
+    }
+
+    fn decompress_stream<'a>(&self, input: Box<dyn Read + 'a>) -> io::Result<Box<dyn Read + 'a>> {
+        let mut decoder = zstd::stream::read::Decoder::new(input)?;
+        // accept the larger windows used with long-distance matching
+        decoder.window_log_max(WINDOW_LOG_MAX)?;
+        Ok(Box::new(decoder))
+    }
+
+    fn set_threads(&mut self, threads: u32) {
+        // a single worker would merely move the work to another thread
+        self.workers = if threads > 1 { threads } else { 0 };
+    }
+
+    fn set_level(&mut self, level: i32) {
+        self.level = level;
+    }
+
+    fn max_compressed_len(&self, len: u64) -> Option<u64> {
+        Some(zstd::zstd_safe::compress_bound(len as usize) as u64)
+    }
+}
+
+///
+/// Codec that stores the content as-is, used for content that is unlikely to
+/// benefit from compression.
+///
+#[derive(Clone, Debug, Default)]
+pub struct StoredCodec;
+
+impl StoredCodec {
+    /// Identifier for bundles that are not compressed.
+    pub const IDENTIFIER: &'static str = "none";
+}
+
+impl Codec for StoredCodec {
+    fn identifier(&self) -> &str {
+        Self::IDENTIFIER
+    }
+
+    fn compress_stream(&self, input: &mut dyn Read, output: &mut dyn Write) -> io::Result<()> {
+        io::copy(input, output)?;
+        Ok(())
+    }
+
+    fn compressor<'a>(
+        &'a self,
+        output: Box<dyn Write + 'a>,
+    ) -> io::Result<Box<dyn WriteFinish + 'a>> {
+        Ok(Box::new(StoredWriter(output)))
+    }
+
+    fn decompress_stream<'a>(&self, input: Box<dyn Read + 'a>) -> io::Result<Box<dyn Read + 'a>> {
+        Ok(input)
+    }
+
+    fn max_compressed_len(&self, len: u64) -> Option<u64> {
+        Some(len)
+    }
+}
+
+impl<W: Write> WriteFinish for zstd::stream::write::Encoder<'_, W> {
+    fn finish(self: Box<Self>) -> io::Result<()> {
+        let mut output = zstd::stream::write::Encoder::finish(*self)?;
+        output.flush()
+    }
+}
+
+// Compressor of the stored codec, which passes the data through as-is.
+struct StoredWriter<'a>(Box<dyn Write + 'a>);
+
+impl Write for StoredWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+impl WriteFinish for StoredWriter<'_> {
+    fn finish(mut self: Box<Self>) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_zstd_round_trip() -> io::Result<()> {
+        let input = "lorem ipsum dolor sit amet ".repeat(100);
+        let mut codec = ZstdCodec::new(3);
+        codec.set_threads(2);
+        let mut compressed: Vec<u8> = vec![];
+        codec.compress_stream(&mut input.as_bytes(), &mut compressed)?;
+        assert!(compressed.len() < input.len());
+        let mut decoder = codec.decompress_stream(Box::new(compressed.as_slice()))?;
+        let mut output = String::new();
+        decoder.read_to_string(&mut output)?;
+        assert_eq!(output, input);
+        Ok(())
+    }
+
+    #[test]
+    fn test_zstd_long_distance() -> io::Result<()> {
+        // the repetition is farther apart than the default window at level 1
+        let mut state: u32 = 0x12345678;
+        let block: Vec<u8> = (0..(4 * 1048576))
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                state as u8
+            })
+            .collect();
+        let input = [block.as_slice(), block.as_slice()].concat();
+        let plain = ZstdCodec::new(1);
+        let mut expected: Vec<u8> = vec![];
+        plain.compress_stream(&mut input.as_slice(), &mut expected)?;
+        let mut codec = ZstdCodec::new(1);
+        codec.set_long_distance_matching(28);
+        codec.set_checksum(true);
+        let mut compressed: Vec<u8> = vec![];
+        codec.compress_stream(&mut input.as_slice(), &mut compressed)?;
+        assert!(compressed.len() < expected.len() * 3 / 4);
+        // any zstd codec can decompress the result
+        let mut decoder = plain.decompress_stream(Box::new(compressed.as_slice()))?;
+        let mut output: Vec<u8> = vec![];
+        decoder.read_to_end(&mut output)?;
+        assert!(output == input);
+        Ok(())
+    }
+
+    // Trivial codec that merely inverts the bits of every byte.
+    struct InvertCodec;
+
+    impl Codec for InvertCodec {
+        fn identifier(&self) -> &str {
+            "invert"
+        }
+
+        fn compress_stream(&self, input: &mut dyn Read, output: &mut dyn Write) -> io::Result<()> {
+            let mut data: Vec<u8> = vec![];
+            input.read_to_end(&mut data)?;
+            let inverted: Vec<u8> = data.iter().map(|b| !b).collect();
+            output.write_all(&inverted)
+        }
+
+        fn decompress_stream<'a>(
+            &self,
+            mut input: Box<dyn Read + 'a>,
+        ) -> io::Result<Box<dyn Read + 'a>> {
+            let mut data: Vec<u8> = vec![];
+            input.read_to_end(&mut data)?;
+            let inverted: Vec<u8> = data.iter().map(|b| !b).collect();
+            Ok(Box::new(io::Cursor::new(inverted)))
+        }
+    }
+
+    #[test]
+    fn test_compressor() -> io::Result<()> {
+        let input = "lorem ipsum dolor sit amet ".repeat(1000);
+        let zstd = ZstdCodec::new(3);
+        let codecs: [&dyn Codec; 3] = [&zstd, &StoredCodec, &InvertCodec];
+        for codec in codecs {
+            // the data is written in pieces, as the builder does
+            let mut compressed: Vec<u8> = vec![];
+            let mut writer = codec.compressor(Box::new(&mut compressed))?;
+            for piece in input.as_bytes().chunks(1000) {
+                writer.write_all(piece)?;
+            }
+            writer.finish()?;
+            let mut decoder = codec.decompress_stream(Box::new(compressed.as_slice()))?;
+            let mut output = String::new();
+            decoder.read_to_string(&mut output)?;
+            assert_eq!(output, input);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_custom_codec() -> Result<(), crate::Error> {
+        use crate::{Error, PackBuilder, PackReader};
+        let workspace = tempfile::tempdir()?;
+        let basepath = workspace.path().join("input");
+        std::fs::create_dir_all(&basepath)?;
+        std::fs::write(basepath.join("hello.txt"), "hello world")?;
+        let packfile = workspace.path().join("pack.db3");
+        let mut builder = PackBuilder::new()?;
+        builder.set_codec(Box::new(InvertCodec));
+        builder.add_dir_all(&basepath)?;
+        builder.finish(&packfile)?;
+
+        // the reader must be told about the codec
+        let mut reader = PackReader::new(&packfile)?;
+        let result = reader.read_range("input/hello.txt", 0, 5);
+        assert!(matches!(result, Err(Error::UnknownCodec(id)) if id == "invert"));
+        reader.register_codec(Box::new(InvertCodec));
+        assert_eq!(reader.read_range("input/hello.txt", 6, 5)?, b"world");
+        Ok(())
+    }
+}
@@ -0,0 +1,723 @@
+//
+// Copyright (c) 2024 Nathan Fiedler
+//
+use crate::{Codec, Error, WriteFinish};
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+use std::cell::RefCell;
+use std::io::{self, Read, Write};
+use std::rc::Rc;
+
+// value of the `encryption` entry in `archive_meta` for archives whose
+// content bundles are encrypted, while the metadata is not, with each segment
+// bound to the rowid of its bundle
+pub(crate) const ENCRYPTION_CONTENT: &str = "content-v2";
+
+// value of the `encryption` entry for archives written before the segments
+// were bound to their bundle, which can still be read and added to
+pub(crate) const ENCRYPTION_CONTENT_UNBOUND: &str = "content";
+
+// name of the `archive_meta` entry that holds the wrapped content key
+pub(crate) const KEY_ENTRY: &str = "encryption-key";
+
+// identifier of the key derivation in the wrapped key
+const KDF_ARGON2ID: &str = "argon2id";
+
+// amount of plaintext sealed together with each authentication tag
+const SEGMENT_SIZE: usize = 65536;
+
+// size of the authentication tag that follows each segment
+const TAG_SIZE: usize = 16;
+
+// size of the random nonce prefix at the start of each bundle, which is
+// followed in the nonce by a 32-bit segment counter and the final flag
+const PREFIX_SIZE: usize = 19;
+
+// size of the random salt for deriving a key from a password
+const SALT_SIZE: usize = 16;
+
+// byte that follows the key within the wrapped key, authenticating whether
+// the segments are bound to their bundle; keys wrapped before this was added
+// have no such byte
+const MODE_BOUND: u8 = 1;
+const MODE_UNBOUND: u8 = 0;
+
+//
+// Key with which the content bundles of an archive are encrypted. The key is
+// generated at random and stored in the archive wrapped by a key derived
+// from the password, such that changing the password does not require
+// encrypting the content again.
+//
+#[derive(Clone)]
+pub(crate) struct ContentKey {
+    key: [u8; 32],
+    // true if each segment is bound to the rowid of its bundle, which is
+    // not the case for archives with the unbound form of encryption
+    bound: bool,
+}
+
+impl ContentKey {
+    // Generate a new key at random.
+    pub(crate) fn generate() -> Result<Self, Error> {
+        let mut key = [0; 32];
+        fill_random(&mut key)?;
+        Ok(Self { key, bound: true })
+    }
+
+    // Encrypt the key, along with whether the segments are bound to their
+    // bundle, with one derived from the password, returning the text that is
+    // recorded in the archive.
+    pub(crate) fn wrap(&self, password: &str) -> Result<String, Error> {
+        let mut salt = [0; SALT_SIZE];
+        fill_random(&mut salt)?;
+        let mut nonce = [0; 24];
+        fill_random(&mut nonce)?;
+        let cipher = derive_cipher(password, &salt)?;
+        let mut plaintext = self.key.to_vec();
+        plaintext.push(if self.bound { MODE_BOUND } else { MODE_UNBOUND });
+        let wrapped = cipher
+            .encrypt(&XNonce::from(nonce), &plaintext[..])
+            .map_err(|_| Error::WrongPassword)?;
+        Ok(format!(
+            "{}${}${}${}",
+            KDF_ARGON2ID,
+            to_hex(&salt),
+            to_hex(&nonce),
+            to_hex(&wrapped)
+        ))
+    }
+
+    // Decrypt the key recorded in the archive using the password, for an
+    // archive with the given value of the `encryption` entry. That entry is
+    // not authenticated, so it must agree with the mode within the key;
+    // only keys wrapped before the mode was recorded rely on the entry.
+    pub(crate) fn unwrap(text: &str, password: &str, encryption: &str) -> Result<Self, Error> {
+        let fields: Vec<&str> = text.split('$').collect();
+        let [KDF_ARGON2ID, salt, nonce, wrapped] = fields[..] else {
+            return Err(Error::UnsupportedFormatFeature(format!(
+                "{}={}",
+                KEY_ENTRY,
+                text.split('$').next().unwrap_or_default()
+            )));
+        };
+        let invalid = || Error::UnsupportedFormatFeature(format!("{}={}", KEY_ENTRY, text));
+        let salt = from_hex(salt).ok_or_else(invalid)?;
+        let nonce: [u8; 24] = from_hex(nonce)
+            .and_then(|n| n.try_into().ok())
+            .ok_or_else(invalid)?;
+        let wrapped = from_hex(wrapped).ok_or_else(invalid)?;
+        let cipher = derive_cipher(password, &salt)?;
+        let plaintext = cipher
+            .decrypt(&XNonce::from(nonce), &wrapped[..])
+            .map_err(|_| Error::WrongPassword)?;
+        let claimed = encryption != ENCRYPTION_CONTENT_UNBOUND;
+        let (key, bound) = match plaintext.split_at_checked(32) {
+            Some((key, [])) => (key, claimed),
+            Some((key, [MODE_BOUND])) => (key, true),
+            Some((key, [MODE_UNBOUND])) => (key, false),
+            _ => return Err(invalid()),
+        };
+        if bound != claimed {
+            return Err(Error::EncryptionMismatch(format!(
+                "encryption entry {:?} does not match the key",
+                encryption
+            )));
+        }
+        let key: [u8; 32] = key.try_into().map_err(|_| Error::WrongPassword)?;
+        Ok(Self { key, bound })
+    }
+
+    // Return the value of the `encryption` entry for archives whose bundles
+    // are encrypted with this key.
+    pub(crate) fn encryption(&self) -> &'static str {
+        if self.bound {
+            ENCRYPTION_CONTENT
+        } else {
+            ENCRYPTION_CONTENT_UNBOUND
+        }
+    }
+
+    // Return true if each segment is bound to the rowid of its bundle.
+    pub(crate) fn is_bound(&self) -> bool {
+        self.bound
+    }
+
+    fn cipher(&self) -> XChaCha20Poly1305 {
+        XChaCha20Poly1305::new(&self.key.into())
+    }
+}
+
+// Derive a cipher from the password and salt using Argon2id.
+fn derive_cipher(password: &str, salt: &[u8]) -> Result<XChaCha20Poly1305, Error> {
+    let mut key = [0; 32];
+    argon2::Argon2::default()
+        .hash_password_into(password.as_bytes(), salt, &mut key)
+        .map_err(|_| Error::WrongPassword)?;
+    Ok(XChaCha20Poly1305::new(&key.into()))
+}
+
+fn fill_random(buf: &mut [u8]) -> Result<(), Error> {
+    getrandom::fill(buf).map_err(|err| io::Error::other(err.to_string()))?;
+    Ok(())
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn from_hex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(text.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+// Return the associated data of the segment with the given number in the
+// bundle with the given rowid, if segments are bound to their bundle, such
+// that segments cannot be moved from one bundle or position to another.
+fn segment_aad(bound: Option<i64>, counter: u32) -> Vec<u8> {
+    match bound {
+        Some(content_id) => {
+            let mut aad = Vec::with_capacity(12);
+            aad.extend_from_slice(&content_id.to_be_bytes());
+            aad.extend_from_slice(&counter.to_be_bytes());
+            aad
+        }
+        None => vec![],
+    }
+}
+
+// Return the nonce for the segment with the given number.
+fn segment_nonce(prefix: &[u8; PREFIX_SIZE], counter: u32, last: bool) -> XNonce {
+    let mut nonce = [0; 24];
+    nonce[..PREFIX_SIZE].copy_from_slice(prefix);
+    nonce[PREFIX_SIZE..PREFIX_SIZE + 4].copy_from_slice(&counter.to_be_bytes());
+    nonce[23] = last as u8;
+    XNonce::from(nonce)
+}
+
+fn crypto_error() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        "content failed authentication, wrong key or corrupt bundle",
+    )
+}
+
+//
+// Encrypts the data written to it in segments, each with its own tag, such
+// that the content can be decrypted as it is read. The final segment is
+// marked as such, to detect truncation. Call `finish()` when done.
+//
+struct SealingWriter<W: Write> {
+    output: W,
+    cipher: XChaCha20Poly1305,
+    // rowid of the bundle being written, if the segments are bound to it
+    bound: Option<i64>,
+    prefix: [u8; PREFIX_SIZE],
+    counter: u32,
+    buffer: Vec<u8>,
+}
+
+impl<W: Write> SealingWriter<W> {
+    fn new(mut output: W, key: &ContentKey, content_id: i64) -> io::Result<Self> {
+        let mut prefix = [0; PREFIX_SIZE];
+        fill_random(&mut prefix).map_err(|err| io::Error::other(err.to_string()))?;
+        output.write_all(&prefix)?;
+        Ok(Self {
+            output,
+            cipher: key.cipher(),
+            bound: key.bound.then_some(content_id),
+            prefix,
+            counter: 0,
+            buffer: Vec::with_capacity(SEGMENT_SIZE),
+        })
+    }
+
+    fn seal(&mut self, last: bool) -> io::Result<()> {
+        let nonce = segment_nonce(&self.prefix, self.counter, last);
+        let aad = segment_aad(self.bound, self.counter);
+        let payload = Payload {
+            msg: &self.buffer[..],
+            aad: &aad,
+        };
+        let sealed = self
+            .cipher
+            .encrypt(&nonce, payload)
+            .map_err(|_| crypto_error())?;
+        self.output.write_all(&sealed)?;
+        self.buffer.clear();
+        self.counter = self
+            .counter
+            .checked_add(1)
+            .ok_or_else(|| io::Error::other("too many segments"))?;
+        Ok(())
+    }
+
+    // Seal whatever remains as the final segment.
+    fn finish(mut self) -> io::Result<()> {
+        self.seal(true)?;
+        self.output.flush()
+    }
+}
+
+impl<W: Write> Write for SealingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // a full segment is sealed only once more data arrives, as the final
+        // segment must be marked as such
+        if self.buffer.len() == SEGMENT_SIZE && !buf.is_empty() {
+            self.seal(false)?;
+        }
+        let count = buf.len().min(SEGMENT_SIZE - self.buffer.len());
+        self.buffer.extend_from_slice(&buf[..count]);
+        Ok(count)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.output.flush()
+    }
+}
+
+//
+// Decrypts the segments written by `SealingWriter` as they are read.
+//
+pub(crate) struct OpeningReader<R: Read> {
+    input: R,
+    cipher: XChaCha20Poly1305,
+    // rowid of the bundle being read, if the segments are bound to it
+    bound: Option<i64>,
+    prefix: Option<[u8; PREFIX_SIZE]>,
+    counter: u32,
+    // sealed data read ahead of the current segment
+    sealed: Vec<u8>,
+    // decrypted data of the current segment and the position within it
+    plain: Vec<u8>,
+    pos: usize,
+    done: bool,
+}
+
+impl<R: Read> OpeningReader<R> {
+    pub(crate) fn new(input: R, key: &ContentKey, content_id: i64) -> Self {
+        Self {
+            input,
+            cipher: key.cipher(),
+            bound: key.bound.then_some(content_id),
+            prefix: None,
+            counter: 0,
+            sealed: Vec::with_capacity(SEGMENT_SIZE + TAG_SIZE + 1),
+            plain: vec![],
+            pos: 0,
+            done: false,
+        }
+    }
+
+    // Read until the buffer holds the given number of bytes, or the input
+    // is exhausted.
+    fn fill_to(&mut self, len: usize) -> io::Result<()> {
+        while self.sealed.len() < len {
+            let start = self.sealed.len();
+            self.sealed.resize(len, 0);
+            let count = self.input.read(&mut self.sealed[start..])?;
+            self.sealed.truncate(start + count);
+            if count == 0 {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    // Decrypt the next segment, which is the last one if nothing follows it.
+    fn open_next(&mut self) -> io::Result<()> {
+        let prefix = match self.prefix {
+            Some(prefix) => prefix,
+            None => {
+                self.fill_to(PREFIX_SIZE)?;
+                let prefix: [u8; PREFIX_SIZE] = self.sealed[..]
+                    .try_into()
+                    .map_err(|_| io::Error::from(io::ErrorKind::UnexpectedEof))?;
+                self.sealed.clear();
+                self.prefix = Some(prefix);
+                prefix
+            }
+        };
+        // read one byte beyond the segment to learn if another follows
+        self.fill_to(SEGMENT_SIZE + TAG_SIZE + 1)?;
+        let last = self.sealed.len() <= SEGMENT_SIZE + TAG_SIZE;
+        let end = self.sealed.len().min(SEGMENT_SIZE + TAG_SIZE);
+        let nonce = segment_nonce(&prefix, self.counter, last);
+        let aad = segment_aad(self.bound, self.counter);
+        let payload = Payload {
+            msg: &self.sealed[..end],
+            aad: &aad,
+        };
+        self.plain = self
+            .cipher
+            .decrypt(&nonce, payload)
+            .map_err(|_| crypto_error())?;
+        self.sealed.drain(..end);
+        self.pos = 0;
+        self.counter += 1;
+        self.done = last;
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for OpeningReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.pos == self.plain.len() {
+            if self.done {
+                return Ok(0);
+            }
+            self.open_next()?;
+        }
+        let count = buf.len().min(self.plain.len() - self.pos);
+        buf[..count].copy_from_slice(&self.plain[self.pos..self.pos + count]);
+        self.pos += count;
+        Ok(count)
+    }
+}
+
+//
+// Codec that encrypts the output of another codec, as used for writing the
+// content bundle with the given rowid in an encrypted archive. The identifier
+// is that of the wrapped codec, as the encryption applies to the archive as
+// a whole.
+//
+pub(crate) struct SealedCodec<'a> {
+    inner: &'a dyn Codec,
+    key: &'a ContentKey,
+    content_id: i64,
+}
+
+impl<'a> SealedCodec<'a> {
+    pub(crate) fn new(inner: &'a dyn Codec, key: &'a ContentKey, content_id: i64) -> Self {
+        Self {
+            inner,
+            key,
+            content_id,
+        }
+    }
+}
+
+impl Codec for SealedCodec<'_> {
+    fn identifier(&self) -> &str {
+        self.inner.identifier()
+    }
+
+    fn compress_stream(&self, input: &mut dyn Read, output: &mut dyn Write) -> io::Result<()> {
+        let mut writer = SealingWriter::new(output, self.key, self.content_id)?;
+        self.inner.compress_stream(input, &mut writer)?;
+        writer.finish()
+    }
+
+    fn compressor<'a>(
+        &'a self,
+        output: Box<dyn Write + 'a>,
+    ) -> io::Result<Box<dyn WriteFinish + 'a>> {
+        let writer = SealingWriter::new(output, self.key, self.content_id)?;
+        let writer = Rc::new(RefCell::new(writer));
+        let inner = self
+            .inner
+            .compressor(Box::new(SharedSealingWriter(writer.clone())))?;
+        Ok(Box::new(SealingCompressor { inner, writer }))
+    }
+
+    fn decompress_stream<'a>(&self, input: Box<dyn Read + 'a>) -> io::Result<Box<dyn Read + 'a>> {
+        let opened = OpeningReader::new(input, self.key, self.content_id);
+        self.inner.decompress_stream(Box::new(opened))
+    }
+
+    fn max_compressed_len(&self, len: u64) -> Option<u64> {
+        let bound = self.inner.max_compressed_len(len)?;
+        let segments = bound / SEGMENT_SIZE as u64 + 1;
+        Some(PREFIX_SIZE as u64 + bound + segments * TAG_SIZE as u64)
+    }
+}
+
+// Handle to the sealing writer that is given to the compressor of the wrapped
+// codec, such that the final segment can be sealed once it has finished.
+struct SharedSealingWriter<'a>(Rc<RefCell<SealingWriter<Box<dyn Write + 'a>>>>);
+
+impl Write for SharedSealingWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.borrow_mut().flush()
+    }
+}
+
+//
+// Compressor of the sealed codec, which encrypts the output of the compressor
+// of the wrapped codec.
+//
+struct SealingCompressor<'a> {
+    inner: Box<dyn WriteFinish + 'a>,
+    writer: Rc<RefCell<SealingWriter<Box<dyn Write + 'a>>>>,
+}
+
+impl Write for SealingCompressor<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.inner.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl WriteFinish for SealingCompressor<'_> {
+    fn finish(self: Box<Self>) -> io::Result<()> {
+        let Self { inner, writer } = *self;
+        inner.finish()?;
+        // the wrapped compressor has released its handle by now
+        let writer = Rc::try_unwrap(writer)
+            .map_err(|_| io::Error::other("compressor retained its output"))?;
+        writer.into_inner().finish()
+    }
+}
+
+//
+// Decrypt the sealed bundle with the key `from`, if any, and encrypt it anew
+// with the key `to`, if any, leaving the compressed data within untouched.
+// The bundle is moved from the rowid `from_id` to the rowid `to_id`.
+//
+pub(crate) fn reseal(
+    value: &[u8],
+    from: Option<&ContentKey>,
+    to: Option<&ContentKey>,
+    from_id: i64,
+    to_id: i64,
+) -> io::Result<Vec<u8>> {
+    let opened = match from {
+        Some(key) => {
+            let mut opened: Vec<u8> = Vec::with_capacity(value.len());
+            OpeningReader::new(value, key, from_id).read_to_end(&mut opened)?;
+            opened
+        }
+        None => value.to_vec(),
+    };
+    match to {
+        Some(key) => {
+            let mut sealed: Vec<u8> = Vec::with_capacity(opened.len() + PREFIX_SIZE);
+            let mut writer = SealingWriter::new(&mut sealed, key, to_id)?;
+            writer.write_all(&opened)?;
+            writer.finish()?;
+            Ok(sealed)
+        }
+        None => Ok(opened),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{PackBuilder, PackReader, StoredCodec, ZstdCodec};
+    use std::fs;
+
+    // Wrap the key as was done before the mode was recorded with it.
+    fn wrap_legacy(key: &ContentKey, password: &str) -> Result<String, Error> {
+        let salt = [7; SALT_SIZE];
+        let nonce = [9; 24];
+        let wrapped = derive_cipher(password, &salt)?
+            .encrypt(&XNonce::from(nonce), &key.key[..])
+            .map_err(|_| Error::WrongPassword)?;
+        Ok(format!(
+            "{}${}${}${}",
+            KDF_ARGON2ID,
+            to_hex(&salt),
+            to_hex(&nonce),
+            to_hex(&wrapped)
+        ))
+    }
+
+    #[test]
+    fn test_unbound_archive() -> Result<(), Error> {
+        let workspace = tempfile::tempdir()?;
+        fs::write(workspace.path().join("one.txt"), "one ".repeat(100))?;
+        // make an archive as written before the segments were bound to
+        // their bundle, with the unbound form and a key without the mode
+        let packfile = workspace.path().join("unbound.db3");
+        let mut builder = PackBuilder::new()?;
+        builder.encrypt_content("hunter2")?;
+        let wrapped: String = builder.conn.query_row(
+            "SELECT value FROM archive_meta WHERE name = ?1",
+            [KEY_ENTRY],
+            |row| row.get(0),
+        )?;
+        let key = ContentKey::unwrap(&wrapped, "hunter2", ENCRYPTION_CONTENT)?;
+        builder.conn.execute(
+            "UPDATE archive_meta SET value = ?2 WHERE name = ?1",
+            [KEY_ENTRY, &wrap_legacy(&key, "hunter2")?],
+        )?;
+        builder.conn.execute(
+            "UPDATE archive_meta SET value = ?1 WHERE name = 'encryption'",
+            [ENCRYPTION_CONTENT_UNBOUND],
+        )?;
+        builder.encrypt_content("hunter2")?;
+        builder.add_file(workspace.path().join("one.txt"), 0)?;
+        builder.finish(&packfile)?;
+
+        // such archives can still be read
+        let mut reader = PackReader::new(&packfile)?;
+        assert_eq!(
+            reader.info()?.encryption.as_deref(),
+            Some(ENCRYPTION_CONTENT_UNBOUND)
+        );
+        reader.set_password("hunter2")?;
+        let mut content: Vec<u8> = vec![];
+        reader.read_path("one.txt", &mut content)?;
+        assert_eq!(content, "one ".repeat(100).as_bytes());
+        drop(reader);
+
+        // changing the password records the mode within the key
+        let mut builder = PackBuilder::open(&packfile)?;
+        builder.change_password("hunter2", "swordfish")?;
+        builder.commit()?;
+        let conn = rusqlite::Connection::open(&packfile)?;
+        conn.execute(
+            "UPDATE archive_meta SET value = ?1 WHERE name = 'encryption'",
+            [ENCRYPTION_CONTENT],
+        )?;
+        drop(conn);
+        let mut reader = PackReader::new(&packfile)?;
+        assert!(matches!(
+            reader.set_password("swordfish"),
+            Err(Error::EncryptionMismatch(_))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_wrap_content_key() -> Result<(), Error> {
+        let key = ContentKey::generate()?;
+        let wrapped = key.wrap("secret")?;
+        assert!(wrapped.starts_with("argon2id$"));
+        let unwrapped = ContentKey::unwrap(&wrapped, "secret", ENCRYPTION_CONTENT)?;
+        assert_eq!(unwrapped.key, key.key);
+        assert!(unwrapped.is_bound());
+        // the entry cannot claim the unbound form for a bound key
+        assert!(matches!(
+            ContentKey::unwrap(&wrapped, "secret", ENCRYPTION_CONTENT_UNBOUND),
+            Err(Error::EncryptionMismatch(_))
+        ));
+        let unbound = ContentKey {
+            key: key.key,
+            bound: false,
+        };
+        let wrapped_unbound = unbound.wrap("secret")?;
+        let unwrapped = ContentKey::unwrap(&wrapped_unbound, "secret", ENCRYPTION_CONTENT_UNBOUND)?;
+        assert!(!unwrapped.is_bound());
+        assert_eq!(unwrapped.encryption(), ENCRYPTION_CONTENT_UNBOUND);
+        assert!(matches!(
+            ContentKey::unwrap(&wrapped_unbound, "secret", ENCRYPTION_CONTENT),
+            Err(Error::EncryptionMismatch(_))
+        ));
+        // keys wrapped before the mode was recorded rely on the entry
+        let legacy = wrap_legacy(&key, "secret")?;
+        assert!(!ContentKey::unwrap(&legacy, "secret", ENCRYPTION_CONTENT_UNBOUND)?.is_bound());
+        assert!(ContentKey::unwrap(&legacy, "secret", ENCRYPTION_CONTENT)?.is_bound());
+        assert!(matches!(
+            ContentKey::unwrap(&wrapped, "Secret", ENCRYPTION_CONTENT),
+            Err(Error::WrongPassword)
+        ));
+        assert!(matches!(
+            ContentKey::unwrap("scrypt$00$00$00", "secret", ENCRYPTION_CONTENT),
+            Err(Error::UnsupportedFormatFeature(_))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_sealed_codec() -> Result<(), Error> {
+        let key = ContentKey::generate()?;
+        // sizes around the segment boundaries
+        for len in [0, 1, SEGMENT_SIZE - 1, SEGMENT_SIZE, SEGMENT_SIZE * 3 + 7] {
+            let data: Vec<u8> = (0..len).map(|i| (i % 251) as u8).collect();
+            let codec = SealedCodec::new(&StoredCodec, &key, 1);
+            let mut sealed: Vec<u8> = vec![];
+            codec.compress_stream(&mut &data[..], &mut sealed)?;
+            assert!(sealed.len() as u64 <= codec.max_compressed_len(len as u64).unwrap());
+            let mut opened: Vec<u8> = vec![];
+            codec
+                .decompress_stream(Box::new(&sealed[..]))?
+                .read_to_end(&mut opened)?;
+            assert_eq!(opened, data);
+
+            // the compressor seals the data as it is written
+            let mut streamed: Vec<u8> = vec![];
+            let mut writer = codec.compressor(Box::new(&mut streamed))?;
+            for piece in data.chunks(1000) {
+                writer.write_all(piece)?;
+            }
+            writer.finish()?;
+            let mut opened: Vec<u8> = vec![];
+            codec
+                .decompress_stream(Box::new(&streamed[..]))?
+                .read_to_end(&mut opened)?;
+            assert_eq!(opened, data);
+
+            // truncating at a segment boundary is detected
+            if len > SEGMENT_SIZE {
+                let truncated = &sealed[..PREFIX_SIZE + SEGMENT_SIZE + TAG_SIZE];
+                let mut opened: Vec<u8> = vec![];
+                let result = codec
+                    .decompress_stream(Box::new(truncated))?
+                    .read_to_end(&mut opened);
+                assert!(result.is_err());
+            }
+        }
+
+        // tampering and the wrong key are both detected
+        let data = "hello world".repeat(100);
+        let zstd = ZstdCodec::default();
+        let codec = SealedCodec::new(&zstd, &key, 1);
+        let mut sealed: Vec<u8> = vec![];
+        codec.compress_stream(&mut data.as_bytes(), &mut sealed)?;
+        let other = ContentKey::generate()?;
+        let wrong = SealedCodec::new(&zstd, &other, 1);
+        let mut opened: Vec<u8> = vec![];
+        assert!(wrong
+            .decompress_stream(Box::new(&sealed[..]))
+            .and_then(|mut r| r.read_to_end(&mut opened))
+            .is_err());
+        // a bundle does not open as any other bundle
+        let moved = SealedCodec::new(&zstd, &key, 2);
+        let mut opened: Vec<u8> = vec![];
+        assert!(moved
+            .decompress_stream(Box::new(&sealed[..]))
+            .and_then(|mut r| r.read_to_end(&mut opened))
+            .is_err());
+        // bundles can be moved from one key to another without decompressing
+        let resealed = reseal(&sealed, Some(&key), Some(&other), 1, 1)?;
+        let mut opened: Vec<u8> = vec![];
+        wrong
+            .decompress_stream(Box::new(&resealed[..]))?
+            .read_to_end(&mut opened)?;
+        assert_eq!(opened, data.as_bytes());
+        let mut opened: Vec<u8> = vec![];
+        let plain = reseal(&resealed, Some(&other), None, 1, 1)?;
+        zstd.decompress_stream(Box::new(&plain[..]))?
+            .read_to_end(&mut opened)?;
+        assert_eq!(opened, data.as_bytes());
+        assert!(reseal(&sealed, Some(&other), None, 1, 1).is_err());
+        // or from one bundle to another with the same key
+        let resealed = reseal(&sealed, Some(&key), Some(&key), 1, 2)?;
+        let mut opened: Vec<u8> = vec![];
+        moved
+            .decompress_stream(Box::new(&resealed[..]))?
+            .read_to_end(&mut opened)?;
+        assert_eq!(opened, data.as_bytes());
+        let mut opened: Vec<u8> = vec![];
+        let last = sealed.len() - 1;
+        sealed[last] ^= 1;
+        assert!(codec
+            .decompress_stream(Box::new(&sealed[..]))
+            .and_then(|mut r| r.read_to_end(&mut opened))
+            .is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,174 @@
+//
+// Copyright (c) 2024 Nathan Fiedler
+//
+use crate::Error;
+use sha2::Digest;
+use std::fmt;
+use std::io::{self, Write};
+use std::str::FromStr;
+
+///
+/// Algorithm used to compute the checksums of the files and content bundles
+/// in an archive.
+///
+/// The identifier of the algorithm is recorded in the `archive_meta` table
+/// under the name `digest`. Archives without such an entry use BLAKE3.
+///
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum DigestAlgorithm {
+    /// BLAKE3 with a 256-bit digest, which is both fast and secure.
+    #[default]
+    Blake3,
+    /// SHA-256, for those who must use a standardized algorithm.
+    Sha256,
+    /// XXH3 with a 128-bit digest, which is the fastest but offers no
+    /// protection against deliberate collisions.
+    Xxh3,
+}
+
+impl DigestAlgorithm {
+    ///
+    /// Return the identifier recorded in the archive for this algorithm.
+    ///
+    pub fn identifier(&self) -> &'static str {
+        match self {
+            DigestAlgorithm::Blake3 => "blake3",
+            DigestAlgorithm::Sha256 => "sha256",
+            DigestAlgorithm::Xxh3 => "xxh3-128",
+        }
+    }
+
+    ///
+    /// Return true if it is infeasible to find two inputs with the same
+    /// digest, such that a digest can identify content from an untrusted
+    /// archive.
+    ///
+    pub fn is_collision_resistant(&self) -> bool {
+        !matches!(self, DigestAlgorithm::Xxh3)
+    }
+
+    ///
+    /// Compute the digest of the given data.
+    ///
+    pub fn hash(&self, data: &[u8]) -> Vec<u8> {
+        let mut hasher = Hasher::new(*self);
+        hasher.update(data);
+        hasher.finalize()
+    }
+}
+
+impl fmt::Display for DigestAlgorithm {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.identifier())
+    }
+}
+
+impl FromStr for DigestAlgorithm {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "blake3" => Ok(DigestAlgorithm::Blake3),
+            "sha256" => Ok(DigestAlgorithm::Sha256),
+            "xxh3-128" | "xxh3" => Ok(DigestAlgorithm::Xxh3),
+            _ => Err(Error::UnknownDigest(s.to_owned())),
+        }
+    }
+}
+
+//
+// Incremental form of a digest algorithm, which can also be written to.
+//
+pub(crate) enum Hasher {
+    Blake3(Box<blake3::Hasher>),
+    Sha256(sha2::Sha256),
+    Xxh3(Box<xxhash_rust::xxh3::Xxh3>),
+}
+
+impl Hasher {
+    pub(crate) fn new(algorithm: DigestAlgorithm) -> Self {
+        match algorithm {
+            DigestAlgorithm::Blake3 => Hasher::Blake3(Box::default()),
+            DigestAlgorithm::Sha256 => Hasher::Sha256(sha2::Sha256::new()),
+            DigestAlgorithm::Xxh3 => Hasher::Xxh3(Box::default()),
+        }
+    }
+
+    pub(crate) fn update(&mut self, data: &[u8]) {
+        match self {
+            Hasher::Blake3(hasher) => {
+                hasher.update(data);
+            }
+            Hasher::Sha256(hasher) => hasher.update(data),
+            Hasher::Xxh3(hasher) => hasher.update(data),
+        }
+    }
+
+    // Return the digest of everything given so far, in its canonical
+    // (big-endian for XXH3) form.
+    pub(crate) fn finalize(&self) -> Vec<u8> {
+        match self {
+            Hasher::Blake3(hasher) => hasher.finalize().as_bytes().to_vec(),
+            Hasher::Sha256(hasher) => hasher.clone().finalize().to_vec(),
+            Hasher::Xxh3(hasher) => hasher.digest128().to_be_bytes().to_vec(),
+        }
+    }
+}
+
+impl Write for Hasher {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.update(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_known_digests() {
+        let to_hex =
+            |digest: Vec<u8>| -> String { digest.iter().map(|b| format!("{:02x}", b)).collect() };
+        assert_eq!(
+            to_hex(DigestAlgorithm::Blake3.hash(b"abc")),
+            "6437b3ac38465133ffb63b75273a8db548c558465d79db03fd359c6cd5bd9d85"
+        );
+        assert_eq!(
+            to_hex(DigestAlgorithm::Sha256.hash(b"abc")),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert_eq!(
+            to_hex(DigestAlgorithm::Xxh3.hash(b"")),
+            "99aa06d3014798d86001c324468d497f"
+        );
+        // digests computed a piece at a time are the same
+        let mut hasher = Hasher::new(DigestAlgorithm::Sha256);
+        hasher.write_all(b"a").unwrap();
+        hasher.write_all(b"bc").unwrap();
+        assert_eq!(hasher.finalize(), DigestAlgorithm::Sha256.hash(b"abc"));
+    }
+
+    #[test]
+    fn test_identifiers() -> Result<(), Error> {
+        for algorithm in [
+            DigestAlgorithm::Blake3,
+            DigestAlgorithm::Sha256,
+            DigestAlgorithm::Xxh3,
+        ] {
+            assert_eq!(
+                algorithm.identifier().parse::<DigestAlgorithm>()?,
+                algorithm
+            );
+        }
+        assert!(matches!(
+            "md5".parse::<DigestAlgorithm>(),
+            Err(Error::UnknownDigest(_))
+        ));
+        Ok(())
+    }
+}
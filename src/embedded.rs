@@ -0,0 +1,193 @@
+//
+// Copyright (c) 2024 Nathan Fiedler
+//
+use crate::vfs::RemoteSource;
+use crate::{Error, PackReader, SQL_HEADER};
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::Path;
+
+// Number of bytes examined at a time when searching for an embedded archive.
+const SCAN_SIZE: usize = 1048576;
+
+// Size of the SQLite database header, which records the database size.
+const DB_HEADER_SIZE: usize = 100;
+
+impl PackReader {
+    ///
+    /// Construct a `PackReader` that reads the archive embedded within the
+    /// given file, starting at `offset` bytes from the beginning and running
+    /// to the end of the file, such as one that was appended to an executable
+    /// to make a self-extractor. The archive is treated as read-only and
+    /// immutable.
+    ///
+    pub fn new_at_offset<P: AsRef<Path>>(path: P, offset: u64) -> Result<Self, Error> {
+        let file = File::open(path.as_ref())?;
+        let len = file.metadata()?.len();
+        if offset >= len {
+            return Err(Error::NotPackFile);
+        }
+        let source = FileSource {
+            file,
+            offset,
+            size: len - offset,
+        };
+        Self::open_source(Box::new(source))
+    }
+
+    ///
+    /// Construct a `PackReader` for the archive found at the end of the given
+    /// file by `find_embedded_pack()`, returning `Error::NotPackFile` if there
+    /// is none.
+    ///
+    pub fn open_embedded<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        match find_embedded_pack(path.as_ref())? {
+            Some(offset) => Self::new_at_offset(path, offset),
+            None => Err(Error::NotPackFile),
+        }
+    }
+}
+
+///
+/// Search the given file for an archive that was concatenated to the end of
+/// it, returning the offset at which the archive begins, if any. A file that
+/// is itself an archive yields an offset of zero.
+///
+/// The archive is recognized by the SQLite database header, which must
+/// record a database size that runs exactly to the end of the file.
+///
+pub fn find_embedded_pack<P: AsRef<Path>>(path: P) -> Result<Option<u64>, Error> {
+    let mut file = File::open(path.as_ref())?;
+    let len = file.metadata()?.len();
+    let mut buffer: Vec<u8> = Vec::with_capacity(SCAN_SIZE + SQL_HEADER.len());
+    let mut pos: u64 = 0;
+    while pos + DB_HEADER_SIZE as u64 <= len {
+        // overlap each chunk with the next so that no header is missed
+        buffer.clear();
+        file.seek(SeekFrom::Start(pos))?;
+        (&mut file)
+            .take((SCAN_SIZE + SQL_HEADER.len() - 1) as u64)
+            .read_to_end(&mut buffer)?;
+        for (index, window) in buffer.windows(SQL_HEADER.len()).enumerate() {
+            if window == SQL_HEADER && database_ends_at(&mut file, pos + index as u64, len)? {
+                return Ok(Some(pos + index as u64));
+            }
+        }
+        pos += SCAN_SIZE as u64;
+    }
+    Ok(None)
+}
+
+// Return true if the database header at the given offset records a database
+// size that reaches exactly to the end of the file.
+fn database_ends_at(file: &mut File, offset: u64, len: u64) -> io::Result<bool> {
+    if offset + DB_HEADER_SIZE as u64 > len {
+        return Ok(false);
+    }
+    let mut header = [0; DB_HEADER_SIZE];
+    file.seek(SeekFrom::Start(offset))?;
+    file.read_exact(&mut header)?;
+    let page_size = match u16::from_be_bytes([header[16], header[17]]) {
+        1 => 65536,
+        size => size as u64,
+    };
+    let change_counter = &header[24..28];
+    let page_count = u32::from_be_bytes([header[28], header[29], header[30], header[31]]) as u64;
+    // the recorded size is only valid if written by the latest change
+    let valid_for = &header[92..96];
+    Ok(page_size >= 512
+        && page_size.is_power_of_two()
+        && page_count > 0
+        && change_counter == valid_for
+        && offset + page_size * page_count == len)
+}
+
+// Reads the portion of a local file that contains an embedded archive.
+struct FileSource {
+    file: File,
+    offset: u64,
+    size: u64,
+}
+
+impl RemoteSource for FileSource {
+    fn size(&self) -> io::Result<u64> {
+        Ok(self.size)
+    }
+
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> io::Result<usize> {
+        if offset >= self.size {
+            return Ok(0);
+        }
+        let len = buf.len().min((self.size - offset) as usize);
+        let buf = &mut buf[..len];
+        let mut count: usize = 0;
+        while count < len {
+            let pos = self.offset + offset + count as u64;
+            #[cfg(unix)]
+            let read = std::os::unix::fs::FileExt::read_at(&self.file, &mut buf[count..], pos)?;
+            #[cfg(windows)]
+            let read =
+                std::os::windows::fs::FileExt::seek_read(&self.file, &mut buf[count..], pos)?;
+            if read == 0 {
+                break;
+            }
+            count += read;
+        }
+        Ok(count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ExtractOptions, PackBuilder};
+    use std::fs;
+
+    #[test]
+    fn test_new_at_offset() -> Result<(), Error> {
+        let workspace = tempfile::tempdir()?;
+        let basepath = workspace.path().join("input");
+        fs::create_dir_all(basepath.join("sub"))?;
+        fs::write(basepath.join("hello.txt"), "hello world")?;
+        fs::write(basepath.join("sub/lorem.txt"), "lorem ipsum ".repeat(1000))?;
+        let packfile = workspace.path().join("pack.db3");
+        let mut builder = PackBuilder::new()?;
+        builder.add_dir_all(&basepath)?;
+        builder.finish(&packfile)?;
+        assert_eq!(find_embedded_pack(&packfile)?, Some(0));
+
+        // prefix with something resembling an executable that happens to
+        // contain the database header, as one linked with SQLite would
+        let mut prefix: Vec<u8> = b"\x7fELF".repeat(5000);
+        prefix.extend_from_slice(SQL_HEADER);
+        prefix.extend_from_slice(&[0; 200]);
+        let combined = workspace.path().join("extractor");
+        let mut data = prefix.clone();
+        data.extend(fs::read(&packfile)?);
+        fs::write(&combined, data)?;
+        assert!(!crate::is_pack_file(&combined)?);
+        assert_eq!(find_embedded_pack(&combined)?, Some(prefix.len() as u64));
+        assert_eq!(find_embedded_pack(basepath.join("hello.txt"))?, None);
+
+        let reader = PackReader::new_at_offset(&combined, prefix.len() as u64)?;
+        assert_eq!(reader.len()?, 4);
+        assert_eq!(reader.read_range("input/hello.txt", 6, 5)?, b"world");
+        let reader = PackReader::open_embedded(&combined)?;
+        let destination = workspace.path().join("output");
+        fs::create_dir(&destination)?;
+        let options = ExtractOptions {
+            destination: destination.clone(),
+            ..Default::default()
+        };
+        assert_eq!(reader.extract_subtree_with("input/sub", &options)?.files, 1);
+        assert_eq!(
+            fs::read(destination.join("input/sub/lorem.txt"))?,
+            fs::read(basepath.join("sub/lorem.txt"))?
+        );
+        assert!(matches!(
+            PackReader::open_embedded(basepath.join("hello.txt")),
+            Err(Error::NotPackFile)
+        ));
+        Ok(())
+    }
+}
@@ -0,0 +1,226 @@
+//
+// Copyright (c) 2024 Nathan Fiedler
+//
+use crate::{Error, PackBuilder, Warning, KIND_SYMLINK};
+use git2::{ObjectType, Repository, Tree};
+use std::path::{Path, PathBuf};
+
+// File modes of the entries in a git tree.
+const MODE_EXECUTABLE: i32 = 0o100755;
+const MODE_SYMLINK: i32 = 0o120000;
+
+impl PackBuilder {
+    ///
+    /// Add the files of the given revision of the git repository at `repo`,
+    /// rather than those in its working directory, to the directory in the
+    /// archive with the rowid `parent`, with `0` being the root of the
+    /// archive. The revision may be anything understood by `git rev-parse`,
+    /// such as `HEAD~3`, a branch or tag name, or a commit identifier.
+    ///
+    /// As with `git archive`, every entry is given the time of the commit,
+    /// files are recorded as executable or not, symbolic links are recorded
+    /// with their committed values, and submodules become empty directories.
+    /// Packing the same revision thus produces the same entries regardless of
+    /// the state of the working directory.
+    ///
+    /// Returns the number of files added.
+    ///
+    /// **Note:** Remember to call `finish()` when done adding content.
+    ///
+    pub fn add_git_revision<P: AsRef<Path>>(
+        &mut self,
+        repo: P,
+        revision: &str,
+        parent: i64,
+    ) -> Result<u64, Error> {
+        let _span = tracing::info_span!("add_git_revision", revision).entered();
+        let repo = Repository::open(repo.as_ref())?;
+        let commit = repo.revparse_single(revision)?.peel_to_commit()?;
+        tracing::info!(commit = %commit.id(), "adding tree of commit");
+        let mtime = commit.time().seconds();
+        let tree = commit.tree()?;
+        self.add_git_tree(&repo, &tree, parent, "", mtime)
+    }
+
+    // Add the entries of the tree to the directory with the rowid `parent`,
+    // whose path within the tree is `dirpath`, returning the number of files
+    // added.
+    fn add_git_tree(
+        &mut self,
+        repo: &Repository,
+        tree: &Tree,
+        parent: i64,
+        dirpath: &str,
+        mtime: i64,
+    ) -> Result<u64, Error> {
+        let mut file_count: u64 = 0;
+        for entry in tree.iter() {
+            self.count_entry()?;
+            let name = String::from_utf8_lossy(entry.name_bytes()).into_owned();
+            let path = format!("{}{}", dirpath, name);
+            if matches!(name.as_str(), "" | "." | "..") || name.contains('/') {
+                return Err(Error::PathEscapesRoot(PathBuf::from(path)));
+            }
+            if entry.name().is_err() {
+                self.warn(Warning::LossyFileName(PathBuf::from(&path)));
+            }
+            if self.find_child(&name, parent)?.is_some() {
+                return Err(Error::PathCollision(path));
+            }
+            match entry.kind() {
+                Some(ObjectType::Tree) => {
+                    let dir_id = self.find_or_add_directory(&name, parent, Path::new(&path))?;
+                    self.record_attrs(dir_id, Some(mtime), Some(0o755))?;
+                    let subtree = repo.find_tree(entry.id())?;
+                    let subpath = format!("{}/", path);
+                    file_count += self.add_git_tree(repo, &subtree, dir_id, &subpath, mtime)?;
+                }
+                Some(ObjectType::Blob) if entry.filemode() == MODE_SYMLINK => {
+                    let blob = repo.find_blob(entry.id())?;
+                    self.conn.execute(
+                        "INSERT INTO item (parent, kind, name, target) VALUES (?1, ?2, ?3, ?4)",
+                        (&parent, KIND_SYMLINK, &name, blob.content()),
+                    )?;
+                    let item_id = self.conn.last_insert_rowid();
+                    self.record_attrs(item_id, Some(mtime), None)?;
+                }
+                Some(ObjectType::Blob) => {
+                    let blob = repo.find_blob(entry.id())?;
+                    let item_id = self.add_stream(&name, parent, blob.content())?;
+                    let mode = if entry.filemode() == MODE_EXECUTABLE {
+                        0o755
+                    } else {
+                        0o644
+                    };
+                    self.record_attrs(item_id, Some(mtime), Some(mode))?;
+                    file_count += 1;
+                }
+                Some(ObjectType::Commit) => {
+                    // the commit of a submodule is in another repository
+                    let dir_id = self.find_or_add_directory(&name, parent, Path::new(&path))?;
+                    self.record_attrs(dir_id, Some(mtime), Some(0o755))?;
+                }
+                kind => {
+                    tracing::debug!(path, ?kind, "skipping unsupported entry");
+                }
+            }
+        }
+        Ok(file_count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{PackReader, KIND_DIRECTORY, KIND_FILE};
+    use std::fs;
+
+    // Commit the given files, as (path, content, mode) tuples, to the
+    // repository, replacing all of the files of the previous commit.
+    fn commit_files(repo: &Repository, files: &[(&str, &str, i32)], message: &str) -> git2::Oid {
+        let mut index = repo.index().unwrap();
+        index.clear().unwrap();
+        for (path, content, mode) in files {
+            let oid = repo.blob(content.as_bytes()).unwrap();
+            let entry = git2::IndexEntry {
+                ctime: git2::IndexTime::new(0, 0),
+                mtime: git2::IndexTime::new(0, 0),
+                dev: 0,
+                ino: 0,
+                mode: *mode as u32,
+                uid: 0,
+                gid: 0,
+                file_size: content.len() as u32,
+                id: oid,
+                flags: 0,
+                flags_extended: 0,
+                path: path.as_bytes().to_vec(),
+            };
+            index.add(&entry).unwrap();
+        }
+        let tree_id = index.write_tree().unwrap();
+        let tree = repo.find_tree(tree_id).unwrap();
+        let signature = git2::Signature::new(
+            "Test",
+            "test@example.com",
+            &git2::Time::new(1700000000 + files.len() as i64, 0),
+        )
+        .unwrap();
+        let parents = match repo.head() {
+            Ok(head) => vec![head.peel_to_commit().unwrap()],
+            Err(_) => vec![],
+        };
+        let parents: Vec<&git2::Commit> = parents.iter().collect();
+        repo.commit(
+            Some("HEAD"),
+            &signature,
+            &signature,
+            message,
+            &tree,
+            &parents,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_add_git_revision() -> Result<(), Error> {
+        let workspace = tempfile::tempdir()?;
+        let repo_path = workspace.path().join("repo");
+        let repo = Repository::init(&repo_path)?;
+        commit_files(
+            &repo,
+            &[
+                ("README.md", "first", 0o100644),
+                ("bin/run.sh", "#!/bin/sh", MODE_EXECUTABLE),
+                ("docs/link", "../README.md", MODE_SYMLINK),
+            ],
+            "first",
+        );
+        commit_files(&repo, &[("README.md", "second", 0o100644)], "second");
+        // the working directory is not consulted
+        fs::write(repo_path.join("untracked.txt"), "untracked")?;
+
+        let packfile = workspace.path().join("pack.db3");
+        let mut builder = PackBuilder::new()?;
+        let parent = builder.add_prefix("snapshot")?;
+        let file_count = builder.add_git_revision(&repo_path, "HEAD~1", parent)?;
+        assert_eq!(file_count, 2);
+        assert!(matches!(
+            builder.add_git_revision(&repo_path, "HEAD", parent),
+            Err(Error::PathCollision(_))
+        ));
+        assert!(matches!(
+            builder.add_git_revision(&repo_path, "no-such-branch", 0),
+            Err(Error::GitError(_))
+        ));
+        builder.finish(&packfile)?;
+
+        let reader = PackReader::new(&packfile)?;
+        let entries = reader.export_entries()?;
+        let mut paths: Vec<(&str, i8)> =
+            entries.iter().map(|e| (e.path.as_str(), e.kind)).collect();
+        paths.sort();
+        assert_eq!(
+            paths,
+            vec![
+                ("snapshot/", KIND_DIRECTORY),
+                ("snapshot/README.md", KIND_FILE),
+                ("snapshot/bin/", KIND_DIRECTORY),
+                ("snapshot/bin/run.sh", KIND_FILE),
+                ("snapshot/docs/", KIND_DIRECTORY),
+                ("snapshot/docs/link", KIND_SYMLINK),
+            ]
+        );
+        let mut content: Vec<u8> = vec![];
+        reader.read_path("snapshot/README.md", &mut content)?;
+        assert_eq!(content, b"first");
+        let link = entries.iter().find(|e| e.kind == KIND_SYMLINK).unwrap();
+        content.clear();
+        reader.read_content(link.id, &mut content)?;
+        assert_eq!(content, b"../README.md");
+        let run = entries.iter().find(|e| e.path.ends_with("run.sh")).unwrap();
+        assert_eq!(run.mode, Some(0o755));
+        assert_eq!(run.mtime, Some(1700000003));
+        Ok(())
+    }
+}
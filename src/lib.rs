@@ -3,8 +3,82 @@
 //
 use rusqlite::Connection;
 use std::fs;
-use std::io::Read;
+use std::io::{self, Read};
 use std::path::{Component, Path, PathBuf};
+use std::time::Duration;
+
+mod archive;
+#[cfg(feature = "async")]
+mod asynchronous;
+mod audit;
+mod bench;
+mod builder;
+mod catalog;
+mod check;
+mod codec;
+mod crypto;
+mod digest;
+// these rely on the files and the SQLite VFS of the operating system
+#[cfg(not(target_family = "wasm"))]
+mod embedded;
+#[cfg(feature = "git")]
+mod git;
+mod macos;
+mod manifest;
+mod merge;
+mod migrate;
+mod mime;
+mod oneshot;
+mod owner;
+mod pragmas;
+mod reader;
+#[cfg(all(feature = "remote", not(target_family = "wasm")))]
+mod remote;
+mod repack;
+mod repair;
+mod sink;
+mod source;
+mod tarfile;
+#[cfg(not(target_family = "wasm"))]
+mod vfs;
+mod zipfile;
+
+pub use archive::{EntryMetadata, ReadArchive};
+#[cfg(feature = "async")]
+pub use asynchronous::{AsyncPackBuilder, AsyncPackReader};
+pub use audit::AuditRecord;
+pub use bench::{benchmark, BenchOptions, BenchResult};
+pub use builder::{
+    CancelToken, CreateReport, EntryMetadataPatch, ErrorPolicy, PackBuilder, SortOrder, Timings,
+    UpdateSummary,
+};
+pub use catalog::{Catalog, CatalogEntry};
+pub use check::Violation;
+pub use codec::{Codec, StoredCodec, WriteFinish, ZstdCodec};
+pub use digest::DigestAlgorithm;
+#[cfg(not(target_family = "wasm"))]
+pub use embedded::find_embedded_pack;
+pub use manifest::{verify_manifest, Discrepancy, ManifestEntry};
+pub use merge::MergePolicy;
+pub use migrate::{migrate, MigrateReport};
+pub use oneshot::{create_archive, extract_archive, CreateOptions};
+pub use owner::OwnerMap;
+pub use pragmas::PerformanceProfile;
+pub use reader::{
+    ArchiveInfo, BundleSpace, CaseCollisions, DuplicateSet, Entry, ExtractLimits, ExtractOptions,
+    ExtractReport, LinkRewrite, ListOptions, ListOrder, PackReader, SpaceReport, TreeNode,
+    TypeReport, TypeStats,
+};
+#[cfg(all(feature = "remote", not(target_family = "wasm")))]
+pub use remote::HttpSource;
+pub use repack::{repack, repack_bundles, repack_subtree};
+pub use repair::{repair_archive, RepairReport};
+pub use sink::{FsSink, MemorySink, Sink, SinkEntry, SinkMetadata};
+pub use source::{FsSource, MemorySource, Source, SourceKind, SourceMetadata};
+#[cfg(all(feature = "remote", not(target_family = "wasm")))]
+pub use vfs::RemoteSource;
+// the version of SQLite bindings used by PackReader::query()
+pub use rusqlite;
 
 ///
 /// This type represents all possible errors that can occur within this crate.
@@ -32,10 +106,553 @@ pub enum Error {
     /// Thread pool is shutting down
     #[error("thread pool is shutting down")]
     ThreadPoolShutdown,
+    /// The path would resolve to a location outside of the extraction root.
+    #[error("path escapes the extraction root: {0:?}")]
+    PathEscapesRoot(PathBuf),
+    /// The content bundle with the given rowid failed checksum verification.
+    #[error("content bundle {0} is corrupt")]
+    CorruptBundle(i64),
+    /// The archive records an entry of a kind this version does not know.
+    #[error("unknown item kind: {0}")]
+    UnknownItemKind(i8),
+    /// The given wildcard pattern is not valid.
+    #[error("invalid pattern: {0}")]
+    InvalidPattern(String),
+    /// An entry with the given path was added more than once, such as from
+    /// several inputs or from more than one archive.
+    #[error("path already exists in the archive: {0}")]
+    PathCollision(String),
+    /// The archive does not contain a file with the given path.
+    #[error("entry not found: {0}")]
+    EntryNotFound(String),
+    /// No codec is registered with the given identifier.
+    #[error("unknown codec: {0}")]
+    UnknownCodec(String),
+    /// The digest algorithm with the given identifier is not supported.
+    #[error("unknown digest algorithm: {0}")]
+    UnknownDigest(String),
+    /// The archive already records checksums computed with another digest
+    /// algorithm, given as (recorded, requested).
+    #[error("archive uses the {0} digest algorithm, not {1}")]
+    DigestMismatch(String, String),
+    /// The archive uses a format feature that this version does not support,
+    /// as named by `PackReader::required_features()`, such as an archive
+    /// written by a newer version.
+    #[error("unsupported format feature: {0}")]
+    UnsupportedFormatFeature(String),
+    /// The content of the archive is encrypted and no password was given.
+    #[error("archive content is encrypted, a password is required")]
+    PasswordRequired,
+    /// The password does not match the one with which the archive was
+    /// encrypted.
+    #[error("incorrect password")]
+    WrongPassword,
+    /// The content of the archives, or of the bundles within an archive,
+    /// would not all be encrypted with the same key.
+    #[error("encryption mismatch: {0}")]
+    EncryptionMismatch(String),
+    /// The text form of an `OwnerMap` could not be parsed.
+    #[error("invalid owner map: {0}")]
+    InvalidOwnerMap(String),
+    /// A record in the audit table of the archive could not be read, such as
+    /// when its list of paths is malformed.
+    #[error("invalid audit record: {0}")]
+    InvalidAudit(String),
+    /// Error occurred while reading or writing a ZIP file.
+    #[error("ZIP error: {0}")]
+    ZipError(#[from] zip::result::ZipError),
+    /// The path cannot be safely used on this system.
+    #[error("unsafe path: {0}")]
+    UnsafePath(#[from] SanitizeError),
+    /// Extracting the archive would exceed one of the `ExtractLimits`.
+    #[error("extraction limit exceeded: {0}")]
+    LimitExceeded(String),
+    /// The operation was cancelled using a `CancelToken`.
+    #[error("operation was cancelled")]
+    Cancelled,
+    /// The archive records only the metadata of the files, not their content.
+    #[error("archive does not contain file content")]
+    MetadataOnly,
+    /// The checksum of the extracted file does not match the one recorded in
+    /// the archive.
+    #[error("checksum mismatch for extracted file: {0:?}")]
+    ChecksumMismatch(PathBuf),
+    /// The SQL statement given to `PackReader::query()` would modify the
+    /// archive.
+    #[error("statement is not read-only: {0}")]
+    NotReadOnly(String),
+    /// Error occurred while reading a git repository.
+    #[cfg(feature = "git")]
+    #[error("git error: {0}")]
+    GitError(#[from] git2::Error),
+    /// Error occurred while making an HTTP request for a remote archive.
+    #[cfg(feature = "remote")]
+    #[error("HTTP error: {0}")]
+    HttpError(#[from] ureq::Error),
+    /// A task running on the blocking thread pool failed to complete.
+    #[cfg(feature = "async")]
+    #[error("background task failed: {0}")]
+    TaskFailed(#[from] tokio::task::JoinError),
+}
+
+///
+/// Reasons that `sanitize_path()` may reject a path component.
+///
+#[derive(thiserror::Error, Clone, Debug, PartialEq)]
+pub enum SanitizeError {
+    /// The name refers to a device on Windows (e.g. `CON` or `nul.txt`).
+    #[error("reserved device name: {0:?}")]
+    ReservedName(String),
+    /// The name ends with a dot or space, which Windows silently removes.
+    #[error("name ends with a dot or space: {0:?}")]
+    TrailingDotOrSpace(String),
+    /// The name refers to an alternate data stream (e.g. `file.txt:stream`).
+    #[error("alternate data stream: {0:?}")]
+    AlternateStream(String),
+}
+
+///
+/// Non-fatal events that occur while creating or extracting an archive, where
+/// an entry was handled differently than might be expected. These are
+/// collected by the `PackBuilder` and `PackReader`, and may also be received
+/// as they happen via `set_on_warning()`.
+///
+#[derive(thiserror::Error, Clone, Debug, PartialEq, Eq)]
+pub enum Warning {
+    /// A named pipe, device node, or socket was left out.
+    #[error("skipped special file {}", .0.display())]
+    SkippedSpecialFile(PathBuf),
+    /// The name of the entry is not valid UTF-8 and was recorded with the
+    /// invalid sequences replaced.
+    #[error("invalid UTF-8 in file name {}", .0.display())]
+    LossyFileName(PathBuf),
+    /// The symbolic link refers to something that does not exist; the link
+    /// itself is recorded as usual.
+    #[error("broken symbolic link {}", .0.display())]
+    BrokenSymlink(PathBuf),
+    /// The attributes of the entry, such as its size and modification time,
+    /// could not be read and were not recorded.
+    #[error("metadata unavailable for {}", .0.display())]
+    MetadataUnavailable(PathBuf),
+    /// The recorded owner or group could not be given to the extracted
+    /// entry, typically for lack of privileges.
+    #[error("cannot change ownership of {}", .0.display())]
+    OwnershipNotRestored(PathBuf),
+    /// The directory is at the maximum depth and its contents were left out.
+    #[error("contents of {} left out, beyond the maximum depth", .0.display())]
+    DepthLimitReached(PathBuf),
+    /// The directory is on a different file system than the input and its
+    /// contents were left out.
+    #[error("contents of {} left out, on another file system", .0.display())]
+    FileSystemBoundary(PathBuf),
+    /// The directory was already visited, typically by way of a bind mount,
+    /// and its contents were left out rather than walking in circles.
+    #[error("contents of {} left out, directory was already visited", .0.display())]
+    DirectoryLoop(PathBuf),
+    /// The checksum of the extracted file does not match the one recorded in
+    /// the archive.
+    #[error("checksum mismatch for extracted file {}", .0.display())]
+    ChecksumMismatch(PathBuf),
+    /// The path of the entry differs only in case from that of another, and
+    /// the entry was extracted under the second name instead.
+    #[error("renamed {} to {} to avoid a case collision", .0.display(), .1.display())]
+    CaseCollision(PathBuf, PathBuf),
+    /// The file is the archive being written, or one of the files kept
+    /// alongside it while writing, and was left out.
+    #[error("skipped the archive being written, {}", .0.display())]
+    SkippedOutput(PathBuf),
+}
+
+// Function that is invoked with each warning as it occurs.
+pub(crate) type WarningCallback = dyn Fn(&Warning) + Send;
+
+/// Value of `Entry::kind` for a regular file.
+pub const KIND_FILE: i8 = 0;
+/// Value of `Entry::kind` for a directory.
+pub const KIND_DIRECTORY: i8 = 1;
+/// Value of `Entry::kind` for a symbolic link.
+pub const KIND_SYMLINK: i8 = 2;
+/// Value of `Entry::kind` for a named pipe (FIFO).
+pub const KIND_FIFO: i8 = 3;
+/// Value of `Entry::kind` for a character device node.
+pub const KIND_CHAR_DEVICE: i8 = 4;
+/// Value of `Entry::kind` for a block device node.
+pub const KIND_BLOCK_DEVICE: i8 = 5;
+
+///
+/// Kind of an entry in the archive, as recorded in the `kind` column of the
+/// `item` table. Converts to and from the `KIND_*` constants.
+///
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ItemKind {
+    /// Regular file, whose content is stored in the bundles.
+    File,
+    /// Directory, which may contain other entries.
+    Directory,
+    /// Symbolic link, whose value is stored with the entry.
+    Symlink,
+    /// Named pipe (FIFO), which has no content.
+    Fifo,
+    /// Character device node, with its major and minor numbers.
+    CharDevice,
+    /// Block device node, with its major and minor numbers.
+    BlockDevice,
+}
+
+impl From<ItemKind> for i8 {
+    fn from(kind: ItemKind) -> Self {
+        match kind {
+            ItemKind::File => KIND_FILE,
+            ItemKind::Directory => KIND_DIRECTORY,
+            ItemKind::Symlink => KIND_SYMLINK,
+            ItemKind::Fifo => KIND_FIFO,
+            ItemKind::CharDevice => KIND_CHAR_DEVICE,
+            ItemKind::BlockDevice => KIND_BLOCK_DEVICE,
+        }
+    }
+}
+
+impl TryFrom<i8> for ItemKind {
+    type Error = Error;
+
+    fn try_from(value: i8) -> Result<Self, Self::Error> {
+        match value {
+            KIND_FILE => Ok(ItemKind::File),
+            KIND_DIRECTORY => Ok(ItemKind::Directory),
+            KIND_SYMLINK => Ok(ItemKind::Symlink),
+            KIND_FIFO => Ok(ItemKind::Fifo),
+            KIND_CHAR_DEVICE => Ok(ItemKind::CharDevice),
+            KIND_BLOCK_DEVICE => Ok(ItemKind::BlockDevice),
+            _ => Err(Error::UnknownItemKind(value)),
+        }
+    }
+}
+
+impl rusqlite::types::ToSql for ItemKind {
+    fn to_sql(&self) -> rusqlite::Result<rusqlite::types::ToSqlOutput<'_>> {
+        Ok(i8::from(*self).into())
+    }
+}
+
+impl rusqlite::types::FromSql for ItemKind {
+    fn column_result(value: rusqlite::types::ValueRef<'_>) -> rusqlite::types::FromSqlResult<Self> {
+        let raw = i8::column_result(value)?;
+        ItemKind::try_from(raw).map_err(|_| rusqlite::types::FromSqlError::OutOfRange(raw.into()))
+    }
+}
+// target size of the uncompressed content bundles
+const BUNDLE_SIZE: u64 = 16777216;
+// value of the `layout` entry in `archive_meta` for content-addressed archives
+const LAYOUT_CONTENT_ADDRESSED: &str = "content-addressed";
+// value of the `layout` entry in `archive_meta` for archives without content
+const LAYOUT_METADATA_ONLY: &str = "metadata-only";
+// value of `itemcontent.content` for files whose content is not stored
+const NO_CONTENT: i64 = 0;
+// value of the `application_id` pragma that identifies pack files ("PACK")
+const APPLICATION_ID: u32 = 0x5041434b;
+// version of the archive format, recorded as the `user_version` pragma
+const FORMAT_VERSION: u32 = 1;
+
+// format features that this version supports, as named by
+// required_features()
+const SUPPORTED_FEATURES: &[&str] = &[
+    "digest=blake3",
+    "digest=sha256",
+    "digest=xxh3-128",
+    "encryption=content",
+    "encryption=content-v2",
+    "layout=content-addressed",
+    "layout=metadata-only",
+];
+
+// columns without which an archive cannot be read, for those tables that
+// are present
+const CORE_COLUMNS: &[(&str, &[&str])] = &[
+    ("item", &["id", "parent", "kind", "name"]),
+    ("content", &["id", "value"]),
+    (
+        "itemcontent",
+        &["item", "itempos", "content", "contentpos", "size"],
+    ),
+];
+
+///
+/// Return the names of the format features that this version supports, in
+/// the form returned by `PackReader::required_features()`.
+///
+pub fn supported_features() -> &'static [&'static str] {
+    SUPPORTED_FEATURES
+}
+
+//
+// Return the names of the format features that are needed to read the
+// archive: each of the `layout`, `digest`, and `encryption` entries in the
+// `archive_meta` table as `name=value`, along with the names listed in the
+// `requires` entry, which is how newer versions mark features that older
+// versions cannot handle.
+//
+pub(crate) fn required_features(conn: &Connection) -> Result<Vec<String>, Error> {
+    let mut features: Vec<String> = vec![];
+    if !has_table(conn, "archive_meta")? {
+        return Ok(features);
+    }
+    let mut stmt = conn.prepare(
+        "SELECT name, value FROM archive_meta
+            WHERE name IN ('layout', 'digest', 'encryption', 'requires')",
+    )?;
+    let rows = stmt
+        .query_map([], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, Option<String>>(1)?))
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+    for (name, value) in rows {
+        let value = value.unwrap_or_default();
+        if name == "requires" {
+            features.extend(
+                value
+                    .split(',')
+                    .map(|f| f.trim())
+                    .filter(|f| !f.is_empty())
+                    .map(|f| f.to_owned()),
+            );
+        } else {
+            features.push(format!("{}={}", name, value));
+        }
+    }
+    features.sort();
+    features.dedup();
+    Ok(features)
+}
+
+//
+// Ensure that the archive has the columns needed to read it and requires no
+// format features beyond those supported, such that an archive written by a
+// newer version fails with a clear error rather than partway through.
+//
+pub(crate) fn check_features(conn: &Connection) -> Result<(), Error> {
+    for (table, columns) in CORE_COLUMNS {
+        if has_table(conn, table)? {
+            for column in columns.iter() {
+                if !has_column(conn, table, column)? {
+                    let feature = format!("schema without {}.{}", table, column);
+                    return Err(Error::UnsupportedFormatFeature(feature));
+                }
+            }
+        }
+    }
+    for feature in required_features(conn)? {
+        if !SUPPORTED_FEATURES.contains(&feature.as_str()) {
+            tracing::warn!(feature, "archive requires an unsupported feature");
+            return Err(Error::UnsupportedFormatFeature(feature));
+        }
+    }
+    Ok(())
+}
+
+//
+// Create the database tables if they do not exist.
+//
+pub(crate) fn create_tables(conn: &Connection) -> rusqlite::Result<()> {
+    // N.B. columns added after the table was first introduced must also be
+    // added to older archives via add_column_if_missing() below
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS item (
+            id INTEGER PRIMARY KEY,
+            parent INTEGER,
+            kind INTEGER,
+            name TEXT NOT NULL,
+            target BLOB
+        )",
+        (),
+    )?;
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS content (
+            id INTEGER PRIMARY KEY,
+            value BLOB,
+            rawsize INTEGER,
+            checksum BLOB,
+            codec TEXT,
+            entries INTEGER
+        )",
+        (),
+    )?;
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS itemcontent (
+            id INTEGER PRIMARY KEY,
+            item INTEGER,
+            itempos INTEGER,
+            content INTEGER,
+            contentpos INTEGER,
+            size INTEGER
+        )",
+        (),
+    )?;
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS attrs (
+            item INTEGER PRIMARY KEY,
+            mtime INTEGER,
+            checksum BLOB,
+            mode INTEGER,
+            devmajor INTEGER,
+            devminor INTEGER,
+            mime TEXT,
+            uid INTEGER,
+            gid INTEGER,
+            uname TEXT,
+            gname TEXT
+        )",
+        (),
+    )?;
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS archive_meta (
+            name TEXT PRIMARY KEY,
+            value TEXT
+        )",
+        (),
+    )?;
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS chunk (
+            digest BLOB PRIMARY KEY,
+            item INTEGER
+        )",
+        (),
+    )?;
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS xattr (
+            item INTEGER,
+            name TEXT NOT NULL,
+            value BLOB,
+            PRIMARY KEY (item, name)
+        )",
+        (),
+    )?;
+    // changes made after the archive was written, which are never altered
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS audit (
+            id INTEGER PRIMARY KEY,
+            time TEXT NOT NULL,
+            operation TEXT NOT NULL,
+            paths TEXT,
+            tool TEXT
+        )",
+        (),
+    )?;
+    conn.execute_batch(
+        "CREATE TRIGGER IF NOT EXISTS audit_no_update BEFORE UPDATE ON audit
+            BEGIN SELECT RAISE(ABORT, 'audit log is append-only'); END;
+        CREATE TRIGGER IF NOT EXISTS audit_no_delete BEFORE DELETE ON audit
+            BEGIN SELECT RAISE(ABORT, 'audit log is append-only'); END;",
+    )?;
+    add_column_if_missing(conn, "item", "target", "BLOB")?;
+    add_column_if_missing(conn, "content", "rawsize", "INTEGER")?;
+    add_column_if_missing(conn, "content", "checksum", "BLOB")?;
+    add_column_if_missing(conn, "content", "codec", "TEXT")?;
+    add_column_if_missing(conn, "content", "entries", "INTEGER")?;
+    add_column_if_missing(conn, "attrs", "mode", "INTEGER")?;
+    add_column_if_missing(conn, "attrs", "devmajor", "INTEGER")?;
+    add_column_if_missing(conn, "attrs", "devminor", "INTEGER")?;
+    add_column_if_missing(conn, "attrs", "mime", "TEXT")?;
+    add_column_if_missing(conn, "attrs", "uid", "INTEGER")?;
+    add_column_if_missing(conn, "attrs", "gid", "INTEGER")?;
+    add_column_if_missing(conn, "attrs", "uname", "TEXT")?;
+    add_column_if_missing(conn, "attrs", "gname", "TEXT")?;
+    // directory listings look up the children of an item sorted by name
+    conn.execute(
+        "CREATE INDEX IF NOT EXISTS item_parent ON item (parent, name)",
+        (),
+    )?;
+    conn.pragma_update(None, "application_id", APPLICATION_ID)?;
+    // leave alone the version of an archive written by a newer release
+    let version: u32 = conn.pragma_query_value(None, "user_version", |row| row.get(0))?;
+    if version < FORMAT_VERSION {
+        conn.pragma_update(None, "user_version", FORMAT_VERSION)?;
+    }
+    Ok(())
+}
+
+//
+// Format the time given in seconds since the Unix epoch in ISO 8601 form.
+//
+pub(crate) fn format_timestamp(epoch: i64) -> String {
+    let (year, month, day) = zipfile::civil_from_days(epoch.div_euclid(86400));
+    let seconds = epoch.rem_euclid(86400);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        seconds / 3600,
+        seconds % 3600 / 60,
+        seconds % 60
+    )
+}
+
+//
+// Report each SQL statement and the time it took as a trace event, for
+// diagnosing slow operations on large archives.
+//
+pub(crate) fn trace_statement(sql: &str, elapsed: Duration) {
+    tracing::trace!(
+        target: "pack_rs::sql",
+        elapsed_us = elapsed.as_micros() as u64,
+        "{}",
+        sql
+    );
+}
+
+//
+// Return the canonical form of the path of a file that may not exist yet, such
+// as an archive about to be written, by resolving its parent directory.
+//
+pub(crate) fn canonical_file_path(path: &Path) -> io::Result<PathBuf> {
+    let name = path
+        .file_name()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "path has no file name"))?;
+    let parent = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    Ok(parent.canonicalize()?.join(name))
+}
+
+//
+// Return true if the named table has a column with the given name.
+//
+pub(crate) fn has_column(conn: &Connection, table: &str, column: &str) -> rusqlite::Result<bool> {
+    let mut stmt = conn.prepare("SELECT 1 FROM pragma_table_info(?1) WHERE name = ?2")?;
+    stmt.exists([table, column])
+}
+
+//
+// Return true if the database has a table with the given name.
+//
+pub(crate) fn has_table(conn: &Connection, table: &str) -> rusqlite::Result<bool> {
+    let mut stmt =
+        conn.prepare("SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = ?1")?;
+    stmt.exists([table])
+}
+
+//
+// Add the column to the table if it is not already there, as would be the case
+// for an archive created by an earlier version.
+//
+pub(crate) fn add_column_if_missing(
+    conn: &Connection,
+    table: &str,
+    column: &str,
+    decl: &str,
+) -> rusqlite::Result<()> {
+    if !has_column(conn, table, column)? {
+        conn.execute(
+            &format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, decl),
+            (),
+        )?;
+    }
+    Ok(())
 }
 
 // Expected SQLite database header: "SQLite format 3\0"
-static SQL_HEADER: &'static [u8] = &[
+static SQL_HEADER: &[u8] = &[
     0x53, 0x51, 0x4c, 0x69, 0x74, 0x65, 0x20, 0x66, 0x6f, 0x72, 0x6d, 0x61, 0x74, 0x20, 0x33, 0x00,
 ];
 
@@ -72,16 +689,171 @@ pub fn is_pack_file<P: AsRef<Path>>(path: P) -> Result<bool, Error> {
 /// refer to the parent directory will be stripped ("foo/../bar" will become
 /// "foo/bar").
 ///
+/// On Windows, components that name a reserved device (`CON`, `NUL`, `COM1`,
+/// etc.), end with a dot or space, or refer to an alternate data stream are
+/// rejected with `Error::UnsafePath`, as such names are either dangerous or
+/// cannot easily be removed.
+///
 pub fn sanitize_path<P: AsRef<Path>>(dirty: P) -> Result<PathBuf, Error> {
     let components = dirty.as_ref().components();
     let allowed = components.filter(|c| matches!(c, Component::Normal(_)));
     let mut path = PathBuf::new();
     for component in allowed {
+        if cfg!(target_family = "windows") {
+            check_windows_name(&component.as_os_str().to_string_lossy())?;
+        }
         path = path.join(component);
     }
     Ok(path)
 }
 
+// Device names that Windows reserves in every directory.
+const RESERVED_NAMES: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL", "CONIN$", "CONOUT$", "COM0", "COM1", "COM2", "COM3", "COM4",
+    "COM5", "COM6", "COM7", "COM8", "COM9", "COM¹", "COM²", "COM³", "LPT0", "LPT1", "LPT2", "LPT3",
+    "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9", "LPT¹", "LPT²", "LPT³",
+];
+
+//
+// Verify that the path component is a safe file name on Windows.
+//
+fn check_windows_name(name: &str) -> Result<(), SanitizeError> {
+    if name.contains(':') {
+        return Err(SanitizeError::AlternateStream(name.to_owned()));
+    }
+    if name.ends_with('.') || name.ends_with(' ') {
+        return Err(SanitizeError::TrailingDotOrSpace(name.to_owned()));
+    }
+    // the device names are reserved regardless of any extension, and Windows
+    // ignores trailing spaces before the extension
+    let stem = name.split('.').next().unwrap_or(name).trim_end_matches(' ');
+    if RESERVED_NAMES.iter().any(|r| r.eq_ignore_ascii_case(stem)) {
+        return Err(SanitizeError::ReservedName(name.to_owned()));
+    }
+    Ok(())
+}
+
+///
+/// Return the location within `root` at which the (sanitized) `relpath` would
+/// be written, after verifying that the existing directories along that path
+/// do not resolve to a location outside of `root`. This guards against an
+/// archive that contains a symbolic link pointing elsewhere, followed by
+/// entries that would be written "through" that link.
+///
+pub fn resolve_beneath<P: AsRef<Path>, Q: AsRef<Path>>(
+    root: P,
+    relpath: Q,
+) -> Result<PathBuf, Error> {
+    let root = fs::canonicalize(root.as_ref())?;
+    let relpath = sanitize_path(relpath)?;
+    let fullpath = root.join(&relpath);
+    if let Some(parent) = fullpath.parent() {
+        // find the deepest directory that already exists and resolve any
+        // symbolic links along the way to that directory
+        let mut existing = parent;
+        while fs::symlink_metadata(existing).is_err() {
+            match existing.parent() {
+                Some(p) => existing = p,
+                None => break,
+            }
+        }
+        let resolved = fs::canonicalize(existing)?;
+        if !resolved.starts_with(&root) {
+            return Err(Error::PathEscapesRoot(relpath));
+        }
+    }
+    Ok(fullpath)
+}
+
+///
+/// Open the file at `relpath` beneath `root` for writing, creating it if it
+/// does not exist, and without truncating it. The file will not be opened if
+/// the path would resolve to a location outside of `root`, nor if the file
+/// itself is a symbolic link.
+///
+/// On Linux the `openat2()` system call with `RESOLVE_BENEATH` is used when
+/// available, with the kernel enforcing the restriction. Elsewhere the path is
+/// resolved and verified before opening with `O_NOFOLLOW` (where supported).
+///
+pub fn open_beneath<P: AsRef<Path>, Q: AsRef<Path>>(
+    root: P,
+    relpath: Q,
+) -> Result<fs::File, Error> {
+    let fullpath = resolve_beneath(root.as_ref(), relpath.as_ref())?;
+    #[cfg(target_os = "linux")]
+    {
+        let relpath = sanitize_path(relpath.as_ref())?;
+        if let Some(file) = openat2_beneath(root.as_ref(), &relpath)? {
+            return Ok(file);
+        }
+    }
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(false);
+    #[cfg(target_family = "unix")]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.custom_flags(libc::O_NOFOLLOW);
+    }
+    #[cfg(not(target_family = "unix"))]
+    {
+        if let Ok(metadata) = fs::symlink_metadata(&fullpath) {
+            if metadata.is_symlink() {
+                return Err(Error::PathEscapesRoot(sanitize_path(relpath)?));
+            }
+        }
+    }
+    match options.open(&fullpath) {
+        Ok(file) => Ok(file),
+        #[cfg(target_family = "unix")]
+        Err(err) if err.raw_os_error() == Some(libc::ELOOP) => {
+            Err(Error::PathEscapesRoot(sanitize_path(relpath)?))
+        }
+        Err(err) => Err(err.into()),
+    }
+}
+
+//
+// Open the file using openat2() with RESOLVE_BENEATH, returning `None` if the
+// system call is not available (older kernels, or blocked by seccomp).
+//
+#[cfg(target_os = "linux")]
+fn openat2_beneath(root: &Path, relpath: &Path) -> Result<Option<fs::File>, Error> {
+    use std::ffi::CString;
+    use std::os::fd::{AsRawFd, FromRawFd};
+    use std::os::unix::ffi::OsStrExt;
+    let dir = fs::File::open(root)?;
+    let Ok(cpath) = CString::new(relpath.as_os_str().as_bytes()) else {
+        return Err(Error::PathEscapesRoot(relpath.to_path_buf()));
+    };
+    // SAFETY: open_how is a plain C struct for which all zeroes is valid
+    let mut how: libc::open_how = unsafe { std::mem::zeroed() };
+    how.flags = (libc::O_WRONLY | libc::O_CREAT | libc::O_NOFOLLOW | libc::O_CLOEXEC) as u64;
+    how.mode = 0o666;
+    how.resolve = libc::RESOLVE_BENEATH;
+    // SAFETY: the path and open_how are valid for the duration of the call
+    let fd = unsafe {
+        libc::syscall(
+            libc::SYS_openat2,
+            dir.as_raw_fd(),
+            cpath.as_ptr(),
+            &how as *const libc::open_how,
+            std::mem::size_of::<libc::open_how>(),
+        )
+    };
+    if fd < 0 {
+        let err = std::io::Error::last_os_error();
+        return match err.raw_os_error() {
+            Some(libc::ENOSYS) | Some(libc::EPERM) => Ok(None),
+            Some(libc::EXDEV) | Some(libc::ELOOP) => {
+                Err(Error::PathEscapesRoot(relpath.to_path_buf()))
+            }
+            _ => Err(err.into()),
+        };
+    }
+    // SAFETY: the system call returned a new file descriptor that we now own
+    Ok(Some(unsafe { fs::File::from_raw_fd(fd as i32) }))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -94,6 +866,66 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_required_features() -> Result<(), Error> {
+        let workspace = tempfile::tempdir()?;
+        let packfile = workspace.path().join("pack.db3");
+        let mut builder = PackBuilder::new()?;
+        builder.set_digest_algorithm(DigestAlgorithm::Sha256)?;
+        builder.set_content_addressed(true)?;
+        builder.add_stream("hello.txt", 0, "hello world".as_bytes())?;
+        builder.finish(&packfile)?;
+        let reader = PackReader::new(&packfile)?;
+        assert_eq!(
+            reader.required_features()?,
+            vec!["digest=sha256", "layout=content-addressed"]
+        );
+        for feature in reader.required_features()? {
+            assert!(supported_features().contains(&feature.as_str()));
+        }
+        drop(reader);
+
+        // a newer version marks the features it needs to be understood
+        let conn = Connection::open(&packfile)?;
+        conn.execute(
+            "INSERT INTO archive_meta (name, value) VALUES ('requires', 'sparse-files, ')",
+            (),
+        )?;
+        let expected = "sparse-files".to_owned();
+        assert!(matches!(
+            PackReader::new(&packfile),
+            Err(Error::UnsupportedFormatFeature(name)) if name == expected
+        ));
+        assert!(matches!(
+            PackBuilder::open(&packfile),
+            Err(Error::UnsupportedFormatFeature(name)) if name == expected
+        ));
+        conn.execute("DELETE FROM archive_meta WHERE name = 'requires'", ())?;
+
+        // as do settings that this version does not know
+        conn.execute(
+            "UPDATE archive_meta SET value = 'md5' WHERE name = 'digest'",
+            (),
+        )?;
+        let expected = "digest=md5".to_owned();
+        assert!(matches!(
+            PackReader::new(&packfile),
+            Err(Error::UnsupportedFormatFeature(name)) if name == expected
+        ));
+        conn.execute(
+            "UPDATE archive_meta SET value = 'sha256' WHERE name = 'digest'",
+            (),
+        )?;
+
+        // as does a schema that lacks the expected columns
+        conn.execute("ALTER TABLE itemcontent DROP COLUMN contentpos", ())?;
+        assert!(matches!(
+            PackReader::new(&packfile),
+            Err(Error::UnsupportedFormatFeature(name)) if name.contains("itemcontent.contentpos")
+        ));
+        Ok(())
+    }
+
     #[test]
     fn test_sanitize_path() -> Result<(), Error> {
         // need to use real paths for the canonicalize() call
@@ -112,6 +944,102 @@ mod tests {
 
         let result = sanitize_path(Path::new("/usr/../src/./lib.rs"))?;
         assert_eq!(result, PathBuf::from("usr/src/lib.rs"));
+
+        #[cfg(target_family = "windows")]
+        {
+            let result = sanitize_path(Path::new("docs\\aux.c"));
+            assert!(matches!(
+                result,
+                Err(Error::UnsafePath(SanitizeError::ReservedName(_)))
+            ));
+        }
+        #[cfg(target_family = "unix")]
+        {
+            let result = sanitize_path(Path::new("docs/aux.c"))?;
+            assert_eq!(result, PathBuf::from("docs/aux.c"));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_check_windows_name() {
+        assert!(check_windows_name("lib.rs").is_ok());
+        assert!(check_windows_name("console.log").is_ok());
+        assert!(check_windows_name("COM10").is_ok());
+        assert!(check_windows_name(".gitignore").is_ok());
+        for name in [
+            "CON",
+            "nul",
+            "Com1",
+            "aux.c",
+            "lpt3.tar.gz",
+            "NUL .txt",
+            "com²",
+        ] {
+            assert_eq!(
+                check_windows_name(name),
+                Err(SanitizeError::ReservedName(name.to_owned()))
+            );
+        }
+        for name in ["readme.", "readme ", "..."] {
+            assert_eq!(
+                check_windows_name(name),
+                Err(SanitizeError::TrailingDotOrSpace(name.to_owned()))
+            );
+        }
+        assert_eq!(
+            check_windows_name("file.txt:stream"),
+            Err(SanitizeError::AlternateStream("file.txt:stream".into()))
+        );
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn test_open_beneath() -> Result<(), Error> {
+        let outside = tempfile::tempdir()?;
+        let root = tempfile::tempdir()?;
+        fs::create_dir(root.path().join("inner"))?;
+        std::os::unix::fs::symlink(outside.path(), root.path().join("escape"))?;
+        std::os::unix::fs::symlink("inner", root.path().join("within"))?;
+        std::os::unix::fs::symlink("/etc/passwd", root.path().join("passwd"))?;
+
+        // links that resolve within the root are acceptable
+        let path = resolve_beneath(root.path(), "within/file.txt")?;
+        assert!(path.starts_with(fs::canonicalize(root.path())?));
+        open_beneath(root.path(), "inner/file.txt")?;
+
+        // writing through a link that leads outside of the root is not
+        let result = resolve_beneath(root.path(), "escape/file.txt");
+        assert!(matches!(result, Err(Error::PathEscapesRoot(_))));
+        let result = open_beneath(root.path(), "escape/sub/file.txt");
+        assert!(matches!(result, Err(Error::PathEscapesRoot(_))));
+        assert!(!outside.path().join("file.txt").exists());
+
+        // nor is writing to a file that is itself a symbolic link
+        let result = open_beneath(root.path(), "passwd");
+        assert!(matches!(result, Err(Error::PathEscapesRoot(_))));
+        Ok(())
+    }
+
+    #[test]
+    fn test_item_kind() -> Result<(), Error> {
+        for raw in KIND_FILE..=KIND_BLOCK_DEVICE {
+            let kind = ItemKind::try_from(raw)?;
+            assert_eq!(i8::from(kind), raw);
+        }
+        assert_eq!(ItemKind::try_from(KIND_SYMLINK)?, ItemKind::Symlink);
+        assert!(matches!(
+            ItemKind::try_from(42),
+            Err(Error::UnknownItemKind(42))
+        ));
+
+        // the kind column converts directly to and from the enum
+        let conn = Connection::open_in_memory()?;
+        let kind: ItemKind = conn.query_row("SELECT ?1", [ItemKind::Fifo], |row| row.get(0))?;
+        assert_eq!(kind, ItemKind::Fifo);
+        let result: Result<ItemKind, rusqlite::Error> =
+            conn.query_row("SELECT 42", [], |row| row.get(0));
+        assert!(result.is_err());
         Ok(())
     }
 }
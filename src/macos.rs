@@ -0,0 +1,110 @@
+//
+// Copyright (c) 2024 Nathan Fiedler
+//
+use crate::Error;
+use std::path::Path;
+
+///
+/// Names of the extended attributes in which macOS keeps the resource fork
+/// and the Finder metadata (type, creator, flags, and label) of a file.
+///
+#[cfg(target_os = "macos")]
+pub(crate) const MAC_METADATA_NAMES: [&str; 2] = ["com.apple.ResourceFork", "com.apple.FinderInfo"];
+
+///
+/// Return the name and value of each of the macOS metadata attributes that the
+/// entry at the given path has. The attributes of a symbolic link itself are
+/// read rather than those of its target. Always returns nothing on systems
+/// other than macOS.
+///
+#[cfg(target_os = "macos")]
+pub(crate) fn read_mac_metadata(path: &Path) -> Result<Vec<(String, Vec<u8>)>, Error> {
+    use std::ffi::CString;
+    use std::io;
+    use std::os::unix::ffi::OsStrExt;
+    let Ok(cpath) = CString::new(path.as_os_str().as_bytes()) else {
+        return Ok(vec![]);
+    };
+    let mut found: Vec<(String, Vec<u8>)> = vec![];
+    for name in MAC_METADATA_NAMES {
+        let cname = CString::new(name).expect("attribute names have no NUL");
+        // SAFETY: a null buffer asks only for the size of the value
+        let size = unsafe {
+            libc::getxattr(
+                cpath.as_ptr(),
+                cname.as_ptr(),
+                std::ptr::null_mut(),
+                0,
+                0,
+                libc::XATTR_NOFOLLOW,
+            )
+        };
+        if size < 0 {
+            let err = io::Error::last_os_error();
+            match err.raw_os_error() {
+                Some(libc::ENOATTR) | Some(libc::ENOTSUP) | Some(libc::EPERM) => continue,
+                _ => return Err(err.into()),
+            }
+        }
+        let mut value: Vec<u8> = vec![0; size as usize];
+        // SAFETY: the buffer is valid for the given length
+        let size = unsafe {
+            libc::getxattr(
+                cpath.as_ptr(),
+                cname.as_ptr(),
+                value.as_mut_ptr() as *mut libc::c_void,
+                value.len(),
+                0,
+                libc::XATTR_NOFOLLOW,
+            )
+        };
+        if size < 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+        value.truncate(size as usize);
+        found.push((name.to_owned(), value));
+    }
+    Ok(found)
+}
+
+#[cfg(not(target_os = "macos"))]
+pub(crate) fn read_mac_metadata(_path: &Path) -> Result<Vec<(String, Vec<u8>)>, Error> {
+    Ok(vec![])
+}
+
+///
+/// Set the named macOS metadata attribute of the entry at the given path.
+/// Does nothing on systems other than macOS.
+///
+#[cfg(target_os = "macos")]
+pub(crate) fn write_mac_metadata(path: &Path, name: &str, value: &[u8]) -> Result<(), Error> {
+    use std::ffi::CString;
+    use std::io;
+    use std::os::unix::ffi::OsStrExt;
+    let Ok(cpath) = CString::new(path.as_os_str().as_bytes()) else {
+        return Err(Error::PathEscapesRoot(path.to_path_buf()));
+    };
+    let Ok(cname) = CString::new(name) else {
+        return Ok(());
+    };
+    // SAFETY: the path, name, and value are valid for the duration of the call
+    let result = unsafe {
+        libc::setxattr(
+            cpath.as_ptr(),
+            cname.as_ptr(),
+            value.as_ptr() as *const libc::c_void,
+            value.len(),
+            0,
+            libc::XATTR_NOFOLLOW,
+        )
+    };
+    if result < 0 {
+        return Err(io::Error::last_os_error().into());
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "macos"))]
+pub(crate) fn write_mac_metadata(_path: &Path, _name: &str, _value: &[u8]) -> Result<(), Error> {
+    Ok(())
+}
@@ -2,829 +2,2804 @@
 // Copyright (c) 2024 Nathan Fiedler
 //
 use clap::{arg, Command};
-use pack_rs::Error;
-use rusqlite::{Connection, DatabaseName};
+use clap_complete::Shell;
+use pack_rs::{
+    BenchOptions, CaseCollisions, Catalog, Codec, CreateReport, DigestAlgorithm,
+    EntryMetadataPatch, Error, ErrorPolicy, ExtractLimits, ExtractOptions, ExtractReport, ItemKind,
+    LinkRewrite, ListOptions, ListOrder, ManifestEntry, MergePolicy, OwnerMap, PackBuilder,
+    PackReader, PerformanceProfile, SortOrder, Timings, TreeNode, UpdateSummary, Warning,
+    ZstdCodec,
+};
 use std::fs;
-use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::io::{self, IsTerminal, Write};
 use std::path::{Path, PathBuf};
-use std::vec;
+use std::sync::OnceLock;
+use std::time::{SystemTime, UNIX_EPOCH};
 
-const KIND_FILE: i8 = 0;
-const KIND_DIRECTORY: i8 = 1;
-const KIND_SYMLINK: i8 = 2;
-const BUNDLE_SIZE: u64 = 16777216;
-
-//
-// Create the database tables if they do not exist.
-//
-fn create_tables(conn: &Connection) -> rusqlite::Result<()> {
-    conn.execute(
-        "CREATE TABLE IF NOT EXISTS item (
-            id INTEGER PRIMARY KEY,
-            parent INTEGER,
-            kind INTEGER,
-            name TEXT NOT NULL
-        )",
-        (),
-    )?;
-    conn.execute(
-        "CREATE TABLE IF NOT EXISTS content (
-            id INTEGER PRIMARY KEY,
-            value BLOB
-        )",
-        (),
-    )?;
-    conn.execute(
-        "CREATE TABLE IF NOT EXISTS itemcontent (
-            id INTEGER PRIMARY KEY,
-            item INTEGER,
-            itempos INTEGER,
-            content INTEGER,
-            contentpos INTEGER,
-            size INTEGER
-        )",
-        (),
-    )?;
-    Ok(())
+///
+/// Limits on the resources used when adding content to an archive.
+///
+struct Limits {
+    // maximum bytes per second read from the input files
+    io: Option<u64>,
+    // maximum number of threads used for compression
+    cpu: Option<u32>,
 }
 
-//
-// Represents the content of a file (item) and its position within a content
-// bundle when building an archive. It is possible that a portion of the file is
-// being added and thus the itempos might be non-zero; similarly the size may be
-// less than the actual file length.
-//
-struct IncomingContent {
-    // path of the file being packed
-    path: PathBuf,
-    // kind of item: file or symlink
-    kind: i8,
-    // the rowid in the item table
-    item: i64,
-    // offset within the file from which to start, usually zero
-    itempos: u64,
-    // offset within the content bundle where the data will go
-    contentpos: u64,
-    // size of the item content
-    size: u64,
-}
-
-///
-/// Creates or updates an archive.
-///
-struct PackBuilder {
-    // database connection
-    conn: Connection,
-    // byte offset within a bundle to which new content is added
-    current_pos: u64,
-    // item content that will reside in the bundle under construction
-    contents: Vec<IncomingContent>,
-    // workspace for compressing the content bundles
-    buffer: Option<Vec<u8>>,
-}
-
-impl PackBuilder {
-    ///
-    /// Construct a new `PackBuilder` that will operate entirely in memory.
-    ///
-    fn new() -> Result<Self, Error> {
-        let conn = Connection::open_in_memory()?;
-        // can set the page_size when creating the database, but not after
-        // conn.pragma_update(None, "page_size", 512)?;
-        create_tables(&conn)?;
-        Ok(Self {
-            conn,
-            current_pos: 0,
-            contents: vec![],
-            buffer: None,
-        })
-    }
-
-    ///
-    /// Visit all of the files and directories within the specified path, adding
-    /// them to the database.
-    ///
-    /// **Note:** Remember to call `finish()` when done adding content.
-    ///
-    fn add_dir_all<P: AsRef<Path>>(&mut self, basepath: P) -> Result<u64, Error> {
-        let mut file_count: u64 = 0;
-        let mut subdirs: Vec<(i64, PathBuf)> = Vec::new();
-        subdirs.push((0, basepath.as_ref().to_path_buf()));
-        while let Some((mut parent_id, currdir)) = subdirs.pop() {
-            parent_id = self.add_directory(&currdir, parent_id)?;
-            let readdir = fs::read_dir(currdir)?;
-            for entry_result in readdir {
-                let entry = entry_result?;
-                let path = entry.path();
-                // DirEntry.metadata() does not follow symlinks and that is good
-                let metadata = entry.metadata()?;
-                if metadata.is_dir() {
-                    subdirs.push((parent_id, path));
-                } else if metadata.is_file() {
-                    self.add_file(&path, parent_id)?;
-                    file_count += 1;
-                } else if metadata.is_symlink() {
-                    self.add_symlink(&path, parent_id)?;
-                }
-            }
+impl Limits {
+    fn from_matches(matches: &clap::ArgMatches) -> Self {
+        Self {
+            io: matches.get_one::<u64>("io-limit").copied(),
+            cpu: matches.get_one::<u32>("cpu-limit").copied(),
         }
-        Ok(file_count)
     }
 
-    ///
-    /// Call `finish()` when all file content has been added to the builder.
-    ///
-    /// The resulting database will be written to the given `path`.
-    ///
-    fn finish<P: AsRef<Path>>(&mut self, path: P) -> Result<(), Error> {
-        if !self.contents.is_empty() {
-            self.process_contents()?;
+    fn apply(&self, builder: &mut PackBuilder) {
+        if let Some(rate) = self.io {
+            builder.set_io_limit(rate);
+        }
+        if let Some(threads) = self.cpu {
+            builder.set_cpu_limit(threads);
         }
-        self.conn.backup(DatabaseName::Main, path, None)?;
-        Ok(())
     }
+}
 
-    ///
-    /// Process the current bundle of item content, clearing the collection and
-    /// resetting the current content position.
-    ///
-    fn process_contents(&mut self) -> Result<(), Error> {
-        self.insert_content()?;
-        self.contents = vec![];
-        self.current_pos = 0;
-        Ok(())
+///
+/// Parse a rate such as `500K` or `20M` into a number of bytes, where the
+/// optional suffix is a binary multiple (K, M, or G).
+///
+fn parse_rate(value: &str) -> Result<u64, String> {
+    parse_size(value).map_err(|_| format!("invalid rate: {}", value.trim()))
+}
+
+///
+/// Parse a size in bytes, with an optional K, M, or G suffix.
+///
+fn parse_size(value: &str) -> Result<u64, String> {
+    let value = value.trim();
+    let (digits, multiplier) = match value.chars().last().map(|c| c.to_ascii_uppercase()) {
+        Some('K') => (&value[..value.len() - 1], 1024),
+        Some('M') => (&value[..value.len() - 1], 1024 * 1024),
+        Some('G') => (&value[..value.len() - 1], 1024 * 1024 * 1024),
+        _ => (value, 1),
+    };
+    match digits
+        .parse::<u64>()
+        .ok()
+        .filter(|count| *count > 0)
+        .and_then(|count| count.checked_mul(multiplier))
+    {
+        Some(bytes) => Ok(bytes),
+        None => Err(format!("invalid size: {}", value)),
     }
+}
 
-    ///
-    /// Add a row to the `item` table that corresponds to this directory.
-    ///
-    fn add_directory<P: AsRef<Path>>(&self, path: P, parent: i64) -> Result<i64, Error> {
-        let name = get_file_name(path.as_ref());
-        self.conn.execute(
-            "INSERT INTO item (parent, kind, name) VALUES (?1, ?2, ?3)",
-            (&parent, KIND_DIRECTORY, &name),
-        )?;
-        Ok(self.conn.last_insert_rowid())
-    }
-
-    ///
-    /// Adds a single file to the archive, returning the item identifier.
-    ///
-    /// Depending on the size of the file and the content bundle so far, this
-    /// may result in writing one or more rows to the content and itemcontent
-    /// tables.
-    ///
-    /// **Note:** Remember to call `finish()` when done adding content.
-    ///
-    fn add_file<P: AsRef<Path>>(&mut self, path: P, parent: i64) -> Result<i64, Error> {
-        let name = get_file_name(path.as_ref());
-        self.conn.execute(
-            "INSERT INTO item (parent, kind, name) VALUES (?1, ?2, ?3)",
-            (&parent, KIND_FILE, &name),
-        )?;
-        let item_id = self.conn.last_insert_rowid();
-        let md = fs::metadata(path.as_ref());
-        let file_len = match md.as_ref() {
-            Ok(attr) => attr.len(),
-            Err(_) => 0,
-        };
-        // empty files will result in an itemcontent row whose size is zero,
-        // allowing for the extraction process to know to create an empty file
-        // (otherwise it is difficult to tell from the available data)
-        let mut itempos: u64 = 0;
-        let mut size: u64 = file_len;
-        loop {
-            if self.current_pos + size > BUNDLE_SIZE {
-                let remainder = BUNDLE_SIZE - self.current_pos;
-                // add a portion of the file to fill the bundle
-                let content = IncomingContent {
-                    path: path.as_ref().to_path_buf(),
-                    kind: KIND_FILE,
-                    item: item_id,
-                    itempos,
-                    contentpos: self.current_pos,
-                    size: remainder,
-                };
-                self.contents.push(content);
-                // insert the content and itemcontent rows and start a new
-                // bundle, then continue with the current file
-                self.process_contents()?;
-                size -= remainder;
-                itempos += remainder;
-            } else {
-                // the remainder of the file fits within this content bundle
-                let content = IncomingContent {
-                    path: path.as_ref().to_path_buf(),
-                    kind: KIND_FILE,
-                    item: item_id,
-                    itempos,
-                    contentpos: self.current_pos,
-                    size,
-                };
-                self.contents.push(content);
-                self.current_pos += size;
-                break;
-            }
+///
+/// Parse the rule for rewriting symbolic links, which is either `relative`
+/// or a mapping of one path prefix to another, as in `FROM=TO`.
+///
+fn parse_link_rewrite(value: &str) -> Result<LinkRewrite, String> {
+    if value == "relative" {
+        return Ok(LinkRewrite::Relative);
+    }
+    match value.split_once('=') {
+        Some((from, to)) if from.starts_with('/') => Ok(LinkRewrite::Prefix {
+            from: from.to_owned(),
+            to: to.to_owned(),
+        }),
+        _ => Err(format!("expected 'relative' or /FROM=TO: {}", value)),
+    }
+}
+
+///
+/// Read the mapping of owners or groups named by the given argument, if any,
+/// using the given function to parse the contents of the file.
+///
+fn owner_map_arg(
+    matches: &clap::ArgMatches,
+    name: &str,
+    parse: fn(&str) -> Result<OwnerMap, Error>,
+) -> Result<Option<OwnerMap>, Error> {
+    match matches.get_one::<PathBuf>(name) {
+        Some(path) => Ok(Some(parse(&fs::read_to_string(path)?)?)),
+        None => Ok(None),
+    }
+}
+
+///
+/// Install a subscriber that writes log events to standard error, with more
+/// detail for each occurrence of the verbose flag: progress, then individual
+/// files and bundles, then SQL statements and their timings.
+///
+fn init_logging(verbosity: u8) {
+    let level = match verbosity {
+        0 => return,
+        1 => tracing::Level::INFO,
+        2 => tracing::Level::DEBUG,
+        _ => tracing::Level::TRACE,
+    };
+    tracing_subscriber::fmt()
+        .with_max_level(level)
+        .with_ansi(io::stderr().is_terminal())
+        .with_writer(io::stderr)
+        .init();
+}
+
+///
+/// Configure the Zstandard codec according to the options given on the
+/// command line, returning `None` if the default codec will suffice.
+///
+fn zstd_codec(matches: &clap::ArgMatches, level: Option<i32>) -> Option<ZstdCodec> {
+    let window_log = matches.get_one::<u32>("long").copied();
+    let workers = matches.get_one::<u32>("zstd-workers").copied();
+    if level.is_none() && window_log.is_none() && workers.is_none() {
+        return None;
+    }
+    let mut codec = ZstdCodec::new(level.unwrap_or(0));
+    codec.set_long_distance_matching(window_log.unwrap_or(0));
+    codec.set_threads(workers.unwrap_or(0));
+    Some(codec)
+}
+
+///
+/// Prepare a builder for the create command according to the options given
+/// on the command line.
+///
+fn create_builder(matches: &clap::ArgMatches) -> Result<PackBuilder, Error> {
+    let mut builder = PackBuilder::new()?;
+    builder.set_performance_profile(performance_profile(matches))?;
+    builder.set_on_warning(print_warning);
+    if let Some(path) = matches.get_one::<PathBuf>("exclude-from") {
+        for pattern in read_patterns(path)?.iter() {
+            builder.add_exclude(pattern)?;
         }
-        Ok(item_id)
-    }
-
-    ///
-    /// Adds a symbolic link to the archive, returning the item identifier.
-    ///
-    /// **Note:** Remember to call `finish()` when done adding content.
-    ///
-    fn add_symlink<P: AsRef<Path>>(&mut self, path: P, parent: i64) -> Result<i64, Error> {
-        let name = get_file_name(path.as_ref());
-        self.conn.execute(
-            "INSERT INTO item (parent, kind, name) VALUES (?1, ?2, ?3)",
-            (&parent, KIND_SYMLINK, &name),
-        )?;
-        let item_id = self.conn.last_insert_rowid();
-        let md = fs::symlink_metadata(path.as_ref());
-        let link_len = match md.as_ref() {
-            Ok(attr) => attr.len(),
-            Err(_) => 0,
-        };
-        // assume that the link value is relatively small and simply add it into
-        // the current content bundle in whole
-        let content = IncomingContent {
-            path: path.as_ref().to_path_buf(),
-            kind: KIND_SYMLINK,
-            item: item_id,
-            itempos: 0,
-            contentpos: self.current_pos,
-            size: link_len,
-        };
-        self.contents.push(content);
-        self.current_pos += link_len;
-        Ok(item_id)
-    }
-
-    //
-    // Creates a content bundle based on the data collected so far, then
-    // compresses it, writing the blob to a new row in the `content` table. Then
-    // creates the necessary rows in the `itemcontent` table to map the file
-    // data to the content bundle.
-    //
-    fn insert_content(&mut self) -> Result<(), Error> {
-        // Allocate a buffer for the compressed data, reusing it each time. For
-        // small data sets this makes no observable difference, but for any
-        // large data set (e.g. Linux kernel), it makes a huge difference.
-        let mut content: Vec<u8> = if let Some(mut buf) = self.buffer.take() {
-            buf.clear();
-            buf
-        } else {
-            Vec::with_capacity(BUNDLE_SIZE as usize)
-        };
-        let mut encoder = zstd::stream::write::Encoder::new(content, 0)?;
-
-        // iterate through the file contents to build the compressed bundle
-        for item in self.contents.iter() {
-            if item.kind == KIND_FILE {
-                let mut input = fs::File::open(&item.path)?;
-                input.seek(SeekFrom::Start(item.itempos))?;
-                let mut chunk = input.take(item.size);
-                io::copy(&mut chunk, &mut encoder)?;
-            } else if item.kind == KIND_SYMLINK {
-                let value = read_link(&item.path)?;
-                encoder.write_all(&value)?;
-            }
+    }
+    if matches.get_flag("reproducible") {
+        // follow the reproducible-builds.org convention
+        let epoch = std::env::var("SOURCE_DATE_EPOCH")
+            .ok()
+            .and_then(|v| v.parse::<i64>().ok())
+            .unwrap_or(0);
+        builder.set_reproducible(epoch);
+    }
+    builder.set_sort_order(sort_order(matches));
+    builder.set_special_files(matches.get_flag("special-files"));
+    builder.set_max_depth(matches.get_one::<usize>("max-depth").copied());
+    builder.set_one_file_system(matches.get_flag("one-file-system"));
+    builder.set_error_policy(error_policy(matches));
+    builder.set_mac_metadata(matches.get_flag("mac-metadata"));
+    builder.set_verify_on_finish(matches.get_flag("verify"));
+    builder.set_store_incompressible(!matches.get_flag("compress-all"));
+    builder.set_digest_algorithm(digest_algorithm(matches))?;
+    builder.set_content_addressed(matches.get_flag("content-addressed"))?;
+    builder.set_metadata_only(matches.get_flag("no-content"));
+    if matches.get_flag("encrypt") {
+        builder.encrypt_content(&require_password(true)?)?;
+    }
+    if let Some(codec) = zstd_codec(matches, None) {
+        builder.set_codec(Box::new(codec));
+    }
+    builder.set_adaptive_compression(matches.get_flag("adaptive"));
+    if let Some(threshold) = matches.get_one::<u64>("large-files") {
+        let level = matches.get_one::<i32>("large-level").copied();
+        let codec = zstd_codec(matches, level).map(|c| Box::new(c) as Box<dyn Codec>);
+        builder.set_large_file_policy(*threshold, codec);
+    }
+    if let Some(limit) = matches.get_one::<u64>("memory-limit") {
+        builder.set_memory_limit(*limit);
+    }
+    Limits::from_matches(matches).apply(&mut builder);
+    Ok(builder)
+}
+
+///
+/// Change the recorded metadata of the entries at the given paths within the
+/// archive, returning the number of entries changed.
+///
+fn set_metadata(pack: &str, paths: Vec<&String>, patch: EntryMetadataPatch) -> Result<u64, Error> {
+    if !pack_rs::is_pack_file(pack)? {
+        return Err(Error::NotPackFile);
+    }
+    let mut builder = PackBuilder::open(pack)?;
+    for path in paths.iter() {
+        builder.set_metadata(path, &patch)?;
+    }
+    builder.commit()?;
+    Ok(paths.len() as u64)
+}
+
+///
+/// Parse the permissions given in octal, such as 644 or 0755.
+///
+fn parse_mode(value: &str) -> Result<u32, String> {
+    match u32::from_str_radix(value, 8) {
+        Ok(mode) if mode <= 0o7777 => Ok(mode),
+        _ => Err(format!("invalid mode: {}", value)),
+    }
+}
+
+///
+/// Parse the owner given as USER, USER:GROUP, or :GROUP, where each is either
+/// a name or a number, into the changes to be made to the recorded owner.
+///
+fn parse_owner(value: &str) -> Result<EntryMetadataPatch, String> {
+    let (user, group) = match value.split_once(':') {
+        Some((user, group)) => (user, Some(group)),
+        None => (value, None),
+    };
+    if group.is_some_and(|g| g.is_empty()) || (user.is_empty() && group.is_none()) {
+        return Err(format!("invalid owner: {}", value));
+    }
+    let mut patch = EntryMetadataPatch::default();
+    if !user.is_empty() {
+        match user.parse::<u32>() {
+            Ok(uid) => patch.uid = Some(uid),
+            Err(_) => patch.uname = Some(user.to_owned()),
         }
-        content = encoder.finish()?;
-        let compressed_len = content.len();
-
-        // create space for the blob by inserting a zeroblob and then
-        // overwriting it with the compressed content bundle
-        //
-        // NOTE: This insert takes the majority of the overall running time when
-        // writing directly to disk.
-        //
-        self.conn.execute(
-            "INSERT INTO content (value) VALUES (ZEROBLOB(?1))",
-            [compressed_len as i32],
-        )?;
-        let content_id = self.conn.last_insert_rowid();
-        let mut blob =
-            self.conn
-                .blob_open(DatabaseName::Main, "content", "value", content_id, false)?;
-        let bytes_written = blob.write(&content)?;
-        if bytes_written != content.len() {
-            return Err(Error::IncompleteBlobWrite);
-        }
-        self.buffer = Some(content);
-
-        // iterate through the item contents and insert new itemcontent rows
-        for item in self.contents.iter() {
-            // create the mapping for this bit of content
-            self.conn.execute(
-                "INSERT INTO itemcontent (
-                    item, itempos, content, contentpos, size
-                ) VALUES (?1, ?2, ?3, ?4, ?5)",
-                (
-                    &item.item,
-                    &item.itempos,
-                    &content_id,
-                    &item.contentpos,
-                    &item.size,
-                ),
-            )?;
+    }
+    if let Some(group) = group {
+        match group.parse::<u32>() {
+            Ok(gid) => patch.gid = Some(gid),
+            Err(_) => patch.gname = Some(group.to_owned()),
         }
+    }
+    Ok(patch)
+}
 
-        Ok(())
+///
+/// Sources of the entries to be added to a new archive.
+///
+struct Inputs<'a> {
+    // files and directories given on the command line
+    paths: Vec<&'a PathBuf>,
+    // file listing the paths to be added
+    files_from: Option<&'a PathBuf>,
+    // tar file whose entries are to be added
+    from_tar: Option<&'a PathBuf>,
+    // revision whose files are added from each of the paths
+    git_rev: Option<&'a String>,
+}
+
+impl<'a> Inputs<'a> {
+    fn from_matches(matches: &'a clap::ArgMatches) -> Self {
+        Self {
+            paths: matches
+                .get_many::<PathBuf>("INPUTS")
+                .into_iter()
+                .flatten()
+                .collect(),
+            files_from: matches.get_one::<PathBuf>("files-from"),
+            from_tar: matches.get_one::<PathBuf>("from-tar"),
+            git_rev: matches.get_one::<String>("git-rev"),
+        }
     }
 }
 
 ///
-/// Create a pack file at the given location and add all of the named inputs.
+/// Create a pack file at the given location and add all of the named inputs,
+/// and the entries of the tar file, if given, placing them within the prefix
+/// directory, if given. With a git revision, the inputs are repositories from
+/// which the files of that revision are added. With concurrent reads, the
+/// archive is built in place and published after each input.
 ///
-/// Returns the total number of files added to the archive.
+/// Returns a summary of the archive, the number of warnings, and the number
+/// of entries that could not be read.
 ///
-fn create_archive<P: AsRef<Path>>(pack: P, inputs: Vec<&PathBuf>) -> Result<u64, Error> {
+fn create_archive<P: AsRef<Path>>(
+    pack: P,
+    inputs: Inputs,
+    prefix: Option<&String>,
+    mut builder: PackBuilder,
+    policy: ErrorPolicy,
+    concurrent_reads: bool,
+) -> Result<(CreateReport, usize, usize), Error> {
     let path_ref = pack.as_ref();
     let path = match path_ref.extension() {
         Some(_) => path_ref.to_path_buf(),
         None => path_ref.with_extension("db3"),
     };
-    let mut builder = PackBuilder::new()?;
-    let mut file_count: u64 = 0;
-    for input in inputs {
-        let metadata = input.metadata()?;
-        if metadata.is_dir() {
-            file_count += builder.add_dir_all(input)?;
-        } else if metadata.is_file() {
-            builder.add_file(input, 0)?;
-            file_count += 1;
+    builder.set_output_path(&path);
+    builder.set_concurrent_reads(concurrent_reads)?;
+    let root = match prefix {
+        Some(prefix) => builder.add_prefix(prefix)?,
+        None => 0,
+    };
+    for input in inputs.paths {
+        match (inputs.git_rev, split_input_mapping(input)) {
+            (Some(revision), _) => add_git_revision(&mut builder, input, revision, root)?,
+            (None, Some((name, path))) => {
+                let name = match prefix {
+                    Some(prefix) => format!("{}/{}", prefix, name),
+                    None => name.to_owned(),
+                };
+                if path.is_dir() {
+                    builder.add_dir_as(path, &name)?
+                } else {
+                    builder.add_file_as(path, &name)?;
+                    1
+                }
+            }
+            (None, None) => builder.add_input(input, root)?,
+        };
+        if concurrent_reads {
+            builder.publish()?;
         }
     }
-    builder.finish(path)?;
-    Ok(file_count)
+    if let Some(list) = inputs.files_from {
+        for path in read_file_list(list)? {
+            builder.add_path(path)?;
+        }
+    }
+    if let Some(tar) = inputs.from_tar {
+        if tar.as_os_str() == "-" {
+            builder.add_tar_stream(io::stdin().lock(), root)?;
+        } else {
+            builder.add_tar_stream(fs::File::open(tar)?, root)?;
+        }
+    }
+    let warnings = builder.warnings().len();
+    let unreadable = print_unreadable(&builder, policy);
+    let report = builder.finish(path)?;
+    Ok((report, warnings, unreadable))
 }
 
 ///
-/// Return the last part of the path, converting to a String.
+/// Split an input of the form `NAME=PATH` into the name under which the file
+/// or directory is to be stored and its path, unless the input names an
+/// existing file as it is.
 ///
-fn get_file_name<P: AsRef<Path>>(path: P) -> String {
-    // ignore any paths that end in '..'
-    if let Some(p) = path.as_ref().file_name() {
-        // ignore any paths that failed UTF-8 translation
-        if let Some(pp) = p.to_str() {
-            return pp.to_owned();
-        }
+fn split_input_mapping(input: &Path) -> Option<(&str, &Path)> {
+    if input.exists() {
+        return None;
+    }
+    let (name, path) = input.to_str()?.split_once('=')?;
+    if name.is_empty() || path.is_empty() {
+        return None;
     }
-    // normal conversion failed, return whatever garbage is there
-    path.as_ref().to_string_lossy().into_owned()
+    Some((name, Path::new(path)))
 }
 
 ///
-/// Read the symbolic link value and convert to raw bytes.
+/// Add the files of the given revision of the repository to the archive.
 ///
-fn read_link(path: &Path) -> Result<Vec<u8>, Error> {
-    // convert whatever value returned by the OS into raw bytes without string conversion
-    use os_str_bytes::OsStringBytes;
-    let value = fs::read_link(path)?;
-    Ok(value.into_os_string().into_raw_vec())
+#[cfg(feature = "git")]
+fn add_git_revision(
+    builder: &mut PackBuilder,
+    repo: &Path,
+    revision: &str,
+    parent: i64,
+) -> Result<u64, Error> {
+    builder.add_git_revision(repo, revision, parent)
+}
+
+#[cfg(not(feature = "git"))]
+fn add_git_revision(
+    _builder: &mut PackBuilder,
+    _repo: &Path,
+    _revision: &str,
+    _parent: i64,
+) -> Result<u64, Error> {
+    let message = "--git-rev requires building with the git feature";
+    Err(io::Error::new(io::ErrorKind::Unsupported, message).into())
 }
 
 ///
-/// Create a symbolic link using the given raw bytes.
+/// Print each non-fatal event to standard error as it occurs.
 ///
-fn write_link(contents: &[u8], filepath: &Path) -> Result<(), Error> {
-    use os_str_bytes::OsStringBytes;
-    // this may panic if the bytes are not valid for this platform
-    let target = std::ffi::OsString::from_io_vec(contents.to_owned())
-        .ok_or_else(|| Error::LinkTextEncoding)?;
-    // cfg! macro will not work in this OS-specific import case
-    {
-        #[cfg(target_family = "unix")]
-        use std::os::unix::fs;
-        #[cfg(target_family = "windows")]
-        use std::os::windows::fs;
-        #[cfg(target_family = "unix")]
-        fs::symlink(target, filepath)?;
-        #[cfg(target_family = "windows")]
-        fs::symlink_file(target, filepath)?;
-    }
-    return Ok(());
-}
-
-///
-/// Reads the contents of an archive.
-///
-struct PackReader {
-    conn: Connection,
-}
-
-impl PackReader {
-    ///
-    /// Construct a new `PackReader` that will read from the pack file at the
-    /// given location.
-    ///
-    fn new<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
-        let conn = Connection::open(path.as_ref())?;
-        Ok(Self { conn })
-    }
-
-    ///
-    /// Return all items in the archive with the `name` as the full path.
-    ///
-    /// Directory entries have a path that ends with a slash (/).
-    ///
-    fn entries(&self) -> Result<Vec<Result<Entry, rusqlite::Error>>, Error> {
-        //
-        // Would love to return an iterator but that is quite difficult given
-        // that the lifetimes and types are not very cooperative.
-        //
-        // Query from Pack in UPackDraft0Shared.pas that queries all items in
-        // ascending order to make it easy to build the results.
-        //
-        let query = "WITH RECURSIVE FIT AS (
-    SELECT *, Name || IIF(Kind = 1, '/', '') AS Path FROM Item WHERE Parent = 0
-    UNION ALL
-    SELECT Item.*, FIT.Path || Item.Name || IIF(Item.Kind = 1, '/', '') AS Path
-        FROM Item INNER JOIN FIT ON FIT.Kind = 1 AND Item.Parent = FIT.ID
-)
-SELECT id, parent, kind, Path FROM FIT;";
-        let mut stmt = self.conn.prepare(query)?;
-        let items: Vec<Result<Entry, rusqlite::Error>> = stmt
-            .query_map([], |row| {
-                Ok(Entry {
-                    id: row.get(0)?,
-                    parent: row.get(1)?,
-                    kind: row.get(2)?,
-                    name: row.get(3)?,
-                })
-            })?
-            .collect();
-        Ok(items)
-    }
-
-    // Returns the number of files extracted.
-    fn extract_all(&self) -> Result<u64, Error> {
-        // ensure all of the directories are created, even empty ones
-        self.ensure_all_directories()?;
-        // create a temporary table for holding the items and their full paths;
-        // start by dropping the table in case it was left behind from a
-        // previous operation
-        self.drop_temp_paths_table()?;
-        self.create_temp_paths_table()?;
-
-        // join the item paths with the itemcontent rows and sort by the content
-        // blob order, making it easier to efficiently process the content blobs
-        let mut stmt = self.conn.prepare(
-            "SELECT content, contentpos, itempos, Size, kind, Path FROM IndexedFiles
-            LEFT JOIN itemcontent ON IndexedFiles.II = ItemContent.Item
-            ORDER BY content, contentpos",
-        )?;
-        let mut item_iter = stmt.query_map([], |row| {
-            Ok(IndexedFile {
-                content: row.get(0)?,
-                contentpos: row.get(1)?,
-                itempos: row.get(2)?,
-                size: row.get(3)?,
-                kind: row.get(4)?,
-                path: row.get(5)?,
-            })
-        })?;
+fn print_warning(warning: &Warning) {
+    eprintln!("warning: {}", warning);
+}
 
-        // process the item blobs from the resulting itemcontent query
-        let mut content_id: i64 = -1;
-        let mut files: Vec<IndexedFile> = vec![];
-        let mut file_count: u64 = 0;
-        while let Some(row_result) = item_iter.next() {
-            let indexed_file = row_result?;
-            if indexed_file.content != content_id {
-                // reached the end of the entries for this content
-                if !files.is_empty() {
-                    file_count += self.process_content(files)?;
-                }
-                content_id = indexed_file.content;
-                files = vec![indexed_file];
-            } else {
-                // another piece of the same content, add to the list
-                files.push(indexed_file);
-            }
-        }
-        // make sure any remaining content is processed
-        if !files.is_empty() {
-            file_count += self.process_content(files)?;
-        }
-
-        // clean up
-        self.drop_temp_paths_table()?;
-        Ok(file_count)
-    }
-
-    // Ensure that all directories in the archive are created, even those that
-    // do not contain any files.
-    fn ensure_all_directories(&self) -> Result<(), Error> {
-        let query = "WITH RECURSIVE FIT AS (
-    SELECT *, Name || IIF(Kind = 1, '/', '') AS Path FROM Item WHERE Parent = 0
-    UNION ALL
-    SELECT Item.*, FIT.Path || Item.Name || IIF(Item.Kind = 1, '/', '') AS Path
-        FROM Item INNER JOIN FIT ON FIT.Kind = 1 AND Item.Parent = FIT.ID
-)
-SELECT Path FROM FIT WHERE Kind = 1;";
-        let mut stmt = self.conn.prepare(query)?;
-        let mut rows = stmt.query([])?;
-        while let Some(row) = rows.next()? {
-            let path: String = row.get(0)?;
-            let fpath = pack_rs::sanitize_path(path)?;
-            fs::create_dir_all(fpath)?;
+///
+/// Print a warning for each entry that could not be read, unless the policy
+/// is to skip them quietly, and return the number of such entries.
+///
+fn print_unreadable(builder: &PackBuilder, policy: ErrorPolicy) -> usize {
+    if policy == ErrorPolicy::Warn {
+        for (path, err) in builder.unreadable() {
+            eprintln!("warning: could not read {}: {}", path.display(), err);
         }
-        Ok(())
     }
+    builder.unreadable().len()
+}
 
-    // Process a single content blob and all of the files it contains.
-    fn process_content(&self, files: Vec<IndexedFile>) -> Result<u64, Error> {
-        assert!(!files.is_empty(), "expected files to be non-empty");
-        let content_id = files[0].content;
-
-        // fetch the blob and decompress
-        let mut blob =
-            self.conn
-                .blob_open(DatabaseName::Main, "content", "value", content_id, true)?;
-        let mut buffer: Vec<u8> = Vec::new();
-        zstd::stream::copy_decode(&mut blob, &mut buffer)?;
-
-        // process each of the rows of content, which are portions of a file
-        let mut file_count: u64 = 0;
-        for entry in files.iter() {
-            // perform basic sanitization of the file path to prevent abuse (it
-            // is theoretically possible that the data could produce a path with
-            // a root, prefix, parent-dir elements)
-            let fpath = pack_rs::sanitize_path(&entry.path)?;
-            if entry.kind == KIND_FILE {
-                // make sure the file exists and is writable
-                let mut output = fs::OpenOptions::new()
-                    .write(true)
-                    .create(true)
-                    .open(&fpath)?;
-                let file_len = fs::metadata(fpath)?.len();
-                if file_len == 0 {
-                    // just created a new file, count it
-                    file_count += 1;
-                }
-                // if the file was an empty file, then we are already done here
-                if entry.size > 0 {
-                    // ensure the file has the appropriate length for writing this
-                    // content chunk into the file, extending it as necessary
-                    if file_len < entry.itempos {
-                        output.set_len(entry.itempos)?;
-                    }
-                    // seek to the correct position within the file for this chunk
-                    if entry.itempos > 0 {
-                        output.seek(SeekFrom::Start(entry.itempos))?;
-                    }
-                    // use Cursor because that's seemingly easier than getting a slice
-                    let mut cursor = std::io::Cursor::new(&buffer);
-                    cursor.seek(SeekFrom::Start(entry.contentpos))?;
-                    let mut chunk = cursor.take(entry.size);
-                    io::copy(&mut chunk, &mut output)?;
-                }
-            } else if entry.kind == KIND_SYMLINK {
-                // use Cursor because that's seemingly easier than getting a slice
-                let mut cursor = std::io::Cursor::new(&buffer);
-                cursor.seek(SeekFrom::Start(entry.contentpos))?;
-                let mut chunk = cursor.take(entry.size);
-                let mut raw_bytes: Vec<u8> = vec![];
-                chunk.read_to_end(&mut raw_bytes)?;
-                write_link(&raw_bytes, &fpath)?;
-            }
-        }
+///
+/// Return the error policy selected by the on-error option.
+///
+fn error_policy(matches: &clap::ArgMatches) -> ErrorPolicy {
+    match matches.get_one::<String>("on-error").map(|s| s.as_str()) {
+        Some("warn") => ErrorPolicy::Warn,
+        Some("skip") => ErrorPolicy::Skip,
+        _ => ErrorPolicy::Fail,
+    }
+}
 
-        Ok(file_count)
-    }
-
-    // Create a table to hold the item identifiers and their full paths and
-    // populate it using the values in the item table.
-    fn create_temp_paths_table(&self) -> Result<(), Error> {
-        self.conn.execute(
-            "CREATE TEMPORARY TABLE IndexedFiles (II INTEGER PRIMARY KEY, kind INTEGER, path TEXT)",
-            (),
-        )?;
-        self.conn.execute(
-            "INSERT INTO IndexedFiles SELECT II, kind, Path FROM (
-                WITH RECURSIVE FIT AS (
-                    SELECT *, Name || IIF(Kind = 1, '/', '') AS Path FROM Item WHERE Parent = 0
-                    UNION ALL
-                    SELECT Item.*, FIT.Path || Item.Name || IIF(Item.Kind = 1, '/', '') AS Path
-                        FROM Item INNER JOIN FIT ON FIT.Kind = 1 AND Item.Parent = FIT.ID
-                )
-                SELECT id AS II, kind, Path FROM FIT WHERE kind <> 1
-            )",
-            (),
-        )?;
-        Ok(())
+///
+/// Print the statistics gathered while creating an archive.
+///
+fn print_create_report(report: &CreateReport) {
+    println!(
+        "Directories: {}, symlinks: {}, special files: {}",
+        report.directories, report.symlinks, report.special_files
+    );
+    println!(
+        "Input: {}, archive: {} ({:.1}%)",
+        format_size(report.input_bytes),
+        format_size(report.archive_bytes),
+        report.ratio()
+    );
+    println!(
+        "Elapsed: {:.2}s ({}/s)",
+        report.elapsed.as_secs_f64(),
+        format_size(report.throughput() as u64)
+    );
+}
+
+///
+/// Print the summary of an extracted archive, after the number of files.
+///
+fn print_extract_report(report: &ExtractReport) {
+    println!(
+        "Directories: {}, symlinks: {}, special files: {}",
+        report.directories, report.symlinks, report.special_files
+    );
+    if report.skipped > 0 || report.overwritten > 0 {
+        println!(
+            "Skipped: {}, overwritten: {}",
+            report.skipped, report.overwritten
+        );
     }
+    println!(
+        "Written: {}, elapsed: {:.2}s ({}/s)",
+        format_size(report.bytes_written),
+        report.elapsed.as_secs_f64(),
+        format_size(report.throughput() as u64)
+    );
+}
 
-    // Drop the table that holds the item identifiers and their full paths.
-    fn drop_temp_paths_table(&self) -> Result<(), Error> {
-        self.conn.execute("DROP TABLE IF EXISTS IndexedFiles", ())?;
-        Ok(())
+///
+/// Print the time spent in each phase of creating an archive.
+///
+fn print_timings(timings: &Timings) {
+    let phases = [
+        ("walking", timings.walking),
+        ("reading", timings.reading),
+        ("compressing", timings.compressing),
+        ("writing", timings.writing),
+        ("inserting", timings.inserting),
+        ("finishing", timings.finishing),
+    ];
+    println!("Timings:");
+    for (name, elapsed) in phases {
+        println!("{:>14}: {:.3}s", name, elapsed.as_secs_f64());
     }
+}
 
-    // returns 0 if file not found
-    #[allow(dead_code)]
-    fn find_file_by_path(&self, relpath: &str) -> Result<i64, Error> {
-        let sql = format!(
-            "WITH RECURSIVE IT AS (
-    SELECT Item.*, ID AS FID FROM Item WHERE
-    ID IN (
-        WITH RECURSIVE FIT AS (
-            SELECT *, '/' || Name || IIF(Kind = 1, '/', '') AS Path FROM Item WHERE Parent = 0
-            UNION ALL
-            SELECT Item.*, FIT.Path || Item.Name || IIF(Item.Kind = 1, '/', '') AS Path
-                FROM Item INNER JOIN FIT ON FIT.Kind = 1 AND Item.Parent = FIT.ID
-                WHERE '/{}' LIKE (Path || '%')
-        )
-        SELECT ID FROM FIT WHERE Path IN ('/{}')
-    )
-    UNION ALL
-    SELECT Item.*, IT.FID FROM Item INNER JOIN IT ON IT.Kind = 1 AND Item.Parent = IT.ID
-),
-ITI AS (SELECT (ROW_NUMBER() OVER (ORDER BY FID, ID) - 1) AS I, * FROM IT)
-SELECT C.I, IFNULL(P.I, -1) AS PI, C.ID, C.Parent, C.Kind, C.Name FROM ITI AS C
-LEFT JOIN ITI AS P ON C.FID = P.FID AND C.Parent = P.ID ORDER BY C.I;",
-            relpath, relpath
+///
+/// Pack a sample of the directory with each combination of settings and print
+/// a table of the results.
+///
+fn run_benchmark(dir: &Path, options: &BenchOptions) -> Result<(), Error> {
+    let results = pack_rs::benchmark(dir, options)?;
+    if let Some(first) = results.first() {
+        println!("Sample: {}", format_size(first.input_bytes));
+    }
+    println!(
+        "{:>10} {:>6} {:>8} {:>10} {:>12} {:>7} {:>9} {:>10} {:>12}",
+        "bundle",
+        "level",
+        "threads",
+        "elapsed",
+        "throughput",
+        "ratio",
+        "profile",
+        "extract",
+        "throughput"
+    );
+    for result in results.iter() {
+        println!(
+            "{:>10} {:>6} {:>8} {:>9.2}s {:>10}/s {:>6.1}% {:>9} {:>9.2}s {:>10}/s",
+            format_size(result.bundle_size),
+            result.level,
+            result.threads,
+            result.elapsed.as_secs_f64(),
+            format_size(result.throughput() as u64),
+            result.ratio(),
+            profile_name(result.profile),
+            result.extract_elapsed.as_secs_f64(),
+            format_size(result.extract_throughput() as u64)
         );
-        let mut stmt = self.conn.prepare(&sql)?;
-        let item_iter = stmt.query_map([], |row| {
-            Ok(Entry {
-                id: row.get(2)?,
-                parent: row.get(3)?,
-                kind: row.get(4)?,
-                name: row.get(5)?,
-            })
-        })?;
-        for entry in item_iter {
-            return Ok(entry?.id);
-        }
-        Ok(0)
-    }
-
-    //
-    // Print the contents of the identified file to stdout.
-    //
-    #[allow(dead_code)]
-    fn print_file(&self, item_id: i64) -> Result<(), Error> {
-        let mut stmt = self.conn.prepare(
-            "SELECT content, contentpos, size FROM itemcontent WHERE item = ?1 ORDER BY itempos",
-        )?;
-        let content_iter = stmt.query_map([&item_id], |row| {
-            Ok(OutgoingContent {
-                content: row.get(0)?,
-                contentpos: row.get(1)?,
-                size: row.get(2)?,
-            })
-        })?;
-        for content_result in content_iter {
-            let itemcontent = content_result?;
-            let mut blob = self.conn.blob_open(
-                DatabaseName::Main,
-                "content",
-                "value",
-                itemcontent.content,
-                true,
-            )?;
-            let mut buffer: Vec<u8> = Vec::new();
-            let mut output = io::stdout();
-            zstd::stream::copy_decode(&mut blob, &mut buffer)?;
-            // use Cursor because that's seemingly easier than getting a slice
-            let mut cursor = std::io::Cursor::new(buffer);
-            cursor.seek(SeekFrom::Start(itemcontent.contentpos))?;
-            let mut chunk = cursor.take(itemcontent.size);
-            io::copy(&mut chunk, &mut output)?;
-        }
-        Ok(())
     }
+    Ok(())
 }
 
 ///
-/// List all file entries in the archive in breadth-first order.
+/// Read the list of paths from the named file, or standard input if the name
+/// is `-`. The paths are separated by NUL characters if any are present, as
+/// produced by `find -print0`, and otherwise by newlines.
 ///
-fn list_contents(pack: &str) -> Result<(), Error> {
-    if !pack_rs::is_pack_file(pack)? {
-        return Err(Error::NotPackFile);
+fn read_file_list(path: &Path) -> Result<Vec<PathBuf>, Error> {
+    use os_str_bytes::OsStringBytes;
+    use std::io::Read;
+    let mut data: Vec<u8> = vec![];
+    if path == Path::new("-") {
+        io::stdin().lock().read_to_end(&mut data)?;
+    } else {
+        fs::File::open(path)?.read_to_end(&mut data)?;
     }
-    let reader = PackReader::new(pack)?;
-    let entries = reader.entries()?;
-    for result in entries {
-        let entry = result?;
-        if entry.kind != KIND_DIRECTORY {
-            println!("{}", entry.name)
+    let separator = if data.contains(&0) { b'\0' } else { b'\n' };
+    let mut paths: Vec<PathBuf> = vec![];
+    for line in data.split(|b| *b == separator) {
+        let line = if separator == b'\n' {
+            line.strip_suffix(b"\r").unwrap_or(line)
+        } else {
+            line
+        };
+        if line.is_empty() {
+            continue;
         }
+        let name = std::ffi::OsString::from_io_vec(line.to_vec()).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidData, "path encoding not recognized")
+        })?;
+        paths.push(PathBuf::from(name));
     }
-    Ok(())
+    Ok(paths)
 }
 
 ///
-/// Extract all of the files from the archive.
+/// Read the exclusion patterns from the named file, one per line, ignoring
+/// blank lines and comments (lines starting with #).
 ///
-fn extract_contents(pack: &str) -> Result<u64, Error> {
-    if !pack_rs::is_pack_file(pack)? {
+fn read_patterns(path: &Path) -> Result<Vec<String>, Error> {
+    let text = fs::read_to_string(path)?;
+    let patterns = text
+        .lines()
+        .map(|line| line.trim_end())
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| line.to_owned())
+        .collect();
+    Ok(patterns)
+}
+
+///
+/// Update the pack file at the given location to match the named inputs,
+/// adding new files, replacing changed files, and removing entries that no
+/// longer exist.
+///
+fn update_archive<P: AsRef<Path>>(
+    pack: P,
+    inputs: Vec<&PathBuf>,
+    limits: Limits,
+    policy: ErrorPolicy,
+    concurrent_reads: bool,
+) -> Result<(UpdateSummary, usize, usize), Error> {
+    if !pack_rs::is_pack_file(pack.as_ref())? {
         return Err(Error::NotPackFile);
     }
-    let reader = PackReader::new(pack)?;
-    let file_count = reader.extract_all()?;
-    Ok(file_count)
+    let mut builder = PackBuilder::open(pack)?;
+    builder.set_concurrent_reads(concurrent_reads)?;
+    if builder.is_encrypted() {
+        builder.encrypt_content(&require_password(false)?)?;
+    }
+    limits.apply(&mut builder);
+    builder.set_error_policy(policy);
+    builder.set_on_warning(print_warning);
+    let summary = builder.update_all(inputs)?;
+    let warnings = builder.warnings().len();
+    let unreadable = print_unreadable(&builder, policy);
+    builder.commit()?;
+    Ok((summary, warnings, unreadable))
 }
 
 ///
-/// `Entry` represents a row from the `item` table.
+/// Create a pack file at the given location that contains the entries from
+/// all of the named archives.
+///
+/// Returns the total number of files in the new archive.
 ///
-#[derive(Clone, Debug)]
-pub struct Entry {
-    pub id: i64,
-    pub parent: i64,
-    pub kind: i8,
-    pub name: String,
+fn merge_archives<P: AsRef<Path>>(
+    pack: P,
+    inputs: Vec<&PathBuf>,
+    policy: MergePolicy,
+    memory_limit: Option<u64>,
+) -> Result<u64, Error> {
+    let mut builder = PackBuilder::new()?;
+    if let Some(limit) = memory_limit {
+        builder.set_memory_limit(limit);
+    }
+    let mut file_count: u64 = 0;
+    for input in inputs {
+        file_count += builder.merge_archive(input, policy)?;
+    }
+    builder.finish(pack)?;
+    Ok(file_count)
 }
 
-// Result from the IndexedFiles temporary table joined with itemcontent table.
-#[derive(Debug)]
-struct IndexedFile {
-    content: i64,
-    contentpos: u64,
-    itempos: u64,
-    size: u64,
-    kind: i8,
-    path: String,
+///
+/// Write the contents of an existing archive to a new archive, compressing
+/// them anew according to the options given on the command line, or copying
+/// the content bundles as-is if the compression is not to change.
+///
+/// Returns the summary of the new archive.
+///
+fn repack_archive(
+    source: &str,
+    pack: &Path,
+    matches: &clap::ArgMatches,
+) -> Result<CreateReport, Error> {
+    let reader = open_reader_for_content(source)?;
+    let mut builder = PackBuilder::new()?;
+    builder.set_performance_profile(performance_profile(matches))?;
+    if let Some(limit) = matches.get_one::<u64>("memory-limit") {
+        builder.set_memory_limit(*limit);
+    }
+    if let Some(size) = matches.get_one::<u64>("bundle-size") {
+        builder.set_bundle_size(*size);
+    }
+    // keep the digest algorithm of the archive unless told otherwise
+    let digest = match matches.get_one::<String>("digest") {
+        Some(_) => digest_algorithm(matches),
+        None => reader.digest_algorithm()?,
+    };
+    builder.set_digest_algorithm(digest)?;
+    builder.set_content_addressed(matches.get_flag("content-addressed"))?;
+    // the new archive is encrypted with the same password, but a new key
+    if reader.is_encrypted() {
+        builder.encrypt_content(&require_password(false)?)?;
+    }
+    // unless the compression is to change, the bundles can be copied as-is
+    let mut recompress = matches.get_flag("recompress")
+        || matches.get_flag("adaptive")
+        || matches.contains_id("bundle-size");
+    if let Some(codec) = zstd_codec(matches, matches.get_one::<i32>("level").copied()) {
+        builder.set_codec(Box::new(codec));
+        recompress = true;
+    }
+    builder.set_adaptive_compression(matches.get_flag("adaptive"));
+    if let Some(threads) = matches.get_one::<u32>("cpu-limit") {
+        builder.set_cpu_limit(*threads);
+    }
+    if recompress {
+        pack_rs::repack(&reader, &mut builder)?;
+    } else {
+        pack_rs::repack_bundles(&reader, &mut builder)?;
+    }
+    builder.finish(pack)
 }
 
-struct OutgoingContent {
-    // rowid of the content in the content table
-    content: i64,
-    // offset within the content bundle where the data will go
-    contentpos: u64,
-    // size of the item content
-    size: u64,
+///
+/// Write each directory at the top level of the archive to an archive of its
+/// own in the output directory, named after the directory, copying the
+/// content bundles that hold nothing of the other directories. Entries at the
+/// top level that are not directories are left out with a warning.
+///
+/// Returns the number of archives written.
+///
+fn split_archive(pack: &str, outdir: &Path) -> Result<u64, Error> {
+    let reader = open_reader_for_content(pack)?;
+    fs::create_dir_all(outdir)?;
+    let mut count: u64 = 0;
+    for entry in reader.list_dir("", 0, u64::MAX)? {
+        let name = entry.name.trim_end_matches('/');
+        if entry.kind != ItemKind::Directory {
+            eprintln!("warning: left out {}, which is not a directory", name);
+            continue;
+        }
+        let mut builder = PackBuilder::new()?;
+        builder.set_digest_algorithm(reader.digest_algorithm()?)?;
+        if reader.is_encrypted() {
+            builder.encrypt_content(&require_password(false)?)?;
+        }
+        let file_count = pack_rs::repack_subtree(&reader, &mut builder, name)?;
+        let path = pack_rs::resolve_beneath(outdir, format!("{}.db3", name))?;
+        builder.finish(&path)?;
+        println!("Wrote {} files to {}", file_count, path.display());
+        count += 1;
+    }
+    Ok(count)
 }
 
-fn cli() -> Command {
-    Command::new("pack-rs")
-        .about("Archiver/compressor")
-        .subcommand_required(true)
-        .arg_required_else_help(true)
-        .subcommand(
-            Command::new("create")
-                .about("Creates an archive from a set of files.")
-                .short_flag('c')
-                .arg(arg!(pack: <PACK> "File path to which the archive will be written."))
-                .arg(
-                    arg!(<INPUTS> ... "Files to add to archive")
-                        .value_parser(clap::value_parser!(PathBuf)),
-                )
-                .arg_required_else_help(true),
-        )
-        .subcommand(
-            Command::new("list")
-                .about("Lists the contents of an archive.")
-                .short_flag('l')
-                .arg(arg!(pack: <PACK> "File path specifying the archive to read from."))
-                .arg_required_else_help(true),
-        )
-        .subcommand(
-            Command::new("extract")
-                .about("Extracts one or more files from an archive.")
-                .short_flag('x')
-                .arg(arg!(pack: <PACK> "File path specifying the archive to read from."))
-                .arg_required_else_help(true),
-        )
+///
+/// Create a pack file at the given location from the contents of a ZIP file.
+///
+/// Returns the number of files added to the archive.
+///
+fn import_zip<P: AsRef<Path>>(
+    pack: P,
+    zipfile: &Path,
+    memory_limit: Option<u64>,
+) -> Result<u64, Error> {
+    let mut builder = PackBuilder::new()?;
+    if let Some(limit) = memory_limit {
+        builder.set_memory_limit(limit);
+    }
+    let file_count = builder.add_zip_archive(zipfile)?;
+    builder.finish(pack)?;
+    Ok(file_count)
 }
 
-fn main() -> Result<(), Error> {
-    let matches = cli().get_matches();
-    match matches.subcommand() {
-        Some(("create", sub_matches)) => {
-            let pack = sub_matches
-                .get_one::<String>("pack")
-                .map(|s| s.as_str())
-                .unwrap_or("pack.db3");
-            let inputs = sub_matches
-                .get_many::<PathBuf>("INPUTS")
-                .into_iter()
-                .flatten()
-                .collect::<Vec<_>>();
-            let file_count = create_archive(pack, inputs)?;
-            println!("Added {} files to {}", file_count, pack);
+///
+/// Open the archive for reading, which may also be given as an HTTP or HTTPS
+/// URL when built with the `remote` feature, or be embedded at the end of
+/// another file, such as a self-extracting executable.
+///
+fn open_reader(pack: &str) -> Result<PackReader, Error> {
+    let mut reader = open_reader_unlocked(pack)?;
+    if reader.is_encrypted() {
+        if let Some(password) = password()? {
+            reader.set_password(&password)?;
         }
-        Some(("list", sub_matches)) => {
-            let pack = sub_matches
-                .get_one::<String>("pack")
-                .map(|s| s.as_str())
+    }
+    Ok(reader)
+}
+
+///
+/// Open the archive for reading the content of its files, prompting for the
+/// password if the content is encrypted and it was not given otherwise.
+///
+fn open_reader_for_content(pack: &str) -> Result<PackReader, Error> {
+    let mut reader = open_reader_unlocked(pack)?;
+    if reader.is_encrypted() {
+        reader.set_password(&require_password(false)?)?;
+    }
+    Ok(reader)
+}
+
+///
+/// Open the archive for reading, without providing the password for any
+/// encrypted content.
+///
+fn open_reader_unlocked(pack: &str) -> Result<PackReader, Error> {
+    #[cfg(feature = "remote")]
+    if pack.starts_with("http://") || pack.starts_with("https://") {
+        return PackReader::open_url(pack);
+    }
+    if !pack_rs::is_pack_file(pack)? {
+        return PackReader::open_embedded(pack);
+    }
+    PackReader::open_read_only(pack)
+}
+
+// File named by the --password-file option, if given.
+static PASSWORD_FILE: OnceLock<PathBuf> = OnceLock::new();
+
+// Password entered at the prompt, such that it is asked for only once.
+static ENTERED_PASSWORD: OnceLock<String> = OnceLock::new();
+
+///
+/// Return the password for encrypted archives, as given by the first line of
+/// the file named by `--password-file`, the `PACKRS_PASSWORD` or
+/// `PACK_PASSWORD` environment variable, or the keyring of the operating
+/// system (with the `keyring` feature), in that order, or as entered at an
+/// earlier prompt.
+///
+fn password() -> Result<Option<String>, Error> {
+    if let Some(path) = PASSWORD_FILE.get() {
+        let contents = fs::read_to_string(path)?;
+        let line = contents.lines().next().unwrap_or("");
+        if !line.is_empty() {
+            return Ok(Some(line.to_owned()));
+        }
+    }
+    if let Some(password) = env_password(&["PACKRS_PASSWORD", "PACK_PASSWORD"]) {
+        return Ok(Some(password));
+    }
+    #[cfg(feature = "keyring")]
+    if let Some(password) = keyring_password() {
+        return Ok(Some(password));
+    }
+    Ok(ENTERED_PASSWORD.get().cloned())
+}
+
+///
+/// Return the value of the first of the named environment variables that is
+/// set and not empty.
+///
+fn env_password(names: &[&str]) -> Option<String> {
+    names
+        .iter()
+        .find_map(|name| std::env::var(name).ok().filter(|p| !p.is_empty()))
+}
+
+///
+/// Return the password for encrypted archives as with `password()`, or else
+/// prompt for it at the terminal, asking a second time to confirm a password
+/// that is about to be set.
+///
+fn require_password(confirm: bool) -> Result<String, Error> {
+    if let Some(password) = password()? {
+        return Ok(password);
+    }
+    let password = prompt_password("Password: ", confirm)?;
+    Ok(ENTERED_PASSWORD.get_or_init(|| password).clone())
+}
+
+///
+/// Prompt for a password at the terminal without showing what is typed,
+/// asking a second time if `confirm` is true. Standard input is left alone,
+/// such that it may be used for other purposes, as with `--files-from -`.
+///
+/// Returns `Error::PasswordRequired` if there is no terminal.
+///
+fn prompt_password(prompt: &str, confirm: bool) -> Result<String, Error> {
+    let password = read_hidden(prompt)?;
+    if password.is_empty() {
+        return Err(Error::PasswordRequired);
+    }
+    if confirm && read_hidden("Confirm password: ")? != password {
+        return Err(
+            io::Error::new(io::ErrorKind::InvalidInput, "the passwords do not match").into(),
+        );
+    }
+    Ok(password)
+}
+
+// Read a line from the terminal with echo turned off.
+#[cfg(unix)]
+fn read_hidden(prompt: &str) -> Result<String, Error> {
+    use std::io::BufRead;
+    use std::os::fd::AsRawFd;
+    let Ok(mut tty) = fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open("/dev/tty")
+    else {
+        return Err(Error::PasswordRequired);
+    };
+    let fd = tty.as_raw_fd();
+    let mut term: libc::termios = unsafe { std::mem::zeroed() };
+    if unsafe { libc::tcgetattr(fd, &mut term) } != 0 {
+        return Err(Error::PasswordRequired);
+    }
+    let saved = term;
+    term.c_lflag &= !libc::ECHO;
+    term.c_lflag |= libc::ECHONL;
+    unsafe { libc::tcsetattr(fd, libc::TCSANOW, &term) };
+    let result = write!(tty, "{}", prompt)
+        .and_then(|_| tty.flush())
+        .and_then(|_| {
+            let mut line = String::new();
+            io::BufReader::new(&tty).read_line(&mut line)?;
+            Ok(line)
+        });
+    unsafe { libc::tcsetattr(fd, libc::TCSANOW, &saved) };
+    Ok(result?.trim_end_matches(['\n', '\r']).to_owned())
+}
+
+// Without a way to turn off echo, the password must be given some other way.
+#[cfg(not(unix))]
+fn read_hidden(_prompt: &str) -> Result<String, Error> {
+    Err(Error::PasswordRequired)
+}
+
+///
+/// Return the password stored in the keyring of the operating system under
+/// the service name `pack-rs`, if any, by way of the `security` command on
+/// macOS and the `secret-tool` command (of libsecret) on other systems.
+///
+#[cfg(feature = "keyring")]
+fn keyring_password() -> Option<String> {
+    let output = if cfg!(target_os = "macos") {
+        std::process::Command::new("security")
+            .args(["find-generic-password", "-s", "pack-rs", "-w"])
+            .output()
+    } else {
+        std::process::Command::new("secret-tool")
+            .args(["lookup", "service", "pack-rs"])
+            .output()
+    };
+    let output = output.ok().filter(|o| o.status.success())?;
+    let password = String::from_utf8(output.stdout).ok()?;
+    let password = password.trim_end_matches(['\n', '\r']);
+    (!password.is_empty()).then(|| password.to_owned())
+}
+
+///
+/// Change the password of an encrypted archive to the one in
+/// `PACKRS_NEW_PASSWORD` or `PACK_NEW_PASSWORD`, or else to one entered at
+/// the terminal, without encrypting the content again.
+///
+fn rekey_archive(pack: &str) -> Result<(), Error> {
+    let current = require_password(false)?;
+    let new = match env_password(&["PACKRS_NEW_PASSWORD", "PACK_NEW_PASSWORD"]) {
+        Some(new) => new,
+        None => prompt_password("New password: ", true)?,
+    };
+    let mut builder = PackBuilder::open(pack)?;
+    builder.change_password(&current, &new)?;
+    builder.commit()?;
+    Ok(())
+}
+
+///
+/// Write the contents of the archive to a new ZIP or tar file, or to standard
+/// output if the path is `-`.
+///
+/// Returns the number of files written.
+///
+fn export_archive(pack: &str, output: &Path, tar: bool) -> Result<u64, Error> {
+    let reader = open_reader_for_content(pack)?;
+    let writer: Box<dyn Write> = if output == Path::new("-") {
+        Box::new(io::stdout().lock())
+    } else {
+        Box::new(fs::File::create(output)?)
+    };
+    if tar {
+        reader.export_tar(writer)
+    } else {
+        reader.export_zip(writer)
+    }
+}
+
+///
+/// List the file entries in the archive selected by the options, in
+/// breadth-first order unless the options say otherwise, optionally with the
+/// value of each symbolic link. Directories are listed only when the options
+/// select entries of a particular kind.
+///
+fn list_contents(
+    pack: &str,
+    options: &ListOptions,
+    long: bool,
+    style: QuotingStyle,
+) -> Result<(), Error> {
+    let reader = open_reader(pack)?;
+    let entries = reader.entries_with(options)?;
+    for result in entries {
+        let entry = result?;
+        let name = quote(entry.name.as_bytes(), style);
+        if long && entry.kind == ItemKind::Symlink {
+            let mut target: Vec<u8> = vec![];
+            reader.read_item(entry.id, &mut target)?;
+            println!("{} -> {}", name, quote(&target, style))
+        } else if entry.kind != ItemKind::Directory || options.kind.is_some() {
+            println!("{}", name)
+        }
+    }
+    Ok(())
+}
+
+///
+/// Print the hierarchy of items in the archive, with the total size of each
+/// directory.
+///
+fn list_tree(pack: &str, style: QuotingStyle) -> Result<(), Error> {
+    let reader = open_reader(pack)?;
+    let tree = reader.tree()?;
+    let total: u64 = tree.iter().map(|n| n.size).sum();
+    println!(". ({})", format_size(total));
+    print_tree_nodes(&tree, "", style);
+    Ok(())
+}
+
+// Print the nodes and their children, drawing the branches of the tree.
+fn print_tree_nodes(nodes: &[TreeNode], prefix: &str, style: QuotingStyle) {
+    for (index, node) in nodes.iter().enumerate() {
+        let last = index + 1 == nodes.len();
+        let branch = if last { "└── " } else { "├── " };
+        let slash = if node.kind == ItemKind::Directory {
+            "/"
+        } else {
+            ""
+        };
+        println!(
+            "{}{}{}{} ({})",
+            prefix,
+            branch,
+            quote(node.name.as_bytes(), style),
+            slash,
+            format_size(node.size)
+        );
+        if !node.children.is_empty() {
+            let indent = if last { "    " } else { "│   " };
+            print_tree_nodes(&node.children, &format!("{}{}", prefix, indent), style);
+        }
+    }
+}
+
+// Format the number of bytes using binary units.
+fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["KiB", "MiB", "GiB", "TiB", "PiB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut value = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while value >= 1024.0 && unit + 1 < UNITS.len() {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", value, UNITS[unit])
+}
+
+///
+/// Write the content of the file identified by either its item identifier
+/// or its path to standard output.
+///
+fn print_item(
+    pack: &str,
+    item_id: Option<i64>,
+    path: Option<&String>,
+    ignore_case: bool,
+    cache: Option<(&PathBuf, u64)>,
+) -> Result<(), Error> {
+    let mut reader = open_reader_for_content(pack)?;
+    reader.set_ignore_case(ignore_case);
+    if let Some((dir, limit)) = cache {
+        reader.set_cache_dir(dir, limit)?;
+    }
+    let mut output = io::BufWriter::new(io::stdout().lock());
+    match (item_id, path) {
+        (Some(item_id), _) => reader.read_item(item_id, &mut output)?,
+        (None, Some(path)) => reader.read_path(path, &mut output)?,
+        (None, None) => unreachable!(),
+    }
+    output.flush()?;
+    Ok(())
+}
+
+///
+/// Write the completion script for the given shell to standard output. For
+/// Bash and fish, the paths of entries within an archive are completed by
+/// calling the hidden `complete-path` subcommand.
+///
+fn print_completions(shell: Shell) -> Result<(), Error> {
+    let mut output = io::stdout().lock();
+    clap_complete::generate(shell, &mut cli(), "pack-rs", &mut output);
+    match shell {
+        Shell::Bash => output.write_all(BASH_PATH_COMPLETION.as_bytes())?,
+        Shell::Fish => output.write_all(FISH_PATH_COMPLETION.as_bytes())?,
+        _ => (),
+    }
+    Ok(())
+}
+
+// Completes the PATH of the cat command, and the PATHS and --dir value of the
+// extract command, from the entries in the archive, which is the first
+// argument after the command that is not an option or the value of one,
+// deferring to the generated function for everything else.
+const BASH_PATH_COMPLETION: &str = r#"
+_pack__rs_paths() {
+    local pack="" options="" word i count=0 paths=0
+    local prev="${COMP_WORDS[COMP_CWORD-1]}"
+    case "${COMP_WORDS[1]}" in
+        cat)
+            options=" --id --cache-dir --cache-limit "
+            ;;
+        extract|-x)
+            options=" --dir --max-size --max-files --max-file-size --max-depth --exec \
+                --case-collisions --rewrite-links --owner-map --group-map --profile "
+            ;;
+    esac
+    if [[ -n "$options" ]]; then
+        for ((i = 2; i < ${#COMP_WORDS[@]}; i++)); do
+            [[ $i -eq $COMP_CWORD ]] && continue
+            word="${COMP_WORDS[i]}"
+            if [[ "$options" == *" $word "* ]]; then
+                ((i++))
+            elif [[ "$word" != -* ]]; then
+                ((i < COMP_CWORD)) && ((count++))
+                [[ -z "$pack" ]] && pack="$word"
+            fi
+        done
+        if [[ "$options" == *" $prev "* ]]; then
+            [[ "$prev" == "--dir" ]] && paths=1
+        elif [[ "${COMP_WORDS[1]}" == "cat" ]]; then
+            ((count == 1)) && paths=1
+        else
+            ((count >= 1)) && paths=1
+        fi
+    fi
+    if [[ $paths -eq 1 && -f "$pack" ]]; then
+        local IFS=$'
+'
+        COMPREPLY=($(pack-rs complete-path -- "$pack" "${COMP_WORDS[COMP_CWORD]}"))
+        compopt -o nospace 2>/dev/null
+        return 0
+    fi
+    _pack__rs "$@"
+}
+complete -F _pack__rs_paths -o nosort -o bashdefault -o default pack-rs
+"#;
+
+// Completes the PATH of the cat command, and the PATHS and --dir value of the
+// extract command, once the archive has been given.
+const FISH_PATH_COMPLETION: &str = r#"
+function __pack_rs_archive
+    set -l before (commandline -opc)
+    set -l options
+    switch "$before[2]"
+        case cat
+            set options --id --cache-dir --cache-limit
+        case extract -x
+            set options --dir --max-size --max-files --max-file-size --max-depth --exec \
+                --case-collisions --rewrite-links --owner-map --group-map --profile
+        case '*'
+            return 1
+    end
+    # the archive may follow the cursor when completing the value of --dir
+    set -l pack
+    set -l skip 0
+    for arg in (commandline -op)[3..-1]
+        if test $skip -eq 1
+            set skip 0
+        else if contains -- $arg $options
+            set skip 1
+        else if not string match -q -- '-*' $arg
+            set pack $arg
+            break
+        end
+    end
+    set -l count 0
+    set skip 0
+    for arg in $before[3..-1]
+        if test $skip -eq 1
+            set skip 0
+        else if contains -- $arg $options
+            set skip 1
+        else if not string match -q -- '-*' $arg
+            set count (math $count + 1)
+        end
+    end
+    if test $skip -eq 1
+        test "$before[-1]" = --dir; or return 1
+    else if test "$before[2]" = cat
+        test $count -eq 1; or return 1
+    else
+        test $count -ge 1; or return 1
+    end
+    test -n "$pack"; and test -f $pack; and echo $pack
+end
+complete -c pack-rs -n '__pack_rs_archive >/dev/null' -f -a '(pack-rs complete-path -- (__pack_rs_archive) (commandline -ct))'
+"#;
+
+///
+/// Print the paths of the entries in the archive that could complete the
+/// given partial path, one per line.
+///
+fn print_path_completions(pack: &str, partial: &str) -> Result<(), Error> {
+    let reader = open_reader(pack)?;
+    let mut output = io::stdout().lock();
+    for path in reader.complete_path(partial)? {
+        writeln!(output, "{}", path)?;
+    }
+    Ok(())
+}
+
+///
+/// Print the changes recorded in the audit table of the archive, oldest
+/// first, along with the paths of the entries affected if `long` is true.
+///
+fn print_history(pack: &str, long: bool) -> Result<(), Error> {
+    let reader = open_reader_unlocked(pack)?;
+    let history = reader.history()?;
+    if history.is_empty() {
+        println!("No changes recorded");
+    }
+    for record in history {
+        println!(
+            "{}  {:<12}  {:>6} entries  {}",
+            record.time,
+            record.operation,
+            record.paths.len(),
+            record.tool
+        );
+        if long {
+            for path in record.paths {
+                println!("    {}", path);
+            }
+        }
+    }
+    Ok(())
+}
+
+///
+/// Print the format, size, and origin of the archive, along with the number
+/// of items and content bundles.
+///
+fn print_summary(pack: &str) -> Result<(), Error> {
+    let reader = open_reader(pack)?;
+    let info = reader.info()?;
+    println!("Format version: {}", info.format_version);
+    println!("Application ID: {:#010x}", info.application_id);
+    println!("Items: {}", info.items);
+    println!("Bundles: {}", info.bundles);
+    println!("Compressed: {} bytes", info.compressed);
+    println!(
+        "Uncompressed: {} bytes ({:.1}%)",
+        info.uncompressed,
+        percentage(info.compressed, info.uncompressed)
+    );
+    if info.codecs.is_empty() {
+        println!("Codecs: (none)");
+    } else {
+        println!("Codecs: {}", info.codecs.join(", "));
+    }
+    println!("Layout: {}", info.layout.as_deref().unwrap_or("standard"));
+    println!("Digest: {}", info.digest);
+    println!(
+        "Encryption: {}",
+        info.encryption.as_deref().unwrap_or("none")
+    );
+    println!("Created: {}", info.created.as_deref().unwrap_or("unknown"));
+    println!("Creator: {}", info.creator.as_deref().unwrap_or("unknown"));
+    Ok(())
+}
+
+///
+/// Print the space used by the content bundles and the largest files.
+///
+fn print_space_report(pack: &str) -> Result<(), Error> {
+    let reader = open_reader(pack)?;
+    let report = reader.space_report()?;
+    println!(
+        "{:>8} {:>8} {:>14} {:>14} {:>7}",
+        "bundle", "entries", "compressed", "uncompressed", "ratio"
+    );
+    let mut entries: u64 = 0;
+    let mut compressed: u64 = 0;
+    let mut uncompressed: u64 = 0;
+    for bundle in report.bundles.iter() {
+        println!(
+            "{:>8} {:>8} {:>14} {:>14} {:>6.1}%",
+            bundle.id,
+            bundle.entries,
+            bundle.compressed,
+            bundle.uncompressed,
+            percentage(bundle.compressed, bundle.uncompressed)
+        );
+        entries += bundle.entries;
+        compressed += bundle.compressed;
+        uncompressed += bundle.uncompressed;
+    }
+    println!(
+        "{:>8} {:>8} {:>14} {:>14} {:>6.1}%",
+        "total",
+        entries,
+        compressed,
+        uncompressed,
+        percentage(compressed, uncompressed)
+    );
+    println!();
+    println!("Dedup savings: {} bytes", report.dedup_savings);
+    println!();
+    println!("Largest files:");
+    for (path, size) in report.largest.iter() {
+        println!("{:>14} {}", size, path);
+    }
+    Ok(())
+}
+
+///
+/// Print the number and total size of the files for each extension and each
+/// detected type of content.
+///
+fn print_type_report(pack: &str) -> Result<(), Error> {
+    let reader = open_reader(pack)?;
+    let report = reader.type_report()?;
+    let total: u64 = report.extensions.iter().map(|s| s.bytes).sum();
+    for (index, (heading, stats, unknown)) in [
+        ("extension", &report.extensions, "(none)"),
+        ("type", &report.mime_types, "(unknown)"),
+    ]
+    .iter()
+    .enumerate()
+    {
+        if index > 0 {
+            println!();
+        }
+        println!("{:>8} {:>14} {:>7}  {}", "files", "bytes", "share", heading);
+        for entry in stats.iter() {
+            println!(
+                "{:>8} {:>14} {:>6.1}%  {}",
+                entry.files,
+                entry.bytes,
+                percentage(entry.bytes, total),
+                entry.name.as_deref().unwrap_or(unknown)
+            );
+        }
+    }
+    Ok(())
+}
+
+///
+/// Print the sets of files that have identical content, with the bytes that
+/// could be reclaimed by keeping only one of each.
+///
+fn print_duplicates(pack: &str) -> Result<(), Error> {
+    let reader = open_reader(pack)?;
+    let sets = reader.duplicates()?;
+    let mut reclaimable: u64 = 0;
+    for set in sets.iter() {
+        let digest: String = set.checksum.iter().map(|b| format!("{:02x}", b)).collect();
+        println!(
+            "{} files of {} bytes, {} reclaimable ({})",
+            set.paths.len(),
+            set.size,
+            set.reclaimable(),
+            &digest[..digest.len().min(16)]
+        );
+        for path in set.paths.iter() {
+            println!("  {}", path);
+        }
+        reclaimable += set.reclaimable();
+    }
+    if sets.is_empty() {
+        println!("No duplicate files");
+    } else {
+        println!();
+        println!(
+            "Duplicate sets: {}, reclaimable: {} bytes",
+            sets.len(),
+            reclaimable
+        );
+        if !reader.is_content_addressed()? {
+            println!("Use create --content-addressed to store identical files only once");
+        }
+    }
+    Ok(())
+}
+
+//
+// Return the first value as a percentage of the second.
+//
+fn percentage(part: u64, whole: u64) -> f64 {
+    if whole == 0 {
+        0.0
+    } else {
+        part as f64 * 100.0 / whole as f64
+    }
+}
+
+///
+/// Print a JSON manifest of every entry in the archive.
+///
+fn print_manifest(pack: &str) -> Result<(), Error> {
+    let reader = open_reader(pack)?;
+    let manifest = reader.manifest()?;
+    let json = serde_json::to_string_pretty(&manifest).map_err(io::Error::from)?;
+    println!("{}", json);
+    Ok(())
+}
+
+///
+/// Read the manifest produced by the manifest command.
+///
+fn read_manifest(manifest: &Path) -> Result<Vec<ManifestEntry>, Error> {
+    let text = fs::read_to_string(manifest)?;
+    let entries: Vec<ManifestEntry> = serde_json::from_str(&text).map_err(io::Error::from)?;
+    Ok(entries)
+}
+
+///
+/// Compare the tree at the given location against the manifest entries,
+/// printing each of the discrepancies that were found.
+///
+/// Returns the number of discrepancies.
+///
+fn verify_tree(entries: &[ManifestEntry], root: &Path) -> Result<usize, Error> {
+    let found = pack_rs::verify_manifest(entries, root)?;
+    for discrepancy in found.iter() {
+        println!("{}", discrepancy);
+    }
+    Ok(found.len())
+}
+
+///
+/// Check the structure and content bundles of the archive, printing each of
+/// the problems that were found.
+///
+/// Returns the number of problems.
+///
+fn verify_archive(pack: &str) -> Result<usize, Error> {
+    let found = open_reader(pack)?.check_archive()?;
+    for violation in found.iter() {
+        println!("{}", violation);
+    }
+    Ok(found.len())
+}
+
+///
+/// Add the archives to the catalog, printing the number of files in each.
+///
+fn catalog_add(catalog: &Path, archives: Vec<&String>) -> Result<(), Error> {
+    let mut catalog = Catalog::open(catalog)?;
+    for archive in archives {
+        let count = catalog.add_archive(archive)?;
+        println!("Added {} files from {}", count, archive);
+    }
+    Ok(())
+}
+
+///
+/// Print the archive and path of every file in the catalog that matches the
+/// pattern, or has the given checksum, returning the number that were found.
+///
+fn catalog_search(
+    catalog: &Path,
+    pattern: &str,
+    checksum: bool,
+    style: QuotingStyle,
+) -> Result<usize, Error> {
+    let catalog = Catalog::open(catalog)?;
+    let found = if checksum {
+        catalog.search_checksum(pattern)?
+    } else {
+        catalog.search(pattern)?
+    };
+    for entry in found.iter() {
+        println!(
+            "{}: {}",
+            quote(entry.archive.as_bytes(), style),
+            quote(entry.path.as_bytes(), style)
+        );
+    }
+    Ok(found.len())
+}
+
+///
+/// Extract the files from the archive, either all of them, those within the
+/// directory, or those selected by the paths and patterns, with the limits,
+/// command, and other reader settings given in the matches.
+///
+fn extract_contents(
+    pack: &str,
+    dir: Option<&String>,
+    paths: &[&str],
+    options: ExtractOptions,
+    matches: &clap::ArgMatches,
+) -> Result<ExtractReport, Error> {
+    let mut reader = open_reader_for_content(pack)?;
+    reader.set_performance_profile(performance_profile(matches))?;
+    reader.set_extract_limits(ExtractLimits {
+        max_total_size: matches.get_one::<u64>("max-size").copied(),
+        max_files: matches.get_one::<u64>("max-files").copied(),
+        max_file_size: matches.get_one::<u64>("max-file-size").copied(),
+        max_depth: matches.get_one::<u64>("max-depth").copied(),
+    });
+    reader.set_ignore_case(matches.get_flag("ignore-case"));
+    if let Some(command) = matches.get_one::<String>("exec") {
+        let command = command.to_owned();
+        reader.set_on_file_extracted(move |path| run_command(&command, path));
+    }
+    reader.set_on_warning(print_warning);
+    match dir {
+        Some(dir) => reader.extract_subtree_with(dir, &options),
+        None if !paths.is_empty() => reader.extract_matching_with(paths, &options),
+        None => reader.extract_all_with(&options),
+    }
+}
+
+///
+/// Run the command for the given file, substituting the path for every `{}`
+/// in the command, or appending the path if there are none. The command is
+/// split on whitespace and run directly, without a shell.
+///
+fn run_command(command: &str, path: &Path) -> Result<(), Error> {
+    let mut words = command.split_whitespace();
+    let program = words
+        .next()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "empty command"))?;
+    let mut args: Vec<std::ffi::OsString> = vec![];
+    let mut substituted = false;
+    for word in words {
+        if word.contains("{}") {
+            // built from the raw path, which need not be valid UTF-8
+            let mut arg = std::ffi::OsString::new();
+            for (index, piece) in word.split("{}").enumerate() {
+                if index > 0 {
+                    arg.push(path);
+                }
+                arg.push(piece);
+            }
+            args.push(arg);
+            substituted = true;
+        } else {
+            args.push(word.into());
+        }
+    }
+    if !substituted {
+        args.push(path.as_os_str().to_owned());
+    }
+    let status = std::process::Command::new(program).args(&args).status()?;
+    if !status.success() {
+        let message = format!("{} exited with {} for {}", program, status, path.display());
+        return Err(io::Error::other(message).into());
+    }
+    Ok(())
+}
+
+fn on_error_arg() -> clap::Arg {
+    arg!(--"on-error" <POLICY> "What to do with files and directories that cannot be read")
+        .value_parser(["fail", "warn", "skip"])
+        .default_value("fail")
+}
+
+fn io_limit_arg() -> clap::Arg {
+    arg!(--"io-limit" <RATE> "Limit reading of files to RATE bytes per second (e.g. 20M)")
+        .value_parser(parse_rate)
+}
+
+fn memory_limit_arg() -> clap::Arg {
+    arg!(--"memory-limit" <SIZE> "Build on disk once the archive exceeds SIZE")
+        .value_parser(parse_size)
+}
+
+fn cpu_limit_arg() -> clap::Arg {
+    arg!(--"cpu-limit" <THREADS> "Use at most THREADS threads for compression")
+        .value_parser(clap::value_parser!(u32).range(1..))
+}
+
+fn profile_arg() -> clap::Arg {
+    arg!(--profile <PROFILE> "Database settings, trading durability for speed")
+        .value_parser(["safe", "balanced", "fast"])
+        .default_value("balanced")
+}
+
+///
+/// Return the performance profile named by the `--profile` option.
+///
+fn performance_profile(matches: &clap::ArgMatches) -> PerformanceProfile {
+    profile_by_name(
+        matches
+            .get_one::<String>("profile")
+            .map_or("", |s| s.as_str()),
+    )
+}
+
+///
+/// Return the performance profile with the given name, defaulting to the
+/// balanced profile.
+///
+fn profile_by_name(name: &str) -> PerformanceProfile {
+    match name {
+        "safe" => PerformanceProfile::Safe,
+        "fast" => PerformanceProfile::Fast,
+        _ => PerformanceProfile::Balanced,
+    }
+}
+
+///
+/// Return the name of the performance profile, as given to `--profile`.
+///
+fn profile_name(profile: PerformanceProfile) -> &'static str {
+    match profile {
+        PerformanceProfile::Safe => "safe",
+        PerformanceProfile::Balanced => "balanced",
+        PerformanceProfile::Fast => "fast",
+    }
+}
+
+fn quoting_style_arg() -> clap::Arg {
+    arg!(--"quoting-style" <STYLE> "How to write paths (default: shell-escape on a terminal)")
+        .value_parser(["literal", "shell-escape", "c"])
+}
+
+///
+/// How paths are written by `list` and `catalog search`, as named by the
+/// `--quoting-style` option.
+///
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum QuotingStyle {
+    // as they are, with invalid UTF-8 replaced
+    Literal,
+    // quoted for the shell when needed, with control characters and invalid
+    // UTF-8 written as $'\ooo'
+    Shell,
+    // within double quotes, with backslash escapes as in C
+    C,
+}
+
+///
+/// Return the quoting style named by the `--quoting-style` option, which
+/// defaults to escaping for the shell when writing to a terminal, such that
+/// control characters in paths cannot garble it, and otherwise to literal.
+///
+fn quoting_style(matches: &clap::ArgMatches) -> QuotingStyle {
+    match matches
+        .get_one::<String>("quoting-style")
+        .map(|s| s.as_str())
+    {
+        Some("literal") => QuotingStyle::Literal,
+        Some("shell-escape") => QuotingStyle::Shell,
+        Some("c") => QuotingStyle::C,
+        _ if io::stdout().is_terminal() => QuotingStyle::Shell,
+        _ => QuotingStyle::Literal,
+    }
+}
+
+///
+/// Return the name, which may not be valid UTF-8, written in the given style.
+///
+fn quote(name: &[u8], style: QuotingStyle) -> String {
+    match style {
+        QuotingStyle::Literal => String::from_utf8_lossy(name).into_owned(),
+        QuotingStyle::Shell => {
+            // characters that never need quoting for the shell
+            let plain = |c: char| c.is_alphanumeric() || "_-./+,:@%=".contains(c);
+            if let Ok(text) = std::str::from_utf8(name) {
+                if !text.is_empty() && text.chars().all(plain) {
+                    return text.to_owned();
+                }
+            }
+            // runs of printable characters go within single quotes, while
+            // everything else is written between them
+            let mut quoted = String::new();
+            let mut open = false;
+            let close = |quoted: &mut String, open: &mut bool| {
+                if *open {
+                    quoted.push('\'');
+                    *open = false;
+                }
+            };
+            for chunk in name.utf8_chunks() {
+                for c in chunk.valid().chars() {
+                    if c.is_control() {
+                        close(&mut quoted, &mut open);
+                        quoted.push_str("$'");
+                        push_escaped(&mut quoted, c.encode_utf8(&mut [0; 4]).as_bytes());
+                        quoted.push('\'');
+                    } else if c == '\'' {
+                        close(&mut quoted, &mut open);
+                        quoted.push_str("\\'");
+                    } else {
+                        if !open {
+                            quoted.push('\'');
+                            open = true;
+                        }
+                        quoted.push(c);
+                    }
+                }
+                if !chunk.invalid().is_empty() {
+                    close(&mut quoted, &mut open);
+                    quoted.push_str("$'");
+                    push_escaped(&mut quoted, chunk.invalid());
+                    quoted.push('\'');
+                }
+            }
+            close(&mut quoted, &mut open);
+            if quoted.is_empty() {
+                quoted.push_str("''");
+            }
+            quoted
+        }
+        QuotingStyle::C => {
+            let mut quoted = String::from("\"");
+            for chunk in name.utf8_chunks() {
+                for c in chunk.valid().chars() {
+                    if c == '"' {
+                        quoted.push_str("\\\"");
+                    } else if c == '\\' || c.is_control() {
+                        push_escaped(&mut quoted, c.encode_utf8(&mut [0; 4]).as_bytes());
+                    } else {
+                        quoted.push(c);
+                    }
+                }
+                push_escaped(&mut quoted, chunk.invalid());
+            }
+            quoted.push('"');
+            quoted
+        }
+    }
+}
+
+// Write the bytes as backslash escapes, using the short forms where C has
+// them and octal otherwise.
+fn push_escaped(quoted: &mut String, bytes: &[u8]) {
+    for byte in bytes {
+        match byte {
+            b'\\' => quoted.push_str("\\\\"),
+            b'\n' => quoted.push_str("\\n"),
+            b'\t' => quoted.push_str("\\t"),
+            b'\r' => quoted.push_str("\\r"),
+            0x07 => quoted.push_str("\\a"),
+            0x08 => quoted.push_str("\\b"),
+            0x0b => quoted.push_str("\\v"),
+            0x0c => quoted.push_str("\\f"),
+            _ => quoted.push_str(&format!("\\{:03o}", byte)),
+        }
+    }
+}
+
+fn digest_arg() -> clap::Arg {
+    arg!(--digest <ALGORITHM> "Algorithm for the file and bundle checksums")
+        .value_parser(["blake3", "sha256", "xxh3"])
+        .default_value("blake3")
+}
+
+///
+/// Return the order of adding files named by the `--sort-by` option.
+///
+fn sort_order(matches: &clap::ArgMatches) -> SortOrder {
+    match matches.get_one::<String>("sort-by").map(|s| s.as_str()) {
+        Some("extension") => SortOrder::Extension,
+        Some("name") => SortOrder::Name,
+        Some("size") => SortOrder::Size,
+        _ => SortOrder::None,
+    }
+}
+
+///
+/// Return the digest algorithm named by the `--digest` option.
+///
+fn digest_algorithm(matches: &clap::ArgMatches) -> DigestAlgorithm {
+    match matches.get_one::<String>("digest").map(|s| s.as_str()) {
+        Some("sha256") => DigestAlgorithm::Sha256,
+        Some("xxh3") => DigestAlgorithm::Xxh3,
+        _ => DigestAlgorithm::Blake3,
+    }
+}
+
+fn cli() -> Command {
+    Command::new("pack-rs")
+        .about("Archiver/compressor")
+        .subcommand_required(true)
+        .arg_required_else_help(true)
+        .arg(
+            arg!(-v --verbose ... "Log progress to standard error (repeat for more detail)")
+                .global(true),
+        )
+        .arg(
+            arg!(--"password-file" <FILE> "Read the password for encrypted content from FILE")
+                .value_parser(clap::value_parser!(PathBuf))
+                .global(true),
+        )
+        .subcommand(
+            Command::new("create")
+                .about("Creates an archive from a set of files.")
+                .short_flag('c')
+                .arg(
+                    arg!(--"exclude-from" <FILE> "Read exclusion patterns from the file")
+                        .value_parser(clap::value_parser!(PathBuf)),
+                )
+                .arg(arg!(--reproducible "Produce identical archives from identical inputs"))
+                .arg(
+                    arg!(--"sort-by" <ORDER> "Order in which to add files, grouping similar ones")
+                        .value_parser(["extension", "name", "size", "none"])
+                        .default_value("none"),
+                )
+                .arg(arg!(--"special-files" "Record named pipes and device nodes"))
+                .arg(
+                    arg!(--"max-depth" <N> "Leave out the contents of directories N levels down")
+                        .value_parser(clap::value_parser!(usize)),
+                )
+                .arg(arg!(--"one-file-system" "Stay within the file system of each input"))
+                .arg(arg!(--"mac-metadata" "Record resource forks and Finder metadata (macOS)"))
+                .arg(arg!(--verify "Verify the archive before moving it into place"))
+                .arg(arg!(--"concurrent-reads" "Build in place, letting others read as it grows"))
+                .arg(arg!(--"compress-all" "Compress files that appear to be incompressible"))
+                .arg(arg!(--adaptive "Adjust the compression level to the speed of the disk"))
+                .arg(arg!(--timings "Report the time spent in each phase of packing"))
+                .arg(arg!(--"content-addressed" "Store identical files only once, keyed by digest"))
+                .arg(
+                    arg!(--"no-content" "Record only the metadata and checksums of the files")
+                        .conflicts_with("content-addressed"),
+                )
+                .arg(arg!(--encrypt "Encrypt file content with the password in PACKRS_PASSWORD"))
+                .arg(
+                    arg!(--long [WINDOWLOG] "Enable long-distance matching (default window 27)")
+                        .value_parser(clap::value_parser!(u32).range(10..=31))
+                        .default_missing_value("27")
+                        .require_equals(true),
+                )
+                .arg(
+                    arg!(--"zstd-workers" <COUNT> "Number of threads used by Zstandard")
+                        .value_parser(clap::value_parser!(u32).range(1..)),
+                )
+                .arg(
+                    arg!(--"large-files" <SIZE> "Give files of at least SIZE their own bundles")
+                        .value_parser(parse_size),
+                )
+                .arg(
+                    arg!(--"large-level" <LEVEL> "Zstandard compression level for large files")
+                        .value_parser(clap::value_parser!(i32).range(1..=22))
+                        .requires("large-files"),
+                )
+                .arg(memory_limit_arg())
+                .arg(
+                    arg!(--"files-from" <FILE> "Add the paths listed in the file (- for stdin)")
+                        .value_parser(clap::value_parser!(PathBuf)),
+                )
+                .arg(
+                    arg!(--"from-tar" <FILE> "Add the entries of the tar file (- for stdin)")
+                        .value_parser(clap::value_parser!(PathBuf))
+                        .conflicts_with("files-from"),
+                )
+                .arg(
+                    arg!(--"git-rev" <REVISION> "Add the files of REVISION of each input repository")
+                        .conflicts_with("files-from"),
+                )
+                .arg(
+                    arg!(--prefix <DIR> "Directory within the archive in which to place the inputs")
+                        .conflicts_with("files-from"),
+                )
+                .arg(on_error_arg())
+                .arg(io_limit_arg())
+                .arg(cpu_limit_arg())
+                .arg(profile_arg())
+                .arg(digest_arg())
+                .arg(arg!(pack: <PACK> "File path to which the archive will be written."))
+                .arg(
+                    arg!([INPUTS] ... "Files to add to archive, or NAME=PATH to store PATH as NAME")
+                        .required_unless_present_any(["files-from", "from-tar"])
+                        .value_parser(clap::value_parser!(PathBuf)),
+                )
+                .arg_required_else_help(true),
+        )
+        .subcommand(
+            Command::new("update")
+                .about("Updates an archive to match a set of files.")
+                .short_flag('u')
+                .arg(on_error_arg())
+                .arg(io_limit_arg())
+                .arg(cpu_limit_arg())
+                .arg(arg!(--"concurrent-reads" "Let others read the archive while it is updated"))
+                .arg(arg!(pack: <PACK> "File path specifying the archive to update."))
+                .arg(
+                    arg!(<INPUTS> ... "Files to compare against the archive")
+                        .value_parser(clap::value_parser!(PathBuf)),
+                )
+                .arg_required_else_help(true),
+        )
+        .subcommand(
+            Command::new("merge")
+                .about("Merges several archives into a new archive.")
+                .arg(
+                    arg!(--policy <POLICY> "How to resolve entries with the same path")
+                        .value_parser(["error", "keep-first", "keep-newest"])
+                        .default_value("error"),
+                )
+                .arg(memory_limit_arg())
+                .arg(arg!(pack: <PACK> "File path to which the archive will be written."))
+                .arg(
+                    arg!(<INPUTS> ... "Archives to be merged")
+                        .value_parser(clap::value_parser!(PathBuf)),
+                )
+                .arg_required_else_help(true),
+        )
+        .subcommand(
+            Command::new("repack")
+                .about("Writes the contents of an archive to a new archive with other settings.")
+                .arg(
+                    arg!(--level <LEVEL> "Zstandard compression level")
+                        .value_parser(clap::value_parser!(i32).range(1..=22)),
+                )
+                .arg(
+                    arg!(--adaptive "Adjust the compression level to the speed of the disk")
+                        .conflicts_with("level"),
+                )
+                .arg(
+                    arg!(--"bundle-size" <SIZE> "Target size of the content bundles (default 16M)")
+                        .value_parser(parse_size),
+                )
+                .arg(arg!(--"content-addressed" "Store identical files only once, keyed by digest"))
+                .arg(arg!(--recompress "Compress all content anew, rather than copying bundles"))
+                .arg(
+                    arg!(--digest <ALGORITHM> "Algorithm for the checksums (default: as before)")
+                        .value_parser(["blake3", "sha256", "xxh3"]),
+                )
+                .arg(
+                    arg!(--long [WINDOWLOG] "Enable long-distance matching (default window 27)")
+                        .value_parser(clap::value_parser!(u32).range(10..=31))
+                        .default_missing_value("27")
+                        .require_equals(true),
+                )
+                .arg(
+                    arg!(--"zstd-workers" <COUNT> "Number of threads used by Zstandard")
+                        .value_parser(clap::value_parser!(u32).range(1..)),
+                )
+                .arg(memory_limit_arg())
+                .arg(cpu_limit_arg())
+                .arg(profile_arg())
+                .arg(arg!(<SOURCE> "Archive to be read"))
+                .arg(
+                    arg!(pack: <PACK> "File path to which the archive will be written.")
+                        .value_parser(clap::value_parser!(PathBuf)),
+                )
+                .arg_required_else_help(true),
+        )
+        .subcommand(
+            Command::new("split")
+                .about("Writes each top-level directory of an archive to an archive of its own.")
+                .arg(
+                    arg!(--"by-top-dir" <OUTDIR> "Directory to which the archives are written")
+                        .value_parser(clap::value_parser!(PathBuf))
+                        .required(true),
+                )
+                .arg(arg!(pack: <PACK> "File path specifying the archive to read from."))
+                .arg_required_else_help(true),
+        )
+        .subcommand(
+            Command::new("from-zip")
+                .about("Creates an archive from the contents of a ZIP file.")
+                .arg(memory_limit_arg())
+                .arg(arg!(pack: <PACK> "File path to which the archive will be written."))
+                .arg(arg!(<ZIP> "ZIP file to be read").value_parser(clap::value_parser!(PathBuf)))
+                .arg_required_else_help(true),
+        )
+        .subcommand(
+            Command::new("to-zip")
+                .about("Writes the contents of an archive to a ZIP file.")
+                .arg(arg!(pack: <PACK> "File path specifying the archive to read from."))
+                .arg(
+                    arg!(<ZIP> "ZIP file to be written (- for stdout)")
+                        .value_parser(clap::value_parser!(PathBuf)),
+                )
+                .arg_required_else_help(true),
+        )
+        .subcommand(
+            Command::new("to-tar")
+                .about("Writes the contents of an archive to a tar file.")
+                .arg(arg!(pack: <PACK> "File path specifying the archive to read from."))
+                .arg(
+                    arg!(<TAR> "tar file to be written (- for stdout)")
+                        .value_parser(clap::value_parser!(PathBuf)),
+                )
+                .arg_required_else_help(true),
+        )
+        .subcommand(
+            Command::new("list")
+                .about("Lists the contents of an archive.")
+                .short_flag('l')
+                .arg(arg!(--tree "Show the hierarchy of entries with directory sizes"))
+                .arg(arg!(--long "Show the value of each symbolic link"))
+                .arg(arg!(--prefix <PREFIX> "Show only the entries whose path begins with PREFIX"))
+                .arg(
+                    arg!(--kind <KIND> "Show only the entries of the given kind")
+                        .value_parser(["file", "dir", "link"]),
+                )
+                .arg(
+                    arg!(--sort <ORDER> "Order in which to show the entries")
+                        .value_parser(["path", "size", "mtime", "none"])
+                        .default_value("none"),
+                )
+                .arg(arg!(--reverse "Show the entries in reverse order"))
+                .arg(quoting_style_arg())
+                .arg(arg!(pack: <PACK> "File path specifying the archive to read from."))
+                .arg_required_else_help(true),
+        )
+        .subcommand(
+            Command::new("cat")
+                .about("Writes the content of a file in an archive to standard output.")
+                .arg(
+                    arg!(--id <ROWID> "Identifier of the item, instead of a path")
+                        .value_parser(clap::value_parser!(i64)),
+                )
+                .arg(arg!(--"ignore-case" "Match the path without regard to case"))
+                .arg(
+                    arg!(--"cache-dir" <DIR> "Keep decompressed bundles in DIR for later reads")
+                        .value_parser(clap::value_parser!(PathBuf)),
+                )
+                .arg(
+                    arg!(--"cache-limit" <SIZE> "Total size of the bundles kept in the cache")
+                        .value_parser(parse_size)
+                        .default_value("1G")
+                        .requires("cache-dir"),
+                )
+                .arg(arg!(pack: <PACK> "File path specifying the archive to read from."))
+                .arg(
+                    arg!([PATH] "Path of the file within the archive")
+                        .required_unless_present("id"),
+                )
+                .arg_required_else_help(true),
+        )
+        .subcommand(
+            Command::new("completions")
+                .about("Writes a shell completion script to standard output.")
+                .arg(
+                    arg!(shell: <SHELL> "Shell for which to generate the script")
+                        .value_parser(clap::value_parser!(Shell)),
+                )
+                .arg_required_else_help(true),
+        )
+        .subcommand(
+            // used by the completion scripts to complete paths within an archive
+            Command::new("complete-path")
+                .hide(true)
+                .arg(arg!(pack: <PACK> "File path specifying the archive to read from."))
+                .arg(arg!([PARTIAL] "Partial path of an entry within the archive")),
+        )
+        .subcommand(
+            Command::new("info")
+                .about("Shows information about an archive.")
+                .short_flag('i')
+                .arg(arg!(--space "Report the space used by content bundles and files"))
+                .arg(
+                    arg!(--types "Report the number and size of files of each type")
+                        .conflicts_with("space"),
+                )
+                .arg(
+                    arg!(--duplicates "Report the sets of files with identical content")
+                        .conflicts_with_all(["space", "types"]),
+                )
+                .arg(arg!(pack: <PACK> "File path specifying the archive to read from."))
+                .arg_required_else_help(true),
+        )
+        .subcommand(
+            Command::new("history")
+                .about("Shows the changes made to an archive after it was written.")
+                .arg(arg!(--long "Show the paths of the entries affected by each change"))
+                .arg(arg!(pack: <PACK> "File path specifying the archive to read from."))
+                .arg_required_else_help(true),
+        )
+        .subcommand(
+            Command::new("manifest")
+                .about("Prints a JSON manifest of the contents of an archive.")
+                .arg(arg!(pack: <PACK> "File path specifying the archive to read from."))
+                .arg_required_else_help(true),
+        )
+        .subcommand(
+            Command::new("verify")
+                .about("Verifies an archive, or an extracted tree against a manifest or archive.")
+                .arg(
+                    arg!(--manifest <FILE> "Manifest produced by the manifest command")
+                        .value_parser(clap::value_parser!(PathBuf))
+                        .required_unless_present("pack"),
+                )
+                .arg(
+                    arg!(--pack <PACK> "Archive whose entries the tree should match")
+                        .conflicts_with("manifest"),
+                )
+                .arg(
+                    arg!([DIR] "Directory containing the extracted tree")
+                        .value_parser(clap::value_parser!(PathBuf))
+                        .required_unless_present("pack"),
+                )
+                .arg_required_else_help(true),
+        )
+        .subcommand(
+            Command::new("rekey")
+                .about("Changes the password of an encrypted archive.")
+                .arg(arg!(pack: <PACK> "File path specifying the archive to change."))
+                .arg_required_else_help(true),
+        )
+        .subcommand(
+            Command::new("repair")
+                .about("Replaces damaged content in an archive using another copy.")
+                .arg(
+                    arg!(--from <REPLICA> "Another copy of the same archive")
+                        .value_parser(clap::value_parser!(PathBuf))
+                        .required(true),
+                )
+                .arg(arg!(pack: <PACK> "File path specifying the archive to repair."))
+                .arg_required_else_help(true),
+        )
+        .subcommand(
+            Command::new("chmod")
+                .about("Changes the recorded permissions of entries in an archive.")
+                .arg(
+                    arg!(mode: <MODE> "Permissions in octal, such as 644")
+                        .value_parser(parse_mode),
+                )
+                .arg(arg!(pack: <PACK> "File path specifying the archive to modify."))
+                .arg(arg!(<PATHS> ... "Paths of the entries within the archive"))
+                .arg_required_else_help(true),
+        )
+        .subcommand(
+            Command::new("chown")
+                .about("Changes the recorded owner and group of entries in an archive.")
+                .arg(
+                    arg!(owner: <OWNER> "Owner as USER, USER:GROUP, or :GROUP, by name or number")
+                        .value_parser(parse_owner),
+                )
+                .arg(arg!(pack: <PACK> "File path specifying the archive to modify."))
+                .arg(arg!(<PATHS> ... "Paths of the entries within the archive"))
+                .arg_required_else_help(true),
+        )
+        .subcommand(
+            Command::new("touch")
+                .about("Changes the recorded modification time of entries in an archive.")
+                .arg(
+                    arg!(--mtime <SECONDS> "Time in seconds since the Unix epoch, instead of now")
+                        .value_parser(clap::value_parser!(i64)),
+                )
+                .arg(arg!(pack: <PACK> "File path specifying the archive to modify."))
+                .arg(arg!(<PATHS> ... "Paths of the entries within the archive"))
+                .arg_required_else_help(true),
+        )
+        .subcommand(
+            Command::new("bench")
+                .about("Compares the speed and size of archives made with various settings.")
+                .arg(
+                    arg!(--sample <SIZE> "Amount of file content to pack (default 64M)")
+                        .value_parser(parse_size),
+                )
+                .arg(
+                    arg!(--"bundle-sizes" <SIZES> "Comma-separated bundle sizes (default 4M,16M,64M)")
+                        .value_parser(parse_size)
+                        .value_delimiter(','),
+                )
+                .arg(
+                    arg!(--levels <LEVELS> "Comma-separated compression levels (default 1,3,9,19)")
+                        .value_parser(clap::value_parser!(i32))
+                        .value_delimiter(','),
+                )
+                .arg(
+                    arg!(--threads <COUNTS> "Comma-separated thread counts (default 1,4)")
+                        .value_parser(clap::value_parser!(u32).range(1..))
+                        .value_delimiter(','),
+                )
+                .arg(
+                    arg!(--profiles <PROFILES> "Comma-separated profiles for extracting (default balanced)")
+                        .value_parser(["safe", "balanced", "fast"])
+                        .value_delimiter(','),
+                )
+                .arg(
+                    arg!(<DIR> "Directory from which to take the sample")
+                        .value_parser(clap::value_parser!(PathBuf)),
+                )
+                .arg_required_else_help(true),
+        )
+        .subcommand(
+            Command::new("catalog")
+                .about("Indexes many archives to find which one contains a file.")
+                .subcommand_required(true)
+                .arg(
+                    arg!(--catalog <FILE> "Location of the catalog database")
+                        .value_parser(clap::value_parser!(PathBuf))
+                        .default_value("catalog.db3")
+                        .global(true),
+                )
+                .subcommand(
+                    Command::new("add")
+                        .about("Adds (or refreshes) the files of the archives in the catalog.")
+                        .arg(arg!(<ARCHIVES> ... "Archives to be indexed"))
+                        .arg_required_else_help(true),
+                )
+                .subcommand(
+                    Command::new("search")
+                        .about("Reports the archives containing files that match the pattern.")
+                        .arg(arg!(--checksum "Treat the pattern as a hex-encoded file digest"))
+                        .arg(quoting_style_arg())
+                        .arg(arg!(<PATTERN> "File name, or path if it contains a slash"))
+                        .arg_required_else_help(true),
+                ),
+        )
+        .subcommand(
+            Command::new("upgrade")
+                .about("Converts an archive to the current format in place.")
+                .arg(arg!(pack: <PACK> "File path specifying the archive to upgrade."))
+                .arg_required_else_help(true),
+        )
+        .subcommand(
+            Command::new("extract")
+                .about("Extracts one or more files from an archive.")
+                .short_flag('x')
+                .arg(arg!(--dir <PATH> "Extract only this directory within the archive"))
+                .arg(arg!(--"ignore-case" "Match the directory and paths without regard to case"))
+                .arg(arg!(--"special-files" "Create named pipes and device nodes"))
+                .arg(arg!(--"mac-metadata" "Restore resource forks and Finder metadata (macOS)"))
+                .arg(
+                    arg!(--"max-size" <SIZE> "Refuse to extract more than SIZE bytes in total")
+                        .value_parser(parse_size),
+                )
+                .arg(
+                    arg!(--"max-files" <COUNT> "Refuse to extract more than COUNT files")
+                        .value_parser(clap::value_parser!(u64)),
+                )
+                .arg(
+                    arg!(--"max-file-size" <SIZE> "Refuse to extract any file larger than SIZE")
+                        .value_parser(parse_size),
+                )
+                .arg(
+                    arg!(--"max-depth" <DEPTH> "Refuse to extract paths deeper than DEPTH")
+                        .value_parser(clap::value_parser!(u64)),
+                )
+                .arg(arg!(--exec <COMMAND> "Run COMMAND for each extracted file, with {} as the path"))
+                .arg(arg!(--resume "Skip files that were completely written by an earlier run"))
+                .arg(
+                    arg!(--"case-collisions" <POLICY> "Rename entries whose names differ only in case")
+                        .value_parser(["auto", "rename", "overwrite"])
+                        .default_value("auto"),
+                )
+                .arg(
+                    arg!(--"verify-on-extract" [POLICY] "Check each file against its checksum as it is written (fail, warn, or skip)")
+                        .value_parser(["fail", "warn", "skip"])
+                        .require_equals(true)
+                        .default_missing_value("fail"),
+                )
+                .arg(
+                    arg!(--"rewrite-links" <RULE> "Rewrite symlinks: 'relative' or /FROM=TO")
+                        .value_parser(parse_link_rewrite),
+                )
+                .arg(arg!(--"same-owner" "Restore the owner and group of each entry"))
+                .arg(
+                    arg!(--"no-same-owner" "Leave the extracted entries owned by the current user")
+                        .overrides_with("same-owner"),
+                )
+                .arg(arg!(--"numeric-owner" "Restore owners by number, ignoring recorded names"))
+                .arg(arg!(--"no-same-permissions" "Apply the umask to the recorded permissions"))
+                .arg(
+                    arg!(--"owner-map" <FILE> "Replace owners as given by OLD:NEW lines in FILE")
+                        .value_parser(clap::value_parser!(PathBuf))
+                        .requires("same-owner"),
+                )
+                .arg(
+                    arg!(--"group-map" <FILE> "Replace groups as given by OLD:NEW lines in FILE")
+                        .value_parser(clap::value_parser!(PathBuf))
+                        .requires("same-owner"),
+                )
+                .arg(profile_arg())
+                .arg(arg!(pack: <PACK> "File path specifying the archive to read from."))
+                .arg(
+                    arg!([PATHS] ... "Entries to extract, as paths, directories, or wildcards")
+                        .conflicts_with("dir"),
+                )
+                .arg_required_else_help(true),
+        )
+}
+
+fn main() -> Result<(), Error> {
+    let matches = cli().get_matches();
+    init_logging(matches.get_count("verbose"));
+    if let Some(path) = matches.get_one::<PathBuf>("password-file") {
+        let _ = PASSWORD_FILE.set(path.to_owned());
+    }
+    match matches.subcommand() {
+        Some(("create", sub_matches)) => {
+            let pack = sub_matches
+                .get_one::<String>("pack")
+                .map(|s| s.as_str())
+                .unwrap_or("pack.db3");
+            let inputs = Inputs::from_matches(sub_matches);
+            let prefix = sub_matches.get_one::<String>("prefix");
+            let builder = create_builder(sub_matches)?;
+            let policy = error_policy(sub_matches);
+            let concurrent_reads = sub_matches.get_flag("concurrent-reads");
+            let (report, warnings, unreadable) =
+                create_archive(pack, inputs, prefix, builder, policy, concurrent_reads)?;
+            let mut notes: Vec<String> = vec![];
+            if warnings > 0 {
+                notes.push(format!("{} warnings", warnings));
+            }
+            if unreadable > 0 {
+                notes.push(format!("{} entries could not be read", unreadable));
+            }
+            if notes.is_empty() {
+                println!("Added {} files to {}", report.files, pack);
+            } else {
+                println!(
+                    "Added {} files to {} ({})",
+                    report.files,
+                    pack,
+                    notes.join(", ")
+                );
+            }
+            print_create_report(&report);
+            if sub_matches.get_flag("timings") {
+                print_timings(&report.timings);
+            }
+        }
+        Some(("update", sub_matches)) => {
+            let pack = sub_matches
+                .get_one::<String>("pack")
+                .map(|s| s.as_str())
+                .unwrap_or("pack.db3");
+            let inputs = sub_matches
+                .get_many::<PathBuf>("INPUTS")
+                .into_iter()
+                .flatten()
+                .collect::<Vec<_>>();
+            let limits = Limits::from_matches(sub_matches);
+            let policy = error_policy(sub_matches);
+            let concurrent_reads = sub_matches.get_flag("concurrent-reads");
+            let (summary, warnings, unreadable) =
+                update_archive(pack, inputs, limits, policy, concurrent_reads)?;
+            println!(
+                "Added {}, changed {}, removed {} entries in {}",
+                summary.added, summary.changed, summary.removed, pack
+            );
+            if warnings > 0 {
+                println!("{} warnings", warnings);
+            }
+            if unreadable > 0 {
+                println!("{} entries could not be read", unreadable);
+            }
+        }
+        Some(("merge", sub_matches)) => {
+            let pack = sub_matches
+                .get_one::<String>("pack")
+                .map(|s| s.as_str())
+                .unwrap_or("pack.db3");
+            let inputs = sub_matches
+                .get_many::<PathBuf>("INPUTS")
+                .into_iter()
+                .flatten()
+                .collect::<Vec<_>>();
+            let policy = match sub_matches.get_one::<String>("policy").map(|s| s.as_str()) {
+                Some("keep-first") => MergePolicy::KeepFirst,
+                Some("keep-newest") => MergePolicy::KeepNewest,
+                _ => MergePolicy::Error,
+            };
+            let memory_limit = sub_matches.get_one::<u64>("memory-limit").copied();
+            let file_count = merge_archives(pack, inputs, policy, memory_limit)?;
+            println!("Merged {} files into {}", file_count, pack);
+        }
+        Some(("repack", sub_matches)) => {
+            let source = sub_matches
+                .get_one::<String>("SOURCE")
+                .expect("SOURCE is required");
+            let pack = sub_matches
+                .get_one::<PathBuf>("pack")
+                .expect("PACK is required");
+            let report = repack_archive(source, pack, sub_matches)?;
+            println!("Repacked {} files into {}", report.files, pack.display());
+            print_create_report(&report);
+        }
+        Some(("split", sub_matches)) => {
+            let pack = sub_matches
+                .get_one::<String>("pack")
+                .map(|s| s.as_str())
+                .unwrap_or("pack.db3");
+            let outdir = sub_matches
+                .get_one::<PathBuf>("by-top-dir")
+                .expect("OUTDIR is required");
+            let count = split_archive(pack, outdir)?;
+            println!("Split {} into {} archives", pack, count);
+        }
+        Some(("from-zip", sub_matches)) => {
+            let pack = sub_matches
+                .get_one::<String>("pack")
+                .map(|s| s.as_str())
+                .unwrap_or("pack.db3");
+            let zipfile = sub_matches
+                .get_one::<PathBuf>("ZIP")
+                .expect("ZIP is required");
+            let memory_limit = sub_matches.get_one::<u64>("memory-limit").copied();
+            let file_count = import_zip(pack, zipfile, memory_limit)?;
+            println!("Added {} files to {}", file_count, pack);
+        }
+        Some(("to-zip", sub_matches)) => {
+            let pack = sub_matches
+                .get_one::<String>("pack")
+                .map(|s| s.as_str())
+                .unwrap_or("pack.db3");
+            let zipfile = sub_matches
+                .get_one::<PathBuf>("ZIP")
+                .expect("ZIP is required");
+            let file_count = export_archive(pack, zipfile, false)?;
+            if zipfile != Path::new("-") {
+                println!("Wrote {} files to {}", file_count, zipfile.display());
+            }
+        }
+        Some(("to-tar", sub_matches)) => {
+            let pack = sub_matches
+                .get_one::<String>("pack")
+                .map(|s| s.as_str())
+                .unwrap_or("pack.db3");
+            let tarfile = sub_matches
+                .get_one::<PathBuf>("TAR")
+                .expect("TAR is required");
+            let file_count = export_archive(pack, tarfile, true)?;
+            if tarfile != Path::new("-") {
+                println!("Wrote {} files to {}", file_count, tarfile.display());
+            }
+        }
+        Some(("list", sub_matches)) => {
+            let pack = sub_matches
+                .get_one::<String>("pack")
+                .map(|s| s.as_str())
+                .unwrap_or("pack.db3");
+            let style = quoting_style(sub_matches);
+            if sub_matches.get_flag("tree") {
+                list_tree(pack, style)?;
+            } else {
+                let options = ListOptions {
+                    prefix: sub_matches.get_one::<String>("prefix").cloned(),
+                    kind: match sub_matches.get_one::<String>("kind").map(|s| s.as_str()) {
+                        Some("file") => Some(ItemKind::File),
+                        Some("dir") => Some(ItemKind::Directory),
+                        Some("link") => Some(ItemKind::Symlink),
+                        _ => None,
+                    },
+                    order: match sub_matches.get_one::<String>("sort").map(|s| s.as_str()) {
+                        Some("path") => ListOrder::Path,
+                        Some("size") => ListOrder::Size,
+                        Some("mtime") => ListOrder::Mtime,
+                        _ => ListOrder::Natural,
+                    },
+                    descending: sub_matches.get_flag("reverse"),
+                };
+                list_contents(pack, &options, sub_matches.get_flag("long"), style)?;
+            }
+        }
+        Some(("cat", sub_matches)) => {
+            let pack = sub_matches
+                .get_one::<String>("pack")
+                .map(|s| s.as_str())
+                .unwrap_or("pack.db3");
+            let item_id = sub_matches.get_one::<i64>("id").copied();
+            let path = sub_matches.get_one::<String>("PATH");
+            let cache = sub_matches.get_one::<PathBuf>("cache-dir").map(|dir| {
+                let limit = sub_matches.get_one::<u64>("cache-limit").copied();
+                (dir, limit.expect("cache-limit has a default"))
+            });
+            print_item(
+                pack,
+                item_id,
+                path,
+                sub_matches.get_flag("ignore-case"),
+                cache,
+            )?;
+        }
+        Some(("completions", sub_matches)) => {
+            let shell = sub_matches
+                .get_one::<Shell>("shell")
+                .copied()
+                .expect("shell is required");
+            print_completions(shell)?;
+        }
+        Some(("complete-path", sub_matches)) => {
+            let pack = sub_matches
+                .get_one::<String>("pack")
+                .map(|s| s.as_str())
+                .unwrap_or("pack.db3");
+            let partial = sub_matches
+                .get_one::<String>("PARTIAL")
+                .map(|s| s.as_str())
+                .unwrap_or("");
+            // completion should never produce noise, so ignore any errors
+            let _ = print_path_completions(pack, partial);
+        }
+        Some(("info", sub_matches)) => {
+            let pack = sub_matches
+                .get_one::<String>("pack")
+                .map(|s| s.as_str())
+                .unwrap_or("pack.db3");
+            if sub_matches.get_flag("space") {
+                print_space_report(pack)?;
+            } else if sub_matches.get_flag("types") {
+                print_type_report(pack)?;
+            } else if sub_matches.get_flag("duplicates") {
+                print_duplicates(pack)?;
+            } else {
+                print_summary(pack)?;
+            }
+        }
+        Some(("history", sub_matches)) => {
+            let pack = sub_matches
+                .get_one::<String>("pack")
+                .map(|s| s.as_str())
+                .unwrap_or("pack.db3");
+            print_history(pack, sub_matches.get_flag("long"))?;
+        }
+        Some(("manifest", sub_matches)) => {
+            let pack = sub_matches
+                .get_one::<String>("pack")
+                .map(|s| s.as_str())
+                .unwrap_or("pack.db3");
+            print_manifest(pack)?;
+        }
+        Some(("verify", sub_matches)) => {
+            let pack = sub_matches.get_one::<String>("pack");
+            if let Some(root) = sub_matches.get_one::<PathBuf>("DIR") {
+                let entries = match pack {
+                    Some(pack) => open_reader(pack)?.manifest()?,
+                    None => {
+                        let manifest = sub_matches
+                            .get_one::<PathBuf>("manifest")
+                            .expect("manifest is required");
+                        read_manifest(manifest)?
+                    }
+                };
+                let count = verify_tree(&entries, root)?;
+                if count > 0 {
+                    eprintln!("Found {} discrepancies", count);
+                    std::process::exit(1);
+                }
+                if pack.is_some() {
+                    println!("Tree matches the archive");
+                } else {
+                    println!("Tree matches the manifest");
+                }
+            } else {
+                let pack = pack.expect("pack is required without DIR");
+                let count = verify_archive(pack)?;
+                if count > 0 {
+                    eprintln!("Found {} problems", count);
+                    std::process::exit(1);
+                }
+                println!("Archive is intact");
+            }
+        }
+        Some(("rekey", sub_matches)) => {
+            let pack = sub_matches
+                .get_one::<String>("pack")
+                .map(|s| s.as_str())
+                .unwrap_or("pack.db3");
+            rekey_archive(pack)?;
+        }
+        Some(("repair", sub_matches)) => {
+            let pack = sub_matches
+                .get_one::<String>("pack")
+                .map(|s| s.as_str())
+                .unwrap_or("pack.db3");
+            let replica = sub_matches
+                .get_one::<PathBuf>("from")
+                .expect("replica is required");
+            let report = pack_rs::repair_archive(pack, replica)?;
+            for content_id in report.unrepaired.iter() {
+                eprintln!("error: no intact copy of content bundle {}", content_id);
+            }
+            println!(
+                "Checked {} bundles, repaired {}",
+                report.checked,
+                report.repaired.len()
+            );
+            if !report.unrepaired.is_empty() {
+                std::process::exit(1);
+            }
+        }
+        Some(("chmod", sub_matches)) => {
+            let pack = sub_matches
+                .get_one::<String>("pack")
+                .map(|s| s.as_str())
+                .unwrap_or("pack.db3");
+            let paths = sub_matches
+                .get_many::<String>("PATHS")
+                .into_iter()
+                .flatten()
+                .collect::<Vec<_>>();
+            let patch = EntryMetadataPatch {
+                mode: sub_matches.get_one::<u32>("mode").copied(),
+                ..Default::default()
+            };
+            let count = set_metadata(pack, paths, patch)?;
+            println!("Changed {} entries in {}", count, pack);
+        }
+        Some(("chown", sub_matches)) => {
+            let pack = sub_matches
+                .get_one::<String>("pack")
+                .map(|s| s.as_str())
+                .unwrap_or("pack.db3");
+            let paths = sub_matches
+                .get_many::<String>("PATHS")
+                .into_iter()
+                .flatten()
+                .collect::<Vec<_>>();
+            let patch = sub_matches
+                .get_one::<EntryMetadataPatch>("owner")
+                .cloned()
+                .expect("OWNER is required");
+            let count = set_metadata(pack, paths, patch)?;
+            println!("Changed {} entries in {}", count, pack);
+        }
+        Some(("touch", sub_matches)) => {
+            let pack = sub_matches
+                .get_one::<String>("pack")
+                .map(|s| s.as_str())
+                .unwrap_or("pack.db3");
+            let paths = sub_matches
+                .get_many::<String>("PATHS")
+                .into_iter()
+                .flatten()
+                .collect::<Vec<_>>();
+            let mtime = match sub_matches.get_one::<i64>("mtime") {
+                Some(mtime) => *mtime,
+                None => SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map(|d| d.as_secs() as i64)
+                    .unwrap_or(0),
+            };
+            let patch = EntryMetadataPatch {
+                mtime: Some(mtime),
+                ..Default::default()
+            };
+            let count = set_metadata(pack, paths, patch)?;
+            println!("Changed {} entries in {}", count, pack);
+        }
+        Some(("bench", sub_matches)) => {
+            let dir = sub_matches
+                .get_one::<PathBuf>("DIR")
+                .expect("directory is required");
+            let mut options = BenchOptions::default();
+            if let Some(sample) = sub_matches.get_one::<u64>("sample") {
+                options.sample_size = *sample;
+            }
+            if let Some(sizes) = sub_matches.get_many::<u64>("bundle-sizes") {
+                options.bundle_sizes = sizes.copied().collect();
+            }
+            if let Some(levels) = sub_matches.get_many::<i32>("levels") {
+                options.levels = levels.copied().collect();
+            }
+            if let Some(threads) = sub_matches.get_many::<u32>("threads") {
+                options.threads = threads.copied().collect();
+            }
+            if let Some(profiles) = sub_matches.get_many::<String>("profiles") {
+                options.profiles = profiles.map(|p| profile_by_name(p)).collect();
+            }
+            run_benchmark(dir, &options)?;
+        }
+        Some(("catalog", sub_matches)) => {
+            let catalog = sub_matches
+                .get_one::<PathBuf>("catalog")
+                .expect("catalog has a default");
+            match sub_matches.subcommand() {
+                Some(("add", add_matches)) => {
+                    let archives = add_matches
+                        .get_many::<String>("ARCHIVES")
+                        .into_iter()
+                        .flatten()
+                        .collect::<Vec<_>>();
+                    catalog_add(catalog, archives)?;
+                }
+                Some(("search", search_matches)) => {
+                    let pattern = search_matches
+                        .get_one::<String>("PATTERN")
+                        .expect("pattern is required");
+                    let checksum = search_matches.get_flag("checksum");
+                    let style = quoting_style(search_matches);
+                    if catalog_search(catalog, pattern, checksum, style)? == 0 {
+                        std::process::exit(1);
+                    }
+                }
+                _ => unreachable!(),
+            }
+        }
+        Some(("upgrade", sub_matches)) => {
+            let pack = sub_matches
+                .get_one::<String>("pack")
+                .map(|s| s.as_str())
                 .unwrap_or("pack.db3");
-            list_contents(pack)?;
+            let report = pack_rs::migrate(pack)?;
+            if report.is_empty() {
+                println!("{} is already up to date", pack);
+            } else {
+                for table in report.tables.iter() {
+                    println!("Added table {}", table);
+                }
+                for column in report.columns.iter() {
+                    println!("Added column {}", column);
+                }
+                println!(
+                    "Upgraded {} ({} bundles updated, {} links moved)",
+                    pack, report.bundles, report.links
+                );
+            }
         }
         Some(("extract", sub_matches)) => {
             let pack = sub_matches
                 .get_one::<String>("pack")
                 .map(|s| s.as_str())
                 .unwrap_or("pack.db3");
-            let file_count = extract_contents(pack)?;
-            println!("Extracted {} files from {}", file_count, pack)
+            let dir = sub_matches.get_one::<String>("dir");
+            let paths = sub_matches
+                .get_many::<String>("PATHS")
+                .into_iter()
+                .flatten()
+                .map(|s| s.as_str())
+                .collect::<Vec<_>>();
+            let options = ExtractOptions {
+                special_files: sub_matches.get_flag("special-files"),
+                mac_metadata: sub_matches.get_flag("mac-metadata"),
+                resume: sub_matches.get_flag("resume"),
+                rewrite_links: sub_matches.get_one::<LinkRewrite>("rewrite-links").cloned(),
+                owners: sub_matches.get_flag("same-owner"),
+                owner_map: owner_map_arg(sub_matches, "owner-map", OwnerMap::parse_users)?,
+                group_map: owner_map_arg(sub_matches, "group-map", OwnerMap::parse_groups)?,
+                numeric_owner: sub_matches.get_flag("numeric-owner"),
+                same_permissions: !sub_matches.get_flag("no-same-permissions"),
+                verify: sub_matches.get_one::<String>("verify-on-extract").map(
+                    |policy| match policy.as_str() {
+                        "warn" => ErrorPolicy::Warn,
+                        "skip" => ErrorPolicy::Skip,
+                        _ => ErrorPolicy::Fail,
+                    },
+                ),
+                case_collisions: match sub_matches
+                    .get_one::<String>("case-collisions")
+                    .map(|s| s.as_str())
+                {
+                    Some("rename") => CaseCollisions::Rename,
+                    Some("overwrite") => CaseCollisions::Overwrite,
+                    _ => CaseCollisions::Auto,
+                },
+                ..Default::default()
+            };
+            let report = extract_contents(pack, dir, &paths, options, sub_matches)?;
+            let warnings = report.warnings.len();
+            if warnings > 0 {
+                println!(
+                    "Extracted {} files from {} ({} warnings)",
+                    report.files, pack, warnings
+                );
+            } else {
+                println!("Extracted {} files from {}", report.files, pack)
+            }
+            print_extract_report(&report);
         }
         _ => unreachable!(),
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("512"), Ok(512));
+        assert_eq!(parse_size("4k"), Ok(4096));
+        assert_eq!(parse_size(" 2M "), Ok(2_097_152));
+        assert_eq!(parse_size("1G"), Ok(1_073_741_824));
+        assert!(parse_size("0").is_err());
+        assert!(parse_size("G").is_err());
+        assert!(parse_size("12X").is_err());
+        // sizes that do not fit are refused rather than wrapping around
+        assert_eq!(
+            parse_rate("99999999999G"),
+            Err("invalid rate: 99999999999G".to_owned())
+        );
+        assert!(parse_size("18446744073709551615K").is_err());
+        assert_eq!(parse_size("18446744073709551615"), Ok(u64::MAX));
+    }
+
+    #[test]
+    fn test_quote() {
+        use QuotingStyle::{Literal, Shell, C};
+        // shell quoting
+        assert_eq!(quote(b"notes.txt", Shell), "notes.txt");
+        assert_eq!(quote(b"", Shell), "''");
+        assert_eq!(quote(b"two words", Shell), "'two words'");
+        assert_eq!(quote(b"it's", Shell), r"'it'\''s'");
+        assert_eq!(quote(b"'", Shell), r"\'");
+        assert_eq!(quote(b"one\ntwo", Shell), r"'one'$'\n''two'");
+        assert_eq!(quote(b"\x1b[0m", Shell), r"$'\033''[0m'");
+        assert_eq!(quote(b"bad\xffname", Shell), r"'bad'$'\377''name'");
+        assert_eq!(quote("café".as_bytes(), Shell), "café");
+        // C quoting
+        assert_eq!(quote(b"", C), r#""""#);
+        assert_eq!(quote(b"it's", C), r#""it's""#);
+        assert_eq!(quote(b"say \"hi\"", C), r#""say \"hi\"""#);
+        assert_eq!(quote(b"back\\slash", C), r#""back\\slash""#);
+        assert_eq!(quote(b"one\ttwo\x01", C), r#""one\ttwo\001""#);
+        assert_eq!(quote(b"bad\xfe\xffname", C), r#""bad\376\377name""#);
+        // literal, with invalid UTF-8 replaced
+        assert_eq!(quote(b"", Literal), "");
+        assert_eq!(quote(b"one\ntwo", Literal), "one\ntwo");
+        assert_eq!(quote(b"bad\xffname", Literal), "bad\u{fffd}name");
+    }
+
+    #[test]
+    fn test_push_escaped() {
+        let mut quoted = String::new();
+        push_escaped(&mut quoted, b"");
+        assert_eq!(quoted, "");
+        push_escaped(&mut quoted, b"\\\n\t\r\x07\x08\x0b\x0c");
+        assert_eq!(quoted, r"\\\n\t\r\a\b\v\f");
+        quoted.clear();
+        push_escaped(&mut quoted, b"\x00\x1b\x7f\xff");
+        assert_eq!(quoted, r"\000\033\177\377");
+    }
+
+    #[test]
+    fn test_env_password() {
+        // names that no other test uses, as the environment is shared
+        let names = ["PACKRS_TEST_PRIMARY", "PACKRS_TEST_ALIAS"];
+        assert_eq!(env_password(&names), None);
+        std::env::set_var(names[1], "alias");
+        assert_eq!(env_password(&names).as_deref(), Some("alias"));
+        // an empty value counts as not set
+        std::env::set_var(names[0], "");
+        assert_eq!(env_password(&names).as_deref(), Some("alias"));
+        std::env::set_var(names[0], "primary");
+        assert_eq!(env_password(&names).as_deref(), Some("primary"));
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn test_run_command() -> Result<(), Error> {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+        let workspace = tempfile::tempdir()?;
+        // a path that is not valid UTF-8 is passed along unchanged
+        let path = workspace.path().join(OsStr::from_bytes(b"caf\xe9"));
+        std::fs::write(&path, "x")?;
+        run_command("cp {} {}.copy", &path)?;
+        let copy = workspace.path().join(OsStr::from_bytes(b"caf\xe9.copy"));
+        assert_eq!(std::fs::read(copy)?, b"x");
+        assert!(run_command("false", &path).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,333 @@
+//
+// Copyright (c) 2024 Nathan Fiedler
+//
+use crate::digest::Hasher;
+use crate::DigestAlgorithm;
+use crate::{
+    has_column, Error, PackReader, KIND_BLOCK_DEVICE, KIND_CHAR_DEVICE, KIND_DIRECTORY, KIND_FIFO,
+    KIND_FILE, KIND_SYMLINK,
+};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+///
+/// Description of a single entry in the archive, suitable for comparing an
+/// extracted tree against the archive from which it came.
+///
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    /// Path of the entry relative to the archive root, without a trailing slash.
+    pub path: String,
+    /// One of `file`, `dir`, `symlink`, `fifo`, `char-device`, or
+    /// `block-device`.
+    pub kind: String,
+    /// Length of the file or symbolic link value, zero for directories.
+    pub size: u64,
+    /// Hex-encoded digest of the file content.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub checksum: Option<String>,
+    /// Identifier of the algorithm that produced the checksum, where none
+    /// means BLAKE3, as for manifests that predate the choice.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub digest: Option<String>,
+    /// Unix permission bits, if recorded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mode: Option<u32>,
+    /// Modification time in seconds since the Unix epoch, if recorded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mtime: Option<i64>,
+    /// Value of the symbolic link.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target: Option<String>,
+}
+
+///
+/// A difference between a manifest entry and what was found on disk.
+///
+#[derive(Clone, Debug, PartialEq)]
+pub enum Discrepancy {
+    /// Nothing exists at the path.
+    Missing(String),
+    /// The path refers to a different kind of entry.
+    KindDiffers(String),
+    /// The file or symbolic link has a different length.
+    SizeDiffers(String),
+    /// The file content does not match the checksum.
+    ChecksumDiffers(String),
+    /// The file permissions are not the same.
+    ModeDiffers(String),
+    /// The symbolic link refers to something else.
+    TargetDiffers(String),
+}
+
+impl fmt::Display for Discrepancy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Discrepancy::Missing(path) => write!(f, "{}: missing", path),
+            Discrepancy::KindDiffers(path) => write!(f, "{}: kind differs", path),
+            Discrepancy::SizeDiffers(path) => write!(f, "{}: size differs", path),
+            Discrepancy::ChecksumDiffers(path) => write!(f, "{}: checksum differs", path),
+            Discrepancy::ModeDiffers(path) => write!(f, "{}: mode differs", path),
+            Discrepancy::TargetDiffers(path) => write!(f, "{}: link target differs", path),
+        }
+    }
+}
+
+impl PackReader {
+    ///
+    /// Produce a manifest of every entry in the archive, in breadth-first order.
+    ///
+    /// File checksums that were not recorded when the archive was created are
+    /// computed from the file content.
+    ///
+    pub fn manifest(&self) -> Result<Vec<ManifestEntry>, Error> {
+        let has_attrs = has_column(&self.conn, "attrs", "mtime")?;
+        let has_mode = has_column(&self.conn, "attrs", "mode")?;
+        let attrs = match (has_attrs, has_mode) {
+            (true, true) => "attrs.checksum, attrs.mode, attrs.mtime",
+            (true, false) => "attrs.checksum, NULL, attrs.mtime",
+            _ => "NULL, NULL, NULL",
+        };
+        let join = if has_attrs {
+            "LEFT JOIN attrs ON attrs.item = FIT.id"
+        } else {
+            ""
+        };
+        let query = format!(
+            "WITH RECURSIVE FIT AS (
+    SELECT *, Name || IIF(Kind = 1, '/', '') AS Path FROM Item WHERE Parent = 0
+    UNION ALL
+    SELECT Item.*, FIT.Path || Item.Name || IIF(Item.Kind = 1, '/', '') AS Path
+        FROM Item INNER JOIN FIT ON FIT.Kind = 1 AND Item.Parent = FIT.ID
+)
+SELECT FIT.id, FIT.kind, FIT.Path, {}, {}
+FROM FIT {};",
+            self.item_size_column(),
+            attrs,
+            join
+        );
+        let mut stmt = self.conn.prepare(&query)?;
+        let rows = stmt
+            .query_map([], |row| {
+                Ok((
+                    row.get::<_, i64>(0)?,
+                    row.get::<_, i8>(1)?,
+                    row.get::<_, String>(2)?,
+                    row.get::<_, u64>(3)?,
+                    row.get::<_, Option<Vec<u8>>>(4)?,
+                    row.get::<_, Option<u32>>(5)?,
+                    row.get::<_, Option<i64>>(6)?,
+                ))
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+        let algorithm = self.digest_algorithm()?;
+        let mut entries: Vec<ManifestEntry> = vec![];
+        for (item_id, kind, path, size, checksum, mode, mtime) in rows {
+            let mut entry = ManifestEntry {
+                path: path.trim_end_matches('/').to_owned(),
+                kind: kind_name(kind).to_owned(),
+                size,
+                checksum: None,
+                digest: None,
+                mode,
+                mtime,
+                target: None,
+            };
+            if kind == KIND_FILE {
+                let digest = match checksum {
+                    Some(digest) => digest,
+                    None => {
+                        let mut hasher = Hasher::new(algorithm);
+                        self.read_content(item_id, &mut hasher)?;
+                        hasher.finalize()
+                    }
+                };
+                entry.checksum = Some(to_hex(&digest));
+                entry.digest = Some(algorithm.identifier().to_owned());
+            } else if kind == KIND_SYMLINK {
+                let mut raw_bytes: Vec<u8> = vec![];
+                self.read_content(item_id, &mut raw_bytes)?;
+                entry.target = Some(String::from_utf8_lossy(&raw_bytes).into_owned());
+            }
+            entries.push(entry);
+        }
+        Ok(entries)
+    }
+}
+
+///
+/// Compare the tree at the given location against the manifest, returning all
+/// of the discrepancies that were found.
+///
+/// Entries that exist on disk but are not in the manifest are ignored. The
+/// permissions are only compared on Unix systems.
+///
+pub fn verify_manifest<P: AsRef<Path>>(
+    entries: &[ManifestEntry],
+    root: P,
+) -> Result<Vec<Discrepancy>, Error> {
+    let mut found: Vec<Discrepancy> = vec![];
+    for entry in entries.iter() {
+        let path = root.as_ref().join(&entry.path);
+        let metadata = match fs::symlink_metadata(&path) {
+            Ok(metadata) => metadata,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                found.push(Discrepancy::Missing(entry.path.clone()));
+                continue;
+            }
+            Err(err) => return Err(err.into()),
+        };
+        let kind = if metadata.is_dir() {
+            KIND_DIRECTORY
+        } else if metadata.is_symlink() {
+            KIND_SYMLINK
+        } else {
+            special_kind(&metadata).unwrap_or(KIND_FILE)
+        };
+        if kind_name(kind) != entry.kind {
+            found.push(Discrepancy::KindDiffers(entry.path.clone()));
+            continue;
+        }
+        if kind == KIND_FILE {
+            if metadata.len() != entry.size {
+                found.push(Discrepancy::SizeDiffers(entry.path.clone()));
+                continue;
+            }
+            if let Some(expected) = entry.checksum.as_ref() {
+                let algorithm = match entry.digest.as_deref() {
+                    Some(identifier) => identifier.parse()?,
+                    None => DigestAlgorithm::Blake3,
+                };
+                let mut hasher = Hasher::new(algorithm);
+                io::copy(&mut fs::File::open(&path)?, &mut hasher)?;
+                if to_hex(&hasher.finalize()) != *expected {
+                    found.push(Discrepancy::ChecksumDiffers(entry.path.clone()));
+                }
+            }
+            #[cfg(target_family = "unix")]
+            if let Some(expected) = entry.mode {
+                use std::os::unix::fs::PermissionsExt;
+                if metadata.permissions().mode() & 0o7777 != expected {
+                    found.push(Discrepancy::ModeDiffers(entry.path.clone()));
+                }
+            }
+        } else if kind == KIND_SYMLINK {
+            if let Some(expected) = entry.target.as_ref() {
+                let target = fs::read_link(&path)?;
+                if target.to_string_lossy() != *expected {
+                    found.push(Discrepancy::TargetDiffers(entry.path.clone()));
+                }
+            }
+        }
+    }
+    Ok(found)
+}
+
+// Return the manifest name for the kind of item.
+fn kind_name(kind: i8) -> &'static str {
+    match kind {
+        KIND_DIRECTORY => "dir",
+        KIND_SYMLINK => "symlink",
+        KIND_FIFO => "fifo",
+        KIND_CHAR_DEVICE => "char-device",
+        KIND_BLOCK_DEVICE => "block-device",
+        _ => "file",
+    }
+}
+
+// Return the kind of a named pipe or device node found on disk.
+fn special_kind(metadata: &fs::Metadata) -> Option<i8> {
+    #[cfg(target_family = "unix")]
+    {
+        use std::os::unix::fs::FileTypeExt;
+        let file_type = metadata.file_type();
+        if file_type.is_fifo() {
+            return Some(KIND_FIFO);
+        } else if file_type.is_char_device() {
+            return Some(KIND_CHAR_DEVICE);
+        } else if file_type.is_block_device() {
+            return Some(KIND_BLOCK_DEVICE);
+        }
+    }
+    let _ = metadata;
+    None
+}
+
+// Encode the bytes as lowercase hexadecimal.
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ExtractOptions, PackBuilder};
+
+    #[test]
+    fn test_manifest_verify() -> Result<(), Error> {
+        let workspace = tempfile::tempdir()?;
+        let basepath = workspace.path().join("input");
+        fs::create_dir_all(basepath.join("sub"))?;
+        fs::write(basepath.join("sub/hello.txt"), "hello world")?;
+        fs::write(basepath.join("empty.txt"), "")?;
+        #[cfg(target_family = "unix")]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let script = basepath.join("run.sh");
+            fs::write(&script, "#!/bin/sh\n")?;
+            fs::set_permissions(&script, fs::Permissions::from_mode(0o755))?;
+            std::os::unix::fs::symlink("sub/hello.txt", basepath.join("link"))?;
+        }
+        let packfile = workspace.path().join("pack.db3");
+        let mut builder = PackBuilder::new()?;
+        builder.add_dir_all(&basepath)?;
+        builder.finish(&packfile)?;
+
+        let reader = PackReader::new(&packfile)?;
+        let manifest = reader.manifest()?;
+        let hello = manifest
+            .iter()
+            .find(|e| e.path == "input/sub/hello.txt")
+            .unwrap();
+        assert_eq!(hello.kind, "file");
+        assert_eq!(hello.size, 11);
+        let expected = to_hex(blake3::hash(b"hello world").as_bytes());
+        assert_eq!(hello.checksum.as_ref(), Some(&expected));
+        let sub = manifest.iter().find(|e| e.path == "input/sub").unwrap();
+        assert_eq!(sub.kind, "dir");
+        #[cfg(target_family = "unix")]
+        {
+            let link = manifest.iter().find(|e| e.path == "input/link").unwrap();
+            assert_eq!(link.target.as_deref(), Some("sub/hello.txt"));
+            let script = manifest.iter().find(|e| e.path == "input/run.sh").unwrap();
+            assert_eq!(script.mode, Some(0o755));
+        }
+
+        // survives a round-trip through JSON
+        let json = serde_json::to_string(&manifest).unwrap();
+        let parsed: Vec<ManifestEntry> = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed, manifest);
+
+        // the extracted tree matches the manifest
+        let destination = workspace.path().join("output");
+        fs::create_dir(&destination)?;
+        let options = ExtractOptions {
+            destination: destination.clone(),
+            ..Default::default()
+        };
+        reader.extract_all_with(&options)?;
+        assert!(verify_manifest(&manifest, &destination)?.is_empty());
+
+        // and discrepancies are detected
+        fs::write(destination.join("input/sub/hello.txt"), "hello wurld")?;
+        fs::remove_file(destination.join("input/empty.txt"))?;
+        let found = verify_manifest(&manifest, &destination)?;
+        assert_eq!(found.len(), 2);
+        assert!(found.contains(&Discrepancy::ChecksumDiffers("input/sub/hello.txt".into())));
+        assert!(found.contains(&Discrepancy::Missing("input/empty.txt".into())));
+        Ok(())
+    }
+}
@@ -0,0 +1,455 @@
+//
+// Copyright (c) 2024 Nathan Fiedler
+//
+use crate::crypto::KEY_ENTRY;
+use crate::{DigestAlgorithm, Error, PackBuilder, KIND_DIRECTORY, KIND_FILE};
+use rusqlite::{Connection, OptionalExtension};
+use std::collections::HashMap;
+use std::path::Path;
+
+///
+/// How to resolve an entry that exists at the same path in more than one of
+/// the archives being merged. Directories that exist in several archives are
+/// always combined into one.
+///
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum MergePolicy {
+    /// Fail with `Error::PathCollision` when a collision is found.
+    #[default]
+    Error,
+    /// Keep the entry that was merged first.
+    KeepFirst,
+    /// Keep the entry with the most recent modification time, favoring the
+    /// entry that was merged first when the times are equal or unknown.
+    KeepNewest,
+}
+
+// An item from the archive being merged.
+struct SourceItem {
+    id: i64,
+    parent: i64,
+    kind: i8,
+    name: String,
+}
+
+impl PackBuilder {
+    ///
+    /// Merge the entire contents of the pack file at the given location into
+    /// this archive, returning the number of files that were added. The
+    /// content bundles are copied as-is, without decompressing them.
+    ///
+    /// With the content-addressed layout, files whose content is already in
+    /// this archive share that content, and bundles that end up unused are
+    /// not copied.
+    ///
+    /// The archive must use the same digest algorithm as this one, unless
+    /// this archive is still empty, in which case it takes on the algorithm
+    /// of the merged archive. Otherwise `Error::DigestMismatch` is returned.
+    /// Likewise, an archive with encrypted content can only be merged into
+    /// one that is empty or encrypted with the same key, otherwise
+    /// `Error::EncryptionMismatch` is returned. Encrypted bundles copied into
+    /// an archive that already has content must be encrypted anew, for which
+    /// the password must be given with `encrypt_content()`.
+    ///
+    /// **Note:** Not supported for builders created using `open()`, as SQLite
+    /// does not allow attaching a database within a transaction.
+    ///
+    pub fn merge_archive<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+        policy: MergePolicy,
+    ) -> Result<u64, Error> {
+        if !crate::is_pack_file(path.as_ref())? {
+            return Err(Error::NotPackFile);
+        }
+        let location = path.as_ref().to_string_lossy();
+        let _span = tracing::info_span!("merge_archive", path = %location).entered();
+        self.conn
+            .execute("ATTACH DATABASE ?1 AS source", [location.as_ref()])?;
+        let result = merge_attached(self, policy);
+        self.conn.execute("DETACH DATABASE source", ())?;
+        let file_count = result?;
+        tracing::info!(files = file_count, "merged archive");
+        // the source is detached, so the database can be moved if need be
+        self.check_memory_limit()?;
+        Ok(file_count)
+    }
+}
+
+// Merge the attached source database into the builder.
+fn merge_attached(builder: &mut PackBuilder, policy: MergePolicy) -> Result<u64, Error> {
+    let conn = &builder.conn;
+    // the item content of such archives does not refer to any bundles
+    let has_meta = conn
+        .prepare(
+            "SELECT 1 FROM source.sqlite_master WHERE type = 'table' AND name = 'archive_meta'",
+        )?
+        .exists([])?;
+    if has_meta
+        && conn
+            .prepare("SELECT 1 FROM source.archive_meta WHERE name = 'layout' AND value = ?1")?
+            .exists([crate::LAYOUT_METADATA_ONLY])?
+    {
+        return Err(Error::MetadataOnly);
+    }
+    // checksums are copied as-is, so must be computed the same way
+    let digest: Option<String> = if has_meta {
+        conn.query_row(
+            "SELECT value FROM source.archive_meta WHERE name = 'digest'",
+            [],
+            |row| row.get(0),
+        )
+        .optional()?
+    } else {
+        None
+    };
+    let digest = match digest {
+        Some(identifier) => identifier.parse()?,
+        None => DigestAlgorithm::Blake3,
+    };
+    builder.set_digest_algorithm(digest)?;
+    // encrypted bundles are also copied as-is, so must use the same key
+    let source_meta = |name: &str| -> Result<Option<String>, Error> {
+        if !has_meta {
+            return Ok(None);
+        }
+        let value = builder
+            .conn
+            .query_row(
+                "SELECT value FROM source.archive_meta WHERE name = ?1",
+                [name],
+                |row| row.get(0),
+            )
+            .optional()?;
+        Ok(value)
+    };
+    let wrapped = source_meta(KEY_ENTRY)?;
+    let encryption = source_meta("encryption")?;
+    let has_content: bool =
+        builder
+            .conn
+            .query_row("SELECT EXISTS (SELECT 1 FROM source.content)", [], |row| {
+                row.get(0)
+            })?;
+    builder.adopt_encryption(wrapped, encryption, has_content)?;
+    let conn = &builder.conn;
+    // copy all of the content bundles, shifting the rowids to avoid those
+    // already in use; any that end up unused will be removed at the end
+    let offset: i64 = conn.query_row("SELECT IFNULL(MAX(id), 0) FROM main.content", [], |row| {
+        row.get(0)
+    })?;
+    let rawsize = source_column_or_null(conn, "content", "rawsize")?;
+    let checksum = source_column_or_null(conn, "content", "checksum")?;
+    let codec = source_column_or_null(conn, "content", "codec")?;
+    let entries = source_column_or_null(conn, "content", "entries")?;
+    conn.execute(
+        &format!(
+            "INSERT INTO main.content (id, value, rawsize, checksum, codec, entries)
+                SELECT id + ?1, value, {}, {}, {}, {} FROM source.content",
+            rawsize, checksum, codec, entries
+        ),
+        [&offset],
+    )?;
+    if builder.encrypted && offset > 0 {
+        rebind_bundles(builder, offset)?;
+    }
+    let conn = &builder.conn;
+    let has_attrs: bool = conn
+        .prepare("SELECT 1 FROM source.sqlite_master WHERE type = 'table' AND name = 'attrs'")?
+        .exists([])?;
+    let mode = source_column_or_null(conn, "attrs", "mode")?;
+    let devmajor = source_column_or_null(conn, "attrs", "devmajor")?;
+    let devminor = source_column_or_null(conn, "attrs", "devminor")?;
+    let mime = source_column_or_null(conn, "attrs", "mime")?;
+    let owner = ["uid", "gid", "uname", "gname"]
+        .into_iter()
+        .map(|column| source_column_or_null(conn, "attrs", column))
+        .collect::<Result<Vec<_>, _>>()?
+        .join(", ");
+    let target = source_column_or_null(conn, "item", "target")?;
+    let has_xattrs: bool = conn
+        .prepare("SELECT 1 FROM source.sqlite_master WHERE type = 'table' AND name = 'xattr'")?
+        .exists([])?;
+
+    // visit the items breadth-first so that parents are merged before children
+    let mut stmt = conn.prepare(
+        "WITH RECURSIVE T(id, parent, kind, name, depth) AS (
+            SELECT id, parent, kind, name, 0 FROM source.item WHERE parent = 0
+            UNION ALL
+            SELECT I.id, I.parent, I.kind, I.name, T.depth + 1 FROM source.item AS I
+                INNER JOIN T ON T.kind = 1 AND I.parent = T.id
+        )
+        SELECT id, parent, kind, name FROM T ORDER BY depth, id",
+    )?;
+    let items = stmt
+        .query_map([], |row| {
+            Ok(SourceItem {
+                id: row.get(0)?,
+                parent: row.get(1)?,
+                kind: row.get(2)?,
+                name: row.get(3)?,
+            })
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+    drop(stmt);
+
+    // mapping of source item rowids to the merged rowids and full paths
+    let mut mapping: HashMap<i64, (i64, String)> = HashMap::new();
+    let mut file_count: u64 = 0;
+    for item in items {
+        let conn = &builder.conn;
+        let (parent, parent_path) = if item.parent == 0 {
+            (0, String::new())
+        } else if let Some((id, path)) = mapping.get(&item.parent) {
+            (*id, path.clone())
+        } else {
+            // parent was skipped due to a collision, skip this as well
+            continue;
+        };
+        let path = format!("{}{}", parent_path, item.name);
+        let existing: Option<(i64, i8)> = conn
+            .query_row(
+                "SELECT id, kind FROM main.item WHERE parent = ?1 AND name = ?2",
+                (&parent, &item.name),
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .map(Some)
+            .or_else(|err| match err {
+                rusqlite::Error::QueryReturnedNoRows => Ok(None),
+                err => Err(err),
+            })?;
+        if let Some((existing_id, existing_kind)) = existing {
+            if existing_kind == KIND_DIRECTORY && item.kind == KIND_DIRECTORY {
+                mapping.insert(item.id, (existing_id, path + "/"));
+                continue;
+            }
+            let replace = match policy {
+                MergePolicy::Error => return Err(Error::PathCollision(path)),
+                MergePolicy::KeepFirst => false,
+                MergePolicy::KeepNewest if has_attrs => {
+                    let theirs: Option<i64> = query_mtime(conn, "source", item.id)?;
+                    let ours: Option<i64> = query_mtime(conn, "main", existing_id)?;
+                    matches!((theirs, ours), (Some(t), Some(o)) if t > o)
+                }
+                MergePolicy::KeepNewest => false,
+            };
+            if !replace {
+                continue;
+            }
+            builder.remove_item(existing_id)?;
+        }
+        conn.execute(
+            &format!(
+                "INSERT INTO main.item (parent, kind, name, target)
+                    SELECT ?1, kind, name, {} FROM source.item WHERE id = ?2",
+                target
+            ),
+            (&parent, &item.id),
+        )?;
+        let item_id = conn.last_insert_rowid();
+        let mut checksum: Option<Vec<u8>> = None;
+        if has_attrs {
+            conn.execute(
+                &format!(
+                    "INSERT INTO main.attrs (item, mtime, checksum, mode, devmajor, devminor, mime,
+                        uid, gid, uname, gname)
+                        SELECT ?1, mtime, checksum, {}, {}, {}, {}, {}
+                        FROM source.attrs WHERE item = ?2",
+                    mode, devmajor, devminor, mime, owner
+                ),
+                (&item_id, &item.id),
+            )?;
+            if item.kind == KIND_FILE {
+                checksum = query_checksum(conn, item_id)?;
+            }
+        }
+        if has_xattrs {
+            conn.execute(
+                "INSERT INTO main.xattr (item, name, value)
+                    SELECT ?1, name, value FROM source.xattr WHERE item = ?2",
+                (&item_id, &item.id),
+            )?;
+        }
+        let suffix = if item.kind == KIND_DIRECTORY { "/" } else { "" };
+        mapping.insert(item.id, (item_id, path + suffix));
+        if item.kind == KIND_FILE {
+            file_count += 1;
+        }
+        if let Some(digest) = checksum {
+            if builder.share_content(item_id, &digest)? {
+                continue;
+            }
+        }
+        builder.conn.execute(
+            "INSERT INTO main.itemcontent (item, itempos, content, contentpos, size)
+                SELECT ?1, itempos, content + ?2, contentpos, size
+                FROM source.itemcontent WHERE item = ?3",
+            (&item_id, &offset, &item.id),
+        )?;
+    }
+    let conn = &builder.conn;
+
+    // remove the content that was not needed due to collisions, including
+    // that of entries merged earlier that have since been replaced
+    conn.execute(
+        "DELETE FROM main.content WHERE id NOT IN (SELECT content FROM main.itemcontent)",
+        (),
+    )?;
+    Ok(file_count)
+}
+
+// Encrypt anew the bundles copied from the source archive, whose rowids were
+// shifted by the given offset, as each is bound to the row in which it is
+// stored.
+fn rebind_bundles(builder: &PackBuilder, offset: i64) -> Result<(), Error> {
+    let key = builder
+        .content_key
+        .as_ref()
+        .ok_or(Error::PasswordRequired)?;
+    if !key.is_bound() {
+        return Ok(());
+    }
+    let mut stmt = builder.conn.prepare("SELECT id FROM source.content")?;
+    let ids = stmt
+        .query_map([], |row| row.get::<_, i64>(0))?
+        .collect::<Result<Vec<_>, _>>()?;
+    for source_id in ids {
+        let content_id = source_id + offset;
+        let value: Vec<u8> = builder.conn.query_row(
+            "SELECT value FROM main.content WHERE id = ?1",
+            [&content_id],
+            |row| row.get(0),
+        )?;
+        let value = crate::crypto::reseal(&value, Some(key), Some(key), source_id, content_id)?;
+        builder.conn.execute(
+            "UPDATE main.content SET value = ?2, checksum = ?3 WHERE id = ?1",
+            (&content_id, &value, builder.digest.hash(&value)),
+        )?;
+    }
+    Ok(())
+}
+
+// Return the name of the column in the source table, or NULL if the source
+// archive was created before that column was introduced.
+fn source_column_or_null(conn: &Connection, table: &str, column: &str) -> Result<String, Error> {
+    let mut stmt = conn.prepare("SELECT 1 FROM pragma_table_info(?1, 'source') WHERE name = ?2")?;
+    if stmt.exists([table, column])? {
+        Ok(column.to_owned())
+    } else {
+        Ok("NULL".to_owned())
+    }
+}
+
+// Return the file checksum of the merged item, if any.
+fn query_checksum(conn: &Connection, item_id: i64) -> Result<Option<Vec<u8>>, Error> {
+    let mut stmt = conn.prepare("SELECT checksum FROM main.attrs WHERE item = ?1")?;
+    let mut rows = stmt.query([&item_id])?;
+    match rows.next()? {
+        Some(row) => Ok(row.get(0)?),
+        None => Ok(None),
+    }
+}
+
+// Return the modification time of the item in the named database.
+fn query_mtime(conn: &Connection, schema: &str, item_id: i64) -> Result<Option<i64>, Error> {
+    let mut stmt = conn.prepare(&format!(
+        "SELECT mtime FROM {}.attrs WHERE item = ?1",
+        schema
+    ))?;
+    let mut rows = stmt.query([&item_id])?;
+    match rows.next()? {
+        Some(row) => Ok(row.get(0)?),
+        None => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::PackReader;
+    use std::fs;
+
+    #[test]
+    fn test_merge_archives() -> Result<(), Error> {
+        let workspace = tempfile::tempdir()?;
+        let mut packs: Vec<std::path::PathBuf> = Vec::new();
+        for (idx, files) in [["common.txt", "one.txt"], ["common.txt", "two.txt"]]
+            .iter()
+            .enumerate()
+        {
+            let basepath = workspace.path().join(format!("input{}", idx)).join("data");
+            fs::create_dir_all(&basepath)?;
+            for name in files {
+                fs::write(basepath.join(name), format!("{} from {}", name, idx))?;
+            }
+            let packfile = workspace.path().join(format!("pack{}.db3", idx));
+            let mut builder = PackBuilder::new()?;
+            builder.add_dir_all(&basepath)?;
+            builder.finish(&packfile)?;
+            packs.push(packfile);
+        }
+
+        // by default a collision is an error
+        let mut builder = PackBuilder::new()?;
+        assert_eq!(builder.merge_archive(&packs[0], MergePolicy::Error)?, 2);
+        let result = builder.merge_archive(&packs[1], MergePolicy::Error);
+        assert!(matches!(result, Err(Error::PathCollision(p)) if p == "data/common.txt"));
+
+        // keeping the first entry combines the directories
+        let mut builder = PackBuilder::new()?;
+        builder.merge_archive(&packs[0], MergePolicy::KeepFirst)?;
+        assert_eq!(builder.merge_archive(&packs[1], MergePolicy::KeepFirst)?, 1);
+        let merged = workspace.path().join("merged.db3");
+        builder.finish(&merged)?;
+        let reader = PackReader::new(&merged)?;
+        let names: Vec<String> = reader
+            .entries()?
+            .into_iter()
+            .map(|e| e.map(|e| e.name))
+            .collect::<Result<Vec<_>, _>>()?;
+        assert_eq!(
+            names,
+            vec!["data/", "data/common.txt", "data/one.txt", "data/two.txt"]
+        );
+        let (_, bundles) = reader.counts()?;
+        assert_eq!(bundles, 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_merge_keep_newest() -> Result<(), Error> {
+        let workspace = tempfile::tempdir()?;
+        let mut packs: Vec<std::path::PathBuf> = Vec::new();
+        for (idx, mtime) in [100, 300, 200].into_iter().enumerate() {
+            let packfile = workspace.path().join(format!("pack{}.db3", idx));
+            let mut builder = PackBuilder::new()?;
+            let content = format!("common from {}", idx);
+            let item_id = builder.add_stream("common.txt", 0, content.as_bytes())?;
+            builder.record_attrs(item_id, Some(mtime), None)?;
+            builder.finish(&packfile)?;
+            packs.push(packfile);
+        }
+
+        // each newer entry replaces the one merged before it
+        let mut builder = PackBuilder::new()?;
+        for packfile in packs.iter() {
+            builder.merge_archive(packfile, MergePolicy::KeepNewest)?;
+        }
+        let merged = workspace.path().join("merged.db3");
+        builder.finish(&merged)?;
+        let reader = PackReader::new(&merged)?;
+        let mut content: Vec<u8> = vec![];
+        reader.read_path("common.txt", &mut content)?;
+        assert_eq!(content, b"common from 1");
+        let conn = Connection::open(&merged)?;
+        let unused: i64 = conn.query_row(
+            "SELECT COUNT(*) FROM content WHERE id NOT IN (SELECT content FROM itemcontent)",
+            [],
+            |row| row.get(0),
+        )?;
+        assert_eq!(unused, 0);
+        let (_, bundles) = reader.counts()?;
+        assert_eq!(bundles, 1);
+        Ok(())
+    }
+}
@@ -0,0 +1,331 @@
+//
+// Copyright (c) 2024 Nathan Fiedler
+//
+use crate::{Codec, DigestAlgorithm, Error, StoredCodec, ZstdCodec, KIND_SYMLINK};
+use rusqlite::{Connection, DatabaseName, OptionalExtension};
+use std::collections::HashSet;
+use std::io::{self, Read};
+use std::path::Path;
+
+///
+/// Outcome of converting an archive to the current schema using `migrate()`.
+///
+#[derive(Clone, Debug, Default)]
+pub struct MigrateReport {
+    /// Tables that were added, by name.
+    pub tables: Vec<String>,
+    /// Columns that were added to existing tables, as `table.column`.
+    pub columns: Vec<String>,
+    /// Number of content bundles whose newly added columns were filled in.
+    pub bundles: u64,
+    /// Number of symbolic links whose values were moved out of the content
+    /// bundles and into the item table.
+    pub links: u64,
+}
+
+impl MigrateReport {
+    ///
+    /// Return `true` if the archive was already using the current schema.
+    ///
+    pub fn is_empty(&self) -> bool {
+        self.tables.is_empty() && self.columns.is_empty() && self.bundles == 0 && self.links == 0
+    }
+}
+
+///
+/// Convert the archive at the given location to the current schema in place,
+/// adding any tables and columns introduced since the archive was created and
+/// filling in the values of those columns that can be derived from the
+/// existing data, such as the size, checksum, and number of entries of each
+/// content bundle. The values of symbolic links are moved out of the content
+/// bundles and into the item table. All of the changes are made within a
+/// single transaction.
+///
+/// Archives created by earlier versions can be read without being converted,
+/// but some features, such as bundle verification, depend on the new columns.
+///
+pub fn migrate<P: AsRef<Path>>(path: P) -> Result<MigrateReport, Error> {
+    if !crate::is_pack_file(path.as_ref())? {
+        return Err(Error::NotPackFile);
+    }
+    let mut conn = Connection::open(path.as_ref())?;
+    let tx = conn.transaction()?;
+    crate::check_features(&tx)?;
+    let tables_before = table_names(&tx)?;
+    let columns_before = column_names(&tx)?;
+    crate::create_tables(&tx)?;
+    // archives that do not record the digest algorithm predate the choice
+    let digest: Option<String> = tx
+        .query_row(
+            "SELECT value FROM archive_meta WHERE name = 'digest'",
+            [],
+            |row| row.get(0),
+        )
+        .optional()?;
+    let digest = match digest {
+        Some(identifier) => identifier.parse()?,
+        None => DigestAlgorithm::Blake3,
+    };
+    let mut report = MigrateReport::default();
+    for table in table_names(&tx)? {
+        if !tables_before.contains(&table) {
+            report.tables.push(table);
+        }
+    }
+    for column in column_names(&tx)? {
+        let table = column.split('.').next().unwrap_or_default();
+        if tables_before.contains(table) && !columns_before.contains(&column) {
+            report.columns.push(column);
+        }
+    }
+    report.tables.sort();
+    report.columns.sort();
+    report.bundles = fill_content_columns(&tx, digest)?;
+    report.links = move_link_targets(&tx)?;
+    if !report.is_empty() {
+        crate::audit::record_audit(&tx, "upgrade", &[])?;
+    }
+    tx.commit()?;
+    Ok(report)
+}
+
+// Return the names of all of the tables in the database.
+fn table_names(conn: &Connection) -> Result<HashSet<String>, Error> {
+    let mut stmt = conn.prepare("SELECT name FROM sqlite_master WHERE type = 'table'")?;
+    let names = stmt
+        .query_map([], |row| row.get::<_, String>(0))?
+        .collect::<Result<HashSet<_>, _>>()?;
+    Ok(names)
+}
+
+// Return the names of all of the columns in the database, as `table.column`.
+fn column_names(conn: &Connection) -> Result<HashSet<String>, Error> {
+    let mut stmt = conn.prepare(
+        "SELECT m.name || '.' || p.name FROM sqlite_master AS m, pragma_table_info(m.name) AS p
+            WHERE m.type = 'table'",
+    )?;
+    let names = stmt
+        .query_map([], |row| row.get::<_, String>(0))?
+        .collect::<Result<HashSet<_>, _>>()?;
+    Ok(names)
+}
+
+// Fill in the columns of the content table that are null, as would be the
+// case for bundles written before those columns were introduced. Returns the
+// number of bundles that were changed.
+fn fill_content_columns(conn: &Connection, digest: DigestAlgorithm) -> Result<u64, Error> {
+    let mut stmt = conn.prepare(
+        "SELECT id, rawsize, checksum, codec, entries FROM content
+            WHERE rawsize IS NULL OR checksum IS NULL OR codec IS NULL OR entries IS NULL",
+    )?;
+    let rows = stmt
+        .query_map([], |row| {
+            Ok((
+                row.get::<_, i64>(0)?,
+                row.get::<_, Option<u64>>(1)?,
+                row.get::<_, Option<Vec<u8>>>(2)?,
+                row.get::<_, Option<String>>(3)?,
+                row.get::<_, Option<u64>>(4)?,
+            ))
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+    for (content_id, rawsize, checksum, codec, entries) in rows.iter() {
+        // bundles without a codec predate there being a choice
+        let codec = codec
+            .clone()
+            .unwrap_or_else(|| ZstdCodec::IDENTIFIER.to_owned());
+        let mut value: Vec<u8> = vec![];
+        let mut blob = conn.blob_open(DatabaseName::Main, "content", "value", *content_id, true)?;
+        blob.read_to_end(&mut value)?;
+        let checksum = match checksum {
+            Some(checksum) => checksum.clone(),
+            None => digest.hash(&value),
+        };
+        let rawsize = match rawsize {
+            Some(rawsize) => Some(*rawsize),
+            None => uncompressed_size(&codec, value)?,
+        };
+        let entries = match entries {
+            Some(entries) => *entries,
+            None => conn.query_row(
+                "SELECT COUNT(DISTINCT contentpos) FROM itemcontent WHERE content = ?1",
+                [content_id],
+                |row| row.get(0),
+            )?,
+        };
+        conn.execute(
+            "UPDATE content SET rawsize = ?2, checksum = ?3, codec = ?4, entries = ?5
+                WHERE id = ?1",
+            (content_id, &rawsize, &checksum, &codec, &entries),
+        )?;
+    }
+    Ok(rows.len() as u64)
+}
+
+// Move the values of symbolic links that are stored in the content bundles to
+// the item table, removing any bundles that are no longer needed. Returns the
+// number of links that were changed.
+fn move_link_targets(conn: &Connection) -> Result<u64, Error> {
+    let mut stmt = conn.prepare(
+        "SELECT id FROM item WHERE kind = ?1 AND target IS NULL
+            AND id IN (SELECT item FROM itemcontent)",
+    )?;
+    let links = stmt
+        .query_map([KIND_SYMLINK], |row| row.get::<_, i64>(0))?
+        .collect::<Result<Vec<_>, _>>()?;
+    for item_id in links.iter() {
+        let mut stmt = conn.prepare(
+            "SELECT content, contentpos, size FROM itemcontent WHERE item = ?1 ORDER BY itempos",
+        )?;
+        let parts = stmt
+            .query_map([item_id], |row| {
+                Ok((
+                    row.get::<_, i64>(0)?,
+                    row.get::<_, u64>(1)?,
+                    row.get::<_, u64>(2)?,
+                ))
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut target: Vec<u8> = vec![];
+        for (content_id, contentpos, size) in parts {
+            read_bundle_range(conn, content_id, contentpos, size, &mut target)?;
+        }
+        conn.execute(
+            "UPDATE item SET target = ?2 WHERE id = ?1",
+            (item_id, &target),
+        )?;
+        conn.execute("DELETE FROM itemcontent WHERE item = ?1", [item_id])?;
+    }
+    if !links.is_empty() {
+        conn.execute(
+            "DELETE FROM content WHERE id NOT IN (SELECT content FROM itemcontent)",
+            (),
+        )?;
+        conn.execute(
+            "UPDATE content SET entries =
+                (SELECT COUNT(DISTINCT contentpos) FROM itemcontent
+                    WHERE itemcontent.content = content.id)",
+            (),
+        )?;
+    }
+    Ok(links.len() as u64)
+}
+
+// Append the given portion of the decompressed content bundle to the output.
+fn read_bundle_range(
+    conn: &Connection,
+    content_id: i64,
+    pos: u64,
+    size: u64,
+    output: &mut Vec<u8>,
+) -> Result<(), Error> {
+    let (value, codec): (Vec<u8>, Option<String>) = conn.query_row(
+        "SELECT value, codec FROM content WHERE id = ?1",
+        [&content_id],
+        |row| Ok((row.get(0)?, row.get(1)?)),
+    )?;
+    let codec = codec.unwrap_or_else(|| ZstdCodec::IDENTIFIER.to_owned());
+    let Some(codec) = builtin_codec(&codec) else {
+        return Err(Error::UnknownCodec(codec));
+    };
+    let mut decoder = codec.decompress_stream(Box::new(io::Cursor::new(value)))?;
+    io::copy(&mut (&mut decoder).take(pos), &mut io::sink())?;
+    decoder.take(size).read_to_end(output)?;
+    Ok(())
+}
+
+// Return the size of the bundle after decompression, if the codec is one of
+// those built into this crate.
+fn uncompressed_size(codec: &str, value: Vec<u8>) -> Result<Option<u64>, Error> {
+    let Some(codec) = builtin_codec(codec) else {
+        return Ok(None);
+    };
+    let mut decoder = codec.decompress_stream(Box::new(io::Cursor::new(value)))?;
+    Ok(Some(io::copy(&mut decoder, &mut io::sink())?))
+}
+
+// Return the codec built into this crate with the given identifier, if any.
+fn builtin_codec(identifier: &str) -> Option<Box<dyn Codec>> {
+    match identifier {
+        ZstdCodec::IDENTIFIER => Some(Box::new(ZstdCodec::default())),
+        StoredCodec::IDENTIFIER => Some(Box::new(StoredCodec)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{PackBuilder, PackReader};
+    use std::fs;
+
+    #[test]
+    fn test_migrate() -> Result<(), Error> {
+        let workspace = tempfile::tempdir()?;
+        let basepath = workspace.path().join("input");
+        fs::create_dir_all(&basepath)?;
+        fs::write(basepath.join("one.txt"), "one ".repeat(100))?;
+        fs::write(basepath.join("two.txt"), "two")?;
+        fs::write(basepath.join("link"), "one.txt")?;
+        let packfile = workspace.path().join("pack.db3");
+        let mut builder = PackBuilder::new()?;
+        builder.add_dir_all(&basepath)?;
+        builder.finish(&packfile)?;
+        assert!(migrate(&packfile)?.is_empty());
+
+        // reduce the archive to the original layout, in which the values of
+        // symbolic links were stored in the content bundles
+        let conn = Connection::open(&packfile)?;
+        conn.execute_batch(
+            "UPDATE item SET kind = 2 WHERE name = 'link';
+            ALTER TABLE item DROP COLUMN target;
+            DROP TABLE attrs;
+            DROP TABLE archive_meta;
+            DROP TABLE chunk;
+            ALTER TABLE content DROP COLUMN rawsize;
+            ALTER TABLE content DROP COLUMN checksum;
+            ALTER TABLE content DROP COLUMN codec;
+            ALTER TABLE content DROP COLUMN entries;",
+        )?;
+        drop(conn);
+
+        // such archives can be read without being converted
+        let reader = PackReader::new(&packfile)?;
+        let manifest = reader.manifest()?;
+        let link = manifest.iter().find(|e| e.path == "input/link").unwrap();
+        assert_eq!(link.target.as_deref(), Some("one.txt"));
+        drop(reader);
+
+        let report = migrate(&packfile)?;
+        assert_eq!(report.tables, vec!["archive_meta", "attrs", "chunk"]);
+        assert_eq!(
+            report.columns,
+            vec![
+                "content.checksum",
+                "content.codec",
+                "content.entries",
+                "content.rawsize",
+                "item.target"
+            ]
+        );
+        assert_eq!(report.bundles, 1);
+        assert_eq!(report.links, 1);
+        assert!(migrate(&packfile)?.is_empty());
+
+        let reader = PackReader::new(&packfile)?;
+        reader.verify_integrity()?;
+        let history = reader.history()?;
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0].operation, "upgrade");
+        let space = reader.space_report()?;
+        assert_eq!(space.bundles[0].uncompressed, 410);
+        assert_eq!(space.bundles[0].entries, 2);
+        assert_eq!(reader.read_range("input/two.txt", 0, 3)?, b"two");
+        let manifest = reader.manifest()?;
+        let link = manifest.iter().find(|e| e.path == "input/link").unwrap();
+        assert_eq!(link.kind, "symlink");
+        assert_eq!(link.target.as_deref(), Some("one.txt"));
+        assert_eq!(link.size, 7);
+        Ok(())
+    }
+}
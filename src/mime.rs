@@ -0,0 +1,134 @@
+//
+// Copyright (c) 2024 Nathan Fiedler
+//
+
+// Number of leading bytes of each file that are examined to detect its type.
+pub(crate) const SNIFF_SIZE: usize = 512;
+
+// Signatures at the start of the content, with the corresponding MIME type.
+static SIGNATURES: &[(&[u8], &str)] = &[
+    (b"\x89PNG\r\n\x1a\n", "image/png"),
+    (b"\xff\xd8\xff", "image/jpeg"),
+    (b"GIF87a", "image/gif"),
+    (b"GIF89a", "image/gif"),
+    (b"II*\x00", "image/tiff"),
+    (b"MM\x00*", "image/tiff"),
+    (b"%PDF-", "application/pdf"),
+    (b"PK\x03\x04", "application/zip"),
+    (b"PK\x05\x06", "application/zip"),
+    (b"\x1f\x8b", "application/gzip"),
+    (b"BZh", "application/x-bzip2"),
+    (b"\xfd7zXZ\x00", "application/x-xz"),
+    (b"\x28\xb5\x2f\xfd", "application/zstd"),
+    (b"7z\xbc\xaf\x27\x1c", "application/x-7z-compressed"),
+    (b"Rar!\x1a\x07", "application/vnd.rar"),
+    (b"SQLite format 3\x00", "application/vnd.sqlite3"),
+    (b"\x7fELF", "application/x-executable"),
+    (b"\xfe\xed\xfa\xce", "application/x-mach-binary"),
+    (b"\xfe\xed\xfa\xcf", "application/x-mach-binary"),
+    (b"\xce\xfa\xed\xfe", "application/x-mach-binary"),
+    (b"\xcf\xfa\xed\xfe", "application/x-mach-binary"),
+    (b"\x00asm", "application/wasm"),
+    (b"\xca\xfe\xba\xbe", "application/java-vm"),
+    (b"ID3", "audio/mpeg"),
+    (b"fLaC", "audio/flac"),
+    (b"OggS", "audio/ogg"),
+    (b"\x1aE\xdf\xa3", "video/x-matroska"),
+    (b"wOFF", "font/woff"),
+    (b"wOF2", "font/woff2"),
+];
+
+// Signatures that are short enough to also begin a text file, and hence are
+// only considered for content that does not look like text.
+static WEAK_SIGNATURES: &[(&[u8], &str)] = &[
+    (b"BM", "image/bmp"),
+    (b"MZ", "application/vnd.microsoft.portable-executable"),
+];
+
+///
+/// Return the MIME type of the content that begins with the given bytes, as
+/// detected by well-known signatures, or `text/plain` if the bytes look like
+/// text. Returns `None` if the type could not be determined.
+///
+pub(crate) fn sniff_mime(data: &[u8]) -> Option<&'static str> {
+    if data.is_empty() {
+        return None;
+    }
+    for (signature, mime) in SIGNATURES.iter() {
+        if data.starts_with(signature) {
+            return Some(mime);
+        }
+    }
+    // RIFF and ISO media files have the identifying bytes further along
+    if data.len() >= 12 && data.starts_with(b"RIFF") {
+        match &data[8..12] {
+            b"WEBP" => return Some("image/webp"),
+            b"WAVE" => return Some("audio/wav"),
+            b"AVI " => return Some("video/x-msvideo"),
+            _ => (),
+        }
+    }
+    if data.len() >= 12 && &data[4..8] == b"ftyp" {
+        return match &data[8..12] {
+            b"heic" | b"heix" | b"mif1" => Some("image/heic"),
+            b"avif" => Some("image/avif"),
+            b"qt  " => Some("video/quicktime"),
+            b"M4A " => Some("audio/mp4"),
+            _ => Some("video/mp4"),
+        };
+    }
+    if data.len() >= 262 && &data[257..262] == b"ustar" {
+        return Some("application/x-tar");
+    }
+    if looks_like_text(data) {
+        return Some("text/plain");
+    }
+    WEAK_SIGNATURES
+        .iter()
+        .find(|(signature, _)| data.starts_with(signature))
+        .map(|(_, mime)| *mime)
+}
+
+// Return true if the data is UTF-8 without any control characters other than
+// whitespace, allowing for a multi-byte character cut off at the end.
+fn looks_like_text(data: &[u8]) -> bool {
+    let valid = match std::str::from_utf8(data) {
+        Ok(text) => text,
+        Err(err) if err.error_len().is_none() => {
+            std::str::from_utf8(&data[..err.valid_up_to()]).unwrap_or_default()
+        }
+        Err(_) => return false,
+    };
+    !valid
+        .chars()
+        .any(|c| c.is_control() && !matches!(c, '\n' | '\r' | '\t' | '\x0c'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sniff_mime() {
+        assert_eq!(sniff_mime(b""), None);
+        assert_eq!(sniff_mime(b"\x89PNG\r\n\x1a\n\x00\x00"), Some("image/png"));
+        assert_eq!(sniff_mime(b"%PDF-1.7\n"), Some("application/pdf"));
+        assert_eq!(
+            sniff_mime(b"RIFF\x00\x00\x00\x00WEBPVP8 "),
+            Some("image/webp")
+        );
+        assert_eq!(sniff_mime(b"\x00\x00\x00\x18ftypisom"), Some("video/mp4"));
+        let mut tar = vec![0; 512];
+        tar[257..262].copy_from_slice(b"ustar");
+        assert_eq!(sniff_mime(&tar), Some("application/x-tar"));
+        assert_eq!(sniff_mime(b"hello world\n"), Some("text/plain"));
+        // a multi-byte character cut off at the end is still text
+        assert_eq!(sniff_mime(&"caf\u{e9}".as_bytes()[..4]), Some("text/plain"));
+        assert_eq!(
+            sniff_mime(b"MZ\x90\x00\x03"),
+            Some("application/vnd.microsoft.portable-executable")
+        );
+        assert_eq!(sniff_mime(b"MZ is a text file"), Some("text/plain"));
+        assert_eq!(sniff_mime(b"\x00\x01\x02\x03"), None);
+    }
+}
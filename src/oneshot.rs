@@ -0,0 +1,168 @@
+//
+// Copyright (c) 2024 Nathan Fiedler
+//
+use crate::{
+    CreateReport, Error, ErrorPolicy, ExtractOptions, ExtractReport, PackBuilder, PackReader,
+    PerformanceProfile, SortOrder, ZstdCodec,
+};
+use std::path::Path;
+
+///
+/// Options that control the creation of an archive by `create_archive()`.
+///
+#[derive(Clone, Debug, Default)]
+pub struct CreateOptions {
+    /// Slash-separated directory within the archive in which to place the
+    /// inputs, rather than at the root.
+    pub prefix: Option<String>,
+    /// Patterns for excluding entries, as with `PackBuilder::add_exclude()`.
+    pub excludes: Vec<String>,
+    /// Zstandard compression level, or `None` for the default.
+    pub level: Option<i32>,
+    /// If true, identical files are stored only once.
+    pub content_addressed: bool,
+    /// If true, only the metadata and checksums of the files are recorded,
+    /// as with `PackBuilder::set_metadata_only()`.
+    pub metadata_only: bool,
+    /// If set, entries are added in sorted order and the modification times
+    /// are clamped to this many seconds since the Unix epoch, as with
+    /// `PackBuilder::set_reproducible()`.
+    pub reproducible: Option<i64>,
+    /// If true, named pipes and device nodes are recorded, otherwise they are
+    /// skipped (the default).
+    pub special_files: bool,
+    /// If set, the contents of directories this many levels below an input
+    /// are left out, as with `PackBuilder::set_max_depth()`.
+    pub max_depth: Option<usize>,
+    /// If true, directories on other file systems are recorded without
+    /// their contents, as with `PackBuilder::set_one_file_system()`.
+    pub one_file_system: bool,
+    /// How to handle files and directories that cannot be read.
+    pub error_policy: ErrorPolicy,
+    /// Order in which the files are added, as with
+    /// `PackBuilder::set_sort_order()`.
+    pub sort_order: SortOrder,
+    /// If true, the compression level is adjusted for each content bundle,
+    /// as with `PackBuilder::set_adaptive_compression()`.
+    pub adaptive: bool,
+    /// Settings for the database that holds the archive.
+    pub profile: PerformanceProfile,
+}
+
+///
+/// Create an archive at the given location that contains the named files and
+/// directories, with the directories added recursively, returning a summary
+/// of the new archive.
+///
+/// ```no_run
+/// # use pack_rs::{create_archive, extract_archive, CreateOptions, ExtractOptions};
+/// create_archive("photos.db3", ["Pictures"], &CreateOptions::default())?;
+/// extract_archive("photos.db3", "restored", &ExtractOptions::default())?;
+/// # Ok::<(), pack_rs::Error>(())
+/// ```
+///
+pub fn create_archive<P, I, Q>(
+    dest: P,
+    inputs: I,
+    options: &CreateOptions,
+) -> Result<CreateReport, Error>
+where
+    P: AsRef<Path>,
+    I: IntoIterator<Item = Q>,
+    Q: AsRef<Path>,
+{
+    let mut builder = PackBuilder::new()?;
+    builder.set_performance_profile(options.profile)?;
+    for pattern in options.excludes.iter() {
+        builder.add_exclude(pattern)?;
+    }
+    if let Some(level) = options.level {
+        builder.set_codec(Box::new(ZstdCodec::new(level)));
+    }
+    builder.set_content_addressed(options.content_addressed)?;
+    builder.set_metadata_only(options.metadata_only);
+    if let Some(epoch) = options.reproducible {
+        builder.set_reproducible(epoch);
+    }
+    builder.set_special_files(options.special_files);
+    builder.set_max_depth(options.max_depth);
+    builder.set_one_file_system(options.one_file_system);
+    builder.set_error_policy(options.error_policy);
+    builder.set_sort_order(options.sort_order);
+    builder.set_adaptive_compression(options.adaptive);
+    builder.set_output_path(dest.as_ref());
+    let root = match options.prefix.as_ref() {
+        Some(prefix) => builder.add_prefix(prefix)?,
+        None => 0,
+    };
+    for input in inputs {
+        builder.add_input(input, root)?;
+    }
+    builder.finish(dest)
+}
+
+///
+/// Extract all of the entries of the archive at the given location into the
+/// `dest` directory, which takes the place of `options.destination`,
+/// returning a summary of what was written.
+///
+pub fn extract_archive<P, Q>(
+    src: P,
+    dest: Q,
+    options: &ExtractOptions,
+) -> Result<ExtractReport, Error>
+where
+    P: AsRef<Path>,
+    Q: AsRef<Path>,
+{
+    let reader = PackReader::open_read_only(src)?;
+    let options = ExtractOptions {
+        destination: dest.as_ref().to_path_buf(),
+        ..options.clone()
+    };
+    reader.extract_all_with(&options)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_create_extract_archive() -> Result<(), Error> {
+        let workspace = tempfile::tempdir()?;
+        let basepath = workspace.path().join("input");
+        fs::create_dir_all(basepath.join("sub"))?;
+        fs::write(basepath.join("hello.txt"), "hello world")?;
+        fs::write(basepath.join("sub/skip.tmp"), "temporary")?;
+        fs::write(workspace.path().join("single.txt"), "single")?;
+        let packfile = workspace.path().join("pack.db3");
+        let options = CreateOptions {
+            prefix: Some("backup".into()),
+            excludes: vec!["*.tmp".into()],
+            level: Some(19),
+            ..Default::default()
+        };
+        let inputs = [basepath.clone(), workspace.path().join("single.txt")];
+        let report = create_archive(&packfile, &inputs, &options)?;
+        assert_eq!(report.files, 2);
+
+        let destination = workspace.path().join("output");
+        fs::create_dir(&destination)?;
+        assert_eq!(
+            extract_archive(&packfile, &destination, &ExtractOptions::default())?.files,
+            2
+        );
+        assert_eq!(
+            fs::read_to_string(destination.join("backup/input/hello.txt"))?,
+            "hello world"
+        );
+        assert_eq!(
+            fs::read_to_string(destination.join("backup/single.txt"))?,
+            "single"
+        );
+        assert!(destination.join("backup/input/sub").is_dir());
+        assert!(!destination.join("backup/input/sub/skip.tmp").exists());
+        Ok(())
+    }
+}
@@ -0,0 +1,286 @@
+//
+// Copyright (c) 2024 Nathan Fiedler
+//
+use crate::Error;
+use std::collections::HashMap;
+
+///
+/// Mapping of the owners (or groups) recorded in an archive to those of the
+/// system on which it is extracted, as given to `ExtractOptions::owner_map`
+/// and `ExtractOptions::group_map`.
+///
+/// In the text form, each line has the form `OLD:NEW`, where `OLD` is either
+/// the numeric identifier or the name recorded in the archive, and `NEW` is
+/// either a numeric identifier or a name known to this system. Blank lines
+/// and lines starting with `#` are ignored.
+///
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct OwnerMap {
+    // replacements for the recorded numeric identifiers
+    by_id: HashMap<u32, u32>,
+    // replacements for the recorded names, which take precedence
+    by_name: HashMap<String, u32>,
+}
+
+impl OwnerMap {
+    ///
+    /// Construct an empty mapping.
+    ///
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    ///
+    /// Parse a mapping of users, in which the new owners may be named by
+    /// the users of this system.
+    ///
+    pub fn parse_users(text: &str) -> Result<Self, Error> {
+        Self::parse(text, user_id)
+    }
+
+    ///
+    /// Parse a mapping of groups, in which the new groups may be named by
+    /// the groups of this system.
+    ///
+    pub fn parse_groups(text: &str) -> Result<Self, Error> {
+        Self::parse(text, group_id)
+    }
+
+    // Parse the text form, resolving new names using the given function.
+    fn parse(text: &str, resolve: fn(&str) -> Option<u32>) -> Result<Self, Error> {
+        let mut map = Self::new();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let invalid = |reason: &str| {
+                Error::InvalidOwnerMap(format!("line {}: {}: {}", number + 1, reason, line))
+            };
+            let (old, new) = line
+                .split_once(':')
+                .ok_or_else(|| invalid("expected OLD:NEW"))?;
+            let (old, new) = (old.trim(), new.trim());
+            if old.is_empty() || new.is_empty() {
+                return Err(invalid("expected OLD:NEW"));
+            }
+            let new = match new.parse::<u32>() {
+                Ok(id) => id,
+                Err(_) => resolve(new).ok_or_else(|| invalid("unknown name"))?,
+            };
+            match old.parse::<u32>() {
+                Ok(id) => map.insert_id(id, new),
+                Err(_) => map.insert_name(old, new),
+            }
+        }
+        Ok(map)
+    }
+
+    ///
+    /// Replace the recorded numeric identifier `old` with `new`.
+    ///
+    pub fn insert_id(&mut self, old: u32, new: u32) {
+        self.by_id.insert(old, new);
+    }
+
+    ///
+    /// Replace the recorded name `old` with the numeric identifier `new`.
+    ///
+    pub fn insert_name(&mut self, old: &str, new: u32) {
+        self.by_name.insert(old.to_owned(), new);
+    }
+
+    ///
+    /// Return the replacement for the recorded identifier and name, if any,
+    /// favoring a match by name.
+    ///
+    pub fn lookup(&self, id: Option<u32>, name: Option<&str>) -> Option<u32> {
+        name.and_then(|n| self.by_name.get(n))
+            .or_else(|| id.and_then(|i| self.by_id.get(&i)))
+            .copied()
+    }
+}
+
+//
+// Owner of an entry as recorded in the archive.
+//
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Owner {
+    pub(crate) uid: Option<u32>,
+    pub(crate) gid: Option<u32>,
+    pub(crate) uname: Option<String>,
+    pub(crate) gname: Option<String>,
+}
+
+//
+// Cache of the names of users and groups, as looking them up may involve
+// reading files or asking a directory service.
+//
+#[derive(Default)]
+pub(crate) struct NameCache {
+    #[cfg(target_family = "unix")]
+    users: HashMap<u32, Option<String>>,
+    #[cfg(target_family = "unix")]
+    groups: HashMap<u32, Option<String>>,
+}
+
+impl NameCache {
+    // Return the owner of the file with the given metadata.
+    #[cfg(target_family = "unix")]
+    pub(crate) fn owner_of(&mut self, metadata: &std::fs::Metadata) -> Owner {
+        use std::os::unix::fs::MetadataExt;
+        let (uid, gid) = (metadata.uid(), metadata.gid());
+        Owner {
+            uid: Some(uid),
+            gid: Some(gid),
+            uname: self
+                .users
+                .entry(uid)
+                .or_insert_with(|| user_name(uid))
+                .clone(),
+            gname: self
+                .groups
+                .entry(gid)
+                .or_insert_with(|| group_name(gid))
+                .clone(),
+        }
+    }
+
+    #[cfg(not(target_family = "unix"))]
+    pub(crate) fn owner_of(&mut self, metadata: &std::fs::Metadata) -> Owner {
+        let _ = metadata;
+        Owner::default()
+    }
+}
+
+// Invoke one of the reentrant passwd or group functions, growing the buffer
+// as needed, and extract the wanted field from the entry that was found, if
+// any. The strings of the entry live in the buffer, hence the extraction.
+#[cfg(target_family = "unix")]
+fn lookup_entry<T, R>(
+    call: impl Fn(&mut T, &mut Vec<libc::c_char>, &mut *mut T) -> libc::c_int,
+    extract: impl FnOnce(&T) -> Option<R>,
+) -> Option<R> {
+    let mut entry: T = unsafe { std::mem::zeroed() };
+    let mut buffer: Vec<libc::c_char> = vec![0; 1024];
+    loop {
+        let mut result: *mut T = std::ptr::null_mut();
+        let rc = call(&mut entry, &mut buffer, &mut result);
+        if rc == libc::ERANGE && buffer.len() < 1 << 20 {
+            buffer.resize(buffer.len() * 2, 0);
+            continue;
+        }
+        if rc == 0 && !result.is_null() {
+            return extract(&entry);
+        }
+        return None;
+    }
+}
+
+// Convert a name returned by the passwd or group functions.
+#[cfg(target_family = "unix")]
+fn name_from_ptr(name: *const libc::c_char) -> Option<String> {
+    if name.is_null() {
+        return None;
+    }
+    let name = unsafe { std::ffi::CStr::from_ptr(name) };
+    name.to_str().ok().map(|n| n.to_owned())
+}
+
+//
+// Return the name of the user with the given identifier, if known.
+//
+#[cfg(target_family = "unix")]
+pub(crate) fn user_name(uid: u32) -> Option<String> {
+    lookup_entry(
+        |pwd: &mut libc::passwd, buf, result| unsafe {
+            libc::getpwuid_r(uid, pwd, buf.as_mut_ptr(), buf.len(), result)
+        },
+        |pwd| name_from_ptr(pwd.pw_name),
+    )
+}
+
+//
+// Return the name of the group with the given identifier, if known.
+//
+#[cfg(target_family = "unix")]
+pub(crate) fn group_name(gid: u32) -> Option<String> {
+    lookup_entry(
+        |grp: &mut libc::group, buf, result| unsafe {
+            libc::getgrgid_r(gid, grp, buf.as_mut_ptr(), buf.len(), result)
+        },
+        |grp| name_from_ptr(grp.gr_name),
+    )
+}
+
+//
+// Return the identifier of the user with the given name, if known.
+//
+#[cfg(target_family = "unix")]
+pub(crate) fn user_id(name: &str) -> Option<u32> {
+    let name = std::ffi::CString::new(name).ok()?;
+    lookup_entry(
+        |pwd: &mut libc::passwd, buf, result| unsafe {
+            libc::getpwnam_r(name.as_ptr(), pwd, buf.as_mut_ptr(), buf.len(), result)
+        },
+        |pwd| Some(pwd.pw_uid),
+    )
+}
+
+//
+// Return the identifier of the group with the given name, if known.
+//
+#[cfg(target_family = "unix")]
+pub(crate) fn group_id(name: &str) -> Option<u32> {
+    let name = std::ffi::CString::new(name).ok()?;
+    lookup_entry(
+        |grp: &mut libc::group, buf, result| unsafe {
+            libc::getgrnam_r(name.as_ptr(), grp, buf.as_mut_ptr(), buf.len(), result)
+        },
+        |grp| Some(grp.gr_gid),
+    )
+}
+
+#[cfg(not(target_family = "unix"))]
+pub(crate) fn user_id(name: &str) -> Option<u32> {
+    let _ = name;
+    None
+}
+
+#[cfg(not(target_family = "unix"))]
+pub(crate) fn group_id(name: &str) -> Option<u32> {
+    let _ = name;
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_owner_map() -> Result<(), Error> {
+        let map = OwnerMap::parse_users("# from the old server\n1000:1001\n\nalice : 2000\n")?;
+        assert_eq!(map.lookup(Some(1000), None), Some(1001));
+        assert_eq!(map.lookup(Some(1000), Some("bob")), Some(1001));
+        assert_eq!(map.lookup(Some(1000), Some("alice")), Some(2000));
+        assert_eq!(map.lookup(Some(500), Some("carol")), None);
+        assert_eq!(map.lookup(None, None), None);
+        assert!(matches!(
+            OwnerMap::parse_users("1000"),
+            Err(Error::InvalidOwnerMap(_))
+        ));
+        assert!(matches!(
+            OwnerMap::parse_users("1000:no-such-user-here"),
+            Err(Error::InvalidOwnerMap(_))
+        ));
+        #[cfg(target_family = "unix")]
+        {
+            let map = OwnerMap::parse_users("1000:root")?;
+            assert_eq!(map.lookup(Some(1000), None), Some(0));
+            assert_eq!(user_name(0).as_deref(), Some("root"));
+            assert_eq!(user_id("root"), Some(0));
+            assert_eq!(group_id("no-such-group-here"), None);
+        }
+        Ok(())
+    }
+}
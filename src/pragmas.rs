@@ -0,0 +1,279 @@
+//
+// Copyright (c) 2024 Nathan Fiedler
+//
+use crate::{Error, PackBuilder, PackReader};
+use rusqlite::Connection;
+use std::path::Path;
+
+///
+/// Settings for the SQLite database that holds the archive, trading
+/// durability and memory use against speed. The page size only applies to
+/// archives that are being created, as it cannot be changed once the
+/// database has content; the journal and synchronous modes only apply when
+/// modifying an existing archive.
+///
+/// When extracting, the profile also determines how much of each content
+/// bundle is read at a time, and whether the operating system is asked to
+/// read the archive ahead of the extraction. The `bench` command, with its
+/// `--profiles` option, compares the extraction speed of the profiles.
+///
+/// For finer control, see `PackBuilder::set_pragma()` and
+/// `PackReader::set_pragma()`.
+///
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PerformanceProfile {
+    /// Small pages and cache, a rollback journal that is synced fully on
+    /// every commit, no memory-mapped I/O, and small reads without any
+    /// read-ahead.
+    Safe,
+    /// Moderate page and cache sizes, a truncated rollback journal with
+    /// normal syncing, memory-mapped reads of up to 1 GiB, and reads of
+    /// 1 MiB with read-ahead.
+    #[default]
+    Balanced,
+    /// Large pages and cache, an in-memory journal without syncing,
+    /// memory-mapped reads of up to 2 GiB, and reads of 4 MiB with
+    /// read-ahead. A crash while modifying an archive may leave it corrupt.
+    Fast,
+}
+
+impl PerformanceProfile {
+    // Size in bytes of the database pages.
+    pub(crate) fn page_size(&self) -> u32 {
+        match self {
+            PerformanceProfile::Safe => 4096,
+            PerformanceProfile::Balanced => 8192,
+            PerformanceProfile::Fast => 65536,
+        }
+    }
+
+    // Size of the page cache, where negative values are in KiB.
+    fn cache_size(&self) -> i64 {
+        match self {
+            PerformanceProfile::Safe => -2048,
+            PerformanceProfile::Balanced => -16384,
+            PerformanceProfile::Fast => -65536,
+        }
+    }
+
+    pub(crate) fn journal_mode(&self) -> &'static str {
+        match self {
+            PerformanceProfile::Safe => "DELETE",
+            PerformanceProfile::Balanced => "TRUNCATE",
+            PerformanceProfile::Fast => "MEMORY",
+        }
+    }
+
+    fn synchronous(&self) -> &'static str {
+        match self {
+            PerformanceProfile::Safe => "FULL",
+            PerformanceProfile::Balanced => "NORMAL",
+            PerformanceProfile::Fast => "OFF",
+        }
+    }
+
+    // Maximum number of bytes of the database that are memory-mapped.
+    fn mmap_size(&self) -> i64 {
+        match self {
+            PerformanceProfile::Safe => 0,
+            PerformanceProfile::Balanced => 1073741824,
+            // the most that SQLite allows by default
+            PerformanceProfile::Fast => 2147418112,
+        }
+    }
+
+    // Number of bytes read from a content bundle at a time, such that the
+    // blob is read in large sequential pieces rather than one page at a time.
+    pub(crate) fn read_buffer_size(&self) -> usize {
+        match self {
+            PerformanceProfile::Safe => 65536,
+            PerformanceProfile::Balanced => 1048576,
+            PerformanceProfile::Fast => 4194304,
+        }
+    }
+
+    // Ask the operating system to start reading the archive at the given
+    // location into memory, up to the memory-mapped size, ahead of it being
+    // extracted. This is merely a hint and failures are ignored.
+    pub(crate) fn advise_read_ahead(&self, path: &Path) {
+        let length = self.mmap_size() as u64;
+        if length == 0 {
+            return;
+        }
+        #[cfg(target_os = "linux")]
+        if let Ok(file) = std::fs::File::open(path) {
+            use std::os::fd::AsRawFd;
+            let length = file.metadata().map_or(0, |m| m.len()).min(length);
+            let rc = unsafe {
+                libc::posix_fadvise(
+                    file.as_raw_fd(),
+                    0,
+                    length as libc::off_t,
+                    libc::POSIX_FADV_WILLNEED,
+                )
+            };
+            tracing::debug!(path = %path.display(), length, rc, "advised read-ahead");
+        }
+        #[cfg(not(target_os = "linux"))]
+        let _ = path;
+    }
+
+    // Apply the settings to a connection that is used for building an
+    // archive. The page size can only be changed while the database is
+    // empty, and the journal and synchronous modes cannot be changed within a
+    // transaction. An archive using the write-ahead log is left as it is, as
+    // that mode can only be left while no one else is reading the archive.
+    pub(crate) fn apply_for_writing(&self, conn: &Connection) -> rusqlite::Result<()> {
+        let page_count: u64 = conn.pragma_query_value(None, "page_count", |row| row.get(0))?;
+        if page_count == 0 {
+            conn.pragma_update(None, "page_size", self.page_size())?;
+        }
+        if conn.is_autocommit() {
+            let mode: String = conn.pragma_query_value(None, "journal_mode", |row| row.get(0))?;
+            if !mode.eq_ignore_ascii_case("wal") {
+                conn.pragma_update(None, "journal_mode", self.journal_mode())?;
+            }
+            conn.pragma_update(None, "synchronous", self.synchronous())?;
+        }
+        self.apply_for_reading(conn)
+    }
+
+    // Apply the settings to a connection that is only used for reading.
+    pub(crate) fn apply_for_reading(&self, conn: &Connection) -> rusqlite::Result<()> {
+        conn.pragma_update(None, "cache_size", self.cache_size())?;
+        conn.pragma_update(None, "mmap_size", self.mmap_size())?;
+        Ok(())
+    }
+}
+
+// Set the named pragma to the given value, which is passed as text.
+fn set_pragma(conn: &Connection, name: &str, value: &str) -> Result<(), Error> {
+    conn.pragma_update(None, name, value)?;
+    Ok(())
+}
+
+impl PackBuilder {
+    ///
+    /// Apply the settings of the given profile to the database, replacing
+    /// those of the default `PerformanceProfile::Balanced`. When building a
+    /// new archive, its page size is changed as it is written by `finish()`.
+    ///
+    pub fn set_performance_profile(&mut self, profile: PerformanceProfile) -> Result<(), Error> {
+        profile.apply_for_writing(&self.conn)?;
+        if self.conn.path().is_none_or(|p| p.is_empty()) {
+            self.page_size = Some(profile.page_size());
+        }
+        self.profile = profile;
+        Ok(())
+    }
+
+    ///
+    /// Set any SQLite pragma to the given value, overriding the performance
+    /// profile, for those that know what they are doing. The pragma name must
+    /// be a plain identifier, such as `cache_size` or `temp_store`.
+    ///
+    pub fn set_pragma(&mut self, name: &str, value: &str) -> Result<(), Error> {
+        set_pragma(&self.conn, name, value)?;
+        self.pragmas.push((name.to_owned(), value.to_owned()));
+        Ok(())
+    }
+
+    // Apply the profile and pragmas given to the builder to the connection
+    // that replaces its database, apart from the journal and synchronous
+    // modes of the profile, which are for the caller to decide.
+    pub(crate) fn reapply_settings(&self, conn: &Connection) -> Result<(), Error> {
+        self.profile.apply_for_reading(conn)?;
+        for (name, value) in self.pragmas.iter() {
+            set_pragma(conn, name, value)?;
+        }
+        Ok(())
+    }
+}
+
+impl PackReader {
+    ///
+    /// Apply the settings of the given profile to the database, replacing
+    /// those of the default `PerformanceProfile::Balanced`. Only the cache and
+    /// memory-mapping sizes, and the reading of the content bundles, are
+    /// relevant when reading.
+    ///
+    pub fn set_performance_profile(&mut self, profile: PerformanceProfile) -> Result<(), Error> {
+        profile.apply_for_reading(&self.conn)?;
+        self.profile = profile;
+        Ok(())
+    }
+
+    ///
+    /// Set any SQLite pragma to the given value, overriding the performance
+    /// profile, for those that know what they are doing. The pragma name must
+    /// be a plain identifier, such as `cache_size` or `mmap_size`.
+    ///
+    pub fn set_pragma(&mut self, name: &str, value: &str) -> Result<(), Error> {
+        set_pragma(&self.conn, name, value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_performance_profile() -> Result<(), Error> {
+        let workspace = tempfile::tempdir()?;
+        let basepath = workspace.path().join("input");
+        fs::create_dir_all(&basepath)?;
+        fs::write(basepath.join("hello.txt"), "hello world")?;
+        let page_size = |conn: &Connection| -> Result<u32, Error> {
+            Ok(conn.pragma_query_value(None, "page_size", |row| row.get(0))?)
+        };
+
+        // the default profile applies to new archives
+        let packfile = workspace.path().join("balanced.db3");
+        let mut builder = PackBuilder::new()?;
+        builder.add_dir_all(&basepath)?;
+        builder.finish(&packfile)?;
+        let reader = PackReader::new(&packfile)?;
+        assert_eq!(page_size(&reader.conn)?, 8192);
+        let cache: i64 = reader
+            .conn
+            .pragma_query_value(None, "cache_size", |row| row.get(0))?;
+        assert_eq!(cache, -16384);
+        let mmap: i64 = reader
+            .conn
+            .pragma_query_value(None, "mmap_size", |row| row.get(0))?;
+        assert_eq!(mmap, 1073741824);
+        assert_eq!(reader.profile.read_buffer_size(), 1048576);
+
+        // the page size of a new archive is changed when it is written
+        let packfile = workspace.path().join("fast.db3");
+        let mut builder = PackBuilder::new()?;
+        builder.add_dir_all(&basepath)?;
+        builder.set_performance_profile(PerformanceProfile::Fast)?;
+        builder.finish(&packfile)?;
+        let mut reader = PackReader::new(&packfile)?;
+        assert_eq!(page_size(&reader.conn)?, 65536);
+        assert_eq!(reader.read_range("input/hello.txt", 0, 5)?, b"hello");
+        reader.set_performance_profile(PerformanceProfile::Safe)?;
+        assert_eq!(reader.profile, PerformanceProfile::Safe);
+        let mmap: i64 = reader
+            .conn
+            .pragma_query_value(None, "mmap_size", |row| row.get(0))?;
+        assert_eq!(mmap, 0);
+
+        // the page size of an existing archive is left alone
+        let mut builder = PackBuilder::open(&packfile)?;
+        builder.set_performance_profile(PerformanceProfile::Safe)?;
+        builder.set_pragma("cache_size", "-1024")?;
+        let cache: i64 = builder
+            .conn
+            .pragma_query_value(None, "cache_size", |row| row.get(0))?;
+        assert_eq!(cache, -1024);
+        assert_eq!(page_size(&builder.conn)?, 65536);
+        builder.commit()?;
+        assert!(builder
+            .set_pragma("cache_size; DROP TABLE item", "1")
+            .is_err());
+        Ok(())
+    }
+}
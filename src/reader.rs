@@ -0,0 +1,5707 @@
+//
+// Copyright (c) 2024 Nathan Fiedler
+//
+use crate::crypto::{ContentKey, OpeningReader, KEY_ENTRY};
+use crate::digest::Hasher;
+use crate::{
+    has_column, has_table, Codec, DigestAlgorithm, Error, ErrorPolicy, ItemKind, OwnerMap,
+    PerformanceProfile, StoredCodec, Warning, WarningCallback, ZstdCodec, KIND_DIRECTORY,
+    KIND_FILE, KIND_SYMLINK,
+};
+use rusqlite::serialize::OwnedData;
+use rusqlite::{Connection, DatabaseName, OpenFlags};
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fs;
+use std::io::{self, BufReader, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::ptr::{self, NonNull};
+use std::rc::Rc;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+
+///
+/// Options that control the extraction of entries from an archive.
+///
+#[derive(Clone, Debug)]
+pub struct ExtractOptions {
+    /// Directory beneath which all entries are written, which defaults to the
+    /// current directory.
+    pub destination: PathBuf,
+    /// If true (the default), directories that contain no files or symbolic
+    /// links (directly or indirectly) are created as well.
+    pub empty_dirs: bool,
+    /// If true, named pipes and device nodes are created, otherwise they are
+    /// skipped (the default). Creating device nodes usually requires elevated
+    /// privileges.
+    pub special_files: bool,
+    /// If true, the resource fork and Finder metadata recorded in the archive
+    /// are restored to the extracted files and directories. This only has an
+    /// effect on macOS.
+    pub mac_metadata: bool,
+    /// If true, files that already exist at the destination with the expected
+    /// size and checksum are left as they are, such that an interrupted
+    /// extraction can be continued without starting over. Any other existing
+    /// files are rewritten from the beginning.
+    pub resume: bool,
+    /// If set, the values of symbolic links are rewritten as they are
+    /// extracted, such that links created on one system still lead somewhere
+    /// useful when extracted elsewhere.
+    pub rewrite_links: Option<LinkRewrite>,
+    /// If true, the owner and group recorded for each entry are restored,
+    /// which usually requires elevated privileges. The recorded names are
+    /// favored over the numeric identifiers, unless a mapping applies. This
+    /// only has an effect on Unix systems.
+    pub owners: bool,
+    /// Replacements for the recorded owners, when restoring them.
+    pub owner_map: Option<OwnerMap>,
+    /// Replacements for the recorded groups, when restoring them.
+    pub group_map: Option<OwnerMap>,
+    /// If true, the recorded names of the owners and groups are ignored when
+    /// restoring them, such that only the numeric identifiers are used (and
+    /// mapped), as with `tar --numeric-owner`.
+    pub numeric_owner: bool,
+    /// If true (the default), the permissions recorded for each file are
+    /// restored exactly. Otherwise the permissions masked by the umask of the
+    /// process are removed, as with `tar --no-same-permissions`. This only
+    /// has an effect on Unix systems.
+    pub same_permissions: bool,
+    /// If set, the checksum of each file is computed as its content is
+    /// written and compared with the one recorded in the archive, without
+    /// reading the file again. A mismatch fails the extraction with
+    /// `Error::ChecksumMismatch` under `ErrorPolicy::Fail`, and is reported as
+    /// `Warning::ChecksumMismatch` under `ErrorPolicy::Warn`; under
+    /// `ErrorPolicy::Skip` the file is also removed. Files for which no
+    /// checksum was recorded are not verified.
+    pub verify: Option<ErrorPolicy>,
+    /// How to handle entries whose paths differ only in case, such as
+    /// `Makefile` and `makefile`, which would overwrite one another at a
+    /// destination that ignores case. By default, such entries are renamed
+    /// if the destination is found to ignore case.
+    pub case_collisions: CaseCollisions,
+}
+
+impl Default for ExtractOptions {
+    fn default() -> Self {
+        Self {
+            destination: PathBuf::from("."),
+            empty_dirs: true,
+            special_files: false,
+            mac_metadata: false,
+            resume: false,
+            rewrite_links: None,
+            owners: false,
+            owner_map: None,
+            group_map: None,
+            numeric_owner: false,
+            same_permissions: true,
+            verify: None,
+            case_collisions: CaseCollisions::default(),
+        }
+    }
+}
+
+///
+/// How entries whose paths differ only in case are extracted, as set in
+/// `ExtractOptions::case_collisions`. Such entries are renamed by adding a
+/// number to the end of the name (before any extension), as in
+/// `makefile (2)`, where the entry whose path sorts first keeps its name.
+/// Directories are never renamed, such that their contents are merged, and
+/// any file that collides with a directory is renamed instead. Each renamed
+/// entry is reported as `Warning::CaseCollision`.
+///
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CaseCollisions {
+    /// Rename the colliding entries if the destination ignores case, as is
+    /// usual on Windows and macOS.
+    #[default]
+    Auto,
+    /// Rename the colliding entries regardless of the destination.
+    Rename,
+    /// Extract the entries as named, such that at a destination that ignores
+    /// case, the one extracted last replaces the others.
+    Overwrite,
+}
+
+///
+/// Options that select and order the entries returned by
+/// `PackReader::entries_with()`. The selection and ordering are done by the
+/// database, such that listing a small part of a very large archive does not
+/// read every entry.
+///
+#[derive(Clone, Debug, Default)]
+pub struct ListOptions {
+    /// If set, only entries whose path begins with this prefix are returned.
+    /// A prefix ending with a slash, such as `docs/`, selects everything
+    /// within that directory, not including the directory itself.
+    pub prefix: Option<String>,
+    /// If set, only entries of this kind are returned.
+    pub kind: Option<ItemKind>,
+    /// Order in which the entries are returned.
+    pub order: ListOrder,
+    /// If true, the order is reversed, except for the natural order.
+    pub descending: bool,
+}
+
+///
+/// Order of the entries returned by `PackReader::entries_with()`, as set in
+/// `ListOptions::order`.
+///
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ListOrder {
+    /// The order in which the database walks the hierarchy, which is the
+    /// quickest to produce.
+    #[default]
+    Natural,
+    /// By full path, comparing bytes.
+    Path,
+    /// By size of the content, with ties ordered by path.
+    Size,
+    /// By modification time, with ties ordered by path.
+    Mtime,
+}
+
+///
+/// How the values of symbolic links are rewritten when extracting, as set in
+/// `ExtractOptions::rewrite_links`.
+///
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum LinkRewrite {
+    /// Absolute values are made relative to the directory that contains the
+    /// link, treating the root of the archive as the root of the file system,
+    /// which suits archives of an entire system image. Relative values are
+    /// left as they are.
+    Relative,
+    /// Values that start with the path `from` have that part replaced by
+    /// `to`, such as for a home directory that moved. Other values are left
+    /// as they are.
+    Prefix { from: String, to: String },
+}
+
+impl LinkRewrite {
+    ///
+    /// Return the rewritten value of the symbolic link at the given
+    /// slash-separated path within the archive.
+    ///
+    pub fn apply(&self, target: &[u8], path: &str) -> Vec<u8> {
+        match self {
+            LinkRewrite::Relative => match target.strip_prefix(b"/") {
+                Some(absolute) => relative_link(absolute, path),
+                None => target.to_vec(),
+            },
+            LinkRewrite::Prefix { from, to } => {
+                let from = from.trim_end_matches('/').as_bytes();
+                let to = to.trim_end_matches('/').as_bytes();
+                // the prefix must match whole components of the value
+                let rest = if from.is_empty() {
+                    target.starts_with(b"/").then_some(target)
+                } else {
+                    target
+                        .strip_prefix(from)
+                        .filter(|rest| rest.is_empty() || rest[0] == b'/')
+                };
+                match rest {
+                    Some(rest) if to.is_empty() && rest.is_empty() => b"/".to_vec(),
+                    Some(rest) => [to, rest].concat(),
+                    None => target.to_vec(),
+                }
+            }
+        }
+    }
+}
+
+// Select the owner (or group) to give an extracted entry: the replacement
+// from the mapping, if any, otherwise the local identifier for the recorded
+// name, falling back to the recorded identifier.
+#[cfg(target_family = "unix")]
+fn resolve_owner(
+    map: Option<&OwnerMap>,
+    id: Option<u32>,
+    name: Option<&str>,
+    resolve: fn(&str) -> Option<u32>,
+) -> Option<u32> {
+    map.and_then(|m| m.lookup(id, name))
+        .or_else(|| name.and_then(resolve))
+        .or(id)
+}
+
+// Return the file mode creation mask of the process. There is no way to read
+// the mask without also setting it, so it is briefly replaced.
+#[cfg(target_family = "unix")]
+fn current_umask() -> u32 {
+    let mask = unsafe { libc::umask(0o022) };
+    unsafe { libc::umask(mask) };
+    mask as u32
+}
+
+// Express the absolute link value (without its leading slash) relative to
+// the directory of the link at the given path within the archive.
+fn relative_link(absolute: &[u8], path: &str) -> Vec<u8> {
+    let mut linkdir: Vec<&[u8]> = path
+        .trim_end_matches('/')
+        .split('/')
+        .filter(|c| !c.is_empty())
+        .map(|c| c.as_bytes())
+        .collect();
+    linkdir.pop();
+    let target: Vec<&[u8]> = absolute
+        .split(|b| *b == b'/')
+        .filter(|c| !c.is_empty() && *c != b".")
+        .collect();
+    // the common leading directories are only skipped when the value does
+    // not climb back out through them
+    let common = if target.contains(&&b".."[..]) {
+        0
+    } else {
+        linkdir
+            .iter()
+            .zip(target.iter())
+            .take_while(|(a, b)| a == b)
+            .count()
+    };
+    let mut components: Vec<&[u8]> = vec![b".."; linkdir.len() - common];
+    components.extend_from_slice(&target[common..]);
+    if components.is_empty() {
+        return b".".to_vec();
+    }
+    components.join(&b'/')
+}
+
+///
+/// Limits on what extracting an archive may produce, guarding against archives
+/// from untrusted sources that would expand to an unreasonable size (so-called
+/// decompression bombs). Each limit is unset (`None`) by default.
+///
+/// The limits are checked against the entries that are to be extracted before
+/// anything is written, such that extraction either completes in full or
+/// fails with `Error::LimitExceeded` without having written anything.
+///
+#[derive(Clone, Debug, Default)]
+pub struct ExtractLimits {
+    /// Maximum combined size in bytes of all files that are extracted.
+    pub max_total_size: Option<u64>,
+    /// Maximum number of files that are extracted.
+    pub max_files: Option<u64>,
+    /// Maximum size in bytes of any one file.
+    pub max_file_size: Option<u64>,
+    /// Maximum number of components in the path of any entry, such that a
+    /// limit of 1 allows only entries at the root of the archive.
+    pub max_depth: Option<u64>,
+}
+
+// Callback invoked with the path of each extracted file.
+type FileCallback = dyn Fn(&Path) -> Result<(), Error> + Send;
+
+///
+/// Reads the contents of an archive.
+///
+pub struct PackReader {
+    pub(crate) conn: Connection,
+    // true if the content table has a checksum column
+    bundle_checksums: bool,
+    // identifier of the algorithm for the file and bundle checksums
+    digest: String,
+    // true if the content table has a codec column
+    bundle_codecs: bool,
+    // true if the item table has a target column for symbolic links
+    link_targets: bool,
+    // codecs available for decompressing the content bundles
+    codecs: Vec<Box<dyn Codec>>,
+    // settings that determine how the content bundles are read
+    pub(crate) profile: PerformanceProfile,
+    // true if the content bundles are encrypted
+    encrypted: bool,
+    // key with which the content bundles are encrypted, once known
+    content_key: Option<ContentKey>,
+    // recently decompressed content bundles
+    cache: RefCell<BundleCache>,
+    // decompressed content bundles kept on disk across processes, if enabled
+    disk_cache: Option<DiskCache>,
+    // true if paths are to be matched without regard to case
+    ignore_case: bool,
+    // new paths of the entries being extracted that collide with others
+    renamed: RefCell<HashMap<String, String>>,
+    // limits that are checked before extracting any entries
+    limits: ExtractLimits,
+    // invoked with the path of each file once its content is written
+    on_file_extracted: Option<Box<FileCallback>>,
+    // non-fatal events that occurred while extracting entries
+    warnings: RefCell<Vec<Warning>>,
+    // invoked with each warning as it occurs
+    on_warning: Option<Box<WarningCallback>>,
+}
+
+impl PackReader {
+    ///
+    /// Construct a new `PackReader` that will read from the pack file at the
+    /// given location.
+    ///
+    pub fn new<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let conn = Connection::open(path.as_ref())?;
+        Self::with_connection(conn)
+    }
+
+    ///
+    /// Construct a new `PackReader` that opens the pack file at the given
+    /// location in read-only mode, such that it never modifies the file.
+    ///
+    /// If the file itself is not writable, it is assumed that no other process
+    /// will be modifying it, and it is opened as immutable, which disables all
+    /// locking. Otherwise the usual locking applies, but without waiting for a
+    /// writer to finish committing its changes; in that case reads will fail
+    /// with a "database is locked" error rather than block.
+    ///
+    pub fn open_read_only<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let metadata = fs::metadata(path.as_ref())?;
+        let mut uri = file_uri(path.as_ref());
+        if metadata.permissions().readonly() {
+            uri.push_str("?immutable=1");
+        }
+        let flags = OpenFlags::SQLITE_OPEN_READ_ONLY
+            | OpenFlags::SQLITE_OPEN_URI
+            | OpenFlags::SQLITE_OPEN_NO_MUTEX;
+        let conn = Connection::open_with_flags(uri, flags)?;
+        conn.busy_timeout(Duration::ZERO)?;
+        Self::with_connection(conn)
+    }
+
+    ///
+    /// Construct a `PackReader` from the content of a pack file that is held
+    /// in memory, such as one fetched by a web page, without touching the file
+    /// system. The data is copied into an in-memory database that is opened
+    /// as read-only. This is the way to open an archive in WebAssembly, where
+    /// the entries can then be listed and read, or extracted to a `MemorySink`
+    /// using `extract_to()`.
+    ///
+    pub fn from_bytes(data: &[u8]) -> Result<Self, Error> {
+        if !data.starts_with(crate::SQL_HEADER) {
+            return Err(Error::NotPackFile);
+        }
+        let mut conn = Connection::open_in_memory()?;
+        // SQLite frees the copy when the connection is closed, so it must be
+        // allocated by SQLite as well
+        let copy = unsafe { rusqlite::ffi::sqlite3_malloc64(data.len().max(1) as u64) };
+        let copy = NonNull::new(copy as *mut u8)
+            .ok_or_else(|| Error::IOError(io::ErrorKind::OutOfMemory.into()))?;
+        // SAFETY: the allocation is at least as long as the data
+        unsafe { ptr::copy_nonoverlapping(data.as_ptr(), copy.as_ptr(), data.len()) };
+        // SAFETY: the allocation came from sqlite3_malloc64()
+        let owned = unsafe { OwnedData::from_raw_nonnull(copy, data.len()) };
+        conn.deserialize(DatabaseName::Main, owned, true)?;
+        Self::with_connection(conn)
+    }
+
+    // Construct a `PackReader` using the given database connection.
+    pub(crate) fn with_connection(mut conn: Connection) -> Result<Self, Error> {
+        conn.profile(Some(crate::trace_statement));
+        PerformanceProfile::default().apply_for_reading(&conn)?;
+        crate::check_features(&conn)?;
+        let bundle_checksums = has_column(&conn, "content", "checksum")?;
+        let bundle_codecs = has_column(&conn, "content", "codec")?;
+        let link_targets = has_column(&conn, "item", "target")?;
+        let mut reader = Self {
+            conn,
+            bundle_checksums,
+            digest: DigestAlgorithm::Blake3.identifier().to_owned(),
+            bundle_codecs,
+            link_targets,
+            codecs: vec![Box::new(ZstdCodec::default()), Box::new(StoredCodec)],
+            profile: PerformanceProfile::default(),
+            encrypted: false,
+            content_key: None,
+            cache: RefCell::new(BundleCache::new(DEFAULT_CACHE_SIZE)),
+            disk_cache: None,
+            ignore_case: false,
+            renamed: RefCell::new(HashMap::new()),
+            limits: ExtractLimits::default(),
+            on_file_extracted: None,
+            warnings: RefCell::new(vec![]),
+            on_warning: None,
+        };
+        if let Some(digest) = reader.archive_meta("digest")? {
+            reader.digest = digest;
+        }
+        reader.encrypted = reader.archive_meta("encryption")?.is_some();
+        Ok(reader)
+    }
+
+    ///
+    /// Return true if the content of the files is encrypted, in which case a
+    /// password must be given to `set_password()` before reading any of it.
+    /// The entries themselves can be listed and searched without a password.
+    ///
+    pub fn is_encrypted(&self) -> bool {
+        self.encrypted
+    }
+
+    ///
+    /// Provide the password with which the content of the archive was
+    /// encrypted, returning `Error::WrongPassword` if it does not match. This
+    /// has no effect if the archive is not encrypted.
+    ///
+    pub fn set_password(&mut self, password: &str) -> Result<(), Error> {
+        if self.encrypted {
+            let wrapped = self
+                .archive_meta(KEY_ENTRY)?
+                .ok_or(Error::PasswordRequired)?;
+            let encryption = self.archive_meta("encryption")?.unwrap_or_default();
+            self.content_key = Some(ContentKey::unwrap(&wrapped, password, &encryption)?);
+        }
+        Ok(())
+    }
+
+    // Return the key for decrypting the content bundles, if they are
+    // encrypted, or an error if the password has not been given.
+    pub(crate) fn content_key(&self) -> Result<Option<&ContentKey>, Error> {
+        match (self.encrypted, self.content_key.as_ref()) {
+            (false, _) => Ok(None),
+            (true, Some(key)) => Ok(Some(key)),
+            (true, None) => Err(Error::PasswordRequired),
+        }
+    }
+
+    ///
+    /// Set the maximum number of bytes of decompressed content bundles that
+    /// are kept in memory, such that reading many small files (or the same
+    /// range repeatedly) does not decompress the same bundle again. This
+    /// applies to `read_range()` and the other methods that read the content
+    /// of individual files, but not to `extract_all()`. Bundles larger than
+    /// the cache are read as far as needed each time, without being held in
+    /// memory. A size of zero disables the cache. The default is 32 MiB.
+    ///
+    pub fn set_cache_size(&mut self, bytes: u64) {
+        self.cache.get_mut().resize(bytes);
+    }
+
+    ///
+    /// Keep decompressed content bundles as files in the given directory, in
+    /// addition to the cache in memory, such that later readers of the same
+    /// archive need not read and decompress those bundles again. This helps
+    /// most with large archives on slow storage or read over the network.
+    /// Once the files exceed `limit` bytes in total, the least recently used
+    /// are removed. The directory is created if necessary, and may be shared
+    /// by any number of archives and processes.
+    ///
+    /// Bundles are cached only if the archive records their checksums, which
+    /// identify them in the cache, and never if they are encrypted, as that
+    /// would leave their content on disk in the clear. Nor are they cached
+    /// if the checksums are computed with a digest that does not resist
+    /// deliberate collisions, such as `xxh3-128`, as then a crafted archive
+    /// could place a bundle in the cache that another archive sharing the
+    /// directory would take to be one of its own.
+    ///
+    pub fn set_cache_dir<P: AsRef<Path>>(&mut self, dir: P, limit: u64) -> Result<(), Error> {
+        fs::create_dir_all(dir.as_ref())?;
+        self.disk_cache = Some(DiskCache {
+            dir: dir.as_ref().to_path_buf(),
+            limit,
+        });
+        Ok(())
+    }
+
+    ///
+    /// If enabled, paths given to `read_range()`, `read_path()`, and the like
+    /// are matched without regard to (ASCII) case, which helps with archives
+    /// created on systems whose file systems ignore case. Where several entries
+    /// match, the one whose name matches exactly is preferred.
+    ///
+    pub fn set_ignore_case(&mut self, enabled: bool) {
+        self.ignore_case = enabled;
+    }
+
+    ///
+    /// Set the limits that are checked before extracting entries with
+    /// `extract_all()` and `extract_subtree()`, for use with archives that
+    /// come from untrusted sources.
+    ///
+    pub fn set_extract_limits(&mut self, limits: ExtractLimits) {
+        self.limits = limits;
+    }
+
+    ///
+    /// Set a function to be invoked with the location of each file written by
+    /// `extract_all()` and `extract_subtree()`, such as to scan the file for
+    /// viruses or log its checksum. The function is called as soon as all of
+    /// the content of a file has been written (and verified, if requested),
+    /// before the permissions and owner of the file are restored, such that
+    /// the file can be read even if it is recorded as unreadable. Any error
+    /// returned by the function stops the extraction and is returned.
+    ///
+    pub fn set_on_file_extracted<F>(&mut self, callback: F)
+    where
+        F: Fn(&Path) -> Result<(), Error> + Send + 'static,
+    {
+        self.on_file_extracted = Some(Box::new(callback));
+    }
+
+    ///
+    /// Set a function to be invoked with each non-fatal event as it occurs,
+    /// such as a special file being skipped during extraction. The warnings
+    /// are also collected and available from `warnings()`.
+    ///
+    pub fn set_on_warning<F>(&mut self, callback: F)
+    where
+        F: Fn(&Warning) + Send + 'static,
+    {
+        self.on_warning = Some(Box::new(callback));
+    }
+
+    ///
+    /// Return the non-fatal events that occurred while extracting entries, in
+    /// the order in which they occurred.
+    ///
+    pub fn warnings(&self) -> Vec<Warning> {
+        self.warnings.borrow().clone()
+    }
+
+    // Record the warning and pass it along to the callback, if any.
+    fn warn(&self, warning: Warning) {
+        tracing::warn!(%warning, "non-fatal event");
+        if let Some(callback) = self.on_warning.as_ref() {
+            callback(&warning);
+        }
+        self.warnings.borrow_mut().push(warning);
+    }
+
+    ///
+    /// Make the codec available for decompressing content bundles, replacing
+    /// any codec with the same identifier.
+    ///
+    pub fn register_codec(&mut self, codec: Box<dyn Codec>) {
+        self.codecs.retain(|c| c.identifier() != codec.identifier());
+        self.codecs.push(codec);
+    }
+
+    // Open the content bundle for reading using the codec that was used to
+    // compress it. Bundles without a recorded codec use zstd.
+    fn open_bundle(&self, content_id: i64) -> Result<BundleStream<'_>, Error> {
+        if content_id == crate::NO_CONTENT {
+            return Err(Error::MetadataOnly);
+        }
+        let identifier: Option<String> = if self.bundle_codecs {
+            self.conn.query_row(
+                "SELECT codec FROM content WHERE id = ?1",
+                [&content_id],
+                |row| row.get(0),
+            )?
+        } else {
+            None
+        };
+        let identifier = identifier.unwrap_or_else(|| ZstdCodec::IDENTIFIER.to_owned());
+        let codec = self
+            .codecs
+            .iter()
+            .find(|c| c.identifier() == identifier)
+            .ok_or(Error::UnknownCodec(identifier))?;
+        let buffer_size = self.profile.read_buffer_size();
+        let key = self.content_key()?;
+        let check = match self.bundle_checksum(content_id)? {
+            Some(expected) => Some(BundleCheck::new(expected, self.digest_algorithm()?)),
+            None => None,
+        };
+        BundleStream::new(
+            &self.conn,
+            content_id,
+            codec.as_ref(),
+            buffer_size,
+            key,
+            check,
+        )
+    }
+
+    ///
+    /// Return all items in the archive with the `name` as the full path.
+    ///
+    /// Directory entries have a path that ends with a slash (/).
+    ///
+    pub fn entries(&self) -> Result<Vec<Result<Entry, rusqlite::Error>>, Error> {
+        self.entries_with(&ListOptions::default())
+    }
+
+    ///
+    /// Return the items in the archive selected by the options, in the order
+    /// they specify, with the `name` as the full path as in `entries()`.
+    ///
+    /// When a prefix is given, the database walks only the directory named
+    /// by the part of the prefix up to the last slash, such that listing one
+    /// directory of a very large archive takes time in proportion to the
+    /// size of that directory. If that directory does not exist, the result
+    /// is empty.
+    ///
+    pub fn entries_with(
+        &self,
+        options: &ListOptions,
+    ) -> Result<Vec<Result<Entry, rusqlite::Error>>, Error> {
+        //
+        // Would love to return an iterator but that is quite difficult given
+        // that the lifetimes and types are not very cooperative.
+        //
+        // Query from Pack in UPackDraft0Shared.pas that queries all items in
+        // ascending order to make it easy to build the results.
+        //
+        let prefix = options.prefix.as_deref().unwrap_or("");
+        let (dir, rest) = match prefix.rfind('/') {
+            Some(pos) => prefix.split_at(pos + 1),
+            None => ("", prefix),
+        };
+        let (parent, base) = match self.lookup_path(dir)? {
+            None if dir.trim_matches('/').is_empty() => (0, String::new()),
+            Some((item_id, KIND_DIRECTORY)) => (item_id, self.item_path(item_id)?),
+            _ => return Ok(vec![]),
+        };
+        let collate = if self.ignore_case {
+            "COLLATE NOCASE"
+        } else {
+            ""
+        };
+        let kind = match options.kind {
+            Some(kind) => format!("WHERE kind = {}", i8::from(kind)),
+            None => String::new(),
+        };
+        let direction = if options.descending { "DESC" } else { "ASC" };
+        let order = match options.order {
+            ListOrder::Natural => String::new(),
+            ListOrder::Path => format!("ORDER BY Path {}", direction),
+            ListOrder::Size => format!(
+                "ORDER BY {} {}, Path {}",
+                self.item_size_column(),
+                direction,
+                direction
+            ),
+            ListOrder::Mtime if has_table(&self.conn, "attrs")? => format!(
+                "ORDER BY (SELECT mtime FROM attrs WHERE attrs.item = FIT.id) {}, Path {}",
+                direction, direction
+            ),
+            ListOrder::Mtime => format!("ORDER BY Path {}", direction),
+        };
+        let query = format!(
+            "WITH RECURSIVE FIT AS (
+    SELECT *, ?2 || Name || IIF(Kind = 1, '/', '') AS Path FROM Item
+        WHERE Parent = ?1 AND SUBSTR(Name, 1, ?4) = ?3 {}
+    UNION ALL
+    SELECT Item.*, FIT.Path || Item.Name || IIF(Item.Kind = 1, '/', '') AS Path
+        FROM Item INNER JOIN FIT ON FIT.Kind = 1 AND Item.Parent = FIT.ID
+)
+SELECT id, parent, kind, Path FROM FIT {} {};",
+            collate, kind, order
+        );
+        let mut stmt = self.conn.prepare(&query)?;
+        let rest_len = rest.chars().count() as i64;
+        let items: Vec<Result<Entry, rusqlite::Error>> = stmt
+            .query_map((&parent, &base, rest, &rest_len), |row| {
+                Ok(Entry {
+                    id: row.get(0)?,
+                    parent: row.get(1)?,
+                    kind: row.get(2)?,
+                    name: row.get(3)?,
+                })
+            })?
+            .collect();
+        Ok(items)
+    }
+
+    ///
+    /// Run a read-only SQL query against the database of the archive, such as
+    /// for analytics over the `item`, `itemcontent`, and `attrs` tables, and
+    /// return the rows converted by `map`. The tables are described in the
+    /// Specification section of the README, and are a stable interface: new
+    /// columns and tables may be added, but existing ones are not changed.
+    /// The `rusqlite` crate is re-exported for the sake of the parameter and
+    /// row types.
+    ///
+    /// Returns `Error::NotReadOnly` if the statement would modify the
+    /// database, and so the archive, or if it returns no rows at all, such as
+    /// `ATTACH`, `BEGIN`, or a `PRAGMA` that changes a setting.
+    ///
+    pub fn query<T, P, F>(&self, sql: &str, params: P, map: F) -> Result<Vec<T>, Error>
+    where
+        P: rusqlite::Params,
+        F: FnMut(&rusqlite::Row<'_>) -> rusqlite::Result<T>,
+    {
+        let mut stmt = self.conn.prepare(sql)?;
+        // statements such as ATTACH and BEGIN do not write to the database
+        // themselves, and so count as read-only, but neither do they return
+        // anything
+        if !stmt.readonly() || stmt.column_count() == 0 {
+            return Err(Error::NotReadOnly(sql.to_owned()));
+        }
+        // and in case anything else slips through, writes are refused
+        self.conn.pragma_update(None, "query_only", true)?;
+        let rows = stmt
+            .query_map(params, map)
+            .and_then(|rows| rows.collect::<Result<Vec<T>, _>>());
+        self.conn.pragma_update(None, "query_only", false)?;
+        Ok(rows?)
+    }
+
+    ///
+    /// Invoke the visitor with each entry in the archive, in breadth-first
+    /// order, along with a reader that yields the content of the entry. The
+    /// content is decompressed as it is read, and not at all if the visitor
+    /// does not read from it. For directories the reader is always empty, while
+    /// for symbolic links it yields the link value.
+    ///
+    /// Any error returned by the visitor stops the traversal and is returned.
+    ///
+    pub fn visit<F>(&self, visitor: F) -> Result<(), Error>
+    where
+        F: FnMut(&Entry, &mut dyn Read) -> Result<(), Error>,
+    {
+        self.visit_with(&ListOptions::default(), visitor)
+    }
+
+    // Invoke the visitor as with visit(), for the entries selected by the
+    // options, in the order they specify.
+    pub(crate) fn visit_with<F>(&self, options: &ListOptions, mut visitor: F) -> Result<(), Error>
+    where
+        F: FnMut(&Entry, &mut dyn Read) -> Result<(), Error>,
+    {
+        for result in self.entries_with(options)? {
+            let entry = result?;
+            if entry.kind == ItemKind::Symlink {
+                if let Some(target) = self.link_target(entry.id)? {
+                    visitor(&entry, &mut target.as_slice())?;
+                    continue;
+                }
+            }
+            let mut content = ContentReader::new(self, entry.id)?;
+            visitor(&entry, &mut content)?;
+        }
+        Ok(())
+    }
+
+    ///
+    /// Return the hierarchy of items in the archive, with the entries at each
+    /// level sorted by name. The size of each directory is the total size of
+    /// the items beneath it.
+    ///
+    pub fn tree(&self) -> Result<Vec<TreeNode>, Error> {
+        let query = format!(
+            "WITH RECURSIVE FIT AS (
+    SELECT *, Name || IIF(Kind = 1, '/', '') AS Path FROM Item WHERE Parent = 0
+    UNION ALL
+    SELECT Item.*, FIT.Path || Item.Name || IIF(Item.Kind = 1, '/', '') AS Path
+        FROM Item INNER JOIN FIT ON FIT.Kind = 1 AND Item.Parent = FIT.ID
+)
+SELECT id, parent, kind, name, {}
+FROM FIT ORDER BY name;",
+            self.item_size_column()
+        );
+        let mut stmt = self.conn.prepare(&query)?;
+        let rows = stmt
+            .query_map([], |row| {
+                Ok((
+                    row.get::<_, i64>(0)?,
+                    row.get::<_, i64>(1)?,
+                    TreeNode {
+                        kind: row.get(2)?,
+                        name: row.get(3)?,
+                        size: row.get(4)?,
+                        children: vec![],
+                    },
+                ))
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut children: HashMap<i64, Vec<(i64, TreeNode)>> = HashMap::new();
+        for (id, parent, node) in rows {
+            children.entry(parent).or_default().push((id, node));
+        }
+        Ok(assemble_tree(0, &mut children))
+    }
+
+    ///
+    /// Extract all of the entries into the current directory, returning a
+    /// summary of what was written.
+    ///
+    pub fn extract_all(&self) -> Result<ExtractReport, Error> {
+        self.extract_all_with(&ExtractOptions::default())
+    }
+
+    ///
+    /// Extract all of the entries according to the given options, returning
+    /// a summary of what was written.
+    ///
+    pub fn extract_all_with(&self, options: &ExtractOptions) -> Result<ExtractReport, Error> {
+        self.extract_scope(&[String::new()], options)
+    }
+
+    ///
+    /// Extract the directory at the given path within the archive, along with
+    /// everything beneath it, into the current directory. Returns a summary
+    /// of what was written.
+    ///
+    pub fn extract_subtree(&self, path: &str) -> Result<ExtractReport, Error> {
+        self.extract_subtree_with(path, &ExtractOptions::default())
+    }
+
+    ///
+    /// Extract the directory at the given path within the archive, along with
+    /// everything beneath it, according to the given options. The entries are
+    /// written at their full path within the archive, such that the structure
+    /// is the same as if the entire archive had been extracted.
+    ///
+    /// Returns `Error::EntryNotFound` if there is no such directory.
+    ///
+    pub fn extract_subtree_with(
+        &self,
+        path: &str,
+        options: &ExtractOptions,
+    ) -> Result<ExtractReport, Error> {
+        match self.lookup_path(path)? {
+            Some((item_id, KIND_DIRECTORY)) => {
+                let scope = self.item_path(item_id)?;
+                self.extract_scope(&[scope], options)
+            }
+            _ => Err(Error::EntryNotFound(path.to_owned())),
+        }
+    }
+
+    ///
+    /// Extract the entries selected by the given paths and patterns into the
+    /// current directory, returning a summary of what was written.
+    ///
+    pub fn extract_matching(&self, selections: &[&str]) -> Result<ExtractReport, Error> {
+        self.extract_matching_with(selections, &ExtractOptions::default())
+    }
+
+    ///
+    /// Extract the entries selected by the given paths and patterns according
+    /// to the given options, returning a summary of what was written. Each
+    /// selection is either the path of an entry or a pattern with the usual
+    /// shell wildcards (`?`, `*`, `**`, and `[...]`) that is matched against
+    /// the paths of all entries, where only `**` matches across slashes.
+    /// Selecting a directory selects everything beneath it. All of the
+    /// selected entries are extracted in a single pass over the content, as
+    /// with `extract_all_with()`.
+    ///
+    /// Returns `Error::EntryNotFound` if a selection matches nothing, and
+    /// `Error::InvalidPattern` if a pattern is malformed.
+    ///
+    pub fn extract_matching_with(
+        &self,
+        selections: &[&str],
+        options: &ExtractOptions,
+    ) -> Result<ExtractReport, Error> {
+        let scopes = self.expand_selections(selections)?;
+        self.extract_scope(&scopes, options)
+    }
+
+    // Convert the selections into the scopes for extraction: the paths of
+    // directories (with a trailing slash) and of other entries.
+    fn expand_selections(&self, selections: &[&str]) -> Result<Vec<String>, Error> {
+        let mut scopes: Vec<String> = vec![];
+        let mut patterns: Vec<(&str, glob::Pattern)> = vec![];
+        for selection in selections {
+            if selection.contains(['*', '?', '[']) {
+                let pattern = glob::Pattern::new(selection.trim_end_matches('/'))
+                    .map_err(|_| Error::InvalidPattern(selection.to_string()))?;
+                patterns.push((selection, pattern));
+            } else {
+                match self.lookup_path(selection)? {
+                    Some((item_id, _)) => scopes.push(self.item_path(item_id)?),
+                    None => return Err(Error::EntryNotFound(selection.to_string())),
+                }
+            }
+        }
+        if patterns.is_empty() {
+            return Ok(scopes);
+        }
+        let options = glob::MatchOptions {
+            case_sensitive: !self.ignore_case,
+            require_literal_separator: true,
+            require_literal_leading_dot: false,
+        };
+        let mut matched = vec![false; patterns.len()];
+        let mut stmt = self.conn.prepare(
+            "WITH RECURSIVE FIT AS (
+    SELECT *, Name || IIF(Kind = 1, '/', '') AS Path FROM Item WHERE Parent = 0
+    UNION ALL
+    SELECT Item.*, FIT.Path || Item.Name || IIF(Item.Kind = 1, '/', '') AS Path
+        FROM Item INNER JOIN FIT ON FIT.Kind = 1 AND Item.Parent = FIT.ID
+)
+SELECT Path FROM FIT;",
+        )?;
+        let mut rows = stmt.query([])?;
+        while let Some(row) = rows.next()? {
+            let path: String = row.get(0)?;
+            let bare = path.trim_end_matches('/');
+            let mut selected = false;
+            for (index, (_, pattern)) in patterns.iter().enumerate() {
+                if pattern.matches_with(bare, options) {
+                    matched[index] = true;
+                    selected = true;
+                }
+            }
+            if selected {
+                scopes.push(path);
+            }
+        }
+        if let Some(index) = matched.iter().position(|m| !m) {
+            return Err(Error::EntryNotFound(patterns[index].0.to_owned()));
+        }
+        Ok(scopes)
+    }
+
+    // Extract the entries within the given scopes, each of which is either
+    // empty (everything), the path of a directory (ending with a slash) and
+    // everything beneath it, or the path of any other entry.
+    fn extract_scope(
+        &self,
+        scopes: &[String],
+        options: &ExtractOptions,
+    ) -> Result<ExtractReport, Error> {
+        let _span = tracing::info_span!("extract", scopes = scopes.len()).entered();
+        let started = Instant::now();
+        let prior_warnings = self.warnings.borrow().len();
+        let mut report = ExtractReport::default();
+        if self.is_metadata_only()? {
+            return Err(Error::MetadataOnly);
+        }
+        // read a single snapshot of the archive, even as a builder in another
+        // connection publishes changes to it
+        let snapshot = self.conn.unchecked_transaction()?;
+        self.create_scope_table(scopes)?;
+        self.check_limits()?;
+        self.content_key()?;
+        if let Some(path) = self.conn.path().filter(|p| !p.is_empty()) {
+            self.profile.advise_read_ahead(Path::new(path));
+        }
+        // ensure all of the directories are created, including empty ones
+        // unless those are to be skipped
+        report.directories = self.ensure_all_directories(scopes, options)?;
+        // create a temporary table for holding the items and their full paths;
+        // start by dropping the table in case it was left behind from a
+        // previous operation
+        self.drop_temp_paths_table()?;
+        self.create_temp_paths_table(scopes)?;
+        self.rename_case_collisions(options)?;
+        if options.resume {
+            report.skipped += self.find_extracted_files(&options.destination)?;
+        }
+        report.overwritten = self.count_existing_files(&options.destination)?;
+
+        // join the item paths with the itemcontent rows and sort by the content
+        // blob order, making it easier to efficiently process the content blobs
+        let mut verifier = match options.verify {
+            Some(_) if has_column(&self.conn, "attrs", "checksum")? => {
+                Some(ExtractVerifier::new(self.digest_algorithm()?))
+            }
+            _ => None,
+        };
+        let checksum = if verifier.is_some() {
+            "(SELECT SUM(size) FROM itemcontent AS IC WHERE IC.item = II),
+                (SELECT checksum FROM attrs WHERE attrs.item = II)"
+        } else {
+            "0, NULL"
+        };
+        let parts = if self.on_file_extracted.is_some() {
+            "(SELECT COUNT(*) FROM itemcontent AS IC WHERE IC.item = II)"
+        } else {
+            "0"
+        };
+        let query = format!(
+            "SELECT content, contentpos, itempos, Size, kind, Path, II, {}, {} FROM IndexedFiles
+            LEFT JOIN itemcontent ON IndexedFiles.II = ItemContent.Item
+            WHERE II NOT IN (SELECT II FROM ExtractedFiles)
+            ORDER BY content, contentpos",
+            checksum, parts
+        );
+        let mut stmt = self.conn.prepare(&query)?;
+        let item_iter = stmt.query_map([], |row| {
+            Ok(IndexedFile {
+                content: row.get(0)?,
+                contentpos: row.get(1)?,
+                itempos: row.get(2)?,
+                size: row.get(3)?,
+                kind: row.get(4)?,
+                path: row.get(5)?,
+                item: row.get(6)?,
+                total: row.get(7)?,
+                checksum: row.get(8)?,
+                parts: row.get(9)?,
+            })
+        })?;
+
+        // process the item blobs from the resulting itemcontent query
+        let mut content_id: i64 = -1;
+        let mut files: Vec<IndexedFile> = vec![];
+        let mut parts_left: HashMap<i64, u64> = HashMap::new();
+        for row_result in item_iter {
+            let indexed_file = row_result?;
+            if indexed_file.content != content_id {
+                // reached the end of the entries for this content
+                if !files.is_empty() {
+                    self.process_content(
+                        files,
+                        options,
+                        &mut verifier,
+                        &mut parts_left,
+                        &mut report,
+                    )?;
+                }
+                content_id = indexed_file.content;
+                files = vec![indexed_file];
+            } else {
+                // another piece of the same content, add to the list
+                files.push(indexed_file);
+            }
+        }
+        // make sure any remaining content is processed
+        if !files.is_empty() {
+            self.process_content(files, options, &mut verifier, &mut parts_left, &mut report)?;
+        }
+        drop(stmt);
+        if let Some(verifier) = verifier {
+            self.finish_verifying(verifier, options, &mut report)?;
+        }
+        report.files = self.conn.query_row(
+            "SELECT COUNT(*) FROM IndexedFiles
+                WHERE kind = ?1 AND II NOT IN (SELECT II FROM ExtractedFiles)",
+            [KIND_FILE],
+            |row| row.get(0),
+        )?;
+        report.symlinks += self.create_symlinks(options)?;
+
+        #[cfg(target_family = "unix")]
+        if options.special_files {
+            report.special_files = self.create_special_files(options)?;
+        }
+        if !options.special_files || cfg!(not(target_family = "unix")) {
+            report.skipped += self.warn_special_files()?;
+        }
+
+        if options.mac_metadata {
+            self.restore_mac_metadata(&options.destination)?;
+        }
+
+        // ownership comes first, as changing it may clear the set-user-ID
+        // and set-group-ID bits
+        #[cfg(target_family = "unix")]
+        if options.owners {
+            self.restore_owners(options)?;
+        }
+
+        // restore the permissions once the content has been written, in case
+        // a file is not writable by the owner
+        #[cfg(target_family = "unix")]
+        self.restore_modes(options)?;
+
+        // writing into a directory changes its modification time, and a
+        // directory may not be writable by the owner, so directories are
+        // restored last, deepest first
+        #[cfg(target_family = "unix")]
+        self.restore_directories(options)?;
+
+        // clean up
+        self.drop_temp_paths_table()?;
+        self.drop_scope_table()?;
+        self.renamed.borrow_mut().clear();
+        snapshot.commit()?;
+        report.warnings = self.warnings.borrow()[prior_warnings..].to_vec();
+        report.elapsed = started.elapsed();
+        tracing::info!(files = report.files, "extracted entries");
+        Ok(report)
+    }
+
+    // Choose new names for the entries within the scope whose paths differ
+    // only in case from those of other entries, if the options call for it,
+    // and apply them to the IndexedFiles table. Each renamed entry is
+    // reported as a warning.
+    fn rename_case_collisions(&self, options: &ExtractOptions) -> Result<(), Error> {
+        self.renamed.borrow_mut().clear();
+        let enabled = match options.case_collisions {
+            CaseCollisions::Overwrite => false,
+            CaseCollisions::Rename => true,
+            CaseCollisions::Auto => ignores_case(&options.destination),
+        };
+        if !enabled {
+            return Ok(());
+        }
+        let query = format!(
+            "WITH RECURSIVE FIT AS (
+    SELECT *, Name || IIF(Kind = 1, '/', '') AS Path FROM Item WHERE Parent = 0
+    UNION ALL
+    SELECT Item.*, FIT.Path || Item.Name || IIF(Item.Kind = 1, '/', '') AS Path
+        FROM Item INNER JOIN FIT ON FIT.Kind = 1 AND Item.Parent = FIT.ID
+)
+SELECT Path, kind FROM FIT WHERE {} ORDER BY Path;",
+            IN_SCOPE
+        );
+        let mut stmt = self.conn.prepare(&query)?;
+        let rows = stmt
+            .query_map([], |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, i8>(1)?))
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut groups: HashMap<String, Vec<(String, i8)>> = HashMap::new();
+        for (path, kind) in rows {
+            let key = path.trim_end_matches('/').to_lowercase();
+            groups.entry(key).or_default().push((path, kind));
+        }
+        let mut taken: HashSet<String> = groups.keys().cloned().collect();
+        let mut colliding: Vec<Vec<(String, i8)>> = groups
+            .into_values()
+            .filter(|group| group.len() > 1)
+            .collect();
+        colliding.sort();
+        let mut renamed = self.renamed.borrow_mut();
+        for group in colliding {
+            // a directory keeps its name, otherwise the first entry does
+            let keeper = group
+                .iter()
+                .position(|(_, kind)| *kind == KIND_DIRECTORY)
+                .unwrap_or(0);
+            for (index, (path, kind)) in group.into_iter().enumerate() {
+                if index == keeper || kind == KIND_DIRECTORY {
+                    continue;
+                }
+                let new_path = (2..)
+                    .map(|number| numbered_path(&path, number))
+                    .find(|candidate| !taken.contains(&candidate.to_lowercase()))
+                    .expect("some number is free");
+                taken.insert(new_path.to_lowercase());
+                self.conn.execute(
+                    "UPDATE IndexedFiles SET path = ?2 WHERE path = ?1",
+                    [&path, &new_path],
+                )?;
+                self.warn(Warning::CaseCollision(
+                    PathBuf::from(&path),
+                    PathBuf::from(&new_path),
+                ));
+                renamed.insert(path, new_path);
+            }
+        }
+        Ok(())
+    }
+
+    // Return the location to which the entry at the given path within the
+    // archive is extracted, after any renaming to avoid case collisions.
+    fn output_path(&self, root: &Path, path: &str) -> Result<PathBuf, Error> {
+        match self.renamed.borrow().get(path) {
+            Some(renamed) => crate::resolve_beneath(root, renamed),
+            None => crate::resolve_beneath(root, path),
+        }
+    }
+
+    // Ensure that extracting the entries within the scope, as held by the
+    // ExtractScope table, would not exceed any of the extraction limits.
+    pub(crate) fn check_limits(&self) -> Result<(), Error> {
+        let limits = &self.limits;
+        if limits.max_total_size.is_none()
+            && limits.max_files.is_none()
+            && limits.max_file_size.is_none()
+            && limits.max_depth.is_none()
+        {
+            return Ok(());
+        }
+        // the depth of a directory is the number of slashes in its path, while
+        // that of any other entry is one more than that
+        let query = format!(
+            "WITH RECURSIVE FIT AS (
+    SELECT *, Name || IIF(Kind = 1, '/', '') AS Path FROM Item WHERE Parent = 0
+    UNION ALL
+    SELECT Item.*, FIT.Path || Item.Name || IIF(Item.Kind = 1, '/', '') AS Path
+        FROM Item INNER JOIN FIT ON FIT.Kind = 1 AND Item.Parent = FIT.ID
+), Scoped AS (
+    SELECT kind, LENGTH(Path) - LENGTH(REPLACE(Path, '/', '')) + IIF(Kind = 1, 0, 1) AS Depth,
+        (SELECT IFNULL(SUM(size), 0) FROM itemcontent WHERE itemcontent.item = FIT.id) AS Size
+    FROM FIT WHERE {}
+)
+SELECT IFNULL(SUM(kind = ?1), 0), IFNULL(SUM(IIF(kind = ?1, Size, 0)), 0),
+    IFNULL(MAX(IIF(kind = ?1, Size, 0)), 0), IFNULL(MAX(Depth), 0)
+FROM Scoped;",
+            IN_SCOPE
+        );
+        let (files, total_size, file_size, depth): (u64, u64, u64, u64) =
+            self.conn.query_row(&query, [KIND_FILE], |row| {
+                Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))
+            })?;
+        let checks = [
+            ("total size", total_size, limits.max_total_size),
+            ("file count", files, limits.max_files),
+            ("largest file size", file_size, limits.max_file_size),
+            ("path depth", depth, limits.max_depth),
+        ];
+        for (name, actual, maximum) in checks {
+            if let Some(maximum) = maximum {
+                if actual > maximum {
+                    return Err(Error::LimitExceeded(format!(
+                        "{} of {} exceeds the maximum of {}",
+                        name, actual, maximum
+                    )));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Record the files that were completely written by an earlier extraction,
+    // as determined by their size and checksum, such that they can be skipped.
+    // Any other files and symbolic links already at the destination are
+    // truncated or removed so they can be written anew.
+    fn find_extracted_files(&self, root: &Path) -> Result<u64, Error> {
+        let checksum = if has_column(&self.conn, "attrs", "checksum")? {
+            "(SELECT checksum FROM attrs WHERE attrs.item = II)"
+        } else {
+            "NULL"
+        };
+        let query = format!(
+            "SELECT II, kind, path, (SELECT IFNULL(SUM(size), 0) FROM itemcontent
+                WHERE itemcontent.item = II), {} FROM IndexedFiles",
+            checksum
+        );
+        let mut stmt = self.conn.prepare(&query)?;
+        let mut rows = stmt.query([])?;
+        let algorithm = self.digest_algorithm()?;
+        let mut skipped: u64 = 0;
+        while let Some(row) = rows.next()? {
+            let item_id: i64 = row.get(0)?;
+            let kind: i8 = row.get(1)?;
+            let path: String = row.get(2)?;
+            let size: u64 = row.get(3)?;
+            let expected: Option<Vec<u8>> = row.get(4)?;
+            let fpath = crate::resolve_beneath(root, &path)?;
+            let Ok(metadata) = fs::symlink_metadata(&fpath) else {
+                continue;
+            };
+            if kind == KIND_SYMLINK {
+                if metadata.is_symlink() {
+                    fs::remove_file(&fpath)?;
+                }
+            } else if metadata.is_file() {
+                let complete = metadata.len() == size
+                    && match expected {
+                        Some(expected) => {
+                            crate::builder::checksum_file(&fpath, algorithm)? == expected
+                        }
+                        None => true,
+                    };
+                if complete {
+                    self.conn
+                        .execute("INSERT INTO ExtractedFiles (II) VALUES (?1)", [item_id])?;
+                    skipped += 1;
+                } else {
+                    crate::open_beneath(root, &path)?.set_len(0)?;
+                }
+            }
+        }
+        tracing::info!(files = skipped, "skipping previously extracted files");
+        Ok(skipped)
+    }
+
+    // Count the files about to be extracted that already exist.
+    fn count_existing_files(&self, root: &Path) -> Result<u64, Error> {
+        let mut stmt = self.conn.prepare(
+            "SELECT path FROM IndexedFiles
+                WHERE kind = ?1 AND II NOT IN (SELECT II FROM ExtractedFiles)",
+        )?;
+        let mut rows = stmt.query([KIND_FILE])?;
+        let mut existing: u64 = 0;
+        while let Some(row) = rows.next()? {
+            let path: String = row.get(0)?;
+            if fs::symlink_metadata(crate::resolve_beneath(root, path)?).is_ok() {
+                existing += 1;
+            }
+        }
+        Ok(existing)
+    }
+
+    // Set the permissions of the extracted files to those recorded in the
+    // archive, if any, less those masked by the umask if so desired.
+    #[cfg(target_family = "unix")]
+    fn restore_modes(&self, options: &ExtractOptions) -> Result<(), Error> {
+        use std::os::unix::fs::PermissionsExt;
+        if !has_column(&self.conn, "attrs", "mode")? {
+            return Ok(());
+        }
+        let mask = if options.same_permissions {
+            0
+        } else {
+            current_umask()
+        };
+        let mut stmt = self.conn.prepare(
+            "SELECT path, mode FROM IndexedFiles INNER JOIN attrs ON attrs.item = II
+            WHERE kind = ?1 AND mode IS NOT NULL",
+        )?;
+        let mut rows = stmt.query([KIND_FILE])?;
+        while let Some(row) = rows.next()? {
+            let path: String = row.get(0)?;
+            let mode: u32 = row.get(1)?;
+            let fpath = crate::resolve_beneath(&options.destination, path)?;
+            if fs::symlink_metadata(&fpath).is_err() {
+                // removed for failing verification
+                continue;
+            }
+            fs::set_permissions(fpath, fs::Permissions::from_mode(mode & !mask))?;
+        }
+        Ok(())
+    }
+
+    // Set the modification times and permissions of the extracted directories
+    // to those recorded in the archive, once everything within them has been
+    // written, starting with the deepest such that a directory that cannot be
+    // entered does not prevent restoring those beneath it.
+    #[cfg(target_family = "unix")]
+    fn restore_directories(&self, options: &ExtractOptions) -> Result<(), Error> {
+        use std::os::unix::fs::PermissionsExt;
+        if !has_column(&self.conn, "attrs", "mtime")? {
+            return Ok(());
+        }
+        let mode = if has_column(&self.conn, "attrs", "mode")? {
+            "mode"
+        } else {
+            "NULL"
+        };
+        let mask = if options.same_permissions {
+            0
+        } else {
+            current_umask()
+        };
+        let query = format!(
+            "WITH RECURSIVE FIT AS (
+    SELECT *, Name || IIF(Kind = 1, '/', '') AS Path FROM Item WHERE Parent = 0
+    UNION ALL
+    SELECT Item.*, FIT.Path || Item.Name || IIF(Item.Kind = 1, '/', '') AS Path
+        FROM Item INNER JOIN FIT ON FIT.Kind = 1 AND Item.Parent = FIT.ID
+)
+SELECT Path, mtime, {} FROM FIT INNER JOIN attrs ON attrs.item = FIT.id
+WHERE Kind = 1 AND {}
+ORDER BY LENGTH(Path) - LENGTH(REPLACE(Path, '/', '')) DESC;",
+            mode, IN_SCOPE
+        );
+        let mut stmt = self.conn.prepare(&query)?;
+        let mut rows = stmt.query([])?;
+        while let Some(row) = rows.next()? {
+            let path: String = row.get(0)?;
+            let mtime: Option<i64> = row.get(1)?;
+            let mode: Option<u32> = row.get(2)?;
+            let fpath = self.output_path(&options.destination, &path)?;
+            if !fs::symlink_metadata(&fpath).is_ok_and(|m| m.is_dir()) {
+                // not created, such as an empty directory
+                continue;
+            }
+            // the time is set first, as the mode may deny reading the directory
+            if let Some(mtime) = mtime {
+                set_dir_mtime(&fpath, mtime)?;
+            }
+            if let Some(mode) = mode {
+                fs::set_permissions(&fpath, fs::Permissions::from_mode(mode & !mask))?;
+            }
+        }
+        Ok(())
+    }
+
+    // Give the extracted entries the owner and group recorded in the archive,
+    // as replaced by the mappings in the options, if any. Entries for which
+    // that is not permitted are reported as warnings.
+    #[cfg(target_family = "unix")]
+    fn restore_owners(&self, options: &ExtractOptions) -> Result<(), Error> {
+        if !has_column(&self.conn, "attrs", "uid")? {
+            return Ok(());
+        }
+        let query = format!(
+            "WITH RECURSIVE FIT AS (
+    SELECT *, Name || IIF(Kind = 1, '/', '') AS Path FROM Item WHERE Parent = 0
+    UNION ALL
+    SELECT Item.*, FIT.Path || Item.Name || IIF(Item.Kind = 1, '/', '') AS Path
+        FROM Item INNER JOIN FIT ON FIT.Kind = 1 AND Item.Parent = FIT.ID
+)
+SELECT Path, uid, gid, uname, gname FROM FIT INNER JOIN attrs ON attrs.item = FIT.id
+WHERE {}
+    AND (uid IS NOT NULL OR gid IS NOT NULL OR uname IS NOT NULL OR gname IS NOT NULL);",
+            IN_SCOPE
+        );
+        let mut stmt = self.conn.prepare(&query)?;
+        let mut rows = stmt.query([])?;
+        while let Some(row) = rows.next()? {
+            let path: String = row.get(0)?;
+            let (uid, gid): (Option<u32>, Option<u32>) = (row.get(1)?, row.get(2)?);
+            let (uname, gname): (Option<String>, Option<String>) = if options.numeric_owner {
+                (None, None)
+            } else {
+                (row.get(3)?, row.get(4)?)
+            };
+            let uid = resolve_owner(
+                options.owner_map.as_ref(),
+                uid,
+                uname.as_deref(),
+                crate::owner::user_id,
+            );
+            let gid = resolve_owner(
+                options.group_map.as_ref(),
+                gid,
+                gname.as_deref(),
+                crate::owner::group_id,
+            );
+            if uid.is_none() && gid.is_none() {
+                continue;
+            }
+            let fpath = self.output_path(&options.destination, &path)?;
+            if fs::symlink_metadata(&fpath).is_err() {
+                // not extracted, such as a special file
+                continue;
+            }
+            if let Err(err) = std::os::unix::fs::lchown(&fpath, uid, gid) {
+                tracing::debug!(path = %fpath.display(), "cannot change owner: {}", err);
+                self.warn(Warning::OwnershipNotRestored(fpath));
+            }
+        }
+        Ok(())
+    }
+
+    // Restore the resource fork and Finder metadata of the extracted files and
+    // directories, if any were recorded.
+    fn restore_mac_metadata(&self, root: &Path) -> Result<(), Error> {
+        if !has_table(&self.conn, "xattr")? {
+            return Ok(());
+        }
+        let query = format!(
+            "WITH RECURSIVE FIT AS (
+    SELECT *, Name || IIF(Kind = 1, '/', '') AS Path FROM Item WHERE Parent = 0
+    UNION ALL
+    SELECT Item.*, FIT.Path || Item.Name || IIF(Item.Kind = 1, '/', '') AS Path
+        FROM Item INNER JOIN FIT ON FIT.Kind = 1 AND Item.Parent = FIT.ID
+)
+SELECT Path, xattr.name, xattr.value FROM FIT INNER JOIN xattr ON xattr.item = FIT.id
+WHERE kind IN (?1, ?2) AND {};",
+            IN_SCOPE
+        );
+        let mut stmt = self.conn.prepare(&query)?;
+        let mut rows = stmt.query((KIND_FILE, KIND_DIRECTORY))?;
+        while let Some(row) = rows.next()? {
+            let path: String = row.get(0)?;
+            let name: String = row.get(1)?;
+            let value: Vec<u8> = row.get(2)?;
+            let fpath = self.output_path(root, &path)?;
+            if fs::symlink_metadata(&fpath).is_ok() {
+                crate::macos::write_mac_metadata(&fpath, &name, &value)?;
+            }
+        }
+        Ok(())
+    }
+
+    // Create the symbolic links whose values are recorded in the item table,
+    // after all of the files have been written.
+    fn create_symlinks(&self, options: &ExtractOptions) -> Result<u64, Error> {
+        if !self.link_targets {
+            return Ok(0);
+        }
+        let query = format!(
+            "WITH RECURSIVE FIT AS (
+    SELECT *, Name || IIF(Kind = 1, '/', '') AS Path FROM Item WHERE Parent = 0
+    UNION ALL
+    SELECT Item.*, FIT.Path || Item.Name || IIF(Item.Kind = 1, '/', '') AS Path
+        FROM Item INNER JOIN FIT ON FIT.Kind = 1 AND Item.Parent = FIT.ID
+)
+SELECT Path, target FROM FIT
+WHERE kind = ?1 AND target IS NOT NULL AND {};",
+            IN_SCOPE
+        );
+        let mut stmt = self.conn.prepare(&query)?;
+        let mut rows = stmt.query([KIND_SYMLINK])?;
+        let mut count: u64 = 0;
+        while let Some(row) = rows.next()? {
+            let path: String = row.get(0)?;
+            let mut target: Vec<u8> = row.get(1)?;
+            let fpath = self.output_path(&options.destination, &path)?;
+            if let Some(rewrite) = options.rewrite_links.as_ref() {
+                target = rewrite.apply(&target, &path);
+            }
+            if options.resume && fs::symlink_metadata(&fpath).is_ok_and(|m| m.is_symlink()) {
+                fs::remove_file(&fpath)?;
+            }
+            write_link(&target, &fpath)?;
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    // Warn about each of the named pipes and device nodes within the scope
+    // that are not being created.
+    fn warn_special_files(&self) -> Result<u64, Error> {
+        use crate::{KIND_BLOCK_DEVICE, KIND_CHAR_DEVICE, KIND_FIFO};
+        let query = format!(
+            "WITH RECURSIVE FIT AS (
+    SELECT *, Name || IIF(Kind = 1, '/', '') AS Path FROM Item WHERE Parent = 0
+    UNION ALL
+    SELECT Item.*, FIT.Path || Item.Name || IIF(Item.Kind = 1, '/', '') AS Path
+        FROM Item INNER JOIN FIT ON FIT.Kind = 1 AND Item.Parent = FIT.ID
+)
+SELECT Path FROM FIT
+WHERE kind IN (?1, ?2, ?3) AND {};",
+            IN_SCOPE
+        );
+        let mut stmt = self.conn.prepare(&query)?;
+        let mut rows = stmt.query((KIND_FIFO, KIND_CHAR_DEVICE, KIND_BLOCK_DEVICE))?;
+        let mut count: u64 = 0;
+        while let Some(row) = rows.next()? {
+            let path: String = row.get(0)?;
+            self.warn(Warning::SkippedSpecialFile(PathBuf::from(path)));
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    // Create the named pipes and device nodes found in the archive.
+    #[cfg(target_family = "unix")]
+    fn create_special_files(&self, options: &ExtractOptions) -> Result<u64, Error> {
+        use crate::{KIND_BLOCK_DEVICE, KIND_CHAR_DEVICE, KIND_FIFO};
+        use std::os::unix::ffi::OsStrExt;
+        let has_devices = has_column(&self.conn, "attrs", "devmajor")?;
+        if !has_devices {
+            // archives without these columns will not have special files
+            return Ok(0);
+        }
+        let query = format!(
+            "WITH RECURSIVE FIT AS (
+    SELECT *, Name || IIF(Kind = 1, '/', '') AS Path FROM Item WHERE Parent = 0
+    UNION ALL
+    SELECT Item.*, FIT.Path || Item.Name || IIF(Item.Kind = 1, '/', '') AS Path
+        FROM Item INNER JOIN FIT ON FIT.Kind = 1 AND Item.Parent = FIT.ID
+)
+SELECT FIT.kind, FIT.Path, attrs.mode, attrs.devmajor, attrs.devminor FROM FIT
+    LEFT JOIN attrs ON attrs.item = FIT.id
+WHERE FIT.kind IN (?1, ?2, ?3) AND {};",
+            IN_SCOPE
+        );
+        let mut stmt = self.conn.prepare(&query)?;
+        let mut rows = stmt.query((KIND_FIFO, KIND_CHAR_DEVICE, KIND_BLOCK_DEVICE))?;
+        let mut count: u64 = 0;
+        while let Some(row) = rows.next()? {
+            let kind: i8 = row.get(0)?;
+            let path: String = row.get(1)?;
+            let mode: u32 = row.get::<_, Option<u32>>(2)?.unwrap_or(0o644);
+            let major: u32 = row.get::<_, Option<u32>>(3)?.unwrap_or(0);
+            let minor: u32 = row.get::<_, Option<u32>>(4)?.unwrap_or(0);
+            let fpath = self.output_path(&options.destination, &path)?;
+            if options.resume && fs::symlink_metadata(&fpath).is_ok() {
+                continue;
+            }
+            let cpath = std::ffi::CString::new(fpath.as_os_str().as_bytes())
+                .map_err(|_| Error::PathEscapesRoot(fpath.clone()))?;
+            let result = if kind == KIND_FIFO {
+                unsafe { libc::mkfifo(cpath.as_ptr(), mode as libc::mode_t) }
+            } else {
+                let format = if kind == KIND_CHAR_DEVICE {
+                    libc::S_IFCHR
+                } else {
+                    libc::S_IFBLK
+                };
+                let device = libc::makedev(major as _, minor as _);
+                unsafe { libc::mknod(cpath.as_ptr(), format | mode as libc::mode_t, device) }
+            };
+            if result != 0 {
+                return Err(io::Error::last_os_error().into());
+            }
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    // Ensure that all directories in the archive are created, optionally
+    // including those that do not contain any files, along with the parents
+    // of any entries selected on their own.
+    fn ensure_all_directories(
+        &self,
+        scopes: &[String],
+        options: &ExtractOptions,
+    ) -> Result<u64, Error> {
+        for scope in scopes.iter().filter(|s| !s.ends_with('/')) {
+            if let Some((parent, _)) = scope.rsplit_once('/') {
+                fs::create_dir_all(crate::resolve_beneath(&options.destination, parent)?)?;
+            }
+        }
+        // when skipping empty directories, only those directories that have a
+        // child other than a directory are created (along with their parents)
+        let condition = if options.empty_dirs {
+            "Kind = 1"
+        } else {
+            "Kind = 1 AND EXISTS (SELECT 1 FROM Item AS C WHERE C.Parent = FIT.ID AND C.Kind <> 1)"
+        };
+        let query = format!(
+            "WITH RECURSIVE FIT AS (
+    SELECT *, Name || IIF(Kind = 1, '/', '') AS Path FROM Item WHERE Parent = 0
+    UNION ALL
+    SELECT Item.*, FIT.Path || Item.Name || IIF(Item.Kind = 1, '/', '') AS Path
+        FROM Item INNER JOIN FIT ON FIT.Kind = 1 AND Item.Parent = FIT.ID
+)
+SELECT Path FROM FIT WHERE {} AND {};",
+            condition, IN_SCOPE
+        );
+        let mut stmt = self.conn.prepare(&query)?;
+        let mut rows = stmt.query([])?;
+        let mut count: u64 = 0;
+        while let Some(row) = rows.next()? {
+            let path: String = row.get(0)?;
+            let fpath = crate::resolve_beneath(&options.destination, path)?;
+            fs::create_dir_all(fpath)?;
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    // Process a single content blob and all of the files it contains, writing
+    // them beneath the given root directory.
+    fn process_content(
+        &self,
+        files: Vec<IndexedFile>,
+        options: &ExtractOptions,
+        verifier: &mut Option<ExtractVerifier>,
+        parts_left: &mut HashMap<i64, u64>,
+        report: &mut ExtractReport,
+    ) -> Result<(), Error> {
+        assert!(!files.is_empty(), "expected files to be non-empty");
+        let root = &options.destination;
+        let content_id = files[0].content;
+        tracing::debug!(content_id, entries = files.len(), "extracting bundle");
+
+        // decompress the blob as the files are written, such that the entire
+        // bundle is never held in memory, verifying it as it is read
+        let mut bundle = self.open_bundle(content_id)?;
+
+        // process each of the rows of content, which are portions of a file
+        for entry in files.iter() {
+            // the file path is sanitized and verified to prevent abuse (it is
+            // theoretically possible that the data could produce a path with a
+            // root, prefix, parent-dir elements, or one that passes through a
+            // previously extracted symbolic link)
+            if entry.kind == KIND_FILE {
+                // make sure the file exists and is writable
+                let mut output = crate::open_beneath(root, &entry.path)?;
+                let file_len = output.metadata()?.len();
+                // if the file was an empty file, then we are already done here
+                if entry.size > 0 {
+                    // ensure the file has the appropriate length for writing this
+                    // content chunk into the file, extending it as necessary
+                    if file_len < entry.itempos {
+                        output.set_len(entry.itempos)?;
+                    }
+                    // seek to the correct position within the file for this chunk
+                    if entry.itempos > 0 {
+                        output.seek(SeekFrom::Start(entry.itempos))?;
+                    }
+                    bundle.seek_to(entry.contentpos)?;
+                    match verifier.as_mut().and_then(|v| v.hasher_for(entry)) {
+                        Some(hasher) => {
+                            bundle.copy_to(entry.size, &mut TeeWriter(&mut output, hasher))?
+                        }
+                        None => bundle.copy_to(entry.size, &mut output)?,
+                    }
+                    report.bytes_written += entry.size;
+                }
+                drop(output);
+                if let Some(verifier) = verifier.as_mut() {
+                    if verifier.written(entry) == Some(false)
+                        && self.checksum_mismatch(&entry.path, options)?
+                    {
+                        verifier.removed.push(entry.item);
+                    }
+                }
+                if let Some(callback) = self.on_file_extracted.as_ref() {
+                    // the callback is invoked once the last part is written,
+                    // unless the file is yet to be verified or was removed
+                    let left = parts_left.entry(entry.item).or_insert(entry.parts);
+                    *left = left.saturating_sub(1);
+                    if *left == 0 && !verifier.as_ref().is_some_and(|v| v.holds(entry.item)) {
+                        callback(&crate::resolve_beneath(root, &entry.path)?)?;
+                    }
+                }
+            } else if entry.kind == KIND_SYMLINK {
+                let mut raw_bytes: Vec<u8> = vec![];
+                bundle.seek_to(entry.contentpos)?;
+                bundle.copy_to(entry.size, &mut raw_bytes)?;
+                let fpath = crate::resolve_beneath(root, &entry.path)?;
+                if let Some(rewrite) = options.rewrite_links.as_ref() {
+                    raw_bytes = rewrite.apply(&raw_bytes, &entry.path);
+                }
+                write_link(&raw_bytes, &fpath)?;
+                report.symlinks += 1;
+            }
+        }
+        bundle.finish()
+    }
+
+    // Verify the files whose content was not written in order, and hence
+    // could not be hashed as it was written, by reading them back, invoking
+    // the callback for those that pass. Then leave out of the report any
+    // files that were removed for failing verification.
+    fn finish_verifying(
+        &self,
+        verifier: ExtractVerifier,
+        options: &ExtractOptions,
+        report: &mut ExtractReport,
+    ) -> Result<(), Error> {
+        let mut removed = verifier.removed;
+        for (item_id, path, expected) in verifier.unordered {
+            let fpath = crate::resolve_beneath(&options.destination, &path)?;
+            let actual = crate::builder::checksum_file(&fpath, verifier.algorithm)?;
+            if actual != expected && self.checksum_mismatch(&path, options)? {
+                removed.push(item_id);
+            } else if let Some(callback) = self.on_file_extracted.as_ref() {
+                callback(&fpath)?;
+            }
+        }
+        for item_id in removed {
+            self.conn
+                .execute("INSERT INTO ExtractedFiles (II) VALUES (?1)", [item_id])?;
+            report.skipped += 1;
+        }
+        Ok(())
+    }
+
+    // Handle an extracted file whose checksum does not match the one recorded
+    // in the archive, according to the policy in the options. Returns true if
+    // the file was removed.
+    fn checksum_mismatch(&self, path: &str, options: &ExtractOptions) -> Result<bool, Error> {
+        let fpath = crate::resolve_beneath(&options.destination, path)?;
+        let removed = match options.verify {
+            Some(ErrorPolicy::Fail) => return Err(Error::ChecksumMismatch(fpath)),
+            Some(ErrorPolicy::Skip) => {
+                fs::remove_file(&fpath)?;
+                true
+            }
+            _ => false,
+        };
+        self.warn(Warning::ChecksumMismatch(fpath));
+        Ok(removed)
+    }
+
+    //
+    // Check the integrity of the database and the checksum of every content
+    // bundle, returning an error for the first problem found.
+    //
+    pub(crate) fn verify_integrity(&self) -> Result<(), Error> {
+        let status: String = self
+            .conn
+            .query_row("PRAGMA integrity_check", [], |row| row.get(0))?;
+        if status != "ok" {
+            return Err(Error::Database);
+        }
+        let mut stmt = self.conn.prepare("SELECT id FROM content")?;
+        let ids = stmt
+            .query_map([], |row| row.get::<_, i64>(0))?
+            .collect::<Result<Vec<_>, _>>()?;
+        for content_id in ids {
+            self.verify_bundle(content_id)?;
+        }
+        Ok(())
+    }
+
+    // Return true if the content bundle is intact, as determined by the
+    // checksum, or by decompressing the entire bundle if there is no checksum.
+    pub(crate) fn check_bundle(&self, content_id: i64) -> Result<bool, Error> {
+        let has_checksum: bool = self.bundle_checksums
+            && self.conn.query_row(
+                "SELECT checksum IS NOT NULL FROM content WHERE id = ?1",
+                [&content_id],
+                |row| row.get(0),
+            )?;
+        let result = if has_checksum {
+            self.verify_bundle(content_id)
+        } else {
+            self.open_bundle(content_id)
+                .and_then(|mut bundle| bundle.copy_to(u64::MAX, &mut io::sink()))
+        };
+        match result {
+            Ok(()) => Ok(true),
+            // not a sign of damage, but rather of an incomplete reader
+            Err(Error::UnknownCodec(id)) => Err(Error::UnknownCodec(id)),
+            Err(_) => Ok(false),
+        }
+    }
+
+    // Verify the checksum of the compressed content bundle (for those archives
+    // that have checksums), reading the blob a piece at a time.
+    fn verify_bundle(&self, content_id: i64) -> Result<(), Error> {
+        if !self.bundle_checksums {
+            return Ok(());
+        }
+        let expected: Option<Vec<u8>> = self.conn.query_row(
+            "SELECT checksum FROM content WHERE id = ?1",
+            [&content_id],
+            |row| row.get(0),
+        )?;
+        if let Some(expected) = expected {
+            let blob =
+                self.conn
+                    .blob_open(DatabaseName::Main, "content", "value", content_id, true)?;
+            let mut blob = BufReader::with_capacity(self.profile.read_buffer_size(), blob);
+            let mut hasher = Hasher::new(self.digest_algorithm()?);
+            io::copy(&mut blob, &mut hasher)?;
+            if hasher.finalize() != expected {
+                tracing::warn!(content_id, "content bundle checksum does not match");
+                return Err(Error::CorruptBundle(content_id));
+            }
+        }
+        Ok(())
+    }
+
+    // Create a table to hold the scopes of an extraction, as used by the
+    // IN_SCOPE condition, replacing any that was left behind.
+    pub(crate) fn create_scope_table(&self, scopes: &[String]) -> Result<(), Error> {
+        self.conn.execute("DROP TABLE IF EXISTS ExtractScope", ())?;
+        self.conn.execute(
+            "CREATE TEMPORARY TABLE ExtractScope (prefix TEXT PRIMARY KEY)",
+            (),
+        )?;
+        let mut stmt = self
+            .conn
+            .prepare("INSERT OR IGNORE INTO ExtractScope (prefix) VALUES (?1)")?;
+        for scope in scopes {
+            stmt.execute([scope])?;
+        }
+        Ok(())
+    }
+
+    // Create a table to hold the item identifiers and their full paths and
+    // populate it by walking the item table from the root of each scope, such
+    // that only the part of the tree within the scope is visited. Symbolic
+    // links are included only if their value is stored in a content bundle.
+    fn create_temp_paths_table(&self, scopes: &[String]) -> Result<(), Error> {
+        self.conn.execute(
+            "CREATE TEMPORARY TABLE IndexedFiles (II INTEGER PRIMARY KEY, kind INTEGER, path TEXT)",
+            (),
+        )?;
+        self.conn.execute(
+            "CREATE TEMPORARY TABLE ExtractedFiles (II INTEGER PRIMARY KEY)",
+            (),
+        )?;
+        // each root is the directory whose children start the walk, the path
+        // of that directory, and the one child to visit if the scope is not a
+        // directory
+        self.conn.execute(
+            "CREATE TEMPORARY TABLE ScopeRoots (parent INTEGER, item INTEGER, prefix TEXT)",
+            (),
+        )?;
+        let mut stmt = self
+            .conn
+            .prepare("INSERT INTO ScopeRoots (parent, item, prefix) VALUES (?1, ?2, ?3)")?;
+        for scope in scopes {
+            if scope.is_empty() {
+                stmt.execute((0, None::<i64>, ""))?;
+            } else if let Some((item_id, kind)) = self.lookup_path(scope)? {
+                if kind == KIND_DIRECTORY {
+                    stmt.execute((item_id, None::<i64>, scope))?;
+                } else {
+                    let parent: i64 = self.conn.query_row(
+                        "SELECT parent FROM item WHERE id = ?1",
+                        [item_id],
+                        |row| row.get(0),
+                    )?;
+                    let prefix = scope.rfind('/').map_or("", |index| &scope[..=index]);
+                    stmt.execute((parent, item_id, prefix))?;
+                }
+            }
+        }
+        let bundled_links = if self.link_targets {
+            "target IS NULL"
+        } else {
+            "1"
+        };
+        // scopes may overlap, as with a directory and a file within it
+        let query = format!(
+            "INSERT OR IGNORE INTO IndexedFiles SELECT II, kind, Path FROM (
+                WITH RECURSIVE FIT AS (
+                    SELECT Item.*, R.prefix || Item.Name || IIF(Item.Kind = 1, '/', '') AS Path
+                        FROM ScopeRoots AS R INNER JOIN Item ON Item.Parent = R.parent
+                        AND (R.item IS NULL OR Item.ID = R.item)
+                    UNION ALL
+                    SELECT Item.*, FIT.Path || Item.Name || IIF(Item.Kind = 1, '/', '') AS Path
+                        FROM Item INNER JOIN FIT ON FIT.Kind = 1 AND Item.Parent = FIT.ID
+                )
+                SELECT id AS II, kind, Path FROM FIT
+                    WHERE kind = ?1 OR (kind = ?2 AND {})
+            )",
+            bundled_links
+        );
+        self.conn.execute(&query, (KIND_FILE, KIND_SYMLINK))?;
+        Ok(())
+    }
+
+    // Drop the table that holds the scopes of an extraction.
+    pub(crate) fn drop_scope_table(&self) -> Result<(), Error> {
+        self.conn.execute("DROP TABLE IF EXISTS ExtractScope", ())?;
+        Ok(())
+    }
+
+    // Drop the table that holds the item identifiers and their full paths.
+    fn drop_temp_paths_table(&self) -> Result<(), Error> {
+        self.conn.execute("DROP TABLE IF EXISTS IndexedFiles", ())?;
+        self.conn
+            .execute("DROP TABLE IF EXISTS ExtractedFiles", ())?;
+        self.conn.execute("DROP TABLE IF EXISTS ScopeRoots", ())?;
+        Ok(())
+    }
+
+    ///
+    /// Return `true` if the archive was created with the content-addressed
+    /// layout, in which files with identical content share that content.
+    ///
+    pub fn is_content_addressed(&self) -> Result<bool, Error> {
+        self.has_layout(crate::LAYOUT_CONTENT_ADDRESSED)
+    }
+
+    ///
+    /// Return `true` if the archive records only the metadata of each file,
+    /// without the content, as created with `PackBuilder::set_metadata_only()`.
+    ///
+    pub fn is_metadata_only(&self) -> Result<bool, Error> {
+        self.has_layout(crate::LAYOUT_METADATA_ONLY)
+    }
+
+    ///
+    /// Return the names of the format features that are needed to read this
+    /// archive, such as `layout=content-addressed` or `digest=sha256`, which
+    /// are among those returned by `supported_features()`, as any archive
+    /// that requires another feature fails to open with
+    /// `Error::UnsupportedFormatFeature`.
+    ///
+    pub fn required_features(&self) -> Result<Vec<String>, Error> {
+        crate::required_features(&self.conn)
+    }
+
+    ///
+    /// Return the algorithm that computed the checksums of the files and
+    /// content bundles, or `Error::UnknownDigest` if it is not supported.
+    ///
+    pub fn digest_algorithm(&self) -> Result<DigestAlgorithm, Error> {
+        self.digest.parse()
+    }
+
+    // Return true if the archive has the given layout.
+    fn has_layout(&self, layout: &str) -> Result<bool, Error> {
+        Ok(self.archive_meta("layout")?.as_deref() == Some(layout))
+    }
+
+    // Return the value of the named entry in the archive_meta table, if any.
+    fn archive_meta(&self, name: &str) -> Result<Option<String>, Error> {
+        if !has_table(&self.conn, "archive_meta")? {
+            return Ok(None);
+        }
+        let mut stmt = self
+            .conn
+            .prepare("SELECT value FROM archive_meta WHERE name = ?1")?;
+        let mut rows = stmt.query([name])?;
+        match rows.next()? {
+            Some(row) => Ok(row.get(0)?),
+            None => Ok(None),
+        }
+    }
+
+    ///
+    /// Gather the facts about the archive as a whole, such as its format
+    /// version, the number of entries and content bundles, their sizes, and
+    /// when the archive was created.
+    ///
+    pub fn info(&self) -> Result<ArchiveInfo, Error> {
+        let application_id: u32 = self
+            .conn
+            .pragma_query_value(None, "application_id", |row| row.get(0))?;
+        let format_version: u32 = self
+            .conn
+            .pragma_query_value(None, "user_version", |row| row.get(0))?;
+        let (items, bundles) = self.counts()?;
+        let query = format!(
+            "SELECT IFNULL(SUM(LENGTH(value)), 0), IFNULL(SUM({}), 0) FROM content",
+            self.bundle_rawsize()?
+        );
+        let (compressed, uncompressed) = self
+            .conn
+            .query_row(&query, [], |row| Ok((row.get(0)?, row.get(1)?)))?;
+        let codecs = if self.bundle_codecs {
+            let mut stmt = self
+                .conn
+                .prepare("SELECT DISTINCT IFNULL(codec, ?1) AS name FROM content ORDER BY name")?;
+            let rows = stmt.query_map([ZstdCodec::IDENTIFIER], |row| row.get(0))?;
+            rows.collect::<Result<Vec<String>, _>>()?
+        } else if bundles > 0 {
+            vec![ZstdCodec::IDENTIFIER.to_owned()]
+        } else {
+            vec![]
+        };
+        Ok(ArchiveInfo {
+            format_version,
+            application_id,
+            items,
+            bundles,
+            compressed,
+            uncompressed,
+            codecs,
+            layout: self.archive_meta("layout")?,
+            digest: self.digest.clone(),
+            encryption: self.archive_meta("encryption")?,
+            created: self.archive_meta("created")?,
+            creator: self.archive_meta("creator")?,
+        })
+    }
+
+    ///
+    /// Find a file whose content has the given digest, as computed by the
+    /// `digest_algorithm()` of the archive, returning its item identifier
+    /// (the `id` of an `Entry`).
+    ///
+    /// For content-addressed archives this is a lookup by primary key, while
+    /// for other archives the recorded file checksums are searched.
+    ///
+    pub fn find_by_digest(&self, digest: &[u8]) -> Result<Option<i64>, Error> {
+        let query = if self.is_content_addressed()? {
+            "SELECT item FROM chunk WHERE digest = ?1"
+        } else if has_column(&self.conn, "attrs", "checksum")? {
+            "SELECT attrs.item FROM attrs INNER JOIN item ON item.id = attrs.item
+                WHERE attrs.checksum = ?1 AND item.kind = 0 ORDER BY attrs.item LIMIT 1"
+        } else {
+            return Ok(None);
+        };
+        let mut stmt = self.conn.prepare(query)?;
+        let mut rows = stmt.query([digest])?;
+        match rows.next()? {
+            Some(row) => Ok(Some(row.get(0)?)),
+            None => Ok(None),
+        }
+    }
+
+    ///
+    /// Return the number of items and the number of content bundles.
+    ///
+    pub fn counts(&self) -> Result<(u64, u64), Error> {
+        let items: u64 = self
+            .conn
+            .query_row("SELECT COUNT(*) FROM item", [], |row| row.get(0))?;
+        let bundles: u64 = self
+            .conn
+            .query_row("SELECT COUNT(*) FROM content", [], |row| row.get(0))?;
+        Ok((items, bundles))
+    }
+
+    ///
+    /// Return the number of entries in the archive, including directories,
+    /// the same as the number returned by `entries()`.
+    ///
+    pub fn len(&self) -> Result<u64, Error> {
+        let count: u64 = self
+            .conn
+            .query_row("SELECT COUNT(*) FROM item", [], |row| row.get(0))?;
+        Ok(count)
+    }
+
+    ///
+    /// Return `true` if the archive has no entries at all.
+    ///
+    pub fn is_empty(&self) -> Result<bool, Error> {
+        let exists: bool =
+            self.conn
+                .query_row("SELECT EXISTS (SELECT 1 FROM item)", [], |row| row.get(0))?;
+        Ok(!exists)
+    }
+
+    ///
+    /// Return `true` if the archive has an entry of any kind with the given
+    /// path. A trailing slash is not required for directories.
+    ///
+    pub fn contains(&self, path: &str) -> Result<bool, Error> {
+        Ok(self.lookup_path(path)?.is_some())
+    }
+
+    ///
+    /// Gather the compressed and uncompressed sizes of the content bundles,
+    /// along with the largest files in the archive.
+    ///
+    /// For archives created before the uncompressed size and number of entries
+    /// were recorded, those values are derived from the item content within
+    /// each bundle.
+    ///
+    pub fn space_report(&self) -> Result<SpaceReport, Error> {
+        let rawsize = self.bundle_rawsize()?;
+        // portions shared by several items are stored only once
+        let counted = "(SELECT COUNT(DISTINCT contentpos) FROM itemcontent
+            WHERE itemcontent.content = content.id)";
+        let entries = if has_column(&self.conn, "content", "entries")? {
+            format!("IFNULL(entries, {})", counted)
+        } else {
+            counted.to_owned()
+        };
+        let query = format!(
+            "SELECT id, length(value), {}, {} FROM content ORDER BY id",
+            rawsize, entries
+        );
+        let mut stmt = self.conn.prepare(&query)?;
+        let bundles = stmt
+            .query_map([], |row| {
+                Ok(BundleSpace {
+                    id: row.get(0)?,
+                    compressed: row.get(1)?,
+                    uncompressed: row.get(2)?,
+                    entries: row.get(3)?,
+                })
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        // content shared by more than one item is stored only once
+        let dedup_savings: u64 = self.conn.query_row(
+            "SELECT IFNULL(SUM(size), 0) - (SELECT IFNULL(SUM(size), 0) FROM
+                (SELECT DISTINCT content, contentpos, size FROM itemcontent))
+            FROM itemcontent",
+            [],
+            |row| row.get(0),
+        )?;
+
+        let query = "WITH RECURSIVE FIT AS (
+    SELECT *, Name || IIF(Kind = 1, '/', '') AS Path FROM Item WHERE Parent = 0
+    UNION ALL
+    SELECT Item.*, FIT.Path || Item.Name || IIF(Item.Kind = 1, '/', '') AS Path
+        FROM Item INNER JOIN FIT ON FIT.Kind = 1 AND Item.Parent = FIT.ID
+)
+SELECT Path, Total FROM FIT
+    INNER JOIN (SELECT item, SUM(size) AS Total FROM itemcontent GROUP BY item) AS IC
+    ON IC.item = FIT.id
+WHERE Kind = 0 ORDER BY Total DESC LIMIT 20;";
+        let mut stmt = self.conn.prepare(query)?;
+        let largest = stmt
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(SpaceReport {
+            bundles,
+            dedup_savings,
+            largest,
+        })
+    }
+
+    // Return the SQL expression for the uncompressed size of a row in the
+    // content table, derived from the item content for older archives.
+    fn bundle_rawsize(&self) -> Result<String, Error> {
+        let derived = "(SELECT IFNULL(MAX(contentpos + size), 0) FROM itemcontent
+            WHERE itemcontent.content = content.id)";
+        if has_column(&self.conn, "content", "rawsize")? {
+            Ok(format!("IFNULL(rawsize, {})", derived))
+        } else {
+            Ok(derived.to_owned())
+        }
+    }
+
+    ///
+    /// Return the number of files and their total size for each file name
+    /// extension, and for each type of content as detected when the files
+    /// were added, both in descending order of size. The extensions are
+    /// converted to lowercase. Files without an extension, or whose type was
+    /// not detected, are counted under `None`.
+    ///
+    pub fn type_report(&self) -> Result<TypeReport, Error> {
+        let mime = if has_table(&self.conn, "attrs")? && has_column(&self.conn, "attrs", "mime")? {
+            "(SELECT mime FROM attrs WHERE attrs.item = item.id)"
+        } else {
+            "NULL"
+        };
+        let query = format!(
+            "SELECT name, {}, (SELECT IFNULL(SUM(size), 0) FROM itemcontent
+                WHERE itemcontent.item = item.id) FROM item WHERE kind = ?1",
+            mime
+        );
+        let mut stmt = self.conn.prepare(&query)?;
+        let rows = stmt
+            .query_map([KIND_FILE], |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, Option<String>>(1)?,
+                    row.get::<_, u64>(2)?,
+                ))
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut extensions: HashMap<Option<String>, TypeStats> = HashMap::new();
+        let mut mimes: HashMap<Option<String>, TypeStats> = HashMap::new();
+        for (name, mime, size) in rows {
+            let extension = match name.rfind('.') {
+                Some(pos) if pos > 0 && pos + 1 < name.len() => {
+                    Some(name[pos + 1..].to_lowercase())
+                }
+                _ => None,
+            };
+            for (map, key) in [(&mut extensions, extension), (&mut mimes, mime)] {
+                let stats = map.entry(key.clone()).or_insert_with(|| TypeStats {
+                    name: key,
+                    files: 0,
+                    bytes: 0,
+                });
+                stats.files += 1;
+                stats.bytes += size;
+            }
+        }
+        let sorted = |map: HashMap<Option<String>, TypeStats>| -> Vec<TypeStats> {
+            let mut stats: Vec<TypeStats> = map.into_values().collect();
+            stats.sort_by(|a, b| b.bytes.cmp(&a.bytes).then_with(|| a.name.cmp(&b.name)));
+            stats
+        };
+        Ok(TypeReport {
+            extensions: sorted(extensions),
+            mime_types: sorted(mimes),
+        })
+    }
+
+    ///
+    /// Find the sets of files in the archive that have identical content, as
+    /// determined by the checksum recorded for each file, in descending order
+    /// of the bytes that could be reclaimed by keeping only one of each. The
+    /// paths within each set are sorted. Empty files, and files added before
+    /// checksums were recorded, are not considered.
+    ///
+    pub fn duplicates(&self) -> Result<Vec<DuplicateSet>, Error> {
+        if !has_table(&self.conn, "attrs")? {
+            return Ok(vec![]);
+        }
+        let query = "WITH RECURSIVE FIT AS (
+    SELECT *, Name || IIF(Kind = 1, '/', '') AS Path FROM Item WHERE Parent = 0
+    UNION ALL
+    SELECT Item.*, FIT.Path || Item.Name || IIF(Item.Kind = 1, '/', '') AS Path
+        FROM Item INNER JOIN FIT ON FIT.Kind = 1 AND Item.Parent = FIT.ID
+)
+SELECT attrs.checksum, FIT.Path, IC.Total FROM FIT
+    INNER JOIN attrs ON attrs.item = FIT.id
+    INNER JOIN (SELECT item, SUM(size) AS Total FROM itemcontent GROUP BY item) AS IC
+    ON IC.item = FIT.id
+WHERE FIT.Kind = ?1 AND IC.Total > 0 AND attrs.checksum IN
+    (SELECT checksum FROM attrs INNER JOIN item ON item.id = attrs.item
+        WHERE item.kind = ?1 AND checksum IS NOT NULL
+        GROUP BY checksum HAVING COUNT(*) > 1)
+ORDER BY attrs.checksum, FIT.Path;";
+        let mut stmt = self.conn.prepare(query)?;
+        let rows = stmt
+            .query_map([KIND_FILE], |row| {
+                Ok((
+                    row.get::<_, Vec<u8>>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, u64>(2)?,
+                ))
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut sets: Vec<DuplicateSet> = vec![];
+        for (checksum, path, size) in rows {
+            match sets.last_mut() {
+                Some(set) if set.checksum == checksum => set.paths.push(path),
+                _ => sets.push(DuplicateSet {
+                    checksum,
+                    size,
+                    paths: vec![path],
+                }),
+            }
+        }
+        // a checksum may be shared with an empty file or a directory
+        sets.retain(|set| set.paths.len() > 1);
+        sets.sort_by(|a, b| {
+            b.reclaimable()
+                .cmp(&a.reclaimable())
+                .then_with(|| a.paths.cmp(&b.paths))
+        });
+        Ok(sets)
+    }
+
+    ///
+    /// Read up to `len` bytes of the named file, starting at `offset`, only
+    /// decompressing those content bundles that hold the requested range. The
+    /// result will be shorter than `len` if the range extends beyond the end
+    /// of the file.
+    ///
+    /// The path is relative to the archive root, as returned from `entries()`.
+    ///
+    pub fn read_range(&self, path: &str, offset: u64, len: u64) -> Result<Vec<u8>, Error> {
+        let item_id = match self.lookup_path(path)? {
+            Some((item_id, KIND_FILE)) => item_id,
+            _ => return Err(Error::EntryNotFound(path.to_owned())),
+        };
+        let end = offset.saturating_add(len);
+        let mut stmt = self.conn.prepare(
+            "SELECT content, contentpos, itempos, size FROM itemcontent
+            WHERE item = ?1 AND itempos < ?3 AND itempos + size > ?2 ORDER BY itempos",
+        )?;
+        let rows = stmt
+            .query_map((&item_id, &offset, &end), |row| {
+                Ok((
+                    OutgoingContent {
+                        content: row.get(0)?,
+                        contentpos: row.get(1)?,
+                        size: row.get(3)?,
+                    },
+                    row.get::<_, u64>(2)?,
+                ))
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut output: Vec<u8> = vec![];
+        for (itemcontent, itempos) in rows {
+            // portion of this chunk that overlaps the requested range
+            let start = offset.max(itempos);
+            let stop = end.min(itempos + itemcontent.size);
+            let pos = itemcontent.contentpos + (start - itempos);
+            self.copy_from_bundle(itemcontent.content, pos, stop - start, &mut output)?;
+        }
+        Ok(output)
+    }
+
+    ///
+    /// Write the content of the file or symbolic link with the given item
+    /// identifier (the `id` of an `Entry`) to the writer, without resolving
+    /// its path.
+    ///
+    /// Returns `Error::EntryNotFound` if there is no such file or link.
+    ///
+    pub fn read_item<W: Write>(&self, item_id: i64, output: &mut W) -> Result<(), Error> {
+        let mut stmt = self.conn.prepare("SELECT kind FROM item WHERE id = ?1")?;
+        let mut rows = stmt.query([&item_id])?;
+        match rows.next()? {
+            Some(row) if row.get::<_, i8>(0)? != KIND_DIRECTORY => (),
+            _ => return Err(Error::EntryNotFound(format!("item {}", item_id))),
+        }
+        self.read_content(item_id, output)
+    }
+
+    ///
+    /// Write the content of the file or symbolic link at the given path to
+    /// the writer.
+    ///
+    /// Returns `Error::EntryNotFound` if there is no such file or link.
+    ///
+    pub fn read_path<W: Write>(&self, path: &str, output: &mut W) -> Result<(), Error> {
+        match self.lookup_path(path)? {
+            Some((item_id, kind)) if kind != KIND_DIRECTORY => self.read_content(item_id, output),
+            _ => Err(Error::EntryNotFound(path.to_owned())),
+        }
+    }
+
+    ///
+    /// Return the entries directly within the directory at the given path,
+    /// sorted by name, skipping the first `offset` entries and returning at
+    /// most `limit` of them, such that very large directories can be browsed
+    /// a page at a time. An empty path refers to the root of the archive. As
+    /// with `entries()`, the name of each entry is its full path, with a
+    /// trailing slash for directories.
+    ///
+    /// Returns `Error::EntryNotFound` if there is no directory at the path.
+    ///
+    pub fn list_dir(&self, path: &str, offset: u64, limit: u64) -> Result<Vec<Entry>, Error> {
+        let (parent, dir) = match self.lookup_path(path)? {
+            None if path.trim_matches('/').is_empty() => (0, String::new()),
+            Some((item_id, KIND_DIRECTORY)) => (item_id, self.item_path(item_id)?),
+            _ => return Err(Error::EntryNotFound(path.to_owned())),
+        };
+        let mut stmt = self.conn.prepare(
+            "SELECT id, kind, name FROM item WHERE parent = ?1
+                ORDER BY name, id LIMIT ?2 OFFSET ?3",
+        )?;
+        // SQLite limits are signed, where a negative value means no limit
+        let limit = i64::try_from(limit).unwrap_or(-1);
+        let offset = i64::try_from(offset).unwrap_or(i64::MAX);
+        let entries = stmt
+            .query_map((&parent, &limit, &offset), |row| {
+                let kind: ItemKind = row.get(1)?;
+                let name: String = row.get(2)?;
+                let suffix = if kind == ItemKind::Directory { "/" } else { "" };
+                Ok(Entry {
+                    id: row.get(0)?,
+                    parent,
+                    kind,
+                    name: format!("{}{}{}", dir, name, suffix),
+                })
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(entries)
+    }
+
+    ///
+    /// Return the paths of the entries that could complete the given partial
+    /// path, for the purpose of interactive completion. The final component
+    /// of `partial` is treated as a prefix of an entry name within the
+    /// directory named by the preceding components. Directory paths end with
+    /// a slash, and the results are sorted by name.
+    ///
+    pub fn complete_path(&self, partial: &str) -> Result<Vec<String>, Error> {
+        let (dir, prefix) = match partial.rfind('/') {
+            Some(pos) => partial.split_at(pos + 1),
+            None => ("", partial),
+        };
+        let parent = match self.lookup_path(dir)? {
+            None if dir.is_empty() => 0,
+            Some((item_id, KIND_DIRECTORY)) => item_id,
+            _ => return Ok(vec![]),
+        };
+        let mut stmt = self
+            .conn
+            .prepare("SELECT name, kind FROM item WHERE parent = ?1 ORDER BY name")?;
+        let rows = stmt
+            .query_map([&parent], |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, i8>(1)?))
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut paths: Vec<String> = vec![];
+        for (name, kind) in rows {
+            let matched = if self.ignore_case {
+                name.to_lowercase().starts_with(&prefix.to_lowercase())
+            } else {
+                name.starts_with(prefix)
+            };
+            if matched {
+                let suffix = if kind == KIND_DIRECTORY { "/" } else { "" };
+                paths.push(format!("{}{}{}", dir, name, suffix));
+            }
+        }
+        Ok(paths)
+    }
+
+    // Return the full path of the item, with a trailing slash for directories.
+    fn item_path(&self, item_id: i64) -> Result<String, Error> {
+        let mut path = String::new();
+        let mut next = item_id;
+        while next != 0 {
+            let (parent, kind, name): (i64, i8, String) = self.conn.query_row(
+                "SELECT parent, kind, name FROM item WHERE id = ?1",
+                [&next],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+            )?;
+            let suffix = if kind == KIND_DIRECTORY { "/" } else { "" };
+            path = format!("{}{}{}", name, suffix, path);
+            next = parent;
+        }
+        Ok(path)
+    }
+
+    // Find the item with the given path, returning its rowid and kind.
+    pub(crate) fn lookup_path(&self, path: &str) -> Result<Option<(i64, i8)>, Error> {
+        let query = if self.ignore_case {
+            "SELECT id, kind FROM item WHERE parent = ?1 AND name = ?2 COLLATE NOCASE
+                ORDER BY name = ?2 DESC, id"
+        } else {
+            "SELECT id, kind FROM item WHERE parent = ?1 AND name = ?2"
+        };
+        let mut stmt = self.conn.prepare(query)?;
+        let mut found: Option<(i64, i8)> = None;
+        for name in path.split('/').filter(|n| !n.is_empty()) {
+            let parent = match found {
+                None => 0,
+                Some((item_id, KIND_DIRECTORY)) => item_id,
+                Some(_) => return Ok(None),
+            };
+            let mut rows = stmt.query((&parent, name))?;
+            match rows.next()? {
+                Some(row) => found = Some((row.get(0)?, row.get(1)?)),
+                None => return Ok(None),
+            }
+        }
+        Ok(found)
+    }
+
+    // returns 0 if file not found
+    #[allow(dead_code)]
+    fn find_file_by_path(&self, relpath: &str) -> Result<i64, Error> {
+        let sql = format!(
+            "WITH RECURSIVE IT AS (
+    SELECT Item.*, ID AS FID FROM Item WHERE
+    ID IN (
+        WITH RECURSIVE FIT AS (
+            SELECT *, '/' || Name || IIF(Kind = 1, '/', '') AS Path FROM Item WHERE Parent = 0
+            UNION ALL
+            SELECT Item.*, FIT.Path || Item.Name || IIF(Item.Kind = 1, '/', '') AS Path
+                FROM Item INNER JOIN FIT ON FIT.Kind = 1 AND Item.Parent = FIT.ID
+                WHERE '/{}' LIKE (Path || '%')
+        )
+        SELECT ID FROM FIT WHERE Path = '/{}' {}
+    )
+    UNION ALL
+    SELECT Item.*, IT.FID FROM Item INNER JOIN IT ON IT.Kind = 1 AND Item.Parent = IT.ID
+),
+ITI AS (SELECT (ROW_NUMBER() OVER (ORDER BY FID, ID) - 1) AS I, * FROM IT)
+SELECT C.I, IFNULL(P.I, -1) AS PI, C.ID, C.Parent, C.Kind, C.Name FROM ITI AS C
+LEFT JOIN ITI AS P ON C.FID = P.FID AND C.Parent = P.ID ORDER BY C.I;",
+            relpath,
+            relpath,
+            if self.ignore_case {
+                "COLLATE NOCASE"
+            } else {
+                ""
+            }
+        );
+        let mut stmt = self.conn.prepare(&sql)?;
+        let mut item_iter = stmt.query_map([], |row| {
+            Ok(Entry {
+                id: row.get(2)?,
+                parent: row.get(3)?,
+                kind: row.get(4)?,
+                name: row.get(5)?,
+            })
+        })?;
+        if let Some(entry) = item_iter.next() {
+            return Ok(entry?.id);
+        }
+        Ok(0)
+    }
+
+    //
+    // Print the contents of the identified file to stdout.
+    //
+    #[allow(dead_code)]
+    fn print_file(&self, item_id: i64) -> Result<(), Error> {
+        self.read_content(item_id, &mut io::stdout())
+    }
+
+    //
+    // Return the value of the symbolic link with the given item identifier, if
+    // it is recorded in the item table; archives created by earlier versions
+    // store the value in the content bundles instead.
+    //
+    pub(crate) fn link_target(&self, item_id: i64) -> Result<Option<Vec<u8>>, Error> {
+        if !self.link_targets {
+            return Ok(None);
+        }
+        let mut stmt = self
+            .conn
+            .prepare_cached("SELECT target FROM item WHERE id = ?1")?;
+        let mut rows = stmt.query([&item_id])?;
+        match rows.next()? {
+            Some(row) => Ok(row.get(0)?),
+            None => Ok(None),
+        }
+    }
+
+    //
+    // Return the SQL expression for the size of an item selected from the FIT
+    // query, which for symbolic links is the length of the link value.
+    //
+    pub(crate) fn item_size_column(&self) -> &'static str {
+        if self.link_targets {
+            "IFNULL(LENGTH(FIT.target),
+                (SELECT IFNULL(SUM(size), 0) FROM itemcontent WHERE itemcontent.item = FIT.id))"
+        } else {
+            "(SELECT IFNULL(SUM(size), 0) FROM itemcontent WHERE itemcontent.item = FIT.id)"
+        }
+    }
+
+    //
+    // Write the contents of the identified file or symbolic link to the writer.
+    //
+    pub(crate) fn read_content<W: Write>(&self, item_id: i64, output: &mut W) -> Result<(), Error> {
+        if let Some(target) = self.link_target(item_id)? {
+            output.write_all(&target)?;
+            return Ok(());
+        }
+        let mut stmt = self.conn.prepare(
+            "SELECT content, contentpos, size FROM itemcontent WHERE item = ?1 ORDER BY itempos",
+        )?;
+        let content_iter = stmt.query_map([&item_id], |row| {
+            Ok(OutgoingContent {
+                content: row.get(0)?,
+                contentpos: row.get(1)?,
+                size: row.get(2)?,
+            })
+        })?;
+        for content_result in content_iter {
+            let itemcontent = content_result?;
+            self.copy_from_bundle(
+                itemcontent.content,
+                itemcontent.contentpos,
+                itemcontent.size,
+                output,
+            )?;
+        }
+        Ok(())
+    }
+
+    //
+    // Write a portion of the uncompressed content bundle to the writer, using
+    // the cache of decompressed bundles if it is enabled.
+    //
+    fn copy_from_bundle<W: Write>(
+        &self,
+        content_id: i64,
+        pos: u64,
+        size: u64,
+        output: &mut W,
+    ) -> Result<(), Error> {
+        if content_id == crate::NO_CONTENT {
+            return Err(Error::MetadataOnly);
+        }
+        if self.cache.borrow().capacity == 0 && self.disk_cache.is_none() {
+            return self.stream_from_bundle(content_id, pos, size, output);
+        }
+        let cached = self.cache.borrow_mut().get(content_id);
+        let data = match cached {
+            Some(data) => data,
+            None => {
+                // a bundle that no cache would keep is read only as far as
+                // needed, rather than decompressed entirely into memory
+                if !self.is_cacheable(content_id)? {
+                    return self.stream_from_bundle(content_id, pos, size, output);
+                }
+                let data = Arc::new(self.load_bundle(content_id)?);
+                self.cache.borrow_mut().insert(content_id, data.clone());
+                data
+            }
+        };
+        let len = data.len() as u64;
+        let start = pos.min(len) as usize;
+        let stop = pos.saturating_add(size).min(len) as usize;
+        output.write_all(&data[start..stop])?;
+        Ok(())
+    }
+
+    // Decompress the bundle only as far as the end of the requested range,
+    // writing that range to the output.
+    fn stream_from_bundle<W: Write>(
+        &self,
+        content_id: i64,
+        pos: u64,
+        size: u64,
+        output: &mut W,
+    ) -> Result<(), Error> {
+        let mut bundle = self.open_bundle(content_id)?;
+        bundle.seek_to(pos)?;
+        bundle.copy_to(size, output)?;
+        bundle.finish()
+    }
+
+    // Return true if the decompressed bundle is small enough to be kept by
+    // the cache in memory, or by the cache on disk if that may be used.
+    fn is_cacheable(&self, content_id: i64) -> Result<bool, Error> {
+        let query = format!(
+            "SELECT {} FROM content WHERE id = ?1",
+            self.bundle_rawsize()?
+        );
+        let rawsize: u64 = self
+            .conn
+            .query_row(&query, [content_id], |row| row.get(0))?;
+        if rawsize <= self.cache.borrow().capacity {
+            return Ok(true);
+        }
+        let on_disk = self
+            .disk_cache
+            .as_ref()
+            .filter(|_| !self.encrypted)
+            .is_some_and(|cache| rawsize + blake3::OUT_LEN as u64 <= cache.limit);
+        Ok(on_disk)
+    }
+
+    //
+    // Read and decompress the entire content bundle, using the cache of
+    // bundles on disk if it is enabled.
+    //
+    fn load_bundle(&self, content_id: i64) -> Result<Vec<u8>, Error> {
+        // the recorded checksum is trusted to identify the bundle only if the
+        // archive could not have been crafted to match that of another
+        let cached = match (&self.disk_cache, self.encrypted) {
+            (Some(cache), false) if self.digest_algorithm()?.is_collision_resistant() => self
+                .bundle_checksum(content_id)?
+                .map(|checksum| (cache, cache.file_path(&self.digest, &checksum))),
+            _ => None,
+        };
+        if let Some((cache, path)) = &cached {
+            if let Some(data) = cache.get(path) {
+                return Ok(data);
+            }
+        }
+        let mut bundle = self.open_bundle(content_id)?;
+        let mut data: Vec<u8> = vec![];
+        bundle.copy_to(u64::MAX, &mut data)?;
+        bundle.finish()?;
+        if let Some((cache, path)) = &cached {
+            cache.insert(path, &data);
+        }
+        Ok(data)
+    }
+
+    // Return the recorded checksum of the content bundle, if any.
+    fn bundle_checksum(&self, content_id: i64) -> Result<Option<Vec<u8>>, Error> {
+        if !self.bundle_checksums {
+            return Ok(None);
+        }
+        let checksum = self.conn.query_row(
+            "SELECT checksum FROM content WHERE id = ?1",
+            [&content_id],
+            |row| row.get(0),
+        )?;
+        Ok(checksum)
+    }
+}
+
+//
+// Decompresses a content bundle as it is read from the database, allowing for
+// moving forward to any position within the uncompressed content. Moving
+// backward is supported by restarting the decompression from the beginning.
+//
+struct BundleStream<'a> {
+    conn: &'a Connection,
+    // rowid of the content in the content table
+    content_id: i64,
+    // codec that was used to compress the content
+    codec: &'a dyn Codec,
+    // number of bytes of the blob to read at a time
+    buffer_size: usize,
+    // key for decrypting the blob, if it is encrypted
+    key: Option<&'a ContentKey>,
+    decoder: Box<dyn Read + 'a>,
+    // position within the uncompressed content
+    pos: u64,
+    // checksum of the blob computed as it is read, if one was recorded
+    check: Option<BundleCheck>,
+}
+
+impl<'a> BundleStream<'a> {
+    fn new(
+        conn: &'a Connection,
+        content_id: i64,
+        codec: &'a dyn Codec,
+        buffer_size: usize,
+        key: Option<&'a ContentKey>,
+        check: Option<BundleCheck>,
+    ) -> Result<Self, Error> {
+        let progress = check.as_ref().map(|c| c.progress.clone());
+        let decoder = Self::open_decoder(conn, content_id, codec, buffer_size, key, progress)?;
+        Ok(Self {
+            conn,
+            content_id,
+            codec,
+            buffer_size,
+            key,
+            decoder,
+            pos: 0,
+            check,
+        })
+    }
+
+    fn open_decoder(
+        conn: &'a Connection,
+        content_id: i64,
+        codec: &'a dyn Codec,
+        buffer_size: usize,
+        key: Option<&'a ContentKey>,
+        progress: Option<Rc<RefCell<CheckProgress>>>,
+    ) -> Result<Box<dyn Read + 'a>, Error> {
+        let blob = conn.blob_open(DatabaseName::Main, "content", "value", content_id, true)?;
+        // the checksum is computed from the blob beneath the decoder, such
+        // that the blob is read only once
+        let blob: Box<dyn Read + 'a> = match progress {
+            Some(progress) => Box::new(CheckingReader {
+                inner: blob,
+                progress,
+            }),
+            None => Box::new(blob),
+        };
+        let blob = BufReader::with_capacity(buffer_size, blob);
+        match key {
+            Some(key) => {
+                let opened = OpeningReader::new(blob, key, content_id);
+                Ok(codec.decompress_stream(Box::new(opened))?)
+            }
+            None => Ok(codec.decompress_stream(Box::new(blob))?),
+        }
+    }
+
+    // Move to the given position within the uncompressed content.
+    fn seek_to(&mut self, pos: u64) -> Result<(), Error> {
+        if pos < self.pos {
+            let progress = self.check.as_ref().map(|check| {
+                check.progress.replace(CheckProgress::new(check.algorithm));
+                check.progress.clone()
+            });
+            self.decoder = Self::open_decoder(
+                self.conn,
+                self.content_id,
+                self.codec,
+                self.buffer_size,
+                self.key,
+                progress,
+            )?;
+            self.pos = 0;
+        }
+        let result = io::copy(
+            &mut (&mut self.decoder).take(pos - self.pos),
+            &mut io::sink(),
+        );
+        match result {
+            Ok(skipped) => self.pos += skipped,
+            Err(err) => return Err(self.diagnose(err.into())),
+        }
+        Ok(())
+    }
+
+    // Read into the buffer from the current position, up to the given limit.
+    fn read_at_most(&mut self, buf: &mut [u8], limit: u64) -> io::Result<usize> {
+        let len = buf.len().min(limit.min(usize::MAX as u64) as usize);
+        match self.decoder.read(&mut buf[..len]) {
+            Ok(count) => {
+                self.pos += count as u64;
+                Ok(count)
+            }
+            Err(_) if self.is_damaged() => {
+                Err(io::Error::other(Error::CorruptBundle(self.content_id)))
+            }
+            Err(err) => Err(err),
+        }
+    }
+
+    // Copy the given number of bytes from the current position to the writer.
+    fn copy_to<W: Write>(&mut self, size: u64, output: &mut W) -> Result<(), Error> {
+        match io::copy(&mut (&mut self.decoder).take(size), output) {
+            Ok(copied) => self.pos += copied,
+            Err(err) => return Err(self.diagnose(err.into())),
+        }
+        Ok(())
+    }
+
+    //
+    // Compare the checksum of the blob with the one recorded in the archive,
+    // reading whatever part of the blob the decoder has not yet read. This is
+    // to be called once everything needed from the bundle has been read, and
+    // returns `Error::CorruptBundle` if the checksums do not match.
+    //
+    fn finish(self) -> Result<(), Error> {
+        let Some(check) = self.check else {
+            return Ok(());
+        };
+        drop(self.decoder);
+        let CheckProgress { mut hasher, count } =
+            check.progress.replace(CheckProgress::new(check.algorithm));
+        let mut blob = self.conn.blob_open(
+            DatabaseName::Main,
+            "content",
+            "value",
+            self.content_id,
+            true,
+        )?;
+        blob.seek(SeekFrom::Start(count))?;
+        io::copy(
+            &mut BufReader::with_capacity(self.buffer_size, blob),
+            &mut hasher,
+        )?;
+        if hasher.finalize() != check.expected {
+            tracing::warn!(
+                content_id = self.content_id,
+                "content bundle checksum does not match"
+            );
+            return Err(Error::CorruptBundle(self.content_id));
+        }
+        Ok(())
+    }
+
+    // Return the error to report for a failure to read the bundle, which is
+    // damage to the bundle if its checksum does not match.
+    fn diagnose(&self, err: Error) -> Error {
+        if self.is_damaged() {
+            Error::CorruptBundle(self.content_id)
+        } else {
+            err
+        }
+    }
+
+    // Return true if the checksum of the entire blob does not match the one
+    // recorded in the archive. This reads the blob once more, and so is only
+    // for explaining a failure to read the bundle.
+    fn is_damaged(&self) -> bool {
+        let Some(check) = self.check.as_ref() else {
+            return false;
+        };
+        let actual = self
+            .conn
+            .blob_open(
+                DatabaseName::Main,
+                "content",
+                "value",
+                self.content_id,
+                true,
+            )
+            .map_err(Error::from)
+            .and_then(|blob| {
+                let mut hasher = Hasher::new(check.algorithm);
+                io::copy(
+                    &mut BufReader::with_capacity(self.buffer_size, blob),
+                    &mut hasher,
+                )?;
+                Ok(hasher.finalize())
+            });
+        matches!(actual, Ok(actual) if actual != check.expected)
+    }
+}
+
+//
+// Checksum of a compressed content bundle that is computed as the bundle is
+// decompressed, to be compared with the checksum recorded in the archive.
+//
+struct BundleCheck {
+    // checksum recorded in the archive
+    expected: Vec<u8>,
+    algorithm: DigestAlgorithm,
+    // shared with the reader beneath the decoder
+    progress: Rc<RefCell<CheckProgress>>,
+}
+
+impl BundleCheck {
+    fn new(expected: Vec<u8>, algorithm: DigestAlgorithm) -> Self {
+        Self {
+            expected,
+            algorithm,
+            progress: Rc::new(RefCell::new(CheckProgress::new(algorithm))),
+        }
+    }
+}
+
+// Hash of the leading bytes of a blob and the number of those bytes.
+struct CheckProgress {
+    hasher: Hasher,
+    count: u64,
+}
+
+impl CheckProgress {
+    fn new(algorithm: DigestAlgorithm) -> Self {
+        Self {
+            hasher: Hasher::new(algorithm),
+            count: 0,
+        }
+    }
+}
+
+//
+// Passes along the bytes read from a blob, adding them to the checksum.
+//
+struct CheckingReader<R: Read> {
+    inner: R,
+    progress: Rc<RefCell<CheckProgress>>,
+}
+
+impl<R: Read> Read for CheckingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let count = self.inner.read(buf)?;
+        let mut progress = self.progress.borrow_mut();
+        progress.hasher.update(&buf[..count]);
+        progress.count += count as u64;
+        Ok(count)
+    }
+}
+
+//
+// Reads the content of a single item, opening each of the bundles that hold
+// the item content only when reading reaches that portion of the item.
+//
+pub(crate) struct ContentReader<'a> {
+    reader: &'a PackReader,
+    // portions of the item content that have yet to be read
+    parts: VecDeque<OutgoingContent>,
+    // bundle being read and the number of bytes remaining in this portion
+    current: Option<(BundleStream<'a>, u64)>,
+}
+
+impl<'a> ContentReader<'a> {
+    pub(crate) fn new(reader: &'a PackReader, item_id: i64) -> Result<Self, Error> {
+        let mut stmt = reader.conn.prepare(
+            "SELECT content, contentpos, size FROM itemcontent WHERE item = ?1 ORDER BY itempos",
+        )?;
+        let parts = stmt
+            .query_map([&item_id], |row| {
+                Ok(OutgoingContent {
+                    content: row.get(0)?,
+                    contentpos: row.get(1)?,
+                    size: row.get(2)?,
+                })
+            })?
+            .collect::<Result<_, _>>()?;
+        Ok(Self {
+            reader,
+            parts,
+            current: None,
+        })
+    }
+
+    // Open the bundle for the next portion of the content, if any.
+    fn open_next(&mut self) -> Result<bool, Error> {
+        match self.parts.pop_front() {
+            Some(part) => {
+                let mut bundle = self.reader.open_bundle(part.content)?;
+                bundle.seek_to(part.contentpos)?;
+                self.current = Some((bundle, part.size));
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+}
+
+impl Read for ContentReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        loop {
+            if let Some((bundle, remaining)) = self.current.as_mut() {
+                if *remaining > 0 {
+                    let count = bundle.read_at_most(buf, *remaining)?;
+                    if count == 0 {
+                        return Err(io::ErrorKind::UnexpectedEof.into());
+                    }
+                    *remaining -= count as u64;
+                    return Ok(count);
+                }
+            }
+            if let Some((bundle, _)) = self.current.take() {
+                bundle.finish().map_err(io::Error::other)?;
+            }
+            if !self.open_next().map_err(io::Error::other)? {
+                return Ok(0);
+            }
+        }
+    }
+}
+
+// Condition limiting the Path of a query to the entries within the scope of
+// an extraction, as held by the ExtractScope table: everything when a scope
+// is empty, everything beneath a scope that is a directory (with a trailing
+// slash), and otherwise the entry at exactly that path.
+const IN_SCOPE: &str = "EXISTS (SELECT 1 FROM ExtractScope WHERE Path = prefix
+    OR ((prefix = '' OR SUBSTR(prefix, -1) = '/') AND SUBSTR(Path, 1, LENGTH(prefix)) = prefix))";
+
+// default size of the cache of decompressed content bundles
+const DEFAULT_CACHE_SIZE: u64 = 33554432;
+
+//
+// Least-recently-used cache of decompressed content bundles.
+//
+struct BundleCache {
+    // maximum total size of the cached bundles
+    capacity: u64,
+    // total size of the cached bundles
+    used: u64,
+    // bundles ordered from least to most recently used
+    entries: VecDeque<(i64, Arc<Vec<u8>>)>,
+    // number of lookups that found the bundle in the cache
+    hits: u64,
+}
+
+impl BundleCache {
+    fn new(capacity: u64) -> Self {
+        Self {
+            capacity,
+            used: 0,
+            entries: VecDeque::new(),
+            hits: 0,
+        }
+    }
+
+    // Return the cached bundle, marking it as the most recently used.
+    fn get(&mut self, content_id: i64) -> Option<Arc<Vec<u8>>> {
+        let index = self.entries.iter().position(|(id, _)| *id == content_id)?;
+        let entry = self.entries.remove(index)?;
+        let data = entry.1.clone();
+        self.entries.push_back(entry);
+        self.hits += 1;
+        Some(data)
+    }
+
+    // Add the bundle to the cache, unless it is larger than the cache itself.
+    fn insert(&mut self, content_id: i64, data: Arc<Vec<u8>>) {
+        let len = data.len() as u64;
+        if len > self.capacity {
+            return;
+        }
+        self.evict(self.capacity - len);
+        self.used += len;
+        self.entries.push_back((content_id, data));
+    }
+
+    // Change the capacity of the cache, evicting bundles as necessary.
+    fn resize(&mut self, capacity: u64) {
+        self.capacity = capacity;
+        self.evict(capacity);
+    }
+
+    // Remove the least recently used bundles until at most `limit` bytes
+    // remain in the cache.
+    fn evict(&mut self, limit: u64) {
+        while self.used > limit {
+            match self.entries.pop_front() {
+                Some((_, data)) => self.used -= data.len() as u64,
+                None => break,
+            }
+        }
+    }
+}
+
+// extension of the files in the cache directory that hold content bundles
+const CACHE_EXTENSION: &str = "bundle";
+
+//
+// Cache of decompressed content bundles held in files within a directory,
+// each named for the checksum of the bundle in the archive and starting with
+// the BLAKE3 hash of the data that follows. The modification time of a file
+// marks when it was last used. Failing to read or write the cache is never
+// an error, the bundle is simply read from the archive.
+//
+struct DiskCache {
+    // directory that holds the cached bundles
+    dir: PathBuf,
+    // maximum total size of the files in the directory
+    limit: u64,
+}
+
+impl DiskCache {
+    // Return the path of the file for the bundle with the given checksum.
+    fn file_path(&self, digest: &str, checksum: &[u8]) -> PathBuf {
+        let hex: String = checksum.iter().map(|b| format!("{:02x}", b)).collect();
+        self.dir
+            .join(format!("{}-{}.{}", digest, hex, CACHE_EXTENSION))
+    }
+
+    // Return the cached bundle if it is present and intact, marking it as the
+    // most recently used.
+    fn get(&self, path: &Path) -> Option<Vec<u8>> {
+        let mut data = fs::read(path).ok()?;
+        if data.len() < blake3::OUT_LEN
+            || blake3::hash(&data[blake3::OUT_LEN..]).as_bytes() != &data[..blake3::OUT_LEN]
+        {
+            tracing::warn!(?path, "removing damaged bundle from cache");
+            let _ = fs::remove_file(path);
+            return None;
+        }
+        if let Ok(file) = fs::File::options().write(true).open(path) {
+            let _ = file.set_modified(SystemTime::now());
+        }
+        data.drain(..blake3::OUT_LEN);
+        Some(data)
+    }
+
+    // Add the bundle to the cache, unless it is larger than the cache itself,
+    // then remove the least recently used bundles to stay within the limit.
+    fn insert(&self, path: &Path, data: &[u8]) {
+        if (data.len() + blake3::OUT_LEN) as u64 > self.limit {
+            return;
+        }
+        // write to a temporary file first so that readers in other processes
+        // never see a partial bundle
+        let partial = path.with_extension(format!("{}.partial", std::process::id()));
+        let result = fs::File::create(&partial).and_then(|mut file| {
+            file.write_all(blake3::hash(data).as_bytes())?;
+            file.write_all(data)?;
+            drop(file);
+            fs::rename(&partial, path)
+        });
+        if let Err(err) = result {
+            tracing::warn!(?path, %err, "could not add bundle to cache");
+            let _ = fs::remove_file(&partial);
+            return;
+        }
+        if let Err(err) = self.evict(path) {
+            tracing::warn!(dir = ?self.dir, %err, "could not evict bundles from cache");
+        }
+    }
+
+    // Remove the least recently used bundles, other than the one that was
+    // just added, until the total size is within the limit.
+    fn evict(&self, keep: &Path) -> io::Result<()> {
+        let mut files: Vec<(SystemTime, u64, PathBuf)> = vec![];
+        let mut used: u64 = 0;
+        for entry in fs::read_dir(&self.dir)? {
+            let entry = entry?;
+            let path = entry.path();
+            if path.extension().and_then(|e| e.to_str()) != Some(CACHE_EXTENSION) {
+                continue;
+            }
+            let metadata = entry.metadata()?;
+            used += metadata.len();
+            if path != keep {
+                let modified = metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+                files.push((modified, metadata.len(), path));
+            }
+        }
+        files.sort();
+        for (_, len, path) in files {
+            if used <= self.limit {
+                break;
+            }
+            match fs::remove_file(&path) {
+                Ok(()) => used -= len,
+                Err(err) if err.kind() == io::ErrorKind::NotFound => used -= len,
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(())
+    }
+}
+
+//
+// Return the path with the number added to the end of the name, before any
+// extension, as in `docs/notes (2).txt`.
+//
+fn numbered_path(path: &str, number: u32) -> String {
+    let (parent, name) = match path.rfind('/') {
+        Some(slash) => path.split_at(slash + 1),
+        None => ("", path),
+    };
+    match name.rfind('.').filter(|dot| *dot > 0) {
+        Some(dot) => format!("{}{} ({}){}", parent, &name[..dot], number, &name[dot..]),
+        None => format!("{}{} ({})", parent, name, number),
+    }
+}
+
+//
+// Return true if the file system of the directory ignores the case of names,
+// as determined by creating a file and looking for it by another case. Any
+// failure to tell is taken to mean that it does not.
+//
+fn ignores_case(dir: &Path) -> bool {
+    let name = format!(".pack-rs-case-probe-{}", std::process::id());
+    let probe = dir.join(&name);
+    if fs::File::create(&probe).is_err() {
+        return false;
+    }
+    let ignores = fs::symlink_metadata(dir.join(name.to_uppercase())).is_ok();
+    let _ = fs::remove_file(&probe);
+    ignores
+}
+
+//
+// Convert the path into an SQLite URI filename, escaping the characters that
+// would otherwise be treated as part of the query string.
+//
+fn file_uri(path: &Path) -> String {
+    let mut uri = String::from("file:");
+    let text = path.to_string_lossy();
+    if cfg!(target_family = "windows") && path.is_absolute() {
+        uri.push('/');
+    }
+    for ch in text.chars() {
+        match ch {
+            '%' => uri.push_str("%25"),
+            '?' => uri.push_str("%3f"),
+            '#' => uri.push_str("%23"),
+            '\\' if cfg!(target_family = "windows") => uri.push('/'),
+            _ => uri.push(ch),
+        }
+    }
+    uri
+}
+
+///
+/// Summary of the entries written by `PackReader::extract_all_with()` and the
+/// other extraction methods. The paths of the files can be gathered as they
+/// are written using `PackReader::set_on_file_extracted()`.
+///
+#[derive(Clone, Debug, Default)]
+pub struct ExtractReport {
+    /// Number of regular files written.
+    pub files: u64,
+    /// Number of directories created, including any that already existed.
+    pub directories: u64,
+    /// Number of symbolic links created.
+    pub symlinks: u64,
+    /// Number of named pipes and device nodes created.
+    pub special_files: u64,
+    /// Total size of the file content written.
+    pub bytes_written: u64,
+    /// Number of entries that were not written, either because they were
+    /// already extracted (when resuming) or because they are special files
+    /// that were not to be created.
+    pub skipped: u64,
+    /// Number of the files written that replaced existing files.
+    pub overwritten: u64,
+    /// Warnings that occurred during the extraction.
+    pub warnings: Vec<Warning>,
+    /// Time taken by the extraction.
+    pub elapsed: Duration,
+}
+
+impl ExtractReport {
+    ///
+    /// Return the average number of bytes written per second.
+    ///
+    pub fn throughput(&self) -> f64 {
+        let seconds = self.elapsed.as_secs_f64();
+        if seconds > 0.0 {
+            self.bytes_written as f64 / seconds
+        } else {
+            0.0
+        }
+    }
+}
+
+///
+/// `Entry` represents a row from the `item` table.
+///
+#[derive(Clone, Debug)]
+pub struct Entry {
+    pub id: i64,
+    pub parent: i64,
+    pub kind: ItemKind,
+    pub name: String,
+}
+
+///
+/// An item in the hierarchy returned by `PackReader::tree()`.
+///
+#[derive(Clone, Debug)]
+pub struct TreeNode {
+    /// Name of the item, without any path.
+    pub name: String,
+    pub kind: ItemKind,
+    /// Size of the item content, or the total size of the items beneath a
+    /// directory.
+    pub size: u64,
+    /// Items within this directory, sorted by name.
+    pub children: Vec<TreeNode>,
+}
+
+// Remove the children of the given parent from the map and build their
+// subtrees, rolling up the size of each directory.
+fn assemble_tree(parent: i64, children: &mut HashMap<i64, Vec<(i64, TreeNode)>>) -> Vec<TreeNode> {
+    let Some(entries) = children.remove(&parent) else {
+        return vec![];
+    };
+    let mut nodes: Vec<TreeNode> = vec![];
+    for (id, mut node) in entries {
+        if node.kind == ItemKind::Directory {
+            node.children = assemble_tree(id, children);
+            node.size = node.children.iter().map(|c| c.size).sum();
+        }
+        nodes.push(node);
+    }
+    nodes
+}
+
+///
+/// Facts about an archive as a whole, as gathered by `PackReader::info()`.
+///
+#[derive(Clone, Debug)]
+pub struct ArchiveInfo {
+    /// Version of the archive format, or zero for archives created before the
+    /// version was recorded.
+    pub format_version: u32,
+    /// SQLite application identifier, which is `0x5041434b` ("PACK") for
+    /// archives created or updated by this version.
+    pub application_id: u32,
+    /// Number of entries, including directories.
+    pub items: u64,
+    /// Number of content bundles.
+    pub bundles: u64,
+    /// Combined size in bytes of the content bundles as stored.
+    pub compressed: u64,
+    /// Combined size in bytes of the content bundles once decompressed.
+    pub uncompressed: u64,
+    /// Identifiers of the codecs used to compress the content bundles.
+    pub codecs: Vec<String>,
+    /// Layout of the archive, such as `content-addressed`, if not the default.
+    pub layout: Option<String>,
+    /// Identifier of the algorithm for the file and bundle checksums.
+    pub digest: String,
+    /// Method by which the content is encrypted, if it is.
+    pub encryption: Option<String>,
+    /// Date and time (UTC) at which the archive was created, if recorded.
+    pub created: Option<String>,
+    /// Name and version of the program that created the archive, if recorded.
+    pub creator: Option<String>,
+}
+
+///
+/// Space used by the archive, as gathered by `PackReader::space_report()`.
+///
+#[derive(Debug)]
+pub struct SpaceReport {
+    /// Sizes of each of the content bundles.
+    pub bundles: Vec<BundleSpace>,
+    /// Bytes saved by item content that is shared with other items.
+    pub dedup_savings: u64,
+    /// Paths and sizes of the largest files, in descending order.
+    pub largest: Vec<(String, u64)>,
+}
+
+///
+/// Files in the archive grouped by type, as gathered by
+/// `PackReader::type_report()`.
+///
+#[derive(Debug)]
+pub struct TypeReport {
+    /// Totals for each file name extension, in descending order of size.
+    pub extensions: Vec<TypeStats>,
+    /// Totals for each detected MIME type, in descending order of size.
+    pub mime_types: Vec<TypeStats>,
+}
+
+///
+/// Number and total size of the files of one type.
+///
+#[derive(Clone, Debug, PartialEq)]
+pub struct TypeStats {
+    /// Extension or MIME type, or `None` for files without one.
+    pub name: Option<String>,
+    /// Number of files of this type.
+    pub files: u64,
+    /// Total size of the files of this type.
+    pub bytes: u64,
+}
+
+///
+/// Files in the archive that have identical content, as found by
+/// `PackReader::duplicates()`.
+///
+#[derive(Clone, Debug, PartialEq)]
+pub struct DuplicateSet {
+    /// BLAKE3 digest of the content that the files have in common.
+    pub checksum: Vec<u8>,
+    /// Size of each of the files.
+    pub size: u64,
+    /// Paths of the files, in sorted order.
+    pub paths: Vec<String>,
+}
+
+impl DuplicateSet {
+    ///
+    /// Return the number of bytes that would be saved by keeping only one of
+    /// the files.
+    ///
+    pub fn reclaimable(&self) -> u64 {
+        self.size * (self.paths.len() as u64 - 1)
+    }
+}
+
+///
+/// Compressed and uncompressed sizes of a single content bundle.
+///
+#[derive(Debug)]
+pub struct BundleSpace {
+    /// Rowid of the content in the content table.
+    pub id: i64,
+    /// Length of the compressed blob.
+    pub compressed: u64,
+    /// Length of the content before compression.
+    pub uncompressed: u64,
+    /// Number of portions of item content within the bundle.
+    pub entries: u64,
+}
+
+// Result from the IndexedFiles temporary table joined with itemcontent table.
+#[derive(Debug)]
+struct IndexedFile {
+    content: i64,
+    contentpos: u64,
+    itempos: u64,
+    size: u64,
+    kind: i8,
+    path: String,
+    // rowid of the item in the item table
+    item: i64,
+    // size of the entire file, when verifying checksums
+    total: Option<u64>,
+    // recorded checksum of the file, when verifying checksums
+    checksum: Option<Vec<u8>>,
+    // number of parts of the entire file, when invoking a callback
+    parts: u64,
+}
+
+//
+// Computes the checksums of the files being extracted as their content is
+// written, one piece at a time. Files whose pieces are not written in order
+// are set aside to be read back once the extraction is complete.
+//
+struct ExtractVerifier {
+    algorithm: DigestAlgorithm,
+    // files partly written, with the number of bytes hashed so far
+    pending: HashMap<i64, (Hasher, u64)>,
+    // files to be read back, with their paths and recorded checksums
+    unordered: Vec<(i64, String, Vec<u8>)>,
+    // identifiers of the files in the unordered list
+    set_aside: HashSet<i64>,
+    // files that were removed for failing verification
+    removed: Vec<i64>,
+}
+
+impl ExtractVerifier {
+    fn new(algorithm: DigestAlgorithm) -> Self {
+        Self {
+            algorithm,
+            pending: HashMap::new(),
+            unordered: vec![],
+            set_aside: HashSet::new(),
+            removed: vec![],
+        }
+    }
+
+    // Return the hasher into which the piece of the file is to be written,
+    // if the file is being verified and the piece follows what was hashed.
+    fn hasher_for(&mut self, entry: &IndexedFile) -> Option<&mut Hasher> {
+        let checksum = entry.checksum.as_ref()?;
+        if self.set_aside.contains(&entry.item) {
+            return None;
+        }
+        let algorithm = self.algorithm;
+        let state = self
+            .pending
+            .entry(entry.item)
+            .or_insert_with(|| (Hasher::new(algorithm), 0));
+        if state.1 != entry.itempos {
+            self.pending.remove(&entry.item);
+            self.set_aside.insert(entry.item);
+            self.unordered
+                .push((entry.item, entry.path.clone(), checksum.clone()));
+            return None;
+        }
+        let state = self.pending.get_mut(&entry.item)?;
+        state.1 += entry.size;
+        Some(&mut state.0)
+    }
+
+    // Return true if the file is to be read back or was removed, and hence is
+    // not yet known to have been extracted successfully.
+    fn holds(&self, item: i64) -> bool {
+        self.set_aside.contains(&item) || self.removed.contains(&item)
+    }
+
+    // Note that the piece of the file was written, returning whether the
+    // checksum matches once the entire file has been written.
+    fn written(&mut self, entry: &IndexedFile) -> Option<bool> {
+        let checksum = entry.checksum.as_ref()?;
+        if self.set_aside.contains(&entry.item) {
+            return None;
+        }
+        let algorithm = self.algorithm;
+        let (hasher, hashed) = self
+            .pending
+            .entry(entry.item)
+            .or_insert_with(|| (Hasher::new(algorithm), 0));
+        if *hashed < entry.total.unwrap_or(0) {
+            return None;
+        }
+        let matched = hasher.finalize() == *checksum;
+        self.pending.remove(&entry.item);
+        Some(matched)
+    }
+}
+
+//
+// Writes to the output while passing the same data to the hasher.
+//
+struct TeeWriter<'a, W: Write>(&'a mut W, &'a mut Hasher);
+
+impl<W: Write> Write for TeeWriter<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = self.0.write(buf)?;
+        self.1.update(&buf[..len]);
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+struct OutgoingContent {
+    // rowid of the content in the content table
+    content: i64,
+    // offset within the content bundle where the data will go
+    contentpos: u64,
+    // size of the item content
+    size: u64,
+}
+
+///
+/// Set the modification time of the directory, given in seconds since the
+/// Unix epoch.
+///
+#[cfg(target_family = "unix")]
+pub(crate) fn set_dir_mtime(dirpath: &Path, mtime: i64) -> Result<(), Error> {
+    let time = if mtime < 0 {
+        SystemTime::UNIX_EPOCH - Duration::from_secs(mtime.unsigned_abs())
+    } else {
+        SystemTime::UNIX_EPOCH + Duration::from_secs(mtime as u64)
+    };
+    fs::File::open(dirpath)?.set_modified(time)?;
+    Ok(())
+}
+
+///
+/// Create a symbolic link using the given raw bytes.
+///
+#[cfg(any(target_family = "unix", target_family = "windows"))]
+pub(crate) fn write_link(contents: &[u8], filepath: &Path) -> Result<(), Error> {
+    use os_str_bytes::OsStringBytes;
+    // this may panic if the bytes are not valid for this platform
+    let target = std::ffi::OsString::from_io_vec(contents.to_owned())
+        .ok_or_else(|| Error::LinkTextEncoding)?;
+    // cfg! macro will not work in this OS-specific import case
+    {
+        #[cfg(target_family = "unix")]
+        use std::os::unix::fs;
+        #[cfg(target_family = "windows")]
+        use std::os::windows::fs;
+        #[cfg(target_family = "unix")]
+        fs::symlink(target, filepath)?;
+        #[cfg(target_family = "windows")]
+        fs::symlink_file(target, filepath)?;
+    }
+    Ok(())
+}
+
+#[cfg(not(any(target_family = "unix", target_family = "windows")))]
+pub(crate) fn write_link(contents: &[u8], filepath: &Path) -> Result<(), Error> {
+    let _ = (contents, filepath);
+    Err(Error::IOError(io::ErrorKind::Unsupported.into()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::PackBuilder;
+
+    #[test]
+    fn test_read_bundle_corrupt() -> Result<(), Error> {
+        let workspace = tempfile::tempdir()?;
+        let basepath = workspace.path().join("input");
+        fs::create_dir(&basepath)?;
+        fs::write(basepath.join("hello.txt"), "hello world")?;
+        let packfile = workspace.path().join("pack.db3");
+        let mut builder = PackBuilder::new()?;
+        builder.add_dir_all(&basepath)?;
+        builder.finish(&packfile)?;
+
+        let reader = PackReader::new(&packfile)?;
+        reader.verify_bundle(1)?;
+
+        // damage the compressed blob by prepending a byte
+        reader.conn.execute(
+            "UPDATE content SET value = CAST(X'00' || value AS BLOB) WHERE id = 1",
+            (),
+        )?;
+        let result = reader.verify_bundle(1);
+        assert!(matches!(result, Err(Error::CorruptBundle(1))));
+        let options = ExtractOptions {
+            destination: workspace.path().to_path_buf(),
+            ..Default::default()
+        };
+        let result = reader.extract_all_with(&options);
+        assert!(matches!(result, Err(Error::CorruptBundle(1))));
+        Ok(())
+    }
+
+    #[test]
+    fn test_bundle_stream() -> Result<(), Error> {
+        // content that does not compress, such that the blob is large
+        let mut state: u64 = 0x9e37_79b9_7f4a_7c15;
+        let mut content: Vec<u8> = Vec::with_capacity(1_048_576);
+        while content.len() < 1_048_576 {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            content.extend_from_slice(&state.to_le_bytes());
+        }
+        let workspace = tempfile::tempdir()?;
+        let packfile = workspace.path().join("pack.db3");
+        let mut builder = PackBuilder::new()?;
+        builder.add_stream("random.bin", 0, content.as_slice())?;
+        builder.finish(&packfile)?;
+        let mut reader = PackReader::new(&packfile)?;
+        reader.profile = PerformanceProfile::Safe;
+        let blob_len: u64 = reader.conn.query_row(
+            "SELECT LENGTH(value) FROM content WHERE id = 1",
+            [],
+            |row| row.get(0),
+        )?;
+
+        let mut bundle = reader.open_bundle(1)?;
+        let mut buffer: Vec<u8> = Vec::new();
+        bundle.seek_to(6)?;
+        bundle.copy_to(5, &mut buffer)?;
+        assert_eq!(buffer, &content[6..11]);
+        // moving backward restarts the decompression
+        buffer.clear();
+        bundle.seek_to(0)?;
+        bundle.copy_to(5, &mut buffer)?;
+        assert_eq!(buffer, &content[..5]);
+        // only the start of the blob has been read and hashed
+        let hashed = bundle.check.as_ref().unwrap().progress.borrow().count;
+        assert!(hashed < blob_len / 2);
+        bundle.finish()?;
+
+        // damage the tail of the blob, which the decoder never reaches
+        let mut blob: Vec<u8> =
+            reader
+                .conn
+                .query_row("SELECT value FROM content WHERE id = 1", [], |row| {
+                    row.get(0)
+                })?;
+        let last = blob.len() - 1;
+        blob[last] ^= 0xff;
+        reader
+            .conn
+            .execute("UPDATE content SET value = ?1 WHERE id = 1", [&blob])?;
+        let mut bundle = reader.open_bundle(1)?;
+        buffer.clear();
+        bundle.copy_to(5, &mut buffer)?;
+        assert_eq!(buffer, &content[..5]);
+        let result = bundle.finish();
+        assert!(matches!(result, Err(Error::CorruptBundle(1))));
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_profiles() -> Result<(), Error> {
+        // content that does not compress, such that the blob is large
+        let mut state: u64 = 0x9e37_79b9_7f4a_7c15;
+        let mut content: Vec<u8> = Vec::with_capacity(4_194_304);
+        while content.len() < 4_194_304 {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            content.extend_from_slice(&state.to_le_bytes());
+        }
+        let workspace = tempfile::tempdir()?;
+        let packfile = workspace.path().join("pack.db3");
+        let mut builder = PackBuilder::new()?;
+        builder.add_stream("random.bin", 0, content.as_slice())?;
+        builder.finish(&packfile)?;
+
+        // memory mapping is enabled, and the blob is read in pieces of the
+        // size given by the profile
+        let mut reader = PackReader::new(&packfile)?;
+        let mut consumed: Vec<u64> = vec![];
+        for (profile, mmap_size) in [
+            (PerformanceProfile::Safe, 0),
+            (PerformanceProfile::Balanced, 1_073_741_824),
+            (PerformanceProfile::Fast, 2_147_418_112),
+        ] {
+            reader.set_performance_profile(profile)?;
+            let mmap: i64 = reader
+                .conn
+                .pragma_query_value(None, "mmap_size", |row| row.get(0))?;
+            assert_eq!(mmap, mmap_size);
+            let mut bundle = reader.open_bundle(1)?;
+            let mut buffer: Vec<u8> = Vec::new();
+            bundle.copy_to(5, &mut buffer)?;
+            assert_eq!(buffer, &content[..5]);
+            let count = bundle.check.as_ref().unwrap().progress.borrow().count;
+            consumed.push(count);
+            bundle.finish()?;
+        }
+        // the decoder asks for whole blocks, which are larger than the small
+        // buffer of the safe profile
+        assert!(consumed[0] < 1_048_576 / 4);
+        assert_eq!(consumed[1], 1_048_576);
+        assert_eq!(consumed[2], 4_194_304);
+
+        // extraction gives the same result with each profile, including
+        // the read-ahead of the archive
+        for (index, profile) in [
+            PerformanceProfile::Safe,
+            PerformanceProfile::Balanced,
+            PerformanceProfile::Fast,
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            reader.set_performance_profile(profile)?;
+            let destination = workspace.path().join(format!("output{}", index));
+            fs::create_dir(&destination)?;
+            let options = ExtractOptions {
+                destination: destination.clone(),
+                ..Default::default()
+            };
+            reader.extract_all_with(&options)?;
+            assert_eq!(fs::read(destination.join("random.bin"))?, content);
+        }
+        // the hint is ignored for a file that cannot be opened
+        PerformanceProfile::Fast.advise_read_ahead(&workspace.path().join("missing.db3"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_extract_empty_entries() -> Result<(), Error> {
+        let workspace = tempfile::tempdir()?;
+        let basepath = workspace.path().join("input");
+        fs::create_dir_all(basepath.join("empty/nested"))?;
+        fs::create_dir_all(basepath.join("full"))?;
+        fs::write(basepath.join("full/zero.bin"), "")?;
+        #[cfg(target_family = "unix")]
+        std::os::unix::fs::symlink("does/not/exist", basepath.join("dangling"))?;
+        let packfile = workspace.path().join("pack.db3");
+        let mut builder = PackBuilder::new()?;
+        builder.add_dir_all(&basepath)?;
+        builder.finish(&packfile)?;
+        let reader = PackReader::new(&packfile)?;
+
+        // by default the empty directories are created
+        let destination = workspace.path().join("output1");
+        fs::create_dir(&destination)?;
+        let options = ExtractOptions {
+            destination: destination.clone(),
+            ..Default::default()
+        };
+        assert_eq!(reader.extract_all_with(&options)?.files, 1);
+        assert!(destination.join("input/empty/nested").is_dir());
+        let metadata = fs::metadata(destination.join("input/full/zero.bin"))?;
+        assert!(metadata.is_file());
+        assert_eq!(metadata.len(), 0);
+        #[cfg(target_family = "unix")]
+        {
+            let target = fs::read_link(destination.join("input/dangling"))?;
+            assert_eq!(target, PathBuf::from("does/not/exist"));
+        }
+
+        // optionally the empty directories are skipped
+        let destination = workspace.path().join("output2");
+        fs::create_dir(&destination)?;
+        let options = ExtractOptions {
+            destination: destination.clone(),
+            empty_dirs: false,
+            ..Default::default()
+        };
+        assert_eq!(reader.extract_all_with(&options)?.files, 1);
+        assert!(!destination.join("input/empty").exists());
+        assert!(destination.join("input/full/zero.bin").is_file());
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_range() -> Result<(), Error> {
+        let workspace = tempfile::tempdir()?;
+        let basepath = workspace.path().join("input");
+        fs::create_dir_all(&basepath)?;
+        // make the file span several content bundles
+        let data: Vec<u8> = (0..(crate::BUNDLE_SIZE * 2 + 1000))
+            .map(|v| (v % 251) as u8)
+            .collect();
+        fs::write(basepath.join("first.bin"), b"abcdef")?;
+        fs::write(basepath.join("large.bin"), &data)?;
+        let packfile = workspace.path().join("pack.db3");
+        let mut builder = PackBuilder::new()?;
+        builder.add_dir_all(&basepath)?;
+        builder.finish(&packfile)?;
+
+        let reader = PackReader::new(&packfile)?;
+        assert_eq!(reader.read_range("input/first.bin", 2, 3)?, b"cde");
+        assert_eq!(reader.read_range("input/first.bin", 4, 100)?, b"ef");
+        assert!(reader.read_range("input/first.bin", 10, 5)?.is_empty());
+        // range that spans the boundary between bundles
+        let offset = crate::BUNDLE_SIZE - 500;
+        let actual = reader.read_range("input/large.bin", offset, 2000)?;
+        let start = offset as usize;
+        assert_eq!(actual, &data[start..start + 2000]);
+        let tail = reader.read_range("/input/large.bin", data.len() as u64 - 10, 10)?;
+        assert_eq!(tail, &data[data.len() - 10..]);
+        assert!(matches!(
+            reader.read_range("input/missing.bin", 0, 1),
+            Err(Error::EntryNotFound(_))
+        ));
+        assert!(matches!(
+            reader.read_range("input", 0, 1),
+            Err(Error::EntryNotFound(_))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_tree() -> Result<(), Error> {
+        let workspace = tempfile::tempdir()?;
+        let basepath = workspace.path().join("input");
+        fs::create_dir_all(basepath.join("sub/deeper"))?;
+        fs::create_dir_all(basepath.join("empty"))?;
+        fs::write(basepath.join("sub/deeper/one.txt"), "one")?;
+        fs::write(basepath.join("sub/two.txt"), "twotwo")?;
+        fs::write(basepath.join("a.txt"), "a")?;
+        let packfile = workspace.path().join("pack.db3");
+        let mut builder = PackBuilder::new()?;
+        builder.add_dir_all(&basepath)?;
+        builder.finish(&packfile)?;
+
+        let reader = PackReader::new(&packfile)?;
+        let tree = reader.tree()?;
+        assert_eq!(tree.len(), 1);
+        let input = &tree[0];
+        assert_eq!(input.name, "input");
+        assert_eq!(input.size, 10);
+        let names: Vec<&str> = input.children.iter().map(|c| c.name.as_str()).collect();
+        assert_eq!(names, vec!["a.txt", "empty", "sub"]);
+        assert_eq!(input.children[1].size, 0);
+        let sub = &input.children[2];
+        assert_eq!(sub.size, 9);
+        assert_eq!(sub.children[0].name, "deeper");
+        assert_eq!(sub.children[0].size, 3);
+        assert_eq!(sub.children[0].children[0].name, "one.txt");
+        Ok(())
+    }
+
+    #[test]
+    fn test_visit() -> Result<(), Error> {
+        let workspace = tempfile::tempdir()?;
+        let basepath = workspace.path().join("input");
+        fs::create_dir_all(basepath.join("sub"))?;
+        let data: Vec<u8> = (0..(crate::BUNDLE_SIZE + 1000))
+            .map(|v| (v % 253) as u8)
+            .collect();
+        fs::write(basepath.join("sub/hello.txt"), "hello world")?;
+        fs::write(basepath.join("large.bin"), &data)?;
+        fs::write(basepath.join("skipped.txt"), "not read")?;
+        let packfile = workspace.path().join("pack.db3");
+        let mut builder = PackBuilder::new()?;
+        builder.add_dir_all(&basepath)?;
+        builder.finish(&packfile)?;
+
+        let reader = PackReader::new(&packfile)?;
+        let mut visited: Vec<(String, Vec<u8>)> = vec![];
+        reader.visit(|entry, content| {
+            let mut buffer: Vec<u8> = vec![];
+            if !entry.name.ends_with("skipped.txt") {
+                content.read_to_end(&mut buffer)?;
+            }
+            visited.push((entry.name.clone(), buffer));
+            Ok(())
+        })?;
+        assert_eq!(visited.len(), 5);
+        let find = |name: &str| visited.iter().find(|v| v.0 == name).unwrap().1.clone();
+        assert!(find("input/").is_empty());
+        assert!(find("input/skipped.txt").is_empty());
+        assert_eq!(find("input/sub/hello.txt"), b"hello world");
+        assert!(find("input/large.bin") == data);
+
+        // errors from the visitor stop the traversal
+        let mut count = 0;
+        let result = reader.visit(|entry, _| {
+            count += 1;
+            Err(Error::EntryNotFound(entry.name.clone()))
+        });
+        assert!(matches!(result, Err(Error::EntryNotFound(_))));
+        assert_eq!(count, 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_open_read_only() -> Result<(), Error> {
+        assert_eq!(file_uri(Path::new("a%b?c#d.db3")), "file:a%25b%3fc%23d.db3");
+        let workspace = tempfile::tempdir()?;
+        let basepath = workspace.path().join("input");
+        fs::create_dir_all(&basepath)?;
+        fs::write(basepath.join("hello.txt"), "hello world")?;
+        let packfile = workspace.path().join("pack #1.db3");
+        let mut builder = PackBuilder::new()?;
+        builder.add_dir_all(&basepath)?;
+        builder.finish(&packfile)?;
+
+        // reading while another connection has uncommitted changes
+        let mut writer = PackBuilder::open(&packfile)?;
+        writer.set_busy_timeout(Duration::from_millis(10))?;
+        writer.add_stream("other.txt", 0, &mut "other".as_bytes())?;
+        let reader = PackReader::open_read_only(&packfile)?;
+        assert_eq!(reader.read_range("input/hello.txt", 0, 5)?, b"hello");
+        assert!(reader.conn.execute("DELETE FROM item", ()).is_err());
+        drop(reader);
+        writer.commit()?;
+
+        // files that cannot be modified are opened as immutable
+        let mut perms = fs::metadata(&packfile)?.permissions();
+        perms.set_readonly(true);
+        fs::set_permissions(&packfile, perms.clone())?;
+        let reader = PackReader::open_read_only(&packfile)?;
+        assert_eq!(reader.read_range("other.txt", 0, 5)?, b"other");
+        drop(reader);
+        #[allow(clippy::permissions_set_readonly_false)]
+        perms.set_readonly(false);
+        fs::set_permissions(&packfile, perms)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_from_bytes() -> Result<(), Error> {
+        let workspace = tempfile::tempdir()?;
+        let basepath = workspace.path().join("input");
+        fs::create_dir_all(basepath.join("sub"))?;
+        fs::write(basepath.join("hello.txt"), "hello world")?;
+        fs::write(basepath.join("sub/more.txt"), "more text")?;
+        let packfile = workspace.path().join("pack.db3");
+        let mut builder = PackBuilder::new()?;
+        builder.add_dir_all(&basepath)?;
+        builder.finish(&packfile)?;
+        let data = fs::read(&packfile)?;
+        fs::remove_file(&packfile)?;
+
+        let reader = PackReader::from_bytes(&data)?;
+        assert_eq!(reader.entries()?.len(), 4);
+        assert_eq!(reader.read_range("input/hello.txt", 6, 5)?, b"world");
+        assert!(reader.conn.execute("DELETE FROM item", ()).is_err());
+        let mut sink = crate::MemorySink::new();
+        assert_eq!(reader.extract_to(&mut sink)?, 2);
+        assert_eq!(
+            sink.entries().get("input/sub/more.txt"),
+            Some(&crate::SinkEntry::File(b"more text".to_vec()))
+        );
+        assert!(matches!(
+            PackReader::from_bytes(b"not a pack file"),
+            Err(Error::NotPackFile)
+        ));
+        assert!(matches!(
+            PackReader::from_bytes(&[]),
+            Err(Error::NotPackFile)
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_bundle_cache() -> Result<(), Error> {
+        let workspace = tempfile::tempdir()?;
+        let basepath = workspace.path().join("input");
+        fs::create_dir_all(&basepath)?;
+        fs::write(basepath.join("one.txt"), "one")?;
+        fs::write(basepath.join("two.txt"), "two")?;
+        let packfile = workspace.path().join("pack.db3");
+        let mut builder = PackBuilder::new()?;
+        builder.add_dir_all(&basepath)?;
+        builder.finish(&packfile)?;
+
+        let mut reader = PackReader::new(&packfile)?;
+        assert_eq!(reader.read_range("input/one.txt", 0, 3)?, b"one");
+        assert_eq!(reader.read_range("input/two.txt", 1, 10)?, b"wo");
+        assert_eq!(reader.read_range("input/one.txt", 1, 1)?, b"n");
+        assert_eq!(reader.cache.borrow().hits, 2);
+        assert_eq!(reader.cache.borrow().entries.len(), 1);
+
+        // bundles larger than the cache are read without being kept
+        reader.set_cache_size(4);
+        assert!(reader.cache.borrow().entries.is_empty());
+        assert_eq!(reader.read_range("input/two.txt", 0, 3)?, b"two");
+        assert_eq!(reader.read_range("input/one.txt", 0, 3)?, b"one");
+        assert!(reader.cache.borrow().entries.is_empty());
+        assert!(!reader.is_cacheable(1)?);
+        reader.set_cache_size(6);
+        assert!(reader.is_cacheable(1)?);
+
+        // disabling the cache empties it
+        reader.set_cache_size(0);
+        assert_eq!(reader.read_range("input/two.txt", 0, 3)?, b"two");
+        assert_eq!(reader.cache.borrow().hits, 2);
+        assert!(reader.cache.borrow().entries.is_empty());
+
+        // least recently used bundles are evicted first
+        let mut cache = BundleCache::new(10);
+        cache.insert(1, Arc::new(vec![0; 4]));
+        cache.insert(2, Arc::new(vec![0; 4]));
+        assert!(cache.get(1).is_some());
+        cache.insert(3, Arc::new(vec![0; 4]));
+        assert!(cache.get(2).is_none());
+        assert!(cache.get(1).is_some());
+        assert!(cache.get(3).is_some());
+        cache.insert(4, Arc::new(vec![0; 11]));
+        assert!(cache.get(4).is_none());
+        assert_eq!(cache.used, 8);
+        Ok(())
+    }
+
+    #[test]
+    fn test_disk_cache() -> Result<(), Error> {
+        let workspace = tempfile::tempdir()?;
+        let basepath = workspace.path().join("input");
+        fs::create_dir_all(&basepath)?;
+        fs::write(basepath.join("one.txt"), "one ".repeat(1000))?;
+        let packfile = workspace.path().join("pack.db3");
+        let mut builder = PackBuilder::new()?;
+        builder.add_dir_all(&basepath)?;
+        builder.finish(&packfile)?;
+        let cachedir = workspace.path().join("cache");
+        let cached_files = || -> io::Result<Vec<PathBuf>> {
+            let mut paths: Vec<PathBuf> = vec![];
+            for entry in fs::read_dir(&cachedir)? {
+                paths.push(entry?.path());
+            }
+            Ok(paths)
+        };
+
+        let mut reader = PackReader::new(&packfile)?;
+        reader.set_cache_size(0);
+        reader.set_cache_dir(&cachedir, 1_048_576)?;
+        assert_eq!(reader.read_range("input/one.txt", 4, 3)?, b"one");
+        assert_eq!(cached_files()?.len(), 1);
+        drop(reader);
+
+        // later readers find the bundle in the cache, without reading it
+        let conn = Connection::open(&packfile)?;
+        conn.execute("UPDATE content SET value = zeroblob(length(value))", ())?;
+        drop(conn);
+        let reader = PackReader::new(&packfile)?;
+        assert!(matches!(
+            reader.read_range("input/one.txt", 0, 3),
+            Err(Error::CorruptBundle(_))
+        ));
+        let mut reader = PackReader::new(&packfile)?;
+        reader.set_cache_dir(&cachedir, 1_048_576)?;
+        assert_eq!(reader.read_range("input/one.txt", 0, 3)?, b"one");
+
+        // damaged files are removed from the cache and not used
+        let path = cached_files()?.remove(0);
+        let mut data = fs::read(&path)?;
+        data[40] ^= 1;
+        fs::write(&path, data)?;
+        let mut reader = PackReader::new(&packfile)?;
+        reader.set_cache_dir(&cachedir, 1_048_576)?;
+        assert!(reader.read_range("input/one.txt", 0, 3).is_err());
+        assert!(cached_files()?.is_empty());
+
+        // least recently used bundles are evicted first
+        let cache = DiskCache {
+            dir: cachedir.clone(),
+            limit: 80,
+        };
+        let paths: Vec<PathBuf> = [b"a", b"b", b"c"]
+            .iter()
+            .map(|checksum| cache.file_path("blake3", &checksum[..]))
+            .collect();
+        let set_used = |path: &Path, secs: u64| -> io::Result<()> {
+            let file = fs::File::options().write(true).open(path)?;
+            file.set_modified(SystemTime::UNIX_EPOCH + Duration::from_secs(secs))
+        };
+        cache.insert(&paths[0], b"aaaa");
+        cache.insert(&paths[1], b"bbbb");
+        set_used(&paths[0], 1)?;
+        set_used(&paths[1], 2)?;
+        assert_eq!(cache.get(&paths[0]), Some(b"aaaa".to_vec()));
+        cache.insert(&paths[2], b"cccc");
+        assert!(paths[0].exists());
+        assert!(!paths[1].exists());
+        assert!(paths[2].exists());
+        cache.insert(&paths[1], &[0; 80]);
+        assert!(!paths[1].exists());
+
+        // bundles identified by a digest that is open to collisions are not
+        // cached, as they could be planted by another archive
+        let xxh3file = workspace.path().join("xxh3.db3");
+        let mut builder = PackBuilder::new()?;
+        builder.set_digest_algorithm(DigestAlgorithm::Xxh3)?;
+        builder.add_dir_all(&basepath)?;
+        builder.finish(&xxh3file)?;
+        let xxh3dir = workspace.path().join("xxh3-cache");
+        let mut reader = PackReader::new(&xxh3file)?;
+        reader.set_cache_size(0);
+        reader.set_cache_dir(&xxh3dir, 1_048_576)?;
+        assert_eq!(reader.read_range("input/one.txt", 4, 3)?, b"one");
+        assert_eq!(fs::read_dir(&xxh3dir)?.count(), 0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_item() -> Result<(), Error> {
+        let workspace = tempfile::tempdir()?;
+        let basepath = workspace.path().join("input");
+        fs::create_dir_all(&basepath)?;
+        fs::write(basepath.join("hello.txt"), "hello world")?;
+        let packfile = workspace.path().join("pack.db3");
+        let mut builder = PackBuilder::new()?;
+        builder.add_dir_all(&basepath)?;
+        builder.finish(&packfile)?;
+
+        let reader = PackReader::new(&packfile)?;
+        let mut ids: HashMap<String, i64> = HashMap::new();
+        for result in reader.entries()? {
+            let entry = result?;
+            ids.insert(entry.name, entry.id);
+        }
+        let mut output: Vec<u8> = vec![];
+        reader.read_item(ids["input/hello.txt"], &mut output)?;
+        assert_eq!(output, b"hello world");
+        output.clear();
+        reader.read_path("input/hello.txt", &mut output)?;
+        assert_eq!(output, b"hello world");
+        let result = reader.read_item(ids["input/"], &mut output);
+        assert!(matches!(result, Err(Error::EntryNotFound(_))));
+        let result = reader.read_item(1000, &mut output);
+        assert!(matches!(result, Err(Error::EntryNotFound(_))));
+        let result = reader.read_path("input", &mut output);
+        assert!(matches!(result, Err(Error::EntryNotFound(_))));
+        Ok(())
+    }
+
+    #[test]
+    fn test_ignore_case() -> Result<(), Error> {
+        let workspace = tempfile::tempdir()?;
+        let basepath = workspace.path().join("Input");
+        fs::create_dir_all(&basepath)?;
+        fs::write(basepath.join("ReadMe.TXT"), "read me")?;
+        fs::write(basepath.join("readme.txt"), "lower")?;
+        fs::write(basepath.join("Other.md"), "other")?;
+        let packfile = workspace.path().join("pack.db3");
+        let mut builder = PackBuilder::new()?;
+        builder.add_dir_all(&basepath)?;
+        builder.finish(&packfile)?;
+
+        let mut reader = PackReader::new(&packfile)?;
+        assert!(reader.read_range("input/OTHER.md", 0, 5).is_err());
+        reader.set_ignore_case(true);
+        assert_eq!(reader.read_range("input/OTHER.md", 0, 5)?, b"other");
+        // exact matches are preferred
+        assert_eq!(reader.read_range("INPUT/readme.txt", 0, 5)?, b"lower");
+        assert_eq!(reader.read_range("input/ReadMe.TXT", 0, 4)?, b"read");
+        Ok(())
+    }
+
+    #[test]
+    fn test_complete_path() -> Result<(), Error> {
+        let workspace = tempfile::tempdir()?;
+        let basepath = workspace.path().join("input");
+        fs::create_dir_all(basepath.join("src"))?;
+        fs::write(basepath.join("src/main.rs"), "fn main() {}")?;
+        fs::write(basepath.join("setup.sh"), "true")?;
+        fs::write(basepath.join("README"), "read me")?;
+        let packfile = workspace.path().join("pack.db3");
+        let mut builder = PackBuilder::new()?;
+        builder.add_dir_all(&basepath)?;
+        builder.finish(&packfile)?;
+
+        let mut reader = PackReader::new(&packfile)?;
+        assert_eq!(reader.complete_path("")?, vec!["input/"]);
+        assert_eq!(reader.complete_path("in")?, vec!["input/"]);
+        assert_eq!(
+            reader.complete_path("input/")?,
+            vec!["input/README", "input/setup.sh", "input/src/"]
+        );
+        assert_eq!(
+            reader.complete_path("input/s")?,
+            vec!["input/setup.sh", "input/src/"]
+        );
+        assert_eq!(
+            reader.complete_path("input/src/m")?,
+            vec!["input/src/main.rs"]
+        );
+        assert!(reader.complete_path("input/r")?.is_empty());
+        assert!(reader.complete_path("input/README/x")?.is_empty());
+        assert!(reader.complete_path("missing/")?.is_empty());
+        reader.set_ignore_case(true);
+        assert_eq!(reader.complete_path("input/r")?, vec!["input/README"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_list_dir() -> Result<(), Error> {
+        let workspace = tempfile::tempdir()?;
+        let basepath = workspace.path().join("input");
+        fs::create_dir_all(basepath.join("sub/deeper"))?;
+        for name in ["c.txt", "a.txt", "e.txt", "b.txt", "d.txt"] {
+            fs::write(basepath.join(name), name)?;
+        }
+        fs::write(basepath.join("sub/deeper/f.txt"), "f")?;
+        let packfile = workspace.path().join("pack.db3");
+        let mut builder = PackBuilder::new()?;
+        builder.add_dir_all(&basepath)?;
+        builder.finish(&packfile)?;
+
+        let reader = PackReader::new(&packfile)?;
+        let names = |path: &str, offset: u64, limit: u64| -> Result<Vec<String>, Error> {
+            let entries = reader.list_dir(path, offset, limit)?;
+            Ok(entries.into_iter().map(|e| e.name).collect())
+        };
+        assert_eq!(names("", 0, 10)?, vec!["input/"]);
+        assert_eq!(
+            names("input", 0, 3)?,
+            vec!["input/a.txt", "input/b.txt", "input/c.txt"]
+        );
+        assert_eq!(
+            names("input/", 3, 3)?,
+            vec!["input/d.txt", "input/e.txt", "input/sub/"]
+        );
+        assert!(names("input", 6, 3)?.is_empty());
+        assert_eq!(names("input/sub", 0, u64::MAX)?, vec!["input/sub/deeper/"]);
+        let entries = reader.list_dir("input/sub/deeper", 0, 10)?;
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].kind, ItemKind::File);
+        assert_eq!(entries[0].name, "input/sub/deeper/f.txt");
+        assert!(matches!(
+            reader.list_dir("input/a.txt", 0, 10),
+            Err(Error::EntryNotFound(_))
+        ));
+        assert!(matches!(
+            reader.list_dir("missing", 0, 10),
+            Err(Error::EntryNotFound(_))
+        ));
+        // each page is read from the index, without scanning or sorting
+        let plan: Vec<String> = reader
+            .conn
+            .prepare(
+                "EXPLAIN QUERY PLAN SELECT id, kind, name FROM item WHERE parent = ?1
+                    ORDER BY name, id LIMIT ?2 OFFSET ?3",
+            )?
+            .query_map([1, 10, 0], |row| row.get(3))?
+            .collect::<Result<_, _>>()?;
+        assert!(plan.iter().any(|step| step.contains("INDEX item_parent")));
+        assert!(!plan.iter().any(|step| step.contains("TEMP B-TREE")));
+        Ok(())
+    }
+
+    #[test]
+    fn test_entries_with() -> Result<(), Error> {
+        let workspace = tempfile::tempdir()?;
+        let basepath = workspace.path().join("input");
+        fs::create_dir_all(basepath.join("docs/manual"))?;
+        fs::create_dir_all(basepath.join("src"))?;
+        fs::write(basepath.join("docs/index.html"), "index page")?;
+        fs::write(basepath.join("docs/manual/intro.html"), "intro")?;
+        fs::write(basepath.join("docs.txt"), "the docs")?;
+        fs::write(basepath.join("src/main.rs"), "fn main() {}")?;
+        let packfile = workspace.path().join("pack.db3");
+        let mut builder = PackBuilder::new()?;
+        builder.add_dir_all(&basepath)?;
+        builder.finish(&packfile)?;
+
+        let reader = PackReader::new(&packfile)?;
+        let names = |options: &ListOptions| -> Result<Vec<String>, Error> {
+            let mut names: Vec<String> = vec![];
+            for result in reader.entries_with(options)? {
+                names.push(result?.name);
+            }
+            Ok(names)
+        };
+        let mut options = ListOptions {
+            prefix: Some("input/docs/".into()),
+            order: ListOrder::Path,
+            ..Default::default()
+        };
+        assert_eq!(
+            names(&options)?,
+            vec![
+                "input/docs/index.html",
+                "input/docs/manual/",
+                "input/docs/manual/intro.html"
+            ]
+        );
+        options.prefix = Some("input/doc".into());
+        options.kind = Some(ItemKind::File);
+        assert_eq!(
+            names(&options)?,
+            vec![
+                "input/docs.txt",
+                "input/docs/index.html",
+                "input/docs/manual/intro.html"
+            ]
+        );
+        options.prefix = None;
+        options.order = ListOrder::Size;
+        options.descending = true;
+        assert_eq!(
+            names(&options)?,
+            vec![
+                "input/src/main.rs",
+                "input/docs/index.html",
+                "input/docs.txt",
+                "input/docs/manual/intro.html"
+            ]
+        );
+        options.prefix = Some("input/missing/".into());
+        assert!(names(&options)?.is_empty());
+        options.prefix = Some("input/docs.txt/".into());
+        assert!(names(&options)?.is_empty());
+        assert_eq!(
+            names(&ListOptions::default())?.len(),
+            reader.entries()?.len()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_query() -> Result<(), Error> {
+        let workspace = tempfile::tempdir()?;
+        let basepath = workspace.path().join("input");
+        fs::create_dir_all(basepath.join("docs"))?;
+        fs::write(basepath.join("docs/index.html"), "index page")?;
+        fs::write(basepath.join("docs/intro.html"), "intro")?;
+        fs::write(basepath.join("main.rs"), "fn main() {}")?;
+        let packfile = workspace.path().join("pack.db3");
+        let mut builder = PackBuilder::new()?;
+        builder.add_dir_all(&basepath)?;
+        builder.finish(&packfile)?;
+
+        let reader = PackReader::new(&packfile)?;
+        let sizes = reader.query(
+            "SELECT item.name, SUM(itemcontent.size) FROM item
+                INNER JOIN itemcontent ON itemcontent.item = item.id
+                WHERE item.name LIKE ?1 GROUP BY item.id ORDER BY item.name",
+            ["%.html"],
+            |row| Ok((row.get::<_, String>(0)?, row.get::<_, u64>(1)?)),
+        )?;
+        assert_eq!(
+            sizes,
+            vec![("index.html".to_owned(), 10), ("intro.html".to_owned(), 5)]
+        );
+        let result = reader.query("DELETE FROM item", [], |row| row.get::<_, i64>(0));
+        assert!(matches!(result, Err(Error::NotReadOnly(_))));
+        assert_eq!(reader.entries()?.len(), 5);
+
+        // statements that SQLite considers read-only, but which attach other
+        // databases, hold locks, or change settings, are refused as well
+        let attached = workspace.path().join("attached.db3");
+        let statements = [
+            format!("ATTACH DATABASE '{}' AS other", attached.display()),
+            "DETACH DATABASE other".to_owned(),
+            "BEGIN EXCLUSIVE".to_owned(),
+            "SAVEPOINT held".to_owned(),
+            "PRAGMA query_only = 0".to_owned(),
+        ];
+        for sql in statements {
+            let result = reader.query(&sql, [], |row| row.get::<_, i64>(0));
+            assert!(matches!(result, Err(Error::NotReadOnly(_))), "{}", sql);
+        }
+        assert!(!attached.exists());
+        // the archive is not left locked, nor is it left read-only
+        let conn = Connection::open(&packfile)?;
+        conn.execute("CREATE TABLE scratch (id INTEGER)", ())?;
+        drop(conn);
+        let count = reader.query("SELECT COUNT(*) FROM item", [], |row| row.get::<_, i64>(0))?;
+        assert_eq!(count, vec![5]);
+        let query_only: bool = reader
+            .conn
+            .pragma_query_value(None, "query_only", |row| row.get(0))?;
+        assert!(!query_only);
+        Ok(())
+    }
+
+    #[test]
+    fn test_extract_subtree() -> Result<(), Error> {
+        let workspace = tempfile::tempdir()?;
+        let basepath = workspace.path().join("input");
+        fs::create_dir_all(basepath.join("docs/manual/empty"))?;
+        fs::create_dir_all(basepath.join("docsx"))?;
+        fs::create_dir_all(basepath.join("src"))?;
+        fs::write(basepath.join("docs/index.html"), "index")?;
+        fs::write(basepath.join("docs/manual/intro.html"), "intro")?;
+        fs::write(basepath.join("docsx/other.html"), "other")?;
+        fs::write(basepath.join("src/main.rs"), "fn main() {}")?;
+        let packfile = workspace.path().join("pack.db3");
+        let mut builder = PackBuilder::new()?;
+        builder.add_dir_all(&basepath)?;
+        builder.finish(&packfile)?;
+
+        let destination = workspace.path().join("output");
+        fs::create_dir(&destination)?;
+        let options = ExtractOptions {
+            destination: destination.clone(),
+            ..Default::default()
+        };
+        let reader = PackReader::new(&packfile)?;
+        assert_eq!(
+            reader.extract_subtree_with("input/docs/", &options)?.files,
+            2
+        );
+        assert_eq!(
+            fs::read_to_string(destination.join("input/docs/manual/intro.html"))?,
+            "intro"
+        );
+        assert!(destination.join("input/docs/manual/empty").is_dir());
+        assert!(destination.join("input/docs/index.html").is_file());
+        assert!(!destination.join("input/docsx").exists());
+        assert!(!destination.join("input/src").exists());
+
+        let result = reader.extract_subtree_with("input/src/main.rs", &options);
+        assert!(matches!(result, Err(Error::EntryNotFound(_))));
+        let result = reader.extract_subtree_with("input/missing", &options);
+        assert!(matches!(result, Err(Error::EntryNotFound(_))));
+        Ok(())
+    }
+
+    #[test]
+    fn test_extract_matching() -> Result<(), Error> {
+        let workspace = tempfile::tempdir()?;
+        let basepath = workspace.path().join("input");
+        fs::create_dir_all(basepath.join("src/util"))?;
+        fs::create_dir_all(basepath.join("docs/manual"))?;
+        fs::write(basepath.join("src/main.rs"), "fn main() {}")?;
+        fs::write(basepath.join("src/util/mod.rs"), "mod util;")?;
+        fs::write(basepath.join("src/notes.txt"), "notes")?;
+        fs::write(basepath.join("docs/manual/intro.html"), "intro")?;
+        fs::write(basepath.join("docs.txt"), "docs")?;
+        fs::write(basepath.join("README.md"), "readme")?;
+        let packfile = workspace.path().join("pack.db3");
+        let mut builder = PackBuilder::new()?;
+        builder.add_dir_all(&basepath)?;
+        builder.finish(&packfile)?;
+
+        let destination = workspace.path().join("output");
+        fs::create_dir(&destination)?;
+        let options = ExtractOptions {
+            destination: destination.clone(),
+            ..Default::default()
+        };
+        let reader = PackReader::new(&packfile)?;
+        let selections = ["input/src/**/*.rs", "input/docs/", "input/README.md"];
+        assert_eq!(
+            reader.extract_matching_with(&selections, &options)?.files,
+            4
+        );
+        let output = destination.join("input");
+        assert_eq!(
+            fs::read_to_string(output.join("src/main.rs"))?,
+            "fn main() {}"
+        );
+        assert!(output.join("src/util/mod.rs").is_file());
+        assert!(output.join("docs/manual/intro.html").is_file());
+        assert_eq!(fs::read_to_string(output.join("README.md"))?, "readme");
+        assert!(!output.join("src/notes.txt").exists());
+        assert!(!output.join("docs.txt").exists());
+
+        // overlapping selections extract each entry once
+        fs::remove_dir_all(&destination)?;
+        fs::create_dir(&destination)?;
+        let selections = ["input/docs/", "input/docs/manual/intro.html"];
+        assert_eq!(
+            reader.extract_matching_with(&selections, &options)?.files,
+            1
+        );
+        assert!(output.join("docs/manual/intro.html").is_file());
+        assert!(!output.join("src").exists());
+
+        // a single wildcard does not match across directories, but a
+        // directory matched by a pattern brings everything beneath it
+        fs::remove_dir_all(&destination)?;
+        fs::create_dir(&destination)?;
+        assert_eq!(
+            reader
+                .extract_matching_with(&["input/*.md"], &options)?
+                .files,
+            1
+        );
+        assert_eq!(
+            reader
+                .extract_matching_with(&["input/do?s"], &options)?
+                .files,
+            1
+        );
+        assert!(output.join("docs/manual/intro.html").is_file());
+        assert!(!output.join("src").exists());
+
+        let result = reader.extract_matching_with(&["input/*.java"], &options);
+        assert!(matches!(result, Err(Error::EntryNotFound(_))));
+        let result = reader.extract_matching_with(&["input/missing"], &options);
+        assert!(matches!(result, Err(Error::EntryNotFound(_))));
+        let result = reader.extract_matching_with(&["input/[a"], &options);
+        assert!(matches!(result, Err(Error::InvalidPattern(_))));
+        Ok(())
+    }
+
+    #[test]
+    fn test_extract_limits() -> Result<(), Error> {
+        let workspace = tempfile::tempdir()?;
+        let basepath = workspace.path().join("input");
+        fs::create_dir_all(basepath.join("a/b/c"))?;
+        fs::write(basepath.join("one.txt"), "one ".repeat(100))?;
+        fs::write(basepath.join("a/two.txt"), "two ".repeat(50))?;
+        fs::write(basepath.join("a/b/three.txt"), "three")?;
+        let packfile = workspace.path().join("pack.db3");
+        let mut builder = PackBuilder::new()?;
+        builder.add_dir_all(&basepath)?;
+        builder.finish(&packfile)?;
+
+        let destination = workspace.path().join("output");
+        fs::create_dir(&destination)?;
+        let options = ExtractOptions {
+            destination: destination.clone(),
+            ..Default::default()
+        };
+        let mut reader = PackReader::new(&packfile)?;
+        let exceeded = [
+            ExtractLimits {
+                max_total_size: Some(604),
+                ..Default::default()
+            },
+            ExtractLimits {
+                max_files: Some(2),
+                ..Default::default()
+            },
+            ExtractLimits {
+                max_file_size: Some(399),
+                ..Default::default()
+            },
+            ExtractLimits {
+                max_depth: Some(3),
+                ..Default::default()
+            },
+        ];
+        for limits in exceeded {
+            reader.set_extract_limits(limits);
+            let result = reader.extract_all_with(&options);
+            assert!(matches!(result, Err(Error::LimitExceeded(_))));
+        }
+        // nothing was written when the limits were exceeded
+        assert_eq!(fs::read_dir(&destination)?.count(), 0);
+
+        // the limits apply only to the entries being extracted
+        reader.set_extract_limits(ExtractLimits {
+            max_total_size: Some(205),
+            max_depth: Some(4),
+            ..Default::default()
+        });
+        assert_eq!(reader.extract_subtree_with("input/a", &options)?.files, 2);
+        reader.set_extract_limits(ExtractLimits {
+            max_total_size: Some(605),
+            max_files: Some(3),
+            max_file_size: Some(400),
+            max_depth: Some(4),
+        });
+        let destination = workspace.path().join("complete");
+        fs::create_dir(&destination)?;
+        let options = ExtractOptions {
+            destination: destination.clone(),
+            ..Default::default()
+        };
+        assert_eq!(reader.extract_all_with(&options)?.files, 3);
+        assert_eq!(
+            fs::read_to_string(destination.join("input/a/b/three.txt"))?,
+            "three"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_extract_report() -> Result<(), Error> {
+        let workspace = tempfile::tempdir()?;
+        let basepath = workspace.path().join("input");
+        fs::create_dir_all(basepath.join("sub"))?;
+        fs::write(basepath.join("one.txt"), "one ".repeat(100))?;
+        fs::write(basepath.join("sub/two.txt"), "two ".repeat(50))?;
+        #[cfg(target_family = "unix")]
+        std::os::unix::fs::symlink("one.txt", basepath.join("link"))?;
+        let packfile = workspace.path().join("pack.db3");
+        let mut builder = PackBuilder::new()?;
+        builder.add_dir_all(&basepath)?;
+        builder.finish(&packfile)?;
+
+        let destination = workspace.path().join("output");
+        fs::create_dir(&destination)?;
+        let mut options = ExtractOptions {
+            destination: destination.clone(),
+            ..Default::default()
+        };
+        let reader = PackReader::new(&packfile)?;
+        let report = reader.extract_all_with(&options)?;
+        assert_eq!(report.files, 2);
+        assert_eq!(report.directories, 2);
+        #[cfg(target_family = "unix")]
+        assert_eq!(report.symlinks, 1);
+        assert_eq!(report.special_files, 0);
+        assert_eq!(report.bytes_written, 600);
+        assert_eq!(report.skipped, 0);
+        assert_eq!(report.overwritten, 0);
+        assert!(report.warnings.is_empty());
+
+        // extracting again replaces the files
+        let report = reader.extract_matching_with(&["input/sub/two.txt"], &options)?;
+        assert_eq!(report.files, 1);
+        assert_eq!(report.bytes_written, 200);
+        assert_eq!(report.overwritten, 1);
+
+        // unless resuming, in which case the complete files are skipped
+        options.resume = true;
+        let report = reader.extract_all_with(&options)?;
+        assert_eq!(report.files, 0);
+        assert_eq!(report.bytes_written, 0);
+        assert_eq!(report.skipped, 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_extract_resume() -> Result<(), Error> {
+        let workspace = tempfile::tempdir()?;
+        let basepath = workspace.path().join("input");
+        fs::create_dir_all(basepath.join("sub"))?;
+        fs::write(basepath.join("one.txt"), "one ".repeat(100))?;
+        fs::write(basepath.join("two.txt"), "two ".repeat(100))?;
+        fs::write(basepath.join("sub/three.txt"), "three ".repeat(100))?;
+        #[cfg(target_family = "unix")]
+        std::os::unix::fs::symlink("one.txt", basepath.join("link"))?;
+        let packfile = workspace.path().join("pack.db3");
+        let mut builder = PackBuilder::new()?;
+        builder.add_dir_all(&basepath)?;
+        builder.finish(&packfile)?;
+
+        // simulate an interrupted extraction: one file is complete, another
+        // was cut short, and one has the right size but the wrong content
+        let destination = workspace.path().join("output");
+        fs::create_dir_all(destination.join("input/sub"))?;
+        fs::write(destination.join("input/one.txt"), "one ".repeat(100))?;
+        fs::write(destination.join("input/two.txt"), "two ".repeat(150))?;
+        fs::write(
+            destination.join("input/sub/three.txt"),
+            "THREE ".repeat(100),
+        )?;
+        #[cfg(target_family = "unix")]
+        std::os::unix::fs::symlink("one.txt", destination.join("input/link"))?;
+        let options = ExtractOptions {
+            destination: destination.clone(),
+            resume: true,
+            ..Default::default()
+        };
+        let reader = PackReader::new(&packfile)?;
+        assert_eq!(reader.extract_all_with(&options)?.files, 2);
+        for name in ["one.txt", "two.txt", "sub/three.txt"] {
+            assert_eq!(
+                fs::read(destination.join("input").join(name))?,
+                fs::read(basepath.join(name))?
+            );
+        }
+        #[cfg(target_family = "unix")]
+        assert_eq!(
+            fs::read_link(destination.join("input/link"))?,
+            PathBuf::from("one.txt")
+        );
+
+        // nothing left to do once everything has been extracted
+        assert_eq!(reader.extract_all_with(&options)?.files, 0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_extract_case_collisions() -> Result<(), Error> {
+        assert_eq!(numbered_path("Makefile", 2), "Makefile (2)");
+        assert_eq!(numbered_path("docs/a.tar.gz", 3), "docs/a.tar (3).gz");
+        assert_eq!(numbered_path("docs/.profile", 2), "docs/.profile (2)");
+        let workspace = tempfile::tempdir()?;
+        let basepath = workspace.path().join("input");
+        fs::create_dir_all(basepath.join("Docs"))?;
+        fs::create_dir_all(basepath.join("docs"))?;
+        fs::create_dir_all(basepath.join("readme"))?;
+        fs::write(basepath.join("Makefile"), "upper")?;
+        fs::write(basepath.join("makefile"), "lower")?;
+        fs::write(basepath.join("makefile (2)"), "taken")?;
+        fs::write(basepath.join("Docs/a.txt"), "upper")?;
+        fs::write(basepath.join("docs/a.txt"), "lower")?;
+        fs::write(basepath.join("README"), "file")?;
+        let packfile = workspace.path().join("pack.db3");
+        let mut builder = PackBuilder::new()?;
+        builder.add_dir_all(&basepath)?;
+        builder.finish(&packfile)?;
+        let reader = PackReader::new(&packfile)?;
+
+        // the temporary directory does not ignore case
+        let destination = workspace.path().join("auto");
+        fs::create_dir(&destination)?;
+        assert!(!ignores_case(&destination));
+        let options = ExtractOptions {
+            destination: destination.clone(),
+            ..Default::default()
+        };
+        let report = reader.extract_all_with(&options)?;
+        assert!(report.warnings.is_empty());
+        assert_eq!(fs::read(destination.join("input/makefile"))?, b"lower");
+
+        let destination = workspace.path().join("renamed");
+        fs::create_dir(&destination)?;
+        let options = ExtractOptions {
+            destination: destination.clone(),
+            case_collisions: CaseCollisions::Rename,
+            ..Default::default()
+        };
+        let report = reader.extract_all_with(&options)?;
+        assert_eq!(report.files, 6);
+        assert_eq!(
+            report.warnings,
+            vec![
+                Warning::CaseCollision("input/docs/a.txt".into(), "input/docs/a (2).txt".into()),
+                Warning::CaseCollision("input/makefile".into(), "input/makefile (3)".into()),
+                Warning::CaseCollision("input/README".into(), "input/README (2)".into()),
+            ]
+        );
+        let read = |path: &str| fs::read_to_string(destination.join(path));
+        assert_eq!(read("input/Makefile")?, "upper");
+        assert_eq!(read("input/makefile (2)")?, "taken");
+        assert_eq!(read("input/makefile (3)")?, "lower");
+        assert_eq!(read("input/Docs/a.txt")?, "upper");
+        assert_eq!(read("input/docs/a (2).txt")?, "lower");
+        assert_eq!(read("input/README (2)")?, "file");
+        assert!(destination.join("input/readme").is_dir());
+        assert!(!destination.join("input/makefile").exists());
+        Ok(())
+    }
+
+    #[test]
+    fn test_extract_verify() -> Result<(), Error> {
+        let workspace = tempfile::tempdir()?;
+        let basepath = workspace.path().join("input");
+        fs::create_dir_all(&basepath)?;
+        fs::write(basepath.join("one.txt"), "one ".repeat(100))?;
+        fs::write(basepath.join("two.txt"), "two ".repeat(100))?;
+        fs::write(basepath.join("empty.txt"), "")?;
+        let large: Vec<u8> = (0..60_000u32).map(|n| (n * 7 % 251) as u8).collect();
+        fs::write(basepath.join("large.bin"), &large)?;
+        let packfile = workspace.path().join("pack.db3");
+        let mut builder = PackBuilder::new()?;
+        builder.set_bundle_size(16_384);
+        builder.add_dir_all(&basepath)?;
+        builder.finish(&packfile)?;
+
+        let destination = workspace.path().join("output");
+        fs::create_dir(&destination)?;
+        let mut options = ExtractOptions {
+            destination: destination.clone(),
+            verify: Some(ErrorPolicy::Fail),
+            ..Default::default()
+        };
+        let reader = PackReader::new(&packfile)?;
+        let report = reader.extract_all_with(&options)?;
+        assert_eq!(report.files, 4);
+        assert!(report.warnings.is_empty());
+        assert_eq!(fs::read(destination.join("input/large.bin"))?, large);
+
+        // a file whose content does not match the recorded checksum
+        let conn = Connection::open(&packfile)?;
+        conn.execute(
+            "UPDATE attrs SET checksum = X'00' WHERE item =
+                (SELECT id FROM item WHERE name = 'two.txt')",
+            (),
+        )?;
+        drop(conn);
+        let reader = PackReader::new(&packfile)?;
+        let two = destination.join("input/two.txt");
+        assert!(matches!(
+            reader.extract_all_with(&options),
+            Err(Error::ChecksumMismatch(path)) if path == two
+        ));
+        options.verify = Some(ErrorPolicy::Warn);
+        let report = reader.extract_all_with(&options)?;
+        assert_eq!(report.files, 4);
+        assert_eq!(
+            report.warnings,
+            vec![Warning::ChecksumMismatch(two.clone())]
+        );
+        assert!(two.exists());
+        options.verify = Some(ErrorPolicy::Skip);
+        let report = reader.extract_all_with(&options)?;
+        assert_eq!(report.files, 3);
+        assert_eq!(report.skipped, 1);
+        assert!(!two.exists());
+        options.verify = None;
+        assert!(reader.extract_all_with(&options)?.warnings.is_empty());
+
+        // pieces written out of order are set aside to be read back
+        let mut verifier = ExtractVerifier::new(DigestAlgorithm::Blake3);
+        let piece = |itempos: u64| IndexedFile {
+            content: 1,
+            contentpos: 0,
+            itempos,
+            size: 10,
+            kind: KIND_FILE,
+            path: "input/file.bin".into(),
+            item: 7,
+            total: Some(20),
+            checksum: Some(vec![0]),
+            parts: 2,
+        };
+        assert!(verifier.hasher_for(&piece(10)).is_none());
+        assert_eq!(verifier.written(&piece(10)), None);
+        assert!(verifier.hasher_for(&piece(0)).is_none());
+        assert_eq!(verifier.unordered.len(), 1);
+        assert!(verifier.pending.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_link_rewrite() {
+        let relative = LinkRewrite::Relative;
+        assert_eq!(relative.apply(b"/etc/hosts", "etc/hosts.link"), b"hosts");
+        assert_eq!(
+            relative.apply(b"/usr/lib/libc.so", "lib/libc.so"),
+            b"../usr/lib/libc.so"
+        );
+        assert_eq!(relative.apply(b"/", "a/b/link"), b"../..");
+        assert_eq!(relative.apply(b"/a", "a/link"), b".");
+        assert_eq!(relative.apply(b"/a/../b", "a/link"), b"../a/../b");
+        assert_eq!(
+            relative.apply(b"already/relative", "a/link"),
+            b"already/relative"
+        );
+        let prefix = LinkRewrite::Prefix {
+            from: "/home/alice".into(),
+            to: "/Users/alice".into(),
+        };
+        assert_eq!(
+            prefix.apply(b"/home/alice/doc", "link"),
+            b"/Users/alice/doc"
+        );
+        assert_eq!(prefix.apply(b"/home/alice", "link"), b"/Users/alice");
+        assert_eq!(
+            prefix.apply(b"/home/alicia/doc", "link"),
+            b"/home/alicia/doc"
+        );
+        assert_eq!(prefix.apply(b"doc", "link"), b"doc");
+        let strip = LinkRewrite::Prefix {
+            from: "/mnt/image".into(),
+            to: "".into(),
+        };
+        assert_eq!(strip.apply(b"/mnt/image/etc", "link"), b"/etc");
+        assert_eq!(strip.apply(b"/mnt/image", "link"), b"/");
+        let root = LinkRewrite::Prefix {
+            from: "/".into(),
+            to: "/sysroot".into(),
+        };
+        assert_eq!(root.apply(b"/etc", "link"), b"/sysroot/etc");
+        assert_eq!(root.apply(b"etc", "link"), b"etc");
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn test_extract_owners() -> Result<(), Error> {
+        use std::os::unix::fs::MetadataExt;
+        let workspace = tempfile::tempdir()?;
+        let basepath = workspace.path().join("input");
+        fs::create_dir_all(&basepath)?;
+        fs::write(basepath.join("one.txt"), "one")?;
+        fs::write(basepath.join("two.txt"), "two")?;
+        let uid = fs::metadata(basepath.join("one.txt"))?.uid();
+        let packfile = workspace.path().join("pack.db3");
+        let mut builder = PackBuilder::new()?;
+        builder.add_dir_all(&basepath)?;
+        builder.finish(&packfile)?;
+
+        let reader = PackReader::new(&packfile)?;
+        let recorded: Vec<Option<u32>> = reader
+            .conn
+            .prepare("SELECT uid FROM attrs")?
+            .query_map([], |row| row.get(0))?
+            .collect::<Result<_, _>>()?;
+        assert!(recorded.iter().all(|id| *id == Some(uid)));
+
+        // only the superuser can give files away
+        if uid != 0 {
+            return Ok(());
+        }
+        let destination = workspace.path().join("output");
+        fs::create_dir(&destination)?;
+        let mut owner_map = OwnerMap::new();
+        owner_map.insert_id(0, 4321);
+        let options = ExtractOptions {
+            destination: destination.clone(),
+            owners: true,
+            owner_map: Some(owner_map),
+            ..Default::default()
+        };
+        reader.extract_all_with(&options)?;
+        let metadata = fs::metadata(destination.join("input/one.txt"))?;
+        assert_eq!(metadata.uid(), 4321);
+        assert_eq!(metadata.gid(), 0);
+
+        // groups may be mapped by their recorded name
+        let mut group_map = OwnerMap::new();
+        group_map.insert_name("root", 4321);
+        let options = ExtractOptions {
+            destination: workspace.path().join("mapped"),
+            owners: true,
+            group_map: Some(group_map),
+            ..Default::default()
+        };
+        fs::create_dir(&options.destination)?;
+        reader.extract_all_with(&options)?;
+        let metadata = fs::metadata(options.destination.join("input/two.txt"))?;
+        assert_eq!(metadata.uid(), 0);
+        assert_eq!(metadata.gid(), 4321);
+
+        // but not when only the numeric identifiers are to be used
+        let options = ExtractOptions {
+            destination: workspace.path().join("numeric"),
+            numeric_owner: true,
+            ..options
+        };
+        fs::create_dir(&options.destination)?;
+        reader.extract_all_with(&options)?;
+        let metadata = fs::metadata(options.destination.join("input/two.txt"))?;
+        assert_eq!(metadata.gid(), 0);
+        Ok(())
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn test_extract_permissions() -> Result<(), Error> {
+        use std::os::unix::fs::PermissionsExt;
+        let workspace = tempfile::tempdir()?;
+        let basepath = workspace.path().join("input");
+        fs::create_dir_all(&basepath)?;
+        let filepath = basepath.join("shared.txt");
+        fs::write(&filepath, "shared")?;
+        fs::set_permissions(&filepath, fs::Permissions::from_mode(0o666))?;
+        let packfile = workspace.path().join("pack.db3");
+        let mut builder = PackBuilder::new()?;
+        builder.add_dir_all(&basepath)?;
+        builder.finish(&packfile)?;
+
+        let reader = PackReader::new(&packfile)?;
+        let destination = workspace.path().join("output");
+        fs::create_dir(&destination)?;
+        let mut options = ExtractOptions {
+            destination: destination.clone(),
+            ..Default::default()
+        };
+        reader.extract_all_with(&options)?;
+        let extracted = destination.join("input/shared.txt");
+        let mode = fs::metadata(&extracted)?.permissions().mode();
+        assert_eq!(mode & 0o777, 0o666);
+
+        options.same_permissions = false;
+        reader.extract_all_with(&options)?;
+        let mode = fs::metadata(&extracted)?.permissions().mode();
+        assert_eq!(mode & 0o777, 0o666 & !current_umask());
+        Ok(())
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn test_extract_directory_metadata() -> Result<(), Error> {
+        use std::os::unix::fs::PermissionsExt;
+        let workspace = tempfile::tempdir()?;
+        let basepath = workspace.path().join("input");
+        fs::create_dir_all(basepath.join("docs/manual"))?;
+        fs::create_dir_all(basepath.join("locked"))?;
+        fs::write(basepath.join("docs/manual/intro.html"), "intro")?;
+        fs::write(basepath.join("locked/secret.txt"), "secret")?;
+        set_dir_mtime(&basepath.join("docs/manual"), 1_000_000_000)?;
+        set_dir_mtime(&basepath.join("docs"), 1_100_000_000)?;
+        fs::set_permissions(basepath.join("locked"), fs::Permissions::from_mode(0o555))?;
+        let packfile = workspace.path().join("pack.db3");
+        let mut builder = PackBuilder::new()?;
+        builder.add_dir_all(&basepath)?;
+        builder.finish(&packfile)?;
+
+        let reader = PackReader::new(&packfile)?;
+        let destination = workspace.path().join("output");
+        fs::create_dir(&destination)?;
+        let options = ExtractOptions {
+            destination: destination.clone(),
+            ..Default::default()
+        };
+        reader.extract_all_with(&options)?;
+        let mtime = |path: &str| -> io::Result<u64> {
+            let modified = fs::metadata(destination.join(path))?.modified()?;
+            Ok(modified
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0))
+        };
+        assert_eq!(mtime("input/docs/manual")?, 1_000_000_000);
+        assert_eq!(mtime("input/docs")?, 1_100_000_000);
+        let locked = destination.join("input/locked");
+        assert_eq!(fs::read(locked.join("secret.txt"))?, b"secret");
+        let mode = fs::metadata(&locked)?.permissions().mode();
+        assert_eq!(mode & 0o777, 0o555);
+
+        // allow the workspace to be removed
+        fs::set_permissions(&locked, fs::Permissions::from_mode(0o755))?;
+        fs::set_permissions(basepath.join("locked"), fs::Permissions::from_mode(0o755))?;
+        Ok(())
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn test_extract_rewrite_links() -> Result<(), Error> {
+        let workspace = tempfile::tempdir()?;
+        let basepath = workspace.path().join("root");
+        fs::create_dir_all(basepath.join("etc"))?;
+        fs::create_dir_all(basepath.join("usr/bin"))?;
+        fs::write(basepath.join("etc/hosts"), "localhost")?;
+        std::os::unix::fs::symlink("/etc/hosts", basepath.join("usr/bin/hosts"))?;
+        std::os::unix::fs::symlink("../etc", basepath.join("usr/etc"))?;
+        let packfile = workspace.path().join("pack.db3");
+        let mut builder = PackBuilder::new()?;
+        builder.add_dir_all(&basepath)?;
+        builder.finish(&packfile)?;
+
+        let destination = workspace.path().join("output");
+        fs::create_dir(&destination)?;
+        let options = ExtractOptions {
+            destination: destination.clone(),
+            rewrite_links: Some(LinkRewrite::Relative),
+            ..Default::default()
+        };
+        let reader = PackReader::new(&packfile)?;
+        reader.extract_all_with(&options)?;
+        assert_eq!(
+            fs::read_link(destination.join("root/usr/bin/hosts"))?,
+            PathBuf::from("../../../etc/hosts")
+        );
+        assert_eq!(
+            fs::read_link(destination.join("root/usr/etc"))?,
+            PathBuf::from("../etc")
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_on_file_extracted() -> Result<(), Error> {
+        use std::sync::Mutex;
+        let workspace = tempfile::tempdir()?;
+        let basepath = workspace.path().join("input");
+        fs::create_dir_all(basepath.join("sub"))?;
+        fs::write(basepath.join("one.txt"), "one")?;
+        fs::write(basepath.join("sub/two.txt"), "two")?;
+        let packfile = workspace.path().join("pack.db3");
+        let mut builder = PackBuilder::new()?;
+        builder.add_dir_all(&basepath)?;
+        builder.finish(&packfile)?;
+
+        let destination = workspace.path().join("output");
+        fs::create_dir(&destination)?;
+        let options = ExtractOptions {
+            destination: destination.clone(),
+            ..Default::default()
+        };
+        // the callback sees each file before its recorded mode is restored
+        let mut builder = PackBuilder::open(&packfile)?;
+        let patch = crate::EntryMetadataPatch {
+            mode: Some(0),
+            ..Default::default()
+        };
+        builder.set_metadata("input/one.txt", &patch)?;
+        builder.commit()?;
+        let seen: Arc<Mutex<Vec<(PathBuf, String)>>> = Arc::new(Mutex::new(vec![]));
+        let mut reader = PackReader::new(&packfile)?;
+        let files = seen.clone();
+        reader.set_on_file_extracted(move |path| {
+            #[cfg(target_family = "unix")]
+            {
+                use std::os::unix::fs::PermissionsExt;
+                assert_ne!(fs::metadata(path)?.permissions().mode() & 0o777, 0);
+            }
+            let content = fs::read_to_string(path)?;
+            files.lock().unwrap().push((path.to_path_buf(), content));
+            Ok(())
+        });
+        assert_eq!(reader.extract_all_with(&options)?.files, 2);
+        #[cfg(target_family = "unix")]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let metadata = fs::metadata(destination.join("input/one.txt"))?;
+            assert_eq!(metadata.permissions().mode() & 0o777, 0);
+        }
+        let mut seen = seen.lock().unwrap().clone();
+        seen.sort();
+        assert_eq!(
+            seen,
+            vec![
+                (destination.join("input/one.txt"), "one".to_owned()),
+                (destination.join("input/sub/two.txt"), "two".to_owned())
+            ]
+        );
+
+        // an error from the callback stops the extraction at that file
+        let destination = workspace.path().join("stopped");
+        fs::create_dir(&destination)?;
+        let options = ExtractOptions {
+            destination: destination.clone(),
+            ..Default::default()
+        };
+        let calls = Arc::new(Mutex::new(0));
+        let counter = calls.clone();
+        reader.set_on_file_extracted(move |path| {
+            *counter.lock().unwrap() += 1;
+            Err(Error::EntryNotFound(path.display().to_string()))
+        });
+        let result = reader.extract_all_with(&options);
+        assert!(matches!(result, Err(Error::EntryNotFound(_))));
+        assert_eq!(*calls.lock().unwrap(), 1);
+        let written = ["input/one.txt", "input/sub/two.txt"]
+            .iter()
+            .filter(|path| destination.join(path).exists())
+            .count();
+        assert_eq!(written, 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_mac_metadata() -> Result<(), Error> {
+        let workspace = tempfile::tempdir()?;
+        let basepath = workspace.path().join("input");
+        fs::create_dir_all(&basepath)?;
+        let filepath = basepath.join("design.psd");
+        fs::write(&filepath, "layers")?;
+        // type and creator codes followed by the Finder flags and so on
+        let mut finder_info: Vec<u8> = b"8BPS8BIM".to_vec();
+        finder_info.resize(32, 0);
+        crate::macos::write_mac_metadata(&filepath, "com.apple.FinderInfo", &finder_info)?;
+        let packfile = workspace.path().join("pack.db3");
+        let mut builder = PackBuilder::new()?;
+        builder.set_mac_metadata(true);
+        builder.add_dir_all(&basepath)?;
+        let recorded: u64 = builder
+            .conn
+            .query_row("SELECT COUNT(*) FROM xattr", [], |row| row.get(0))?;
+        builder.finish(&packfile)?;
+        let expected = if cfg!(target_os = "macos") { 1 } else { 0 };
+        assert_eq!(recorded, expected);
+
+        let destination = workspace.path().join("output");
+        fs::create_dir(&destination)?;
+        let options = ExtractOptions {
+            destination: destination.clone(),
+            mac_metadata: true,
+            ..Default::default()
+        };
+        let reader = PackReader::new(&packfile)?;
+        assert_eq!(reader.extract_all_with(&options)?.files, 1);
+        let extracted = destination.join("input/design.psd");
+        assert_eq!(fs::read_to_string(&extracted)?, "layers");
+        let restored = crate::macos::read_mac_metadata(&extracted)?;
+        if cfg!(target_os = "macos") {
+            assert_eq!(
+                restored,
+                vec![("com.apple.FinderInfo".to_owned(), finder_info)]
+            );
+        } else {
+            assert!(restored.is_empty());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_info() -> Result<(), Error> {
+        let workspace = tempfile::tempdir()?;
+        let basepath = workspace.path().join("input");
+        fs::create_dir_all(&basepath)?;
+        fs::write(basepath.join("one.txt"), "one ".repeat(1000))?;
+        let packfile = workspace.path().join("pack.db3");
+        let mut builder = PackBuilder::new()?;
+        builder.set_reproducible(1_709_210_096);
+        builder.add_dir_all(&basepath)?;
+        builder.finish(&packfile)?;
+
+        let reader = PackReader::new(&packfile)?;
+        let info = reader.info()?;
+        assert_eq!(info.format_version, 1);
+        assert_eq!(info.application_id, 0x5041434b);
+        assert_eq!(info.items, 2);
+        assert_eq!(info.bundles, 1);
+        assert_eq!(info.uncompressed, 4000);
+        assert!(info.compressed > 0 && info.compressed < info.uncompressed);
+        assert_eq!(info.codecs, vec!["zstd"]);
+        assert_eq!(info.layout, None);
+        assert_eq!(info.encryption, None);
+        assert_eq!(info.created.as_deref(), Some("2024-02-29T12:34:56Z"));
+        assert!(info.creator.unwrap().starts_with("pack-rs "));
+
+        // updating the archive keeps the original creation time
+        fs::write(workspace.path().join("two.txt"), "two")?;
+        let mut builder = PackBuilder::open(&packfile)?;
+        builder.add_file(workspace.path().join("two.txt"), 0)?;
+        builder.commit()?;
+        let reader = PackReader::new(&packfile)?;
+        let info = reader.info()?;
+        assert_eq!(info.created.as_deref(), Some("2024-02-29T12:34:56Z"));
+        assert_eq!(info.bundles, 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_encrypted_content() -> Result<(), Error> {
+        let workspace = tempfile::tempdir()?;
+        let basepath = workspace.path().join("input");
+        fs::create_dir_all(&basepath)?;
+        fs::write(basepath.join("secret.txt"), "secret ".repeat(20_000))?;
+        let packfile = workspace.path().join("pack.db3");
+        let mut builder = PackBuilder::new()?;
+        builder.encrypt_content("hunter2")?;
+        builder.add_dir_all(&basepath)?;
+        builder.finish(&packfile)?;
+
+        // the catalog can be browsed without the password
+        let mut reader = PackReader::new(&packfile)?;
+        assert!(reader.is_encrypted());
+        assert_eq!(reader.info()?.encryption.as_deref(), Some("content-v2"));
+        assert!(reader.contains("input/secret.txt")?);
+        let entries = reader.export_entries()?;
+        let secret = entries.iter().find(|e| e.kind == KIND_FILE).unwrap();
+        assert_eq!(secret.size, 140_000);
+        let raw: Vec<u8> =
+            reader
+                .conn
+                .query_row("SELECT value FROM content LIMIT 1", [], |row| row.get(0))?;
+        assert!(!raw.windows(7).any(|w| w == b"secret "));
+
+        // but the content cannot be read without it
+        let mut content: Vec<u8> = vec![];
+        assert!(matches!(
+            reader.read_path("input/secret.txt", &mut content),
+            Err(Error::PasswordRequired)
+        ));
+        assert!(matches!(
+            reader.set_password("hunter3"),
+            Err(Error::WrongPassword)
+        ));
+        reader.set_password("hunter2")?;
+        reader.read_path("input/secret.txt", &mut content)?;
+        assert_eq!(content, "secret ".repeat(20_000).as_bytes());
+
+        // adding content requires the password as well
+        fs::write(workspace.path().join("more.txt"), "more")?;
+        let mut builder = PackBuilder::open(&packfile)?;
+        assert!(builder.is_encrypted());
+        assert!(matches!(
+            builder.add_file(workspace.path().join("more.txt"), 0),
+            Err(Error::PasswordRequired)
+        ));
+        drop(builder);
+        let mut builder = PackBuilder::open(&packfile)?;
+        builder.encrypt_content("hunter2")?;
+        builder.add_file(workspace.path().join("more.txt"), 0)?;
+        builder.commit()?;
+
+        // unencrypted content cannot be mixed in by merging
+        let plainfile = workspace.path().join("plain.db3");
+        let mut builder = PackBuilder::new()?;
+        builder.add_file(workspace.path().join("more.txt"), 0)?;
+        builder.finish(&plainfile)?;
+        let mut builder = PackBuilder::new()?;
+        assert_eq!(builder.merge_archive(&packfile, Default::default())?, 2);
+        assert!(builder.is_encrypted());
+        assert!(matches!(
+            builder.merge_archive(&plainfile, Default::default()),
+            Err(Error::EncryptionMismatch(_))
+        ));
+        let mut builder = PackBuilder::open(&plainfile)?;
+        assert!(matches!(
+            builder.encrypt_content("hunter2"),
+            Err(Error::EncryptionMismatch(_))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_encrypted_bundles_bound() -> Result<(), Error> {
+        let workspace = tempfile::tempdir()?;
+        fs::write(workspace.path().join("one.txt"), "one ".repeat(100))?;
+        fs::write(workspace.path().join("two.txt"), "two ".repeat(100))?;
+        fs::write(workspace.path().join("three.txt"), "three")?;
+        let packfile = workspace.path().join("pack.db3");
+        let mut builder = PackBuilder::new()?;
+        builder.encrypt_content("hunter2")?;
+        builder.add_file(workspace.path().join("one.txt"), 0)?;
+        builder.finish(&packfile)?;
+        let mut builder = PackBuilder::open(&packfile)?;
+        builder.encrypt_content("hunter2")?;
+        builder.add_file(workspace.path().join("two.txt"), 0)?;
+        builder.commit()?;
+
+        // bundles copied with a merge are encrypted anew for their new rows
+        let otherfile = workspace.path().join("other.db3");
+        fs::copy(&packfile, &otherfile)?;
+        let mut builder = PackBuilder::open(&otherfile)?;
+        builder.encrypt_content("hunter2")?;
+        builder.add_file(workspace.path().join("three.txt"), 0)?;
+        builder.commit()?;
+        let mergedfile = workspace.path().join("merged.db3");
+        let mut builder = PackBuilder::new()?;
+        builder.merge_archive(&packfile, Default::default())?;
+        builder.encrypt_content("hunter2")?;
+        builder.merge_archive(&otherfile, crate::MergePolicy::KeepFirst)?;
+        builder.finish(&mergedfile)?;
+        let mut reader = PackReader::new(&mergedfile)?;
+        reader.set_password("hunter2")?;
+        let mut content: Vec<u8> = vec![];
+        reader.read_path("three.txt", &mut content)?;
+        assert_eq!(content, b"three");
+
+        // swapping the bundles of two rows is detected
+        let conn = Connection::open(&packfile)?;
+        for (from, to) in [(1, 0), (2, 1), (0, 2)] {
+            conn.execute("UPDATE content SET id = ?2 WHERE id = ?1", [from, to])?;
+        }
+        conn.execute(
+            "UPDATE itemcontent SET content = CASE content WHEN 1 THEN 2 ELSE 1 END",
+            (),
+        )?;
+        drop(conn);
+        let mut reader = PackReader::new(&packfile)?;
+        reader.set_password("hunter2")?;
+        let mut content: Vec<u8> = vec![];
+        assert!(reader.read_path("one.txt", &mut content).is_err());
+
+        // the unbound form cannot be claimed by changing the entry alone
+        let conn = Connection::open(&packfile)?;
+        conn.execute(
+            "UPDATE archive_meta SET value = 'content' WHERE name = 'encryption'",
+            (),
+        )?;
+        drop(conn);
+        let mut reader = PackReader::new(&packfile)?;
+        assert!(matches!(
+            reader.set_password("hunter2"),
+            Err(Error::EncryptionMismatch(_))
+        ));
+        let mut builder = PackBuilder::open(&packfile)?;
+        assert!(matches!(
+            builder.encrypt_content("hunter2"),
+            Err(Error::EncryptionMismatch(_))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_change_password() -> Result<(), Error> {
+        let workspace = tempfile::tempdir()?;
+        let basepath = workspace.path().join("input");
+        fs::create_dir_all(&basepath)?;
+        fs::write(basepath.join("secret.txt"), "secret")?;
+        let packfile = workspace.path().join("pack.db3");
+        let mut builder = PackBuilder::new()?;
+        builder.encrypt_content("hunter2")?;
+        builder.add_dir_all(&basepath)?;
+        builder.finish(&packfile)?;
+        let bundles = || -> Result<Vec<Vec<u8>>, Error> {
+            let reader = PackReader::new(&packfile)?;
+            let mut stmt = reader.conn.prepare("SELECT value FROM content")?;
+            let values = stmt
+                .query_map([], |row| row.get(0))?
+                .collect::<Result<Vec<Vec<u8>>, _>>()?;
+            Ok(values)
+        };
+        let before = bundles()?;
+
+        let mut builder = PackBuilder::open(&packfile)?;
+        assert!(matches!(
+            builder.change_password("hunter3", "swordfish"),
+            Err(Error::WrongPassword)
+        ));
+        let wrapped: String = PackReader::new(&packfile)?.conn.query_row(
+            "SELECT value FROM archive_meta WHERE name = ?1",
+            [crate::crypto::KEY_ENTRY],
+            |row| row.get(0),
+        )?;
+        builder.change_password("hunter2", "swordfish")?;
+        builder.commit()?;
+        drop(builder);
+
+        // the old wrapped key is not left in a free page of the file
+        let raw = fs::read(&packfile)?;
+        assert!(!raw
+            .windows(wrapped.len())
+            .any(|window| window == wrapped.as_bytes()));
+        // the content was not encrypted again
+        assert_eq!(bundles()?, before);
+        let mut reader = PackReader::new(&packfile)?;
+        assert!(matches!(
+            reader.set_password("hunter2"),
+            Err(Error::WrongPassword)
+        ));
+        reader.set_password("swordfish")?;
+        let mut content: Vec<u8> = vec![];
+        reader.read_path("input/secret.txt", &mut content)?;
+        assert_eq!(content, b"secret");
+        let history = reader.history()?;
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0].operation, "rekey");
+        assert!(history[0].paths.is_empty());
+
+        let plainfile = workspace.path().join("plain.db3");
+        let mut builder = PackBuilder::new()?;
+        builder.add_dir_all(&basepath)?;
+        builder.finish(&plainfile)?;
+        let mut builder = PackBuilder::open(&plainfile)?;
+        assert!(matches!(
+            builder.change_password("hunter2", "swordfish"),
+            Err(Error::EncryptionMismatch(_))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_len_and_contains() -> Result<(), Error> {
+        let workspace = tempfile::tempdir()?;
+        let basepath = workspace.path().join("input");
+        fs::create_dir_all(basepath.join("sub"))?;
+        fs::write(basepath.join("one.txt"), "one")?;
+        fs::write(basepath.join("sub/two.txt"), "two")?;
+        let packfile = workspace.path().join("pack.db3");
+        let mut builder = PackBuilder::new()?;
+        builder.add_dir_all(&basepath)?;
+        builder.finish(&packfile)?;
+
+        let mut reader = PackReader::new(&packfile)?;
+        assert_eq!(reader.len()?, 4);
+        assert_eq!(reader.len()?, reader.entries()?.len() as u64);
+        assert!(!reader.is_empty()?);
+        assert!(reader.contains("input/one.txt")?);
+        assert!(reader.contains("input/sub")?);
+        assert!(reader.contains("input/sub/")?);
+        assert!(!reader.contains("input/one.txt/two.txt")?);
+        assert!(!reader.contains("input/ONE.txt")?);
+        reader.set_ignore_case(true);
+        assert!(reader.contains("input/ONE.txt")?);
+
+        let emptyfile = workspace.path().join("empty.db3");
+        PackBuilder::new()?.finish(&emptyfile)?;
+        let reader = PackReader::new(&emptyfile)?;
+        assert_eq!(reader.len()?, 0);
+        assert!(reader.is_empty()?);
+        assert!(!reader.contains("input")?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_type_report() -> Result<(), Error> {
+        let workspace = tempfile::tempdir()?;
+        let basepath = workspace.path().join("input");
+        fs::create_dir_all(basepath.join("docs.d"))?;
+        fs::write(basepath.join("one.txt"), "one")?;
+        fs::write(basepath.join("docs.d/TWO.TXT"), "two two")?;
+        fs::write(basepath.join("README"), "readme")?;
+        fs::write(basepath.join(".hidden"), [0, 1, 2])?;
+        let mut image: Vec<u8> = b"\x89PNG\r\n\x1a\n".to_vec();
+        image.extend_from_slice(&[0; 100]);
+        fs::write(basepath.join("image.png"), &image)?;
+        let packfile = workspace.path().join("pack.db3");
+        let mut builder = PackBuilder::new()?;
+        builder.add_dir_all(&basepath)?;
+        builder.finish(&packfile)?;
+
+        let reader = PackReader::new(&packfile)?;
+        let report = reader.type_report()?;
+        let stats = |name: Option<&str>, files: u64, bytes: u64| TypeStats {
+            name: name.map(|n| n.to_owned()),
+            files,
+            bytes,
+        };
+        assert_eq!(
+            report.extensions,
+            vec![
+                stats(Some("png"), 1, 108),
+                stats(Some("txt"), 2, 10),
+                stats(None, 2, 9),
+            ]
+        );
+        assert_eq!(
+            report.mime_types,
+            vec![
+                stats(Some("image/png"), 1, 108),
+                stats(Some("text/plain"), 3, 16),
+                stats(None, 1, 3),
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_space_report() -> Result<(), Error> {
+        let workspace = tempfile::tempdir()?;
+        let basepath = workspace.path().join("input");
+        fs::create_dir_all(basepath.join("sub"))?;
+        fs::write(basepath.join("one.txt"), "one")?;
+        fs::write(basepath.join("three.txt"), "three ".repeat(10))?;
+        fs::write(basepath.join("sub/two.txt"), "two ".repeat(25))?;
+        fs::write(basepath.join("sub/copy.txt"), "two ".repeat(25))?;
+        let packfile = workspace.path().join("pack.db3");
+        let mut builder = PackBuilder::new()?;
+        builder.set_content_addressed(true)?;
+        builder.set_codec(Box::new(crate::StoredCodec));
+        builder.add_dir_all(&basepath)?;
+        builder.finish(&packfile)?;
+
+        let reader = PackReader::new(&packfile)?;
+        let report = reader.space_report()?;
+        // the copy shares the content of the original
+        assert_eq!(report.bundles.len(), 1);
+        let bundle = &report.bundles[0];
+        assert_eq!(bundle.id, 1);
+        assert_eq!(bundle.uncompressed, 163);
+        // stored content is not compressed
+        assert_eq!(bundle.compressed, 163);
+        assert_eq!(report.dedup_savings, 100);
+        let mut largest = report.largest.clone();
+        largest[..2].sort();
+        assert_eq!(
+            largest,
+            vec![
+                ("input/sub/copy.txt".to_owned(), 100),
+                ("input/sub/two.txt".to_owned(), 100),
+                ("input/three.txt".to_owned(), 60),
+                ("input/one.txt".to_owned(), 3),
+            ]
+        );
+
+        // compressed bundles report the length of the blob
+        let packfile = workspace.path().join("zstd.db3");
+        let mut builder = PackBuilder::new()?;
+        builder.add_dir_all(&basepath)?;
+        builder.finish(&packfile)?;
+        let reader = PackReader::new(&packfile)?;
+        let blob_len: u64 = reader.conn.query_row(
+            "SELECT LENGTH(value) FROM content WHERE id = 1",
+            [],
+            |row| row.get(0),
+        )?;
+        let report = reader.space_report()?;
+        assert_eq!(report.bundles.len(), 1);
+        assert_eq!(report.bundles[0].compressed, blob_len);
+        assert!(blob_len < 263);
+        assert_eq!(report.bundles[0].uncompressed, 263);
+        assert_eq!(report.dedup_savings, 0);
+        drop(reader);
+
+        // the uncompressed size is derived for archives that do not record it
+        let conn = Connection::open(&packfile)?;
+        conn.execute("UPDATE content SET rawsize = NULL", [])?;
+        drop(conn);
+        let reader = PackReader::new(&packfile)?;
+        assert_eq!(reader.space_report()?.bundles[0].uncompressed, 263);
+        Ok(())
+    }
+
+    #[test]
+    fn test_bundle_entries() -> Result<(), Error> {
+        let workspace = tempfile::tempdir()?;
+        let basepath = workspace.path().join("input");
+        fs::create_dir_all(basepath.join("sub"))?;
+        fs::write(basepath.join("one.txt"), "one")?;
+        fs::write(basepath.join("three.txt"), "three ".repeat(10))?;
+        fs::write(basepath.join("sub/two.txt"), "two ".repeat(25))?;
+        fs::write(basepath.join("sub/copy.txt"), "two ".repeat(25))?;
+        let packfile = workspace.path().join("pack.db3");
+        let mut builder = PackBuilder::new()?;
+        builder.set_content_addressed(true)?;
+        builder.set_bundle_size(64);
+        builder.add_dir_all(&basepath)?;
+        builder.finish(&packfile)?;
+
+        // the first bundle holds three.txt, one.txt, and the start of
+        // two.txt, whose remainder spans the other two bundles; the copy
+        // shares those same portions
+        let entries = |reader: &PackReader| -> Result<Vec<u64>, Error> {
+            let report = reader.space_report()?;
+            Ok(report.bundles.iter().map(|b| b.entries).collect())
+        };
+        let reader = PackReader::new(&packfile)?;
+        assert_eq!(entries(&reader)?, vec![3, 1, 1]);
+        let mut stmt = reader
+            .conn
+            .prepare("SELECT entries FROM content ORDER BY id")?;
+        let recorded = stmt
+            .query_map([], |row| row.get(0))?
+            .collect::<Result<Vec<u64>, _>>()?;
+        assert_eq!(recorded, vec![3, 1, 1]);
+        drop(stmt);
+        drop(reader);
+
+        // the counts are derived for archives that do not record them
+        let conn = Connection::open(&packfile)?;
+        conn.execute("UPDATE content SET entries = NULL", [])?;
+        drop(conn);
+        let reader = PackReader::new(&packfile)?;
+        assert_eq!(entries(&reader)?, vec![3, 1, 1]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_duplicates() -> Result<(), Error> {
+        let workspace = tempfile::tempdir()?;
+        let basepath = workspace.path().join("input");
+        fs::create_dir_all(basepath.join("copy"))?;
+        fs::write(basepath.join("a.txt"), "small")?;
+        fs::write(basepath.join("copy/a.txt"), "small")?;
+        fs::write(basepath.join("big.txt"), "large ".repeat(100))?;
+        fs::write(basepath.join("copy/big.txt"), "large ".repeat(100))?;
+        fs::write(basepath.join("copy/big2.txt"), "large ".repeat(100))?;
+        fs::write(basepath.join("unique.txt"), "unique")?;
+        fs::write(basepath.join("empty1"), "")?;
+        fs::write(basepath.join("empty2"), "")?;
+        let packfile = workspace.path().join("pack.db3");
+        let mut builder = PackBuilder::new()?;
+        builder.add_dir_all(&basepath)?;
+        builder.finish(&packfile)?;
+
+        let reader = PackReader::new(&packfile)?;
+        let sets = reader.duplicates()?;
+        assert_eq!(sets.len(), 2);
+        assert_eq!(
+            sets[0].paths,
+            vec!["input/big.txt", "input/copy/big.txt", "input/copy/big2.txt"]
+        );
+        assert_eq!(sets[0].size, 600);
+        assert_eq!(sets[0].reclaimable(), 1200);
+        assert_eq!(
+            sets[0].checksum,
+            blake3::hash("large ".repeat(100).as_bytes()).as_bytes()
+        );
+        assert_eq!(sets[1].paths, vec!["input/a.txt", "input/copy/a.txt"]);
+        assert_eq!(sets[1].reclaimable(), 5);
+        Ok(())
+    }
+}
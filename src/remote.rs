@@ -0,0 +1,228 @@
+//
+// Copyright (c) 2024 Nathan Fiedler
+//
+use crate::{Error, PackReader, RemoteSource};
+use std::io::{self, Read};
+use std::time::Duration;
+
+///
+/// Reads an archive over HTTP using range requests, which works with any
+/// server that supports them, including S3 and GCS (by way of a public or
+/// presigned URL).
+///
+/// Each request is made conditional on the `ETag` (or failing that, the
+/// `Last-Modified` time) returned when the source was constructed, such that
+/// reading fails if the archive is replaced on the server, rather than
+/// mixing pages of two different databases.
+///
+/// Requires the `remote` feature.
+///
+pub struct HttpSource {
+    agent: ureq::Agent,
+    url: String,
+    size: u64,
+    // strong entity tag of the archive, if the server provided one
+    etag: Option<String>,
+    // modification time of the archive, if the server provided one
+    last_modified: Option<String>,
+}
+
+impl HttpSource {
+    ///
+    /// Construct a source that reads from the given URL, requesting the first
+    /// byte to learn the size of the archive. Returns an error if the server
+    /// does not support range requests.
+    ///
+    pub fn new(url: &str) -> Result<Self, Error> {
+        let agent: ureq::Agent = ureq::Agent::config_builder()
+            .timeout_global(Some(Duration::from_secs(60)))
+            .build()
+            .into();
+        let response = agent.get(url).header("Range", "bytes=0-0").call()?;
+        let size = range_total(&response)
+            .filter(|_| response.status() == 206)
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    "server does not support range requests",
+                )
+            })?;
+        let header = |name: &str| {
+            response
+                .headers()
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(|value| value.to_owned())
+        };
+        // weak tags cannot be used with If-Match
+        let etag = header("etag").filter(|tag| !tag.starts_with("W/"));
+        let last_modified = header("last-modified");
+        Ok(Self {
+            agent,
+            url: url.to_owned(),
+            size,
+            etag,
+            last_modified,
+        })
+    }
+}
+
+// Return the total size of the resource given in the Content-Range header.
+fn range_total(response: &ureq::http::Response<ureq::Body>) -> Option<u64> {
+    response
+        .headers()
+        .get("content-range")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.rsplit('/').next())
+        .and_then(|total| total.parse::<u64>().ok())
+}
+
+impl RemoteSource for HttpSource {
+    fn size(&self) -> io::Result<u64> {
+        Ok(self.size)
+    }
+
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() || offset >= self.size {
+            return Ok(0);
+        }
+        let last = (offset + buf.len() as u64).min(self.size) - 1;
+        let mut request = self
+            .agent
+            .get(&self.url)
+            .header("Range", format!("bytes={}-{}", offset, last));
+        if let Some(etag) = self.etag.as_ref() {
+            request = request.header("If-Match", etag);
+        } else if let Some(modified) = self.last_modified.as_ref() {
+            request = request.header("If-Unmodified-Since", modified);
+        }
+        let mut response = request.call().map_err(|err| match err {
+            ureq::Error::StatusCode(412) => archive_changed(),
+            err => io::Error::other(err),
+        })?;
+        // anything other than the requested range of the same archive means
+        // the archive was replaced or the server ignored the range
+        if response.status() != 206 || range_total(&response) != Some(self.size) {
+            return Err(archive_changed());
+        }
+        let mut reader = response.body_mut().as_reader();
+        let mut count: usize = 0;
+        while count < buf.len() {
+            let read = reader.read(&mut buf[count..])?;
+            if read == 0 {
+                break;
+            }
+            count += read;
+        }
+        Ok(count)
+    }
+}
+
+// Error for when the archive on the server is not the one that was opened.
+fn archive_changed() -> io::Error {
+    io::Error::other("archive changed on the server since it was opened")
+}
+
+impl PackReader {
+    ///
+    /// Construct a `PackReader` that reads the archive from the given source,
+    /// fetching only those portions of the database that are needed to list
+    /// the entries or read the selected files. The archive is treated as
+    /// read-only and immutable.
+    ///
+    /// Requires the `remote` feature.
+    ///
+    pub fn open_remote(source: Box<dyn RemoteSource>) -> Result<Self, Error> {
+        Self::open_source(source)
+    }
+
+    ///
+    /// Construct a `PackReader` that reads the archive at the given HTTP or
+    /// HTTPS URL using range requests, as with `open_remote()`.
+    ///
+    /// Requires the `remote` feature.
+    ///
+    pub fn open_url(url: &str) -> Result<Self, Error> {
+        Self::open_remote(Box::new(HttpSource::new(url)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vfs::tests::build_archive;
+    use std::fs;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::Arc;
+
+    #[test]
+    fn test_open_url() -> Result<(), Error> {
+        let workspace = tempfile::tempdir()?;
+        let packfile = build_archive(workspace.path())?;
+        let data = fs::read(&packfile)?;
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let url = format!("http://{}/pack.db3", listener.local_addr()?);
+        // version of the archive on the server, which is its entity tag
+        let version = Arc::new(AtomicU64::new(1));
+        let current = version.clone();
+        // serve range requests until the test finishes
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let Ok(mut stream) = stream else {
+                    return;
+                };
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut range: Option<(usize, usize)> = None;
+                let mut if_match: Option<String> = None;
+                let mut line = String::new();
+                while reader.read_line(&mut line).unwrap_or(0) > 0 {
+                    let etag = format!("\"v{}\"", current.load(Ordering::Relaxed));
+                    if line == "\r\n" {
+                        let (start, end) = range.take().unwrap();
+                        if if_match.take().is_some_and(|tag| tag != etag) {
+                            let header = "HTTP/1.1 412 Precondition Failed\r\n\
+                                Content-Length: 0\r\n\r\n";
+                            stream.write_all(header.as_bytes()).unwrap();
+                            line.clear();
+                            continue;
+                        }
+                        let end = end.min(data.len() - 1);
+                        let header = format!(
+                            "HTTP/1.1 206 Partial Content\r\nContent-Length: {}\r\n\
+                            Content-Range: bytes {}-{}/{}\r\nETag: {}\r\n\r\n",
+                            end - start + 1,
+                            start,
+                            end,
+                            data.len(),
+                            etag
+                        );
+                        stream.write_all(header.as_bytes()).unwrap();
+                        stream.write_all(&data[start..=end]).unwrap();
+                    } else if let Some(value) = line.to_ascii_lowercase().strip_prefix("range:") {
+                        let (start, end) = value
+                            .trim()
+                            .trim_start_matches("bytes=")
+                            .split_once('-')
+                            .unwrap();
+                        range = Some((start.parse().unwrap(), end.parse().unwrap()));
+                    } else if line.to_ascii_lowercase().starts_with("if-match:") {
+                        if_match = Some(line[9..].trim().to_owned());
+                    }
+                    line.clear();
+                }
+            }
+        });
+        let reader = PackReader::open_url(&url)?;
+        assert_eq!(reader.read_range("input/two/hello.txt", 0, 5)?, b"hello");
+        let entries = reader.entries()?;
+        assert_eq!(entries.len(), 38);
+
+        // replacing the archive on the server causes reading to fail
+        let reader = PackReader::open_url(&url)?;
+        version.store(2, Ordering::Relaxed);
+        assert!(reader.read_range("input/one/0.bin", 0, 16).is_err());
+        Ok(())
+    }
+}
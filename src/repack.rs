@@ -0,0 +1,694 @@
+//
+// Copyright (c) 2024 Nathan Fiedler
+//
+use crate::digest::Hasher;
+use crate::owner::Owner;
+use crate::{Error, ItemKind, ListOptions, PackBuilder, PackReader, StoredCodec, ZstdCodec};
+use std::collections::{HashMap, HashSet};
+
+// Attributes of an item in the archive being repacked.
+#[derive(Default)]
+struct SourceAttrs {
+    mtime: Option<i64>,
+    mode: Option<u32>,
+    checksum: Option<Vec<u8>>,
+    devmajor: Option<u32>,
+    devminor: Option<u32>,
+    mime: Option<String>,
+    owner: Owner,
+}
+
+///
+/// Add every entry of the archive being read to the builder, decompressing
+/// the content of each file and compressing it anew using the settings of the
+/// builder, such as the codec, bundle size, and content-addressed layout.
+/// The modification times, permissions, and other recorded metadata are
+/// carried over, as are the file checksums if the builder uses the same
+/// digest algorithm as the archive, otherwise they are computed anew.
+/// Returns the number of files that were added.
+///
+/// The content of an encrypted archive is only written to a builder that
+/// encrypts its content as well, see `PackBuilder::encrypt_content()`,
+/// otherwise `Error::EncryptionMismatch` is returned.
+///
+/// **Note:** Remember to call `finish()` on the builder when done.
+///
+pub fn repack(reader: &PackReader, builder: &mut PackBuilder) -> Result<u64, Error> {
+    repack_entries(reader, builder, None, None)
+}
+
+///
+/// Like `repack()`, except that the content bundles that were compressed
+/// with the same codec as the builder uses are copied as they are, rather
+/// than being decompressed and compressed again, such that the operation is
+/// limited by the speed of the disk rather than the processor. Only the
+/// codec identifier is compared, not the compression level. Each bundle is
+/// checked against its checksum before it is copied. The bundles of an
+/// encrypted archive are decrypted and encrypted anew with the key of the
+/// builder, again without decompressing them.
+///
+/// Files whose content is in bundles written by another codec are compressed
+/// anew, as are all files when the builder uses the content-addressed
+/// layout, which requires the content of each file to be considered on its
+/// own.
+///
+/// **Note:** Remember to call `finish()` on the builder when done.
+///
+pub fn repack_bundles(reader: &PackReader, builder: &mut PackBuilder) -> Result<u64, Error> {
+    let copier = if builder.content_addressed || builder.metadata_only {
+        None
+    } else {
+        Some(BundleCopier::new(reader, builder)?)
+    };
+    repack_entries(reader, builder, copier, None)
+}
+
+///
+/// Like `repack_bundles()`, except that only the entry at the root of the
+/// archive with the given name, and everything within it, is added to the
+/// builder, as when splitting a large archive into one archive for each
+/// top-level directory. Bundles that also hold the content of entries
+/// outside of that are not copied; those files are compressed anew, such
+/// that the new archive holds nothing of the other entries.
+///
+/// Returns `Error::EntryNotFound` if there is no such entry at the root.
+///
+/// **Note:** Remember to call `finish()` on the builder when done.
+///
+pub fn repack_subtree(
+    reader: &PackReader,
+    builder: &mut PackBuilder,
+    name: &str,
+) -> Result<u64, Error> {
+    let root_id: i64 = reader
+        .conn
+        .query_row(
+            "SELECT id FROM item WHERE parent = 0 AND name = ?1",
+            [name],
+            |row| row.get(0),
+        )
+        .map_err(|err| match err {
+            rusqlite::Error::QueryReturnedNoRows => Error::EntryNotFound(name.to_owned()),
+            err => err.into(),
+        })?;
+    let copier = if builder.content_addressed || builder.metadata_only {
+        None
+    } else {
+        let mut copier = BundleCopier::new(reader, builder)?;
+        copier.shared = shared_bundles(reader, root_id)?;
+        Some(copier)
+    };
+    repack_entries(reader, builder, copier, Some(root_id))
+}
+
+// Return the rowids of the bundles that hold content of entries both within
+// and outside of the subtree with the given root.
+fn shared_bundles(reader: &PackReader, root_id: i64) -> Result<HashSet<i64>, Error> {
+    let mut stmt = reader.conn.prepare(
+        "WITH RECURSIVE T AS (
+    SELECT id FROM item WHERE id = ?1
+    UNION ALL
+    SELECT item.id FROM item INNER JOIN T ON item.parent = T.id
+)
+SELECT DISTINCT content FROM itemcontent
+    WHERE item NOT IN (SELECT id FROM T)
+        AND content IN (SELECT content FROM itemcontent WHERE item IN (SELECT id FROM T))",
+    )?;
+    let shared = stmt
+        .query_map([&root_id], |row| row.get(0))?
+        .collect::<Result<HashSet<i64>, _>>()?;
+    Ok(shared)
+}
+
+// Add every entry of the archive to the builder, or only the entry at the root
+// with the given rowid and those within it, copying the bundles that can be
+// copied, if given a means to do so.
+fn repack_entries(
+    reader: &PackReader,
+    builder: &mut PackBuilder,
+    mut copier: Option<BundleCopier>,
+    root_id: Option<i64>,
+) -> Result<u64, Error> {
+    if reader.is_encrypted() && !builder.encrypted {
+        return Err(Error::EncryptionMismatch(
+            "content of encrypted archive would be written without encryption".into(),
+        ));
+    }
+    let conn = &reader.conn;
+    let attrs_query = if crate::has_table(conn, "attrs")? {
+        let column = |name: &str| -> Result<String, Error> {
+            if crate::has_column(conn, "attrs", name)? {
+                Ok(name.to_owned())
+            } else {
+                Ok("NULL".to_owned())
+            }
+        };
+        Some(format!(
+            "SELECT mtime, {}, checksum, {}, {}, {}, {}, {}, {}, {} FROM attrs WHERE item = ?1",
+            column("mode")?,
+            column("devmajor")?,
+            column("devminor")?,
+            column("mime")?,
+            column("uid")?,
+            column("gid")?,
+            column("uname")?,
+            column("gname")?
+        ))
+    } else {
+        None
+    };
+    let has_xattrs = crate::has_table(conn, "xattr")?;
+    let same_digest = reader.digest_algorithm().ok() == Some(builder.digest);
+    // mapping of source item rowids to the rowids in the new archive
+    let mut mapping: HashMap<i64, i64> = HashMap::new();
+    let mut file_count: u64 = 0;
+    // the database walks only the entries whose names start with that of
+    // the root, of which the others are skipped along with their contents
+    let options = match root_id {
+        Some(root_id) => ListOptions {
+            prefix: Some(reader.conn.query_row(
+                "SELECT name FROM item WHERE id = ?1",
+                [&root_id],
+                |row| row.get(0),
+            )?),
+            ..Default::default()
+        },
+        None => ListOptions::default(),
+    };
+    reader.visit_with(&options, |entry, content| {
+        let parent = if entry.parent == 0 {
+            if root_id.is_some_and(|root_id| root_id != entry.id) {
+                return Ok(());
+            }
+            0
+        } else {
+            match mapping.get(&entry.parent) {
+                Some(id) => *id,
+                None => return Ok(()),
+            }
+        };
+        let name = entry
+            .name
+            .trim_end_matches('/')
+            .rsplit('/')
+            .next()
+            .unwrap_or_default();
+        let attrs = match attrs_query.as_ref() {
+            Some(query) => {
+                let mut stmt = conn.prepare_cached(query)?;
+                let mut rows = stmt.query([&entry.id])?;
+                match rows.next()? {
+                    Some(row) => SourceAttrs {
+                        mtime: row.get(0)?,
+                        mode: row.get(1)?,
+                        checksum: row.get(2)?,
+                        devmajor: row.get(3)?,
+                        devminor: row.get(4)?,
+                        mime: row.get(5)?,
+                        owner: Owner {
+                            uid: row.get(6)?,
+                            gid: row.get(7)?,
+                            uname: row.get(8)?,
+                            gname: row.get(9)?,
+                        },
+                    },
+                    None => SourceAttrs::default(),
+                }
+            }
+            None => SourceAttrs::default(),
+        };
+        builder.count_entry()?;
+        builder.conn.execute(
+            "INSERT INTO item (parent, kind, name) VALUES (?1, ?2, ?3)",
+            (&parent, &entry.kind, name),
+        )?;
+        let item_id = builder.conn.last_insert_rowid();
+        mapping.insert(entry.id, item_id);
+        builder.record_attrs(item_id, attrs.mtime, attrs.mode)?;
+        builder.record_owner(item_id, &attrs.owner)?;
+        if attrs.devmajor.is_some() || attrs.devminor.is_some() {
+            builder.conn.execute(
+                "UPDATE attrs SET devmajor = ?2, devminor = ?3 WHERE item = ?1",
+                (&item_id, &attrs.devmajor, &attrs.devminor),
+            )?;
+        }
+        if has_xattrs {
+            let mut stmt = conn.prepare_cached("SELECT name, value FROM xattr WHERE item = ?1")?;
+            let xattrs = stmt
+                .query_map([&entry.id], |row| {
+                    Ok((row.get::<_, String>(0)?, row.get::<_, Vec<u8>>(1)?))
+                })?
+                .collect::<Result<Vec<_>, _>>()?;
+            for (name, value) in xattrs {
+                builder.conn.execute(
+                    "INSERT INTO xattr (item, name, value) VALUES (?1, ?2, ?3)",
+                    (&item_id, &name, &value),
+                )?;
+            }
+        }
+        if entry.kind == ItemKind::File {
+            file_count += 1;
+            let digest = match attrs.checksum {
+                Some(digest) if same_digest => Some(digest),
+                _ if builder.content_addressed => {
+                    // the digest is needed before the content is added
+                    let mut hasher = Hasher::new(builder.digest);
+                    reader.read_content(entry.id, &mut hasher)?;
+                    Some(hasher.finalize())
+                }
+                _ => None,
+            };
+            if let Some(digest) = digest.as_ref() {
+                builder.conn.execute(
+                    "UPDATE attrs SET checksum = ?2 WHERE item = ?1",
+                    (&item_id, digest),
+                )?;
+                if builder.share_content(item_id, digest)? {
+                    return Ok(());
+                }
+            }
+            if let Some(copier) = copier.as_mut() {
+                if copier.copy_item(reader, builder, entry.id, item_id)? {
+                    if digest.is_none() {
+                        // the content is needed only to compute the checksum
+                        let mut hasher = Hasher::new(builder.digest);
+                        std::io::copy(content, &mut hasher)?;
+                        builder.conn.execute(
+                            "UPDATE attrs SET checksum = ?2 WHERE item = ?1",
+                            (&item_id, &hasher.finalize()),
+                        )?;
+                    }
+                    // the type is not revealed for encrypted content
+                    if let Some(mime) = attrs.mime.as_ref().filter(|_| !builder.encrypted) {
+                        builder.conn.execute(
+                            "UPDATE attrs SET mime = ?2 WHERE item = ?1",
+                            (&item_id, mime),
+                        )?;
+                    }
+                    return Ok(());
+                }
+            }
+            builder.add_stream_content(content, item_id)?;
+        } else if entry.kind == ItemKind::Symlink {
+            let mut target: Vec<u8> = vec![];
+            content.read_to_end(&mut target)?;
+            builder.conn.execute(
+                "UPDATE item SET target = ?2 WHERE id = ?1",
+                (&item_id, &target),
+            )?;
+        }
+        Ok(())
+    })?;
+    if let Some(copier) = copier.as_ref() {
+        tracing::info!(bundles = copier.copied.len(), "copied content bundles");
+    }
+    tracing::info!(files = file_count, "repacked archive");
+    Ok(file_count)
+}
+
+//
+// Copies the content bundles of the archive being repacked to the builder,
+// for those bundles written by the same codec that the builder uses.
+//
+struct BundleCopier {
+    // identifier of the codec of the builder
+    codec: String,
+    // true if the source archive records the codec of each bundle
+    has_codecs: bool,
+    // expressions for the columns that may be missing in older archives
+    rawsize: &'static str,
+    entries: &'static str,
+    // rowids of the bundles already copied, keyed by their rowids in the
+    // source archive
+    copied: HashMap<i64, i64>,
+    // rowids of the bundles that also hold content that is not to be copied
+    shared: HashSet<i64>,
+}
+
+impl BundleCopier {
+    fn new(reader: &PackReader, builder: &PackBuilder) -> Result<Self, Error> {
+        let conn = &reader.conn;
+        let column = |name: &'static str| -> Result<&'static str, Error> {
+            if crate::has_column(conn, "content", name)? {
+                Ok(name)
+            } else {
+                Ok("NULL")
+            }
+        };
+        Ok(Self {
+            codec: builder.codec.identifier().to_owned(),
+            has_codecs: crate::has_column(conn, "content", "codec")?,
+            rawsize: column("rawsize")?,
+            entries: column("entries")?,
+            copied: HashMap::new(),
+            shared: HashSet::new(),
+        })
+    }
+
+    // Give the new item the content of the item in the source archive by
+    // copying the bundles that hold it, returning `false` (having changed
+    // nothing) if any of those bundles cannot be copied.
+    fn copy_item(
+        &mut self,
+        reader: &PackReader,
+        builder: &mut PackBuilder,
+        source_id: i64,
+        item_id: i64,
+    ) -> Result<bool, Error> {
+        let mut stmt = reader.conn.prepare_cached(
+            "SELECT itempos, content, contentpos, size FROM itemcontent
+                WHERE item = ?1 ORDER BY itempos",
+        )?;
+        let parts = stmt
+            .query_map([&source_id], |row| {
+                Ok((
+                    row.get::<_, i64>(0)?,
+                    row.get::<_, i64>(1)?,
+                    row.get::<_, i64>(2)?,
+                    row.get::<_, i64>(3)?,
+                ))
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+        if parts.is_empty() {
+            return Ok(false);
+        }
+        for (_, content_id, _, _) in parts.iter() {
+            if !self.copied.contains_key(content_id) && !self.is_copyable(reader, *content_id)? {
+                return Ok(false);
+            }
+        }
+        for (itempos, content_id, contentpos, size) in parts {
+            let new_id = match self.copied.get(&content_id) {
+                Some(new_id) => *new_id,
+                None => self.copy_bundle(reader, builder, content_id)?,
+            };
+            builder.conn.execute(
+                "INSERT INTO itemcontent (item, itempos, content, contentpos, size)
+                    VALUES (?1, ?2, ?3, ?4, ?5)",
+                (&item_id, &itempos, &new_id, &contentpos, &size),
+            )?;
+        }
+        Ok(true)
+    }
+
+    // Return true if the bundle was written by the codec of the builder, or
+    // was stored without compression, as the builder would do likewise, and
+    // holds only content that is to be copied.
+    fn is_copyable(&self, reader: &PackReader, content_id: i64) -> Result<bool, Error> {
+        if self.shared.contains(&content_id) {
+            return Ok(false);
+        }
+        let identifier: Option<String> = if self.has_codecs {
+            reader.conn.query_row(
+                "SELECT codec FROM content WHERE id = ?1",
+                [&content_id],
+                |row| row.get(0),
+            )?
+        } else {
+            None
+        };
+        let identifier = identifier.unwrap_or_else(|| ZstdCodec::IDENTIFIER.to_owned());
+        Ok(identifier == self.codec || identifier == StoredCodec::IDENTIFIER)
+    }
+
+    // Copy the bundle to the builder after verifying it, returning the rowid
+    // of the new bundle.
+    fn copy_bundle(
+        &mut self,
+        reader: &PackReader,
+        builder: &mut PackBuilder,
+        content_id: i64,
+    ) -> Result<i64, Error> {
+        if !reader.check_bundle(content_id)? {
+            return Err(Error::CorruptBundle(content_id));
+        }
+        let started = std::time::Instant::now();
+        let (value, rawsize, codec, entries): (Vec<u8>, Option<i64>, Option<String>, Option<i64>) =
+            reader.conn.query_row(
+                &format!(
+                    "SELECT value, {}, {}, {} FROM content WHERE id = ?1",
+                    self.rawsize,
+                    if self.has_codecs { "codec" } else { "NULL" },
+                    self.entries
+                ),
+                [&content_id],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)),
+            )?;
+        builder.add_timing(started, |t| &mut t.reading);
+        let from = reader.content_key()?;
+        let to = if builder.encrypted {
+            Some(
+                builder
+                    .content_key
+                    .as_ref()
+                    .ok_or(Error::PasswordRequired)?,
+            )
+        } else {
+            None
+        };
+        let new_id = crate::builder::next_content_id(&builder.conn)?;
+        let value = if from.is_some() || to.is_some() {
+            crate::crypto::reseal(&value, from, to, content_id, new_id)?
+        } else {
+            value
+        };
+        let started = std::time::Instant::now();
+        let codec = codec.unwrap_or_else(|| ZstdCodec::IDENTIFIER.to_owned());
+        builder.conn.execute(
+            "INSERT INTO content (id, value, rawsize, checksum, codec, entries)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            (
+                &new_id,
+                &value,
+                &rawsize,
+                builder.digest.hash(&value),
+                &codec,
+                &entries,
+            ),
+        )?;
+        builder.add_timing(started, |t| &mut t.writing);
+        tracing::debug!(content_id, new_id, "copied content bundle");
+        builder.check_memory_limit()?;
+        self.copied.insert(content_id, new_id);
+        Ok(new_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{DigestAlgorithm, ZstdCodec};
+    use std::fs;
+
+    #[test]
+    fn test_repack() -> Result<(), Error> {
+        let workspace = tempfile::tempdir()?;
+        let basepath = workspace.path().join("input");
+        fs::create_dir_all(basepath.join("docs/empty"))?;
+        fs::write(basepath.join("docs/notes.txt"), "notes ".repeat(1000))?;
+        fs::write(basepath.join("docs/copy.txt"), "notes ".repeat(1000))?;
+        fs::write(basepath.join("hello.txt"), "hello world")?;
+        fs::write(basepath.join("empty.txt"), "")?;
+        #[cfg(unix)]
+        std::os::unix::fs::symlink("hello.txt", basepath.join("link"))?;
+        let original = workspace.path().join("original.db3");
+        let mut builder = PackBuilder::new()?;
+        builder.set_bundle_size(1024);
+        builder.add_dir_all(&basepath)?;
+        builder.finish(&original)?;
+
+        let reader = PackReader::new(&original)?;
+        let repacked = workspace.path().join("repacked.db3");
+        let mut builder = PackBuilder::new()?;
+        builder.set_codec(Box::new(ZstdCodec::new(19)));
+        builder.set_digest_algorithm(DigestAlgorithm::Xxh3)?;
+        builder.set_content_addressed(true)?;
+        assert_eq!(repack(&reader, &mut builder)?, 4);
+        builder.finish(&repacked)?;
+
+        let names = |reader: &PackReader| -> Result<Vec<String>, Error> {
+            let mut names = reader
+                .entries()?
+                .into_iter()
+                .map(|e| e.map(|e| e.name))
+                .collect::<Result<Vec<_>, _>>()?;
+            names.sort();
+            Ok(names)
+        };
+        let result = PackReader::new(&repacked)?;
+        assert_eq!(names(&result)?, names(&reader)?);
+        assert!(result.is_content_addressed()?);
+        assert_eq!(result.digest_algorithm()?, DigestAlgorithm::Xxh3);
+        result.verify_integrity()?;
+        // the two identical files now share one copy of the content, in a
+        // single bundle of the default size
+        let rawsize: u64 =
+            result
+                .conn
+                .query_row("SELECT SUM(rawsize) FROM content", [], |row| row.get(0))?;
+        assert_eq!(rawsize, 6011);
+        assert_eq!(result.counts()?.1, 1);
+        assert!(reader.counts()?.1 > 1);
+
+        let destination = workspace.path().join("output");
+        fs::create_dir(&destination)?;
+        let options = crate::ExtractOptions {
+            destination: destination.clone(),
+            ..Default::default()
+        };
+        result.extract_all_with(&options)?;
+        for name in ["docs/notes.txt", "docs/copy.txt", "hello.txt", "empty.txt"] {
+            assert_eq!(
+                fs::read(destination.join("input").join(name))?,
+                fs::read(basepath.join(name))?
+            );
+        }
+        assert!(destination.join("input/docs/empty").is_dir());
+        #[cfg(unix)]
+        assert_eq!(
+            fs::read_link(destination.join("input/link"))?,
+            std::path::PathBuf::from("hello.txt")
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_repack_bundles() -> Result<(), Error> {
+        let workspace = tempfile::tempdir()?;
+        let basepath = workspace.path().join("input");
+        fs::create_dir_all(basepath.join("docs"))?;
+        fs::write(basepath.join("docs/notes.txt"), "notes ".repeat(1000))?;
+        fs::write(basepath.join("hello.txt"), "hello world")?;
+        fs::write(basepath.join("empty.txt"), "")?;
+        let original = workspace.path().join("original.db3");
+        let mut builder = PackBuilder::new()?;
+        builder.set_bundle_size(1024);
+        builder.add_dir_all(&basepath)?;
+        builder.finish(&original)?;
+        let bundles = |reader: &PackReader| -> Result<Vec<Vec<u8>>, Error> {
+            let mut stmt = reader
+                .conn
+                .prepare("SELECT value FROM content ORDER BY id")?;
+            let values = stmt
+                .query_map([], |row| row.get(0))?
+                .collect::<Result<Vec<_>, _>>()?;
+            Ok(values)
+        };
+
+        // the bundles are copied as they are, with the checksums computed
+        // anew for the other digest algorithm
+        let reader = PackReader::new(&original)?;
+        let copied = workspace.path().join("copied.db3");
+        let mut builder = PackBuilder::new()?;
+        builder.set_digest_algorithm(DigestAlgorithm::Xxh3)?;
+        assert_eq!(repack_bundles(&reader, &mut builder)?, 3);
+        builder.finish(&copied)?;
+        let result = PackReader::new(&copied)?;
+        assert_eq!(bundles(&result)?, bundles(&reader)?);
+        result.verify_integrity()?;
+        assert!(result.check_archive()?.is_empty());
+        assert_eq!(
+            result.read_range("input/docs/notes.txt", 5994, 6)?,
+            b"notes "
+        );
+        assert_eq!(result.read_range("input/hello.txt", 0, 5)?, b"hello");
+
+        // encrypted bundles are sealed anew with the key of the builder
+        let encrypted = workspace.path().join("encrypted.db3");
+        let mut builder = PackBuilder::new()?;
+        builder.encrypt_content("secret")?;
+        builder.add_dir_all(&basepath)?;
+        builder.finish(&encrypted)?;
+        let mut reader = PackReader::new(&encrypted)?;
+        reader.set_password("secret")?;
+        let rekeyed = workspace.path().join("rekeyed.db3");
+        let mut builder = PackBuilder::new()?;
+        builder.encrypt_content("other")?;
+        repack_bundles(&reader, &mut builder)?;
+        builder.finish(&rekeyed)?;
+        let mut result = PackReader::new(&rekeyed)?;
+        assert!(result.set_password("secret").is_err());
+        result.set_password("other")?;
+        result.verify_integrity()?;
+        assert_eq!(result.read_range("input/hello.txt", 6, 5)?, b"world");
+
+        // damaged bundles are not copied
+        let conn = rusqlite::Connection::open(&original)?;
+        conn.execute("UPDATE content SET value = ZEROBLOB(LENGTH(value))", ())?;
+        drop(conn);
+        let reader = PackReader::new(&original)?;
+        let mut builder = PackBuilder::new()?;
+        assert!(matches!(
+            repack_bundles(&reader, &mut builder),
+            Err(Error::CorruptBundle(_))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_repack_subtree() -> Result<(), Error> {
+        let workspace = tempfile::tempdir()?;
+        let alpha = workspace.path().join("alpha");
+        let beta = workspace.path().join("beta");
+        fs::create_dir_all(alpha.join("docs"))?;
+        fs::create_dir_all(&beta)?;
+        fs::write(alpha.join("docs/big.txt"), "alpha ".repeat(1000))?;
+        fs::write(alpha.join("small.txt"), "a")?;
+        fs::write(beta.join("small.txt"), "b")?;
+        fs::write(workspace.path().join("alphabet.txt"), "abc")?;
+        let original = workspace.path().join("original.db3");
+        let mut builder = PackBuilder::new()?;
+        builder.set_bundle_size(1024);
+        builder.add_dir_all(&alpha)?;
+        builder.add_dir_all(&beta)?;
+        builder.add_input(workspace.path().join("alphabet.txt"), 0)?;
+        builder.finish(&original)?;
+        let bundles = |reader: &PackReader| -> Result<Vec<(Vec<u8>, u64)>, Error> {
+            let mut stmt = reader
+                .conn
+                .prepare("SELECT value, rawsize FROM content ORDER BY id")?;
+            let values = stmt
+                .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+                .collect::<Result<Vec<_>, _>>()?;
+            Ok(values)
+        };
+
+        let reader = PackReader::new(&original)?;
+        let split = workspace.path().join("split.db3");
+        let mut builder = PackBuilder::new()?;
+        assert_eq!(repack_subtree(&reader, &mut builder, "alpha")?, 2);
+        builder.finish(&split)?;
+        let result = PackReader::new(&split)?;
+        let mut names: Vec<String> = vec![];
+        for entry in result.entries()? {
+            names.push(entry?.name);
+        }
+        names.sort();
+        assert_eq!(
+            names,
+            vec![
+                "alpha/",
+                "alpha/docs/",
+                "alpha/docs/big.txt",
+                "alpha/small.txt"
+            ]
+        );
+        result.verify_integrity()?;
+        assert!(result.check_archive()?.is_empty());
+        assert_eq!(result.read_range("alpha/small.txt", 0, 1)?, b"a");
+        // the first bundle holds only the content of this directory and is
+        // copied, while the last is shared with the other entries and is not
+        let copied = bundles(&result)?;
+        let source = bundles(&reader)?;
+        assert!(copied.contains(&source[0]));
+        assert!(!copied.contains(&source[source.len() - 1]));
+        assert_eq!(result.read_range("alpha/docs/big.txt", 5994, 6)?, b"alpha ");
+
+        let mut builder = PackBuilder::new()?;
+        assert!(matches!(
+            repack_subtree(&reader, &mut builder, "gamma"),
+            Err(Error::EntryNotFound(_))
+        ));
+        Ok(())
+    }
+}
@@ -0,0 +1,192 @@
+//
+// Copyright (c) 2024 Nathan Fiedler
+//
+use crate::{has_column, Error, PackReader};
+use std::io::Read;
+use std::path::Path;
+
+///
+/// Outcome of repairing an archive using `repair_archive()`.
+///
+#[derive(Clone, Debug, Default)]
+pub struct RepairReport {
+    /// Number of content bundles that were checked.
+    pub checked: u64,
+    /// Rowids of the damaged bundles that were replaced.
+    pub repaired: Vec<i64>,
+    /// Rowids of the damaged bundles for which no good replacement was found.
+    pub unrepaired: Vec<i64>,
+}
+
+///
+/// Find the damaged content bundles in the archive at `damaged` and replace
+/// them with the matching bundles from `replica`, another copy of the same
+/// archive. The archive is modified in place.
+///
+/// Bundles with a recorded checksum are damaged if the checksum does not
+/// match, and are replaced by the bundle in the replica with that checksum.
+/// Bundles from older archives without checksums are damaged if they cannot
+/// be decompressed, and are replaced by the bundle with the same rowid, if
+/// that can be decompressed.
+///
+pub fn repair_archive<P: AsRef<Path>, Q: AsRef<Path>>(
+    damaged: P,
+    replica: Q,
+) -> Result<RepairReport, Error> {
+    if !crate::is_pack_file(damaged.as_ref())? || !crate::is_pack_file(replica.as_ref())? {
+        return Err(Error::NotPackFile);
+    }
+    let target = PackReader::new(damaged)?;
+    let source = PackReader::new(replica)?;
+    let mut report = RepairReport::default();
+    let mut stmt = target.conn.prepare("SELECT id FROM content ORDER BY id")?;
+    let ids = stmt
+        .query_map([], |row| row.get::<_, i64>(0))?
+        .collect::<Result<Vec<_>, _>>()?;
+    for content_id in ids {
+        report.checked += 1;
+        if target.check_bundle(content_id)? {
+            continue;
+        }
+        match find_replacement(&target, &source, content_id)? {
+            Some(source_id) => {
+                let mut blob = source.conn.blob_open(
+                    rusqlite::DatabaseName::Main,
+                    "content",
+                    "value",
+                    source_id,
+                    true,
+                )?;
+                let mut value: Vec<u8> = vec![];
+                blob.read_to_end(&mut value)?;
+                target.conn.execute(
+                    "UPDATE content SET value = ?2 WHERE id = ?1",
+                    (&content_id, &value),
+                )?;
+                report.repaired.push(content_id);
+            }
+            None => report.unrepaired.push(content_id),
+        }
+    }
+    // archives written by earlier versions lack the audit table
+    if !report.repaired.is_empty() && crate::has_table(&target.conn, "audit")? {
+        crate::audit::record_audit(&target.conn, "repair", &[])?;
+    }
+    Ok(report)
+}
+
+// Return the rowid of the intact bundle in the source archive that can
+// replace the damaged bundle in the target archive.
+fn find_replacement(
+    target: &PackReader,
+    source: &PackReader,
+    content_id: i64,
+) -> Result<Option<i64>, Error> {
+    let checksum: Option<Vec<u8>> = if has_column(&target.conn, "content", "checksum")? {
+        target.conn.query_row(
+            "SELECT checksum FROM content WHERE id = ?1",
+            [&content_id],
+            |row| row.get(0),
+        )?
+    } else {
+        None
+    };
+    let candidate: Option<i64> = match checksum {
+        Some(checksum) if has_column(&source.conn, "content", "checksum")? => {
+            let mut stmt = source
+                .conn
+                .prepare("SELECT id FROM content WHERE checksum = ?1 LIMIT 1")?;
+            let mut rows = stmt.query([&checksum])?;
+            match rows.next()? {
+                Some(row) => Some(row.get(0)?),
+                None => None,
+            }
+        }
+        Some(_) => None,
+        None => {
+            let mut stmt = source
+                .conn
+                .prepare("SELECT id FROM content WHERE id = ?1")?;
+            let mut rows = stmt.query([&content_id])?;
+            match rows.next()? {
+                Some(row) => Some(row.get(0)?),
+                None => None,
+            }
+        }
+    };
+    match candidate {
+        Some(source_id) if source.check_bundle(source_id)? => Ok(Some(source_id)),
+        _ => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::PackBuilder;
+    use std::fs;
+
+    // Overwrite a few bytes in the middle of the content bundle.
+    fn corrupt_bundle(path: &Path, content_id: i64) -> Result<(), Error> {
+        use std::io::{Seek, SeekFrom, Write};
+        let conn = rusqlite::Connection::open(path)?;
+        let mut blob = conn.blob_open(
+            rusqlite::DatabaseName::Main,
+            "content",
+            "value",
+            content_id,
+            false,
+        )?;
+        blob.seek(SeekFrom::Start(4))?;
+        blob.write_all(b"damaged")?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_repair_archive() -> Result<(), Error> {
+        let workspace = tempfile::tempdir()?;
+        let basepath = workspace.path().join("input");
+        fs::create_dir_all(&basepath)?;
+        let data: Vec<u8> = (0..(crate::BUNDLE_SIZE + 1000))
+            .map(|v| (v % 241) as u8)
+            .collect();
+        fs::write(basepath.join("large.bin"), &data)?;
+        fs::write(basepath.join("small.txt"), "hello world")?;
+        let replica = workspace.path().join("good.db3");
+        let mut builder = PackBuilder::new()?;
+        builder.add_dir_all(&basepath)?;
+        builder.finish(&replica)?;
+        let damaged = workspace.path().join("damaged.db3");
+        fs::copy(&replica, &damaged)?;
+
+        // flip some bits in the first bundle
+        corrupt_bundle(&damaged, 1)?;
+        let reader = PackReader::new(&damaged)?;
+        assert!(!reader.check_bundle(1)?);
+        assert!(reader.check_bundle(2)?);
+        drop(reader);
+
+        let report = repair_archive(&damaged, &replica)?;
+        assert_eq!(report.checked, 3);
+        assert_eq!(report.repaired, vec![1]);
+        assert!(report.unrepaired.is_empty());
+        let reader = PackReader::new(&damaged)?;
+        reader.verify_integrity()?;
+        let actual = reader.read_range("input/large.bin", 0, data.len() as u64)?;
+        assert!(actual == data);
+        let history = reader.history()?;
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0].operation, "repair");
+
+        // a damaged replica cannot be used to repair anything
+        for content_id in 1..=3 {
+            corrupt_bundle(&replica, content_id)?;
+        }
+        let broken = workspace.path().join("broken.db3");
+        fs::copy(&replica, &broken)?;
+        let report = repair_archive(&broken, &replica)?;
+        assert!(report.repaired.is_empty());
+        assert_eq!(report.unrepaired, vec![1, 2, 3]);
+        Ok(())
+    }
+}
@@ -0,0 +1,328 @@
+//
+// Copyright (c) 2024 Nathan Fiedler
+//
+use crate::{Error, ItemKind, PackReader};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+///
+/// Metadata recorded in the archive for an entry, as given to
+/// `Sink::set_metadata()`.
+///
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SinkMetadata {
+    /// Modification time in seconds since the Unix epoch, if recorded.
+    pub mtime: Option<i64>,
+    /// Unix permission bits, if recorded.
+    pub mode: Option<u32>,
+}
+
+///
+/// Destination to which `PackReader::extract_to()` writes the entries of an
+/// archive, allowing for targets other than the local file system, such as
+/// an in-memory tree, an object store, or a recorder in a test harness.
+///
+/// Paths are relative and slash-separated, as stored in the archive, without
+/// a trailing slash for directories. They are not sanitized, so a sink that
+/// writes to a file system should use `resolve_beneath()` or the like.
+///
+pub trait Sink {
+    ///
+    /// Create the directory at the given path. Directories are created before
+    /// any of the entries within them.
+    ///
+    fn create_dir(&mut self, path: &str) -> Result<(), Error>;
+
+    ///
+    /// Write a file at the given path with the content read from `content`.
+    ///
+    fn write_file(&mut self, path: &str, content: &mut dyn Read) -> Result<(), Error>;
+
+    ///
+    /// Create a symbolic link at the given path with the given raw value.
+    ///
+    fn create_symlink(&mut self, path: &str, target: &[u8]) -> Result<(), Error>;
+
+    ///
+    /// Apply the metadata recorded for the entry at the given path. This is
+    /// invoked once every entry has been written, for those entries that
+    /// have any recorded metadata, with the entries within a directory coming
+    /// before the directory itself. The default implementation does nothing.
+    ///
+    fn set_metadata(&mut self, path: &str, metadata: &SinkMetadata) -> Result<(), Error> {
+        let _ = (path, metadata);
+        Ok(())
+    }
+}
+
+///
+/// `Sink` that writes to a directory on the local file system, restoring the
+/// permissions of the files.
+///
+/// `PackReader::extract_all_with()` is better suited to the local file system,
+/// as it decompresses each content bundle only once, and supports the other
+/// extraction options.
+///
+#[derive(Clone, Debug)]
+pub struct FsSink {
+    root: PathBuf,
+}
+
+impl FsSink {
+    ///
+    /// Construct a sink that writes entries beneath the given directory,
+    /// which must already exist.
+    ///
+    pub fn new<P: AsRef<Path>>(root: P) -> Self {
+        Self {
+            root: root.as_ref().to_path_buf(),
+        }
+    }
+}
+
+impl Sink for FsSink {
+    fn create_dir(&mut self, path: &str) -> Result<(), Error> {
+        fs::create_dir_all(crate::resolve_beneath(&self.root, path)?)?;
+        Ok(())
+    }
+
+    fn write_file(&mut self, path: &str, content: &mut dyn Read) -> Result<(), Error> {
+        let mut output = crate::open_beneath(&self.root, path)?;
+        output.set_len(0)?;
+        io::copy(content, &mut output)?;
+        Ok(())
+    }
+
+    fn create_symlink(&mut self, path: &str, target: &[u8]) -> Result<(), Error> {
+        let fpath = crate::resolve_beneath(&self.root, path)?;
+        crate::reader::write_link(target, &fpath)
+    }
+
+    fn set_metadata(&mut self, path: &str, metadata: &SinkMetadata) -> Result<(), Error> {
+        // as with extract_all(), the permissions of files are restored, along
+        // with the modification times of directories
+        #[cfg(target_family = "unix")]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let fpath = crate::resolve_beneath(&self.root, path)?;
+            let is_dir = fs::symlink_metadata(&fpath)?.is_dir();
+            if let Some(mtime) = metadata.mtime.filter(|_| is_dir) {
+                crate::reader::set_dir_mtime(&fpath, mtime)?;
+            }
+            if let Some(mode) = metadata.mode {
+                if is_dir || fs::symlink_metadata(&fpath)?.is_file() {
+                    fs::set_permissions(fpath, fs::Permissions::from_mode(mode))?;
+                }
+            }
+        }
+        #[cfg(not(target_family = "unix"))]
+        let _ = (path, metadata);
+        Ok(())
+    }
+}
+
+///
+/// An entry recorded by a `MemorySink`.
+///
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SinkEntry {
+    /// A directory.
+    Directory,
+    /// A file and its content.
+    File(Vec<u8>),
+    /// A symbolic link and its raw value.
+    Symlink(Vec<u8>),
+}
+
+///
+/// `Sink` that records the entries and their metadata in memory, such as for
+/// inspecting the contents of an archive in tests.
+///
+#[derive(Clone, Debug, Default)]
+pub struct MemorySink {
+    entries: BTreeMap<String, SinkEntry>,
+    metadata: BTreeMap<String, SinkMetadata>,
+}
+
+impl MemorySink {
+    ///
+    /// Construct an empty `MemorySink`.
+    ///
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    ///
+    /// Return the entries that were written, by path.
+    ///
+    pub fn entries(&self) -> &BTreeMap<String, SinkEntry> {
+        &self.entries
+    }
+
+    ///
+    /// Return the metadata that was applied to the entry at the given path.
+    ///
+    pub fn metadata(&self, path: &str) -> Option<&SinkMetadata> {
+        self.metadata.get(path)
+    }
+}
+
+impl Sink for MemorySink {
+    fn create_dir(&mut self, path: &str) -> Result<(), Error> {
+        self.entries.insert(path.to_owned(), SinkEntry::Directory);
+        Ok(())
+    }
+
+    fn write_file(&mut self, path: &str, content: &mut dyn Read) -> Result<(), Error> {
+        let mut data: Vec<u8> = vec![];
+        content.read_to_end(&mut data)?;
+        self.entries.insert(path.to_owned(), SinkEntry::File(data));
+        Ok(())
+    }
+
+    fn create_symlink(&mut self, path: &str, target: &[u8]) -> Result<(), Error> {
+        self.entries
+            .insert(path.to_owned(), SinkEntry::Symlink(target.to_vec()));
+        Ok(())
+    }
+
+    fn set_metadata(&mut self, path: &str, metadata: &SinkMetadata) -> Result<(), Error> {
+        self.metadata.insert(path.to_owned(), metadata.clone());
+        Ok(())
+    }
+}
+
+impl PackReader {
+    ///
+    /// Write all of the directories, files, and symbolic links in the archive
+    /// to the given sink, returning the number of files written. Named pipes
+    /// and device nodes are not written. The extraction limits are checked
+    /// before anything is written.
+    ///
+    pub fn extract_to<S: Sink + ?Sized>(&self, sink: &mut S) -> Result<u64, Error> {
+        let _span = tracing::info_span!("extract_to").entered();
+        if self.is_metadata_only()? {
+            return Err(Error::MetadataOnly);
+        }
+        self.create_scope_table(&[String::new()])?;
+        self.check_limits()?;
+        self.drop_scope_table()?;
+        let mut file_count: u64 = 0;
+        let mut written: Vec<(i64, String)> = vec![];
+        self.visit(|entry, content| {
+            let path = entry.name.trim_end_matches('/');
+            match entry.kind {
+                ItemKind::Directory => sink.create_dir(path)?,
+                ItemKind::File => {
+                    sink.write_file(path, content)?;
+                    file_count += 1;
+                }
+                ItemKind::Symlink => {
+                    let mut target: Vec<u8> = vec![];
+                    content.read_to_end(&mut target)?;
+                    sink.create_symlink(path, &target)?;
+                }
+                _ => return Ok(()),
+            }
+            written.push((entry.id, path.to_owned()));
+            Ok(())
+        })?;
+        if crate::has_table(&self.conn, "attrs")? {
+            let mode = if crate::has_column(&self.conn, "attrs", "mode")? {
+                "mode"
+            } else {
+                "NULL"
+            };
+            let query = format!("SELECT mtime, {} FROM attrs WHERE item = ?1", mode);
+            let mut stmt = self.conn.prepare(&query)?;
+            // entries are visited breadth-first, so in reverse the children
+            // come before their parents
+            for (item_id, path) in written.iter().rev() {
+                let mut rows = stmt.query([item_id])?;
+                if let Some(row) = rows.next()? {
+                    let metadata = SinkMetadata {
+                        mtime: row.get(0)?,
+                        mode: row.get(1)?,
+                    };
+                    sink.set_metadata(path, &metadata)?;
+                }
+            }
+        }
+        tracing::info!(files = file_count, "extracted entries to sink");
+        Ok(file_count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{MemorySource, PackBuilder};
+
+    #[test]
+    fn test_memory_sink() -> Result<(), Error> {
+        let mut source = MemorySource::new();
+        source.add_file("site/index.html", "<html></html>");
+        source.add_file("site/css/style.css", "body {}");
+        source.add_symlink("site/home.html", "index.html");
+        source.add_dir("site/empty");
+        let workspace = tempfile::tempdir()?;
+        let packfile = workspace.path().join("pack.db3");
+        let mut builder = PackBuilder::new()?;
+        builder.add_dir_all_from(&source, "site")?;
+        let item_id = builder.add_stream("extra.txt", 0, "extra".as_bytes())?;
+        builder.record_attrs(item_id, Some(1_709_210_096), Some(0o600))?;
+        builder.finish(&packfile)?;
+
+        let reader = PackReader::new(&packfile)?;
+        let mut sink = MemorySink::new();
+        assert_eq!(reader.extract_to(&mut sink)?, 3);
+        let expected: BTreeMap<String, SinkEntry> = [
+            ("extra.txt", SinkEntry::File(b"extra".to_vec())),
+            ("site", SinkEntry::Directory),
+            ("site/css", SinkEntry::Directory),
+            ("site/css/style.css", SinkEntry::File(b"body {}".to_vec())),
+            ("site/empty", SinkEntry::Directory),
+            ("site/home.html", SinkEntry::Symlink(b"index.html".to_vec())),
+            (
+                "site/index.html",
+                SinkEntry::File(b"<html></html>".to_vec()),
+            ),
+        ]
+        .into_iter()
+        .map(|(path, entry)| (path.to_owned(), entry))
+        .collect();
+        assert_eq!(sink.entries(), &expected);
+        assert_eq!(
+            sink.metadata("extra.txt"),
+            Some(&SinkMetadata {
+                mtime: Some(1_709_210_096),
+                mode: Some(0o600)
+            })
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_fs_sink() -> Result<(), Error> {
+        let workspace = tempfile::tempdir()?;
+        let basepath = workspace.path().join("input");
+        fs::create_dir_all(basepath.join("sub"))?;
+        fs::write(basepath.join("sub/hello.txt"), "hello world")?;
+        let packfile = workspace.path().join("pack.db3");
+        let mut builder = PackBuilder::new()?;
+        builder.add_dir_all(&basepath)?;
+        builder.finish(&packfile)?;
+
+        let destination = workspace.path().join("output");
+        fs::create_dir(&destination)?;
+        let reader = PackReader::new(&packfile)?;
+        assert_eq!(reader.extract_to(&mut FsSink::new(&destination))?, 1);
+        assert_eq!(
+            fs::read_to_string(destination.join("input/sub/hello.txt"))?,
+            "hello world"
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,458 @@
+//
+// Copyright (c) 2024 Nathan Fiedler
+//
+use crate::digest::Hasher;
+use crate::{Error, PackBuilder, KIND_DIRECTORY, KIND_FILE, KIND_SYMLINK};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+///
+/// Type of an entry provided by a `Source`.
+///
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SourceKind {
+    /// Regular file whose content can be read with `Source::open()`.
+    File,
+    /// Directory whose entries can be listed with `Source::read_dir()`.
+    Directory,
+    /// Symbolic link whose value can be read with `Source::read_link()`.
+    Symlink,
+    /// Anything else, such as a named pipe, which is skipped.
+    Other,
+}
+
+///
+/// Metadata of an entry provided by a `Source`.
+///
+#[derive(Clone, Debug)]
+pub struct SourceMetadata {
+    /// Type of the entry.
+    pub kind: SourceKind,
+    /// Size in bytes of the content of a file.
+    pub len: u64,
+    /// Modification time in seconds since the Unix epoch, if known.
+    pub mtime: Option<i64>,
+    /// Unix permission bits, if known.
+    pub mode: Option<u32>,
+}
+
+///
+/// File system from which `PackBuilder::add_dir_all_from()` reads the files,
+/// directories, and symbolic links to be added to an archive, allowing for
+/// virtual file systems such as an in-memory tree, the contents of another
+/// archive, or a remote mount.
+///
+pub trait Source {
+    ///
+    /// Return the paths of the entries within the directory at the given path.
+    ///
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<PathBuf>>;
+
+    ///
+    /// Return the metadata of the entry at the given path, without following
+    /// symbolic links.
+    ///
+    fn metadata(&self, path: &Path) -> io::Result<SourceMetadata>;
+
+    ///
+    /// Open the file at the given path for reading its content.
+    ///
+    fn open(&self, path: &Path) -> io::Result<Box<dyn Read + '_>>;
+
+    ///
+    /// Return the value of the symbolic link at the given path.
+    ///
+    fn read_link(&self, path: &Path) -> io::Result<PathBuf>;
+}
+
+///
+/// `Source` that reads from the local file system using `std::fs`.
+///
+/// `PackBuilder::add_dir_all()` is better suited to the local file system, as
+/// it also records special files and macOS metadata, and skips compressing
+/// files that are unlikely to benefit.
+///
+#[derive(Clone, Copy, Debug, Default)]
+pub struct FsSource;
+
+impl Source for FsSource {
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<PathBuf>> {
+        fs::read_dir(path)?
+            .map(|entry| entry.map(|e| e.path()))
+            .collect()
+    }
+
+    fn metadata(&self, path: &Path) -> io::Result<SourceMetadata> {
+        let metadata = fs::symlink_metadata(path)?;
+        let kind = if metadata.is_dir() {
+            SourceKind::Directory
+        } else if metadata.is_file() {
+            SourceKind::File
+        } else if metadata.is_symlink() {
+            SourceKind::Symlink
+        } else {
+            SourceKind::Other
+        };
+        Ok(SourceMetadata {
+            kind,
+            len: metadata.len(),
+            mtime: crate::builder::get_mtime(&metadata),
+            mode: crate::builder::get_mode(&metadata),
+        })
+    }
+
+    fn open(&self, path: &Path) -> io::Result<Box<dyn Read + '_>> {
+        Ok(Box::new(fs::File::open(path)?))
+    }
+
+    fn read_link(&self, path: &Path) -> io::Result<PathBuf> {
+        fs::read_link(path)
+    }
+}
+
+// Entry within a MemorySource.
+#[derive(Clone, Debug)]
+enum MemoryEntry {
+    Directory,
+    File(Vec<u8>),
+    Symlink(PathBuf),
+}
+
+///
+/// `Source` that holds a tree of files, directories, and symbolic links in
+/// memory, such as for packing generated content without writing it to disk
+/// first. Parent directories are created as entries are added.
+///
+#[derive(Clone, Debug, Default)]
+pub struct MemorySource {
+    entries: BTreeMap<PathBuf, MemoryEntry>,
+}
+
+impl MemorySource {
+    ///
+    /// Construct an empty `MemorySource`.
+    ///
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    ///
+    /// Add a directory at the given path, along with any parent directories.
+    ///
+    pub fn add_dir<P: AsRef<Path>>(&mut self, path: P) {
+        for ancestor in path.as_ref().ancestors() {
+            if !ancestor.as_os_str().is_empty() {
+                self.entries
+                    .insert(ancestor.to_path_buf(), MemoryEntry::Directory);
+            }
+        }
+    }
+
+    ///
+    /// Add a file with the given content at the given path, replacing any
+    /// entry already at that path.
+    ///
+    pub fn add_file<P: AsRef<Path>, C: Into<Vec<u8>>>(&mut self, path: P, contents: C) {
+        self.insert(path.as_ref(), MemoryEntry::File(contents.into()));
+    }
+
+    ///
+    /// Add a symbolic link with the given value at the given path, replacing
+    /// any entry already at that path.
+    ///
+    pub fn add_symlink<P: AsRef<Path>, Q: AsRef<Path>>(&mut self, path: P, target: Q) {
+        let target = target.as_ref().to_path_buf();
+        self.insert(path.as_ref(), MemoryEntry::Symlink(target));
+    }
+
+    // Insert the entry after adding its parent directories.
+    fn insert(&mut self, path: &Path, entry: MemoryEntry) {
+        if let Some(parent) = path.parent() {
+            self.add_dir(parent);
+        }
+        self.entries.insert(path.to_path_buf(), entry);
+    }
+
+    // Find the entry at the given path, or return a not-found error.
+    fn get(&self, path: &Path) -> io::Result<&MemoryEntry> {
+        self.entries.get(path).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("no such entry: {}", path.display()),
+            )
+        })
+    }
+}
+
+impl Source for MemorySource {
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<PathBuf>> {
+        match self.get(path)? {
+            MemoryEntry::Directory => Ok(self
+                .entries
+                .keys()
+                .filter(|p| p.parent() == Some(path))
+                .cloned()
+                .collect()),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("not a directory: {}", path.display()),
+            )),
+        }
+    }
+
+    fn metadata(&self, path: &Path) -> io::Result<SourceMetadata> {
+        let (kind, len) = match self.get(path)? {
+            MemoryEntry::Directory => (SourceKind::Directory, 0),
+            MemoryEntry::File(data) => (SourceKind::File, data.len() as u64),
+            MemoryEntry::Symlink(_) => (SourceKind::Symlink, 0),
+        };
+        Ok(SourceMetadata {
+            kind,
+            len,
+            mtime: None,
+            mode: None,
+        })
+    }
+
+    fn open(&self, path: &Path) -> io::Result<Box<dyn Read + '_>> {
+        match self.get(path)? {
+            MemoryEntry::File(data) => Ok(Box::new(data.as_slice())),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("not a file: {}", path.display()),
+            )),
+        }
+    }
+
+    fn read_link(&self, path: &Path) -> io::Result<PathBuf> {
+        match self.get(path)? {
+            MemoryEntry::Symlink(target) => Ok(target.clone()),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("not a symbolic link: {}", path.display()),
+            )),
+        }
+    }
+}
+
+impl PackBuilder {
+    ///
+    /// Visit all of the files and directories within the specified path of
+    /// the given source, adding them to the root of the archive, as with
+    /// `add_dir_all()` but for any `Source`. The exclusion patterns, sorting
+    /// of entries, error policy, and content-addressed layout apply as usual.
+    /// Entries that are not files, directories, or symbolic links are noted
+    /// in `skipped()`.
+    ///
+    /// Returns the number of files added.
+    ///
+    /// **Note:** Remember to call `finish()` when done adding content.
+    ///
+    pub fn add_dir_all_from<S, P>(&mut self, source: &S, basepath: P) -> Result<u64, Error>
+    where
+        S: Source + ?Sized,
+        P: AsRef<Path>,
+    {
+        let basepath = basepath.as_ref();
+        let _span = tracing::info_span!("add_dir_all_from", path = %basepath.display()).entered();
+        let name = entry_name(basepath);
+        if self.find_child(&name, 0)?.is_some() {
+            return Err(Error::PathCollision(name));
+        }
+        let mut file_count: u64 = 0;
+        let mut subdirs: Vec<(i64, PathBuf)> = vec![(0, basepath.to_path_buf())];
+        // paths within the archive are relative to the parent of the base path
+        let archive_root = basepath.parent().unwrap_or(Path::new(""));
+        while let Some((parent_id, currdir)) = subdirs.pop() {
+            let metadata = source.metadata(&currdir)?;
+            let dir_id = self.add_source_entry(&currdir, parent_id, KIND_DIRECTORY)?;
+            self.record_attrs(dir_id, metadata.mtime, metadata.mode)?;
+            let started = Instant::now();
+            let listing = source.read_dir(&currdir);
+            self.add_timing(started, |t| &mut t.walking);
+            let mut entries = match listing {
+                Ok(entries) => entries,
+                Err(err) => {
+                    // the directory itself remains, without its contents
+                    self.handle_unreadable(&currdir, err.into())?;
+                    continue;
+                }
+            };
+            if self.sort_entries {
+                entries.sort();
+            }
+            for path in entries {
+                self.check_cancelled()?;
+                self.count_entry()?;
+                if !self.excludes.is_empty() {
+                    let relpath = path.strip_prefix(archive_root).unwrap_or(&path);
+                    if self.is_excluded(relpath) {
+                        continue;
+                    }
+                }
+                let started = Instant::now();
+                let metadata = source.metadata(&path);
+                self.add_timing(started, |t| &mut t.walking);
+                let metadata = match metadata {
+                    Ok(metadata) => metadata,
+                    Err(err) => {
+                        self.handle_unreadable(&path, err.into())?;
+                        continue;
+                    }
+                };
+                match metadata.kind {
+                    SourceKind::Directory => subdirs.push((dir_id, path)),
+                    SourceKind::File => {
+                        if self.add_source_file(source, &path, dir_id, &metadata)? {
+                            file_count += 1;
+                        }
+                    }
+                    SourceKind::Symlink => {
+                        use os_str_bytes::OsStringBytes;
+                        let target = source.read_link(&path)?.into_os_string().into_raw_vec();
+                        let item_id = self.add_source_entry(&path, dir_id, KIND_SYMLINK)?;
+                        self.record_attrs(item_id, metadata.mtime, metadata.mode)?;
+                        self.conn.execute(
+                            "UPDATE item SET target = ?2 WHERE id = ?1",
+                            (&item_id, &target),
+                        )?;
+                    }
+                    SourceKind::Other => self.skip_special(path),
+                }
+            }
+        }
+        Ok(file_count)
+    }
+
+    // Add the file from the source to the directory with the given rowid,
+    // returning false if it could not be read and was left out.
+    fn add_source_file<S: Source + ?Sized>(
+        &mut self,
+        source: &S,
+        path: &Path,
+        parent: i64,
+        metadata: &SourceMetadata,
+    ) -> Result<bool, Error> {
+        let mut input = match source.open(path) {
+            Ok(input) => input,
+            Err(err) => return self.handle_unreadable(path, err.into()).map(|_| false),
+        };
+        let item_id = self.add_source_entry(path, parent, KIND_FILE)?;
+        self.record_attrs(item_id, metadata.mtime, metadata.mode)?;
+        tracing::debug!(path = %path.display(), size = metadata.len, "adding file");
+        if self.content_addressed && !self.metadata_only {
+            // the digest is needed before the content is added
+            let mut hasher = Hasher::new(self.digest);
+            io::copy(&mut input, &mut hasher)?;
+            let digest = hasher.finalize();
+            self.conn.execute(
+                "INSERT INTO attrs (item, checksum) VALUES (?1, ?2)
+                    ON CONFLICT(item) DO UPDATE SET checksum = ?2",
+                (&item_id, &digest),
+            )?;
+            if self.share_content(item_id, &digest)? {
+                return Ok(true);
+            }
+            self.add_stream_content(source.open(path)?, item_id)?;
+        } else {
+            self.add_stream_content(input, item_id)?;
+        }
+        Ok(true)
+    }
+
+    // Insert an item of the given kind for the entry at the path.
+    fn add_source_entry(&self, path: &Path, parent: i64, kind: i8) -> Result<i64, Error> {
+        self.conn.execute(
+            "INSERT INTO item (parent, kind, name) VALUES (?1, ?2, ?3)",
+            (&parent, kind, self.entry_name(path)),
+        )?;
+        Ok(self.conn.last_insert_rowid())
+    }
+}
+
+// Return the final component of the path as the name of the entry.
+fn entry_name(path: &Path) -> String {
+    match path.file_name() {
+        Some(name) => name.to_string_lossy().into_owned(),
+        None => path.to_string_lossy().into_owned(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{PackReader, StoredCodec};
+
+    #[test]
+    fn test_memory_source() -> Result<(), Error> {
+        let mut source = MemorySource::new();
+        source.add_file("site/index.html", "<html></html>");
+        source.add_file("site/css/style.css", "body {}");
+        source.add_file("site/css/print.css", "body {}");
+        source.add_file("site/notes.tmp", "scratch");
+        source.add_symlink("site/home.html", "index.html");
+        source.add_dir("site/empty");
+        assert_eq!(
+            source.metadata(Path::new("site/css"))?.kind,
+            SourceKind::Directory
+        );
+        assert!(source.open(Path::new("site/missing")).is_err());
+
+        let workspace = tempfile::tempdir()?;
+        let packfile = workspace.path().join("pack.db3");
+        let mut builder = PackBuilder::new()?;
+        builder.set_content_addressed(true)?;
+        builder.set_codec(Box::new(StoredCodec));
+        builder.add_exclude("*.tmp")?;
+        assert_eq!(builder.add_dir_all_from(&source, "site")?, 3);
+        builder.finish(&packfile)?;
+
+        let reader = PackReader::new(&packfile)?;
+        let mut paths: Vec<String> = reader
+            .entries()?
+            .into_iter()
+            .map(|e| e.map(|e| e.name))
+            .collect::<Result<Vec<_>, _>>()?;
+        paths.sort();
+        assert_eq!(
+            paths,
+            vec![
+                "site/",
+                "site/css/",
+                "site/css/print.css",
+                "site/css/style.css",
+                "site/empty/",
+                "site/home.html",
+                "site/index.html",
+            ]
+        );
+        assert_eq!(reader.read_range("site/index.html", 1, 4)?, b"html");
+        assert_eq!(reader.read_range("site/css/print.css", 0, 4)?, b"body");
+        // identical files share the same content
+        assert_eq!(reader.duplicates()?.len(), 1);
+        assert_eq!(reader.space_report()?.dedup_savings, 7);
+        Ok(())
+    }
+
+    #[test]
+    fn test_fs_source() -> Result<(), Error> {
+        let workspace = tempfile::tempdir()?;
+        let basepath = workspace.path().join("input");
+        fs::create_dir_all(basepath.join("sub"))?;
+        fs::write(basepath.join("sub/hello.txt"), "hello world")?;
+        let packfile = workspace.path().join("pack.db3");
+        let mut builder = PackBuilder::new()?;
+        assert_eq!(builder.add_dir_all_from(&FsSource, &basepath)?, 1);
+        assert!(matches!(
+            builder.add_dir_all_from(&FsSource, &basepath),
+            Err(Error::PathCollision(_))
+        ));
+        builder.finish(&packfile)?;
+        let reader = PackReader::new(&packfile)?;
+        assert_eq!(reader.read_range("input/sub/hello.txt", 6, 5)?, b"world");
+        Ok(())
+    }
+}
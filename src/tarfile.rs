@@ -0,0 +1,757 @@
+//
+// Copyright (c) 2024 Nathan Fiedler
+//
+use crate::{
+    Error, PackBuilder, PackReader, Warning, KIND_BLOCK_DEVICE, KIND_CHAR_DEVICE, KIND_DIRECTORY,
+    KIND_FIFO, KIND_FILE, KIND_SYMLINK,
+};
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+
+// Size of the blocks that make up a tar file.
+const BLOCK_SIZE: usize = 512;
+
+// Largest value that fits in the 12 byte numeric fields of the header.
+const MAX_OCTAL: u64 = 0o77777777777;
+
+// Largest pax or GNU extended header that will be read into memory.
+const MAX_EXTENDED: u64 = 1048576;
+
+impl PackBuilder {
+    ///
+    /// Add all of the entries from the tar stream read from the given reader,
+    /// such as the output of `git archive` or `docker export`, reading the
+    /// content of each file directly from the stream. Entries are added to
+    /// the directory in the archive with the rowid `parent`, with `0` being
+    /// the root of the archive, creating directories as needed. The ustar and
+    /// pax formats are supported, as are the long names written by GNU tar.
+    ///
+    /// Hard links share the content of the file to which they refer. Named
+    /// pipes and device nodes are only added if enabled with
+    /// `set_special_files()`, otherwise they are noted in `skipped()`.
+    ///
+    /// Returns the number of files added.
+    ///
+    /// **Note:** Remember to call `finish()` when done adding content.
+    ///
+    pub fn add_tar_stream<R: Read>(&mut self, input: R, parent: i64) -> Result<u64, Error> {
+        let mut input = io::BufReader::new(input);
+        let mut file_count: u64 = 0;
+        // files added from this stream, for resolving hard links
+        let mut files: HashMap<Vec<String>, i64> = HashMap::new();
+        // values from extended headers that apply to the next entry
+        let mut extended = Extended::default();
+        let mut block = [0; BLOCK_SIZE];
+        while read_block(&mut input, &mut block)? && block.iter().any(|b| *b != 0) {
+            let header = ParsedHeader::parse(&block)?;
+            if matches!(header.typeflag, b'x' | b'g' | b'L' | b'K') {
+                if header.size > MAX_EXTENDED {
+                    return Err(invalid_data("extended header is too large").into());
+                }
+                let mut data: Vec<u8> = vec![];
+                (&mut input).take(header.size).read_to_end(&mut data)?;
+                if data.len() as u64 != header.size {
+                    return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+                }
+                skip_data(&mut input, padding(header.size))?;
+                match header.typeflag {
+                    b'x' => extended.parse_pax(&data)?,
+                    b'L' => extended.path = Some(trim_nul(&data).to_vec()),
+                    b'K' => extended.link = Some(trim_nul(&data).to_vec()),
+                    // global headers mostly carry comments, such as the
+                    // commit identifier written by git archive
+                    _ => (),
+                }
+                continue;
+            }
+            self.count_entry()?;
+            let size = extended.size.take().unwrap_or(header.size);
+            let raw_path = extended.path.take().unwrap_or(header.path);
+            let link = extended.link.take().unwrap_or(header.link);
+            let mtime = extended.mtime.take().unwrap_or(header.mtime);
+            let path = String::from_utf8_lossy(&raw_path).into_owned();
+            if std::str::from_utf8(&raw_path).is_err() {
+                self.warn(Warning::LossyFileName(PathBuf::from(&path)));
+            }
+            let names = split_path(&path)?;
+            // very old archives mark directories with only a trailing slash
+            let typeflag = match header.typeflag {
+                b'0' | b'\0' if path.ends_with('/') => b'5',
+                flag => flag,
+            };
+            let mut consumed: u64 = 0;
+            if let Some((name, dirs)) = names.split_last() {
+                let relpath = Path::new(&path);
+                let mut dir_id = parent;
+                for dir in dirs {
+                    dir_id = self.find_or_add_directory(dir, dir_id, relpath)?;
+                }
+                if typeflag != b'5' && self.find_child(name, dir_id)?.is_some() {
+                    return Err(Error::PathCollision(path));
+                }
+                let item_id = match typeflag {
+                    b'5' => Some(self.find_or_add_directory(name, dir_id, relpath)?),
+                    b'0' | b'\0' | b'7' => {
+                        let mut content = (&mut input).take(size);
+                        let item_id = self.add_stream(name, dir_id, &mut content)?;
+                        if content.limit() > 0 {
+                            return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+                        }
+                        consumed = size;
+                        files.insert(names.clone(), item_id);
+                        file_count += 1;
+                        Some(item_id)
+                    }
+                    b'1' => {
+                        let target = String::from_utf8_lossy(&link).into_owned();
+                        let canonical = *files.get(&split_path(&target)?).ok_or_else(|| {
+                            invalid_data(&format!("hard link to unknown file {}", target))
+                        })?;
+                        self.conn.execute(
+                            "INSERT INTO item (parent, kind, name) VALUES (?1, ?2, ?3)",
+                            (&dir_id, KIND_FILE, name),
+                        )?;
+                        let item_id = self.conn.last_insert_rowid();
+                        self.conn.execute(
+                            "INSERT INTO attrs (item, checksum)
+                                SELECT ?1, checksum FROM attrs WHERE item = ?2",
+                            (&item_id, &canonical),
+                        )?;
+                        // the content may not have been written to a bundle yet
+                        self.aliases.push((item_id, canonical));
+                        file_count += 1;
+                        Some(item_id)
+                    }
+                    b'2' => {
+                        self.conn.execute(
+                            "INSERT INTO item (parent, kind, name, target) VALUES (?1, ?2, ?3, ?4)",
+                            (&dir_id, KIND_SYMLINK, name, &link),
+                        )?;
+                        Some(self.conn.last_insert_rowid())
+                    }
+                    b'3' | b'4' | b'6' if !self.special_files => {
+                        self.skip_special(PathBuf::from(&path));
+                        None
+                    }
+                    b'3' | b'4' | b'6' => {
+                        let kind = match typeflag {
+                            b'3' => KIND_CHAR_DEVICE,
+                            b'4' => KIND_BLOCK_DEVICE,
+                            _ => KIND_FIFO,
+                        };
+                        self.conn.execute(
+                            "INSERT INTO item (parent, kind, name) VALUES (?1, ?2, ?3)",
+                            (&dir_id, kind, name),
+                        )?;
+                        Some(self.conn.last_insert_rowid())
+                    }
+                    flag => {
+                        tracing::debug!(path, typeflag = flag, "skipping unsupported entry");
+                        None
+                    }
+                };
+                if let Some(item_id) = item_id {
+                    self.record_attrs(item_id, Some(mtime), Some(header.mode & 0o7777))?;
+                    if matches!(typeflag, b'3' | b'4') {
+                        self.conn.execute(
+                            "UPDATE attrs SET devmajor = ?2, devminor = ?3 WHERE item = ?1",
+                            (&item_id, &header.device.0, &header.device.1),
+                        )?;
+                    }
+                }
+            }
+            skip_data(&mut input, size - consumed + padding(size))?;
+        }
+        Ok(file_count)
+    }
+}
+
+impl PackReader {
+    ///
+    /// Write all of the entries in the archive as a POSIX tar file to the
+    /// given writer, decompressing the content of each file directly into the
+    /// tar stream, such that the output can be sent to a pipe or socket
+    /// without any intermediate files. Paths that are too long for the basic
+    /// tar header are recorded using pax extended headers.
+    ///
+    /// Returns the number of files written.
+    ///
+    pub fn export_tar<W: Write>(&self, output: W) -> Result<u64, Error> {
+        let mut writer = io::BufWriter::new(output);
+        let mut file_count: u64 = 0;
+        for entry in self.export_entries()? {
+            let mut header = TarHeader {
+                path: &entry.path,
+                typeflag: b'0',
+                size: 0,
+                mode: entry.mode.unwrap_or(0o644),
+                mtime: entry.mtime.unwrap_or(0),
+                link: vec![],
+                device: (entry.devmajor.unwrap_or(0), entry.devminor.unwrap_or(0)),
+            };
+            match entry.kind {
+                KIND_DIRECTORY => {
+                    header.typeflag = b'5';
+                    header.mode = entry.mode.unwrap_or(0o755);
+                }
+                KIND_SYMLINK => {
+                    header.typeflag = b'2';
+                    header.mode = entry.mode.unwrap_or(0o777);
+                    self.read_content(entry.id, &mut header.link)?;
+                }
+                KIND_FIFO => header.typeflag = b'6',
+                KIND_CHAR_DEVICE => header.typeflag = b'3',
+                KIND_BLOCK_DEVICE => header.typeflag = b'4',
+                KIND_FILE => header.size = entry.size,
+                _ => continue,
+            }
+            header.write_to(&mut writer)?;
+            if entry.kind == KIND_FILE {
+                let mut counter = CountingWriter {
+                    inner: &mut writer,
+                    count: 0,
+                };
+                self.read_content(entry.id, &mut counter)?;
+                if counter.count != entry.size {
+                    return Err(Error::IOError(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("content of {} is not the recorded size", entry.path),
+                    )));
+                }
+                write_padding(&mut writer, entry.size)?;
+                file_count += 1;
+            }
+        }
+        // the end of the archive is marked by two empty blocks
+        writer.write_all(&[0; BLOCK_SIZE * 2])?;
+        writer.flush()?;
+        Ok(file_count)
+    }
+}
+
+// Values of the header that precedes each entry in the tar file.
+struct TarHeader<'a> {
+    path: &'a str,
+    typeflag: u8,
+    size: u64,
+    mode: u32,
+    mtime: i64,
+    link: Vec<u8>,
+    device: (u32, u32),
+}
+
+impl TarHeader<'_> {
+    // Write the header, preceded by a pax extended header for any values that
+    // do not fit within the ustar header.
+    fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        let mut records: Vec<u8> = vec![];
+        if self.path.len() > 100 {
+            pax_record(&mut records, "path", self.path.as_bytes());
+        }
+        if self.link.len() > 100 {
+            pax_record(&mut records, "linkpath", &self.link);
+        }
+        if self.size > MAX_OCTAL {
+            pax_record(&mut records, "size", self.size.to_string().as_bytes());
+        }
+        if self.mtime < 0 || self.mtime as u64 > MAX_OCTAL {
+            pax_record(&mut records, "mtime", self.mtime.to_string().as_bytes());
+        }
+        if !records.is_empty() {
+            let extended = TarHeader {
+                path: "././@PaxHeader",
+                typeflag: b'x',
+                size: records.len() as u64,
+                mode: 0o644,
+                mtime: 0,
+                link: vec![],
+                device: (0, 0),
+            };
+            writer.write_all(&extended.to_block())?;
+            writer.write_all(&records)?;
+            write_padding(writer, records.len() as u64)?;
+        }
+        writer.write_all(&self.to_block())
+    }
+
+    // Produce the ustar header block, truncating or clamping any values that
+    // do not fit, and computing the checksum.
+    fn to_block(&self) -> [u8; BLOCK_SIZE] {
+        let mut block = [0; BLOCK_SIZE];
+        let name = truncate(self.path.as_bytes(), 100);
+        block[..name.len()].copy_from_slice(name);
+        write_octal(&mut block[100..108], (self.mode & 0o7777) as u64);
+        write_octal(&mut block[108..116], 0);
+        write_octal(&mut block[116..124], 0);
+        write_octal(&mut block[124..136], self.size.min(MAX_OCTAL));
+        write_octal(
+            &mut block[136..148],
+            self.mtime.clamp(0, MAX_OCTAL as i64) as u64,
+        );
+        block[156] = self.typeflag;
+        let link = truncate(&self.link, 100);
+        block[157..157 + link.len()].copy_from_slice(link);
+        block[257..263].copy_from_slice(b"ustar\0");
+        block[263..265].copy_from_slice(b"00");
+        if matches!(self.typeflag, b'3' | b'4') {
+            write_octal(&mut block[329..337], self.device.0 as u64);
+            write_octal(&mut block[337..345], self.device.1 as u64);
+        }
+        // the checksum is computed with the checksum field filled with spaces
+        block[148..156].fill(b' ');
+        let checksum: u64 = block.iter().map(|b| *b as u64).sum();
+        let digits = format!("{:06o}\0 ", checksum);
+        block[148..156].copy_from_slice(digits.as_bytes());
+        block
+    }
+}
+
+// Values of a header read from a tar stream.
+struct ParsedHeader {
+    path: Vec<u8>,
+    typeflag: u8,
+    size: u64,
+    mode: u32,
+    mtime: i64,
+    link: Vec<u8>,
+    device: (u32, u32),
+}
+
+impl ParsedHeader {
+    // Parse the header block, verifying its checksum.
+    fn parse(block: &[u8; BLOCK_SIZE]) -> io::Result<Self> {
+        let stored = parse_number(&block[148..156])?;
+        let unsigned: u64 = block
+            .iter()
+            .enumerate()
+            .map(|(i, b)| {
+                if (148..156).contains(&i) {
+                    32
+                } else {
+                    *b as u64
+                }
+            })
+            .sum();
+        // some old implementations summed the bytes as signed values
+        let signed: i64 = block
+            .iter()
+            .enumerate()
+            .map(|(i, b)| {
+                if (148..156).contains(&i) {
+                    32
+                } else {
+                    *b as i8 as i64
+                }
+            })
+            .sum();
+        if stored != unsigned && stored as i64 != signed {
+            return Err(invalid_data("tar header checksum mismatch"));
+        }
+        let mut path = trim_nul(&block[..100]).to_vec();
+        let prefix = trim_nul(&block[345..500]);
+        if &block[257..262] == b"ustar" && !prefix.is_empty() {
+            let mut full = prefix.to_vec();
+            full.push(b'/');
+            full.extend_from_slice(&path);
+            path = full;
+        }
+        Ok(Self {
+            path,
+            typeflag: block[156],
+            size: parse_number(&block[124..136])?,
+            mode: parse_number(&block[100..108])? as u32,
+            mtime: parse_number(&block[136..148])? as i64,
+            link: trim_nul(&block[157..257]).to_vec(),
+            device: (
+                parse_number(&block[329..337])? as u32,
+                parse_number(&block[337..345])? as u32,
+            ),
+        })
+    }
+}
+
+// Values from pax extended headers and GNU long name headers, which take the
+// place of those in the header that follows.
+#[derive(Default)]
+struct Extended {
+    path: Option<Vec<u8>>,
+    link: Option<Vec<u8>>,
+    size: Option<u64>,
+    mtime: Option<i64>,
+}
+
+impl Extended {
+    // Parse the records of a pax extended header, each of which is prefixed
+    // by its own length.
+    fn parse_pax(&mut self, mut data: &[u8]) -> io::Result<()> {
+        while !data.is_empty() {
+            let space = data
+                .iter()
+                .position(|b| *b == b' ')
+                .ok_or_else(|| invalid_data("malformed pax record"))?;
+            let len: usize = std::str::from_utf8(&data[..space])
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .filter(|len| *len > space + 1 && *len <= data.len())
+                .ok_or_else(|| invalid_data("malformed pax record"))?;
+            let record = &data[space + 1..len - 1];
+            data = &data[len..];
+            let Some(equals) = record.iter().position(|b| *b == b'=') else {
+                return Err(invalid_data("malformed pax record"));
+            };
+            let (key, value) = (&record[..equals], &record[equals + 1..]);
+            let number = || {
+                std::str::from_utf8(value)
+                    .ok()
+                    .and_then(|s| s.split('.').next())
+                    .and_then(|s| s.parse::<i64>().ok())
+                    .ok_or_else(|| invalid_data("malformed pax number"))
+            };
+            match key {
+                b"path" => self.path = Some(value.to_vec()),
+                b"linkpath" => self.link = Some(value.to_vec()),
+                b"size" => self.size = Some(number()? as u64),
+                b"mtime" => self.mtime = Some(number()?),
+                _ => (),
+            }
+        }
+        Ok(())
+    }
+}
+
+// Read one block from the stream, returning false if the stream ended before
+// the block began.
+fn read_block<R: Read>(input: &mut R, block: &mut [u8; BLOCK_SIZE]) -> io::Result<bool> {
+    let mut count: usize = 0;
+    while count < BLOCK_SIZE {
+        let read = input.read(&mut block[count..])?;
+        if read == 0 {
+            if count == 0 {
+                return Ok(false);
+            }
+            return Err(io::Error::from(io::ErrorKind::UnexpectedEof));
+        }
+        count += read;
+    }
+    Ok(true)
+}
+
+// Read and discard the given number of bytes from the stream.
+fn skip_data<R: Read>(input: &mut R, len: u64) -> io::Result<()> {
+    if io::copy(&mut input.take(len), &mut io::sink())? != len {
+        return Err(io::Error::from(io::ErrorKind::UnexpectedEof));
+    }
+    Ok(())
+}
+
+// Parse a numeric header field, which is either octal digits or, if the high
+// bit of the first byte is set, a big-endian binary number.
+fn parse_number(field: &[u8]) -> io::Result<u64> {
+    if field[0] & 0x80 != 0 {
+        let mut value: u64 = (field[0] & 0x7f) as u64;
+        for byte in &field[1..] {
+            value = (value << 8) | *byte as u64;
+        }
+        return Ok(value);
+    }
+    let text = std::str::from_utf8(field).map_err(|_| invalid_data("malformed tar number"))?;
+    let digits = text.trim_matches(|c| c == '\0' || c == ' ');
+    if digits.is_empty() {
+        return Ok(0);
+    }
+    u64::from_str_radix(digits, 8).map_err(|_| invalid_data("malformed tar number"))
+}
+
+// Split the slash-separated path into names, dropping any current directory
+// components and applying the parent directory components, but refusing any
+// that would escape the root.
+fn split_path(path: &str) -> Result<Vec<String>, Error> {
+    let mut names: Vec<String> = vec![];
+    for name in path.split('/') {
+        match name {
+            "" | "." => (),
+            ".." => {
+                if names.pop().is_none() {
+                    return Err(Error::PathEscapesRoot(PathBuf::from(path)));
+                }
+            }
+            _ => names.push(name.to_owned()),
+        }
+    }
+    Ok(names)
+}
+
+// Return the bytes up to the first NUL, if any.
+fn trim_nul(bytes: &[u8]) -> &[u8] {
+    match bytes.iter().position(|b| *b == 0) {
+        Some(pos) => &bytes[..pos],
+        None => bytes,
+    }
+}
+
+// Number of zeros that follow data of the given length to fill the block.
+fn padding(len: u64) -> u64 {
+    (BLOCK_SIZE as u64 - len % BLOCK_SIZE as u64) % BLOCK_SIZE as u64
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_owned())
+}
+
+// Write the value as zero-padded octal digits followed by a NUL.
+fn write_octal(field: &mut [u8], value: u64) {
+    let digits = format!("{:0width$o}\0", value, width = field.len() - 1);
+    field.copy_from_slice(digits.as_bytes());
+}
+
+// Append a pax extended header record, which is prefixed by its own length.
+fn pax_record(records: &mut Vec<u8>, key: &str, value: &[u8]) {
+    // length of the key, value, space, equals, and newline
+    let rest = key.len() + value.len() + 3;
+    let mut len = rest + 1;
+    while len != rest + len.to_string().len() {
+        len = rest + len.to_string().len();
+    }
+    records.extend_from_slice(format!("{} {}=", len, key).as_bytes());
+    records.extend_from_slice(value);
+    records.push(b'\n');
+}
+
+// Return at most the given number of leading bytes.
+fn truncate(bytes: &[u8], len: usize) -> &[u8] {
+    &bytes[..bytes.len().min(len)]
+}
+
+// Write zeros to fill out the last block of data of the given length.
+fn write_padding<W: Write>(writer: &mut W, len: u64) -> io::Result<()> {
+    let remainder = (len % BLOCK_SIZE as u64) as usize;
+    if remainder > 0 {
+        writer.write_all(&[0; BLOCK_SIZE][remainder..])?;
+    }
+    Ok(())
+}
+
+// Writer that counts the bytes passed through to another writer.
+struct CountingWriter<'a, W: Write> {
+    inner: &'a mut W,
+    count: u64,
+}
+
+impl<W: Write> Write for CountingWriter<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.count += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::PackBuilder;
+    use std::fs;
+
+    // Parse the octal digits of a header field.
+    fn parse_octal(field: &[u8]) -> u64 {
+        let text = std::str::from_utf8(field).unwrap();
+        u64::from_str_radix(text.trim_matches(|c| c == '\0' || c == ' '), 8).unwrap()
+    }
+
+    #[test]
+    fn test_pax_record() {
+        let mut records: Vec<u8> = vec![];
+        pax_record(&mut records, "path", b"abc");
+        assert_eq!(records, b"12 path=abc\n");
+        records.clear();
+        // the third digit of the length pushes the record to 101 bytes
+        pax_record(&mut records, "path", &[b'a'; 91]);
+        assert_eq!(records.len(), 101);
+        assert!(records.starts_with(b"101 path="));
+    }
+
+    #[test]
+    fn test_parse_number() {
+        assert_eq!(parse_number(b"0000644\0").unwrap(), 0o644);
+        assert_eq!(parse_number(b"     17 ").unwrap(), 0o17);
+        assert_eq!(parse_number(b"\0\0\0\0").unwrap(), 0);
+        assert_eq!(
+            parse_number(&[0x80, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0]).unwrap(),
+            256
+        );
+        assert!(parse_number(b"0000089\0").is_err());
+    }
+
+    #[test]
+    fn test_split_path() -> Result<(), Error> {
+        assert_eq!(split_path("./a//b/")?, vec!["a", "b"]);
+        assert_eq!(split_path("a/../b")?, vec!["b"]);
+        assert!(split_path("./").unwrap().is_empty());
+        assert!(matches!(
+            split_path("a/../../etc"),
+            Err(Error::PathEscapesRoot(_))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_add_tar_stream() -> Result<(), Error> {
+        let workspace = tempfile::tempdir()?;
+        let basepath = workspace.path().join("input");
+        let long_name = "y".repeat(150);
+        fs::create_dir_all(basepath.join("sub"))?;
+        fs::create_dir_all(basepath.join("empty"))?;
+        fs::write(basepath.join("sub/hello.txt"), "hello world")?;
+        fs::write(basepath.join(&long_name), "lorem ipsum ".repeat(100))?;
+        #[cfg(target_family = "unix")]
+        std::os::unix::fs::symlink("sub/hello.txt", basepath.join("link"))?;
+        let packfile = workspace.path().join("pack.db3");
+        let mut builder = PackBuilder::new()?;
+        builder.add_dir_all(&basepath)?;
+        builder.finish(&packfile)?;
+        let mut stream: Vec<u8> = vec![];
+        PackReader::new(&packfile)?.export_tar(&mut stream)?;
+
+        // replace the end of the archive with a hard link and a file with a
+        // GNU long name, followed by a device node
+        stream.truncate(stream.len() - BLOCK_SIZE * 2);
+        let mut header = TarHeader {
+            path: "input/hardlink",
+            typeflag: b'1',
+            size: 0,
+            mode: 0o644,
+            mtime: 1_700_000_000,
+            link: b"input/sub/hello.txt".to_vec(),
+            device: (0, 0),
+        };
+        header.write_to(&mut stream)?;
+        let gnu_name = format!("input/gnu/{}", "z".repeat(120));
+        header.path = "././@LongLink";
+        header.typeflag = b'L';
+        header.size = gnu_name.len() as u64;
+        header.link = vec![];
+        stream.extend_from_slice(&header.to_block());
+        stream.extend_from_slice(gnu_name.as_bytes());
+        write_padding(&mut stream, gnu_name.len() as u64)?;
+        header.path = &gnu_name[..100];
+        header.typeflag = b'0';
+        header.size = 3;
+        stream.extend_from_slice(&header.to_block());
+        stream.extend_from_slice(b"gnu");
+        write_padding(&mut stream, 3)?;
+        header.path = "input/null";
+        header.typeflag = b'3';
+        header.size = 0;
+        header.device = (1, 3);
+        header.write_to(&mut stream)?;
+        stream.extend_from_slice(&[0; BLOCK_SIZE * 2]);
+
+        let repacked = workspace.path().join("repacked.db3");
+        let mut builder = PackBuilder::new()?;
+        let root = builder.add_prefix("restored")?;
+        assert_eq!(builder.add_tar_stream(&stream[..], root)?, 4);
+        assert_eq!(builder.skipped(), [PathBuf::from("input/null")]);
+        builder.finish(&repacked)?;
+
+        let reader = PackReader::new(&repacked)?;
+        let read = |path: &str| reader.read_range(path, 0, 4096);
+        assert_eq!(read("restored/input/sub/hello.txt")?, b"hello world");
+        assert_eq!(read("restored/input/hardlink")?, b"hello world");
+        assert_eq!(
+            read(&format!("restored/input/{}", long_name))?,
+            "lorem ipsum ".repeat(100).as_bytes()
+        );
+        assert_eq!(read(&format!("restored/{}", gnu_name))?, b"gnu");
+        assert!(reader.contains("restored/input/empty")?);
+        #[cfg(target_family = "unix")]
+        assert!(reader.contains("restored/input/link")?);
+
+        // a stream cut short is an error
+        let mut builder = PackBuilder::new()?;
+        let result = builder.add_tar_stream(&stream[..BLOCK_SIZE + 100], 0);
+        assert!(matches!(result, Err(Error::IOError(_))));
+        Ok(())
+    }
+
+    #[test]
+    fn test_export_tar() -> Result<(), Error> {
+        let workspace = tempfile::tempdir()?;
+        let basepath = workspace.path().join("input");
+        let long_name = "x".repeat(120);
+        fs::create_dir_all(basepath.join("sub"))?;
+        fs::write(basepath.join("sub/hello.txt"), "hello world")?;
+        fs::write(basepath.join(&long_name), "long")?;
+        #[cfg(target_family = "unix")]
+        std::os::unix::fs::symlink("sub/hello.txt", basepath.join("link"))?;
+        let packfile = workspace.path().join("pack.db3");
+        let mut builder = PackBuilder::new()?;
+        builder.add_dir_all(&basepath)?;
+        builder.finish(&packfile)?;
+
+        let reader = PackReader::new(&packfile)?;
+        let mut output: Vec<u8> = vec![];
+        assert_eq!(reader.export_tar(&mut output)?, 2);
+        assert_eq!(output.len() % BLOCK_SIZE, 0);
+        assert!(output.ends_with(&[0; BLOCK_SIZE * 2]));
+
+        // walk the headers, gathering the names and contents
+        let mut found: Vec<(String, u8, Vec<u8>)> = vec![];
+        let mut pos = 0;
+        let mut long_path: Option<String> = None;
+        while output[pos] != 0 {
+            let block = &output[pos..pos + BLOCK_SIZE];
+            let stored: u64 = parse_octal(&block[148..156]);
+            let computed: u64 = block
+                .iter()
+                .enumerate()
+                .map(|(i, b)| {
+                    if (148..156).contains(&i) {
+                        32
+                    } else {
+                        *b as u64
+                    }
+                })
+                .sum();
+            assert_eq!(stored, computed);
+            let size = parse_octal(&block[124..136]) as usize;
+            let data = output[pos + BLOCK_SIZE..pos + BLOCK_SIZE + size].to_vec();
+            pos += BLOCK_SIZE + size.div_ceil(BLOCK_SIZE) * BLOCK_SIZE;
+            if block[156] == b'x' {
+                let record = String::from_utf8(data).unwrap();
+                let (_, value) = record.trim_end().split_once("path=").unwrap();
+                long_path = Some(value.to_owned());
+                continue;
+            }
+            let name = String::from_utf8(block[..100].to_vec()).unwrap();
+            let name = long_path
+                .take()
+                .unwrap_or_else(|| name.trim_end_matches('\0').to_owned());
+            let content = if block[156] == b'2' {
+                block[157..257]
+                    .iter()
+                    .take_while(|b| **b != 0)
+                    .copied()
+                    .collect()
+            } else {
+                data
+            };
+            found.push((name, block[156], content));
+        }
+        found.sort();
+        let mut expected: Vec<(String, u8, Vec<u8>)> = vec![
+            ("input/".into(), b'5', vec![]),
+            ("input/sub/".into(), b'5', vec![]),
+            ("input/sub/hello.txt".into(), b'0', b"hello world".to_vec()),
+            (format!("input/{}", long_name), b'0', b"long".to_vec()),
+        ];
+        #[cfg(target_family = "unix")]
+        expected.push(("input/link".into(), b'2', b"sub/hello.txt".to_vec()));
+        expected.sort();
+        assert_eq!(found, expected);
+        Ok(())
+    }
+}
@@ -0,0 +1,511 @@
+//
+// Copyright (c) 2024 Nathan Fiedler
+//
+use crate::{Error, PackReader};
+use rusqlite::{ffi, Connection, OpenFlags};
+use std::collections::{HashMap, VecDeque};
+use std::ffi::{c_char, c_int, c_void, CStr};
+use std::io;
+use std::ptr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock};
+
+///
+/// Source of the bytes of an archive that is stored elsewhere, such as in an
+/// object store, which is read a range at a time as the database pages are
+/// needed, rather than being downloaded in full.
+///
+/// Requires the `remote` feature.
+///
+pub trait RemoteSource: Send + Sync {
+    ///
+    /// Return the size of the archive in bytes.
+    ///
+    fn size(&self) -> io::Result<u64>;
+
+    ///
+    /// Read the bytes starting at the given offset into the buffer, returning
+    /// the number of bytes read, which is less than the length of the buffer
+    /// only when the end of the archive has been reached.
+    ///
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> io::Result<usize>;
+}
+
+impl PackReader {
+    // Construct a reader for the archive read from the given source by way of
+    // the VFS, as for remote and embedded archives.
+    pub(crate) fn open_source(source: Box<dyn RemoteSource>) -> Result<Self, Error> {
+        register_vfs()?;
+        let name = format!(
+            "{}{}",
+            NAME_PREFIX,
+            NEXT_SOURCE.fetch_add(1, Ordering::Relaxed)
+        );
+        lock_sources()?.push((name.clone(), Arc::from(source)));
+        let flags = OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX;
+        let result = Connection::open_with_flags_and_vfs(&name, flags, VFS_NAME)
+            .map_err(Error::from)
+            .and_then(PackReader::with_connection);
+        // the source now belongs to the open database file
+        lock_sources()?.retain(|(n, _)| *n != name);
+        result
+    }
+}
+
+// Lock the sources that are waiting to be opened, failing rather than
+// panicking if another thread panicked while holding the lock.
+fn lock_sources() -> Result<MutexGuard<'static, Vec<PendingSource>>, Error> {
+    SOURCES
+        .lock()
+        .map_err(|_| Error::IOError(io::Error::other("remote source registry is poisoned")))
+}
+
+// Name under which the VFS is registered with SQLite.
+const VFS_NAME: &str = "pack-rs-remote";
+
+// Prefix of the database names that refer to a remote source.
+const NAME_PREFIX: &str = "pack-rs-remote:";
+
+// Size of the ranges that are read from the source, each of which covers many
+// database pages; reading larger ranges means fewer requests.
+const BLOCK_SIZE: u64 = 64 * 1024;
+
+// Maximum number of blocks kept in memory for each open database.
+const CACHED_BLOCKS: usize = 256;
+
+// Source that is waiting to be opened by the VFS, with its database name.
+type PendingSource = (String, Arc<dyn RemoteSource>);
+
+// Sources that are waiting to be opened by the VFS.
+static SOURCES: Mutex<Vec<PendingSource>> = Mutex::new(Vec::new());
+
+// Used to give each source a unique database name.
+static NEXT_SOURCE: AtomicU64 = AtomicU64::new(1);
+
+// Result code from registering the VFS, which happens only once.
+static REGISTERED: OnceLock<c_int> = OnceLock::new();
+
+// Register the VFS with SQLite, if that has not been done already. The VFS is
+// a copy of the default VFS, which is used for everything other than opening
+// the remote database, such as the temporary files used while extracting.
+fn register_vfs() -> Result<(), Error> {
+    let rc = *REGISTERED.get_or_init(|| unsafe {
+        let default = ffi::sqlite3_vfs_find(ptr::null());
+        if default.is_null() {
+            return ffi::SQLITE_ERROR;
+        }
+        let mut vfs: ffi::sqlite3_vfs = ptr::read(default);
+        vfs.szOsFile = vfs.szOsFile.max(std::mem::size_of::<RemoteFile>() as c_int);
+        vfs.pNext = ptr::null_mut();
+        vfs.zName = c"pack-rs-remote".as_ptr();
+        vfs.pAppData = default as *mut c_void;
+        vfs.xOpen = Some(vfs_open);
+        vfs.xDelete = Some(vfs_delete);
+        vfs.xAccess = Some(vfs_access);
+        vfs.xFullPathname = Some(vfs_full_pathname);
+        // the VFS must remain valid for the life of the process
+        ffi::sqlite3_vfs_register(Box::into_raw(Box::new(vfs)), 0)
+    });
+    if rc == ffi::SQLITE_OK {
+        Ok(())
+    } else {
+        Err(Error::SQLError(rusqlite::Error::SqliteFailure(
+            ffi::Error::new(rc),
+            Some("could not register remote VFS".into()),
+        )))
+    }
+}
+
+// Return true if the database name refers to a remote source.
+unsafe fn is_remote(name: *const c_char) -> bool {
+    !name.is_null()
+        && CStr::from_ptr(name)
+            .to_bytes()
+            .starts_with(NAME_PREFIX.as_bytes())
+}
+
+// Return the default VFS, to which everything else is delegated.
+unsafe fn default_vfs(vfs: *mut ffi::sqlite3_vfs) -> *mut ffi::sqlite3_vfs {
+    (*vfs).pAppData as *mut ffi::sqlite3_vfs
+}
+
+unsafe extern "C" fn vfs_open(
+    vfs: *mut ffi::sqlite3_vfs,
+    name: *const c_char,
+    file: *mut ffi::sqlite3_file,
+    flags: c_int,
+    out_flags: *mut c_int,
+) -> c_int {
+    let default = default_vfs(vfs);
+    if !is_remote(name) {
+        let Some(open) = (*default).xOpen else {
+            return ffi::SQLITE_ERROR;
+        };
+        return open(default, name, file, flags, out_flags);
+    }
+    // journals and the like are never created for the remote database
+    if flags & ffi::SQLITE_OPEN_MAIN_DB == 0 || flags & ffi::SQLITE_OPEN_READONLY == 0 {
+        return ffi::SQLITE_CANTOPEN;
+    }
+    let name = CStr::from_ptr(name).to_string_lossy();
+    // a panic must not unwind into SQLite
+    let Ok(sources) = SOURCES.lock() else {
+        return ffi::SQLITE_ERROR;
+    };
+    let Some(source) = sources
+        .iter()
+        .find(|(n, _)| *n == name)
+        .map(|(_, s)| s.clone())
+    else {
+        return ffi::SQLITE_CANTOPEN;
+    };
+    drop(sources);
+    let size = match source.size() {
+        Ok(size) => size,
+        Err(err) => {
+            tracing::warn!(%err, "could not open remote archive");
+            return ffi::SQLITE_CANTOPEN;
+        }
+    };
+    let handle = Box::new(RemoteHandle {
+        source,
+        size,
+        blocks: HashMap::new(),
+        order: VecDeque::new(),
+    });
+    ptr::write(
+        file as *mut RemoteFile,
+        RemoteFile {
+            base: ffi::sqlite3_file {
+                pMethods: &REMOTE_METHODS,
+            },
+            handle: Box::into_raw(handle),
+        },
+    );
+    if !out_flags.is_null() {
+        *out_flags = ffi::SQLITE_OPEN_READONLY | ffi::SQLITE_OPEN_MAIN_DB;
+    }
+    ffi::SQLITE_OK
+}
+
+unsafe extern "C" fn vfs_delete(
+    vfs: *mut ffi::sqlite3_vfs,
+    name: *const c_char,
+    sync_dir: c_int,
+) -> c_int {
+    if is_remote(name) {
+        return ffi::SQLITE_READONLY;
+    }
+    let default = default_vfs(vfs);
+    match (*default).xDelete {
+        Some(delete) => delete(default, name, sync_dir),
+        None => ffi::SQLITE_ERROR,
+    }
+}
+
+unsafe extern "C" fn vfs_access(
+    vfs: *mut ffi::sqlite3_vfs,
+    name: *const c_char,
+    flags: c_int,
+    result: *mut c_int,
+) -> c_int {
+    // only ever asked about journals and such, which do not exist
+    if is_remote(name) {
+        *result = 0;
+        return ffi::SQLITE_OK;
+    }
+    let default = default_vfs(vfs);
+    match (*default).xAccess {
+        Some(access) => access(default, name, flags, result),
+        None => ffi::SQLITE_ERROR,
+    }
+}
+
+unsafe extern "C" fn vfs_full_pathname(
+    vfs: *mut ffi::sqlite3_vfs,
+    name: *const c_char,
+    size: c_int,
+    output: *mut c_char,
+) -> c_int {
+    if is_remote(name) {
+        let bytes = CStr::from_ptr(name).to_bytes_with_nul();
+        if bytes.len() > size as usize {
+            return ffi::SQLITE_CANTOPEN;
+        }
+        ptr::copy_nonoverlapping(bytes.as_ptr() as *const c_char, output, bytes.len());
+        return ffi::SQLITE_OK;
+    }
+    let default = default_vfs(vfs);
+    match (*default).xFullPathname {
+        Some(full_pathname) => full_pathname(default, name, size, output),
+        None => ffi::SQLITE_ERROR,
+    }
+}
+
+// Database file that is read from a remote source, laid out such that SQLite
+// sees only the leading `sqlite3_file` structure.
+#[repr(C)]
+struct RemoteFile {
+    base: ffi::sqlite3_file,
+    handle: *mut RemoteHandle,
+}
+
+// Reads blocks from the source, keeping the most recently fetched in memory.
+struct RemoteHandle {
+    source: Arc<dyn RemoteSource>,
+    size: u64,
+    blocks: HashMap<u64, Vec<u8>>,
+    // block indices in the order they were fetched
+    order: VecDeque<u64>,
+}
+
+impl RemoteHandle {
+    // Read into the buffer from the given offset, returning the number of
+    // bytes read, which is less than requested only at the end of the source.
+    fn read(&mut self, offset: u64, buf: &mut [u8]) -> io::Result<usize> {
+        let mut count: usize = 0;
+        while count < buf.len() {
+            let pos = offset + count as u64;
+            if pos >= self.size {
+                break;
+            }
+            let index = pos / BLOCK_SIZE;
+            let block = self.fetch_block(index)?;
+            let start = (pos - index * BLOCK_SIZE) as usize;
+            if start >= block.len() {
+                break;
+            }
+            let len = (block.len() - start).min(buf.len() - count);
+            buf[count..count + len].copy_from_slice(&block[start..start + len]);
+            count += len;
+        }
+        Ok(count)
+    }
+
+    // Return the block with the given index, reading it from the source if it
+    // is not already in memory, and evicting the oldest blocks as needed.
+    fn fetch_block(&mut self, index: u64) -> io::Result<&[u8]> {
+        if !self.blocks.contains_key(&index) {
+            let offset = index * BLOCK_SIZE;
+            let len = BLOCK_SIZE.min(self.size - offset) as usize;
+            let mut data: Vec<u8> = vec![0; len];
+            let mut filled: usize = 0;
+            while filled < len {
+                let read = self
+                    .source
+                    .read_at(offset + filled as u64, &mut data[filled..])?;
+                if read == 0 {
+                    break;
+                }
+                filled += read;
+            }
+            data.truncate(filled);
+            tracing::debug!(offset, len = filled, "fetched remote block");
+            while self.order.len() >= CACHED_BLOCKS {
+                if let Some(oldest) = self.order.pop_front() {
+                    self.blocks.remove(&oldest);
+                }
+            }
+            self.blocks.insert(index, data);
+            self.order.push_back(index);
+        }
+        Ok(&self.blocks[&index])
+    }
+}
+
+static REMOTE_METHODS: ffi::sqlite3_io_methods = ffi::sqlite3_io_methods {
+    iVersion: 1,
+    xClose: Some(file_close),
+    xRead: Some(file_read),
+    xWrite: Some(file_write),
+    xTruncate: Some(file_truncate),
+    xSync: Some(file_sync),
+    xFileSize: Some(file_size),
+    xLock: Some(file_lock),
+    xUnlock: Some(file_lock),
+    xCheckReservedLock: Some(file_check_reserved_lock),
+    xFileControl: Some(file_control),
+    xSectorSize: Some(file_sector_size),
+    xDeviceCharacteristics: Some(file_device_characteristics),
+    xShmMap: None,
+    xShmLock: None,
+    xShmBarrier: None,
+    xShmUnmap: None,
+    xFetch: None,
+    xUnfetch: None,
+};
+
+unsafe extern "C" fn file_close(file: *mut ffi::sqlite3_file) -> c_int {
+    let file = file as *mut RemoteFile;
+    drop(Box::from_raw((*file).handle));
+    (*file).handle = ptr::null_mut();
+    ffi::SQLITE_OK
+}
+
+unsafe extern "C" fn file_read(
+    file: *mut ffi::sqlite3_file,
+    buf: *mut c_void,
+    amount: c_int,
+    offset: ffi::sqlite3_int64,
+) -> c_int {
+    let handle = &mut *(*(file as *mut RemoteFile)).handle;
+    let buf = std::slice::from_raw_parts_mut(buf as *mut u8, amount as usize);
+    match handle.read(offset as u64, buf) {
+        Ok(count) if count == buf.len() => ffi::SQLITE_OK,
+        Ok(count) => {
+            // SQLite requires that the remainder be zero-filled
+            buf[count..].fill(0);
+            ffi::SQLITE_IOERR_SHORT_READ
+        }
+        Err(err) => {
+            tracing::warn!(%err, "could not read remote archive");
+            ffi::SQLITE_IOERR_READ
+        }
+    }
+}
+
+unsafe extern "C" fn file_write(
+    _file: *mut ffi::sqlite3_file,
+    _buf: *const c_void,
+    _amount: c_int,
+    _offset: ffi::sqlite3_int64,
+) -> c_int {
+    ffi::SQLITE_READONLY
+}
+
+unsafe extern "C" fn file_truncate(
+    _file: *mut ffi::sqlite3_file,
+    _size: ffi::sqlite3_int64,
+) -> c_int {
+    ffi::SQLITE_READONLY
+}
+
+unsafe extern "C" fn file_sync(_file: *mut ffi::sqlite3_file, _flags: c_int) -> c_int {
+    ffi::SQLITE_OK
+}
+
+unsafe extern "C" fn file_size(
+    file: *mut ffi::sqlite3_file,
+    size: *mut ffi::sqlite3_int64,
+) -> c_int {
+    let handle = &*(*(file as *mut RemoteFile)).handle;
+    *size = handle.size as ffi::sqlite3_int64;
+    ffi::SQLITE_OK
+}
+
+// Locking is unnecessary as the database is never modified.
+unsafe extern "C" fn file_lock(_file: *mut ffi::sqlite3_file, _level: c_int) -> c_int {
+    ffi::SQLITE_OK
+}
+
+unsafe extern "C" fn file_check_reserved_lock(
+    _file: *mut ffi::sqlite3_file,
+    result: *mut c_int,
+) -> c_int {
+    *result = 0;
+    ffi::SQLITE_OK
+}
+
+unsafe extern "C" fn file_control(
+    _file: *mut ffi::sqlite3_file,
+    _op: c_int,
+    _arg: *mut c_void,
+) -> c_int {
+    ffi::SQLITE_NOTFOUND
+}
+
+unsafe extern "C" fn file_sector_size(_file: *mut ffi::sqlite3_file) -> c_int {
+    0
+}
+
+unsafe extern "C" fn file_device_characteristics(_file: *mut ffi::sqlite3_file) -> c_int {
+    ffi::SQLITE_IOCAP_IMMUTABLE
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::{ExtractOptions, PackBuilder};
+    use std::fs;
+
+    // Source that reads from memory, counting the bytes that were read.
+    struct MemorySource {
+        data: Vec<u8>,
+        fetched: Arc<AtomicU64>,
+    }
+
+    impl RemoteSource for MemorySource {
+        fn size(&self) -> io::Result<u64> {
+            Ok(self.data.len() as u64)
+        }
+
+        fn read_at(&self, offset: u64, buf: &mut [u8]) -> io::Result<usize> {
+            let start = (offset as usize).min(self.data.len());
+            let len = buf.len().min(self.data.len() - start);
+            buf[..len].copy_from_slice(&self.data[start..start + len]);
+            self.fetched.fetch_add(len as u64, Ordering::Relaxed);
+            Ok(len)
+        }
+    }
+
+    // Build an archive with several directories, each with enough random
+    // (and hence incompressible) content to span many blocks of the database.
+    pub(crate) fn build_archive(workspace: &std::path::Path) -> Result<std::path::PathBuf, Error> {
+        let basepath = workspace.join("input");
+        let mut state: u64 = 0x2545f4914f6cdd1d;
+        for dir in ["one", "two", "three", "four"] {
+            fs::create_dir_all(basepath.join(dir))?;
+            for index in 0..8 {
+                let data: Vec<u8> = (0..32768)
+                    .map(|_| {
+                        state ^= state << 13;
+                        state ^= state >> 7;
+                        state ^= state << 17;
+                        state as u8
+                    })
+                    .collect();
+                fs::write(basepath.join(dir).join(format!("{}.bin", index)), data)?;
+            }
+        }
+        fs::write(basepath.join("two/hello.txt"), "hello world")?;
+        let packfile = workspace.join("pack.db3");
+        let mut builder = PackBuilder::new()?;
+        builder.add_dir_all(&basepath)?;
+        builder.finish(&packfile)?;
+        Ok(packfile)
+    }
+
+    #[test]
+    fn test_open_source() -> Result<(), Error> {
+        let workspace = tempfile::tempdir()?;
+        let packfile = build_archive(workspace.path())?;
+        let data = fs::read(&packfile)?;
+        let total = data.len() as u64;
+        let fetched = Arc::new(AtomicU64::new(0));
+        let source = MemorySource {
+            data,
+            fetched: fetched.clone(),
+        };
+        let reader = PackReader::open_source(Box::new(source))?;
+        // listing the entries reads none of the content
+        assert_eq!(reader.entries()?.len(), 38);
+        assert!(fetched.load(Ordering::Relaxed) < total / 4);
+        assert_eq!(reader.read_range("input/two/hello.txt", 6, 5)?, b"world");
+
+        // extracting uses temporary tables, which must work as usual
+        let destination = workspace.path().join("output");
+        fs::create_dir(&destination)?;
+        let options = ExtractOptions {
+            destination: destination.clone(),
+            ..Default::default()
+        };
+        assert_eq!(reader.extract_subtree_with("input/two", &options)?.files, 9);
+        assert_eq!(
+            fs::read(destination.join("input/two/3.bin"))?,
+            fs::read(workspace.path().join("input/two/3.bin"))?
+        );
+        assert!(!destination.join("input/one").exists());
+        assert!(reader.is_content_addressed().is_ok());
+        Ok(())
+    }
+}
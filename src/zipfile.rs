@@ -0,0 +1,305 @@
+//
+// Copyright (c) 2024 Nathan Fiedler
+//
+use crate::{has_column, Error, PackBuilder, PackReader, KIND_DIRECTORY, KIND_FILE, KIND_SYMLINK};
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::{Component, Path, PathBuf};
+use zip::write::SimpleFileOptions;
+use zip::DateTime;
+
+impl PackBuilder {
+    ///
+    /// Add all of the entries from the ZIP file at the given location, reading
+    /// the content of each entry directly from the ZIP file. Entries are added
+    /// beneath the root of the archive, creating directories as needed.
+    ///
+    /// Returns the number of files added.
+    ///
+    /// **Note:** Remember to call `finish()` when done adding content.
+    ///
+    pub fn add_zip_archive<P: AsRef<Path>>(&mut self, path: P) -> Result<u64, Error> {
+        let file = fs::File::open(path.as_ref())?;
+        let mut archive = zip::ZipArchive::new(io::BufReader::new(file))?;
+        let mut file_count: u64 = 0;
+        for index in 0..archive.len() {
+            self.count_entry()?;
+            let mut entry = archive.by_index(index)?;
+            let relpath = entry.enclosed_name().ok_or_else(|| {
+                Error::PathEscapesRoot(PathBuf::from(
+                    String::from_utf8_lossy(entry.name_raw()).into_owned(),
+                ))
+            })?;
+            let names = normalize(&relpath);
+            let Some((name, dirs)) = names.split_last() else {
+                continue;
+            };
+            let mut parent: i64 = 0;
+            for dir in dirs {
+                parent = self.find_or_add_directory(dir, parent, &relpath)?;
+            }
+            let item_id = if entry.is_dir() {
+                self.find_or_add_directory(name, parent, &relpath)?
+            } else {
+                if self.find_child(name, parent)?.is_some() {
+                    return Err(Error::PathCollision(relpath.to_string_lossy().into_owned()));
+                }
+                if entry.is_symlink() {
+                    let mut target: Vec<u8> = vec![];
+                    entry.read_to_end(&mut target)?;
+                    self.conn.execute(
+                        "INSERT INTO item (parent, kind, name, target) VALUES (?1, ?2, ?3, ?4)",
+                        (&parent, KIND_SYMLINK, name, &target),
+                    )?;
+                    self.conn.last_insert_rowid()
+                } else {
+                    file_count += 1;
+                    self.add_stream(name, parent, &mut entry)?
+                }
+            };
+            let mtime = entry.last_modified().and_then(zip_to_epoch);
+            let mode = entry.unix_mode().map(|mode| mode & 0o7777);
+            self.record_attrs(item_id, mtime, mode)?;
+        }
+        Ok(file_count)
+    }
+}
+
+impl PackReader {
+    ///
+    /// Write all of the entries in the archive as a ZIP file to the given
+    /// writer, decompressing the content of each file directly into the ZIP
+    /// stream. The writer need not support seeking, such that the output can
+    /// be sent to a pipe or socket without any intermediate files.
+    ///
+    /// Returns the number of files written.
+    ///
+    pub fn export_zip<W: Write>(&self, output: W) -> Result<u64, Error> {
+        let mut writer = zip::ZipWriter::new_stream(io::BufWriter::new(output));
+        let mut file_count: u64 = 0;
+        for entry in self.export_entries()? {
+            let mut options =
+                SimpleFileOptions::default().large_file(entry.size >= u32::MAX as u64);
+            if let Some(datetime) = entry.mtime.and_then(epoch_to_zip) {
+                options = options.last_modified_time(datetime);
+            }
+            if let Some(mode) = entry.mode {
+                options = options.unix_permissions(mode);
+            }
+            if entry.kind == KIND_DIRECTORY {
+                writer.add_directory(entry.path, options)?;
+            } else if entry.kind == KIND_SYMLINK {
+                let mut raw_bytes: Vec<u8> = vec![];
+                self.read_content(entry.id, &mut raw_bytes)?;
+                let target = String::from_utf8_lossy(&raw_bytes).into_owned();
+                writer.add_symlink(entry.path, target, options)?;
+            } else if entry.kind == KIND_FILE {
+                writer.start_file(entry.path, options)?;
+                self.read_content(entry.id, &mut writer)?;
+                file_count += 1;
+            }
+        }
+        writer.finish()?.flush()?;
+        Ok(file_count)
+    }
+
+    ///
+    /// Return every item in the archive, in breadth-first order, along with
+    /// the attributes needed to write it to another container format.
+    ///
+    pub(crate) fn export_entries(&self) -> Result<Vec<ExportEntry>, Error> {
+        let attrs = if has_column(&self.conn, "attrs", "devmajor")? {
+            "(SELECT mtime FROM attrs WHERE item = FIT.id),
+            (SELECT mode FROM attrs WHERE item = FIT.id),
+            (SELECT devmajor FROM attrs WHERE item = FIT.id),
+            (SELECT devminor FROM attrs WHERE item = FIT.id)"
+        } else if has_column(&self.conn, "attrs", "mode")? {
+            "(SELECT mtime FROM attrs WHERE item = FIT.id),
+            (SELECT mode FROM attrs WHERE item = FIT.id), NULL, NULL"
+        } else if has_column(&self.conn, "attrs", "mtime")? {
+            "(SELECT mtime FROM attrs WHERE item = FIT.id), NULL, NULL, NULL"
+        } else {
+            "NULL, NULL, NULL, NULL"
+        };
+        let query = format!(
+            "WITH RECURSIVE FIT AS (
+    SELECT *, Name || IIF(Kind = 1, '/', '') AS Path FROM Item WHERE Parent = 0
+    UNION ALL
+    SELECT Item.*, FIT.Path || Item.Name || IIF(Item.Kind = 1, '/', '') AS Path
+        FROM Item INNER JOIN FIT ON FIT.Kind = 1 AND Item.Parent = FIT.ID
+)
+SELECT id, kind, Path,
+    (SELECT IFNULL(SUM(size), 0) FROM itemcontent WHERE itemcontent.item = FIT.id),
+    {}
+FROM FIT;",
+            attrs
+        );
+        let mut stmt = self.conn.prepare(&query)?;
+        let rows = stmt
+            .query_map([], |row| {
+                Ok(ExportEntry {
+                    id: row.get(0)?,
+                    kind: row.get(1)?,
+                    path: row.get(2)?,
+                    size: row.get(3)?,
+                    mtime: row.get(4)?,
+                    mode: row.get(5)?,
+                    devmajor: row.get(6)?,
+                    devminor: row.get(7)?,
+                })
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(rows)
+    }
+}
+
+// Item in the archive that is to be written to another container format.
+pub(crate) struct ExportEntry {
+    // rowid in the item table
+    pub(crate) id: i64,
+    pub(crate) kind: i8,
+    // full path, with a trailing slash for directories
+    pub(crate) path: String,
+    // total size of the item content
+    pub(crate) size: u64,
+    pub(crate) mtime: Option<i64>,
+    pub(crate) mode: Option<u32>,
+    pub(crate) devmajor: Option<u32>,
+    pub(crate) devminor: Option<u32>,
+}
+
+// Resolve the path into a list of names, dropping any current directory
+// components and applying the parent directory components.
+fn normalize(path: &Path) -> Vec<String> {
+    let mut names: Vec<String> = vec![];
+    for component in path.components() {
+        match component {
+            Component::Normal(name) => names.push(name.to_string_lossy().into_owned()),
+            Component::ParentDir => {
+                names.pop();
+            }
+            _ => (),
+        }
+    }
+    names
+}
+
+// Convert the ZIP date and time (which has no time zone) to seconds since the
+// Unix epoch, treating it as UTC.
+fn zip_to_epoch(datetime: DateTime) -> Option<i64> {
+    if !datetime.is_valid() {
+        return None;
+    }
+    let days = days_from_civil(
+        datetime.year() as i64,
+        datetime.month() as i64,
+        datetime.day() as i64,
+    );
+    let seconds =
+        datetime.hour() as i64 * 3600 + datetime.minute() as i64 * 60 + datetime.second() as i64;
+    Some(days * 86400 + seconds)
+}
+
+// Convert seconds since the Unix epoch to a ZIP date and time, if the time is
+// within the range supported by the ZIP format.
+fn epoch_to_zip(epoch: i64) -> Option<DateTime> {
+    let (year, month, day) = civil_from_days(epoch.div_euclid(86400));
+    let seconds = epoch.rem_euclid(86400);
+    DateTime::from_date_and_time(
+        u16::try_from(year).ok()?,
+        month as u8,
+        day as u8,
+        (seconds / 3600) as u8,
+        (seconds % 3600 / 60) as u8,
+        (seconds % 60) as u8,
+    )
+    .ok()
+}
+
+// Number of days since 1970-01-01 for the given proleptic Gregorian date, from
+// http://howardhinnant.github.io/date_algorithms.html
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let mp = (month + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+// Proleptic Gregorian date for the given number of days since 1970-01-01.
+pub(crate) fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ExtractOptions;
+
+    #[test]
+    fn test_epoch_conversion() {
+        assert_eq!(days_from_civil(1970, 1, 1), 0);
+        assert_eq!(civil_from_days(0), (1970, 1, 1));
+        assert_eq!(civil_from_days(19782), (2024, 2, 29));
+        let datetime = epoch_to_zip(1_709_210_096).unwrap();
+        assert_eq!(zip_to_epoch(datetime), Some(1_709_210_096));
+        // before the ZIP epoch of 1980
+        assert!(epoch_to_zip(0).is_none());
+    }
+
+    #[test]
+    fn test_zip_round_trip() -> Result<(), Error> {
+        let workspace = tempfile::tempdir()?;
+        let basepath = workspace.path().join("input");
+        fs::create_dir_all(basepath.join("sub/empty"))?;
+        fs::write(basepath.join("sub/hello.txt"), "hello world")?;
+        fs::write(basepath.join("zero.txt"), "")?;
+        #[cfg(target_family = "unix")]
+        std::os::unix::fs::symlink("sub/hello.txt", basepath.join("link"))?;
+        let packfile = workspace.path().join("pack.db3");
+        let mut builder = PackBuilder::new()?;
+        builder.add_dir_all(&basepath)?;
+        builder.finish(&packfile)?;
+
+        // export to a ZIP file and then import that into a new archive
+        let zipfile = workspace.path().join("archive.zip");
+        let reader = PackReader::new(&packfile)?;
+        assert_eq!(reader.export_zip(fs::File::create(&zipfile)?)?, 2);
+        let copyfile = workspace.path().join("copy.db3");
+        let mut builder = PackBuilder::new()?;
+        assert_eq!(builder.add_zip_archive(&zipfile)?, 2);
+        builder.finish(&copyfile)?;
+
+        let original = reader.manifest()?;
+        let copied = PackReader::new(&copyfile)?.manifest()?;
+        for entry in original.iter() {
+            let other = copied.iter().find(|e| e.path == entry.path).unwrap();
+            assert_eq!(other.kind, entry.kind);
+            assert_eq!(other.size, entry.size);
+            assert_eq!(other.checksum, entry.checksum);
+            assert_eq!(other.target, entry.target);
+        }
+        assert_eq!(original.len(), copied.len());
+
+        let destination = workspace.path().join("output");
+        fs::create_dir(&destination)?;
+        let options = ExtractOptions {
+            destination: destination.clone(),
+            ..Default::default()
+        };
+        PackReader::new(&copyfile)?.extract_all_with(&options)?;
+        assert!(crate::verify_manifest(&original, &destination)?.is_empty());
+        Ok(())
+    }
+}